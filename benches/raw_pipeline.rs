@@ -0,0 +1,63 @@
+//! Speed of the RAW pipeline on synthetic mosaics. Output deltas for the same
+//! fixtures are checked by `raw::tests::test_fixture_regressions`.
+
+// Its unit tests are compiled out here, leaving their imports unused, and only the
+// bilinear demosaic is measured
+#[allow(dead_code, unused_imports)]
+#[path = "../src/raw.rs"]
+mod raw;
+#[path = "../src/raw_fixtures.rs"]
+mod raw_fixtures;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// A 12 MP sensor
+const WIDTH: usize = 4000;
+const HEIGHT: usize = 3000;
+
+fn bench_demosaic(c: &mut Criterion) {
+    let mut group = c.benchmark_group("demosaic_bilinear");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    for pattern in ["RGGB", "BGGR", "GRBG", "GBRG"] {
+        let data = raw_fixtures::mosaic(WIDTH, HEIGHT, pattern, 512, 16383, 1.0);
+        group.bench_with_input(BenchmarkId::from_parameter(pattern), &data, |b, data| {
+            b.iter(|| {
+                raw::demosaic_bilinear(
+                    black_box(data),
+                    WIDTH,
+                    HEIGHT,
+                    pattern,
+                    &[16383; 4],
+                    &[512; 4],
+                    &[2.0, 1.0, 1.5, 1.0],
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_orientation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply_orientation");
+    group.sample_size(10);
+    let img = image::DynamicImage::new_rgb8(WIDTH as u32, HEIGHT as u32);
+    for orientation in [3, 6, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(orientation), &orientation, |b, &orientation| {
+            b.iter(|| raw::apply_orientation(black_box(img.clone()), orientation))
+        });
+    }
+    group.finish();
+}
+
+fn bench_corpus(c: &mut Criterion) {
+    let mut group = c.benchmark_group("develop_fixture");
+    for fixture in raw_fixtures::corpus() {
+        group.bench_function(&fixture.name, |b| b.iter(|| black_box(&fixture).develop()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_demosaic, bench_orientation, bench_corpus);
+criterion_main!(benches);
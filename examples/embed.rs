@@ -0,0 +1,37 @@
+//! Drive the embeddable viewer without a window: open an image, draw it into an
+//! offscreen texture and save what was drawn.
+//!
+//!     cargo run --example embed -- photo.nef view.png
+
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use momemtum::snapshot::Target;
+use momemtum::Viewer;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 600;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args_os().skip(1).map(PathBuf::from);
+    let input = args.next().context("usage: embed <image> [output.png]")?;
+    let output = args.next().unwrap_or_else(|| PathBuf::from("embed.png"));
+
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .ok_or_else(|| anyhow!("No usable GPU adapter"))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))?;
+    let (device, queue) = (Arc::new(device), Arc::new(queue));
+
+    let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+    let mut viewer = Viewer::new(device.clone(), queue.clone(), format, (WIDTH, HEIGHT));
+    viewer.open(&input)?;
+
+    let target = Target::new(&device, WIDTH, HEIGHT, format);
+    viewer.render_into(&target.view);
+    let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Readback Encoder") });
+    target.read(&device, &queue, encoder)?.save(&output)?;
+    println!("{}", output.display());
+    Ok(())
+}
@@ -0,0 +1,37 @@
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/raw.rs"]
+mod raw;
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+/// Header fields as rawloader would report them, independent of the sample count.
+#[derive(Arbitrary, Debug)]
+struct Input {
+    width: u16,
+    height: u16,
+    pattern: u8,
+    whitelevels: Vec<u16>,
+    blacklevels: Vec<u16>,
+    wb_coeffs: Vec<f32>,
+    data: Vec<u16>,
+}
+
+fuzz_target!(|input: Input| {
+    let pattern = ["RGGB", "BGGR", "GRBG", "GBRG", ""][input.pattern as usize % 5];
+    let width = input.width as usize;
+    let height = input.height as usize;
+    if let Some(rgb) = raw::demosaic_bilinear(
+        &input.data,
+        width,
+        height,
+        pattern,
+        &input.whitelevels,
+        &input.blacklevels,
+        &input.wb_coeffs,
+    ) {
+        assert_eq!(rgb.len(), width * height * 3);
+    }
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/loader.rs"]
+mod loader;
+#[allow(dead_code)]
+#[path = "../../src/progress.rs"]
+mod progress;
+#[allow(dead_code)]
+#[path = "../../src/raw.rs"]
+mod raw;
+#[allow(dead_code)]
+#[path = "../../src/xmp.rs"]
+mod xmp;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Also applies whatever orientation value the file claims
+    let (_, orientation) = loader::parse_exif(data);
+    if let Some(orientation) = orientation {
+        raw::apply_orientation(image::DynamicImage::new_rgb8(3, 2), orientation);
+    }
+});
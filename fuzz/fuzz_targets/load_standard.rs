@@ -0,0 +1,21 @@
+#![no_main]
+
+// The loader and what it depends on, built from the main crate's sources
+#[allow(dead_code)]
+#[path = "../../src/loader.rs"]
+mod loader;
+#[allow(dead_code)]
+#[path = "../../src/progress.rs"]
+mod progress;
+#[allow(dead_code)]
+#[path = "../../src/raw.rs"]
+mod raw;
+#[allow(dead_code)]
+#[path = "../../src/xmp.rs"]
+mod xmp;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = loader::decode_standard(data);
+});
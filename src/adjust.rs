@@ -0,0 +1,175 @@
+//! View adjustments: brightness, contrast, gamma and saturation applied by the
+//! image shader, for looking into shadows or judging focus without editing the
+//! file, and how strongly a preview LUT applies. They stay in effect while
+//! browsing until reset.
+
+use crate::quad::Rect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Param {
+    Brightness,
+    Contrast,
+    Gamma,
+    Saturation,
+    /// Mix of the LUT's colors over the image's
+    LutIntensity,
+}
+
+impl Param {
+    pub const ALL: [Param; 5] = [Param::Brightness, Param::Contrast, Param::Gamma, Param::Saturation, Param::LutIntensity];
+
+    /// Lowest, highest and neutral value, and the step of one key press.
+    fn range(self) -> (f32, f32, f32, f32) {
+        match self {
+            Param::Brightness => (-0.5, 0.5, 0.0, 0.05),
+            Param::Contrast => (0.5, 2.0, 1.0, 0.05),
+            Param::Gamma => (0.25, 4.0, 1.0, 0.05),
+            Param::Saturation => (0.0, 2.0, 1.0, 0.05),
+            Param::LutIntensity => (0.0, 1.0, 1.0, 0.05),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Param::Brightness => "Brightness",
+            Param::Contrast => "Contrast",
+            Param::Gamma => "Gamma",
+            Param::Saturation => "Saturation",
+            Param::LutIntensity => "LUT",
+        }
+    }
+}
+
+/// Slider track width in logical pixels
+pub const SLIDER_WIDTH: f32 = 160.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjustments {
+    values: [f32; 5],
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self { values: Param::ALL.map(|p| p.range().2) }
+    }
+}
+
+impl Adjustments {
+    pub fn get(&self, param: Param) -> f32 {
+        self.values[param as usize]
+    }
+
+    pub fn set(&mut self, param: Param, value: f32) {
+        let (min, max, _, step) = param.range();
+        // Snap to whole steps so keys land on round values again after dragging
+        self.values[param as usize] = ((value / step).round() * step).clamp(min, max);
+    }
+
+    /// One step up or down.
+    pub fn step(&mut self, param: Param, up: bool) {
+        let step = param.range().3;
+        self.set(param, self.get(param) + if up { step } else { -step });
+    }
+
+    pub fn reset(&mut self, param: Param) {
+        self.values[param as usize] = param.range().2;
+    }
+
+    /// Whether the image shows as it is, the LUT aside.
+    pub fn is_neutral(&self) -> bool {
+        self.uniform() == Self::default().uniform()
+    }
+
+    /// Where the value sits on its slider, 0 at the left end.
+    pub fn fraction(&self, param: Param) -> f32 {
+        let (min, max, _, _) = param.range();
+        (self.get(param) - min) / (max - min)
+    }
+
+    pub fn set_fraction(&mut self, param: Param, t: f32) {
+        let (min, max, _, _) = param.range();
+        self.set(param, min + t.clamp(0.0, 1.0) * (max - min));
+    }
+
+    /// Brightness, contrast, gamma and saturation for the shader.
+    pub fn uniform(&self) -> [f32; 4] {
+        [self.values[0], self.values[1], self.values[2], self.values[3]]
+    }
+
+    pub fn lut_intensity(&self) -> f32 {
+        self.get(Param::LutIntensity)
+    }
+
+    /// "Gamma 1.40" for the panel.
+    pub fn describe(&self, param: Param) -> String {
+        match param {
+            Param::Brightness => format!("{} {:+.2}", param.name(), self.get(param)),
+            Param::LutIntensity => format!("{} {:.0}%", param.name(), self.get(param) * 100.0),
+            _ => format!("{} {:.2}", param.name(), self.get(param)),
+        }
+    }
+}
+
+/// Adjust mode: the slider panel is shown and the keys change the values.
+#[derive(Default)]
+pub struct AdjustTool {
+    pub active: bool,
+    pub values: Adjustments,
+    pub selected: usize,
+    /// Slider being dragged
+    pub dragging: Option<usize>,
+}
+
+impl AdjustTool {
+    pub fn selected_param(&self) -> Param {
+        Param::ALL[self.selected]
+    }
+
+    pub fn select(&mut self, down: bool) {
+        let n = Param::ALL.len();
+        self.selected = if down { (self.selected + 1) % n } else { (self.selected + n - 1) % n };
+    }
+}
+
+/// The slider tracks, top to bottom, in a panel at `(x, y)` whose rows are
+/// `row` pixels high, `scale` being the window's pixels per logical pixel.
+pub fn slider_rects(x: f32, y: f32, row: f32, scale: f32) -> [Rect; Param::ALL.len()] {
+    let width = SLIDER_WIDTH * scale;
+    let track = 4.0 * scale;
+    std::array::from_fn(|i| Rect::new(x, y + (i as f32 + 1.0) * row * 2.0 - row * 0.5 - track / 2.0, width, track))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjustments() {
+        let mut adjust = Adjustments::default();
+        assert!(adjust.is_neutral());
+        assert_eq!(adjust.uniform(), [0.0, 1.0, 1.0, 1.0]);
+
+        for _ in 0..3 {
+            adjust.step(Param::Brightness, true);
+        }
+        assert_eq!(adjust.describe(Param::Brightness), "Brightness +0.15");
+        for _ in 0..100 {
+            adjust.step(Param::Saturation, false);
+        }
+        assert_eq!(adjust.get(Param::Saturation), 0.0);
+
+        adjust.set_fraction(Param::Contrast, 1.0);
+        assert_eq!(adjust.get(Param::Contrast), 2.0);
+        adjust.set_fraction(Param::Gamma, 0.2);
+        assert_eq!(adjust.describe(Param::Gamma), "Gamma 1.00");
+        assert!((adjust.fraction(Param::Gamma) - 0.2).abs() < 1e-6);
+
+        for param in Param::ALL {
+            adjust.reset(param);
+        }
+        assert!(adjust.is_neutral());
+        adjust.step(Param::LutIntensity, false);
+        assert_eq!(adjust.describe(Param::LutIntensity), "LUT 95%");
+        assert!(adjust.is_neutral());
+    }
+}
@@ -0,0 +1,173 @@
+//! Playback of animated GIFs and PNGs: play and pause, stepping frame by frame,
+//! speed and looping. Frames are swapped into the image texture as they come due.
+
+use image::RgbaImage;
+use std::time::{Duration, Instant};
+
+use crate::loader::Frame;
+
+/// Playback speeds to step through, as multiples of the file's timing
+const SPEEDS: [f32; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+/// Browsers show frames with shorter or no delays at this rate, and so do we
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// What the playback keys do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    PlayPause,
+    /// One frame forward, or back
+    Step(bool),
+    /// Faster, or slower
+    Speed(bool),
+    Loop,
+}
+
+pub struct Animation {
+    frames: Vec<Frame>,
+    pub current: usize,
+    pub playing: bool,
+    /// Start over after the last frame, or stop there
+    pub looping: bool,
+    speed: usize,
+    /// When the current frame was shown
+    shown_at: Instant,
+}
+
+impl Animation {
+    pub fn new(frames: Vec<Frame>, now: Instant) -> Self {
+        Self { frames, current: 0, playing: true, looping: true, speed: 3, shown_at: now }
+    }
+
+    /// Decoded pixels of all frames.
+    pub fn bytes(&self) -> u64 {
+        self.frames.iter().map(|f| f.image.as_raw().len() as u64).sum()
+    }
+
+    pub fn frame(&self) -> &RgbaImage {
+        &self.frames[self.current].image
+    }
+
+    pub fn speed(&self) -> f32 {
+        SPEEDS[self.speed]
+    }
+
+    fn delay(&self) -> Duration {
+        let delay = self.frames[self.current].delay;
+        let delay = if delay < MIN_DELAY { if delay.is_zero() { DEFAULT_DELAY } else { MIN_DELAY } } else { delay };
+        // Whole nanoseconds, so a frame at normal speed lasts exactly its delay
+        Duration::from_nanos((delay.as_nanos() as f64 / self.speed() as f64).round() as u64)
+    }
+
+    /// Move on to the frame due at `now`. Returns whether the frame changed.
+    pub fn advance(&mut self, now: Instant) -> bool {
+        if !self.playing {
+            return false;
+        }
+        let mut changed = false;
+        // Catch up after a stall without showing every skipped frame
+        while now.duration_since(self.shown_at) >= self.delay() {
+            if self.current + 1 == self.frames.len() && !self.looping {
+                self.playing = false;
+                break;
+            }
+            self.shown_at += self.delay();
+            self.current = (self.current + 1) % self.frames.len();
+            changed = true;
+            if now.duration_since(self.shown_at) > Duration::from_secs(1) {
+                self.shown_at = now;
+            }
+        }
+        changed
+    }
+
+    /// When the next frame is due, while playing.
+    pub fn next_change(&self) -> Option<Instant> {
+        self.playing.then(|| self.shown_at + self.delay())
+    }
+
+    pub fn toggle_play(&mut self, now: Instant) {
+        self.playing = !self.playing;
+        // Play from the start again once a one-shot animation has ended
+        if self.playing && !self.looping && self.current + 1 == self.frames.len() {
+            self.current = 0;
+        }
+        self.shown_at = now;
+    }
+
+    /// Pause and show the next or previous frame.
+    pub fn step(&mut self, forward: bool, now: Instant) {
+        let n = self.frames.len();
+        self.playing = false;
+        self.current = if forward { (self.current + 1) % n } else { (self.current + n - 1) % n };
+        self.shown_at = now;
+    }
+
+    pub fn change_speed(&mut self, faster: bool) {
+        self.speed = if faster { (self.speed + 1).min(SPEEDS.len() - 1) } else { self.speed.saturating_sub(1) };
+    }
+
+    pub fn control(&mut self, control: Control, now: Instant) {
+        match control {
+            Control::PlayPause => self.toggle_play(now),
+            Control::Step(forward) => self.step(forward, now),
+            Control::Speed(faster) => self.change_speed(faster),
+            Control::Loop => self.looping = !self.looping,
+        }
+    }
+
+    /// "Frame 3/12, 2x, once" for the overlay.
+    pub fn status(&self) -> String {
+        let mut text = format!("Frame {}/{}", self.current + 1, self.frames.len());
+        if !self.playing {
+            text.push_str(", paused");
+        }
+        if self.speed() != 1.0 {
+            text.push_str(&format!(", {}x", self.speed()));
+        }
+        if !self.looping {
+            text.push_str(", once");
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playback() {
+        let frame = |ms| Frame { image: RgbaImage::new(1, 1), delay: Duration::from_millis(ms) };
+        let start = Instant::now();
+        let mut animation = Animation::new(vec![frame(100), frame(0), frame(50)], start);
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(!animation.advance(at(99)));
+        assert!(animation.advance(at(100)));
+        assert_eq!(animation.current, 1);
+        assert_eq!(animation.next_change(), Some(at(200)));
+        // No delay means the default 100 ms
+        animation.advance(at(199));
+        assert_eq!(animation.current, 1);
+        animation.advance(at(250));
+        assert_eq!(animation.current, 0);
+
+        animation.change_speed(true);
+        assert_eq!(animation.speed(), 2.0);
+        animation.step(false, at(300));
+        assert_eq!((animation.current, animation.playing), (2, false));
+        assert_eq!(animation.next_change(), None);
+        assert!(!animation.advance(at(1000)));
+        assert_eq!(animation.status(), "Frame 3/3, paused, 2x");
+
+        // Played once, it stops on the last frame and starts over when played again
+        animation.control(Control::Loop, at(1000));
+        animation.step(false, at(1000));
+        animation.toggle_play(at(1000));
+        animation.advance(at(1100));
+        assert_eq!((animation.current, animation.playing), (2, false));
+        animation.toggle_play(at(1200));
+        assert_eq!(animation.current, 0);
+    }
+}
@@ -0,0 +1,118 @@
+//! Unattended panning across panoramas for exhibitions and video walls: the
+//! view glides at a constant speed from one end of the image to the other and
+//! starts over. It advances by whole display refresh periods, so every frame
+//! moves the same distance even when the event loop wakes up unevenly.
+
+use std::time::{Duration, Instant};
+
+/// Used when the monitor doesn't report its refresh rate.
+const DEFAULT_REFRESH_HZ: f64 = 60.0;
+
+/// Camera position for one frame, in the viewer's world units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub zoom: f32,
+    pub x: f32,
+    pub y: f32,
+}
+
+pub struct AutoPan {
+    /// Window pixels per second; negative runs the other way
+    speed: f32,
+    refresh: Duration,
+    last_frame: Option<Instant>,
+    /// Share of the way from one end to the other
+    progress: f32,
+}
+
+impl AutoPan {
+    pub fn new(speed: f32, refresh_hz: Option<f64>) -> Self {
+        let hz = refresh_hz.filter(|hz| *hz > 0.0).unwrap_or(DEFAULT_REFRESH_HZ);
+        let mut pan = Self { speed, refresh: Duration::from_secs_f64(1.0 / hz), last_frame: None, progress: 0.0 };
+        pan.restart();
+        pan
+    }
+
+    /// Back to the start, e.g. for a new image.
+    pub fn restart(&mut self) {
+        self.progress = if self.speed < 0.0 { 1.0 } else { 0.0 };
+        self.last_frame = None;
+    }
+
+    /// Refresh periods since the previous frame; at least one once running.
+    fn periods(&mut self, now: Instant) -> u32 {
+        let periods = match self.last_frame {
+            Some(last) => (now.duration_since(last).as_secs_f64() / self.refresh.as_secs_f64()).round().max(1.0) as u32,
+            None => 0,
+        };
+        self.last_frame = Some(now);
+        periods
+    }
+
+    /// Move on for the frame shown at `now` and return where the camera goes.
+    pub fn advance(&mut self, now: Instant, image_aspect: f32, window_aspect: f32, window_height: f32) -> Frame {
+        let periods = self.periods(now);
+        let (zoom, range) = extent(image_aspect, window_aspect);
+        // World units span window_height / (2 * zoom) pixels; the travel is 2 * range of them
+        let travel = range * window_height / zoom;
+        if travel >= 1.0 {
+            let step = self.speed * periods as f32 * self.refresh.as_secs_f32() / travel;
+            self.progress = (self.progress + step).rem_euclid(1.0);
+        }
+        frame(image_aspect, window_aspect, self.progress)
+    }
+}
+
+/// The zoom filling the window across the image's short side, and how far the
+/// camera can travel either way from the center along its long side.
+fn extent(image_aspect: f32, window_aspect: f32) -> (f32, f32) {
+    if image_aspect >= window_aspect {
+        (1.0, image_aspect - window_aspect)
+    } else {
+        let zoom = image_aspect / window_aspect;
+        (zoom, 1.0 - zoom)
+    }
+}
+
+/// Wide images run left to right at full height, tall ones top to bottom at full width.
+pub fn frame(image_aspect: f32, window_aspect: f32, progress: f32) -> Frame {
+    let (zoom, range) = extent(image_aspect, window_aspect);
+    let offset = range * (2.0 * progress - 1.0);
+    if image_aspect >= window_aspect {
+        Frame { zoom, x: offset, y: 0.0 }
+    } else {
+        Frame { zoom, x: 0.0, y: -offset }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        // A 4:1 panorama in a 2:1 window starts with its left end filling the window
+        assert_eq!(frame(4.0, 2.0, 0.0), Frame { zoom: 1.0, x: -2.0, y: 0.0 });
+        assert_eq!(frame(4.0, 2.0, 1.0), Frame { zoom: 1.0, x: 2.0, y: 0.0 });
+        // A tall image fills the width and starts at the top
+        assert_eq!(frame(0.5, 1.0, 0.0), Frame { zoom: 0.5, x: 0.0, y: 0.5 });
+    }
+
+    #[test]
+    fn test_advance_by_refresh_periods() {
+        let mut pan = AutoPan::new(100.0, Some(50.0));
+        let start = Instant::now();
+        // 4:1 in a 2:1 window 500 pixels high: 1000 pixels to travel
+        assert_eq!(pan.advance(start, 4.0, 2.0, 500.0).x, -2.0);
+        // 2 pixels a period; a late frame catches up by whole periods
+        pan.advance(start + Duration::from_millis(20), 4.0, 2.0, 500.0);
+        pan.advance(start + Duration::from_millis(61), 4.0, 2.0, 500.0);
+        assert!((pan.progress - 6.0 / 1000.0).abs() < 1e-6);
+
+        // Going backwards loops around the start
+        let mut pan = AutoPan::new(-100.0, Some(50.0));
+        pan.advance(start, 4.0, 2.0, 500.0);
+        pan.advance(start + Duration::from_millis(20), 4.0, 2.0, 500.0);
+        assert!((pan.progress - (1.0 - 2.0 / 1000.0)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,157 @@
+//! Small markers on filmstrip and grid thumbnails for triage at a glance:
+//! file type, clipped highlights or shadows, rating and flags, GPS position,
+//! and in the grid a luminance histogram.
+
+use image::DynamicImage;
+
+use crate::quad::Rect;
+
+pub const HISTOGRAM_BINS: usize = 16;
+/// Share of clipped pixels from which a clipping badge shows.
+const CLIP_THRESHOLD: f32 = 0.01;
+
+const RAW_COLOR: [f32; 4] = [0.95, 0.55, 0.1, 1.0];
+const JPEG_COLOR: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const HIGHLIGHTS_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+const SHADOWS_COLOR: [f32; 4] = [0.25, 0.35, 1.0, 1.0];
+const STAR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const PICK_COLOR: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const REJECT_COLOR: [f32; 4] = [0.9, 0.15, 0.15, 1.0];
+const GPS_COLOR: [f32; 4] = [0.3, 0.85, 0.4, 1.0];
+const BACKING: [f32; 4] = [0.0, 0.0, 0.0, 0.7];
+
+/// Exposure of a thumbnail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExposureStats {
+    /// Share of pixels black in every channel
+    pub shadows: f32,
+    /// Share of pixels white in some channel
+    pub highlights: f32,
+    /// Luminance distribution, scaled so the fullest bin is 1
+    pub histogram: [f32; HISTOGRAM_BINS],
+}
+
+impl ExposureStats {
+    pub fn measure(img: &DynamicImage) -> Self {
+        let rgb = img.to_rgb8();
+        let (mut shadows, mut highlights) = (0usize, 0usize);
+        let mut histogram = [0usize; HISTOGRAM_BINS];
+        for p in rgb.pixels() {
+            let [r, g, b] = p.0;
+            if r.max(g).max(b) <= 2 {
+                shadows += 1;
+            }
+            if r.max(g).max(b) >= 253 {
+                highlights += 1;
+            }
+            let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            histogram[((luma / 256.0 * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)] += 1;
+        }
+        let total = (rgb.width() * rgb.height()).max(1) as f32;
+        let fullest = histogram.iter().copied().max().unwrap_or(0).max(1) as f32;
+        Self {
+            shadows: shadows as f32 / total,
+            highlights: highlights as f32 / total,
+            histogram: histogram.map(|n| n as f32 / fullest),
+        }
+    }
+}
+
+/// What is known about one thumbnail's file.
+#[derive(Debug, Default)]
+pub struct Marks<'a> {
+    pub raw: bool,
+    pub jpeg: bool,
+    /// 1 to 5 stars, or -1 when rejected
+    pub rating: Option<i32>,
+    pub pick: bool,
+    pub located: bool,
+    pub exposure: Option<&'a ExposureStats>,
+}
+
+/// Badge rectangles and colors for a thumbnail `cell`, with a histogram when `histogram` is set.
+pub fn layout(cell: Rect, marks: &Marks, histogram: bool) -> Vec<(Rect, [f32; 4])> {
+    let size = (cell.h * 0.1).clamp(4.0, 10.0);
+    let pad = 3.0;
+    let mut quads = Vec::new();
+    let dot = |quads: &mut Vec<(Rect, [f32; 4])>, x: f32, y: f32, color: [f32; 4]| {
+        let rect = Rect::new(x, y, size, size);
+        quads.push((rect.inset(-1.0), BACKING));
+        quads.push((rect, color));
+    };
+
+    // Top left: file type
+    let top = cell.y + pad;
+    if marks.raw || marks.jpeg {
+        dot(&mut quads, cell.x + pad, top, if marks.raw { RAW_COLOR } else { JPEG_COLOR });
+    }
+    // Top right: clipping
+    if let Some(stats) = marks.exposure {
+        let mut x = cell.x + cell.w - pad - size;
+        for (share, color) in [(stats.highlights, HIGHLIGHTS_COLOR), (stats.shadows, SHADOWS_COLOR)] {
+            if share >= CLIP_THRESHOLD {
+                dot(&mut quads, x, top, color);
+                x -= size + pad;
+            }
+        }
+    }
+    // Bottom left: flags and stars
+    let bottom = cell.y + cell.h - pad - size;
+    let mut x = cell.x + pad;
+    if marks.pick {
+        dot(&mut quads, x, bottom, PICK_COLOR);
+        x += size + pad;
+    }
+    match marks.rating {
+        Some(-1) => dot(&mut quads, x, bottom, REJECT_COLOR),
+        Some(stars @ 1..=5) => {
+            let star = (size * 0.6).max(3.0);
+            let row = Rect::new(x, bottom + size - star, stars as f32 * (star + 1.0) - 1.0, star);
+            quads.push((row.inset(-1.0), BACKING));
+            for i in 0..stars {
+                quads.push((Rect::new(x + i as f32 * (star + 1.0), row.y, star, star), STAR_COLOR));
+            }
+        }
+        _ => {}
+    }
+    // Bottom right: GPS
+    if marks.located {
+        dot(&mut quads, cell.x + cell.w - pad - size, bottom, GPS_COLOR);
+    }
+
+    if let (true, Some(stats)) = (histogram, marks.exposure) {
+        let area = Rect::new(cell.x + cell.w * 0.3, cell.y + cell.h * 0.8, cell.w * 0.4, cell.h * 0.15);
+        quads.push((area, [0.0, 0.0, 0.0, 0.5]));
+        let bar = area.w / HISTOGRAM_BINS as f32;
+        for (i, value) in stats.histogram.iter().enumerate() {
+            let h = area.h * value;
+            quads.push((Rect::new(area.x + i as f32 * bar, area.y + area.h - h, bar, h), [0.9, 0.9, 0.9, 0.8]));
+        }
+    }
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_and_layout() {
+        let mut img = image::RgbImage::from_pixel(10, 10, image::Rgb([128, 128, 128]));
+        for x in 0..10 {
+            img.put_pixel(x, 0, image::Rgb([255, 255, 255]));
+            img.put_pixel(x, 9, image::Rgb([0, 0, 0]));
+        }
+        let stats = ExposureStats::measure(&DynamicImage::ImageRgb8(img));
+        assert_eq!((stats.highlights, stats.shadows), (0.1, 0.1));
+        assert_eq!(stats.histogram[HISTOGRAM_BINS / 2], 1.0);
+        assert_eq!(stats.histogram[HISTOGRAM_BINS - 1], 10.0 / 80.0);
+
+        let cell = Rect::new(0.0, 0.0, 100.0, 100.0);
+        assert!(layout(cell, &Marks::default(), true).is_empty());
+        let marks = Marks { raw: true, rating: Some(3), located: true, exposure: Some(&stats), ..Marks::default() };
+        // RAW and both clipping dots with backings, a row of three stars, and the GPS dot
+        assert_eq!(layout(cell, &marks, false).len(), 2 + 2 + 2 + 4 + 2);
+        assert_eq!(layout(cell, &marks, true).len(), 12 + 1 + HISTOGRAM_BINS);
+    }
+}
@@ -0,0 +1,84 @@
+//! A hand-picked set of images gathered while browsing, across folders. It
+//! lives for the session only and never touches the files themselves.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct Basket {
+    paths: Vec<PathBuf>,
+}
+
+impl Basket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `path`, or take it out if it is already in. Returns whether it is in now.
+    pub fn toggle(&mut self, path: &Path) -> bool {
+        match self.paths.iter().position(|p| p == path) {
+            Some(i) => {
+                self.paths.remove(i);
+                false
+            }
+            None => {
+                self.paths.push(path.to_path_buf());
+                true
+            }
+        }
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.iter().any(|p| p == path)
+    }
+
+    /// The images in the order they were added, leaving out those deleted since.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.paths.iter().filter(|p| p.exists()).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.paths.clear();
+    }
+
+    /// Follow a file that was renamed or moved.
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        for path in self.paths.iter_mut().filter(|p| *p == from) {
+            *path = to.to_path_buf();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_toggle_and_rename() {
+        let dir = scratch_dir("basket");
+        let (a, b) = (dir.join("a.jpg"), dir.join("b.jpg"));
+        std::fs::write(&a, b"").unwrap();
+        std::fs::write(&b, b"").unwrap();
+
+        let mut basket = Basket::new();
+        assert!(basket.toggle(&b));
+        assert!(basket.toggle(&a));
+        assert_eq!(basket.paths(), vec![b.clone(), a.clone()]);
+        assert!(!basket.toggle(&b));
+        assert!(!basket.contains(&b));
+
+        let moved = dir.join("c.jpg");
+        std::fs::rename(&a, &moved).unwrap();
+        assert!(basket.paths().is_empty());
+        basket.rename(&a, &moved);
+        assert_eq!(basket.paths(), vec![moved]);
+    }
+}
@@ -0,0 +1,200 @@
+//! `momemtum bench`: how long each stage of opening an image takes, file by file, on
+//! real photos, to measure changes to the decode pipeline. The stages are those the
+//! loader reports progress for, and the upload is timed on a device without a window.
+//! The criterion benches in `benches/` cover the RAW pipeline on synthetic mosaics.
+
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::progress::Stage;
+
+/// Stages timed, in the order of the table's columns
+const STAGES: [Stage; 4] = [Stage::Reading, Stage::Decoding, Stage::Demosaicing, Stage::Orienting];
+
+/// Time spent in each stage of opening one image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timings {
+    pub path: PathBuf,
+    pub size: (u32, u32),
+    /// Per stage of `STAGES`; zero for those the file didn't go through
+    pub stages: [Duration; STAGES.len()],
+    /// None without a GPU, or when the image is too large for one texture
+    pub upload: Option<Duration>,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.stages.iter().sum::<Duration>() + self.upload.unwrap_or_default()
+    }
+}
+
+/// The device uploads are timed on, and the largest texture it takes.
+pub struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    max_side: u32,
+}
+
+impl Gpu {
+    pub fn new() -> Result<Self> {
+        let (adapter, device, queue) = pollster::block_on(crate::gpu::headless())?;
+        eprintln!("Uploading with {}", crate::gpu::describe(&adapter.get_info()));
+        let max_side = device.limits().max_texture_dimension_2d;
+        Ok(Self { device, queue, max_side })
+    }
+
+    /// Convert `image` to RGBA if it isn't, copy it into a new texture and wait for the GPU.
+    fn upload(&self, image: image::DynamicImage) -> Option<Duration> {
+        let (w, h) = (image.width(), image.height());
+        if w.max(h) > self.max_side {
+            return None;
+        }
+        let start = Instant::now();
+        let rgba = image.into_rgba8();
+        let texture = crate::texture::Texture::new(&self.device, (w, h), Some("Benchmark"));
+        texture.write_rows(&self.queue, &rgba, 0..h);
+        self.queue.submit(std::iter::empty());
+        self.device.poll(wgpu::Maintain::Wait);
+        Some(start.elapsed())
+    }
+}
+
+/// Open `path` the way the viewer does, minus the sandbox and tiling, timing each stage.
+pub fn measure(path: &Path, gpu: Option<&Gpu>) -> Result<Timings> {
+    let reports = Rc::new(RefCell::new(Vec::new()));
+    let sink = {
+        let reports = reports.clone();
+        move |stage, _| reports.borrow_mut().push((stage, Instant::now()))
+    };
+    let start = Instant::now();
+    let loaded = crate::progress::watch(sink, || crate::loader::load_image(path))?;
+    let stages = stage_times(start, &reports.borrow(), Instant::now());
+    let size = (loaded.image.width(), loaded.image.height());
+    Ok(Timings {
+        path: path.to_path_buf(),
+        size,
+        stages,
+        upload: gpu.and_then(|gpu| gpu.upload(loaded.image)),
+    })
+}
+
+/// Time from each stage's first report to the next stage's, the last one running to
+/// `end`. Time before the first report counts towards reading.
+fn stage_times(start: Instant, reports: &[(Stage, Instant)], end: Instant) -> [Duration; STAGES.len()] {
+    let mut times = [Duration::ZERO; STAGES.len()];
+    let mut add = |(stage, since): (Stage, Instant), until: Instant| {
+        if let Some(i) = STAGES.iter().position(|s| *s == stage) {
+            times[i] += until.saturating_duration_since(since);
+        }
+    };
+    let mut current = (Stage::Reading, start);
+    for &(stage, at) in reports {
+        if stage != current.0 {
+            add(current, at);
+            current = (stage, at);
+        }
+    }
+    add(current, end);
+    times
+}
+
+/// A row per file and the mean of each column, in milliseconds.
+pub fn format_table(timings: &[Timings]) -> String {
+    let name = |p: &Path| p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let width = timings.iter().map(|t| name(&t.path).chars().count()).max().unwrap_or(0).max(8);
+    let ms = |d: Duration| format!("{:.1}", d.as_secs_f64() * 1000.0);
+    let mut headers: Vec<&str> = STAGES.iter().map(|s| s.label()).collect();
+    headers.extend(["Upload", "Total"]);
+    let row = |first: &str, size: &str, cells: &[String]| {
+        let cells: Vec<String> = cells.iter().map(|c| format!("{:>11}", c)).collect();
+        format!("{:<width$} {:>11} {}\n", first, size, cells.join(" "), width = width)
+    };
+    let mut out = row("File", "Pixels", &headers.iter().map(|h| h.to_string()).collect::<Vec<_>>());
+    out.push_str(&format!("{}\n", "-".repeat(width + 12 * (headers.len() + 1))));
+    for t in timings {
+        let mut cells: Vec<String> = t.stages.iter().map(|d| ms(*d)).collect();
+        cells.push(t.upload.map_or_else(|| "-".to_string(), ms));
+        cells.push(ms(t.total()));
+        out.push_str(&row(&name(&t.path), &format!("{}x{}", t.size.0, t.size.1), &cells));
+    }
+    if timings.len() > 1 {
+        let n = timings.len() as u32;
+        let mut cells: Vec<String> = (0..STAGES.len()).map(|i| ms(timings.iter().map(|t| t.stages[i]).sum::<Duration>() / n)).collect();
+        let uploads: Vec<Duration> = timings.iter().filter_map(|t| t.upload).collect();
+        cells.push(match uploads.len() {
+            0 => "-".to_string(),
+            count => ms(uploads.iter().sum::<Duration>() / count as u32),
+        });
+        cells.push(ms(timings.iter().map(Timings::total).sum::<Duration>() / n));
+        out.push_str(&row("Mean", "", &cells));
+    }
+    out
+}
+
+/// Time every file in `files`, printing the table. Files that fail are reported and left out.
+pub fn run(files: &[PathBuf], upload: bool) -> Result<()> {
+    let gpu = match upload {
+        true => Gpu::new().map_err(|e| eprintln!("Not timing uploads: {:#}", e)).ok(),
+        false => None,
+    };
+    let mut timings = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        eprint!("\rDecoding {} of {}", i + 1, files.len());
+        match measure(file, gpu.as_ref()) {
+            Ok(t) => timings.push(t),
+            Err(e) => eprintln!("\rFailed to decode {}: {:#}", file.display(), e),
+        }
+    }
+    eprintln!();
+    if timings.is_empty() {
+        return Err(anyhow!("No image could be decoded"));
+    }
+    print!("{}", format_table(&timings));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_stage_times() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let reports = [
+            (Stage::Reading, at(0)),
+            (Stage::Reading, at(5)),
+            (Stage::Decoding, at(10)),
+            (Stage::Orienting, at(40)),
+        ];
+        let times = stage_times(start, &reports, at(45));
+        let ms = Duration::from_millis;
+        assert_eq!(times, [ms(10), ms(30), ms(0), ms(5)]);
+
+        let timings = Timings { path: PathBuf::from("/photos/a.jpg"), size: (6000, 4000), stages: times, upload: None };
+        assert_eq!(timings.total(), ms(45));
+        let table = format_table(&[timings.clone(), Timings { upload: Some(ms(5)), ..timings }]);
+        let lines: Vec<Vec<&str>> = table.lines().map(|line| line.split_whitespace().collect()).collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], ["File", "Pixels", "Reading", "Decoding", "Demosaicing", "Rotating", "Upload", "Total"]);
+        assert_eq!(lines[2], ["a.jpg", "6000x4000", "10.0", "30.0", "0.0", "5.0", "-", "45.0"]);
+        assert_eq!(lines[3][6..], ["5.0", "50.0"]);
+        // Only files that were uploaded count towards the mean upload
+        assert_eq!(lines[4], ["Mean", "10.0", "30.0", "0.0", "5.0", "5.0", "47.5"]);
+    }
+
+    #[test]
+    fn test_measure_decodes() {
+        let dir = scratch_dir("bench");
+        let path = dir.join("bench.png");
+        image::RgbImage::new(64, 48).save(&path).unwrap();
+        let timings = measure(&path, None).unwrap();
+        assert_eq!(timings.size, (64, 48));
+        assert_eq!(timings.upload, None);
+        assert_eq!(timings.stages[2], Duration::ZERO);
+    }
+}
@@ -0,0 +1,218 @@
+//! The on-disk caches: where they live, and the panel that shows their sizes
+//! and clears them. Place names looked up for the Places list are one too, kept in
+//! the library database rather than a folder of their own.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheKind {
+    Thumbnails,
+    MapTiles,
+    /// Images opened from the web
+    Downloads,
+    /// Names of the places images were taken at, from the geocoder
+    PlaceNames,
+}
+
+impl CacheKind {
+    pub const ALL: &'static [CacheKind] = &[CacheKind::Thumbnails, CacheKind::MapTiles, CacheKind::Downloads, CacheKind::PlaceNames];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CacheKind::Thumbnails => "Thumbnails",
+            CacheKind::MapTiles => "Map tiles",
+            CacheKind::Downloads => "Downloads",
+            CacheKind::PlaceNames => "Place names",
+        }
+    }
+
+    /// Folder of this cache: under `root` when one is configured, else the platform default.
+    /// None for place names, which are in the library.
+    pub fn dir(self, root: Option<&Path>) -> Option<PathBuf> {
+        match (self, root) {
+            (CacheKind::Thumbnails, Some(root)) => Some(root.join("thumbnails")),
+            (CacheKind::Thumbnails, None) => crate::thumbnail::DiskCache::default_location(),
+            (CacheKind::MapTiles, Some(root)) => Some(root.join("map-tiles")),
+            (CacheKind::MapTiles, None) => crate::map::default_cache_dir(),
+            (CacheKind::Downloads, Some(root)) => Some(root.join("downloads")),
+            (CacheKind::Downloads, None) => Some(crate::remote::default_dir()),
+            (CacheKind::PlaceNames, _) => None,
+        }
+    }
+
+    /// Bytes Momemtum stores in `dir`, or for place names in the library at that path.
+    fn usage(self, dir: &Path) -> u64 {
+        match self {
+            // The default thumbnail folder is shared with file managers
+            CacheKind::Thumbnails => crate::thumbnail::DiskCache::new(dir.to_path_buf(), 0).usage(),
+            CacheKind::MapTiles | CacheKind::Downloads => walkdir::WalkDir::new(dir)
+                .into_iter()
+                .flatten()
+                .filter_map(|e| e.metadata().ok())
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+                .sum(),
+            CacheKind::PlaceNames if !dir.exists() => 0,
+            CacheKind::PlaceNames => crate::library::Library::open_at(dir).and_then(|lib| lib.place_names_size()).unwrap_or_else(|e| {
+                log::warn!("Failed to measure the place names: {:?}", e);
+                0
+            }),
+        }
+    }
+
+    fn clear(self, dir: &Path) -> Result<()> {
+        match self {
+            CacheKind::Thumbnails => {
+                crate::thumbnail::DiskCache::new(dir.to_path_buf(), 0).prune()?;
+            }
+            CacheKind::MapTiles if dir.exists() => std::fs::remove_dir_all(dir)?,
+            CacheKind::MapTiles => {}
+            // The folder stays, as a private one in the temporary directory must
+            CacheKind::Downloads => {
+                for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+                    if entry.file_type().is_ok_and(|t| t.is_file()) {
+                        std::fs::remove_file(entry.path())?;
+                    }
+                }
+            }
+            CacheKind::PlaceNames if dir.exists() => crate::library::Library::open_at(dir)?.clear_place_names()?,
+            CacheKind::PlaceNames => {}
+        }
+        Ok(())
+    }
+}
+
+/// Cache sizes and clear actions, shown in the title bar while open.
+/// Sizes are measured in the background as the thumbnail cache can hold many files.
+pub struct CachePanel {
+    root: Option<PathBuf>,
+    /// The library database, holding the place names
+    library: PathBuf,
+    sizes: Vec<Option<u64>>,
+    sender: Sender<(usize, u64)>,
+    receiver: Receiver<(usize, u64)>,
+}
+
+impl CachePanel {
+    pub fn open(root: Option<PathBuf>, library: PathBuf) -> Self {
+        let (sender, receiver) = channel();
+        let mut panel = Self {
+            root,
+            library,
+            sizes: vec![None; CacheKind::ALL.len()],
+            sender,
+            receiver,
+        };
+        for index in 0..CacheKind::ALL.len() {
+            panel.measure(index);
+        }
+        panel
+    }
+
+    fn measure(&mut self, index: usize) {
+        self.sizes[index] = None;
+        let kind = CacheKind::ALL[index];
+        let Some(dir) = self.location(kind) else {
+            self.sizes[index] = Some(0);
+            return;
+        };
+        let sender = self.sender.clone();
+        rayon::spawn(move || {
+            let _ = sender.send((index, kind.usage(&dir)));
+        });
+    }
+
+    fn location(&self, kind: CacheKind) -> Option<PathBuf> {
+        match kind {
+            CacheKind::PlaceNames => Some(self.library.clone()),
+            _ => kind.dir(self.root.as_deref()),
+        }
+    }
+
+    /// Take in finished measurements.
+    pub fn poll(&mut self) {
+        for (index, bytes) in self.receiver.try_iter() {
+            self.sizes[index] = Some(bytes);
+        }
+    }
+
+    /// Empty the cache listed at `number` (1-based, as shown).
+    pub fn clear(&mut self, number: usize) -> Result<()> {
+        let Some(index) = number.checked_sub(1).filter(|i| *i < CacheKind::ALL.len()) else {
+            return Ok(());
+        };
+        let kind = CacheKind::ALL[index];
+        let result = match self.location(kind) {
+            Some(dir) => kind.clear(&dir),
+            None => Ok(()),
+        };
+        self.measure(index);
+        result
+    }
+
+    pub fn display(&self, read_only: bool) -> String {
+        let entries: Vec<String> = CacheKind::ALL
+            .iter()
+            .zip(&self.sizes)
+            .enumerate()
+            .map(|(i, (kind, size))| {
+                let size = size.map_or("…".to_string(), format_size);
+                format!("{}: {} {}", i + 1, kind.label(), size)
+            })
+            .collect();
+        let hint = if read_only { "Escape to close" } else { "press a number to clear, Escape to close" };
+        format!("Caches: {} ({})", entries.join(" | "), hint)
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 * 1024 {
+        format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+    } else if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{} KB", bytes.div_ceil(1024))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_panel_measures_and_clears() {
+        let root = scratch_dir("cachepanel");
+        let tiles = CacheKind::MapTiles.dir(Some(&root)).unwrap();
+        std::fs::create_dir_all(tiles.join("server/3/4")).unwrap();
+        std::fs::write(tiles.join("server/3/4/5.png"), vec![0u8; 3000]).unwrap();
+
+        let library = root.join("library.db");
+        crate::library::Library::open_at(&library).unwrap().store_place_name("45.76,4.84", "Lyon, France").unwrap();
+
+        let mut panel = CachePanel::open(Some(root.to_path_buf()), library.clone());
+        let wait = |panel: &mut CachePanel| {
+            while panel.sizes.iter().any(Option::is_none) {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                panel.poll();
+            }
+        };
+        wait(&mut panel);
+        assert_eq!(panel.sizes, vec![Some(0), Some(3000), Some(0), Some(22)]);
+        assert_eq!(
+            panel.display(false),
+            "Caches: 1: Thumbnails 0 KB | 2: Map tiles 3 KB | 3: Downloads 0 KB | 4: Place names 1 KB (press a number to clear, Escape to close)"
+        );
+
+        panel.clear(2).unwrap();
+        assert!(!tiles.exists());
+        panel.clear(4).unwrap();
+        wait(&mut panel);
+        assert_eq!(panel.sizes[3], Some(0));
+        assert!(panel.clear(9).is_ok());
+
+        assert_eq!(format_size(5 * 1024 * 1024 + 1), "5.0 MB");
+    }
+}
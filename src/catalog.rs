@@ -0,0 +1,388 @@
+//! Library mode: every image below the folders listed under `[library]`, cataloged in
+//! the library database with its metadata so the whole collection can be searched and
+//! browsed without walking the disk. The folders are scanned in the background on
+//! launch and kept current by a watcher of their own.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+
+use crate::filter::Comparison;
+use crate::library::Library;
+
+/// Files written to the database per transaction while scanning
+const BATCH: usize = 200;
+
+/// A library search: every term must hold, and the capture date must fall in the range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub terms: Vec<Term>,
+    /// First and last day as `YYYYMMDD` numbers, both included
+    pub taken: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    /// Text the path, camera, lens, or the sidecar's note, title or keywords contain
+    Word(String),
+    Camera(String),
+    Lens(String),
+    Iso(Comparison, u32),
+    /// The XMP star rating, 0 when unrated and -1 for rejects
+    Rating(Comparison, i32),
+}
+
+impl Query {
+    /// Parse a search such as `japan 2023-04` or `camera:"X-T5" iso:>3200 date:2024-06`;
+    /// blank text means no search. Quotes keep spaces in a term, and a date or range
+    /// may be given with or without `date:`.
+    pub fn parse(text: &str) -> Result<Option<Self>> {
+        let mut query = Self { terms: Vec::new(), taken: None };
+        for token in tokens(text)? {
+            let (key, value) = match token.split_once(':') {
+                Some((key, value)) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()) => {
+                    (Some(key.to_ascii_lowercase()), value)
+                }
+                _ => (None, token.as_str()),
+            };
+            match key.as_deref() {
+                None if parse_dates(value)?.is_none() => query.terms.push(Term::Word(value.to_string())),
+                None | Some("date") => {
+                    let range = parse_dates(value)?.ok_or_else(|| anyhow!("Expected a date like 2024-06, not \"{}\"", value))?;
+                    if query.taken.replace(range).is_some() {
+                        return Err(anyhow!("Only one date or date range can be searched"));
+                    }
+                }
+                Some("camera") => query.terms.push(Term::Camera(non_empty(&token, value)?)),
+                Some("lens") => query.terms.push(Term::Lens(non_empty(&token, value)?)),
+                Some("iso") => {
+                    let (cmp, number) = comparison(value);
+                    let iso = number.parse().map_err(|_| anyhow!("Invalid ISO in \"{}\"", token))?;
+                    query.terms.push(Term::Iso(cmp, iso));
+                }
+                Some("rating") => {
+                    let (cmp, number) = comparison(value);
+                    let rating = number.parse().map_err(|_| anyhow!("Invalid rating in \"{}\"", token))?;
+                    query.terms.push(Term::Rating(cmp, rating));
+                }
+                Some(other) => {
+                    return Err(anyhow!("Unknown field \"{}\"; expected camera, lens, iso, rating or date", other))
+                }
+            }
+        }
+        Ok((!query.terms.is_empty() || query.taken.is_some()).then_some(query))
+    }
+}
+
+/// Split `text` at whitespace outside double quotes, dropping the quotes.
+fn tokens(text: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => tokens.extend((!current.is_empty()).then(|| std::mem::take(&mut current))),
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("Missing closing quote"));
+    }
+    tokens.extend((!current.is_empty()).then_some(current));
+    Ok(tokens)
+}
+
+/// The comparison `value` starts with, equality when it has none.
+fn comparison(value: &str) -> (Comparison, &str) {
+    Comparison::parse_prefix(value).unwrap_or((Comparison::Equal, value))
+}
+
+fn non_empty(token: &str, value: &str) -> Result<String> {
+    match value.trim() {
+        "" => Err(anyhow!("Expected some text after \"{}\"", token)),
+        value => Ok(value.to_string()),
+    }
+}
+
+/// The days, as `YYYYMMDD`, a date (`2024-06`) or date range (`2023..2024-06`) covers;
+/// none for other text.
+fn parse_dates(text: &str) -> Result<Option<(u32, u32)>> {
+    match text.split_once("..") {
+        Some((from, to)) if is_date(from) && is_date(to) => Ok(Some((first_day(from), last_day(to)))),
+        Some((from, to)) if is_date(from) || is_date(to) => {
+            Err(anyhow!("Expected a date range like 2023-04..2024, not \"{}\"", text))
+        }
+        _ => Ok(is_date(text).then(|| (first_day(text), last_day(text)))),
+    }
+}
+
+/// `20240601` for `2024-06`.
+fn first_day(date: &str) -> u32 {
+    day_number(date, 1, 1)
+}
+
+/// `20240631` for `2024-06`: past the month's last day, which compares the same.
+fn last_day(date: &str) -> u32 {
+    day_number(date, 12, 31)
+}
+
+/// A date checked by `is_date` as `YYYYMMDD`, with the month and day it leaves out.
+fn day_number(date: &str, month: u32, day: u32) -> u32 {
+    let mut parts = date.split('-').map(|p| p.parse::<u32>().unwrap_or(0));
+    let year = parts.next().unwrap_or(0);
+    year * 10000 + parts.next().unwrap_or(month) * 100 + parts.next().unwrap_or(day)
+}
+
+/// `2024`, `2024-06` or `2024-06-21`.
+fn is_date(text: &str) -> bool {
+    let parts: Vec<&str> = text.split('-').collect();
+    parts.len() <= 3
+        && parts.iter().zip([4, 2, 2]).all(|(part, len)| part.len() == len && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+enum Job {
+    /// Catalog what is new or changed in the library folders and forget what is gone
+    Scan,
+    /// Paths the watcher saw change
+    Changed(Vec<PathBuf>),
+}
+
+/// The library folders, cataloged on a background thread.
+pub struct Catalog {
+    // Kept alive for its events
+    _watcher: Option<crate::watcher::FolderWatcher>,
+    scanning: Arc<AtomicBool>,
+    scanned: Arc<AtomicUsize>,
+}
+
+impl Catalog {
+    /// Catalog the library folders `roots`; none when none of them is there.
+    pub fn start(roots: &[PathBuf]) -> Option<Self> {
+        let present: Vec<PathBuf> = roots.iter().filter(|r| r.is_dir()).cloned().collect();
+        if present.is_empty() {
+            return None;
+        }
+        let (jobs, queue) = channel();
+        let scanning = Arc::new(AtomicBool::new(true));
+        let scanned = Arc::new(AtomicUsize::new(0));
+        {
+            let roots = roots.to_vec();
+            let (scanning, scanned) = (scanning.clone(), scanned.clone());
+            std::thread::spawn(move || {
+                crate::priority::set_current(crate::priority::Priority::Background);
+                // A connection of its own, like the indexer's
+                let library = match Library::open() {
+                    Ok(library) => library,
+                    Err(e) => {
+                        log::warn!("Library mode unavailable: {:?}", e);
+                        scanning.store(false, Ordering::Relaxed);
+                        return;
+                    }
+                };
+                for job in queue {
+                    let result = match job {
+                        Job::Scan => rescan(&library, &roots, &scanned),
+                        Job::Changed(paths) => update(&library, &roots, &paths),
+                    };
+                    if let Err(e) = result {
+                        log::warn!("Failed to update the library catalog: {:?}", e);
+                    }
+                    scanning.store(false, Ordering::Relaxed);
+                }
+            });
+        }
+        let _ = jobs.send(Job::Scan);
+        let watcher = crate::watcher::FolderWatcher::new(move |paths| {
+            let _ = jobs.send(Job::Changed(paths));
+        })
+        .map_err(|e| log::warn!("Library folders can't be watched: {:?}", e))
+        .ok()
+        .map(|mut watcher| {
+            for root in &present {
+                watcher.add(root);
+            }
+            watcher
+        });
+        log::info!("Library mode on {:?}", present);
+        Some(Self { _watcher: watcher, scanning, scanned })
+    }
+
+    /// Files looked at by the first scan so far, while it runs.
+    pub fn scan_progress(&self) -> Option<usize> {
+        self.scanning.load(Ordering::Relaxed).then(|| self.scanned.load(Ordering::Relaxed))
+    }
+}
+
+/// Catalog what is new or changed below the library folders `roots` and forget what is
+/// gone, as well as folders no longer in the library. Those missing, like a drive that
+/// isn't plugged in, keep what was cataloged.
+pub fn rescan(library: &Library, roots: &[PathBuf], scanned: &AtomicUsize) -> Result<()> {
+    library.uncatalog_roots_except(roots)?;
+    for root in roots {
+        if root.is_dir() {
+            scan(library, root, root, scanned)?;
+        } else {
+            log::warn!("Library folder {:?} not found", root);
+        }
+    }
+    Ok(())
+}
+
+/// Catalog the images below `dir`, in the library folder `root`, reading the metadata of
+/// those not indexed at their current modification time, and drop files that are gone.
+fn scan(library: &Library, root: &Path, dir: &Path, scanned: &AtomicUsize) -> Result<()> {
+    let mut found = Vec::new();
+    crate::navigator::collect_images(dir, true, &mut found);
+    log::debug!("{} images in the library folder {:?}", found.len(), dir);
+    for batch in found.chunks(BATCH) {
+        library.in_transaction(|| {
+            for path in batch {
+                crate::priority::yield_to_interactive();
+                catalog_file(library, path, root)?;
+            }
+            Ok(())
+        })?;
+        scanned.fetch_add(batch.len(), Ordering::Relaxed);
+    }
+    let found: HashSet<PathBuf> = found.into_iter().collect();
+    let gone: Vec<PathBuf> =
+        library.cataloged(root)?.into_iter().filter(|p| p.starts_with(dir) && !found.contains(p)).collect();
+    library.in_transaction(|| gone.iter().try_for_each(|path| library.uncatalog(path)))
+}
+
+/// Bring the catalog up to date with changes seen by the watcher.
+fn update(library: &Library, roots: &[PathBuf], paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        let Some(root) = roots.iter().find(|root| path.starts_with(root)) else {
+            continue;
+        };
+        if crate::navigator::has_extension(path, &["xmp"]) {
+            for image in crate::xmp::sidecar_images(path) {
+                catalog_file(library, &image, root)?;
+            }
+        } else if path.is_dir() {
+            // A folder moved in, or renamed
+            scan(library, root, path, &AtomicUsize::new(0))?;
+        } else if path.exists() {
+            if crate::navigator::is_supported_image(path) {
+                catalog_file(library, path, root)?;
+            }
+        } else {
+            // A file or a whole folder that was removed or renamed away
+            library.uncatalog(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Catalog one image below `root`, its metadata from the file index.
+fn catalog_file(library: &Library, path: &Path, root: &Path) -> Result<()> {
+    let Some((mtime, size)) = crate::index::stamp(path) else {
+        return library.uncatalog(path);
+    };
+    if library.indexed_file(path, mtime, size)?.is_none() {
+        if let Some(info) = crate::index::read(path) {
+            library.store_file_info(path, &info)?;
+        }
+    }
+    library.catalog_file(path, root, &crate::xmp::read_sidecar(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    fn word(text: &str) -> Term {
+        Term::Word(text.to_string())
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let query = Query::parse("Japan  x-t5 2023-04").unwrap().unwrap();
+        assert_eq!(query.terms, [word("Japan"), word("x-t5")]);
+        assert_eq!(query.taken, Some((20230401, 20230431)));
+        let query = Query::parse("2023..2024-06-21").unwrap().unwrap();
+        assert!(query.terms.is_empty());
+        assert_eq!(query.taken, Some((20230101, 20240621)));
+        // Not dates
+        assert_eq!(Query::parse("202 2024-6").unwrap().unwrap().terms, [word("202"), word("2024-6")]);
+        assert_eq!(Query::parse("  ").unwrap(), None);
+        assert!(Query::parse("2023 2024").is_err());
+        assert!(Query::parse("2023..june").is_err());
+        assert_eq!(Query::parse("a..b").unwrap().unwrap().terms, [word("a..b")]);
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let query = Query::parse(r#"camera:"X-T5" Lens:"56mm F1.2" iso:>3200 rating:3 date:2024-06 "new york""#).unwrap().unwrap();
+        assert_eq!(
+            query.terms,
+            [
+                Term::Camera("X-T5".to_string()),
+                Term::Lens("56mm F1.2".to_string()),
+                Term::Iso(Comparison::Greater, 3200),
+                Term::Rating(Comparison::Equal, 3),
+                word("new york"),
+            ]
+        );
+        assert_eq!(query.taken, Some((20240601, 20240631)));
+        assert_eq!(Query::parse("iso:<=800").unwrap().unwrap().terms, [Term::Iso(Comparison::LessOrEqual, 800)]);
+        assert!(Query::parse("iso:high").is_err());
+        assert!(Query::parse("camera:").is_err());
+        assert!(Query::parse("date:june").is_err());
+        assert!(Query::parse("date:2023 2024").is_err());
+        assert!(Query::parse("shutter:1/250").is_err());
+        assert!(Query::parse(r#"camera:"X-T5"#).is_err());
+        // Only letters make a field name
+        assert_eq!(Query::parse("12:30").unwrap().unwrap().terms, [word("12:30")]);
+    }
+
+    #[test]
+    fn test_scan_and_update() {
+        let dir = scratch_dir("catalog");
+        std::fs::create_dir_all(dir.join("trip")).unwrap();
+        let (a, b) = (dir.join("a.png"), dir.join("trip").join("b.png"));
+        image::RgbImage::new(4, 3).save(&a).unwrap();
+        image::RgbImage::new(4, 3).save(&b).unwrap();
+        let library = Library::open_in_memory().unwrap();
+        let sorted = |mut paths: Vec<PathBuf>| {
+            paths.sort();
+            paths
+        };
+
+        let scanned = AtomicUsize::new(0);
+        scan(&library, &dir, &dir, &scanned).unwrap();
+        assert_eq!(scanned.load(Ordering::Relaxed), 2);
+        assert_eq!(sorted(library.cataloged(&dir).unwrap()), [a.clone(), b.clone()]);
+        let (mtime, size) = crate::index::stamp(&a).unwrap();
+        assert_eq!(library.indexed_file(&a, mtime, size).unwrap().unwrap().dimensions, Some((4, 3)));
+
+        // Sidecars in both naming schemes lead back to their image
+        assert_eq!(crate::xmp::sidecar_images(&dir.join("a.xmp")), std::slice::from_ref(&a));
+        assert_eq!(crate::xmp::sidecar_images(&dir.join("a.png.xmp")), std::slice::from_ref(&a));
+        let roots = [dir.to_path_buf()];
+        update(&library, &roots, &[dir.join("a.xmp")]).unwrap();
+        let query = Query::parse("a.png rating:0").unwrap().unwrap();
+        assert_eq!(library.search(&query).unwrap(), std::slice::from_ref(&a));
+
+        // A folder removed, then moved back in
+        let trip = std::fs::read(&b).unwrap();
+        std::fs::remove_dir_all(dir.join("trip")).unwrap();
+        update(&library, &roots, &[dir.join("trip")]).unwrap();
+        assert_eq!(library.cataloged(&dir).unwrap(), std::slice::from_ref(&a));
+        std::fs::create_dir_all(dir.join("trip")).unwrap();
+        std::fs::write(&b, trip).unwrap();
+        update(&library, &roots, &[dir.join("trip")]).unwrap();
+        assert_eq!(sorted(library.cataloged(&dir).unwrap()), [a.clone(), b.clone()]);
+        std::fs::remove_file(&b).unwrap();
+        update(&library, &roots, std::slice::from_ref(&b)).unwrap();
+        // Outside the library
+        update(&library, &roots, &[PathBuf::from("/elsewhere/c.png")]).unwrap();
+        assert_eq!(library.catalog_count().unwrap(), 1);
+    }
+}
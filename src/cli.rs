@@ -0,0 +1,590 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::navigator::SortOrder;
+
+/// Command-line options for Momemtum.
+#[derive(Parser, Debug)]
+#[command(name = "momemtum", version, about = "High-performance image viewer")]
+pub struct Args {
+    /// Image file, folder or HTTP(S) URL to open
+    pub path: Option<PathBuf>,
+
+    /// Start in fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    /// Advance to the next image every SECONDS
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub slideshow: Option<f32>,
+
+    /// Pan across each image at PIXELS per second, in step with the display refresh
+    /// (negative runs right to left, or bottom to top)
+    #[arg(long, value_name = "PIXELS", value_parser = parse_speed, allow_negative_numbers = true)]
+    pub auto_pan: Option<f32>,
+
+    /// Order of images within the folder
+    #[arg(long, value_enum, default_value_t = SortOrder::Name)]
+    pub sort: SortOrder,
+
+    /// Include images from subfolders
+    #[arg(long, short)]
+    pub recursive: bool,
+
+    /// Visit the images in random order without repeats
+    #[arg(long)]
+    pub shuffle: bool,
+
+    /// Background color as #rrggbb, overriding the config file
+    #[arg(long, value_name = "COLOR", value_parser = parse_background)]
+    pub background: Option<String>,
+
+    /// Decode images in a separate restricted process (for untrusted files)
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// Read-only kiosk mode: disables delete, move, rename, metadata writes and settings changes
+    #[arg(long, alias = "read-only", global = true)]
+    pub kiosk: bool,
+
+    /// What to log, like RUST_LOG, e.g. "debug" or "warn,momemtum::loader=trace",
+    /// overriding the config file
+    #[arg(long, value_name = "FILTER", global = true)]
+    pub log: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Rename images from a template filled from EXIF, e.g. "{date}_{camera}_{seq:3}".
+    /// Placeholders: {date} {time} {year} {month} {day} {camera} {make} {name} {seq} {seq:N}
+    Rename {
+        template: String,
+        /// Image files or folders
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Only print the old and new names
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Revert the last batch of file operations (e.g. a rename)
+    Undo,
+    /// Composite 2 to 4 images side by side into a single image for sharing comparisons
+    Montage {
+        #[arg(required = true, num_args = 2..=crate::montage::MAX_IMAGES)]
+        paths: Vec<PathBuf>,
+        /// Output file (.png or .jpg)
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Caption each image with its file name
+        #[arg(long)]
+        labels: bool,
+        /// Replace the output file if it exists
+        #[arg(long)]
+        overwrite: bool,
+        /// Color space of the output; `color_space` under [export] when unset
+        #[arg(long, value_enum)]
+        color_space: Option<crate::export::ColorSpace>,
+    },
+    /// Save every frame of an animated GIF, WebP or PNG as numbered PNGs
+    Frames {
+        path: PathBuf,
+        /// Folder for the frames, created if needed
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Replace frames saved there before
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Save copies without EXIF, GPS, XMP or IPTC metadata for sharing, as `<name>_clean.jpg`
+    /// (or `.png`) next to each image
+    Strip {
+        /// Image files or folders
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Replace copies saved before
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Convert images to another format, optionally scaled down, e.g. `convert --to webp --max-size 2048 shoot/`
+    Convert {
+        /// Image files or folders
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        #[arg(long, value_enum)]
+        to: crate::convert::Format,
+        /// Scale images down so neither side exceeds PIXELS
+        #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+        max_size: Option<u32>,
+        /// Folder for the copies; a `converted` folder next to each image when unset
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Leave out the source's EXIF and XMP; `strip_metadata` under [export] when unset
+        #[arg(long)]
+        strip_metadata: bool,
+        /// Replace copies saved before
+        #[arg(long)]
+        overwrite: bool,
+        /// Color space of JPEG and PNG copies; `color_space` under [export] when unset
+        #[arg(long, value_enum)]
+        color_space: Option<crate::export::ColorSpace>,
+    },
+    /// Render images as a grid of thumbnails in one PNG, JPEG or PDF, e.g. `contact shoot/ -o shoot.pdf --captions`
+    Contact {
+        /// Image files or folders
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Output file (.pdf, .png, or a JPEG for any other extension)
+        #[arg(short, long)]
+        output: PathBuf,
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..))]
+        columns: u32,
+        /// Size of the square each thumbnail is fitted into
+        #[arg(long, value_name = "PIXELS", default_value_t = crate::thumbnail::THUMBNAIL_SIZE,
+              value_parser = clap::value_parser!(u32).range(16..=4096))]
+        cell: u32,
+        /// Caption each thumbnail with its file name
+        #[arg(long)]
+        captions: bool,
+        /// Replace the output file if it exists
+        #[arg(long)]
+        overwrite: bool,
+        /// Color space of PNG and JPEG sheets; `color_space` under [export] when unset
+        #[arg(long, value_enum)]
+        color_space: Option<crate::export::ColorSpace>,
+    },
+    /// Save a thumbnail of one image without opening a window, e.g. `thumb photo.nef -o photo.jpg --size 512`
+    Thumb {
+        path: PathBuf,
+        /// Output file (.png, or a JPEG for any other extension)
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Longest side of the thumbnail in pixels; smaller images keep their size
+        #[arg(long, value_name = "PIXELS", default_value_t = crate::thumbnail::THUMBNAIL_SIZE,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        size: u32,
+        /// Replace the output file if it exists
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Time reading, decoding, demosaicing, rotating and uploading each image and print a
+    /// table, e.g. `bench shoot/`
+    Bench {
+        /// Image files or folders
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Leave out the upload to the GPU, e.g. on a machine without one
+        #[arg(long)]
+        no_upload: bool,
+    },
+    /// Print the images of the library matching a query, one path per line, e.g.
+    /// `search camera:X-T5 'iso:>3200' date:2024-06`
+    Search {
+        /// Words the path, camera or lens contains, `camera:`, `lens:`, `iso:`, `rating:`
+        /// and `date:` terms, all of which must match
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Catalog what changed in the `[library]` folders first
+        #[arg(long)]
+        update: bool,
+    },
+    /// Internal: decode one image for a sandboxed viewer
+    #[command(hide = true)]
+    DecodeWorker {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long)]
+        nearest_demosaic: bool,
+    },
+}
+
+impl Command {
+    /// Whether the command writes files or the library, which kiosk mode refuses.
+    pub fn writes(&self) -> bool {
+        match self {
+            Command::Rename { dry_run, .. } => !dry_run,
+            Command::Search { update, .. } => *update,
+            Command::Undo
+            | Command::Montage { .. }
+            | Command::Frames { .. }
+            | Command::Strip { .. }
+            | Command::Convert { .. }
+            | Command::Contact { .. }
+            | Command::Thumb { .. } => true,
+            Command::Bench { .. } | Command::DecodeWorker { .. } => false,
+        }
+    }
+}
+
+/// Run a headless subcommand.
+pub fn run(command: &Command, read_only: bool, config: &crate::config::Config) -> Result<()> {
+    if read_only && command.writes() {
+        return Err(anyhow!("This command writes files, which is disabled in kiosk mode"));
+    }
+    let safety = &config.safety;
+    match command {
+        Command::Rename { template, paths, dry_run } => {
+            let files = collect_images(paths);
+            if files.is_empty() {
+                return Err(anyhow!("No supported images found"));
+            }
+            let plan = crate::rename::plan(&files, template)?;
+            print!("{}", crate::rename::format_table(&plan));
+
+            let problems = crate::rename::conflicts(&plan);
+            for problem in &problems {
+                eprintln!("Conflict: {}", problem);
+            }
+            if *dry_run {
+                return Ok(());
+            }
+            if safety.dry_run {
+                println!("Dry run: nothing renamed (dry_run is set under [safety])");
+                return Ok(());
+            }
+            if !problems.is_empty() {
+                return Err(anyhow!("Nothing renamed because of conflicts"));
+            }
+
+            let operations = crate::rename::execute(&plan)?;
+            let mut library = crate::library::Library::open()?;
+            crate::journal::record(&mut library, &operations)?;
+            println!("Renamed {} file(s). Run `momemtum undo` to revert.", operations.len());
+            Ok(())
+        }
+        Command::Undo => {
+            let library = crate::library::Library::open()?;
+            let reverted = crate::journal::undo_last(&library)?;
+            if reverted.is_empty() {
+                println!("Nothing to undo");
+            } else {
+                println!("Reverted {} operation(s)", reverted.len());
+            }
+            Ok(())
+        }
+        Command::Montage { paths, output, labels, overwrite, color_space } => {
+            if output.exists() && safety.confirm_overwrite && !overwrite {
+                return Err(anyhow!(
+                    "{} already exists; pass --overwrite or set confirm_overwrite = false under [safety]",
+                    output.display()
+                ));
+            }
+            crate::montage::save(paths, *labels, output, color_space.unwrap_or(config.export.color_space))?;
+            println!("Saved {}", output.display());
+            Ok(())
+        }
+        Command::Frames { path, output, overwrite } => {
+            let paths = crate::frames::extract(path, output, *overwrite || !safety.confirm_overwrite)?;
+            println!("Saved {} frame(s) to {}", paths.len(), output.display());
+            Ok(())
+        }
+        Command::Strip { paths, overwrite } => {
+            let files = collect_images(paths);
+            if files.is_empty() {
+                return Err(anyhow!("No supported images found"));
+            }
+            let mut failed = 0;
+            for file in &files {
+                let target = crate::export::clean_path(file);
+                if target.exists() && safety.confirm_overwrite && !overwrite {
+                    eprintln!("Skipped {}: {} already exists", file.display(), target.display());
+                    failed += 1;
+                    continue;
+                }
+                match crate::export::save_clean(file, &target) {
+                    Ok(()) => println!("Saved {}", target.display()),
+                    Err(e) => {
+                        eprintln!("Failed to strip {}: {:#}", file.display(), e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(anyhow!("{} of {} image(s) not saved", failed, files.len()));
+            }
+            Ok(())
+        }
+        Command::Convert { paths, to, max_size, output, strip_metadata, overwrite, color_space } => {
+            let files = collect_images(paths);
+            if files.is_empty() {
+                return Err(anyhow!("No supported images found"));
+            }
+            let options = crate::convert::Options {
+                format: *to,
+                max_size: *max_size,
+                color_space: color_space.unwrap_or(config.export.color_space),
+                strip_metadata: *strip_metadata || config.export.strip_metadata,
+                overwrite: *overwrite || !safety.confirm_overwrite,
+            };
+            let targets = crate::convert::target_paths(&files, output.as_deref(), *to);
+            if safety.dry_run {
+                for (file, target) in files.iter().zip(&targets) {
+                    println!("{} → {}", file.display(), target.display());
+                }
+                println!("Dry run: nothing converted (dry_run is set under [safety])");
+                return Ok(());
+            }
+            let progress = crate::convert::start(files, targets, options);
+            while !progress.finished() {
+                eprint!("\rConverting {} of {}", progress.done(), progress.total);
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            eprintln!("\rConverting {} of {}", progress.done(), progress.total);
+            let errors = progress.errors();
+            for (file, error) in &errors {
+                eprintln!("Failed to convert {}: {}", file.display(), error);
+            }
+            println!("Converted {} image(s)", progress.total - errors.len());
+            if !errors.is_empty() {
+                return Err(anyhow!("{} of {} image(s) not converted", errors.len(), progress.total));
+            }
+            Ok(())
+        }
+        Command::Contact { paths, output, columns, cell, captions, overwrite, color_space } => {
+            if output.exists() && safety.confirm_overwrite && !overwrite {
+                return Err(anyhow!(
+                    "{} already exists; pass --overwrite or set confirm_overwrite = false under [safety]",
+                    output.display()
+                ));
+            }
+            let files = collect_images(paths);
+            if files.is_empty() {
+                return Err(anyhow!("No supported images found"));
+            }
+            let layout = crate::contact::Layout { columns: *columns, cell: *cell, captions: *captions };
+            let cache = crate::cache::CacheKind::Thumbnails
+                .dir(config.cache.directory.as_deref())
+                .map(|dir| crate::thumbnail::DiskCache::new(dir, config.cache.thumbnail_cache_mb * 1024 * 1024));
+            let space = color_space.unwrap_or(config.export.color_space);
+            let failed = crate::contact::save(&files, output, &layout, space, cache.as_ref())?;
+            println!("Saved {} with {} image(s)", output.display(), files.len() - failed.len());
+            Ok(())
+        }
+        Command::Thumb { path, output, size, overwrite } => {
+            if output.exists() && safety.confirm_overwrite && !overwrite {
+                return Err(anyhow!(
+                    "{} already exists; pass --overwrite or set confirm_overwrite = false under [safety]",
+                    output.display()
+                ));
+            }
+            crate::thumbnail::save(path, output, *size)?;
+            println!("Saved {}", output.display());
+            Ok(())
+        }
+        Command::Bench { paths, no_upload } => {
+            let files = collect_images(paths);
+            if files.is_empty() {
+                return Err(anyhow!("No supported images found"));
+            }
+            crate::bench::run(&files, !no_upload)
+        }
+        Command::Search { query, update } => {
+            let text = query.iter().map(|arg| quote_arg(arg)).collect::<Vec<_>>().join(" ");
+            let query = crate::catalog::Query::parse(&text)?.ok_or_else(|| anyhow!("Nothing to search for"))?;
+            let library = crate::library::Library::open()?;
+            if *update {
+                let scanned = std::sync::atomic::AtomicUsize::new(0);
+                crate::catalog::rescan(&library, &config.library.folders, &scanned)?;
+                eprintln!("Looked at {} image(s)", scanned.into_inner());
+            }
+            if library.catalog_count()? == 0 {
+                return Err(anyhow!(
+                    "The library catalog is empty; list folders under [library] in the config and pass --update or open the viewer"
+                ));
+            }
+            for path in library.search(&query)? {
+                println!("{}", path.display());
+            }
+            Ok(())
+        }
+        Command::DecodeWorker { input, output, nearest_demosaic } => {
+            crate::sandbox::run_worker(input, output, *nearest_demosaic)
+        }
+    }
+}
+
+fn parse_seconds(text: &str) -> std::result::Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err("expected a positive number of seconds".to_string()),
+    }
+}
+
+fn parse_speed(text: &str) -> std::result::Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(speed) if speed != 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err("expected a non-zero number of pixels per second".to_string()),
+    }
+}
+
+fn parse_background(text: &str) -> std::result::Result<String, String> {
+    match crate::config::parse_color(text) {
+        Some(_) => Ok(text.to_string()),
+        None => Err("expected a color like #000000".to_string()),
+    }
+}
+
+/// An argument as one search term: the shell has already taken its quotes off, so one
+/// holding spaces, like `camera:FUJIFILM X-T5`, gets them back.
+fn quote_arg(arg: &str) -> String {
+    if !arg.contains(char::is_whitespace) || arg.contains('"') {
+        return arg.to_string();
+    }
+    match arg.split_once(':') {
+        Some((key, value)) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()) => {
+            format!("{}:\"{}\"", key, value)
+        }
+        _ => format!("\"{}\"", arg),
+    }
+}
+
+/// Expand folders into their supported images, sorted by name.
+fn collect_images(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(path)
+                .map(|rd| rd.flatten().map(|e| e.path()).collect())
+                .unwrap_or_default();
+            entries.retain(|p| p.is_file() && crate::navigator::is_supported_image(p));
+            entries.sort();
+            files.extend(entries);
+        } else if path.is_file() {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kiosk_flag() {
+        let args = Args::parse_from(["momemtum"]);
+        assert!(!args.kiosk);
+
+        let args = Args::parse_from(["momemtum", "--kiosk"]);
+        assert!(args.kiosk);
+
+        let args = Args::parse_from(["momemtum", "--read-only"]);
+        assert!(args.kiosk);
+
+        // Every subcommand that writes is refused under it; previews and reads are not
+        let writes = |argv: &[&str]| Args::parse_from(argv).command.unwrap().writes();
+        assert!(writes(&["momemtum", "rename", "{date}", "shoot"]));
+        assert!(!writes(&["momemtum", "rename", "{date}", "shoot", "--dry-run"]));
+        assert!(writes(&["momemtum", "undo"]));
+        assert!(writes(&["momemtum", "strip", "a.jpg"]));
+        assert!(writes(&["momemtum", "convert", "--to", "webp", "a.jpg"]));
+        assert!(writes(&["momemtum", "contact", "shoot/", "-o", "a.pdf"]));
+        assert!(writes(&["momemtum", "montage", "a.jpg", "b.jpg", "-o", "c.png"]));
+        assert!(writes(&["momemtum", "thumb", "a.jpg", "-o", "b.jpg"]));
+        assert!(writes(&["momemtum", "frames", "a.gif", "-o", "frames/"]));
+        assert!(writes(&["momemtum", "search", "lanterns", "--update"]));
+        assert!(!writes(&["momemtum", "search", "lanterns"]));
+        assert!(!writes(&["momemtum", "bench", "shoot/"]));
+        let config = crate::config::Config::default();
+        let strip = Args::parse_from(["momemtum", "strip", "a.jpg", "--overwrite"]).command.unwrap();
+        assert!(run(&strip, true, &config).is_err());
+    }
+
+    #[test]
+    fn test_viewer_options() {
+        let args = Args::parse_from([
+            "momemtum", "shoot", "--fullscreen", "--slideshow", "5", "--sort", "date", "--recursive",
+            "--shuffle", "--background", "#000000", "--auto-pan", "-120",
+        ]);
+        assert_eq!(args.path, Some(PathBuf::from("shoot")));
+        assert!(args.fullscreen);
+        assert_eq!(args.slideshow, Some(5.0));
+        assert_eq!(args.sort, SortOrder::Modified);
+        assert!(args.recursive);
+        assert!(args.shuffle);
+        assert_eq!(args.background.as_deref(), Some("#000000"));
+        assert_eq!(args.auto_pan, Some(-120.0));
+        assert!(args.command.is_none());
+
+        assert!(Args::try_parse_from(["momemtum", "--background", "black"]).is_err());
+        assert!(Args::try_parse_from(["momemtum", "--slideshow", "0"]).is_err());
+        assert!(Args::try_parse_from(["momemtum", "--auto-pan", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_rename_subcommand() {
+        let args = Args::parse_from(["momemtum", "rename", "{date}_{seq}", "shoot", "--dry-run", "--kiosk"]);
+        assert!(args.kiosk);
+        match args.command {
+            Some(Command::Rename { template, paths, dry_run }) => {
+                assert_eq!(template, "{date}_{seq}");
+                assert_eq!(paths, vec![PathBuf::from("shoot")]);
+                assert!(dry_run);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Args::try_parse_from(["momemtum", "rename", "{date}"]).is_err());
+    }
+
+    #[test]
+    fn test_convert_subcommand() {
+        let args = Args::parse_from(["momemtum", "convert", "--to", "webp", "--max-size", "2048", "shoot/"]);
+        match args.command {
+            Some(Command::Convert { paths, to, max_size, output, .. }) => {
+                assert_eq!(paths, vec![PathBuf::from("shoot/")]);
+                assert_eq!((to, max_size, output), (crate::convert::Format::Webp, Some(2048), None));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Args::try_parse_from(["momemtum", "convert", "--to", "jpg", "--max-size", "0", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn test_contact_subcommand() {
+        let args = Args::parse_from(["momemtum", "contact", "shoot/", "-o", "shoot.pdf", "--columns", "4", "--captions"]);
+        match args.command {
+            Some(Command::Contact { paths, output, columns, cell, captions, .. }) => {
+                assert_eq!((paths, output), (vec![PathBuf::from("shoot/")], PathBuf::from("shoot.pdf")));
+                assert_eq!((columns, cell, captions), (4, crate::thumbnail::THUMBNAIL_SIZE, true));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Args::try_parse_from(["momemtum", "contact", "shoot/", "-o", "a.png", "--columns", "0"]).is_err());
+        assert!(Args::try_parse_from(["momemtum", "contact", "-o", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn test_thumb_subcommand() {
+        let args = Args::parse_from(["momemtum", "thumb", "photo.nef", "-o", "out.jpg", "--size", "512"]);
+        match args.command {
+            Some(Command::Thumb { path, output, size, overwrite }) => {
+                assert_eq!((path, output), (PathBuf::from("photo.nef"), PathBuf::from("out.jpg")));
+                assert_eq!((size, overwrite), (512, false));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        match Args::parse_from(["momemtum", "thumb", "photo.nef", "-o", "out.png"]).command {
+            Some(Command::Thumb { size, .. }) => assert_eq!(size, crate::thumbnail::THUMBNAIL_SIZE),
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Args::try_parse_from(["momemtum", "thumb", "photo.nef"]).is_err());
+        assert!(Args::try_parse_from(["momemtum", "thumb", "a.jpg", "-o", "b.jpg", "--size", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_search_subcommand() {
+        let args = Args::parse_from(["momemtum", "search", "camera:FUJIFILM X-T5", "iso:>3200", "new york", "--update"]);
+        match args.command {
+            Some(Command::Search { query, update }) => {
+                let text: Vec<String> = query.iter().map(|arg| quote_arg(arg)).collect();
+                assert_eq!(text, [r#"camera:"FUJIFILM X-T5""#, "iso:>3200", r#""new york""#]);
+                assert!(update);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Args::try_parse_from(["momemtum", "search"]).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Compare up to four images in panes that share one view: zooming or panning
+//! moves them all together, to pick the best frame of a short burst. In blink
+//! mode one image fills the window and a key swaps it for the other of a pair,
+//! so small differences jump out; in wipe mode the pair shares the window,
+//! split by a divider that can be dragged across; difference mode shows how
+//! far apart the pair is at each pixel, amplified and optionally as a heatmap.
+
+use image::DynamicImage;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+
+use crate::quad::Rect;
+
+pub const MAX_PANES: usize = crate::montage::MAX_IMAGES;
+/// Longest side panes are decoded to; four full-size textures would not fit every GPU.
+const MAX_TEXTURE_SIZE: u32 = 4096;
+const GAP: f32 = 4.0;
+const MAX_ZOOM: f32 = 64.0;
+const MAX_GAIN: f32 = 64.0;
+
+/// The shared view: magnification over fitting the pane, and the image point
+/// (texture coordinates) at the pane center.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub zoom: f32,
+    pub center: (f32, f32),
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self { zoom: 1.0, center: (0.5, 0.5) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Every image in its own pane
+    Side,
+    /// The focused image over the whole area
+    Blink,
+    /// The focused image left of the divider, the one focused before right of it
+    Wipe,
+    /// The per-pixel difference between the focused image and the one focused before
+    Difference,
+}
+
+pub struct Compare {
+    pub paths: Vec<PathBuf>,
+    /// The pane keyboard actions apply to
+    pub focus: usize,
+    /// The pane focused before, which blinking swaps with
+    pub previous: usize,
+    pub view: View,
+    pub mode: Mode,
+    /// Divider position in wipe mode, as a share of the area width
+    pub split: f32,
+    /// The divider is being dragged
+    pub dragging_split: bool,
+    /// What the difference is multiplied by before showing it
+    pub gain: f32,
+    /// Show the difference as a heatmap rather than per channel
+    pub heatmap: bool,
+    receiver: Receiver<(usize, DynamicImage)>,
+}
+
+impl Compare {
+    /// Start decoding the first `MAX_PANES` of `paths` in the background.
+    pub fn open(mut paths: Vec<PathBuf>) -> Self {
+        paths.truncate(MAX_PANES);
+        let (sender, receiver) = channel();
+        for (i, path) in paths.iter().cloned().enumerate() {
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                let _interactive = crate::priority::interactive();
+                match crate::sandbox::load(&path) {
+                    Ok(loaded) => {
+                        let mut img = loaded.image;
+                        if img.width().max(img.height()) > MAX_TEXTURE_SIZE {
+                            img = img.resize(MAX_TEXTURE_SIZE, MAX_TEXTURE_SIZE, image::imageops::FilterType::Triangle);
+                        }
+                        let _ = sender.send((i, img));
+                        crate::redraw::wake();
+                    }
+                    Err(e) => log::warn!("Failed to load {:?} for comparing: {:?}", path, e),
+                }
+            });
+        }
+        Self {
+            paths,
+            focus: 0,
+            previous: 1,
+            view: View::default(),
+            mode: Mode::Side,
+            split: 0.5,
+            dragging_split: false,
+            gain: 1.0,
+            heatmap: false,
+            receiver,
+        }
+    }
+
+    /// Panes decoded since the last call.
+    pub fn poll(&self) -> Vec<(usize, DynamicImage)> {
+        self.receiver.try_iter().collect()
+    }
+
+    pub fn set_focus(&mut self, pane: usize) {
+        if pane < self.paths.len() && pane != self.focus {
+            self.previous = self.focus;
+            self.focus = pane;
+        }
+    }
+
+    pub fn focus_next(&mut self, forward: bool) {
+        let n = self.paths.len().max(1);
+        self.set_focus(if forward { (self.focus + 1) % n } else { (self.focus + n - 1) % n });
+    }
+
+    /// Show the other image of the A/B pair.
+    pub fn swap(&mut self) {
+        self.set_focus(self.previous);
+    }
+
+    /// Switch to `mode`, or back to side by side if already in it.
+    pub fn toggle_mode(&mut self, mode: Mode) {
+        self.mode = if self.mode == mode { Mode::Side } else { mode };
+    }
+
+    /// Pane rectangles in `area`: one per image, all of it for each while blinking or wiping.
+    pub fn panes(&self, area: Rect) -> Vec<Rect> {
+        match self.mode {
+            Mode::Side => layout(area, self.paths.len()),
+            Mode::Blink | Mode::Wipe | Mode::Difference => vec![area; self.paths.len()],
+        }
+    }
+
+    /// Double or halve the difference gain.
+    pub fn amplify(&mut self, more: bool) {
+        self.gain = if more { self.gain * 2.0 } else { self.gain / 2.0 }.clamp(1.0, MAX_GAIN);
+    }
+
+    /// Put the wipe divider at window x `x` of `area`.
+    pub fn set_split(&mut self, x: f32, area: Rect) {
+        self.split = ((x - area.x) / area.w.max(1.0)).clamp(0.0, 1.0);
+    }
+
+    /// Zoom by `factor`, keeping the image point under `point` of `pane` in place.
+    pub fn zoom_at(&mut self, factor: f32, pane: Rect, aspect: f32, point: (f32, f32)) {
+        let zoom = (self.view.zoom * factor).clamp(1.0, MAX_ZOOM);
+        if zoom <= 1.0 {
+            self.view = View::default();
+            return;
+        }
+        let (iw, ih) = displayed_size(pane, aspect, self.view.zoom);
+        let (ox, oy) = origin(pane, (iw, ih), self.view.center);
+        let under = ((point.0 - ox) / iw, (point.1 - oy) / ih);
+        let (iw, ih) = displayed_size(pane, aspect, zoom);
+        let (cx, cy) = (pane.x + pane.w / 2.0, pane.y + pane.h / 2.0);
+        self.view.zoom = zoom;
+        self.view.center = clamp_center((under.0 - (point.0 - cx) / iw, under.1 - (point.1 - cy) / ih));
+    }
+
+    /// Move the view by a window-pixel drag over `pane`.
+    pub fn pan(&mut self, dx: f32, dy: f32, pane: Rect, aspect: f32) {
+        let (iw, ih) = displayed_size(pane, aspect, self.view.zoom);
+        self.view.center = clamp_center((self.view.center.0 - dx / iw, self.view.center.1 - dy / ih));
+    }
+}
+
+fn clamp_center(center: (f32, f32)) -> (f32, f32) {
+    (center.0.clamp(0.0, 1.0), center.1.clamp(0.0, 1.0))
+}
+
+/// Pane rectangles in `area`: side by side for two, otherwise two by two.
+pub fn layout(area: Rect, count: usize) -> Vec<Rect> {
+    let (columns, rows) = match count {
+        0 => return Vec::new(),
+        1 => (1, 1),
+        2 => (2, 1),
+        _ => (2, 2),
+    };
+    let w = (area.w - GAP * (columns - 1) as f32) / columns as f32;
+    let h = (area.h - GAP * (rows - 1) as f32) / rows as f32;
+    (0..count)
+        .map(|i| {
+            let (column, row) = (i % columns, i / columns);
+            Rect::new(area.x + column as f32 * (w + GAP), area.y + row as f32 * (h + GAP), w, h)
+        })
+        .collect()
+}
+
+fn displayed_size(pane: Rect, aspect: f32, zoom: f32) -> (f32, f32) {
+    let (w, h) = if pane.w / pane.h > aspect { (pane.h * aspect, pane.h) } else { (pane.w, pane.w / aspect) };
+    (w * zoom, h * zoom)
+}
+
+fn origin(pane: Rect, size: (f32, f32), center: (f32, f32)) -> (f32, f32) {
+    (pane.x + pane.w / 2.0 - center.0 * size.0, pane.y + pane.h / 2.0 - center.1 * size.1)
+}
+
+/// Where an image of `aspect` shows in `pane` under `view`: the window rectangle
+/// and the texture coordinates it shows, clipped to the pane.
+pub fn place(pane: Rect, aspect: f32, view: &View) -> Option<(Rect, [f32; 4])> {
+    let (iw, ih) = displayed_size(pane, aspect, view.zoom);
+    let (ox, oy) = origin(pane, (iw, ih), view.center);
+    let (x0, y0) = (ox.max(pane.x), oy.max(pane.y));
+    let (x1, y1) = ((ox + iw).min(pane.x + pane.w), (oy + ih).min(pane.y + pane.h));
+    if x1 <= x0 || y1 <= y0 {
+        return None;
+    }
+    let uv = [(x0 - ox) / iw, (y0 - oy) / ih, (x1 - ox) / iw, (y1 - oy) / ih];
+    Some((Rect::new(x0, y0, x1 - x0, y1 - y0), uv))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_and_place() {
+        let panes = layout(Rect::new(0.0, 0.0, 804.0, 404.0), 4);
+        assert_eq!(panes[3], Rect::new(404.0, 204.0, 400.0, 200.0));
+        assert_eq!(layout(Rect::new(0.0, 0.0, 804.0, 400.0), 2)[1].w, 400.0);
+
+        // Fitted, a 2:1 image fills a 2:1 pane
+        let pane = panes[0];
+        let (rect, uv) = place(pane, 2.0, &View::default()).unwrap();
+        assert_eq!((rect, uv), (pane, [0.0, 0.0, 1.0, 1.0]));
+        // A square one is centered
+        let (rect, _) = place(pane, 1.0, &View::default()).unwrap();
+        assert_eq!(rect, Rect::new(100.0, 0.0, 200.0, 200.0));
+
+        // At 2x around the top left quarter, that quarter fills the pane
+        let view = View { zoom: 2.0, center: (0.25, 0.25) };
+        let (rect, uv) = place(pane, 2.0, &view).unwrap();
+        assert_eq!((rect, uv), (pane, [0.0, 0.0, 0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_zoom_keeps_the_point() {
+        let mut compare = Compare::open(Vec::new());
+        let pane = Rect::new(0.0, 0.0, 400.0, 200.0);
+        // Zooming in on the top left corner keeps it there
+        compare.zoom_at(2.0, pane, 2.0, (0.0, 0.0));
+        assert_eq!(compare.view, View { zoom: 2.0, center: (0.25, 0.25) });
+        compare.pan(-200.0, -100.0, pane, 2.0);
+        assert_eq!(compare.view.center, (0.5, 0.5));
+        compare.zoom_at(0.25, pane, 2.0, (0.0, 0.0));
+        assert_eq!(compare.view, View::default());
+    }
+
+    #[test]
+    fn test_blink_swaps_the_pair() {
+        let mut compare = Compare::open(["a", "b", "c"].iter().map(PathBuf::from).collect());
+        compare.swap();
+        assert_eq!((compare.focus, compare.previous), (1, 0));
+        compare.swap();
+        assert_eq!(compare.focus, 0);
+        // Picking another image makes it the pair with the one shown
+        compare.set_focus(2);
+        compare.swap();
+        assert_eq!(compare.focus, 0);
+        compare.set_focus(7);
+        assert_eq!(compare.focus, 0);
+
+        let area = Rect::new(0.0, 0.0, 800.0, 400.0);
+        compare.toggle_mode(Mode::Blink);
+        assert_eq!(compare.panes(area), vec![area; 3]);
+        compare.toggle_mode(Mode::Wipe);
+        assert_eq!(compare.mode, Mode::Wipe);
+        compare.set_split(200.0, area);
+        assert_eq!(compare.split, 0.25);
+        compare.set_split(-5.0, area);
+        assert_eq!(compare.split, 0.0);
+        compare.toggle_mode(Mode::Difference);
+        assert_eq!(compare.panes(area), vec![area; 3]);
+        compare.amplify(false);
+        assert_eq!(compare.gain, 1.0);
+        (0..8).for_each(|_| compare.amplify(true));
+        assert_eq!(compare.gain, MAX_GAIN);
+        compare.toggle_mode(Mode::Difference);
+        assert_eq!(compare.panes(area).len(), 3);
+        assert_eq!(compare.panes(area)[1].w, 398.0);
+    }
+}
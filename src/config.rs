@@ -0,0 +1,431 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::keymap::KeyList;
+
+/// User settings, persisted as `config.toml` in the platform config directory.
+/// Missing keys fall back to their defaults, so old config files keep working.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub view: ViewConfig,
+    pub cache: CacheConfig,
+    pub decode: DecodeConfig,
+    pub navigation: NavigationConfig,
+    pub library: LibraryConfig,
+    pub map: MapConfig,
+    pub places: PlacesConfig,
+    pub safety: SafetyConfig,
+    pub culling: CullingConfig,
+    pub matte: MatteConfig,
+    pub snapshot: SnapshotConfig,
+    pub export: ExportConfig,
+    pub exposure: ExposureConfig,
+    pub log: LogConfig,
+    /// Action name to key combination(s), e.g. `next_image = ["Right", "Space"]`.
+    /// Actions not listed keep their default keys.
+    pub keybindings: BTreeMap<String, KeyList>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Hand files opened later to the running window instead of starting another one
+    pub single_instance: bool,
+    /// Reopen the last image, view, window placement, sort order and compare on launch
+    pub restore_session: bool,
+    /// How frames reach the display; vsync is used where the one asked for isn't supported
+    pub present_mode: PresentMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentMode {
+    /// Vsync: every frame waits for the display's refresh, never tearing
+    Fifo,
+    /// A newer frame replaces one waiting for the refresh: no tearing, less lag
+    Mailbox,
+    /// Frames show at once, which can tear while panning
+    Immediate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ViewConfig {
+    /// Background color as `#rrggbb`
+    pub background: String,
+    pub zoom_mode: ZoomMode,
+    /// Switch between `light_background` and `dark_background` automatically
+    pub theme: ThemeMode,
+    pub light_background: String,
+    pub dark_background: String,
+    /// Latitude and longitude for the `sun` theme, e.g. `[48.85, 2.35]`
+    pub location: Option<[f64; 2]>,
+    /// Seconds between images in a slideshow started without `--slideshow`; none for no slideshow
+    pub slideshow_interval: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeMode {
+    /// Always `background`
+    Fixed,
+    /// Follow the OS light/dark setting
+    System,
+    /// Dark between sunset and sunrise at `location`
+    Sun,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoomMode {
+    /// Whole image visible
+    Fit,
+    /// One image pixel per screen pixel
+    Actual,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub thumbnail_cache_mb: u64,
+    /// Images opened from the web kept, in MB, past which the oldest are deleted
+    pub download_cache_mb: u64,
+    /// Memory held for images, tiles and thumbnails, in MB, past which what can be
+    /// loaded again is dropped; 0 for no limit
+    pub memory_budget_mb: u64,
+    /// Folder for all caches, e.g. on a fast SSD; the platform cache folder when unset
+    pub directory: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DecodeConfig {
+    /// Decode in a separate restricted process so a malicious file can't take down the viewer
+    pub sandbox: bool,
+    /// JPEGs of at least this many megapixels open as a screen-sized proxy, decoded at
+    /// full resolution only when zoomed in past it; 0 always decodes them whole
+    pub proxy_megapixels: u64,
+    /// How RAW files fill in the colors their sensor didn't record
+    pub demosaic: DemosaicMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DemosaicMode {
+    /// Averaged from the neighbours
+    Bilinear,
+    /// Copied from the nearest neighbour: faster, with color fringes on fine detail
+    Nearest,
+}
+
+impl DemosaicMode {
+    pub fn algorithm(self) -> crate::raw::Demosaic {
+        match self {
+            DemosaicMode::Bilinear => crate::raw::Demosaic::Bilinear,
+            DemosaicMode::Nearest => crate::raw::Demosaic::Nearest,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct NavigationConfig {
+    /// Next on the last image goes to the first, and previous on the first to the last
+    pub wrap_around: bool,
+    /// Visit the folder in random order without repeats
+    pub shuffle: bool,
+    /// Clicking the left or right edge of a fitted image goes to the previous or next one
+    pub edge_click: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Folders whose images, subfolders included, are cataloged for searching the whole
+    /// library; library mode is off without any
+    pub folders: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MapConfig {
+    /// Slippy-map tile server, with `{z}`, `{x}` and `{y}` placeholders
+    pub tile_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PlacesConfig {
+    /// Reverse geocoding service naming places, with `{lat}` and `{lon}` placeholders.
+    /// Names looked up before are kept; an empty URL looks up no others
+    pub geocode_url: String,
+    /// Photos taken this many kilometres apart or closer count as one place
+    pub radius_km: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SafetyConfig {
+    /// Ask before moving a file to the trash
+    pub confirm_delete: bool,
+    /// Ask before an export replaces an existing file
+    pub confirm_overwrite: bool,
+    /// Ask before writing notes or other metadata to XMP sidecars
+    pub confirm_metadata_write: bool,
+    /// Batch operations (renaming, converting, trashing duplicates) only show what they would do
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct CullingConfig {
+    /// Folders the keys 1 to 9 send the current image to, in order
+    pub targets: Vec<PathBuf>,
+    /// What the number keys do; with Shift they do the other
+    pub mode: TransferMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferMode {
+    #[default]
+    Move,
+    Copy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MatteConfig {
+    /// Border color as `#rrggbb`
+    pub color: String,
+    /// Border width as a fraction of the image's shorter side
+    pub width: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    /// Width of saved views in pixels, keeping the window's aspect ratio; the window's when unset
+    pub width: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ExportConfig {
+    /// Color space of crops, redacted copies, saved views and montages: `srgb`, `display-p3` or `adobe-rgb`
+    pub color_space: crate::export::ColorSpace,
+    /// Leave out the source's EXIF and XMP (camera, date, location) from crops and saved views
+    pub strip_metadata: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ExposureConfig {
+    /// Display value (0-1) from which a channel counts as blown in the clipping warning
+    pub highlight: f32,
+    /// Display value (0-1) up to which all channels count as crushed
+    pub shadow: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Least severe messages logged: `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+    /// Levels of single subsystems, by module name, e.g. `loader = "debug"`
+    pub subsystems: BTreeMap<String, String>,
+    /// Also write the log to `momemtum.log` in the data folder
+    pub file: bool,
+    /// Size in MB at which the log file is set aside for a new one
+    pub max_file_mb: u64,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self { width: 1280, height: 800, single_instance: true, restore_session: false, present_mode: PresentMode::Fifo }
+    }
+}
+
+impl Default for ViewConfig {
+    fn default() -> Self {
+        Self {
+            background: "#595959".to_string(),
+            zoom_mode: ZoomMode::Fit,
+            theme: ThemeMode::Fixed,
+            light_background: "#a6a6a6".to_string(),
+            dark_background: "#262626".to_string(),
+            location: None,
+            slideshow_interval: None,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            thumbnail_cache_mb: crate::thumbnail::DEFAULT_CACHE_BYTES / 1024 / 1024,
+            download_cache_mb: crate::remote::DEFAULT_KEEP_BYTES / 1024 / 1024,
+            memory_budget_mb: 2048,
+            directory: None,
+        }
+    }
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        Self { sandbox: false, proxy_megapixels: 100, demosaic: DemosaicMode::Bilinear }
+    }
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        Self { tile_url: crate::map::DEFAULT_TILE_URL.to_string() }
+    }
+}
+
+impl Default for PlacesConfig {
+    fn default() -> Self {
+        Self { geocode_url: crate::places::DEFAULT_GEOCODE_URL.to_string(), radius_km: 10.0 }
+    }
+}
+
+impl Default for MatteConfig {
+    fn default() -> Self {
+        Self { color: "#ffffff".to_string(), width: 0.08 }
+    }
+}
+
+impl Default for ExposureConfig {
+    fn default() -> Self {
+        Self { highlight: 0.98, shadow: 0.02 }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { level: "info".to_string(), subsystems: BTreeMap::new(), file: true, max_file_mb: 5 }
+    }
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            confirm_delete: true,
+            confirm_overwrite: true,
+            confirm_metadata_write: false,
+            dry_run: false,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            window: WindowConfig::default(),
+            view: ViewConfig::default(),
+            cache: CacheConfig::default(),
+            decode: DecodeConfig::default(),
+            navigation: NavigationConfig::default(),
+            library: LibraryConfig::default(),
+            map: MapConfig::default(),
+            places: PlacesConfig::default(),
+            safety: SafetyConfig::default(),
+            culling: CullingConfig::default(),
+            matte: MatteConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            export: ExportConfig::default(),
+            exposure: ExposureConfig::default(),
+            log: LogConfig::default(),
+            keybindings: crate::keymap::default_bindings(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("momemtum").join("config.toml"))
+    }
+
+    /// Load the config file, writing the defaults on first run so users can edit them
+    /// unless `write_defaults` is false, as in kiosk mode. What went wrong comes back
+    /// with it, to be logged once logging is set up, which the config says how to do.
+    pub fn load(write_defaults: bool) -> (Self, Option<String>) {
+        let Some(path) = Self::path() else {
+            return (Self::default(), None);
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => match toml::from_str(&text) {
+                Ok(config) => (config, None),
+                Err(e) => (Self::default(), Some(format!("Invalid config file {:?}, using defaults: {}", path, e))),
+            },
+            Err(_) => {
+                let config = Self::default();
+                if !write_defaults {
+                    return (config, None);
+                }
+                let problem = config.save().err().map(|e| format!("Failed to write default config: {:?}", e));
+                (config, problem)
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow!("No config directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Parse `#rrggbb` into linear-light RGB, as expected by an sRGB render target.
+pub fn parse_color(text: &str) -> Option<[f64; 3]> {
+    let hex = text.trim().strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| -> Option<f64> {
+        let v = u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()? as f64 / 255.0;
+        Some(if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) })
+    };
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config: Config = toml::from_str("[view]\nzoom_mode = \"actual\"\n").unwrap();
+        assert_eq!(config.view.zoom_mode, ZoomMode::Actual);
+        assert_eq!(config.view.background, ViewConfig::default().background);
+        assert_eq!(config.window, WindowConfig::default());
+
+        let config: Config = toml::from_str("[decode]\ndemosaic = \"nearest\"\n[view]\nslideshow_interval = 4.5\n").unwrap();
+        assert_eq!(config.decode.demosaic, DemosaicMode::Nearest);
+        assert_eq!(config.view.slideshow_interval, Some(4.5));
+        assert!(toml::from_str::<Config>("[decode]\ndemosaic = \"ahd\"\n").is_err());
+
+        let text = toml::to_string_pretty(&Config::default()).unwrap();
+        assert_eq!(toml::from_str::<Config>(&text).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#000000"), Some([0.0, 0.0, 0.0]));
+        assert_eq!(parse_color("#ffffff"), Some([1.0, 1.0, 1.0]));
+        let gray = parse_color("#595959").unwrap();
+        assert!((gray[0] - 0.1).abs() < 0.001);
+        assert_eq!(parse_color("595959"), None);
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+    }
+}
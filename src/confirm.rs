@@ -0,0 +1,94 @@
+//! Yes/no questions before destructive operations, as configured under `[safety]`.
+
+use winit::event::KeyEvent;
+use winit::keyboard::{Key, NamedKey};
+
+use crate::config::SafetyConfig;
+
+/// The kinds of operation a confirmation can be switched off for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Guard {
+    Delete,
+    Overwrite,
+    MetadataWrite,
+}
+
+impl Guard {
+    /// Whether the settings ask to confirm this kind of operation.
+    pub fn required(self, safety: &SafetyConfig) -> bool {
+        match self {
+            Guard::Delete => safety.confirm_delete,
+            Guard::Overwrite => safety.confirm_overwrite,
+            Guard::MetadataWrite => safety.confirm_metadata_write,
+        }
+    }
+
+    /// "Don't ask again".
+    pub fn stop_asking(self, safety: &mut SafetyConfig) {
+        match self {
+            Guard::Delete => safety.confirm_delete = false,
+            Guard::Overwrite => safety.confirm_overwrite = false,
+            Guard::MetadataWrite => safety.confirm_metadata_write = false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Answer {
+    Yes,
+    No,
+    /// Yes, and don't ask again
+    Always,
+}
+
+/// An operation waiting for the user's answer, shown in the window title.
+pub struct Confirmation<T> {
+    pub guard: Guard,
+    pub question: String,
+    pub action: T,
+}
+
+impl<T> Confirmation<T> {
+    pub fn new(guard: Guard, question: String, action: T) -> Self {
+        Self { guard, question, action }
+    }
+
+    pub fn display(&self) -> String {
+        format!("{} (Y)es / (N)o / (A)lways, don't ask again", self.question)
+    }
+}
+
+/// Y or Enter, N or Escape, A; other keys are ignored.
+pub fn answer(event: &KeyEvent) -> Option<Answer> {
+    match &event.logical_key {
+        Key::Named(NamedKey::Enter) => Some(Answer::Yes),
+        Key::Named(NamedKey::Escape) => Some(Answer::No),
+        Key::Character(c) => match c.to_lowercase().as_str() {
+            "y" => Some(Answer::Yes),
+            "n" => Some(Answer::No),
+            "a" => Some(Answer::Always),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guards_follow_settings() {
+        let mut safety = SafetyConfig::default();
+        assert!(Guard::Delete.required(&safety));
+        assert!(Guard::Overwrite.required(&safety));
+        assert!(!Guard::MetadataWrite.required(&safety));
+
+        Guard::Delete.stop_asking(&mut safety);
+        assert!(!Guard::Delete.required(&safety));
+        assert!(Guard::Overwrite.required(&safety));
+
+        let confirmation = Confirmation::new(Guard::Delete, "Delete a.jpg?".to_string(), ());
+        assert_eq!(confirmation.display(), "Delete a.jpg? (Y)es / (N)o / (A)lways, don't ask again");
+    }
+}
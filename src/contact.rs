@@ -0,0 +1,239 @@
+//! Contact sheets: a folder's images as a grid of thumbnails, optionally captioned with
+//! their file names, in a single PNG, JPEG or PDF to share an overview of a shoot.
+
+use anyhow::{anyhow, Result};
+use image::{imageops, DynamicImage, RgbImage};
+use rayon::prelude::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::export::{ColorSpace, Metadata};
+use crate::montage::{draw_text, text_width, BACKGROUND, GLYPH_HEIGHT};
+use crate::thumbnail::{DiskCache, Thumbnailer, X_LARGE_THUMBNAIL_SIZE};
+
+const GAP: u32 = 16;
+/// Resolution PDF pages are sized for, so a sheet prints at a sensible size
+const PDF_DPI: f32 = 150.0;
+const PDF_JPEG_QUALITY: u8 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub columns: u32,
+    /// Width and height of the square each thumbnail is fitted into
+    pub cell: u32,
+    pub captions: bool,
+}
+
+impl Layout {
+    /// Caption size: one font pixel per 128 pixels of cell.
+    fn caption_scale(&self) -> u32 {
+        (self.cell / 128).clamp(1, 4)
+    }
+
+    fn caption_height(&self) -> u32 {
+        match self.captions {
+            true => GLYPH_HEIGHT * self.caption_scale() + GAP / 2,
+            false => 0,
+        }
+    }
+
+    fn row_height(&self) -> u32 {
+        self.cell + self.caption_height() + GAP
+    }
+}
+
+/// Place `thumbs` left to right, top to bottom, each centered in its cell with its name below.
+pub fn compose(thumbs: &[(RgbImage, String)], layout: &Layout) -> RgbImage {
+    let columns = layout.columns.clamp(1, thumbs.len().max(1) as u32);
+    let rows = (thumbs.len() as u32).div_ceil(columns).max(1);
+    let width = columns * (layout.cell + GAP) + GAP;
+    let height = rows * layout.row_height() + GAP;
+    let mut sheet = RgbImage::from_pixel(width, height, BACKGROUND);
+
+    let scale = layout.caption_scale();
+    for (i, (thumb, name)) in thumbs.iter().enumerate() {
+        let x = GAP + (i as u32 % columns) * (layout.cell + GAP);
+        let y = GAP + (i as u32 / columns) * layout.row_height();
+        let fitted = if thumb.width() > layout.cell || thumb.height() > layout.cell {
+            DynamicImage::ImageRgb8(thumb.clone()).thumbnail(layout.cell, layout.cell).to_rgb8()
+        } else {
+            thumb.clone()
+        };
+        let dx = (layout.cell - fitted.width()) / 2;
+        let dy = (layout.cell - fitted.height()) / 2;
+        imageops::replace(&mut sheet, &fitted, (x + dx) as i64, (y + dy) as i64);
+        if layout.captions {
+            let text_x = x + (layout.cell - text_width(name, scale).min(layout.cell)) / 2;
+            draw_text(&mut sheet, name, text_x, y + layout.cell + GAP / 2, scale, layout.cell);
+        }
+    }
+    sheet
+}
+
+/// Render `files` as a contact sheet to `output`: a PDF page for `.pdf`, a PNG for `.png`
+/// and a JPEG otherwise. Thumbnails come from the disk cache when it has them in a large
+/// enough size. Images that fail to decode are left out; their paths are returned.
+pub fn save(
+    files: &[PathBuf],
+    output: &Path,
+    layout: &Layout,
+    space: ColorSpace,
+    cache: Option<&DiskCache>,
+) -> Result<Vec<PathBuf>> {
+    if files.is_empty() {
+        return Err(anyhow!("A contact sheet needs at least one image"));
+    }
+    // Cached thumbnails only come in the viewer's sizes; bigger cells decode directly
+    let size = Thumbnailer::size_for(layout.cell as f32);
+    let cache = cache.filter(|_| layout.cell <= X_LARGE_THUMBNAIL_SIZE);
+    let results: Vec<Result<RgbImage>> = files
+        .par_iter()
+        .map(|file| match cache {
+            Some(cache) => crate::thumbnail::load_thumbnail(file, Some(cache), size).map(|t| t.to_rgb8()),
+            None => crate::sandbox::load(file).map(|l| l.image.thumbnail(layout.cell, layout.cell).to_rgb8()),
+        })
+        .collect();
+
+    let mut thumbs = Vec::new();
+    let mut failed = Vec::new();
+    for (file, result) in files.iter().zip(results) {
+        match result {
+            Ok(thumb) => {
+                let name = file.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                thumbs.push((thumb, name));
+            }
+            Err(e) => {
+                log::warn!("Failed to load {}: {:#}", file.display(), e);
+                failed.push(file.clone());
+            }
+        }
+    }
+    if thumbs.is_empty() {
+        return Err(anyhow!("None of the images could be loaded"));
+    }
+
+    let sheet = compose(&thumbs, layout);
+    let pdf = output.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("pdf"));
+    if pdf {
+        save_pdf(&sheet, output)?;
+    } else {
+        crate::export::save(&DynamicImage::ImageRgb8(sheet), output, space, &Metadata::default())?;
+    }
+    Ok(failed)
+}
+
+/// Write `sheet` as a one-page PDF holding it as a JPEG, with the page sized for `PDF_DPI`.
+fn save_pdf(sheet: &RgbImage, output: &Path) -> Result<()> {
+    let mut jpeg = Vec::new();
+    let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, PDF_JPEG_QUALITY);
+    sheet.write_with_encoder(encoder)?;
+    std::fs::write(output, pdf_page(&jpeg, sheet.width(), sheet.height()))?;
+    Ok(())
+}
+
+/// A minimal PDF: catalog, page tree, one page and the image drawn over all of it.
+fn pdf_page(jpeg: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let page_width = width as f32 * 72.0 / PDF_DPI;
+    let page_height = height as f32 * 72.0 / PDF_DPI;
+    let content = format!("q {:.2} 0 0 {:.2} 0 0 cm /Im0 Do Q", page_width, page_height);
+    let objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] \
+             /Resources << /XObject << /Im0 4 0 R >> >> /Contents 5 0 R >>",
+            page_width, page_height
+        )
+        .into_bytes(),
+        [
+            format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+                 /BitsPerComponent 8 /Filter /DCTDecode /Length {} >>\nstream\n",
+                width,
+                height,
+                jpeg.len()
+            )
+            .as_bytes(),
+            jpeg,
+            &b"\nendstream"[..],
+        ]
+        .concat(),
+        format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content).into_bytes(),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        let _ = writeln!(pdf, "{} 0 obj", i + 1);
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    let _ = writeln!(pdf, "xref\n0 {}\n0000000000 65535 f ", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(pdf, "{:010} 00000 n ", offset);
+    }
+    let _ = writeln!(pdf, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF", objects.len() + 1, xref);
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use image::Rgb;
+
+    #[test]
+    fn test_compose_grid() {
+        let layout = Layout { columns: 2, cell: 100, captions: false };
+        let wide = RgbImage::from_pixel(200, 100, Rgb([255, 0, 0]));
+        let tall = RgbImage::from_pixel(50, 100, Rgb([0, 0, 255]));
+        let thumbs = vec![(wide.clone(), "a".into()), (tall, "b".into()), (wide, "c".into())];
+        let sheet = compose(&thumbs, &layout);
+
+        assert_eq!(sheet.dimensions(), (GAP * 3 + 200, (100 + GAP) * 2 + GAP));
+        // The wide image is fitted to 100x50 and centered vertically
+        assert_eq!(*sheet.get_pixel(GAP + 50, GAP + 50), Rgb([255, 0, 0]));
+        assert_eq!(*sheet.get_pixel(GAP + 50, GAP + 10), BACKGROUND);
+        // The tall one is centered horizontally in the second cell
+        assert_eq!(*sheet.get_pixel(GAP * 2 + 100 + 50, GAP + 50), Rgb([0, 0, 255]));
+        assert_eq!(*sheet.get_pixel(GAP * 2 + 100 + 10, GAP + 50), BACKGROUND);
+        // The third wraps to the next row
+        assert_eq!(*sheet.get_pixel(GAP + 50, GAP * 2 + 100 + 50), Rgb([255, 0, 0]));
+
+        let captioned = compose(&thumbs, &Layout { captions: true, ..layout });
+        assert!(captioned.height() > sheet.height());
+    }
+
+    #[test]
+    fn test_save_png_and_pdf() {
+        let dir = scratch_dir("contact");
+        let mut files: Vec<PathBuf> = (0..3).map(|i| dir.join(format!("{}.png", i))).collect();
+        for file in &files {
+            RgbImage::from_pixel(300, 200, Rgb([20, 160, 60])).save(file).unwrap();
+        }
+        files.push(dir.join("missing.jpg"));
+        let layout = Layout { columns: 3, cell: 64, captions: true };
+
+        let png = dir.join("sheet.png");
+        let failed = save(&files, &png, &layout, ColorSpace::Srgb, None).unwrap();
+        assert_eq!(failed, vec![dir.join("missing.jpg")]);
+        assert_eq!(image::open(&png).unwrap().width(), GAP * 4 + 64 * 3);
+
+        let pdf = dir.join("sheet.pdf");
+        save(&files[..1], &pdf, &layout, ColorSpace::Srgb, None).unwrap();
+        let data = std::fs::read(&pdf).unwrap();
+        assert!(data.starts_with(b"%PDF-1.4"));
+        assert!(data.ends_with(b"%%EOF\n"));
+        // The cross-reference table points at each object
+        let tail = String::from_utf8_lossy(&data[data.len() - 32..]);
+        let xref: usize = tail.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        let table = String::from_utf8_lossy(&data[xref..]);
+        assert!(table.starts_with("xref"));
+        let first: usize = table.lines().nth(3).unwrap()[..10].parse().unwrap();
+        assert!(data[first..].starts_with(b"1 0 obj"));
+
+        assert!(save(&files[3..], &png, &layout, ColorSpace::Srgb, None).is_err());
+    }
+}
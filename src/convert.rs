@@ -0,0 +1,202 @@
+//! Batch conversion: decoding many files through the loader on the background
+//! pool and saving them in another format, scaled down to a longest side if
+//! asked, e.g. for web copies of a whole shoot.
+
+use crate::export::{ColorSpace, Metadata};
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Folder next to the sources the copies go to unless another is given
+pub const OUTPUT_DIR: &str = "converted";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[value(alias = "jpg")]
+    Jpeg,
+    Png,
+    /// Lossless WebP
+    Webp,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpg",
+            Format::Png => "png",
+            Format::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub format: Format,
+    /// Longest side in pixels; larger images are scaled down to it
+    pub max_size: Option<u32>,
+    pub color_space: ColorSpace,
+    /// Leave out the source's EXIF and XMP (WebP copies never carry them)
+    pub strip_metadata: bool,
+    pub overwrite: bool,
+}
+
+/// The format and optional longest side typed into the viewer's prompt, like "webp 2048".
+pub fn parse_spec(text: &str) -> Option<(Format, Option<u32>)> {
+    let mut words = text.split_whitespace();
+    let format = <Format as clap::ValueEnum>::from_str(words.next()?, true).ok()?;
+    let max_size = match words.next() {
+        Some(size) => Some(size.trim_end_matches("px").parse().ok().filter(|&s| s > 0)?),
+        None => None,
+    };
+    words.next().is_none().then_some((format, max_size))
+}
+
+/// Where the copies of `files` go: `<name>.<ext>` in `dir`, or in a `converted` folder next to
+/// each source. Sources sharing a name (`IMG_1.nef`, `IMG_1.jpg`) get their extension appended.
+pub fn target_paths(files: &[PathBuf], dir: Option<&Path>, format: Format) -> Vec<PathBuf> {
+    let folder = |file: &Path| match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => file.parent().unwrap_or(Path::new("")).join(OUTPUT_DIR),
+    };
+    let plain = |file: &Path| {
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        folder(file).join(format!("{}.{}", stem, format.extension()))
+    };
+    let targets: Vec<PathBuf> = files.iter().map(|f| plain(f)).collect();
+    files
+        .iter()
+        .zip(&targets)
+        .map(|(file, target)| {
+            if targets.iter().filter(|t| *t == target).count() == 1 {
+                return target.clone();
+            }
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = file.extension().unwrap_or_default().to_string_lossy();
+            folder(file).join(format!("{}_{}.{}", stem, ext, format.extension()))
+        })
+        .collect()
+}
+
+/// Scale `image` down so neither side exceeds `max_size`.
+pub fn fit(image: DynamicImage, max_size: Option<u32>) -> DynamicImage {
+    match max_size {
+        Some(max) if image.width() > max || image.height() > max => {
+            image.resize(max, max, image::imageops::FilterType::Lanczos3)
+        }
+        _ => image,
+    }
+}
+
+/// Decode `source` (upright, RAW files developed) and save it to `target`.
+pub fn convert(source: &Path, target: &Path, options: &Options) -> Result<()> {
+    if target.exists() && !options.overwrite {
+        return Err(anyhow!("{} already exists", target.display()));
+    }
+    let loaded = crate::sandbox::load(source)?;
+    let image = fit(loaded.image, options.max_size);
+    if let Some(dir) = target.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    match options.format {
+        Format::Webp => {
+            let file = std::io::BufWriter::new(std::fs::File::create(target)?);
+            image.to_rgba8().write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(file))?;
+        }
+        Format::Jpeg | Format::Png => {
+            let mut metadata = Metadata::read(source);
+            if options.strip_metadata {
+                metadata = metadata.colors_only();
+            }
+            crate::export::save(&image, target, options.color_space, &metadata)?;
+        }
+    }
+    Ok(())
+}
+
+/// How far a batch has got, shared with the threads running it.
+#[derive(Debug, Default)]
+pub struct Progress {
+    pub total: usize,
+    done: AtomicUsize,
+    /// Sources that failed, with why
+    errors: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl Progress {
+    /// Files finished so far, failed ones included.
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::SeqCst)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.done() == self.total
+    }
+
+    pub fn errors(&self) -> Vec<(PathBuf, String)> {
+        self.errors.lock().map(|e| e.clone()).unwrap_or_default()
+    }
+}
+
+/// Convert `files` to `targets` in parallel on the background pool, without waiting.
+pub fn start(files: Vec<PathBuf>, targets: Vec<PathBuf>, options: Options) -> Arc<Progress> {
+    let progress = Arc::new(Progress { total: files.len(), ..Default::default() });
+    let shared = progress.clone();
+    std::thread::spawn(move || {
+        files.par_iter().zip(&targets).for_each(|(file, target)| {
+            crate::priority::yield_to_interactive();
+            if let Err(e) = convert(file, target, &options) {
+                if let Ok(mut errors) = shared.errors.lock() {
+                    errors.push((file.clone(), format!("{:#}", e)));
+                }
+            }
+            shared.done.fetch_add(1, Ordering::SeqCst);
+        });
+    });
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_convert_batch() {
+        assert_eq!(parse_spec("webp 2048"), Some((Format::Webp, Some(2048))));
+        assert_eq!(parse_spec(" JPG "), Some((Format::Jpeg, None)));
+        assert_eq!(parse_spec("png 0"), None);
+        assert_eq!(parse_spec("gif"), None);
+
+        let dir = scratch_dir("convert");
+        let files = vec![dir.join("a.png"), dir.join("b.jpg"), dir.join("b.png")];
+        for file in &files {
+            image::RgbImage::from_pixel(400, 100, image::Rgb([10, 120, 200])).save(file).unwrap();
+        }
+        let targets = target_paths(&files, None, Format::Webp);
+        let out = dir.join(OUTPUT_DIR);
+        assert_eq!(targets, vec![out.join("a.webp"), out.join("b_jpg.webp"), out.join("b_png.webp")]);
+
+        let options = Options {
+            format: Format::Webp,
+            max_size: Some(200),
+            color_space: ColorSpace::Srgb,
+            strip_metadata: false,
+            overwrite: false,
+        };
+        let progress = start(files.clone(), targets.clone(), options.clone());
+        while !progress.finished() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(progress.errors(), vec![]);
+        let copy = image::open(&targets[0]).unwrap();
+        assert_eq!((copy.width(), copy.height()), (200, 50));
+        assert_eq!(copy.to_rgb8().get_pixel(100, 25).0, [10, 120, 200]);
+
+        // Copies made before are kept unless replacing them is asked for
+        assert!(convert(&files[0], &targets[0], &options).is_err());
+        assert!(convert(&files[0], &targets[0], &Options { overwrite: true, ..options }).is_ok());
+    }
+}
@@ -0,0 +1,185 @@
+//! Panics written down. The hook saves a report with the backtrace, the renderer, the
+//! image last opened and the end of the log next to the config file, and the next
+//! launch offers to open it: release builds on Windows have no console, so the window
+//! would otherwise just vanish.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lines from the end of the log file included in a report
+const LOG_LINES: usize = 50;
+
+/// What was going on, for the report.
+#[derive(Debug, Default)]
+struct Context {
+    renderer: Option<String>,
+    file: Option<PathBuf>,
+}
+
+static CONTEXT: Mutex<Context> = Mutex::new(Context { renderer: None, file: None });
+
+/// The folder reports go to, next to `config.toml`.
+fn dir() -> Option<PathBuf> {
+    Some(crate::config::Config::path()?.parent()?.join("crashes"))
+}
+
+/// Names the report the next launch hasn't offered yet.
+fn pending_marker(dir: &Path) -> PathBuf {
+    dir.join("pending")
+}
+
+/// Write a report whenever the viewer panics, on top of the usual message on stderr.
+pub fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let message = match (info.payload().downcast_ref::<&str>(), info.payload().downcast_ref::<String>()) {
+            (Some(text), _) => text.to_string(),
+            (_, Some(text)) => text.clone(),
+            _ => "Unknown panic".to_string(),
+        };
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line()));
+        let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+        let log = crate::logging::file_path().map(|path| log_tail(&path, LOG_LINES)).unwrap_or_default();
+        // A panic while the context is locked mustn't deadlock the hook
+        let text = match CONTEXT.try_lock() {
+            Ok(context) => report(&message, location.as_deref(), &backtrace, &context, &log),
+            Err(_) => report(&message, location.as_deref(), &backtrace, &Context::default(), &log),
+        };
+        if let Some(dir) = dir() {
+            match save(&dir, &text) {
+                Ok(path) => eprintln!("Crash report saved to {}", path.display()),
+                Err(e) => eprintln!("Failed to save the crash report: {}", e),
+            }
+        }
+    }));
+}
+
+/// Note the adapter in use, see `gpu::describe`.
+pub fn set_renderer(renderer: String) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        context.renderer = Some(renderer);
+    }
+}
+
+/// Note the image being opened.
+pub fn set_file(path: &Path) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        context.file = Some(path.to_path_buf());
+    }
+}
+
+fn report(message: &str, location: Option<&str>, backtrace: &str, context: &Context, log: &str) -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (date, time) = crate::rename::format_unix_time(secs);
+    let mut text = format!(
+        "Momemtum {} crashed at {} {}:{}:{} UTC\n\n",
+        env!("CARGO_PKG_VERSION"),
+        date,
+        &time[0..2],
+        &time[2..4],
+        &time[4..6]
+    );
+    text.push_str(&format!("Panic: {}\n", message));
+    text.push_str(&format!("At: {}\n", location.unwrap_or("unknown")));
+    text.push_str(&format!("Platform: {} {}\n", std::env::consts::OS, std::env::consts::ARCH));
+    text.push_str(&format!("Renderer: {}\n", context.renderer.as_deref().unwrap_or("not set up")));
+    let file = context.file.as_ref().map_or_else(|| "none".to_string(), |f| f.display().to_string());
+    text.push_str(&format!("Last opened: {}\n\nBacktrace:\n{}\n", file, backtrace.trim_end()));
+    if !log.is_empty() {
+        text.push_str(&format!("\nEnd of the log:\n{}", log));
+    }
+    text
+}
+
+/// Save `text` as a new report in `dir` and mark it for the next launch to offer.
+fn save(dir: &Path, text: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let path = dir.join(format!("crash-{}-{}.txt", secs, std::process::id()));
+    std::fs::write(&path, text)?;
+    std::fs::write(pending_marker(dir), path.to_string_lossy().as_bytes())?;
+    Ok(path)
+}
+
+/// The report of a crash since the last launch, once.
+fn take_pending(dir: &Path) -> Option<PathBuf> {
+    let marker = pending_marker(dir);
+    let path = PathBuf::from(std::fs::read_to_string(&marker).ok()?.trim());
+    let _ = std::fs::remove_file(&marker);
+    path.exists().then_some(path)
+}
+
+/// After a crash, ask whether to open its report.
+pub fn offer_report() {
+    let Some(path) = dir().and_then(|dir| take_pending(&dir)) else {
+        return;
+    };
+    let answer = rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Warning)
+        .set_title("Momemtum crashed")
+        .set_description(format!(
+            "Momemtum closed unexpectedly last time. A report was saved to\n{}\n\nOpen it? Attaching it to a bug report helps fix the problem.",
+            path.display()
+        ))
+        .set_buttons(rfd::MessageButtons::YesNo)
+        .show();
+    if answer == rfd::MessageDialogResult::Yes {
+        if let Err(e) = crate::remote::open_in_browser(&path.to_string_lossy()) {
+            log::warn!("Failed to open the crash report: {:#}", e);
+        }
+    }
+}
+
+/// The last `lines` lines of the file at `path`, reading no more than its end.
+fn log_tail(path: &Path, lines: usize) -> String {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return String::new();
+    };
+    let len = file.metadata().map_or(0, |m| m.len());
+    let start = len.saturating_sub(lines as u64 * 200);
+    let mut bytes = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut bytes).is_err() {
+        return String::new();
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    let mut all: Vec<&str> = text.lines().collect();
+    // A line cut off by where reading started is left out
+    if start > 0 && !all.is_empty() {
+        all.remove(0);
+    }
+    all[all.len().saturating_sub(lines)..].iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_report_and_pending() {
+        let dir = scratch_dir("crash");
+        let log = dir.join("momemtum.log");
+        std::fs::create_dir_all(&dir).unwrap();
+        let lines: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&log, lines).unwrap();
+        assert_eq!(log_tail(&log, 2), "line 98\nline 99\n");
+        assert_eq!(log_tail(&dir.join("missing.log"), 2), "");
+
+        let context = Context { renderer: Some("Vulkan: Test GPU".to_string()), file: Some(PathBuf::from("/photos/a.nef")) };
+        let text = report("index out of bounds", Some("src/raw.rs:10"), "0: main", &context, &log_tail(&log, 1));
+        assert!(text.contains("Panic: index out of bounds\nAt: src/raw.rs:10\n"));
+        assert!(text.contains("Renderer: Vulkan: Test GPU\n"));
+        assert!(text.contains("Last opened: /photos/a.nef\n"));
+        assert!(text.ends_with("End of the log:\nline 99\n"));
+
+        // Offered once
+        assert_eq!(take_pending(&dir), None);
+        let path = save(&dir, &text).unwrap();
+        assert_eq!(take_pending(&dir), Some(path.clone()));
+        assert_eq!(take_pending(&dir), None);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+    }
+}
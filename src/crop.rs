@@ -0,0 +1,220 @@
+//! Crop mode: a selection on the image, optionally held to an aspect ratio,
+//! exported as a new file.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::export::{ColorSpace, Metadata};
+
+/// Crop mode state. Coordinates are image texture coordinates (0..1).
+pub struct CropTool {
+    pub active: bool,
+    /// x0, y0, x1, y1
+    pub rect: Option<[f32; 4]>,
+    /// Index into `framing::PRESETS` the selection keeps to; free when `None`
+    pub preset: Option<usize>,
+    drag: Option<Drag>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Drag {
+    /// Spanning a selection from a fixed corner
+    Span((f32, f32)),
+    /// Moving the selection, held at an offset from its top left corner
+    Move((f32, f32)),
+}
+
+impl Default for CropTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CropTool {
+    pub fn new() -> Self {
+        Self { active: false, rect: None, preset: None, drag: None }
+    }
+
+    /// Start dragging at `uv`: a corner within `grab` resizes from the opposite
+    /// one, inside the selection moves it, elsewhere starts a new one.
+    pub fn begin_drag(&mut self, uv: (f32, f32), grab: (f32, f32)) {
+        let near = |x: f32, y: f32| (uv.0 - x).abs() <= grab.0 && (uv.1 - y).abs() <= grab.1;
+        self.drag = Some(match self.rect {
+            Some([x0, y0, x1, y1]) => {
+                let corners = [((x0, y0), (x1, y1)), ((x1, y0), (x0, y1)), ((x0, y1), (x1, y0)), ((x1, y1), (x0, y0))];
+                match corners.iter().find(|(corner, _)| near(corner.0, corner.1)) {
+                    Some((_, opposite)) => Drag::Span(*opposite),
+                    None if (x0..=x1).contains(&uv.0) && (y0..=y1).contains(&uv.1) => Drag::Move((uv.0 - x0, uv.1 - y0)),
+                    None => Drag::Span(clamp(uv)),
+                }
+            }
+            None => Drag::Span(clamp(uv)),
+        });
+    }
+
+    /// Follow the cursor; `image_aspect` is the image's width over height.
+    pub fn update_drag(&mut self, uv: (f32, f32), image_aspect: f32) {
+        let ratio = self.uv_ratio(image_aspect);
+        match self.drag {
+            Some(Drag::Span(anchor)) => self.rect = Some(span(anchor, clamp(uv), ratio)),
+            Some(Drag::Move((dx, dy))) => {
+                if let Some([x0, y0, x1, y1]) = self.rect {
+                    let (w, h) = (x1 - x0, y1 - y0);
+                    let x = (uv.0 - dx).clamp(0.0, 1.0 - w);
+                    let y = (uv.1 - dy).clamp(0.0, 1.0 - h);
+                    self.rect = Some([x, y, x + w, y + h]);
+                }
+            }
+            None => {}
+        }
+    }
+
+    pub fn end_drag(&mut self) {
+        if let Some([x0, y0, x1, y1]) = self.rect {
+            if x1 - x0 < 0.002 || y1 - y0 < 0.002 {
+                self.rect = None;
+            }
+        }
+        self.drag = None;
+    }
+
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Next aspect-ratio preset after free, fitting the selection to it around its center.
+    pub fn cycle_preset(&mut self, image_aspect: f32) {
+        self.preset = match self.preset {
+            None => Some(0),
+            Some(i) if i + 1 < crate::framing::PRESETS.len() => Some(i + 1),
+            Some(_) => None,
+        };
+        if let (Some([x0, y0, x1, y1]), Some(ratio)) = (self.rect, self.uv_ratio(image_aspect)) {
+            let (cx, cy) = ((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+            let (mut w, mut h) = (x1 - x0, y1 - y0);
+            if w / h > ratio {
+                w = h * ratio;
+            } else {
+                h = w / ratio;
+            }
+            self.rect = Some([cx - w / 2.0, cy - h / 2.0, cx + w / 2.0, cy + h / 2.0]);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rect = None;
+        self.drag = None;
+    }
+
+    /// The preset's ratio in texture coordinates, where the image is 1x1.
+    fn uv_ratio(&self, image_aspect: f32) -> Option<f32> {
+        self.preset.map(|i| crate::framing::PRESETS[i].ratio / image_aspect)
+    }
+}
+
+fn clamp(uv: (f32, f32)) -> (f32, f32) {
+    (uv.0.clamp(0.0, 1.0), uv.1.clamp(0.0, 1.0))
+}
+
+/// The rectangle from `anchor` towards `to`, shrunk to `ratio` (width over height) if given.
+fn span(anchor: (f32, f32), to: (f32, f32), ratio: Option<f32>) -> [f32; 4] {
+    let (mut dx, mut dy) = (to.0 - anchor.0, to.1 - anchor.1);
+    if let Some(ratio) = ratio {
+        if dx.abs() > dy.abs() * ratio {
+            dx = dx.signum() * dy.abs() * ratio;
+        } else {
+            dy = dy.signum() * dx.abs() / ratio;
+        }
+    }
+    let (x1, y1) = (anchor.0 + dx, anchor.1 + dy);
+    [anchor.0.min(x1), anchor.1.min(y1), anchor.0.max(x1), anchor.1.max(y1)]
+}
+
+/// The selection in pixels of a `size` image: x, y, width, height.
+pub fn pixel_rect(rect: [f32; 4], size: (u32, u32)) -> (u32, u32, u32, u32) {
+    let (w, h) = (size.0 as f32, size.1 as f32);
+    let x0 = ((rect[0] * w).round() as u32).min(size.0.saturating_sub(1));
+    let y0 = ((rect[1] * h).round() as u32).min(size.1.saturating_sub(1));
+    let x1 = ((rect[2] * w).round() as u32).clamp(x0 + 1, size.0.max(1));
+    let y1 = ((rect[3] * h).round() as u32).clamp(y0 + 1, size.1.max(1));
+    (x0, y0, x1 - x0, y1 - y0)
+}
+
+/// `photo.jpg` -> `photo_crop.jpg`; RAW sources are exported as JPEG.
+pub fn export_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let ext = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("png") => "png",
+        _ => "jpg",
+    };
+    path.with_file_name(format!("{}_crop.{}", stem, ext))
+}
+
+/// Reload `source` at full resolution and save the selected region, with the
+/// source's metadata unless `strip_metadata`.
+pub fn export(source: &Path, rect: [f32; 4], space: ColorSpace, strip_metadata: bool) -> Result<PathBuf> {
+    let loaded = crate::sandbox::load(source)?;
+    let (x, y, w, h) = pixel_rect(rect, (loaded.image.width(), loaded.image.height()));
+    let cropped = loaded.image.crop_imm(x, y, w, h);
+    let target = export_path(source);
+    let mut metadata = Metadata::read(source);
+    if strip_metadata {
+        metadata = metadata.colors_only();
+    }
+    crate::export::save(&cropped, &target, space, &metadata)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drag_modes() {
+        let mut crop = CropTool::new();
+        let grab = (0.01, 0.01);
+        let px = |crop: &CropTool| crop.rect.map(|r| pixel_rect(r, (1000, 1000)));
+        crop.begin_drag((0.6, 0.7), grab);
+        crop.update_drag((0.2, 1.5), 1.5);
+        crop.end_drag();
+        assert_eq!(px(&crop), Some((200, 700, 400, 300)));
+
+        // Inside moves, stopping at the image edge
+        crop.begin_drag((0.4, 0.8), grab);
+        crop.update_drag((0.5, 0.5), 1.5);
+        assert_eq!(px(&crop), Some((300, 400, 400, 300)));
+        crop.update_drag((0.0, 0.5), 1.5);
+        assert_eq!(crop.rect.unwrap()[0], 0.0);
+        crop.end_drag();
+
+        // A corner resizes from the opposite one
+        crop.begin_drag((0.4, 0.7), grab);
+        crop.update_drag((0.8, 0.9), 1.5);
+        assert_eq!(px(&crop), Some((0, 400, 800, 500)));
+        crop.end_drag();
+
+        // A click without a drag drops the selection
+        crop.begin_drag((0.95, 0.05), grab);
+        crop.update_drag((0.95, 0.05), 1.5);
+        crop.end_drag();
+        assert_eq!(crop.rect, None);
+    }
+
+    #[test]
+    fn test_presets_hold_the_ratio() {
+        let mut crop = CropTool::new();
+        crop.rect = Some([0.0, 0.0, 1.0, 1.0]);
+        // Square on a 3:2 image
+        crop.cycle_preset(1.5);
+        let (_, _, w, h) = pixel_rect(crop.rect.unwrap(), (3000, 2000));
+        assert_eq!((w, h), (2000, 2000));
+
+        crop.clear();
+        crop.begin_drag((0.0, 0.0), (0.01, 0.01));
+        crop.update_drag((1.0, 0.5), 1.5);
+        let (_, _, w, h) = pixel_rect(crop.rect.unwrap(), (3000, 2000));
+        assert_eq!((w, h), (1000, 1000));
+
+        assert_eq!(export_path(Path::new("/a/b.NEF")), PathBuf::from("/a/b_crop.jpg"));
+    }
+}
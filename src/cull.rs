@@ -0,0 +1,117 @@
+//! Sending images to target folders with the number keys while culling a shoot.
+
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+use crate::config::TransferMode;
+use crate::history::Change;
+
+/// What sending an image did, as (from, to) pairs in the order they were done.
+#[derive(Debug, Default, PartialEq)]
+pub struct Sent {
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    pub copied: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Sent {
+    /// The change to undo it by.
+    pub fn change(self) -> Change {
+        match (self.moved.is_empty(), self.copied.is_empty()) {
+            (_, true) => Change::Moved(self.moved),
+            (true, false) => Change::Copied(self.copied),
+            (false, false) => Change::Batch(vec![Change::Moved(self.moved), Change::Copied(self.copied)]),
+        }
+    }
+}
+
+/// Copy or move `path` and its XMP sidecar into `folder`, creating the folder if
+/// needed. Nothing is overwritten. A sidecar the image shares with others, such as
+/// `a.xmp` of both `a.jpg` and `a.cr2`, is copied rather than moved so they keep it.
+/// When a file can't be sent, those sent before it are taken back.
+pub fn send(path: &Path, folder: &Path, mode: TransferMode) -> Result<Sent> {
+    let name = path.file_name().ok_or_else(|| anyhow!("{:?} has no file name", path))?;
+    std::fs::create_dir_all(folder)?;
+    let mut files = vec![(path.to_path_buf(), folder.join(name), mode)];
+    let sidecar = crate::xmp::sidecar_path(path);
+    if let (true, Some(sidecar_name)) = (sidecar.exists(), sidecar.file_name()) {
+        let shared = crate::xmp::sidecar_images(&sidecar).iter().any(|image| image != path);
+        files.push((sidecar.clone(), folder.join(sidecar_name), if shared { TransferMode::Copy } else { mode }));
+    }
+    if let Some((_, to, _)) = files.iter().find(|(_, to, _)| to.exists()) {
+        return Err(anyhow!("{:?} already exists", to));
+    }
+
+    let mut sent = Sent::default();
+    for (from, to, mode) in files {
+        let result = match mode {
+            TransferMode::Copy => std::fs::copy(&from, &to).map(|_| ()).map_err(anyhow::Error::from),
+            TransferMode::Move => crate::journal::move_file(&from, &to),
+        };
+        if let Err(e) = result {
+            return Err(take_back(&sent, e.context(format!("Failed to send {}", from.display()))));
+        }
+        match mode {
+            TransferMode::Copy => sent.copied.push((from, to)),
+            TransferMode::Move => sent.moved.push((from, to)),
+        }
+    }
+    Ok(sent)
+}
+
+/// Undo what `sent` did after sending the next file failed with `e`.
+fn take_back(sent: &Sent, e: anyhow::Error) -> anyhow::Error {
+    let copies = sent.copied.iter().map(|(_, to)| std::fs::remove_file(to).map_err(anyhow::Error::from).map_err(|back| (to, back)));
+    let moves = sent.moved.iter().rev().map(|(from, to)| crate::journal::move_file(to, from).map_err(|back| (to, back)));
+    for result in copies.chain(moves) {
+        if let Err((to, back)) = result {
+            return e.context(format!("{} was sent and couldn't be taken back: {:#}", to.display(), back));
+        }
+    }
+    e.context("Nothing was sent")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_send() {
+        let dir = scratch_dir("cull");
+        let shoot = dir.join("shoot");
+        std::fs::create_dir_all(&shoot).unwrap();
+        let image = shoot.join("a.jpg");
+        std::fs::write(&image, b"jpeg").unwrap();
+        std::fs::write(shoot.join("a.xmp"), b"xmp").unwrap();
+
+        let maybe = dir.join("maybe");
+        assert_eq!(send(&image, &maybe, TransferMode::Copy).unwrap().copied.len(), 2);
+        assert!(image.exists() && maybe.join("a.jpg").exists() && maybe.join("a.xmp").exists());
+        assert!(send(&image, &maybe, TransferMode::Copy).is_err());
+
+        let keep = dir.join("keep");
+        let moved = send(&image, &keep, TransferMode::Move).unwrap();
+        assert_eq!(moved.moved[0], (image.clone(), keep.join("a.jpg")));
+        assert!(!image.exists() && !shoot.join("a.xmp").exists());
+        assert_eq!(std::fs::read(keep.join("a.jpg")).unwrap(), b"jpeg");
+
+        // The RAW next to a JPEG keeps the sidecar they share
+        let (jpeg, raw) = (shoot.join("b.jpg"), shoot.join("b.cr2"));
+        std::fs::write(&jpeg, b"jpeg").unwrap();
+        std::fs::write(&raw, b"raw").unwrap();
+        std::fs::write(shoot.join("b.xmp"), b"xmp").unwrap();
+        let sent = send(&jpeg, &keep, TransferMode::Move).unwrap();
+        assert_eq!(sent.moved, [(jpeg.clone(), keep.join("b.jpg"))]);
+        assert_eq!(sent.copied, [(shoot.join("b.xmp"), keep.join("b.xmp"))]);
+        assert!(shoot.join("b.xmp").exists() && keep.join("b.xmp").exists());
+        assert!(matches!(sent.change(), Change::Batch(_)));
+
+        // A sidecar that can't be sent takes the image back with it
+        std::fs::write(shoot.join("c.jpg"), b"jpeg").unwrap();
+        std::fs::write(shoot.join("c.cr2"), b"raw").unwrap();
+        std::fs::create_dir(shoot.join("c.xmp")).unwrap();
+        let error = send(&shoot.join("c.jpg"), &keep, TransferMode::Move).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Nothing was sent"), "{:#}", error);
+        assert!(shoot.join("c.jpg").exists() && !keep.join("c.jpg").exists());
+    }
+}
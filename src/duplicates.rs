@@ -0,0 +1,219 @@
+//! Duplicate and near-duplicate detection: a difference hash (dHash) of every image in
+//! the folder, computed from the thumbnails on the background pool, and the groups of
+//! images whose hashes are within a few bits of each other, for bursts and copied files.
+
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Hashes differing in at most this many of their 64 bits count as the same picture.
+pub const DEFAULT_THRESHOLD: u32 = 6;
+
+/// 64-bit difference hash: the image shrunk to 9x8 gray pixels, one bit per pair of
+/// horizontal neighbours telling whether brightness falls. It survives scaling,
+/// recompression and small exposure changes.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, image::imageops::FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+    hash
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Images whose hashes are within `threshold` bits of another in the group, linked
+/// transitively. Only groups of two or more are returned, in the order of `hashes`.
+pub fn group(hashes: &[(PathBuf, u64)], threshold: u32) -> Vec<Vec<PathBuf>> {
+    // Union-find over the images
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if distance(hashes[i].1, hashes[j].1) <= threshold {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let mut groups: Vec<(usize, Vec<PathBuf>)> = Vec::new();
+    for (i, (path, _)) in hashes.iter().enumerate() {
+        let r = root(&mut parent, i);
+        match groups.iter_mut().find(|(g, _)| *g == r) {
+            Some((_, members)) => members.push(path.clone()),
+            None => groups.push((r, vec![path.clone()])),
+        }
+    }
+    groups.into_iter().map(|(_, members)| members).filter(|m| m.len() > 1).collect()
+}
+
+/// A folder being hashed in the background, shared with the threads doing it.
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub total: usize,
+    done: AtomicUsize,
+    groups: Mutex<Option<Vec<Vec<PathBuf>>>>,
+}
+
+impl Scan {
+    /// Images hashed so far, failed ones included.
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::SeqCst)
+    }
+
+    /// The groups found, once every image has been hashed.
+    pub fn take_groups(&self) -> Option<Vec<Vec<PathBuf>>> {
+        self.groups.lock().ok()?.take()
+    }
+}
+
+/// Hash `files` in parallel on the background pool without waiting. Thumbnails come
+/// from `cache_dir` when they are there, so folders seen in the grid hash quickly.
+pub fn start(files: Vec<PathBuf>, cache_dir: Option<PathBuf>, threshold: u32) -> Arc<Scan> {
+    let scan = Arc::new(Scan { total: files.len(), ..Default::default() });
+    let shared = scan.clone();
+    std::thread::spawn(move || {
+        let cache = cache_dir.map(|dir| crate::thumbnail::DiskCache::new(dir, u64::MAX));
+        let hashes: Vec<(PathBuf, u64)> = files
+            .par_iter()
+            .filter_map(|file| {
+                crate::priority::yield_to_interactive();
+                let thumb = crate::thumbnail::load_thumbnail(file, cache.as_ref(), crate::thumbnail::THUMBNAIL_SIZE);
+                shared.done.fetch_add(1, Ordering::SeqCst);
+                match thumb {
+                    Ok(thumb) => Some((file.clone(), dhash(&thumb))),
+                    Err(e) => {
+                        log::warn!("Failed to hash {:?}: {:?}", file, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        if let Ok(mut groups) = shared.groups.lock() {
+            *groups = Some(group(&hashes, threshold));
+        }
+    });
+    scan
+}
+
+/// The groups found, and the one being browsed.
+#[derive(Debug)]
+pub struct Duplicates {
+    groups: Vec<Vec<PathBuf>>,
+    current: usize,
+}
+
+impl Duplicates {
+    /// `None` when there is nothing to review.
+    pub fn new(groups: Vec<Vec<PathBuf>>) -> Option<Self> {
+        (!groups.is_empty()).then_some(Self { groups, current: 0 })
+    }
+
+    pub fn group(&self) -> &[PathBuf] {
+        &self.groups[self.current]
+    }
+
+    /// Move to the next or previous group, wrapping around.
+    pub fn step(&mut self, forward: bool) -> &[PathBuf] {
+        let len = self.groups.len();
+        self.current = if forward { (self.current + 1) % len } else { (self.current + len - 1) % len };
+        self.group()
+    }
+
+    /// Forget files that were deleted or moved away. Groups left with a single image are
+    /// dropped; returns false once no group is left.
+    pub fn forget(&mut self, gone: &[PathBuf]) -> bool {
+        for members in self.groups.iter_mut() {
+            members.retain(|p| !gone.contains(p));
+        }
+        let before = self.groups[..self.current].iter().filter(|m| m.len() < 2).count();
+        self.groups.retain(|m| m.len() > 1);
+        self.current = self.current.saturating_sub(before).min(self.groups.len().saturating_sub(1));
+        !self.groups.is_empty()
+    }
+
+    /// The other images of the current group, to delete when keeping `keep`.
+    pub fn others(&self, keep: &Path) -> Vec<PathBuf> {
+        self.group().iter().filter(|p| *p != keep).cloned().collect()
+    }
+
+    /// "Duplicates 2/5 (3 images)"
+    pub fn label(&self) -> String {
+        format!("Duplicates {}/{} ({} images)", self.current + 1, self.groups.len(), self.group().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn waves(width: u32, height: u32, flip: bool) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let phase = x as f32 / width as f32 * 12.0 + y as f32 / height as f32 * 5.0;
+            let v = (128.0 + 100.0 * phase.sin()) as u8;
+            Rgb(if flip { [255 - v; 3] } else { [v; 3] })
+        }))
+    }
+
+    #[test]
+    fn test_dhash_matches_resized_copies() {
+        let original = waves(640, 480, false);
+        let smaller = original.resize(160, 120, image::imageops::FilterType::Lanczos3);
+        let inverted = waves(640, 480, true);
+        assert!(distance(dhash(&original), dhash(&smaller)) <= DEFAULT_THRESHOLD);
+        assert!(distance(dhash(&original), dhash(&inverted)) > DEFAULT_THRESHOLD);
+        assert_eq!(distance(0b1011, 0b0001), 2);
+    }
+
+    #[test]
+    fn test_group_links_near_hashes() {
+        let p = |name: &str| PathBuf::from(name);
+        let hashes = vec![
+            (p("a.jpg"), 0x0000_0000_0000_0000),
+            (p("b.jpg"), 0xFFFF_FFFF_FFFF_FFFF),
+            (p("c.jpg"), 0x0000_0000_0000_000F),
+            (p("d.jpg"), 0x0000_0000_0000_00FF),
+            (p("e.jpg"), 0x0F0F_0F0F_0000_0000),
+        ];
+        // d is 8 bits from a but 4 from c, so it joins through c
+        assert_eq!(group(&hashes, 4), vec![vec![p("a.jpg"), p("c.jpg"), p("d.jpg")]]);
+        assert!(group(&hashes, 0).is_empty());
+    }
+
+    #[test]
+    fn test_forget_drops_resolved_groups() {
+        let p = |name: &str| PathBuf::from(name);
+        let mut duplicates = Duplicates::new(vec![
+            vec![p("a"), p("b")],
+            vec![p("c"), p("d"), p("e")],
+            vec![p("f"), p("g")],
+        ])
+        .unwrap();
+        duplicates.step(true);
+        assert_eq!(duplicates.label(), "Duplicates 2/3 (3 images)");
+        assert_eq!(duplicates.others(&p("d")), vec![p("c"), p("e")]);
+
+        // Resolving an earlier group keeps the current one in view
+        assert!(duplicates.forget(&[p("a")]));
+        assert_eq!(duplicates.group(), &[p("c"), p("d"), p("e")]);
+        assert!(duplicates.forget(&[p("c"), p("e")]));
+        assert_eq!(duplicates.group(), &[p("f"), p("g")]);
+        assert!(!duplicates.forget(&[p("g")]));
+        assert!(Duplicates::new(Vec::new()).is_none());
+    }
+}
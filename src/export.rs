@@ -0,0 +1,811 @@
+//! Writing edited copies (crops, redactions, saved views, montages): the file
+//! format follows the extension, and pixels are converted from the source's color
+//! profile, or from sRGB when it has none, to the chosen color space with its ICC
+//! profile embedded, so other applications show the colors as intended. The
+//! source's EXIF and XMP come along unless the copy is meant to be stripped.
+
+use anyhow::Result;
+use exif::{Context, In, Tag};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const JPEG_QUALITY: u8 = 92;
+
+/// Target color space of exported files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+}
+
+type Matrix = [[f64; 3]; 3];
+
+const D65: [f64; 2] = [0.3127, 0.3290];
+/// The ICC profile connection space white
+const D50_XYZ: [f64; 3] = [0.9642, 1.0, 0.8249];
+const BRADFORD: Matrix = [[0.8951, 0.2664, -0.1614], [-0.7502, 1.7135, 0.0367], [0.0389, -0.0685, 1.0296]];
+
+impl ColorSpace {
+    pub fn label(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "sRGB",
+            ColorSpace::DisplayP3 => "Display P3",
+            ColorSpace::AdobeRgb => "Adobe RGB",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            ColorSpace::Srgb => ColorSpace::DisplayP3,
+            ColorSpace::DisplayP3 => ColorSpace::AdobeRgb,
+            ColorSpace::AdobeRgb => ColorSpace::Srgb,
+        }
+    }
+
+    /// Red, green and blue chromaticities; all three use a D65 white.
+    fn primaries(self) -> [[f64; 2]; 3] {
+        match self {
+            ColorSpace::Srgb => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]],
+            ColorSpace::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+            ColorSpace::AdobeRgb => [[0.64, 0.33], [0.21, 0.71], [0.15, 0.06]],
+        }
+    }
+
+    /// Linear RGB to XYZ with a D65 white.
+    fn to_xyz(self) -> Matrix {
+        let p = self.primaries().map(xy_to_xyz);
+        let columns = [[p[0][0], p[1][0], p[2][0]], [p[0][1], p[1][1], p[2][1]], [p[0][2], p[1][2], p[2][2]]];
+        let s = apply(&invert(&columns), xy_to_xyz(D65));
+        columns.map(|row| [row[0] * s[0], row[1] * s[1], row[2] * s[2]])
+    }
+
+    /// Encoded value from linear light.
+    fn encode(self, linear: f64) -> f64 {
+        let linear = linear.clamp(0.0, 1.0);
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => srgb_encode(linear),
+            ColorSpace::AdobeRgb => linear.powf(256.0 / 563.0),
+        }
+    }
+}
+
+fn xy_to_xyz([x, y]: [f64; 2]) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+fn srgb_decode(v: f64) -> f64 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn srgb_encode(v: f64) -> f64 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn apply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+fn invert(m: &Matrix) -> Matrix {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    [
+        [cofactor(1, 2, 1, 2) / det, -cofactor(0, 2, 1, 2) / det, cofactor(0, 1, 1, 2) / det],
+        [-cofactor(1, 2, 0, 2) / det, cofactor(0, 2, 0, 2) / det, -cofactor(0, 1, 0, 2) / det],
+        [cofactor(1, 2, 0, 1) / det, -cofactor(0, 2, 0, 1) / det, cofactor(0, 1, 0, 1) / det],
+    ]
+}
+
+/// Bradford adaptation from D65 to the D50 white ICC profiles are expressed in.
+fn d65_to_d50() -> Matrix {
+    let source = apply(&BRADFORD, xy_to_xyz(D65));
+    let target = apply(&BRADFORD, D50_XYZ);
+    let scale = [[target[0] / source[0], 0.0, 0.0], [0.0, target[1] / source[1], 0.0], [0.0, 0.0, target[2] / source[2]]];
+    multiply(&invert(&BRADFORD), &multiply(&scale, &BRADFORD))
+}
+
+/// Convert 8-bit sRGB pixels with `channels` bytes each (alpha is left alone) to `space`.
+pub fn convert(pixels: &mut [u8], channels: usize, space: ColorSpace) {
+    if space == ColorSpace::Srgb {
+        return;
+    }
+    let decode = [0, 1, 2].map(|_| std::array::from_fn(|v| srgb_decode(v as f64 / 255.0)));
+    let m = multiply(&invert(&space.to_xyz()), &ColorSpace::Srgb.to_xyz());
+    transform(pixels, channels, &decode, &m, space);
+}
+
+/// Convert 8-bit pixels described by `source` to `space`.
+fn convert_tagged(pixels: &mut [u8], channels: usize, source: &SourceProfile, space: ColorSpace) {
+    let m = multiply(&invert(&multiply(&d65_to_d50(), &space.to_xyz())), &source.to_pcs);
+    transform(pixels, channels, &source.decode, &m, space);
+}
+
+/// Decode each channel to linear light, mix with `m` and encode for `space`.
+fn transform(pixels: &mut [u8], channels: usize, decode: &[[f64; 256]; 3], m: &Matrix, space: ColorSpace) {
+    const STEPS: usize = 4096;
+    let encode: Vec<u8> = (0..=STEPS).map(|i| (space.encode(i as f64 / STEPS as f64) * 255.0).round() as u8).collect();
+    for pixel in pixels.chunks_exact_mut(channels) {
+        let linear = apply(m, [0, 1, 2].map(|c| decode[c][pixel[c] as usize]));
+        for (c, value) in linear.into_iter().enumerate() {
+            pixel[c] = encode[(value.clamp(0.0, 1.0) * STEPS as f64).round() as usize];
+        }
+    }
+}
+
+/// What the values of a source's pixels mean, read from its RGB matrix/TRC profile, the
+/// kind cameras and editors embed for sRGB, Adobe RGB or Display P3: a curve per channel
+/// to linear light, and the matrix from there to the D50 profile connection space.
+struct SourceProfile {
+    decode: [[f64; 256]; 3],
+    to_pcs: Matrix,
+}
+
+impl SourceProfile {
+    /// None for any other kind of profile, e.g. a LUT-based or CMYK one.
+    fn parse(icc: &[u8]) -> Option<Self> {
+        if icc.get(16..24)? != b"RGB XYZ " {
+            return None;
+        }
+        let count = be_u32(icc, 128)? as usize;
+        let tag = |signature: &[u8; 4]| -> Option<&[u8]> {
+            (0..count.min(256)).find_map(|i| {
+                let entry = 132 + i * 12;
+                if icc.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let (offset, size) = (be_u32(icc, entry + 4)? as usize, be_u32(icc, entry + 8)? as usize);
+                icc.get(offset..offset.checked_add(size)?)
+            })
+        };
+        let mut to_pcs = [[0.0; 3]; 3];
+        for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let xyz = tag(signature).filter(|t| t.starts_with(b"XYZ "))?;
+            for (row, values) in to_pcs.iter_mut().enumerate() {
+                values[column] = s15_fixed16(xyz, 8 + row * 4)?;
+            }
+        }
+        let mut decode = [[0.0; 256]; 3];
+        for (curve, signature) in decode.iter_mut().zip([b"rTRC", b"gTRC", b"bTRC"]) {
+            let trc = tag(signature)?;
+            for (v, linear) in curve.iter_mut().enumerate() {
+                *linear = tone_curve(trc, v as f64 / 255.0)?;
+            }
+        }
+        Some(Self { decode, to_pcs })
+    }
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at.checked_add(4)?)?.try_into().ok()?))
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at.checked_add(2)?)?.try_into().ok()?))
+}
+
+fn s15_fixed16(data: &[u8], at: usize) -> Option<f64> {
+    Some(be_u32(data, at)? as i32 as f64 / 65536.0)
+}
+
+/// Linear light for the encoded value `x` (0 to 1) through a `curv` or `para` tag.
+fn tone_curve(tag: &[u8], x: f64) -> Option<f64> {
+    let y = match tag.get(0..4)? {
+        b"curv" => match be_u32(tag, 8)? as usize {
+            0 => x,
+            1 => x.powf(be_u16(tag, 12)? as f64 / 256.0),
+            n => {
+                // Evenly spaced samples, interpolated linearly
+                let at = x * (n - 1) as f64;
+                let i = (at.floor() as usize).min(n - 2);
+                let (a, b) = (be_u16(tag, 12 + i * 2)? as f64, be_u16(tag, 14 + i * 2)? as f64);
+                (a + (b - a) * (at - i as f64)) / 65535.0
+            }
+        },
+        b"para" => {
+            let kind = be_u16(tag, 8)? as usize;
+            let count = *[1, 3, 4, 5, 7].get(kind)?;
+            let mut p = [0.0; 7];
+            for (i, value) in p.iter_mut().take(count).enumerate() {
+                *value = s15_fixed16(tag, 12 + i * 4)?;
+            }
+            let [g, a, b, c, d, e, f] = p;
+            match kind {
+                0 => x.powf(g),
+                1 if x >= -b / a => (a * x + b).powf(g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(g) + c,
+                2 => c,
+                3 if x >= d => (a * x + b).powf(g),
+                3 => c * x,
+                _ if x >= d => (a * x + b).powf(g) + e,
+                _ => c * x + f,
+            }
+        }
+        _ => return None,
+    };
+    y.is_finite().then(|| y.clamp(0.0, 1.0))
+}
+
+/// A version 2 display profile describing `space` by its D50-adapted primaries and tone curve.
+pub fn icc_profile(space: ColorSpace) -> Vec<u8> {
+    let s15 = |v: f64| ((v * 65536.0).round() as i32).to_be_bytes();
+    let xyz = |v: [f64; 3]| -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for c in v {
+            tag.extend_from_slice(&s15(c));
+        }
+        tag
+    };
+    let text = |t: &str| -> Vec<u8> {
+        let mut tag = b"text\0\0\0\0".to_vec();
+        tag.extend_from_slice(t.as_bytes());
+        tag.push(0);
+        tag
+    };
+    let desc = |t: &str| -> Vec<u8> {
+        let mut tag = b"desc\0\0\0\0".to_vec();
+        tag.extend_from_slice(&(t.len() as u32 + 1).to_be_bytes());
+        tag.extend_from_slice(t.as_bytes());
+        tag.push(0);
+        // No Unicode or ScriptCode descriptions
+        tag.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+        tag
+    };
+    let curve = match space {
+        ColorSpace::AdobeRgb => {
+            // A plain gamma of 563/256 as u8Fixed8
+            let mut tag = b"curv\0\0\0\0".to_vec();
+            tag.extend_from_slice(&1u32.to_be_bytes());
+            tag.extend_from_slice(&0x0233u16.to_be_bytes());
+            tag
+        }
+        _ => {
+            const ENTRIES: usize = 1024;
+            let mut tag = b"curv\0\0\0\0".to_vec();
+            tag.extend_from_slice(&(ENTRIES as u32).to_be_bytes());
+            for i in 0..ENTRIES {
+                let linear = srgb_decode(i as f64 / (ENTRIES - 1) as f64);
+                tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+            }
+            tag
+        }
+    };
+    let colorants = multiply(&d65_to_d50(), &space.to_xyz());
+    let column = |c: usize| [colorants[0][c], colorants[1][c], colorants[2][c]];
+
+    // The three tone curves share one copy of the data
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", desc(space.label())),
+        (b"cprt", text("No copyright, use freely")),
+        (b"wtpt", xyz(D50_XYZ)),
+        (b"rXYZ", xyz(column(0))),
+        (b"gXYZ", xyz(column(1))),
+        (b"bXYZ", xyz(column(2))),
+        (b"rTRC", curve),
+    ];
+    let table_size = 4 + 12 * (tags.len() + 2);
+    let mut table = ((tags.len() + 2) as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let mut curve_entry = [0u8; 8];
+    for (signature, tag) in &tags {
+        let offset = (128 + table_size + data.len()) as u32;
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&offset.to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        if *signature == b"rTRC" {
+            curve_entry[..4].copy_from_slice(&offset.to_be_bytes());
+            curve_entry[4..].copy_from_slice(&(tag.len() as u32).to_be_bytes());
+        }
+        data.extend_from_slice(tag);
+        // Tags start on four-byte boundaries
+        data.resize(data.len().div_ceil(4) * 4, 0);
+    }
+    for signature in [b"gTRC", b"bTRC"] {
+        table.extend_from_slice(signature);
+        table.extend_from_slice(&curve_entry);
+    }
+
+    let size = 128 + table.len() + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]);
+    profile.extend_from_slice(&[2, 0x10, 0, 0]);
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    // Creation date: 2024-01-01 00:00:00
+    for part in [2024u16, 1, 1, 0, 0, 0] {
+        profile.extend_from_slice(&part.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 24]);
+    // Perceptual rendering intent
+    profile.extend_from_slice(&[0; 4]);
+    for c in D50_XYZ {
+        profile.extend_from_slice(&s15(c));
+    }
+    profile.resize(128, 0);
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+/// Fields of the source's first image directory that describe the picture rather than
+/// how its pixels were stored, so they stay true for a developed or cropped copy.
+const TIFF_TAGS: [Tag; 10] = [
+    Tag::ImageDescription,
+    Tag::Make,
+    Tag::Model,
+    Tag::Software,
+    Tag::DateTime,
+    Tag::Artist,
+    Tag::Copyright,
+    Tag::XResolution,
+    Tag::YResolution,
+    Tag::ResolutionUnit,
+];
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Longest XMP packet fitting one JPEG segment
+const MAX_XMP_LEN: usize = 65533 - XMP_SIGNATURE.len();
+
+/// What an exported copy carries over from its source.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    /// EXIF as TIFF data, with the orientation reset since exports are saved upright
+    exif: Option<Vec<u8>>,
+    /// The embedded XMP packet, or else the sidecar's
+    xmp: Option<String>,
+    /// The source's color profile, describing its pixel values
+    icc: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Collect what `source` holds; unreadable or missing parts are left out.
+    pub fn read(source: &Path) -> Self {
+        let Ok(data) = std::fs::read(source) else {
+            return Self::default();
+        };
+        let xmp = embedded_xmp(&data)
+            .or_else(|| std::fs::read_to_string(crate::xmp::sidecar_path(source)).ok())
+            .map(|xml| crate::xmp::set_orientation_upright(&xml));
+        Self { exif: upright_exif(&data), xmp, icc: icc_of(&data) }
+    }
+
+    /// Only the color profile, for copies that must not give away where or when they were taken.
+    pub fn colors_only(self) -> Self {
+        Self { exif: None, xmp: None, icc: self.icc }
+    }
+}
+
+/// The EXIF of an encoded image or TIFF-based RAW file, rewritten without the layout of its pixels.
+fn upright_exif(data: &[u8]) -> Option<Vec<u8>> {
+    let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(data)).ok()?;
+    rewrite_exif(&exif, Tag::Orientation, 1)
+}
+
+/// EXIF as written by `upright_exif`, with its ColorSpace field saying sRGB, or
+/// "uncalibrated" as EXIF has it for any other space, which the embedded profile names.
+fn tag_color_space(tiff: &[u8], space: ColorSpace) -> Option<Vec<u8>> {
+    let exif = exif::Reader::new().read_raw(tiff.to_vec()).ok()?;
+    rewrite_exif(&exif, Tag::ColorSpace, if space == ColorSpace::Srgb { 1 } else { 0xFFFF })
+}
+
+/// The fields of `exif` that stay true for an exported copy, with `tag` set to `value`.
+fn rewrite_exif(exif: &exif::Exif, tag: Tag, value: u16) -> Option<Vec<u8>> {
+    let set = exif::Field { tag, ifd_num: In::PRIMARY, value: exif::Value::Short(vec![value]) };
+    let mut writer = exif::experimental::Writer::new();
+    writer.push_field(&set);
+    for field in exif.fields().filter(|f| f.ifd_num == In::PRIMARY && f.tag != tag) {
+        let keep = match field.tag.context() {
+            // Rewriting EXIF written by `upright_exif` keeps the orientation it set
+            Context::Tiff => TIFF_TAGS.contains(&field.tag) || field.tag == Tag::Orientation,
+            Context::Exif => !matches!(field.tag, Tag::PixelXDimension | Tag::PixelYDimension),
+            Context::Gps => true,
+            _ => false,
+        };
+        if keep && !matches!(field.value, exif::Value::Unknown(..)) {
+            writer.push_field(field);
+        }
+    }
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    writer.write(&mut tiff, exif.little_endian()).ok()?;
+    Some(tiff.into_inner())
+}
+
+/// JPEG segments before the image data, as (marker, body).
+fn jpeg_segments(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return segments;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let end = pos + 2 + u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if end > data.len() || end < pos + 4 {
+            break;
+        }
+        segments.push((marker, &data[pos + 4..end]));
+        pos = end;
+    }
+    segments
+}
+
+fn embedded_xmp(data: &[u8]) -> Option<String> {
+    jpeg_segments(data)
+        .into_iter()
+        .find_map(|(marker, body)| (marker == 0xE1).then(|| body.strip_prefix(XMP_SIGNATURE)).flatten())
+        .map(|packet| String::from_utf8_lossy(packet).into_owned())
+}
+
+/// The ICC profile embedded in a JPEG or PNG.
+fn icc_of(data: &[u8]) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+    let cursor = std::io::Cursor::new(data);
+    match image::guess_format(data).ok()? {
+        image::ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(cursor).ok()?.icc_profile(),
+        image::ImageFormat::Png => image::codecs::png::PngDecoder::new(cursor).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+fn jpeg_segment(marker: u8, signature: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let len = 2 + signature.len() + parts.iter().map(|p| p.len()).sum::<usize>();
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&(len as u16).to_be_bytes());
+    segment.extend_from_slice(signature);
+    for part in parts {
+        segment.extend_from_slice(part);
+    }
+    segment
+}
+
+/// Add EXIF, XMP and the color profile to an encoded JPEG, after the JFIF header if there is one.
+fn embed_jpeg(jpeg: &mut Vec<u8>, exif: Option<&[u8]>, xmp: Option<&str>, profile: &[u8]) {
+    let mut segments = Vec::new();
+    if let Some(exif) = exif.filter(|e| e.len() <= 65527) {
+        segments.extend(jpeg_segment(0xE1, b"Exif\0\0", &[exif]));
+    }
+    if let Some(xmp) = xmp.filter(|x| x.len() <= MAX_XMP_LEN) {
+        segments.extend(jpeg_segment(0xE1, XMP_SIGNATURE, &[xmp.as_bytes()]));
+    }
+    const CHUNK: usize = 65519 - 14;
+    let chunks: Vec<&[u8]> = profile.chunks(CHUNK).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        segments.extend(jpeg_segment(0xE2, b"ICC_PROFILE\0", &[&[i as u8 + 1, chunks.len() as u8], chunk]));
+    }
+    let mut at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) && jpeg.len() >= 6 {
+        at = 4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+    }
+    jpeg.splice(at..at, segments);
+}
+
+/// Save `img` as PNG for a `.png` target, else as a high-quality JPEG, with `metadata`.
+/// The pixels are converted to `space` from the source's profile, or from sRGB when it
+/// has none. A source profile that can't be converted from is kept, pixels untouched.
+pub fn save(img: &DynamicImage, target: &Path, space: ColorSpace, metadata: &Metadata) -> Result<()> {
+    let source = metadata.icc.as_deref().map(|icc| (icc, SourceProfile::parse(icc)));
+    let kept = match &source {
+        Some((icc, None)) => {
+            log::warn!("Can't convert from the color profile of {:?}'s source; kept it instead of {}", target, space.label());
+            Some(icc.to_vec())
+        }
+        _ => None,
+    };
+    let to_space = |pixels: &mut [u8], channels: usize| match &source {
+        None => convert(pixels, channels, space),
+        Some((_, Some(profile))) => convert_tagged(pixels, channels, profile, space),
+        Some((_, None)) => {}
+    };
+    let exif = match &kept {
+        Some(_) => metadata.exif.clone(),
+        None => metadata.exif.as_deref().map(|tiff| tag_color_space(tiff, space).unwrap_or_else(|| tiff.to_vec())),
+    };
+    let profile = kept.unwrap_or_else(|| icc_profile(space));
+    let png = target.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("png"));
+    if png {
+        let mut rgba = img.to_rgba8();
+        to_space(&mut rgba, 4);
+        let mut info = png::Info::with_size(rgba.width(), rgba.height());
+        info.color_type = png::ColorType::Rgba;
+        info.bit_depth = png::BitDepth::Eight;
+        info.icc_profile = Some(profile.into());
+        let file = std::io::BufWriter::new(std::fs::File::create(target)?);
+        let mut writer = png::Encoder::with_info(file, info)?.write_header()?;
+        if let Some(exif) = &exif {
+            writer.write_chunk(png::chunk::ChunkType(*b"eXIf"), exif)?;
+        }
+        if let Some(xmp) = &metadata.xmp {
+            // Uncompressed international text, without language or translated keyword
+            let mut chunk = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+            chunk.extend_from_slice(xmp.as_bytes());
+            writer.write_chunk(png::chunk::iTXt, &chunk)?;
+        }
+        writer.write_image_data(&rgba)?;
+        writer.finish()?;
+    } else {
+        let mut rgb = img.to_rgb8();
+        to_space(&mut rgb, 3);
+        let mut jpeg = Vec::new();
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY);
+        rgb.write_with_encoder(encoder)?;
+        embed_jpeg(&mut jpeg, exif.as_deref(), metadata.xmp.as_deref(), &profile);
+        std::fs::write(target, jpeg)?;
+    }
+    Ok(())
+}
+
+/// `photo.jpg` -> `photo_clean.jpg`; PNGs stay PNG and anything else becomes a JPEG.
+pub fn clean_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let ext = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("png") => "png",
+        _ => "jpg",
+    };
+    path.with_file_name(format!("{}_clean.{}", stem, ext))
+}
+
+/// Save a copy of `source` for sharing, without its EXIF, GPS position, XMP or IPTC data;
+/// only the color profile and orientation are kept. JPEGs are copied as they are, without
+/// re-encoding, and other files are decoded and saved upright.
+pub fn save_clean(source: &Path, target: &Path) -> Result<()> {
+    let data = std::fs::read(source)?;
+    if let Some(clean) = strip_jpeg(&data) {
+        std::fs::write(target, clean)?;
+        return Ok(());
+    }
+    let loaded = crate::sandbox::load(source)?;
+    save(&loaded.image, target, ColorSpace::Srgb, &Metadata::read(source).colors_only())
+}
+
+/// A JPEG's header segments and image data without metadata. The orientation survives in
+/// an otherwise empty EXIF block, as the pixels are stored unrotated.
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let segments = jpeg_segments(data);
+    let scan = 2 + segments.iter().map(|(_, body)| body.len() + 4).sum::<usize>();
+    if data.get(scan..scan + 2) != Some(&[0xFF, 0xDA]) {
+        return None;
+    }
+    let mut out = vec![0xFF, 0xD8];
+    for (marker, body) in segments {
+        // JFIF, the color profile and Adobe's color transform stay; EXIF, XMP, IPTC,
+        // vendor blocks and comments go
+        let keep = match marker {
+            0xE0 => body.starts_with(b"JFIF\0"),
+            0xE2 => body.starts_with(b"ICC_PROFILE\0"),
+            0xEE => body.starts_with(b"Adobe"),
+            0xE1..=0xEF | 0xFE => false,
+            _ => true,
+        };
+        if keep {
+            out.extend(jpeg_segment(marker, body, &[]));
+        }
+    }
+    // Phones append preview images, with EXIF of their own, after the end of the image
+    out.extend_from_slice(&data[scan..jpeg_end(data, scan).unwrap_or(data.len())]);
+
+    let orientation = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(data))
+        .ok()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY).and_then(|f| f.value.get_uint(0)))
+        .filter(|&o| (2..=8).contains(&o));
+    if let Some(orientation) = orientation {
+        let field = exif::Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: exif::Value::Short(vec![orientation as u16]) };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&field);
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).ok()?;
+        embed_jpeg(&mut out, Some(tiff.get_ref()), None, &[]);
+    }
+    Some(out)
+}
+
+/// Where the first image of a JPEG ends, just past its EOI marker, walking from the
+/// marker at `pos` over segments and entropy-coded data.
+fn jpeg_end(data: &[u8], mut pos: usize) -> Option<usize> {
+    while pos + 2 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            0xD9 => return Some(pos + 2),
+            // Fill byte
+            0xFF => pos += 1,
+            0xD0..=0xD7 => pos += 2,
+            _ => pos += 2 + u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize,
+        }
+        if marker == 0xDA || (0xD0..=0xD7).contains(&marker) {
+            // Scan data up to the next marker; 0xFF in the data is followed by 0 or a restart marker
+            while pos + 1 < data.len() && !(data[pos] == 0xFF && data[pos + 1] != 0 && !(0xD0..=0xD7).contains(&data[pos + 1])) {
+                pos += 1;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_conversion() {
+        // The well-known D50 colorants of sRGB
+        let colorants = multiply(&d65_to_d50(), &ColorSpace::Srgb.to_xyz());
+        let expected = [[0.4361, 0.3851, 0.1431], [0.2225, 0.7169, 0.0606], [0.0139, 0.0971, 0.7141]];
+        for (row, expected) in colorants.iter().zip(expected) {
+            for (value, expected) in row.iter().zip(expected) {
+                assert!((value - expected).abs() < 2e-3, "{:?}", colorants);
+            }
+        }
+
+        // Pure sRGB red sits inside the wider P3 gamut; grays stay gray
+        let mut pixels = [255, 0, 0, 128, 128, 128];
+        convert(&mut pixels, 3, ColorSpace::DisplayP3);
+        assert_eq!(pixels, [234, 51, 35, 128, 128, 128]);
+    }
+
+    #[test]
+    fn test_source_profile() {
+        let colors = [255, 0, 0, 40, 200, 90, 128, 128, 128, 0, 0, 0];
+        for from in [ColorSpace::Srgb, ColorSpace::DisplayP3, ColorSpace::AdobeRgb] {
+            let source = SourceProfile::parse(&icc_profile(from)).unwrap();
+            // Into its own space the pixels stay put
+            let mut pixels = colors;
+            convert_tagged(&mut pixels, 3, &source, from);
+            for (value, expected) in pixels.iter().zip(colors) {
+                assert!(value.abs_diff(expected) <= 1, "{:?}: {:?}", from, pixels);
+            }
+        }
+        // A tagged sRGB source converts like an untagged one
+        let source = SourceProfile::parse(&icc_profile(ColorSpace::Srgb)).unwrap();
+        let (mut tagged, mut untagged) = (colors, colors);
+        convert_tagged(&mut tagged, 3, &source, ColorSpace::DisplayP3);
+        convert(&mut untagged, 3, ColorSpace::DisplayP3);
+        for (value, expected) in tagged.iter().zip(untagged) {
+            assert!(value.abs_diff(expected) <= 1, "{:?} {:?}", tagged, untagged);
+        }
+        assert!(SourceProfile::parse(b"not a profile").is_none());
+        assert!(SourceProfile::parse(&icc_profile(ColorSpace::Srgb)[..200]).is_none());
+    }
+
+    #[test]
+    fn test_save_converts_tagged_sources() {
+        let dir = scratch_dir("export-tagged");
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([200, 100, 50])));
+        let mut writer = exif::experimental::Writer::new();
+        let srgb = exif::Field { tag: Tag::ColorSpace, ifd_num: In::PRIMARY, value: exif::Value::Short(vec![1]) };
+        writer.push_field(&srgb);
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, true).unwrap();
+        let adobe = icc_profile(ColorSpace::AdobeRgb);
+        let metadata = Metadata { exif: Some(tiff.into_inner()), xmp: None, icc: Some(adobe.clone()) };
+
+        use image::ImageDecoder;
+        let target = dir.join("adobe.png");
+        save(&img, &target, ColorSpace::AdobeRgb, &metadata).unwrap();
+        let data = std::fs::read(&target).unwrap();
+        let mut decoder = image::codecs::png::PngDecoder::new(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.icc_profile(), Some(adobe));
+        let pixel = image::load_from_memory(&data).unwrap().to_rgb8().get_pixel(0, 0).0;
+        assert!(pixel.iter().zip([200u8, 100, 50]).all(|(v, e)| v.abs_diff(e) <= 1), "{:?}", pixel);
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(exif.get_field(Tag::ColorSpace, In::PRIMARY).unwrap().value.get_uint(0), Some(0xFFFF));
+
+        // The same Adobe RGB values mean a more saturated color than in sRGB
+        let target = dir.join("srgb.png");
+        save(&img, &target, ColorSpace::Srgb, &metadata).unwrap();
+        let data = std::fs::read(&target).unwrap();
+        let pixel = image::load_from_memory(&data).unwrap().to_rgb8().get_pixel(0, 0).0;
+        assert!(pixel[0] > 200 && pixel[2] < 50, "{:?}", pixel);
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(exif.get_field(Tag::ColorSpace, In::PRIMARY).unwrap().value.get_uint(0), Some(1));
+
+        // A profile that can't be converted from is kept, with the pixels as they are
+        let odd = Metadata { icc: Some(b"not a profile".to_vec()), ..Metadata::default() };
+        let target = dir.join("odd.png");
+        save(&img, &target, ColorSpace::DisplayP3, &odd).unwrap();
+        let mut decoder = image::codecs::png::PngDecoder::new(std::fs::File::open(&target).unwrap()).unwrap();
+        assert_eq!(decoder.icc_profile(), odd.icc);
+        assert_eq!(image::open(&target).unwrap().to_rgb8().get_pixel(0, 0).0, [200, 100, 50]);
+    }
+
+    #[test]
+    fn test_save_embeds_the_profile() {
+        let dir = scratch_dir("export");
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([200, 100, 50])));
+        let profile = icc_profile(ColorSpace::AdobeRgb);
+        assert_eq!(u32::from_be_bytes(profile[..4].try_into().unwrap()) as usize, profile.len());
+        assert_eq!(&profile[36..40], b"acsp");
+
+        use image::ImageDecoder;
+        let jpeg = dir.join("a.jpg");
+        save(&img, &jpeg, ColorSpace::AdobeRgb, &Metadata::default()).unwrap();
+        let mut decoder = image::codecs::jpeg::JpegDecoder::new(std::fs::File::open(&jpeg).unwrap()).unwrap();
+        assert_eq!(decoder.icc_profile(), Some(profile.clone()));
+        let png = dir.join("a.png");
+        save(&img, &png, ColorSpace::AdobeRgb, &Metadata::default()).unwrap();
+        let mut decoder = image::codecs::png::PngDecoder::new(std::fs::File::open(&png).unwrap()).unwrap();
+        assert_eq!(decoder.icc_profile(), Some(profile));
+    }
+
+    #[test]
+    fn test_save_carries_metadata_upright() {
+        let dir = scratch_dir("export-metadata");
+        let source = dir.join("portrait.jpg");
+        image::RgbImage::from_pixel(8, 4, image::Rgb([90, 90, 90])).save(&source).unwrap();
+        crate::rotate::save(&source, 1).unwrap();
+        std::fs::write(dir.join("portrait.xmp"), r#"<rdf:Description tiff:Orientation="6" xmp:Rating="4"/>"#).unwrap();
+
+        let metadata = Metadata::read(&source);
+        assert_eq!(metadata.xmp.as_deref(), Some(r#"<rdf:Description tiff:Orientation="1" xmp:Rating="4"/>"#));
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(4, 8));
+        for name in ["copy.jpg", "copy.png"] {
+            let target = dir.join(name);
+            save(&img, &target, ColorSpace::Srgb, &metadata).unwrap();
+            let data = std::fs::read(&target).unwrap();
+            let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)).unwrap();
+            let orientation = exif.get_field(Tag::Orientation, In::PRIMARY).unwrap();
+            assert_eq!(orientation.value.get_uint(0), Some(1), "{}", name);
+            assert!(data.windows(14).any(|w| w == b"xmp:Rating=\"4\""), "{}", name);
+        }
+
+        let stripped = metadata.colors_only();
+        assert_eq!((stripped.exif, stripped.xmp), (None, None));
+    }
+
+    #[test]
+    fn test_clean_copy() {
+        let dir = scratch_dir("export-clean");
+        let img = image::RgbImage::from_fn(16, 8, |x, y| image::Rgb([x as u8 * 16, y as u8 * 32, 90]));
+        let mut jpeg = Vec::new();
+        img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90)).unwrap();
+        let fields = [
+            exif::Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: exif::Value::Short(vec![6]) },
+            exif::Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: exif::Value::Ascii(vec![b"N".to_vec()]) },
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let profile = icc_profile(ColorSpace::DisplayP3);
+        embed_jpeg(&mut jpeg, Some(tiff.get_ref()), Some("<x:xmpmeta>secret</x:xmpmeta>"), &profile);
+        jpeg.extend_from_slice(b"\xFF\xD8 appended preview");
+        let source = dir.join("photo.jpeg");
+        std::fs::write(&source, &jpeg).unwrap();
+
+        let target = clean_path(&source);
+        assert_eq!(target, dir.join("photo_clean.jpg"));
+        save_clean(&source, &target).unwrap();
+        let data = std::fs::read(&target).unwrap();
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(exif.get_field(Tag::Orientation, In::PRIMARY).unwrap().value.get_uint(0), Some(6));
+        assert!(exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY).is_none());
+        assert!(!data.windows(6).any(|w| w == b"secret") && !data.ends_with(b"preview"));
+        // The image data is copied, not re-encoded
+        assert_eq!(image::load_from_memory(&data).unwrap().to_rgb8(), image::load_from_memory(&jpeg).unwrap().to_rgb8());
+        use image::ImageDecoder;
+        let mut decoder = image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.icc_profile(), Some(profile));
+    }
+}
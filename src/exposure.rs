@@ -0,0 +1,62 @@
+//! Exposure overlays drawn by the image shader: clipping warnings (blown
+//! highlights red, crushed blacks blue) and false color, which paints tonal
+//! bands so exposure can be judged at a glance.
+
+use crate::config::ExposureConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlay {
+    #[default]
+    Off,
+    Clipping,
+    FalseColor,
+}
+
+impl Overlay {
+    /// Switch to `overlay`, or off if it is already shown.
+    pub fn toggled(self, overlay: Overlay) -> Overlay {
+        if self == overlay {
+            Overlay::Off
+        } else {
+            overlay
+        }
+    }
+
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            Overlay::Off => None,
+            Overlay::Clipping => Some("Clipping"),
+            Overlay::FalseColor => Some("False color"),
+        }
+    }
+
+    /// Mode and the highlight and shadow thresholds, as display values, for the shader.
+    pub fn uniform(self, config: &ExposureConfig) -> [f32; 4] {
+        let mode = match self {
+            Overlay::Off => 0.0,
+            Overlay::Clipping => 1.0,
+            Overlay::FalseColor => 2.0,
+        };
+        let highlight = config.highlight.clamp(0.5, 1.0);
+        let shadow = config.shadow.clamp(0.0, 0.5);
+        [mode, highlight, shadow, 0.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlay_toggle() {
+        let overlay = Overlay::Off.toggled(Overlay::Clipping);
+        assert_eq!(overlay, Overlay::Clipping);
+        let overlay = overlay.toggled(Overlay::FalseColor);
+        assert_eq!(overlay.label(), Some("False color"));
+        assert_eq!(overlay.toggled(Overlay::FalseColor), Overlay::Off);
+
+        let config = ExposureConfig { highlight: 1.5, shadow: 0.02 };
+        assert_eq!(Overlay::Clipping.uniform(&config), [1.0, 1.0, 0.02, 0.0]);
+        assert_eq!(Overlay::Off.uniform(&config)[0], 0.0);
+    }
+}
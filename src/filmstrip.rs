@@ -0,0 +1,71 @@
+use crate::quad::Rect;
+
+pub const STRIP_HEIGHT: f32 = 120.0;
+const PADDING: f32 = 10.0;
+
+/// Position the filmstrip cells along the bottom of the window, keeping `current` centered.
+/// Returns the index into the image list and cell rectangle of every visible cell.
+pub fn layout(width: f32, height: f32, count: usize, current: Option<usize>) -> Vec<(usize, Rect)> {
+    let cell = STRIP_HEIGHT - 2.0 * PADDING;
+    let stride = cell + PADDING;
+    let y = height - STRIP_HEIGHT + PADDING;
+    let origin = width / 2.0 - cell / 2.0;
+    let current = current.unwrap_or(0);
+
+    let half = (width / 2.0 / stride).ceil() as usize + 1;
+    let first = current.saturating_sub(half);
+    let last = (current + half + 1).min(count);
+
+    (first..last)
+        .filter_map(|i| {
+            let x = origin + (i as f32 - current as f32) * stride;
+            if x + cell < 0.0 || x > width {
+                None
+            } else {
+                Some((i, Rect::new(x, y, cell, cell)))
+            }
+        })
+        .collect()
+}
+
+pub fn strip_rect(width: f32, height: f32) -> Rect {
+    Rect::new(0.0, height - STRIP_HEIGHT, width, STRIP_HEIGHT)
+}
+
+/// Fit an image with the given aspect ratio inside `cell`, centered.
+pub fn fit(cell: Rect, aspect: f32) -> Rect {
+    if aspect >= 1.0 {
+        let h = cell.w / aspect;
+        Rect::new(cell.x, cell.y + (cell.h - h) / 2.0, cell.w, h)
+    } else {
+        let w = cell.h * aspect;
+        Rect::new(cell.x + (cell.w - w) / 2.0, cell.y, w, cell.h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_centers_current() {
+        let cells = layout(1000.0, 800.0, 50, Some(20));
+        let (_, current) = cells.iter().find(|(i, _)| *i == 20).unwrap();
+        assert_eq!(current.x + current.w / 2.0, 500.0);
+        assert!(cells.iter().all(|(_, r)| r.x + r.w >= 0.0 && r.x <= 1000.0));
+        assert!(cells.iter().all(|(_, r)| r.y >= 800.0 - STRIP_HEIGHT));
+
+        // First image: nothing to the left of it
+        let cells = layout(1000.0, 800.0, 50, Some(0));
+        assert_eq!(cells.first().unwrap().0, 0);
+
+        assert!(layout(1000.0, 800.0, 0, None).is_empty());
+    }
+
+    #[test]
+    fn test_fit() {
+        let cell = Rect::new(0.0, 0.0, 100.0, 100.0);
+        assert_eq!(fit(cell, 2.0), Rect::new(0.0, 25.0, 100.0, 50.0));
+        assert_eq!(fit(cell, 0.5), Rect::new(25.0, 0.0, 50.0, 100.0));
+    }
+}
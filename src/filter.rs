@@ -0,0 +1,195 @@
+//! Narrowing the navigation list with a typed filter such as `*.nef rating>=3`.
+
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+/// All terms must match. Terms are file name globs (`*.nef`, `IMG_1???.jpg`),
+/// rating comparisons against the XMP sidecar (`rating>=3`), or plain text
+/// that the file name must contain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    text: String,
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Glob(Vec<char>),
+    Rating(Comparison, i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    /// The operator at the start of `text` (`>=`, `<`, `!=`, ...) and what follows it.
+    pub fn parse_prefix(text: &str) -> Option<(Self, &str)> {
+        [
+            (">=", Comparison::GreaterOrEqual),
+            ("<=", Comparison::LessOrEqual),
+            ("!=", Comparison::NotEqual),
+            ("==", Comparison::Equal),
+            ("=", Comparison::Equal),
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+        ]
+        .into_iter()
+        .find_map(|(op, cmp)| text.strip_prefix(op).map(|rest| (cmp, rest)))
+    }
+
+    /// The operator as written in SQL.
+    pub fn sql(self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "=",
+            Comparison::NotEqual => "!=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Greater => ">",
+        }
+    }
+
+    fn holds(self, a: i32, b: i32) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Greater => a > b,
+        }
+    }
+}
+
+impl Filter {
+    /// Parse a filter; blank text means no filter.
+    pub fn parse(text: &str) -> Result<Option<Self>> {
+        let terms = text.split_whitespace().map(parse_term).collect::<Result<Vec<_>>>()?;
+        if terms.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self { text: text.trim().to_string(), terms }))
+    }
+
+    /// The filter as the user typed it.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let name: Vec<char> = name.chars().collect();
+        // The sidecar is only read when a rating term needs it
+        let mut rating = None;
+        self.terms.iter().all(|term| match term {
+            Term::Glob(pattern) => glob_match(pattern, &name),
+            Term::Rating(cmp, value) => {
+                let rating = *rating.get_or_insert_with(|| crate::xmp::read_sidecar(path).rating.unwrap_or(0));
+                cmp.holds(rating, *value)
+            }
+        })
+    }
+}
+
+fn parse_term(term: &str) -> Result<Term> {
+    if let Some(rest) = strip_prefix_ignore_case(term, "rating") {
+        let (cmp, value) = Comparison::parse_prefix(rest)
+            .ok_or_else(|| anyhow!("Expected a comparison after \"rating\" in {:?}", term))?;
+        let value = value.parse().map_err(|_| anyhow!("Invalid rating in {:?}", term))?;
+        return Ok(Term::Rating(cmp, value));
+    }
+
+    let pattern = term.to_lowercase();
+    let pattern = if pattern.contains(['*', '?']) { pattern } else { format!("*{}*", pattern) };
+    Ok(Term::Glob(pattern.chars().collect()))
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then(|| &text[prefix.len()..])
+}
+
+/// `*` matches any run of characters and `?` a single one.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was and how much of the name it has swallowed so far
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp + 1;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use std::path::PathBuf;
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        glob_match(&pattern.chars().collect::<Vec<_>>(), &name.chars().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("*.nef", "dsc_0001.nef"));
+        assert!(!glob("*.nef", "dsc_0001.nef.xmp"));
+        assert!(glob("img_1???.jpg", "img_1234.jpg"));
+        assert!(!glob("img_1???.jpg", "img_12345.jpg"));
+        assert!(glob("*a*b*", "xaxxbx"));
+        assert!(glob("?.jpg", "é.jpg"));
+        assert!(glob("*", ""));
+    }
+
+    #[test]
+    fn test_parse_and_match() {
+        assert_eq!(Filter::parse("  ").unwrap(), None);
+        assert!(Filter::parse("rating>>3").is_err());
+        assert!(Filter::parse("rating").is_err());
+
+        let filter = Filter::parse("*.NEF  sunset").unwrap().unwrap();
+        assert_eq!(filter.text(), "*.NEF  sunset");
+        assert!(filter.matches(&PathBuf::from("/shoot/Sunset_01.nef")));
+        assert!(!filter.matches(&PathBuf::from("/shoot/Sunset_01.jpg")));
+        assert!(!filter.matches(&PathBuf::from("/shoot/dawn.nef")));
+    }
+
+    #[test]
+    fn test_rating() {
+        let dir = scratch_dir("filter");
+        let rated = dir.join("rated.jpg");
+        std::fs::write(dir.join("rated.xmp"), r#"<rdf:Description xmp:Rating="4"></rdf:Description>"#).unwrap();
+        let unrated = dir.join("unrated.jpg");
+
+        let filter = Filter::parse("rating>=3").unwrap().unwrap();
+        assert!(filter.matches(&rated));
+        assert!(!filter.matches(&unrated));
+        let filter = Filter::parse("Rating=0").unwrap().unwrap();
+        assert!(!filter.matches(&rated));
+        assert!(filter.matches(&unrated));
+    }
+}
@@ -0,0 +1,66 @@
+//! Saving every frame of an animated GIF, WebP or PNG as numbered PNGs, for
+//! when one particular frame is wanted.
+
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+/// The files `extract` writes for an animation of `count` frames:
+/// `<name>_001.png`, `<name>_002.png`, ... in `dir`.
+pub fn frame_paths(source: &Path, count: usize, dir: &Path) -> Vec<PathBuf> {
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let digits = count.to_string().len().max(3);
+    (1..=count).map(|i| dir.join(format!("{}_{:0width$}.png", stem, i, width = digits))).collect()
+}
+
+/// Decode the frames of `source` and write them into `dir`, creating it if needed.
+/// Existing files are only replaced with `overwrite`. Returns the files written.
+pub fn extract(source: &Path, dir: &Path, overwrite: bool) -> Result<Vec<PathBuf>> {
+    let frames = crate::loader::decode_frames(&std::fs::read(source)?);
+    if frames.is_empty() {
+        return Err(anyhow!("{} is not animated", source.display()));
+    }
+    let paths = frame_paths(source, frames.len(), dir);
+    if let Some(existing) = paths.iter().find(|p| p.exists()).filter(|_| !overwrite) {
+        return Err(anyhow!("{} already exists", existing.display()));
+    }
+    std::fs::create_dir_all(dir)?;
+    for (frame, path) in frames.iter().zip(&paths) {
+        frame.image.save(path)?;
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    #[test]
+    fn test_extract_gif_frames() {
+        let dir = scratch_dir("frames");
+        let source = dir.join("clip.gif");
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        {
+            let file = std::fs::File::create(&source).unwrap();
+            let mut encoder = image::codecs::gif::GifEncoder::new(file);
+            for color in colors {
+                let image = RgbaImage::from_pixel(4, 2, Rgba(color));
+                encoder.encode_frame(Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(50, 1))).unwrap();
+            }
+        }
+
+        let out = dir.join("frames");
+        let paths = extract(&source, &out, false).unwrap();
+        assert_eq!(paths, vec![out.join("clip_001.png"), out.join("clip_002.png"), out.join("clip_003.png")]);
+        for (path, color) in paths.iter().zip(colors) {
+            let frame = image::open(path).unwrap().to_rgba8();
+            assert_eq!(frame.dimensions(), (4, 2));
+            assert_eq!(frame.get_pixel(1, 1).0, color);
+        }
+        // Earlier frames are kept unless replacing them is asked for
+        assert!(extract(&source, &out, false).is_err());
+        assert!(extract(&source, &out, true).is_ok());
+        assert_eq!(frame_paths(&source, 1200, &out)[0], out.join("clip_0001.png"));
+    }
+}
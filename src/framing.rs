@@ -0,0 +1,53 @@
+//! Aspect-ratio framing guides drawn over the image, without cropping it.
+
+use crate::quad::Rect;
+
+pub struct Preset {
+    pub label: &'static str,
+    /// Width over height
+    pub ratio: f32,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset { label: "1:1", ratio: 1.0 },
+    Preset { label: "4:5", ratio: 4.0 / 5.0 },
+    Preset { label: "3:2", ratio: 3.0 / 2.0 },
+    Preset { label: "16:9", ratio: 16.0 / 9.0 },
+    Preset { label: "9:16", ratio: 9.0 / 16.0 },
+    Preset { label: "1.85:1", ratio: 1.85 },
+    Preset { label: "2.39:1", ratio: 2.39 },
+];
+
+/// The largest rectangle of `ratio` centered in `image`.
+pub fn crop_rect(image: Rect, ratio: f32) -> Rect {
+    let (w, h) = if image.w / image.h > ratio { (image.h * ratio, image.h) } else { (image.w, image.w / ratio) };
+    Rect::new(image.x + (image.w - w) / 2.0, image.y + (image.h - h) / 2.0, w, h)
+}
+
+/// The parts of `image` outside `crop`: above, below, left and right.
+pub fn outside(image: Rect, crop: Rect) -> [Rect; 4] {
+    [
+        Rect::new(image.x, image.y, image.w, crop.y - image.y),
+        Rect::new(image.x, crop.y + crop.h, image.w, image.y + image.h - crop.y - crop.h),
+        Rect::new(image.x, crop.y, crop.x - image.x, crop.h),
+        Rect::new(crop.x + crop.w, crop.y, image.x + image.w - crop.x - crop.w, crop.h),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crop_rect() {
+        let image = Rect::new(0.0, 0.0, 600.0, 400.0);
+        let square = crop_rect(image, 1.0);
+        assert_eq!((square.x, square.y, square.w, square.h), (100.0, 0.0, 400.0, 400.0));
+        let cinema = crop_rect(image, 2.0);
+        assert_eq!((cinema.x, cinema.y, cinema.w, cinema.h), (0.0, 50.0, 600.0, 300.0));
+
+        let [top, bottom, left, right] = outside(image, square);
+        assert_eq!((top.h, bottom.h), (0.0, 0.0));
+        assert_eq!((left.x, left.w, right.x, right.w), (0.0, 100.0, 500.0, 100.0));
+    }
+}
@@ -0,0 +1,205 @@
+//! Geo-referencing of GeoTIFF rasters: pixel to world coordinates for the
+//! cursor readout and ground distances for the scale bar.
+
+use std::path::Path;
+use tiff::decoder::Decoder;
+use tiff::tags::Tag;
+
+const GT_MODEL_TYPE: u16 = 1024;
+const GEOGRAPHIC_TYPE: u16 = 2048;
+const PROJECTED_CS_TYPE: u16 = 3072;
+const PROJ_LINEAR_UNITS: u16 = 3076;
+/// Mean length of one degree of latitude.
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+/// The coordinate reference system, as far as needed to label and measure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crs {
+    /// Longitude/latitude in degrees
+    Geographic { epsg: Option<u16> },
+    /// Planar coordinates; `metres_per_unit` is known for metre and foot units
+    Projected { epsg: Option<u16>, metres_per_unit: Option<f64> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoInfo {
+    /// `x = t[0] + t[1] * col + t[2] * row`, `y = t[3] + t[4] * col + t[5] * row`
+    transform: [f64; 6],
+    pub crs: Crs,
+}
+
+impl GeoInfo {
+    /// Geo-referencing of a TIFF, or `None` for plain TIFFs and other formats.
+    pub fn read(path: &Path) -> Option<Self> {
+        if !crate::slide::is_tiff(path) {
+            return None;
+        }
+        let file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+        let mut decoder = Decoder::new(file).ok()?;
+        let mut doubles = |tag| decoder.get_tag_f64_vec(tag).ok();
+        let matrix = doubles(Tag::ModelTransformationTag);
+        let scale = doubles(Tag::ModelPixelScaleTag);
+        let tiepoint = doubles(Tag::ModelTiepointTag);
+        let keys = decoder.get_tag_u16_vec(Tag::GeoKeyDirectoryTag).unwrap_or_default();
+        Self::from_tags(matrix.as_deref(), scale.as_deref(), tiepoint.as_deref(), &keys)
+    }
+
+    fn from_tags(matrix: Option<&[f64]>, scale: Option<&[f64]>, tiepoint: Option<&[f64]>, keys: &[u16]) -> Option<Self> {
+        let transform = match (matrix, scale, tiepoint) {
+            (Some(m), _, _) if m.len() >= 8 => [m[3], m[0], m[1], m[7], m[4], m[5]],
+            (None, Some(s), Some(t)) if s.len() >= 2 && t.len() >= 6 => {
+                // The tiepoint pins raster (I, J) to world (X, Y); rows grow southwards
+                [t[3] - t[0] * s[0], s[0], 0.0, t[4] + t[1] * s[1], 0.0, -s[1]]
+            }
+            _ => return None,
+        };
+        if transform.iter().any(|v| !v.is_finite()) {
+            return None;
+        }
+
+        let key = |id: u16| geo_key(keys, id);
+        let crs = if key(GT_MODEL_TYPE) == Some(2) || (key(GT_MODEL_TYPE).is_none() && key(GEOGRAPHIC_TYPE).is_some() && key(PROJECTED_CS_TYPE).is_none()) {
+            Crs::Geographic { epsg: key(GEOGRAPHIC_TYPE) }
+        } else {
+            let metres_per_unit = match key(PROJ_LINEAR_UNITS) {
+                Some(9001) => Some(1.0),
+                Some(9002) => Some(0.3048),
+                Some(9003) => Some(1200.0 / 3937.0),
+                // UTM and most other EPSG projected systems are in metres
+                None if key(PROJECTED_CS_TYPE).is_some() => Some(1.0),
+                _ => None,
+            };
+            Crs::Projected { epsg: key(PROJECTED_CS_TYPE), metres_per_unit }
+        };
+        Some(Self { transform, crs })
+    }
+
+    /// World coordinates of a point in image pixels.
+    pub fn pixel_to_world(&self, x: f64, y: f64) -> (f64, f64) {
+        let t = &self.transform;
+        (t[0] + t[1] * x + t[2] * y, t[3] + t[4] * x + t[5] * y)
+    }
+
+    /// Coordinates for the status line, e.g. `47.37690°N 8.54170°E`.
+    pub fn format(&self, x: f64, y: f64) -> String {
+        let (wx, wy) = self.pixel_to_world(x, y);
+        match self.crs {
+            Crs::Geographic { .. } => format!(
+                "{:.5}°{} {:.5}°{}",
+                wy.abs(),
+                if wy >= 0.0 { 'N' } else { 'S' },
+                wx.abs(),
+                if wx >= 0.0 { 'E' } else { 'W' }
+            ),
+            Crs::Projected { epsg, .. } => {
+                let mut text = format!("E {:.1} N {:.1}", wx, wy);
+                if let Some(code) = epsg {
+                    text.push_str(&format!(" (EPSG:{})", code));
+                }
+                text
+            }
+        }
+    }
+
+    /// Ground distance covered by one image pixel horizontally at pixel (`x`, `y`).
+    pub fn metres_per_pixel(&self, x: f64, y: f64) -> Option<f64> {
+        let t = &self.transform;
+        let units = t[1].hypot(t[4]);
+        match self.crs {
+            Crs::Geographic { .. } => {
+                let (_, lat) = self.pixel_to_world(x, y);
+                Some(units * METRES_PER_DEGREE * lat.to_radians().cos())
+            }
+            Crs::Projected { metres_per_unit, .. } => metres_per_unit.map(|m| units * m),
+        }
+    }
+}
+
+/// Value of a GeoKey stored directly in the directory (not in the double or ASCII params).
+fn geo_key(keys: &[u16], id: u16) -> Option<u16> {
+    let count = *keys.get(3)? as usize;
+    keys.get(4..)?
+        .chunks_exact(4)
+        .take(count)
+        .find(|entry| entry[0] == id && entry[1] == 0)
+        .map(|entry| entry[3])
+}
+
+/// A round scale bar length no longer than `max_pixels` on screen, with its label.
+pub fn scale_bar(metres_per_screen_pixel: f64, max_pixels: f64) -> Option<(f64, String)> {
+    if !(metres_per_screen_pixel.is_finite() && metres_per_screen_pixel > 0.0) {
+        return None;
+    }
+    let max_metres = metres_per_screen_pixel * max_pixels;
+    let magnitude = 10f64.powf(max_metres.log10().floor());
+    let metres = [5.0, 2.0, 1.0].into_iter().map(|m| m * magnitude).find(|&m| m <= max_metres)?;
+    let label = if metres >= 1000.0 {
+        format!("{} km", metres / 1000.0)
+    } else if metres >= 1.0 {
+        format!("{} m", metres)
+    } else {
+        format!("{} cm", (metres * 100.0 * 1000.0).round() / 1000.0)
+    };
+    Some((metres / metres_per_screen_pixel, label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    // Version 1.1.0 header with UTM zone 32N and metres
+    const UTM_KEYS: [u16; 12] = [1, 1, 0, 2, GT_MODEL_TYPE, 0, 1, 1, PROJECTED_CS_TYPE, 0, 1, 32632];
+
+    #[test]
+    fn test_tiepoint_transform() {
+        let geo = GeoInfo::from_tags(None, Some(&[10.0, 10.0, 0.0]), Some(&[0.0, 0.0, 0.0, 500_000.0, 5_200_000.0, 0.0]), &UTM_KEYS).unwrap();
+        assert_eq!(geo.pixel_to_world(100.0, 50.0), (501_000.0, 5_199_500.0));
+        assert_eq!(geo.format(100.0, 50.0), "E 501000.0 N 5199500.0 (EPSG:32632)");
+        assert_eq!(geo.metres_per_pixel(0.0, 0.0), Some(10.0));
+
+        assert!(GeoInfo::from_tags(None, Some(&[10.0, 10.0, 0.0]), None, &UTM_KEYS).is_none());
+    }
+
+    #[test]
+    fn test_geographic() {
+        let keys = [1, 1, 0, 2, GT_MODEL_TYPE, 0, 1, 2, GEOGRAPHIC_TYPE, 0, 1, 4326];
+        let matrix = [0.001, 0.0, 0.0, -74.0, 0.0, -0.001, 0.0, 41.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+        let geo = GeoInfo::from_tags(Some(&matrix), None, None, &keys).unwrap();
+        assert_eq!(geo.crs, Crs::Geographic { epsg: Some(4326) });
+        assert_eq!(geo.format(500.0, 1000.0), "40.00000°N 73.50000°W");
+        // A thousandth of a degree of longitude at 40° north is about 85 m
+        let m = geo.metres_per_pixel(0.0, 1000.0).unwrap();
+        assert!((m - 85.28).abs() < 0.1, "{}", m);
+    }
+
+    #[test]
+    fn test_scale_bar() {
+        assert_eq!(scale_bar(10.0, 150.0), Some((100.0, "1 km".to_string())));
+        // 450 m fit, so the bar rounds down to 200 m
+        assert_eq!(scale_bar(3.0, 150.0), Some((200.0 / 3.0, "200 m".to_string())));
+        assert_eq!(scale_bar(0.001, 150.0).unwrap().1, "10 cm");
+        assert_eq!(scale_bar(0.0, 150.0), None);
+    }
+
+    #[test]
+    fn test_read_geotiff() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        let dir = scratch_dir("geotiff");
+        let path = dir.join("dem.tif");
+        {
+            let file = std::fs::File::create(&path).unwrap();
+            let mut tiff = TiffEncoder::new(file).unwrap();
+            let mut image = tiff.new_image::<colortype::Gray8>(4, 4).unwrap();
+            image.encoder().write_tag(Tag::ModelPixelScaleTag, &[2.0f64, 2.0, 0.0][..]).unwrap();
+            image.encoder().write_tag(Tag::ModelTiepointTag, &[0.0f64, 0.0, 0.0, 1000.0, 2000.0, 0.0][..]).unwrap();
+            image.encoder().write_tag(Tag::GeoKeyDirectoryTag, &UTM_KEYS[..]).unwrap();
+            image.write_data(&[0u8; 16]).unwrap();
+        }
+
+        let geo = GeoInfo::read(&path).unwrap();
+        assert_eq!(geo.pixel_to_world(1.0, 1.0), (1002.0, 1998.0));
+        assert!(GeoInfo::read(&dir.join("missing.tif")).is_none());
+    }
+}
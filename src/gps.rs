@@ -0,0 +1,79 @@
+//! Camera positions from EXIF GPS tags.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Latitude and longitude in degrees from an EXIF map as built by the loader,
+/// where positions read like `50 deg 3 min 12.34 sec N`.
+pub fn position(exif: &HashMap<String, String>) -> Option<(f64, f64)> {
+    let lat = degrees(exif.get("GPSLatitude")?, 'N', 'S')?;
+    let lon = degrees(exif.get("GPSLongitude")?, 'E', 'W')?;
+    // Cameras without a fix write zeros
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) || (lat == 0.0 && lon == 0.0) {
+        return None;
+    }
+    Some((lat, lon))
+}
+
+/// Position of a file without decoding it.
+pub fn read_position(path: &Path) -> Option<(f64, f64)> {
+    position(&crate::loader::read_exif(path))
+}
+
+/// "40.51000° N, 73.96667° W" for the title bar.
+pub fn format(lat: f64, lon: f64) -> String {
+    let hemisphere = |v: f64, positive, negative| if v < 0.0 { negative } else { positive };
+    format!("{:.5}° {}, {:.5}° {}", lat.abs(), hemisphere(lat, 'N', 'S'), lon.abs(), hemisphere(lon, 'E', 'W'))
+}
+
+/// Signed decimal degrees, "40.51000, -73.96667", as map sites and apps accept when pasted.
+pub fn decimal(lat: f64, lon: f64) -> String {
+    format!("{:.5}, {:.5}", lat, lon)
+}
+
+/// The position on OpenStreetMap, with a marker.
+pub fn map_url(lat: f64, lon: f64) -> String {
+    format!("https://www.openstreetmap.org/?mlat={lat:.5}&mlon={lon:.5}#map=16/{lat:.5}/{lon:.5}")
+}
+
+fn degrees(text: &str, positive: char, negative: char) -> Option<f64> {
+    let parts: Vec<&str> = text.split_whitespace().collect();
+    let [d, "deg", m, "min", s, "sec", rest @ ..] = parts.as_slice() else {
+        return None;
+    };
+    let value = d.parse::<f64>().ok()? + m.parse::<f64>().ok()? / 60.0 + s.parse::<f64>().ok()? / 3600.0;
+    match rest.first().and_then(|r| r.chars().next()) {
+        Some(c) if c == negative => Some(-value),
+        Some(c) if c == positive => Some(value),
+        _ => None,
+    }
+    .filter(|v| v.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exif(lat: &str, lon: &str) -> HashMap<String, String> {
+        HashMap::from([
+            ("GPSLatitude".to_string(), lat.to_string()),
+            ("GPSLongitude".to_string(), lon.to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_position() {
+        let (lat, lon) = position(&exif("40 deg 30 min 36 sec N", "73 deg 58 min 0 sec W")).unwrap();
+        assert!((lat - 40.51).abs() < 1e-9);
+        assert!((lon + 73.966_666).abs() < 1e-5);
+        assert!(position(&exif("33 deg 51 min 0 sec S", "151 deg 12 min 0 sec E")).unwrap().0 < 0.0);
+
+        assert_eq!(position(&exif("0 deg 0 min 0 sec N", "0 deg 0 min 0 sec E")), None);
+        assert_eq!(position(&exif("40 deg 30 min 36 sec", "73 deg 58 min 0 sec W")), None);
+        assert_eq!(position(&HashMap::new()), None);
+
+        assert_eq!(format(lat, lon), "40.51000° N, 73.96667° W");
+        assert_eq!(decimal(lat, lon), "40.51000, -73.96667");
+        assert_eq!(map_url(-33.85, 151.2), "https://www.openstreetmap.org/?mlat=-33.85000&mlon=151.20000#map=16/-33.85000/151.20000");
+    }
+}
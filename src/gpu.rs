@@ -0,0 +1,185 @@
+//! Choosing what to render with. The platform's native API comes first, then OpenGL
+//! for old drivers, then a software adapter wgpu can find (WARP, llvmpipe,
+//! SwiftShader). When none of them works the viewer falls back to drawing on the CPU,
+//! see `software`.
+
+use anyhow::{anyhow, Result};
+use winit::window::Window;
+
+/// The backends tried in order, and whether to ask for a software adapter.
+const ATTEMPTS: [(&str, wgpu::Backends, bool); 3] = [
+    ("native", wgpu::Backends::PRIMARY, false),
+    ("OpenGL", wgpu::Backends::GL, false),
+    ("software", wgpu::Backends::all(), true),
+];
+
+pub struct Gpu<'w> {
+    pub surface: wgpu::Surface<'w>,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+/// A device that can present to `window`, from the first backend that provides one.
+pub async fn init(window: &Window) -> Result<Gpu<'_>> {
+    let mut errors = Vec::new();
+    for (name, backends, fallback) in ATTEMPTS {
+        match attempt(window, backends, fallback).await {
+            Ok(gpu) => {
+                let renderer = describe(&gpu.adapter.get_info());
+                log::info!("Rendering with {}", renderer);
+                crate::crash::set_renderer(renderer);
+                return Ok(gpu);
+            }
+            Err(e) => errors.push(format!("{}: {:#}", name, e)),
+        }
+    }
+    Err(anyhow!("No usable GPU adapter ({})", errors.join("; ")))
+}
+
+async fn attempt(window: &Window, backends: wgpu::Backends, fallback: bool) -> Result<Gpu<'_>> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { backends, ..Default::default() });
+    let surface = instance.create_surface(window)?;
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface),
+            force_fallback_adapter: fallback,
+        })
+        .await
+        .ok_or_else(|| anyhow!("no adapter"))?;
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+            },
+            None,
+        )
+        .await?;
+    Ok(Gpu { surface, adapter, device, queue })
+}
+
+/// A device that needn't present anywhere, e.g. to time uploads without a window.
+pub async fn headless() -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        })
+        .await
+        .ok_or_else(|| anyhow!("No usable GPU adapter"))?;
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+            },
+            None,
+        )
+        .await?;
+    Ok((adapter, device, queue))
+}
+
+/// "Vulkan: NVIDIA GeForce RTX 3060 (NVIDIA 535.104)", for diagnostics.
+pub fn describe(info: &wgpu::AdapterInfo) -> String {
+    let backend = match info.backend {
+        wgpu::Backend::Vulkan => "Vulkan",
+        wgpu::Backend::Metal => "Metal",
+        wgpu::Backend::Dx12 => "Direct3D 12",
+        wgpu::Backend::Gl => "OpenGL",
+        wgpu::Backend::BrowserWebGpu => "WebGPU",
+        wgpu::Backend::Empty => "None",
+    };
+    let software = match info.device_type {
+        wgpu::DeviceType::Cpu => ", software",
+        _ => "",
+    };
+    match (info.driver.as_str(), info.driver_info.as_str()) {
+        ("", "") => format!("{}: {}{}", backend, info.name, software),
+        (driver, "") | ("", driver) => format!("{}: {} ({}{})", backend, info.name, driver, software),
+        (driver, version) => format!("{}: {} ({} {}{})", backend, info.name, driver, version, software),
+    }
+}
+
+/// The present mode for `wanted`, or vsync, which every surface supports, when
+/// `supported` lacks it.
+pub fn present_mode(wanted: crate::config::PresentMode, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+    use crate::config::PresentMode;
+    let mode = match wanted {
+        PresentMode::Fifo => wgpu::PresentMode::Fifo,
+        PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+        PresentMode::Immediate => wgpu::PresentMode::Immediate,
+    };
+    if supported.contains(&mode) {
+        mode
+    } else {
+        wgpu::PresentMode::Fifo
+    }
+}
+
+/// The supported one of vsync, mailbox and immediate following `current`.
+pub fn next_present_mode(current: wgpu::PresentMode, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+    const CYCLE: [wgpu::PresentMode; 3] = [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox, wgpu::PresentMode::Immediate];
+    let at = CYCLE.iter().position(|m| *m == current).unwrap_or(0);
+    (1..=CYCLE.len())
+        .map(|step| CYCLE[(at + step) % CYCLE.len()])
+        .find(|m| *m == wgpu::PresentMode::Fifo || supported.contains(m))
+        .unwrap_or(wgpu::PresentMode::Fifo)
+}
+
+pub fn describe_present_mode(mode: wgpu::PresentMode) -> &'static str {
+    match mode {
+        wgpu::PresentMode::Fifo | wgpu::PresentMode::AutoVsync => "vsync",
+        wgpu::PresentMode::FifoRelaxed => "adaptive vsync",
+        wgpu::PresentMode::Mailbox => "mailbox",
+        wgpu::PresentMode::Immediate | wgpu::PresentMode::AutoNoVsync => "immediate",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_adapter() {
+        let mut info = wgpu::AdapterInfo {
+            name: "llvmpipe (LLVM 15.0.7, 256 bits)".to_string(),
+            vendor: 0x10005,
+            device: 0,
+            device_type: wgpu::DeviceType::Cpu,
+            driver: "llvmpipe".to_string(),
+            driver_info: "Mesa 23.2.1".to_string(),
+            backend: wgpu::Backend::Vulkan,
+        };
+        assert_eq!(describe(&info), "Vulkan: llvmpipe (LLVM 15.0.7, 256 bits) (llvmpipe Mesa 23.2.1, software)");
+        info.device_type = wgpu::DeviceType::DiscreteGpu;
+        info.backend = wgpu::Backend::Gl;
+        info.driver.clear();
+        assert_eq!(describe(&info), "OpenGL: llvmpipe (LLVM 15.0.7, 256 bits) (Mesa 23.2.1)");
+        info.driver_info.clear();
+        assert_eq!(describe(&info), "OpenGL: llvmpipe (LLVM 15.0.7, 256 bits)");
+    }
+
+    #[test]
+    fn test_present_modes() {
+        use crate::config::PresentMode;
+        use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+        // Drivers list immediate first; it is only used when asked for
+        let supported = [Immediate, Fifo, Mailbox];
+        assert_eq!(present_mode(PresentMode::Fifo, &supported), Fifo);
+        assert_eq!(present_mode(PresentMode::Mailbox, &supported), Mailbox);
+        assert_eq!(present_mode(PresentMode::Mailbox, &[Immediate, Fifo]), Fifo);
+
+        assert_eq!(next_present_mode(Fifo, &supported), Mailbox);
+        assert_eq!(next_present_mode(Mailbox, &supported), Immediate);
+        assert_eq!(next_present_mode(Immediate, &supported), Fifo);
+        // Unsupported modes are skipped
+        assert_eq!(next_present_mode(Fifo, &[Fifo, Immediate]), Immediate);
+        assert_eq!(next_present_mode(Fifo, &[Fifo]), Fifo);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::quad::Rect;
+
+/// Cell size in logical pixels
+pub const CELL: f32 = 180.0;
+const PADDING: f32 = 12.0;
+const STRIDE: f32 = CELL + PADDING;
+
+/// Height of a group's title row in logical pixels
+const HEADER: f32 = 36.0;
+
+/// A title over the cells from `first` on, up to the next header's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub first: usize,
+    pub title: String,
+}
+
+/// Full-window thumbnail grid of the current folder.
+pub struct Grid {
+    pub visible: bool,
+    pub selected: usize,
+    pub scroll: f32,
+    /// Titles splitting the cells into groups, in order; none for a plain grid
+    pub headers: Vec<Header>,
+}
+
+/// Rows of cells as index ranges, and headers as indices into `Grid::headers`, each
+/// with its top before scrolling.
+struct Lines {
+    rows: Vec<(std::ops::Range<usize>, f32)>,
+    headers: Vec<(usize, f32)>,
+    height: f32,
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Grid {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            selected: 0,
+            scroll: 0.0,
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn columns(width: f32) -> usize {
+        (((width - PADDING) / STRIDE).floor() as usize).max(1)
+    }
+
+    /// Every row from the top: each group starts on a row of its own under its header.
+    fn lines(&self, width: f32, count: usize) -> Lines {
+        let columns = Self::columns(width);
+        let mut groups: Vec<(Option<usize>, std::ops::Range<usize>)> = Vec::new();
+        let first = self.headers.first().map_or(count, |h| h.first.min(count));
+        if first > 0 {
+            groups.push((None, 0..first));
+        }
+        for (i, header) in self.headers.iter().enumerate() {
+            let end = self.headers.get(i + 1).map_or(count, |next| next.first).min(count);
+            groups.push((Some(i), header.first.min(count)..end));
+        }
+        let mut lines = Lines { rows: Vec::new(), headers: Vec::new(), height: PADDING };
+        for (header, range) in groups {
+            if let Some(header) = header {
+                lines.headers.push((header, lines.height));
+                lines.height += HEADER;
+            }
+            for start in range.clone().step_by(columns) {
+                lines.rows.push((start..(start + columns).min(range.end), lines.height));
+                lines.height += STRIDE;
+            }
+        }
+        lines
+    }
+
+    /// Left edge of the first column, centering the columns in the window.
+    fn left(width: f32) -> f32 {
+        let used = Self::columns(width) as f32 * STRIDE - PADDING;
+        ((width - used) / 2.0).max(PADDING)
+    }
+
+    fn cell_rect(&self, index: usize, width: f32, lines: &Lines) -> Rect {
+        let row = lines.rows.partition_point(|(cells, _)| cells.end <= index).min(lines.rows.len().saturating_sub(1));
+        let (start, top) = lines.rows.get(row).map_or((0, PADDING), |(cells, top)| (cells.start, *top));
+        let col = index.saturating_sub(start);
+        Rect::new(Self::left(width) + col as f32 * STRIDE, top - self.scroll, CELL, CELL)
+    }
+
+    /// Cells intersecting the window, as (index, rectangle) pairs.
+    pub fn layout(&self, width: f32, height: f32, count: usize) -> Vec<(usize, Rect)> {
+        let lines = self.lines(width, count);
+        // A row below the window too, so its thumbnails are ready when scrolled to
+        (lines.rows.iter())
+            .filter(|(_, top)| *top + STRIDE > self.scroll && *top < self.scroll + height + STRIDE)
+            .flat_map(|(cells, _)| cells.clone())
+            .map(|i| (i, self.cell_rect(i, width, &lines)))
+            .collect()
+    }
+
+    /// Headers intersecting the window, with the rectangle of their title row.
+    pub fn header_rects(&self, width: f32, height: f32, count: usize) -> Vec<(&Header, Rect)> {
+        let used = Self::columns(width) as f32 * STRIDE - PADDING;
+        (self.lines(width, count).headers.into_iter())
+            .filter(|(_, top)| *top + HEADER > self.scroll && *top < self.scroll + height)
+            .map(|(i, top)| (&self.headers[i], Rect::new(Self::left(width), top - self.scroll, used, HEADER)))
+            .collect()
+    }
+
+    /// Move the selection by whole cells, clamped to the list. Moving by rows keeps the
+    /// column, or goes to the last cell of a shorter row.
+    pub fn move_selection(&mut self, dx: isize, dy: isize, width: f32, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.selected = (self.selected as isize + dx).clamp(0, count as isize - 1) as usize;
+        if dy == 0 {
+            return;
+        }
+        let rows = self.lines(width, count).rows;
+        let row = rows.partition_point(|(cells, _)| cells.end <= self.selected);
+        let col = self.selected - rows[row].0.start;
+        let (target, _) = &rows[(row as isize + dy).clamp(0, rows.len() as isize - 1) as usize];
+        self.selected = (target.start + col).min(target.end - 1);
+    }
+
+    /// Scroll so that the selected cell is fully on screen, with its header if it is the
+    /// first of a group.
+    pub fn ensure_visible(&mut self, width: f32, height: f32, count: usize) {
+        let rect = self.cell_rect(self.selected, width, &self.lines(width, count));
+        let top = match self.headers.iter().any(|h| h.first == self.selected) {
+            true => rect.y - HEADER,
+            false => rect.y,
+        };
+        if top < PADDING {
+            self.scroll += top - PADDING;
+        } else if rect.y + rect.h > height - PADDING {
+            self.scroll += rect.y + rect.h - (height - PADDING);
+        }
+        self.scroll = self.scroll.max(0.0);
+    }
+
+    pub fn scroll_by(&mut self, delta: f32, width: f32, height: f32, count: usize) {
+        let max_scroll = (self.lines(width, count).height - height).max(0.0);
+        self.scroll = (self.scroll + delta).clamp(0.0, max_scroll);
+    }
+
+    /// Number of cells per screenful, for page-wise movement.
+    pub fn rows_per_page(height: f32) -> isize {
+        ((height / STRIDE).floor() as isize).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_selection() {
+        let width = 1000.0;
+        let columns = Grid::columns(width);
+        assert_eq!(columns, 5);
+
+        let mut grid = Grid::new();
+        grid.move_selection(1, 0, width, 12);
+        assert_eq!(grid.selected, 1);
+        grid.move_selection(0, 1, width, 12);
+        assert_eq!(grid.selected, 6);
+        grid.move_selection(0, 5, width, 12);
+        assert_eq!(grid.selected, 11);
+        grid.move_selection(-100, 0, width, 12);
+        assert_eq!(grid.selected, 0);
+    }
+
+    #[test]
+    fn test_ensure_visible_and_layout() {
+        let (width, height) = (1000.0, 600.0);
+        let mut grid = Grid::new();
+        grid.selected = 49;
+        grid.ensure_visible(width, height, 50);
+        assert!(grid.scroll > 0.0);
+
+        let cells = grid.layout(width, height, 50);
+        assert!(cells.iter().any(|(i, _)| *i == 49));
+        assert!(!cells.iter().any(|(i, _)| *i == 0));
+    }
+
+    #[test]
+    fn test_headers() {
+        let (width, height) = (1000.0, 600.0);
+        let mut grid = Grid::new();
+        let header = |first, title: &str| Header { first, title: title.to_string() };
+        grid.headers = vec![header(0, "21 June 2024 (7)"), header(7, "22 June 2024 (3)")];
+        // Five columns: the first group takes two rows, the second starts a row of its own
+        let cells = grid.layout(width, height, 10);
+        let rect = |i: usize| cells.iter().find(|(j, _)| *j == i).unwrap().1;
+        assert_eq!(rect(0).y, PADDING + HEADER);
+        assert_eq!(rect(5).y, PADDING + HEADER + STRIDE);
+        assert_eq!(rect(7).x, rect(0).x);
+        assert_eq!(rect(7).y, PADDING + 2.0 * HEADER + 2.0 * STRIDE);
+        let headers = grid.header_rects(width, height, 10);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[1].1.y, rect(7).y - HEADER);
+
+        // Down from the second column of the short row lands in the next group's
+        grid.selected = 6;
+        grid.move_selection(0, 1, width, 10);
+        assert_eq!(grid.selected, 8);
+        grid.move_selection(0, -1, width, 10);
+        assert_eq!(grid.selected, 6);
+        // and from the fifth column, on the last cell of a shorter row
+        grid.selected = 4;
+        grid.move_selection(0, 1, width, 10);
+        assert_eq!(grid.selected, 6);
+
+        grid.scroll_by(10_000.0, width, 300.0, 10);
+        assert_eq!(grid.scroll, PADDING + 2.0 * HEADER + 3.0 * STRIDE - 300.0);
+        // Scrolled past the first cell of a group, its header comes back into view too
+        grid.scroll = rect(7).y;
+        grid.selected = 7;
+        grid.ensure_visible(width, 300.0, 10);
+        assert_eq!(grid.scroll, rect(7).y - HEADER - PADDING);
+    }
+}
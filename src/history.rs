@@ -0,0 +1,293 @@
+//! Undo and redo for what the viewer changes on disk: moves and renames, copies
+//! sent to culling folders, ratings, rotations and deletions while the file is
+//! still in the trash. Reverting a change performs the opposite file operation
+//! and yields the change that reverts it back, so undo and redo share one path.
+
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+/// Changes kept for undo; older ones are forgotten
+const MAX_CHANGES: usize = 200;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Files moved or renamed, as (from, to) in the order they were done
+    Moved(Vec<(PathBuf, PathBuf)>),
+    /// Copies made, as (source, copy)
+    Copied(Vec<(PathBuf, PathBuf)>),
+    /// Copies removed again, as (source, copy)
+    Uncopied(Vec<(PathBuf, PathBuf)>),
+    /// Rating and pick flag of an image, before and after
+    Rated { path: PathBuf, from: (i32, bool), to: (i32, bool) },
+    /// A JPEG turned clockwise on disk
+    Rotated { path: PathBuf, quarter_turns: u32 },
+    Trashed(PathBuf),
+    Restored(PathBuf),
+    /// Changes made together, such as an image moved and its shared sidecar copied,
+    /// undone and redone as one
+    Batch(Vec<Change>),
+}
+
+impl Change {
+    /// Undo the change on disk. Returns the change that redoes it. A change of several
+    /// files that fails partway is rolled back, so it still describes the files.
+    pub fn revert(&self) -> Result<Change> {
+        Ok(match self {
+            Change::Moved(moves) => {
+                let back: Vec<_> = moves.iter().rev().map(|(from, to)| (to.clone(), from.clone())).collect();
+                each(&back, |(from, to)| crate::journal::move_file(from, to), |(from, to)| crate::journal::move_file(to, from))?;
+                Change::Moved(back)
+            }
+            Change::Copied(copies) => {
+                each(copies, remove_copy, make_copy)?;
+                Change::Uncopied(copies.clone())
+            }
+            Change::Uncopied(copies) => {
+                if let Some((_, copy)) = copies.iter().find(|(_, copy)| copy.exists()) {
+                    return Err(anyhow!("{:?} already exists", copy));
+                }
+                each(copies, make_copy, remove_copy)?;
+                Change::Copied(copies.clone())
+            }
+            Change::Rated { path, from, to } => {
+                crate::xmp::write_rating(path, from.0, from.1)?;
+                Change::Rated { path: path.clone(), from: *to, to: *from }
+            }
+            Change::Rotated { path, quarter_turns } => {
+                let back = (4 - quarter_turns % 4) % 4;
+                crate::rotate::save(path, back)?;
+                Change::Rotated { path: path.clone(), quarter_turns: back }
+            }
+            Change::Trashed(path) => {
+                restore_from_trash(path)?;
+                Change::Restored(path.clone())
+            }
+            Change::Restored(path) => {
+                trash::delete(path)?;
+                Change::Trashed(path.clone())
+            }
+            Change::Batch(changes) => {
+                // Reverted latest first; reverting what comes back redoes them in order
+                let mut back = Vec::new();
+                for change in changes.iter().rev() {
+                    match change.revert() {
+                        Ok(redo) => back.push(redo),
+                        Err(e) => return Err(roll_back(&back, e)),
+                    }
+                }
+                Change::Batch(back)
+            }
+        })
+    }
+
+    /// The image to show once this change is done: where a moved image went,
+    /// or the image rated, rotated or brought back.
+    pub fn shown(&self) -> Option<&Path> {
+        match self {
+            Change::Moved(moves) => moves.first().map(|(_, to)| to.as_path()),
+            Change::Rated { path, .. } | Change::Rotated { path, .. } | Change::Restored(path) => Some(path),
+            Change::Copied(_) | Change::Uncopied(_) | Change::Trashed(_) => None,
+            Change::Batch(changes) => changes.iter().find_map(Change::shown),
+        }
+    }
+
+    /// The files this change moved, as (from, to), for the library's journal.
+    pub fn moves(&self) -> Vec<(PathBuf, PathBuf)> {
+        match self {
+            Change::Moved(moves) => moves.clone(),
+            Change::Batch(changes) => changes.iter().flat_map(Change::moves).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// What the title bar says once the change is undone or redone.
+    pub fn describe(&self) -> String {
+        let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        match self {
+            Change::Moved(moves) => match moves.first() {
+                Some((from, to)) if from.parent() == to.parent() => format!("rename of {} to {}", name(from), name(to)),
+                Some((from, to)) => format!("move of {} to {}", name(from), to.parent().map(name).unwrap_or_default()),
+                None => "move".to_string(),
+            },
+            Change::Copied(copies) | Change::Uncopied(copies) => {
+                format!("copy of {}", copies.first().map(|(source, _)| name(source)).unwrap_or_default())
+            }
+            Change::Rated { path, .. } => format!("rating of {}", name(path)),
+            Change::Rotated { path, .. } => format!("rotation of {}", name(path)),
+            Change::Trashed(path) | Change::Restored(path) => format!("deletion of {}", name(path)),
+            Change::Batch(changes) => changes.first().map_or("change".to_string(), Change::describe),
+        }
+    }
+}
+
+fn make_copy((source, copy): &(PathBuf, PathBuf)) -> Result<()> {
+    std::fs::copy(source, copy)?;
+    Ok(())
+}
+
+fn remove_copy((_, copy): &(PathBuf, PathBuf)) -> Result<()> {
+    Ok(std::fs::remove_file(copy)?)
+}
+
+/// Do `step` for each pair in turn. When one fails, `undo` those done, latest first, as
+/// `journal::undo_last` does, so that all or nothing is done.
+fn each(
+    pairs: &[(PathBuf, PathBuf)],
+    step: impl Fn(&(PathBuf, PathBuf)) -> Result<()>,
+    undo: impl Fn(&(PathBuf, PathBuf)) -> Result<()>,
+) -> Result<()> {
+    for (i, pair) in pairs.iter().enumerate() {
+        let Err(e) = step(pair) else {
+            continue;
+        };
+        for done in pairs[..i].iter().rev() {
+            if let Err(back) = undo(done) {
+                return Err(e.context(format!(
+                    "Failed halfway, and the change to {} couldn't be taken back: {:#}",
+                    done.1.display(),
+                    back
+                )));
+            }
+        }
+        return Err(e.context("Nothing was changed"));
+    }
+    Ok(())
+}
+
+/// Revert `done`, the changes made before one failed with `e`, latest first.
+fn roll_back(done: &[Change], e: anyhow::Error) -> anyhow::Error {
+    for change in done.iter().rev() {
+        if let Err(back) = change.revert() {
+            return e.context(format!("Failed halfway, and the {} couldn't be taken back: {:#}", change.describe(), back));
+        }
+    }
+    e.context("Nothing was changed")
+}
+
+/// Bring `path` back from the trash, the most recently deleted copy if there are several.
+#[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+fn restore_from_trash(path: &Path) -> Result<()> {
+    let item = trash::os_limited::list()?
+        .into_iter()
+        .filter(|item| item.original_path() == path)
+        .max_by_key(|item| item.time_deleted)
+        .ok_or_else(|| anyhow!("{:?} is no longer in the trash", path))?;
+    trash::os_limited::restore_all([item])?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "windows", all(unix, not(target_os = "macos")))))]
+fn restore_from_trash(path: &Path) -> Result<()> {
+    Err(anyhow!("Restoring {:?} from the trash isn't supported on this system", path))
+}
+
+/// Changes that can be undone, and the undone ones that can be redone.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    /// Remember a change just made; anything undone before can no longer be redone.
+    pub fn push(&mut self, change: Change) {
+        self.redo.clear();
+        self.undo.push(change);
+        if self.undo.len() > MAX_CHANGES {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Revert the latest change, or with `redo` the latest undone one. Returns the
+    /// change reverted and the one now in effect, or None with nothing to do. A
+    /// change that fails to revert stays where it was.
+    pub fn step(&mut self, redo: bool) -> Result<Option<(Change, Change)>> {
+        let (from, to) = if redo { (&mut self.redo, &mut self.undo) } else { (&mut self.undo, &mut self.redo) };
+        let Some(change) = from.pop() else {
+            return Ok(None);
+        };
+        match change.revert() {
+            Ok(reverse) => {
+                to.push(reverse.clone());
+                Ok(Some((change, reverse)))
+            }
+            Err(e) => {
+                from.push(change);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_undo_redo() {
+        let dir = scratch_dir("history");
+        std::fs::create_dir_all(dir.join("keep")).unwrap();
+        let (a, b, copy) = (dir.join("a.jpg"), dir.join("b.jpg"), dir.join("keep").join("a.jpg"));
+        std::fs::write(&a, b"jpeg").unwrap();
+
+        let mut history = History::default();
+        crate::journal::move_file(&a, &b).unwrap();
+        history.push(Change::Moved(vec![(a.clone(), b.clone())]));
+        std::fs::copy(&b, &copy).unwrap();
+        history.push(Change::Copied(vec![(b.clone(), copy.clone())]));
+
+        let (_, uncopied) = history.step(false).unwrap().unwrap();
+        assert_eq!(uncopied, Change::Uncopied(vec![(b.clone(), copy.clone())]));
+        assert!(!copy.exists());
+        let (undone, now) = history.step(false).unwrap().unwrap();
+        assert_eq!(undone.describe(), "rename of a.jpg to b.jpg");
+        assert_eq!(now.shown(), Some(a.as_path()));
+        assert!(a.exists() && !b.exists());
+        assert_eq!(history.step(false).unwrap(), None);
+
+        // Redo goes forward again in order
+        history.step(true).unwrap();
+        assert!(b.exists() && !a.exists());
+        // A new change drops what was left to redo
+        history.push(Change::Moved(vec![(b.clone(), a.clone())]));
+        assert_eq!(history.step(true).unwrap(), None);
+
+        // A revert that fails leaves the change to try again
+        let mut history = History::default();
+        history.push(Change::Moved(vec![(dir.join("gone.jpg"), dir.join("missing.jpg"))]));
+        assert!(history.step(false).is_err());
+        assert_eq!(history.undo.len(), 1);
+
+        // One that fails partway takes back what it did first
+        let (c, d) = (dir.join("c.jpg"), dir.join("d.jpg"));
+        std::fs::write(&d, b"jpeg").unwrap();
+        history.push(Change::Moved(vec![(dir.join("gone.jpg"), dir.join("missing.jpg")), (c.clone(), d.clone())]));
+        assert!(history.step(false).is_err());
+        assert!(d.exists() && !c.exists());
+        std::fs::copy(&d, &copy).unwrap();
+        history.push(Change::Copied(vec![(d.clone(), copy.clone()), (d.clone(), dir.join("keep").join("gone.jpg"))]));
+        assert!(history.step(false).is_err());
+        assert!(copy.exists());
+        assert_eq!(history.undo.len(), 3);
+
+        // A batch is undone latest first and redone in order, or not at all
+        let (e, e_copy, f) = (dir.join("e.jpg"), dir.join("keep").join("e.xmp"), dir.join("keep").join("e.jpg"));
+        std::fs::write(dir.join("e.xmp"), b"xmp").unwrap();
+        std::fs::write(&f, b"jpeg").unwrap();
+        std::fs::copy(dir.join("e.xmp"), &e_copy).unwrap();
+        let mut history = History::default();
+        let sent = Change::Batch(vec![Change::Moved(vec![(e.clone(), f.clone())]), Change::Copied(vec![(dir.join("e.xmp"), e_copy.clone())])]);
+        assert_eq!(sent.moves(), [(e.clone(), f.clone())]);
+        assert_eq!(sent.describe(), "move of e.jpg to keep");
+        history.push(sent);
+        let (_, now) = history.step(false).unwrap().unwrap();
+        assert!(e.exists() && !f.exists() && !e_copy.exists());
+        assert_eq!(now.shown(), Some(e.as_path()));
+        history.step(true).unwrap();
+        assert!(!e.exists() && f.exists() && e_copy.exists());
+        std::fs::write(&e, b"other").unwrap();
+        assert!(history.step(false).is_err());
+        assert!(f.exists() && e_copy.exists());
+    }
+}
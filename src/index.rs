@@ -0,0 +1,168 @@
+//! Read-ahead metadata for the files of the open folder: dimensions, capture
+//! date and the basic EXIF fields, read in the background and kept in the
+//! library database so sorting by capture date and file properties don't wait
+//! for an image to be opened. Entries are re-read when the file's modification
+//! time or size changes.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileInfo {
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: i64,
+    pub size: u64,
+    pub dimensions: Option<(u32, u32)>,
+    /// EXIF capture time as `YYYY-MM-DD HH:MM:SS`
+    pub taken: Option<String>,
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    /// Shutter speed, aperture and ISO, e.g. `1/250 s f/2.8 ISO 200`
+    pub exposure: Option<String>,
+    pub iso: Option<u32>,
+    pub position: Option<(f64, f64)>,
+    /// From the XMP sidecar, which changes without the image; read on every
+    /// request rather than stored
+    pub rating: Option<i32>,
+    pub pick: bool,
+}
+
+/// Modification time and size as stored in the index.
+pub fn stamp(path: &Path) -> Option<(i64, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64);
+    Some((mtime, meta.len()))
+}
+
+/// Read the metadata of one file without decoding its pixels.
+pub fn read(path: &Path) -> Option<FileInfo> {
+    let (mtime, size) = stamp(path)?;
+    let exif = crate::loader::read_exif(path);
+    let field = |key: &str| exif.get(key).map(|v| v.trim().trim_matches('"').trim().to_string()).filter(|v| !v.is_empty());
+
+    let taken = ["DateTimeOriginal", "DateTimeDigitized", "DateTime"]
+        .iter()
+        .find_map(|key| field(key))
+        .filter(|v| v.len() >= 19 && v.is_char_boundary(19))
+        .map(|v| v[..19].to_string());
+    let camera = match (field("Make"), field("Model")) {
+        (Some(make), Some(model)) if !model.starts_with(&make) => Some(format!("{} {}", make, model)),
+        (make, model) => model.or(make),
+    };
+    // Some cameras list several values; the first is the one shot at
+    let iso = field("PhotographicSensitivity").and_then(|v| v.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok());
+    let exposure: Vec<String> = [field("ExposureTime"), field("FNumber"), field("PhotographicSensitivity").map(|iso| format!("ISO {}", iso))]
+        .into_iter()
+        .flatten()
+        .collect();
+    // RAW files keep the full size in EXIF; the image crate knows the rest
+    let dimensions = image::image_dimensions(path).ok().or_else(|| {
+        let w = field("PixelXDimension")?.parse().ok()?;
+        let h = field("PixelYDimension")?.parse().ok()?;
+        Some((w, h))
+    });
+
+    Some(FileInfo {
+        mtime,
+        size,
+        dimensions,
+        taken,
+        camera,
+        lens: field("LensModel"),
+        exposure: (!exposure.is_empty()).then(|| exposure.join(" ")),
+        iso,
+        position: crate::gps::position(&exif),
+        rating: None,
+        pick: false,
+    })
+}
+
+/// Indexes files on a background thread, answering from the library database
+/// when the stored entry is still current.
+pub struct Indexer {
+    sender: Sender<PathBuf>,
+    receiver: Receiver<(PathBuf, FileInfo)>,
+    requested: HashSet<PathBuf>,
+}
+
+impl Default for Indexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Indexer {
+    pub fn new() -> Self {
+        let (sender, requests) = channel::<PathBuf>();
+        let (results, receiver) = channel();
+        std::thread::spawn(move || {
+            crate::priority::set_current(crate::priority::Priority::Background);
+            // A connection of its own; without a database everything is read afresh
+            let library = crate::library::Library::open().ok();
+            for path in requests {
+                crate::priority::yield_to_interactive();
+                let Some((mtime, size)) = stamp(&path) else {
+                    continue;
+                };
+                let stored = library.as_ref().and_then(|lib| lib.indexed_file(&path, mtime, size).ok().flatten());
+                let mut info = match stored {
+                    Some(info) => info,
+                    None => {
+                        let Some(info) = read(&path) else {
+                            continue;
+                        };
+                        if let Some(Err(e)) = library.as_ref().map(|lib| lib.store_file_info(&path, &info)) {
+                            log::warn!("Failed to index {:?}: {:?}", path, e);
+                        }
+                        info
+                    }
+                };
+                let xmp = crate::xmp::read_sidecar(&path);
+                (info.rating, info.pick) = (xmp.rating, xmp.pick);
+                if results.send((path, info)).is_err() {
+                    break;
+                }
+                crate::redraw::wake();
+            }
+        });
+        Self { sender, receiver, requested: HashSet::new() }
+    }
+
+    /// Queue files that were not asked for before.
+    pub fn request(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            if self.requested.insert(path.clone()) {
+                let _ = self.sender.send(path.clone());
+            }
+        }
+    }
+
+    /// Entries finished since the last call.
+    pub fn poll(&mut self) -> Vec<(PathBuf, FileInfo)> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_read() {
+        let dir = scratch_dir("index");
+        let path = dir.join("small.png");
+        image::RgbImage::new(7, 3).save(&path).unwrap();
+
+        let info = read(&path).unwrap();
+        assert_eq!(info.dimensions, Some((7, 3)));
+        assert_eq!(info.size, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(info.taken, None);
+        assert!(read(&dir.join("missing.png")).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use interprocess::local_socket::{prelude::*, GenericFilePath, GenericNamespaced, Listener, ListenerOptions, Name, Stream};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Longest forwarded path accepted, in bytes, in case something else connects
+const MAX_PATH_BYTES: usize = 64 * 1024;
+
+/// Outcome of looking for an already running viewer.
+pub enum Instance {
+    /// No other instance; paths opened by later invocations arrive on this listener.
+    Primary(Listener),
+    /// The path was handed to the running instance, this process can exit.
+    Forwarded,
+    /// Another instance is running but there was nothing to forward: run standalone.
+    Secondary,
+}
+
+/// Forward `path` to a running instance, or become the instance others forward to.
+pub fn claim(path: Option<&Path>) -> io::Result<Instance> {
+    claim_at(socket_name()?, path)
+}
+
+fn claim_at(name: Name<'static>, path: Option<&Path>) -> io::Result<Instance> {
+    if let Ok(mut stream) = Stream::connect(name.borrow()) {
+        let Some(path) = path else {
+            return Ok(Instance::Secondary);
+        };
+        // The running instance has a different working directory
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let bytes = path_bytes(&path);
+        stream.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        stream.write_all(&bytes)?;
+        return Ok(Instance::Forwarded);
+    }
+    // Nobody answered, so a leftover socket file is from a crashed instance
+    let listener = ListenerOptions::new().name(name).try_overwrite(true).create_sync()?;
+    Ok(Instance::Primary(listener))
+}
+
+/// Accept forwarded paths on a background thread for the lifetime of the process. Each
+/// connection is read on a thread of its own, so one that never sends doesn't hold up
+/// the next.
+pub fn serve(listener: Listener, on_open: impl Fn(PathBuf) + Send + Sync + 'static) {
+    let on_open = Arc::new(on_open);
+    std::thread::spawn(move || {
+        for conn in listener.incoming().filter_map(Result::ok) {
+            let on_open = on_open.clone();
+            std::thread::spawn(move || {
+                if let Some(path) = read_path(conn) {
+                    on_open(path);
+                }
+            });
+        }
+    });
+}
+
+/// The one path a connection sends: its length in bytes, then the bytes.
+fn read_path(mut conn: impl Read) -> Option<PathBuf> {
+    let mut len = [0; 4];
+    conn.read_exact(&mut len).ok()?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 || len > MAX_PATH_BYTES {
+        return None;
+    }
+    let mut bytes = vec![0; len];
+    conn.read_exact(&mut bytes).ok()?;
+    path_from_bytes(bytes)
+}
+
+/// A path as sent between instances: as is on Unix, where it may not be UTF-8, and as
+/// UTF-16 on Windows.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    Some(PathBuf::from(<std::ffi::OsString as std::os::unix::ffi::OsStringExt>::from_vec(bytes)))
+}
+
+#[cfg(windows)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    std::os::windows::ffi::OsStrExt::encode_wide(path.as_os_str()).flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(windows)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let wide: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    Some(PathBuf::from(<std::ffi::OsString as std::os::windows::ffi::OsStringExt>::from_wide(&wide)))
+}
+
+/// A per-user endpoint: a socket in the private runtime directory where there is
+/// one, otherwise a named pipe (Windows) or abstract socket tagged with the user.
+fn socket_name() -> io::Result<Name<'static>> {
+    if let Some(dir) = dirs::runtime_dir() {
+        return dir.join("momemtum.sock").to_fs_name::<GenericFilePath>();
+    }
+    if GenericNamespaced::is_supported() {
+        let user = std::env::var("USERNAME").or_else(|_| std::env::var("USER")).unwrap_or_default();
+        return format!("momemtum-{}.sock", user).to_ns_name::<GenericNamespaced>();
+    }
+    let dir = dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("momemtum");
+    std::fs::create_dir_all(&dir)?;
+    dir.join("instance.sock").to_fs_name::<GenericFilePath>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_forward_to_primary() {
+        let dir = scratch_dir("instance");
+        let image = dir.join("photo.jpg");
+        std::fs::write(&image, b"").unwrap();
+        let name = || dir.join("test.sock").to_fs_name::<GenericFilePath>().unwrap();
+
+        let Instance::Primary(listener) = claim_at(name(), None).unwrap() else {
+            panic!("first instance should become primary");
+        };
+        let (tx, rx) = mpsc::channel();
+        serve(listener, move |path| tx.send(path).unwrap());
+
+        assert!(matches!(claim_at(name(), None).unwrap(), Instance::Secondary));
+        assert!(matches!(claim_at(name(), Some(&image)).unwrap(), Instance::Forwarded));
+        let received = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received, std::fs::canonicalize(&image).unwrap());
+
+        // A connection that sends nothing doesn't hold up the next one
+        let _silent = Stream::connect(name()).unwrap();
+        assert!(matches!(claim_at(name(), Some(&image)).unwrap(), Instance::Forwarded));
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn test_path_bytes() {
+        let path = PathBuf::from("/photos/été/a\nb.jpg");
+        assert_eq!(path_from_bytes(path_bytes(&path)), Some(path.clone()));
+        let mut sent = (path_bytes(&path).len() as u32).to_le_bytes().to_vec();
+        sent.extend(path_bytes(&path));
+        assert_eq!(read_path(&sent[..]), Some(path));
+        assert_eq!(read_path(&[0, 0, 0, 0][..]), None);
+        assert_eq!(read_path(&[5, 0, 0, 0, b'a'][..]), None);
+        #[cfg(unix)]
+        {
+            let raw = PathBuf::from(<std::ffi::OsString as std::os::unix::ffi::OsStringExt>::from_vec(b"/photos/\xff.jpg".to_vec()));
+            assert_eq!(path_from_bytes(path_bytes(&raw)), Some(raw));
+        }
+    }
+}
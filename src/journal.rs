@@ -0,0 +1,144 @@
+//! The undo journal of the library: file operations such as the moves of culling,
+//! recorded in batches so `momemtum undo` can revert the latest one after the viewer
+//! has closed, and the file moves it and the viewer's own undo history make, which
+//! never replace an existing file.
+
+use anyhow::{anyhow, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::library::{JournalEntry, Library};
+
+/// A reversible file operation recorded in the library journal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+impl Operation {
+    fn to_entry(&self) -> JournalEntry {
+        match self {
+            Operation::Rename { from, to } => ("rename".to_string(), from.clone(), to.clone()),
+        }
+    }
+
+    fn from_entry(kind: &str, source: PathBuf, target: PathBuf) -> Result<Self> {
+        match kind {
+            "rename" => Ok(Operation::Rename { from: source, to: target }),
+            other => Err(anyhow!("Unknown journal operation {:?}", other)),
+        }
+    }
+}
+
+/// Record operations that were just performed as one undoable batch.
+pub fn record(library: &mut Library, operations: &[Operation]) -> Result<i64> {
+    let entries: Vec<_> = operations.iter().map(Operation::to_entry).collect();
+    library.record_batch(&entries)
+}
+
+/// Revert the most recent batch, newest operation first. Returns the reverted operations.
+pub fn undo_last(library: &Library) -> Result<Vec<Operation>> {
+    let Some((batch, entries)) = library.last_batch()? else {
+        return Ok(Vec::new());
+    };
+    let operations = entries
+        .into_iter()
+        .map(|(kind, source, target)| Operation::from_entry(&kind, source, target))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (i, op) in operations.iter().enumerate().rev() {
+        let result = match op {
+            Operation::Rename { from, to } => move_file(to, from),
+        };
+        if let Err(e) = result {
+            // Redo what was already reverted, so the batch in the journal still matches the files
+            for op in &operations[i + 1..] {
+                match op {
+                    Operation::Rename { from, to } => {
+                        if let Err(redo) = move_file(from, to) {
+                            return Err(e.context(format!(
+                                "Undo failed halfway and {} couldn't be renamed back to {}: {:#}",
+                                from.display(),
+                                to.display(),
+                                redo
+                            )));
+                        }
+                    }
+                }
+            }
+            return Err(e.context("Undo failed; nothing was reverted"));
+        }
+    }
+    library.remove_batch(batch)?;
+    Ok(operations)
+}
+
+/// Rename the file `from` to `to`, refusing to overwrite an existing file, even one that
+/// appears meanwhile: a plain rename would replace it, so the file is linked under its new
+/// name, which fails if the name is taken, and then unlinked from its old one.
+pub fn move_file(from: &Path, to: &Path) -> Result<()> {
+    match std::fs::hard_link(from, to) {
+        Ok(()) => {
+            if let Err(e) = std::fs::remove_file(from) {
+                let _ = std::fs::remove_file(to);
+                return Err(e.into());
+            }
+            Ok(())
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(anyhow!("{:?} already exists", to)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(e.into()),
+        // Between file systems, or on one without hard links, a move is a copy and a delete
+        Err(_) => {
+            copy_new(from, to)?;
+            std::fs::remove_file(from)?;
+            Ok(())
+        }
+    }
+}
+
+/// Copy `from` to `to`, a file created for it, so an existing one is never replaced.
+/// A copy cut short is removed.
+fn copy_new(from: &Path, to: &Path) -> Result<()> {
+    let mut source = std::fs::File::open(from)?;
+    let mut target = std::fs::OpenOptions::new().write(true).create_new(true).open(to).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => anyhow!("{:?} already exists", to),
+        _ => e.into(),
+    })?;
+    let copied = std::io::copy(&mut source, &mut target)
+        .and_then(|_| target.set_permissions(source.metadata()?.permissions()))
+        .and_then(|_| target.sync_all());
+    if let Err(e) = copied {
+        drop(target);
+        let _ = std::fs::remove_file(to);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_move_file() {
+        let dir = scratch_dir("journal");
+        let (a, b, c) = (dir.join("a.jpg"), dir.join("b.jpg"), dir.join("c.jpg"));
+        std::fs::write(&a, b"a").unwrap();
+        std::fs::write(&b, b"b").unwrap();
+
+        let error = move_file(&a, &b).unwrap_err();
+        assert!(error.to_string().contains("already exists"), "{}", error);
+        assert_eq!(std::fs::read(&b).unwrap(), b"b");
+        move_file(&a, &c).unwrap();
+        assert!(!a.exists());
+        assert_eq!(std::fs::read(&c).unwrap(), b"a");
+        assert!(move_file(&a, &dir.join("d.jpg")).is_err());
+
+        // The copy taking over where hard links can't be made doesn't replace files either
+        assert!(copy_new(&c, &b).is_err());
+        assert_eq!(std::fs::read(&b).unwrap(), b"b");
+        copy_new(&c, &a).unwrap();
+        assert_eq!(std::fs::read(&a).unwrap(), b"a");
+    }
+}
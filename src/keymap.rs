@@ -0,0 +1,516 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use winit::keyboard::{KeyCode, ModifiersState};
+
+/// Everything a key can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    OpenFile,
+    OpenFolder,
+    NextImage,
+    PrevImage,
+    ZoomIn,
+    ZoomOut,
+    ToggleFilmstrip,
+    ToggleGrid,
+    EditNote,
+    Redact,
+    TogglePrivate,
+    TogglePrivateFolder,
+    UnlockPrivate,
+    CycleSort,
+    ToggleRecursive,
+    ToggleShuffle,
+    Filter,
+    ToggleMap,
+    CachePanel,
+    FirstImage,
+    LastImage,
+    GoTo,
+    Delete,
+    Rename,
+    CycleTheme,
+    ToggleMatte,
+    ClearRating,
+    Rate1,
+    Rate2,
+    Rate3,
+    Rate4,
+    Rate5,
+    TogglePick,
+    ToggleReject,
+    CycleFraming,
+    NudgeLeft,
+    NudgeRight,
+    NudgeUp,
+    NudgeDown,
+    NudgePixelLeft,
+    NudgePixelRight,
+    NudgePixelUp,
+    NudgePixelDown,
+    SnapshotView,
+    Crop,
+    ToggleBasket,
+    OpenBasket,
+    ClearBasket,
+    SavePlaylist,
+    ToggleFavorite,
+    OpenFavorites,
+    RecentFiles,
+    QuickOpen,
+    RotateClockwise,
+    RotateCounterclockwise,
+    SaveRotation,
+    Compare,
+    CycleExportColorSpace,
+    Paste,
+    SyncViews,
+    Undo,
+    Redo,
+    PlayPause,
+    PrevFrame,
+    NextFrame,
+    SlowerPlayback,
+    FasterPlayback,
+    ToggleLoop,
+    ExtractFrames,
+    Adjust,
+    ResetAdjustments,
+    ClippingWarning,
+    FalseColor,
+    ToggleLut,
+    LoadLut,
+    ShowOriginal,
+    BeforeAfter,
+    CopyCoordinates,
+    OpenLocation,
+    EditMetadata,
+    ExportClean,
+    Convert,
+    FindDuplicates,
+    ExifPanel,
+    Properties,
+    Diagnostics,
+    CyclePresentMode,
+    SearchLibrary,
+    Places,
+    Timeline,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Action::Quit,
+        Action::OpenFile,
+        Action::OpenFolder,
+        Action::NextImage,
+        Action::PrevImage,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ToggleFilmstrip,
+        Action::ToggleGrid,
+        Action::EditNote,
+        Action::Redact,
+        Action::TogglePrivate,
+        Action::TogglePrivateFolder,
+        Action::UnlockPrivate,
+        Action::CycleSort,
+        Action::ToggleRecursive,
+        Action::ToggleShuffle,
+        Action::Filter,
+        Action::ToggleMap,
+        Action::CachePanel,
+        Action::FirstImage,
+        Action::LastImage,
+        Action::GoTo,
+        Action::Delete,
+        Action::Rename,
+        Action::CycleTheme,
+        Action::ToggleMatte,
+        Action::ClearRating,
+        Action::Rate1,
+        Action::Rate2,
+        Action::Rate3,
+        Action::Rate4,
+        Action::Rate5,
+        Action::TogglePick,
+        Action::ToggleReject,
+        Action::CycleFraming,
+        Action::NudgeLeft,
+        Action::NudgeRight,
+        Action::NudgeUp,
+        Action::NudgeDown,
+        Action::NudgePixelLeft,
+        Action::NudgePixelRight,
+        Action::NudgePixelUp,
+        Action::NudgePixelDown,
+        Action::SnapshotView,
+        Action::Crop,
+        Action::ToggleBasket,
+        Action::OpenBasket,
+        Action::ClearBasket,
+        Action::SavePlaylist,
+        Action::ToggleFavorite,
+        Action::OpenFavorites,
+        Action::RecentFiles,
+        Action::QuickOpen,
+        Action::RotateClockwise,
+        Action::RotateCounterclockwise,
+        Action::SaveRotation,
+        Action::Compare,
+        Action::CycleExportColorSpace,
+        Action::Paste,
+        Action::SyncViews,
+        Action::Undo,
+        Action::Redo,
+        Action::PlayPause,
+        Action::PrevFrame,
+        Action::NextFrame,
+        Action::SlowerPlayback,
+        Action::FasterPlayback,
+        Action::ToggleLoop,
+        Action::ExtractFrames,
+        Action::Adjust,
+        Action::ResetAdjustments,
+        Action::ClippingWarning,
+        Action::FalseColor,
+        Action::ToggleLut,
+        Action::LoadLut,
+        Action::ShowOriginal,
+        Action::BeforeAfter,
+        Action::CopyCoordinates,
+        Action::OpenLocation,
+        Action::EditMetadata,
+        Action::ExportClean,
+        Action::Convert,
+        Action::FindDuplicates,
+        Action::ExifPanel,
+        Action::Properties,
+        Action::Diagnostics,
+        Action::CyclePresentMode,
+        Action::SearchLibrary,
+        Action::Places,
+        Action::Timeline,
+    ];
+
+    fn default_keys(&self) -> &'static [&'static str] {
+        match self {
+            Action::Quit => &["Escape"],
+            Action::OpenFile => &["Ctrl+O"],
+            Action::OpenFolder => &["Ctrl+Shift+O"],
+            Action::NextImage => &["Right"],
+            Action::PrevImage => &["Left"],
+            Action::ZoomIn => &["Equal", "NumpadAdd"],
+            Action::ZoomOut => &["Minus", "NumpadSubtract"],
+            Action::ToggleFilmstrip => &["T"],
+            Action::ToggleGrid => &["G"],
+            Action::EditNote => &["N"],
+            Action::Redact => &["X"],
+            Action::TogglePrivate => &["P"],
+            Action::TogglePrivateFolder => &["Shift+P"],
+            Action::UnlockPrivate => &["Ctrl+U"],
+            Action::CycleSort => &["S"],
+            Action::ToggleRecursive => &["R"],
+            Action::ToggleShuffle => &["Shift+S"],
+            Action::Filter => &["Backslash"],
+            Action::ToggleMap => &["M"],
+            Action::CachePanel => &["F9"],
+            Action::FirstImage => &["Home"],
+            Action::LastImage => &["End"],
+            Action::GoTo => &["Ctrl+G"],
+            Action::Delete => &["Delete"],
+            Action::Rename => &["F2"],
+            Action::CycleTheme => &["Shift+B"],
+            Action::ToggleMatte => &["Shift+M"],
+            Action::ClearRating => &["Ctrl+0"],
+            Action::Rate1 => &["Ctrl+1"],
+            Action::Rate2 => &["Ctrl+2"],
+            Action::Rate3 => &["Ctrl+3"],
+            Action::Rate4 => &["Ctrl+4"],
+            Action::Rate5 => &["Ctrl+5"],
+            Action::TogglePick => &["Ctrl+K"],
+            Action::ToggleReject => &["Ctrl+X"],
+            Action::CycleFraming => &["Shift+C"],
+            Action::NudgeLeft => &["Shift+Left"],
+            Action::NudgeRight => &["Shift+Right"],
+            Action::NudgeUp => &["Shift+Up"],
+            Action::NudgeDown => &["Shift+Down"],
+            Action::NudgePixelLeft => &["Ctrl+Shift+Left"],
+            Action::NudgePixelRight => &["Ctrl+Shift+Right"],
+            Action::NudgePixelUp => &["Ctrl+Shift+Up"],
+            Action::NudgePixelDown => &["Ctrl+Shift+Down"],
+            Action::SnapshotView => &["Ctrl+Shift+S"],
+            Action::Crop => &["C"],
+            Action::ToggleBasket => &["B"],
+            Action::OpenBasket => &["Ctrl+B"],
+            Action::ClearBasket => &["Ctrl+Shift+B"],
+            Action::SavePlaylist => &["Ctrl+Shift+L"],
+            Action::ToggleFavorite => &["F"],
+            Action::OpenFavorites => &["Shift+F"],
+            Action::RecentFiles => &["Ctrl+R"],
+            Action::QuickOpen => &["Ctrl+P"],
+            Action::RotateClockwise => &["BracketRight"],
+            Action::RotateCounterclockwise => &["BracketLeft"],
+            Action::SaveRotation => &["Ctrl+S"],
+            Action::Compare => &["K"],
+            Action::CycleExportColorSpace => &["E"],
+            Action::Paste => &["Ctrl+V"],
+            Action::SyncViews => &["Ctrl+L"],
+            Action::Undo => &["Ctrl+Z"],
+            Action::Redo => &["Ctrl+Shift+Z", "Ctrl+Y"],
+            Action::PlayPause => &["Shift+Space"],
+            Action::PrevFrame => &["Comma"],
+            Action::NextFrame => &["Period"],
+            Action::SlowerPlayback => &["Shift+Comma"],
+            Action::FasterPlayback => &["Shift+Period"],
+            Action::ToggleLoop => &["Shift+L"],
+            Action::ExtractFrames => &["Ctrl+E"],
+            Action::Adjust => &["A"],
+            Action::ResetAdjustments => &["Shift+A"],
+            Action::ClippingWarning => &["J"],
+            Action::FalseColor => &["Shift+J"],
+            Action::ToggleLut => &["U"],
+            Action::LoadLut => &["Shift+U"],
+            Action::ShowOriginal => &["Backquote"],
+            Action::BeforeAfter => &["Shift+Backquote"],
+            Action::CopyCoordinates => &["Ctrl+Shift+C"],
+            Action::OpenLocation => &["Ctrl+Shift+M"],
+            Action::EditMetadata => &["I"],
+            Action::ExportClean => &["Ctrl+Shift+E"],
+            Action::Convert => &["Ctrl+Shift+R"],
+            Action::FindDuplicates => &["D"],
+            Action::ExifPanel => &["F3"],
+            Action::Properties => &["Alt+Enter"],
+            Action::Diagnostics => &["F12"],
+            Action::CyclePresentMode => &["Shift+F12"],
+            Action::SearchLibrary => &["Ctrl+F"],
+            Action::Places => &["Ctrl+M"],
+            Action::Timeline => &["Shift+G"],
+        }
+    }
+
+    /// The snake_case name used in the config file.
+    fn name(&self) -> String {
+        toml::Value::try_from(self)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+}
+
+/// One or several key combinations for an action in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    fn keys(&self) -> Vec<&str> {
+        match self {
+            KeyList::One(key) => vec![key.as_str()],
+            KeyList::Many(keys) => keys.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// The default bindings, in config-file form.
+pub fn default_bindings() -> BTreeMap<String, KeyList> {
+    Action::ALL
+        .iter()
+        .map(|action| {
+            let keys: Vec<String> = action.default_keys().iter().map(|k| k.to_string()).collect();
+            let list = if keys.len() == 1 { KeyList::One(keys[0].clone()) } else { KeyList::Many(keys) };
+            (action.name(), list)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyCombo {
+    pub key: KeyCode,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl KeyCombo {
+    /// Parse "Ctrl+Shift+P", "Right", "Space", "F5", ...
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut combo = KeyCombo { key: KeyCode::Escape, ctrl: false, shift: false, alt: false };
+        let mut key = None;
+        for part in text.split('+').map(str::trim) {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" | "cmd" => combo.ctrl = true,
+                "shift" => combo.shift = true,
+                "alt" | "option" => combo.alt = true,
+                _ => key = Some(parse_key(part)?),
+            }
+        }
+        combo.key = key?;
+        Some(combo)
+    }
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let lower = name.to_lowercase();
+    if lower.len() == 1 {
+        let c = lower.chars().next()?;
+        if c.is_ascii_lowercase() {
+            return letter_key(c);
+        }
+        if c.is_ascii_digit() {
+            return digit_key(c);
+        }
+    }
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        return function_key(n);
+    }
+    Some(match lower.as_str() {
+        "left" | "arrowleft" => KeyCode::ArrowLeft,
+        "right" | "arrowright" => KeyCode::ArrowRight,
+        "up" | "arrowup" => KeyCode::ArrowUp,
+        "down" | "arrowdown" => KeyCode::ArrowDown,
+        "space" => KeyCode::Space,
+        "enter" | "return" => KeyCode::Enter,
+        "escape" | "esc" => KeyCode::Escape,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "tab" => KeyCode::Tab,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "equal" | "plus" | "=" => KeyCode::Equal,
+        "minus" | "-" => KeyCode::Minus,
+        "comma" | "," => KeyCode::Comma,
+        "period" | "." => KeyCode::Period,
+        "slash" | "/" => KeyCode::Slash,
+        "backslash" | "\\" => KeyCode::Backslash,
+        "semicolon" | ";" => KeyCode::Semicolon,
+        "quote" | "'" => KeyCode::Quote,
+        "backquote" | "`" => KeyCode::Backquote,
+        "bracketleft" | "[" => KeyCode::BracketLeft,
+        "bracketright" | "]" => KeyCode::BracketRight,
+        "numpadadd" => KeyCode::NumpadAdd,
+        "numpadsubtract" => KeyCode::NumpadSubtract,
+        "numpadenter" => KeyCode::NumpadEnter,
+        _ => return None,
+    })
+}
+
+fn letter_key(c: char) -> Option<KeyCode> {
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF,
+        KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL,
+        KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR,
+        KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX,
+        KeyCode::KeyY, KeyCode::KeyZ,
+    ];
+    LETTERS.get((c as u8 - b'a') as usize).copied()
+}
+
+fn digit_key(c: char) -> Option<KeyCode> {
+    const DIGITS: [KeyCode; 10] = [
+        KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+        KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    ];
+    DIGITS.get((c as u8 - b'0') as usize).copied()
+}
+
+fn function_key(n: u8) -> Option<KeyCode> {
+    const FUNCTION: [KeyCode; 12] = [
+        KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+        KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    ];
+    FUNCTION.get((n as usize).checked_sub(1)?).copied()
+}
+
+/// Lookup table from key combinations to actions.
+pub struct Keymap {
+    bindings: HashMap<KeyCombo, Action>,
+}
+
+impl Keymap {
+    /// Defaults, with every action named in `overrides` rebound to the given keys.
+    pub fn new(overrides: &BTreeMap<String, KeyList>) -> Self {
+        let mut bindings = HashMap::new();
+        for action in Action::ALL {
+            let keys: Vec<&str> = match overrides.get(&action.name()) {
+                Some(list) => list.keys(),
+                None => action.default_keys().to_vec(),
+            };
+            for key in keys {
+                match KeyCombo::parse(key) {
+                    Some(combo) => {
+                        bindings.insert(combo, *action);
+                    }
+                    None => log::warn!("Unknown key {:?} for {}", key, action.name()),
+                }
+            }
+        }
+        for name in overrides.keys() {
+            if !Action::ALL.iter().any(|a| &a.name() == name) {
+                log::warn!("Unknown action {:?} in keybindings", name);
+            }
+        }
+        Self { bindings }
+    }
+
+    pub fn lookup(&self, key: KeyCode, modifiers: ModifiersState) -> Option<Action> {
+        let combo = KeyCombo {
+            key,
+            ctrl: modifiers.control_key() || modifiers.super_key(),
+            shift: modifiers.shift_key(),
+            alt: modifiers.alt_key(),
+        };
+        self.bindings.get(&combo).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_combo() {
+        let combo = KeyCombo::parse("Ctrl+Shift+p").unwrap();
+        assert_eq!(combo, KeyCombo { key: KeyCode::KeyP, ctrl: true, shift: true, alt: false });
+        assert_eq!(KeyCombo::parse("Right").unwrap().key, KeyCode::ArrowRight);
+        assert_eq!(KeyCombo::parse("F12").unwrap().key, KeyCode::F12);
+        assert_eq!(KeyCombo::parse("7").unwrap().key, KeyCode::Digit7);
+        assert!(KeyCombo::parse("Ctrl+").is_none());
+        assert!(KeyCombo::parse("Hyper+K").is_none());
+    }
+
+    #[test]
+    fn test_overrides_replace_defaults() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert("next_image".to_string(), KeyList::Many(vec!["Space".into(), "L".into()]));
+        let keymap = Keymap::new(&overrides);
+
+        let none = ModifiersState::empty();
+        assert_eq!(keymap.lookup(KeyCode::Space, none), Some(Action::NextImage));
+        assert_eq!(keymap.lookup(KeyCode::KeyL, none), Some(Action::NextImage));
+        assert_eq!(keymap.lookup(KeyCode::ArrowRight, none), None);
+        // Untouched actions keep their defaults
+        assert_eq!(keymap.lookup(KeyCode::ArrowLeft, none), Some(Action::PrevImage));
+        assert_eq!(keymap.lookup(KeyCode::KeyP, ModifiersState::SHIFT), Some(Action::TogglePrivateFolder));
+        assert_eq!(keymap.lookup(KeyCode::KeyP, none), Some(Action::TogglePrivate));
+        assert_eq!(keymap.lookup(KeyCode::KeyP, ModifiersState::CONTROL), Some(Action::QuickOpen));
+        assert_eq!(keymap.lookup(KeyCode::KeyK, ModifiersState::CONTROL), Some(Action::TogglePick));
+    }
+
+    #[test]
+    fn test_default_bindings_roundtrip() {
+        let defaults = default_bindings();
+        assert_eq!(defaults.len(), Action::ALL.len());
+        assert_eq!(defaults.get("zoom_in"), Some(&KeyList::Many(vec!["Equal".into(), "NumpadAdd".into()])));
+        let text = toml::to_string(&defaults).unwrap();
+        let parsed: BTreeMap<String, KeyList> = toml::from_str(&text).unwrap();
+        assert_eq!(parsed, defaults);
+    }
+}
@@ -0,0 +1,86 @@
+//! The viewer as a library. The app itself is built on it, so all of its modules are
+//! public; applications drawing an image view of their own into a texture they own,
+//! such as a DAM plugin or an editor's preview pane, use `viewer::Viewer`, which draws
+//! with the app's `renderer` and decodes through its `sandbox`.
+
+pub mod adjust;
+pub mod animation;
+pub mod autopan;
+pub mod badges;
+pub mod basket;
+pub mod bench;
+pub mod cache;
+pub mod catalog;
+pub mod cli;
+pub mod compare;
+pub mod config;
+pub mod confirm;
+pub mod contact;
+pub mod convert;
+pub mod crash;
+pub mod crop;
+pub mod cull;
+pub mod duplicates;
+pub mod export;
+pub mod exposure;
+pub mod filmstrip;
+pub mod filter;
+pub mod frames;
+pub mod framing;
+pub mod geotiff;
+pub mod gps;
+pub mod gpu;
+pub mod grid;
+pub mod history;
+pub mod index;
+pub mod instance;
+pub mod journal;
+pub mod keymap;
+pub mod library;
+pub mod loader;
+pub mod logging;
+pub mod lut;
+pub mod map;
+pub mod matte;
+pub mod memory;
+pub mod montage;
+pub mod navigator;
+pub mod places;
+pub mod playlist;
+pub mod priority;
+pub mod progress;
+pub mod prompt;
+pub mod properties;
+pub mod proxy;
+pub mod pyramid;
+pub mod quad;
+pub mod raw;
+#[cfg(test)]
+mod raw_fixtures;
+pub mod redact;
+pub mod redraw;
+pub mod remote;
+pub mod rename;
+pub mod renderer;
+pub mod rotate;
+pub mod sandbox;
+#[cfg(test)]
+mod scratch;
+pub mod session;
+pub mod slide;
+pub mod snapshot;
+pub mod software;
+pub mod state;
+pub mod stats;
+pub mod sync;
+pub mod texture;
+pub mod theme;
+pub mod thumbnail;
+pub mod timeline;
+pub mod touch;
+pub mod ui;
+pub mod viewer;
+pub mod watcher;
+pub mod xmp;
+
+pub use viewer::Viewer;
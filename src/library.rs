@@ -0,0 +1,743 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::path::{Path, PathBuf};
+
+use crate::index::FileInfo;
+
+/// A journal row: operation kind, source path, target path.
+pub type JournalEntry = (String, PathBuf, PathBuf);
+
+/// Persistent per-user library database stored in the platform data directory.
+pub struct Library {
+    conn: Connection,
+}
+
+impl Library {
+    pub fn open() -> Result<Self> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::open_at(&path)
+    }
+
+    /// Where the library database is kept.
+    pub fn path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("momemtum")
+            .join("library.db")
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        Self::init(conn)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS private_paths (
+                path TEXT PRIMARY KEY NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS private_pin (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                salt TEXT NOT NULL,
+                hash TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS favorites (
+                path TEXT PRIMARY KEY NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS recent (
+                path TEXT PRIMARY KEY NOT NULL,
+                opened INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS journal (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                batch INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                source TEXT NOT NULL,
+                target TEXT NOT NULL,
+                created INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS file_index (
+                path TEXT PRIMARY KEY NOT NULL,
+                mtime INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                width INTEGER,
+                height INTEGER,
+                taken TEXT,
+                camera TEXT,
+                lens TEXT,
+                exposure TEXT,
+                iso INTEGER,
+                day INTEGER,
+                latitude REAL,
+                longitude REAL
+            );
+            CREATE TABLE IF NOT EXISTS catalog (
+                path TEXT PRIMARY KEY NOT NULL,
+                root TEXT NOT NULL,
+                rating INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS catalog_text (
+                path TEXT PRIMARY KEY NOT NULL,
+                description TEXT,
+                title TEXT,
+                keywords TEXT
+            );
+            CREATE TABLE IF NOT EXISTS place_names (
+                key TEXT PRIMARY KEY NOT NULL,
+                name TEXT NOT NULL
+            );",
+        )?;
+        // Indexes from before the ISO and day were stored are read again
+        let has_day: bool = conn.query_row("SELECT COUNT(*) > 0 FROM pragma_table_info('file_index') WHERE name = 'day'", [], |row| row.get(0))?;
+        if !has_day {
+            conn.execute_batch(
+                "DELETE FROM file_index;
+                 ALTER TABLE file_index ADD COLUMN iso INTEGER;
+                 ALTER TABLE file_index ADD COLUMN day INTEGER;",
+            )?;
+        }
+        // The catalog is written from a thread of its own while the viewer searches it
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        Ok(Self { conn })
+    }
+
+    /// Files or folders marked as private. A private folder hides everything below it.
+    pub fn private_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare("SELECT path FROM private_paths ORDER BY path")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut paths = Vec::new();
+        for row in rows {
+            paths.push(canonical(Path::new(&row?)));
+        }
+        Ok(paths)
+    }
+
+    /// Mark or unmark `path`, which is stored in its `canonical` form.
+    pub fn set_private(&self, path: &Path, private: bool) -> Result<()> {
+        let key = canonical(path).to_string_lossy().into_owned();
+        if private {
+            self.conn.execute("INSERT OR IGNORE INTO private_paths (path) VALUES (?1)", params![key])?;
+        } else {
+            // Also the form it was given in, as rows stored before paths were canonical may have it
+            self.conn.execute(
+                "DELETE FROM private_paths WHERE path IN (?1, ?2)",
+                params![key, path.to_string_lossy()],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Whether a PIN has been chosen for unlocking private images.
+    pub fn has_private_pin(&self) -> Result<bool> {
+        let count: i64 = self.conn.query_row("SELECT COUNT(*) FROM private_pin", [], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+
+    /// Choose the PIN that unlocks private images, replacing any earlier one. Only a salted
+    /// PBKDF2 hash of it is kept.
+    pub fn set_private_pin(&self, pin: &str) -> Result<()> {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let salt = format!("{:x}", md5::compute(format!("{}-{}", nanos, std::process::id())));
+        self.conn.execute(
+            "INSERT OR REPLACE INTO private_pin (id, salt, hash) VALUES (1, ?1, ?2)",
+            params![salt, pin_hash(&salt, pin)],
+        )?;
+        Ok(())
+    }
+
+    /// Whether `pin` is the one chosen; false when none has been.
+    pub fn check_private_pin(&self, pin: &str) -> Result<bool> {
+        let mut stmt = self.conn.prepare("SELECT salt, hash FROM private_pin WHERE id = 1")?;
+        let mut rows = stmt.query([])?;
+        Ok(match rows.next()? {
+            Some(row) => pin_hash(&row.get::<_, String>(0)?, pin) == row.get::<_, String>(1)?,
+            None => false,
+        })
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 rounds for a PIN, slow enough that trying every short PIN takes a
+/// while; tests use few to stay fast.
+#[cfg(not(test))]
+const PIN_ROUNDS: u32 = 600_000;
+#[cfg(test)]
+const PIN_ROUNDS: u32 = 1_000;
+
+fn pin_hash(salt: &str, pin: &str) -> String {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(pin.as_bytes(), salt.as_bytes(), PIN_ROUNDS, &mut hash);
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Library {
+    /// Images marked as favorites, by path.
+    pub fn favorites(&self) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare("SELECT path FROM favorites ORDER BY path")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut paths = Vec::new();
+        for row in rows {
+            paths.push(PathBuf::from(row?));
+        }
+        Ok(paths)
+    }
+
+    pub fn set_favorite(&self, path: &Path, favorite: bool) -> Result<()> {
+        let key = path.to_string_lossy();
+        if favorite {
+            self.conn.execute("INSERT OR IGNORE INTO favorites (path) VALUES (?1)", params![key])?;
+        } else {
+            self.conn.execute("DELETE FROM favorites WHERE path = ?1", params![key])?;
+        }
+        Ok(())
+    }
+
+    /// Remember that `path` was opened, keeping the `keep` most recent paths.
+    pub fn record_recent(&self, path: &Path, keep: usize) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO recent (path, opened) VALUES (?1, (SELECT COALESCE(MAX(opened), 0) + 1 FROM recent))",
+            params![path.to_string_lossy()],
+        )?;
+        self.conn.execute(
+            "DELETE FROM recent WHERE path NOT IN (SELECT path FROM recent ORDER BY opened DESC LIMIT ?1)",
+            params![keep as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Files and folders opened lately, the last one first.
+    pub fn recent(&self) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare("SELECT path FROM recent ORDER BY opened DESC")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut paths = Vec::new();
+        for row in rows {
+            paths.push(PathBuf::from(row?));
+        }
+        Ok(paths)
+    }
+}
+
+impl Library {
+    /// Append a batch of file operations to the journal and return its batch id.
+    pub fn record_batch(&mut self, entries: &[JournalEntry]) -> Result<i64> {
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        let tx = self.conn.transaction()?;
+        let batch: i64 = tx.query_row("SELECT COALESCE(MAX(batch), 0) + 1 FROM journal", [], |row| row.get(0))?;
+        for (kind, source, target) in entries {
+            tx.execute(
+                "INSERT INTO journal (batch, kind, source, target, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![batch, kind, source.to_string_lossy(), target.to_string_lossy(), created],
+            )?;
+        }
+        tx.commit()?;
+        Ok(batch)
+    }
+
+    /// The most recent batch, with its operations in the order they were performed.
+    pub fn last_batch(&self) -> Result<Option<(i64, Vec<JournalEntry>)>> {
+        let batch: Option<i64> = self.conn.query_row("SELECT MAX(batch) FROM journal", [], |row| row.get(0))?;
+        let Some(batch) = batch else {
+            return Ok(None);
+        };
+        let mut stmt = self.conn.prepare("SELECT kind, source, target FROM journal WHERE batch = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![batch], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                PathBuf::from(row.get::<_, String>(1)?),
+                PathBuf::from(row.get::<_, String>(2)?),
+            ))
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(row?);
+        }
+        Ok(Some((batch, entries)))
+    }
+
+    pub fn remove_batch(&self, batch: i64) -> Result<()> {
+        self.conn.execute("DELETE FROM journal WHERE batch = ?1", params![batch])?;
+        Ok(())
+    }
+}
+
+impl Library {
+    /// The stored metadata of `path`, if it was indexed at this modification time and size.
+    pub fn indexed_file(&self, path: &Path, mtime: i64, size: u64) -> Result<Option<FileInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT width, height, taken, camera, lens, exposure, latitude, longitude, iso
+             FROM file_index WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+        )?;
+        let mut rows = stmt.query(params![path.to_string_lossy(), mtime, size as i64])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let dimensions = match (row.get::<_, Option<u32>>(0)?, row.get::<_, Option<u32>>(1)?) {
+            (Some(w), Some(h)) => Some((w, h)),
+            _ => None,
+        };
+        let position = match (row.get::<_, Option<f64>>(6)?, row.get::<_, Option<f64>>(7)?) {
+            (Some(lat), Some(lon)) => Some((lat, lon)),
+            _ => None,
+        };
+        Ok(Some(FileInfo {
+            mtime,
+            size,
+            dimensions,
+            taken: row.get(2)?,
+            camera: row.get(3)?,
+            lens: row.get(4)?,
+            exposure: row.get(5)?,
+            iso: row.get(8)?,
+            position,
+            ..FileInfo::default()
+        }))
+    }
+
+    pub fn store_file_info(&self, path: &Path, info: &FileInfo) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO file_index
+             (path, mtime, size, width, height, taken, camera, lens, exposure, iso, day, latitude, longitude)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                path.to_string_lossy(),
+                info.mtime,
+                info.size as i64,
+                info.dimensions.map(|d| d.0),
+                info.dimensions.map(|d| d.1),
+                info.taken,
+                info.camera,
+                info.lens,
+                info.exposure,
+                info.iso,
+                info.taken.as_deref().and_then(day),
+                info.position.map(|p| p.0),
+                info.position.map(|p| p.1),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Library {
+    /// Run `f` as one transaction, much faster than committing every write on its own.
+    pub fn in_transaction<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let tx = self.conn.unchecked_transaction()?;
+        let result = f()?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    /// Add `path`, found below the library folder `root`, to the catalog or update what its
+    /// sidecar says: the rating, note, title and keywords. Its other metadata lives in the
+    /// file index.
+    pub fn catalog_file(&self, path: &Path, root: &Path, xmp: &crate::xmp::XmpData) -> Result<()> {
+        let key = path.to_string_lossy();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO catalog (path, root, rating) VALUES (?1, ?2, ?3)",
+            params![key, root.to_string_lossy(), xmp.rating],
+        )?;
+        let keywords = (!xmp.keywords.is_empty()).then(|| xmp.keywords.join(", "));
+        if xmp.description.is_none() && xmp.title.is_none() && keywords.is_none() {
+            self.conn.execute("DELETE FROM catalog_text WHERE path = ?1", params![key])?;
+        } else {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO catalog_text (path, description, title, keywords) VALUES (?1, ?2, ?3, ?4)",
+                params![key, xmp.description, xmp.title, keywords],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Drop `path` from the catalog, and everything below it if it was a folder.
+    pub fn uncatalog(&self, path: &Path) -> Result<()> {
+        let key = path.to_string_lossy();
+        let below = format!("{}{}", key.trim_end_matches(std::path::MAIN_SEPARATOR), std::path::MAIN_SEPARATOR);
+        for table in ["catalog", "catalog_text"] {
+            self.conn.execute(
+                &format!("DELETE FROM {} WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2", table),
+                params![key, below],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The files cataloged below the library folder `root`.
+    pub fn cataloged(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare("SELECT path FROM catalog WHERE root = ?1")?;
+        let rows = stmt.query_map(params![root.to_string_lossy()], |row| row.get::<_, String>(0))?;
+        let mut paths = Vec::new();
+        for row in rows {
+            paths.push(PathBuf::from(row?));
+        }
+        Ok(paths)
+    }
+
+    /// Forget the files of folders no longer in the library.
+    pub fn uncatalog_roots_except(&self, roots: &[PathBuf]) -> Result<()> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT root FROM catalog")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut stale = Vec::new();
+        for row in rows {
+            let root = row?;
+            if !roots.iter().any(|r| r.to_string_lossy() == root) {
+                stale.push(root);
+            }
+        }
+        for root in stale {
+            self.conn.execute("DELETE FROM catalog WHERE root = ?1", params![root])?;
+        }
+        self.conn.execute("DELETE FROM catalog_text WHERE path NOT IN (SELECT path FROM catalog)", [])?;
+        Ok(())
+    }
+
+    pub fn catalog_count(&self) -> Result<u64> {
+        Ok(self.conn.query_row("SELECT COUNT(*) FROM catalog", [], |row| row.get::<_, i64>(0))? as u64)
+    }
+
+    /// Cataloged files matching `query`, in order of capture, those without a capture date last.
+    pub fn search(&self, query: &crate::catalog::Query) -> Result<Vec<PathBuf>> {
+        use crate::catalog::Term;
+        use rusqlite::types::Value;
+        let mut sql = "SELECT catalog.path FROM catalog \
+                       LEFT JOIN file_index ON file_index.path = catalog.path \
+                       LEFT JOIN catalog_text ON catalog_text.path = catalog.path WHERE 1"
+            .to_string();
+        let mut values: Vec<Value> = Vec::new();
+        // Each condition takes the value pushed last as `?n`
+        let mut condition = |value: Value, clause: &str| {
+            values.push(value);
+            sql.push_str(&format!(" AND ({})", clause.replace("?n", &format!("?{}", values.len()))));
+        };
+        for term in &query.terms {
+            match term {
+                Term::Word(word) => condition(
+                    contains(word),
+                    "catalog.path LIKE ?n ESCAPE '\\' OR file_index.camera LIKE ?n ESCAPE '\\' OR file_index.lens LIKE ?n ESCAPE '\\' \
+                     OR catalog_text.description LIKE ?n ESCAPE '\\' OR catalog_text.title LIKE ?n ESCAPE '\\' \
+                     OR catalog_text.keywords LIKE ?n ESCAPE '\\'",
+                ),
+                Term::Camera(camera) => condition(contains(camera), "file_index.camera LIKE ?n ESCAPE '\\'"),
+                Term::Lens(lens) => condition(contains(lens), "file_index.lens LIKE ?n ESCAPE '\\'"),
+                Term::Iso(cmp, iso) => condition(Value::Integer(*iso as i64), &format!("file_index.iso {} ?n", cmp.sql())),
+                Term::Rating(cmp, rating) => {
+                    condition(Value::Integer(*rating as i64), &format!("COALESCE(catalog.rating, 0) {} ?n", cmp.sql()))
+                }
+            }
+        }
+        if let Some((from, to)) = query.taken {
+            condition(Value::Integer(from as i64), "file_index.day >= ?n");
+            condition(Value::Integer(to as i64), "file_index.day <= ?n");
+        }
+        sql.push_str(" ORDER BY file_index.taken IS NULL, file_index.taken, catalog.path");
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(&values), |row| row.get::<_, String>(0))?;
+        let mut paths = Vec::new();
+        for row in rows {
+            paths.push(PathBuf::from(row?));
+        }
+        Ok(paths)
+    }
+}
+
+impl Library {
+    /// Cataloged images with a GPS position, by path.
+    pub fn located(&self) -> Result<Vec<(PathBuf, (f64, f64))>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT catalog.path, file_index.latitude, file_index.longitude
+             FROM catalog JOIN file_index ON file_index.path = catalog.path
+             WHERE file_index.latitude IS NOT NULL AND file_index.longitude IS NOT NULL
+             ORDER BY catalog.path",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?))))?;
+        let mut located = Vec::new();
+        for row in rows {
+            let (path, position) = row?;
+            located.push((PathBuf::from(path), position));
+        }
+        Ok(located)
+    }
+
+    /// The name looked up for the place at `key`, see `places::key`; empty when the
+    /// geocoder knew none.
+    pub fn place_name(&self, key: &str) -> Result<Option<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM place_names WHERE key = ?1")?;
+        let mut rows = stmt.query(params![key])?;
+        Ok(match rows.next()? {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        })
+    }
+
+    pub fn store_place_name(&self, key: &str, name: &str) -> Result<()> {
+        self.conn.execute("INSERT OR REPLACE INTO place_names (key, name) VALUES (?1, ?2)", params![key, name])?;
+        Ok(())
+    }
+
+    /// Bytes of text the looked up place names take.
+    pub fn place_names_size(&self) -> Result<u64> {
+        let bytes: i64 = self.conn.query_row("SELECT COALESCE(SUM(LENGTH(key) + LENGTH(name)), 0) FROM place_names", [], |row| row.get(0))?;
+        Ok(bytes as u64)
+    }
+
+    /// Forget the looked up place names, so they are looked up again.
+    pub fn clear_place_names(&self) -> Result<()> {
+        self.conn.execute("DELETE FROM place_names", [])?;
+        Ok(())
+    }
+}
+
+/// The day of a capture time `YYYY-MM-DD HH:MM:SS` as the number `YYYYMMDD`.
+fn day(taken: &str) -> Option<u32> {
+    let date = taken.get(..10)?;
+    let digits: String = date.split('-').collect();
+    (digits.len() == 8).then(|| digits.parse().ok()).flatten()
+}
+
+/// A LIKE pattern for text containing `text`, which may hold `%` and `_` itself.
+fn contains(text: &str) -> rusqlite::types::Value {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    rusqlite::types::Value::Text(format!("%{}%", escaped))
+}
+
+/// True if `path` equals one of `roots` or lives below one of them. `roots` are expected
+/// `canonical`; `path` is made so before comparing.
+pub fn is_under_any(path: &Path, roots: &[PathBuf]) -> bool {
+    if roots.is_empty() {
+        return false;
+    }
+    let path = canonical(path);
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+/// `path` resolved through symlinks and `..` when it exists, else only made absolute,
+/// so the same file always compares equal however it was reached.
+pub fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use crate::xmp::XmpData;
+
+    #[test]
+    fn test_private_paths() {
+        let lib = Library::open_in_memory().unwrap();
+        let folder = PathBuf::from("/photos/private");
+        let file = PathBuf::from("/photos/a.jpg");
+
+        lib.set_private(&folder, true).unwrap();
+        lib.set_private(&file, true).unwrap();
+        let private = lib.private_paths().unwrap();
+        assert!(is_under_any(&folder.join("b.jpg"), &private));
+        assert!(is_under_any(&file, &private));
+        assert!(!is_under_any(Path::new("/photos/c.jpg"), &private));
+
+        lib.set_private(&file, false).unwrap();
+        assert_eq!(lib.private_paths().unwrap(), vec![folder.clone()]);
+
+        // Relative paths are stored, and compared, absolute
+        lib.set_private(Path::new("shoot/hidden"), true).unwrap();
+        let private = lib.private_paths().unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        assert!(private.contains(&cwd.join("shoot/hidden")));
+        assert!(is_under_any(Path::new("shoot/hidden/a.jpg"), &private));
+        assert!(is_under_any(&cwd.join("shoot/hidden/a.jpg"), &private));
+        lib.set_private(&cwd.join("shoot/hidden"), false).unwrap();
+        assert_eq!(lib.private_paths().unwrap(), vec![folder]);
+
+        assert!(!lib.has_private_pin().unwrap());
+        assert!(!lib.check_private_pin("").unwrap());
+        lib.set_private_pin("2468").unwrap();
+        assert!(lib.has_private_pin().unwrap());
+        assert!(lib.check_private_pin("2468").unwrap());
+        assert!(!lib.check_private_pin("1357").unwrap());
+        lib.set_private_pin("1357").unwrap();
+        assert!(lib.check_private_pin("1357").unwrap() && !lib.check_private_pin("2468").unwrap());
+    }
+
+    #[test]
+    fn test_favorites() {
+        let lib = Library::open_in_memory().unwrap();
+        let (a, b) = (PathBuf::from("/photos/b.jpg"), PathBuf::from("/archive/a.jpg"));
+        lib.set_favorite(&a, true).unwrap();
+        lib.set_favorite(&b, true).unwrap();
+        lib.set_favorite(&b, true).unwrap();
+        assert_eq!(lib.favorites().unwrap(), vec![b.clone(), a.clone()]);
+        lib.set_favorite(&b, false).unwrap();
+        assert_eq!(lib.favorites().unwrap(), vec![a]);
+    }
+
+    #[test]
+    fn test_recent() {
+        let lib = Library::open_in_memory().unwrap();
+        for path in ["/photos/a.jpg", "/photos/shoot", "/photos/b.jpg", "/photos/a.jpg"] {
+            lib.record_recent(Path::new(path), 2).unwrap();
+        }
+        // Opening again moves a path to the front; the oldest fall off
+        assert_eq!(lib.recent().unwrap(), vec![PathBuf::from("/photos/a.jpg"), PathBuf::from("/photos/b.jpg")]);
+    }
+
+    #[test]
+    fn test_journal_batches() {
+        let mut lib = Library::open_in_memory().unwrap();
+        assert!(lib.last_batch().unwrap().is_none());
+
+        let first = vec![("rename".to_string(), PathBuf::from("/a.jpg"), PathBuf::from("/b.jpg"))];
+        let second = vec![
+            ("rename".to_string(), PathBuf::from("/c.jpg"), PathBuf::from("/d.jpg")),
+            ("rename".to_string(), PathBuf::from("/e.jpg"), PathBuf::from("/f.jpg")),
+        ];
+        let b1 = lib.record_batch(&first).unwrap();
+        let b2 = lib.record_batch(&second).unwrap();
+        assert!(b2 > b1);
+
+        assert_eq!(lib.last_batch().unwrap(), Some((b2, second)));
+        lib.remove_batch(b2).unwrap();
+        assert_eq!(lib.last_batch().unwrap(), Some((b1, first)));
+    }
+
+    #[test]
+    fn test_file_index() {
+        let lib = Library::open_in_memory().unwrap();
+        let path = Path::new("/photos/a.nef");
+        let info = FileInfo {
+            mtime: 1_700_000_000,
+            size: 25_000_000,
+            dimensions: Some((6048, 4024)),
+            taken: Some("2024-06-21 10:00:00".to_string()),
+            camera: Some("NIKON Z 6".to_string()),
+            position: Some((48.85, 2.35)),
+            ..FileInfo::default()
+        };
+        lib.store_file_info(path, &info).unwrap();
+        assert_eq!(lib.indexed_file(path, info.mtime, info.size).unwrap(), Some(info.clone()));
+        // Changed on disk since
+        assert_eq!(lib.indexed_file(path, info.mtime + 1, info.size).unwrap(), None);
+        assert_eq!(lib.indexed_file(Path::new("/photos/b.nef"), info.mtime, info.size).unwrap(), None);
+
+        assert_eq!(day("2024-06-21 10:00:00"), Some(20240621));
+        assert_eq!(day("2024:06:21"), None);
+    }
+
+    #[test]
+    fn test_index_from_before_iso_and_day() {
+        let dir = scratch_dir("oldindex");
+        let path = dir.join("oldindex.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE file_index (path TEXT PRIMARY KEY NOT NULL, mtime INTEGER NOT NULL, size INTEGER NOT NULL,
+             width INTEGER, height INTEGER, taken TEXT, camera TEXT, lens TEXT, exposure TEXT, latitude REAL, longitude REAL);
+             INSERT INTO file_index (path, mtime, size, exposure) VALUES ('/photos/a.jpg', 1, 2, 'ISO 200');",
+        )
+        .unwrap();
+        drop(conn);
+        // Dropped to be read again with the new columns
+        let lib = Library::open_at(&path).unwrap();
+        assert_eq!(lib.indexed_file(Path::new("/photos/a.jpg"), 1, 2).unwrap(), None);
+        lib.store_file_info(Path::new("/photos/a.jpg"), &FileInfo { iso: Some(200), ..FileInfo::default() }).unwrap();
+        drop(lib);
+        assert_eq!(Library::open_at(&path).unwrap().indexed_file(Path::new("/photos/a.jpg"), 0, 0).unwrap().unwrap().iso, Some(200));
+    }
+
+    #[test]
+    fn test_catalog_search() {
+        let lib = Library::open_in_memory().unwrap();
+        let root = Path::new("/photos");
+        let file = |path: &str, taken: Option<&str>, camera: Option<&str>| {
+            let info = FileInfo { taken: taken.map(str::to_string), camera: camera.map(str::to_string), ..FileInfo::default() };
+            lib.store_file_info(Path::new(path), &info).unwrap();
+            lib.catalog_file(Path::new(path), root, &XmpData::default()).unwrap();
+        };
+        lib.in_transaction(|| {
+            file("/photos/japan/b.jpg", Some("2023-04-02 09:00:00"), Some("FUJIFILM X-T5"));
+            file("/photos/japan/a.jpg", Some("2023-04-03 18:30:00"), Some("FUJIFILM X-T5"));
+            file("/photos/home/c.nef", Some("2024-06-21 10:00:00"), Some("NIKON Z 6"));
+            file("/photos/home/scan_1.png", None, None);
+            Ok(())
+        })
+        .unwrap();
+        let night = FileInfo {
+            taken: Some("2024-06-21 22:40:00".to_string()),
+            camera: Some("NIKON Z 6".to_string()),
+            lens: Some("NIKKOR Z 85mm f/1.8 S".to_string()),
+            exposure: Some("1/60 s f/1.8 ISO 6400".to_string()),
+            iso: Some(6400),
+            ..FileInfo::default()
+        };
+        lib.store_file_info(Path::new("/photos/home/d.nef"), &night).unwrap();
+        let sidecar = XmpData {
+            description: Some("Lanterns on the river".to_string()),
+            title: Some("Midsummer".to_string()),
+            keywords: vec!["festival".to_string(), "night".to_string()],
+            rating: Some(4),
+            ..XmpData::default()
+        };
+        lib.catalog_file(Path::new("/photos/home/d.nef"), root, &sidecar).unwrap();
+        let search = |text: &str| -> Vec<String> {
+            let query = crate::catalog::Query::parse(text).unwrap().unwrap();
+            lib.search(&query).unwrap().iter().map(|p| p.to_string_lossy().into_owned()).collect()
+        };
+        assert_eq!(search("japan"), ["/photos/japan/b.jpg", "/photos/japan/a.jpg"]);
+        assert_eq!(search("x-t5 a.jpg"), ["/photos/japan/a.jpg"]);
+        assert_eq!(search("nikon"), ["/photos/home/c.nef", "/photos/home/d.nef"]);
+        assert_eq!(search("2023-04-03..2024"), ["/photos/japan/a.jpg", "/photos/home/c.nef", "/photos/home/d.nef"]);
+        assert_eq!(search("2024-06-21 home"), ["/photos/home/c.nef", "/photos/home/d.nef"]);
+        assert_eq!(search("lens:85mm iso:>3200"), ["/photos/home/d.nef"]);
+        assert_eq!(search("iso:<3200"), Vec::<String>::new());
+        assert_eq!(search(r#"camera:"z 6" rating:0"#), ["/photos/home/c.nef"]);
+        assert_eq!(search("rating:>=4"), ["/photos/home/d.nef"]);
+        assert_eq!(search("camera:fujifilm date:2023-04-02"), ["/photos/japan/b.jpg"]);
+        // LIKE wildcards are taken literally
+        assert_eq!(search("c_nef"), Vec::<String>::new());
+        assert_eq!(search("scan_1"), ["/photos/home/scan_1.png"]);
+        // Plain words are found in the note, title and keywords of the sidecar too
+        assert_eq!(search("lanterns"), ["/photos/home/d.nef"]);
+        assert_eq!(search("midsummer festival"), ["/photos/home/d.nef"]);
+        lib.catalog_file(Path::new("/photos/home/d.nef"), root, &XmpData::default()).unwrap();
+        assert_eq!(search("festival"), Vec::<String>::new());
+
+        lib.uncatalog(Path::new("/photos/japan")).unwrap();
+        assert_eq!(lib.catalog_count().unwrap(), 3);
+        lib.uncatalog_roots_except(&[PathBuf::from("/archive")]).unwrap();
+        assert_eq!(lib.cataloged(root).unwrap(), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn test_located_and_place_names() {
+        let lib = Library::open_in_memory().unwrap();
+        let root = Path::new("/photos");
+        let files = [("/photos/b.jpg", Some((45.76, 4.84))), ("/photos/a.jpg", None), ("/elsewhere/c.jpg", Some((1.0, 2.0)))];
+        for (path, position) in files {
+            lib.store_file_info(Path::new(path), &FileInfo { position, ..FileInfo::default() }).unwrap();
+            if path.starts_with("/photos") {
+                lib.catalog_file(Path::new(path), root, &XmpData::default()).unwrap();
+            }
+        }
+        // Only cataloged files with a position
+        assert_eq!(lib.located().unwrap(), vec![(PathBuf::from("/photos/b.jpg"), (45.76, 4.84))]);
+
+        assert_eq!(lib.place_name("45.76,4.84").unwrap(), None);
+        lib.store_place_name("45.76,4.84", "Lyon, France").unwrap();
+        lib.store_place_name("0.00,-30.00", "").unwrap();
+        assert_eq!(lib.place_name("45.76,4.84").unwrap().as_deref(), Some("Lyon, France"));
+        assert_eq!(lib.place_name("0.00,-30.00").unwrap().as_deref(), Some(""));
+        assert_eq!(lib.place_names_size().unwrap(), 10 + 12 + 11);
+        lib.clear_place_names().unwrap();
+        assert_eq!(lib.place_name("45.76,4.84").unwrap(), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! Where messages go: stderr, and a log file in the data folder, the only place to find
+//! them in release builds on Windows, which have no console. Levels come from the `[log]`
+//! section of the config, per subsystem, unless `--log` or `RUST_LOG` give a filter.
+//! The viewer logs through `tracing`; the `log` macros of its libraries, wgpu's among
+//! them, are passed on to it. Messages logged while an image is being opened, from
+//! whichever thread, are in the `load{n=N}` span of that open, see `progress::load_span`.
+
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LogConfig;
+
+/// Rotated log files kept besides the current one
+const KEEP: usize = 3;
+
+/// `momemtum.log` in the data folder.
+pub fn file_path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("momemtum").join("momemtum.log"))
+}
+
+/// Send what the viewer and its libraries log to stderr and, when the config asks for
+/// it, the log file. `filter` is the one given with `--log`.
+pub fn init(config: &LogConfig, filter: Option<&str>) {
+    let spec = filter_spec(config, filter, std::env::var("RUST_LOG").ok().as_deref());
+    let file = file_path().filter(|_| config.file).and_then(|path| match RotatingFile::open(&path, config.max_file_mb * 1024 * 1024) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Failed to open the log file {:?}: {}", path, e);
+            None
+        }
+    });
+    // Colors would end up in the file as escape codes
+    let (writer, ansi) = match file {
+        Some(file) => (BoxMakeWriter::new(Mutex::new(Tee(file))), false),
+        None => (BoxMakeWriter::new(std::io::stderr), std::io::stderr().is_terminal()),
+    };
+    let _ = subscriber(&spec, writer, ansi).try_init();
+}
+
+/// A subscriber logging what `spec` lets through to `writer`, each message after the
+/// spans it was logged in.
+fn subscriber(spec: &str, writer: BoxMakeWriter, ansi: bool) -> impl tracing::Subscriber + Send + Sync {
+    let filter = EnvFilter::try_new(spec).unwrap_or_else(|e| {
+        eprintln!("Invalid log filter {:?}: {}", spec, e);
+        EnvFilter::new("warn")
+    });
+    tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer).with_ansi(ansi).finish()
+}
+
+/// The filter to log with, in `RUST_LOG` syntax: the one given on the command line, else
+/// the environment's, else the config's. Libraries only log their warnings by default.
+fn filter_spec(config: &LogConfig, cli: Option<&str>, env: Option<&str>) -> String {
+    if let Some(spec) = cli.or(env).filter(|s| !s.trim().is_empty()) {
+        return spec.to_string();
+    }
+    let mut spec = format!("warn,momemtum={}", config.level);
+    for (subsystem, level) in &config.subsystems {
+        spec.push_str(&format!(",momemtum::{}={}", subsystem, level));
+    }
+    spec
+}
+
+/// Writes to stderr as well as the log file.
+struct Tee(RotatingFile);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let _ = std::io::stderr().write_all(buf);
+        self.0.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// A log file that is moved aside to `momemtum.1.log`, and so on up to `KEEP`, once it
+/// reaches its size limit.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl RotatingFile {
+    /// Append to the file at `path`, rotating it first if it is full already.
+    fn open(path: &Path, max_bytes: u64) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        let mut file = Self { path: path.to_path_buf(), file, written, max_bytes };
+        if file.full() {
+            file.rotate()?;
+        }
+        Ok(file)
+    }
+
+    fn full(&self) -> bool {
+        self.max_bytes > 0 && self.written >= self.max_bytes
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for n in (1..KEEP).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.full() {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// `momemtum.2.log` for `momemtum.log` and 2.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_filter_spec() {
+        let config = LogConfig {
+            level: "info".to_string(),
+            subsystems: std::collections::BTreeMap::from([("loader".to_string(), "debug".to_string())]),
+            ..LogConfig::default()
+        };
+        assert_eq!(filter_spec(&config, None, None), "warn,momemtum=info,momemtum::loader=debug");
+        assert_eq!(filter_spec(&config, None, Some("trace")), "trace");
+        assert_eq!(filter_spec(&config, Some("error"), Some("trace")), "error");
+        assert_eq!(filter_spec(&config, None, Some(" ")), "warn,momemtum=info,momemtum::loader=debug");
+    }
+
+    /// Lines logged in a test, shared with the subscriber writing them.
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_load_span() {
+        let captured = Captured::default();
+        let writer = {
+            let captured = captured.clone();
+            BoxMakeWriter::new(move || captured.clone())
+        };
+        let dispatch = tracing::Dispatch::new(subscriber("warn,momemtum=debug", writer, false));
+        tracing::dispatcher::with_default(&dispatch, || {
+            let span = crate::progress::load_span();
+            span.in_scope(|| tracing::debug!("Opening"));
+            // Threads the load is handed to log in it too
+            let (handed, dispatch) = (span.clone(), dispatch.clone());
+            std::thread::spawn(move || tracing::dispatcher::with_default(&dispatch, || handed.in_scope(|| tracing::warn!("Decoded"))))
+                .join()
+                .unwrap();
+            tracing::info!("Idle");
+            tracing::debug!(target: "wgpu_core", "Filtered out");
+        });
+        let text = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", text);
+        let n = lines[0].split("load{n=").nth(1).and_then(|s| s.split('}').next()).unwrap();
+        assert!(lines[0].contains("DEBUG") && lines[0].ends_with("momemtum::logging::tests: Opening"), "{}", lines[0]);
+        assert!(lines[1].contains(&format!("load{{n={}}}", n)) && lines[1].ends_with("Decoded"), "{}", lines[1]);
+        assert!(!lines[2].contains("load{") && lines[2].ends_with("Idle"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = scratch_dir("log");
+        let path = dir.join("momemtum.log");
+        let mut file = RotatingFile::open(&path, 10).unwrap();
+        for line in ["first line\n", "second line\n", "third line\n", "fourth line\n", "fifth line\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        drop(file);
+        let read = |n| std::fs::read_to_string(if n == 0 { path.clone() } else { rotated_path(&path, n) }).unwrap();
+        assert_eq!(read(0), "fifth line\n");
+        assert_eq!(read(1), "fourth line\n");
+        assert_eq!(read(3), "second line\n");
+        // Only KEEP rotated files are kept
+        assert!(!rotated_path(&path, KEEP + 1).exists());
+
+        // A full file from last time is rotated on opening
+        RotatingFile::open(&path, 10).unwrap();
+        assert_eq!(read(0), "");
+        assert_eq!(read(1), "fifth line\n");
+    }
+}
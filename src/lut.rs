@@ -0,0 +1,151 @@
+//! 3D lookup tables in the `.cube` format, for previewing log or flat images
+//! through a grading LUT. The table goes to the GPU as a 3D texture sampled by
+//! the image shader.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+/// Largest grid accepted; common LUTs are 17, 33 or 65 points per side
+const MAX_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut {
+    pub title: Option<String>,
+    /// Grid points per side
+    pub size: usize,
+    /// Input values mapped to the first and last grid points, per channel
+    pub domain: ([f32; 3], [f32; 3]),
+    /// Output colors, red varying fastest, then green, then blue
+    pub table: Vec<[f32; 3]>,
+}
+
+impl Lut {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain = ([0.0; 3], [1.0; 3]);
+        let mut table = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = || anyhow!("Invalid line {} of the LUT: {}", number + 1, line);
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword {
+                "TITLE" => title = Some(rest.trim().trim_matches('"').to_string()),
+                "LUT_3D_SIZE" => {
+                    let n: usize = rest.trim().parse().map_err(|_| error())?;
+                    if !(2..=MAX_SIZE).contains(&n) {
+                        return Err(anyhow!("Unsupported LUT size {}", n));
+                    }
+                    size = Some(n);
+                }
+                "LUT_1D_SIZE" => return Err(anyhow!("1D LUTs are not supported")),
+                "DOMAIN_MIN" => domain.0 = triple(rest).ok_or_else(error)?,
+                "DOMAIN_MAX" => domain.1 = triple(rest).ok_or_else(error)?,
+                // Other keywords (e.g. LUT_IN_VIDEO_RANGE) don't change the table
+                k if k.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+                _ => table.push(triple(line).ok_or_else(error)?),
+            }
+        }
+        let size = size.ok_or_else(|| anyhow!("Not a 3D LUT: LUT_3D_SIZE is missing"))?;
+        if table.len() != size * size * size {
+            return Err(anyhow!("Expected {} LUT entries, found {}", size * size * size, table.len()));
+        }
+        if (0..3).any(|c| domain.1[c] <= domain.0[c]) {
+            return Err(anyhow!("Invalid LUT domain"));
+        }
+        Ok(Self { title, size, domain, table })
+    }
+
+    /// The table as RGBA half-float bits, the layout of a 3D texture `size` texels on every side.
+    pub fn texels(&self) -> Vec<u16> {
+        self.table
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0].map(|v| half::f16::from_f32(v).to_bits()))
+            .collect()
+    }
+
+    /// A table that changes nothing, for when no LUT is loaded.
+    pub fn identity(size: usize) -> Self {
+        let step = |i: usize| i as f32 / (size - 1) as f32;
+        let mut table = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    table.push([step(r), step(g), step(b)]);
+                }
+            }
+        }
+        Self { title: None, size, domain: ([0.0; 3], [1.0; 3]), table }
+    }
+
+    /// Output color for `rgb`, by trilinear interpolation as on the GPU.
+    #[cfg(test)]
+    fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let n = self.size;
+        let mut index = [0; 3];
+        let mut frac = [0.0; 3];
+        for c in 0..3 {
+            let t = ((rgb[c] - self.domain.0[c]) / (self.domain.1[c] - self.domain.0[c])).clamp(0.0, 1.0) * (n - 1) as f32;
+            index[c] = (t.floor() as usize).min(n - 2);
+            frac[c] = t - index[c] as f32;
+        }
+        let at = |r, g, b| self.table[(index[2] + b) * n * n + (index[1] + g) * n + index[0] + r];
+        let mut out = [0.0; 3];
+        for (corner, weight) in (0..8).map(|i| {
+            let (r, g, b) = (i & 1, (i >> 1) & 1, (i >> 2) & 1);
+            let w = [r, g, b].iter().enumerate().map(|(c, &bit)| if bit == 1 { frac[c] } else { 1.0 - frac[c] }).product::<f32>();
+            (at(r, g, b), w)
+        }) {
+            for c in 0..3 {
+                out[c] += corner[c] * weight;
+            }
+        }
+        out
+    }
+}
+
+fn triple(text: &str) -> Option<[f32; 3]> {
+    let mut values = text.split_whitespace().map(|v| v.parse::<f32>().ok());
+    let triple = [values.next()??, values.next()??, values.next()??];
+    values.next().is_none().then_some(triple)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cube() {
+        // Inverts red and halves blue
+        let mut text = String::from("# comment\nTITLE \"Test\"\nLUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 1 1 1\n\n");
+        for b in 0..2 {
+            for g in 0..2 {
+                for r in 0..2 {
+                    text.push_str(&format!("{} {} {}\n", 1 - r, g, b as f32 / 2.0));
+                }
+            }
+        }
+        let lut = Lut::parse(&text).unwrap();
+        assert_eq!(lut.title.as_deref(), Some("Test"));
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.table[1], [0.0, 0.0, 0.0]);
+        assert_eq!(lut.apply([0.25, 0.5, 1.0]), [0.75, 0.5, 0.5]);
+        assert_eq!(lut.texels().len(), 8 * 4);
+
+        let identity = Lut::identity(17);
+        let out = identity.apply([0.3, 0.6, 0.9]);
+        assert!(out.iter().zip([0.3, 0.6, 0.9]).all(|(a, b)| (a - b).abs() < 1e-5));
+
+        assert!(Lut::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(Lut::parse("LUT_1D_SIZE 1024\n").is_err());
+        assert!(Lut::parse("LUT_3D_SIZE 2\n0 0 zero\n").is_err());
+    }
+}
@@ -1,27 +1,467 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod state;
-mod texture;
-mod loader;
-mod navigator;
-use state::State;
+use clap::Parser;
+use momemtum::state::State;
+use momemtum::{
+    cli, config, crash, exposure, instance, library, loader, logging, navigator, playlist, priority, progress,
+    proxy, pyramid, redraw, remote, sandbox, session, software, stats, sync, watcher,
+};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use winit::{
     event::*,
-    event_loop::EventLoopBuilder,
-    window::WindowBuilder,
+    event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy},
+    keyboard::ModifiersState,
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
-use crate::loader::LoadedImage;
+use momemtum::animation::Control;
+use momemtum::keymap::{Action, Keymap};
+use momemtum::loader::LoadedImage;
 
 #[derive(Debug)]
 enum AppEvent {
-    ImageLoaded(LoadedImage),
+    /// A decoded image, with the span of its load and the tile pyramid to stream when
+    /// it is too large for one texture
+    ImageLoaded(tracing::Span, Box<LoadedImage>, Option<std::sync::Arc<dyn pyramid::TileSource>>),
+    /// A file or folder forwarded by another invocation
+    OpenPath(PathBuf),
+    /// Files in the watched folder were created, modified or removed
+    FilesChanged(Vec<PathBuf>),
+    /// Bytes of an image being downloaded received so far, and its size if known
+    DownloadProgress(u64, Option<u64>),
+    /// A download failed, and why, with the span of the load it was for
+    DownloadFailed(tracing::Span, String),
+    /// How far the decode of the image being opened has got
+    LoadProgress(progress::Stage, Option<f32>),
+    /// An image couldn't be decoded, and why, with the span of its load
+    LoadFailed(tracing::Span, PathBuf, String),
+    /// Another synchronized window was panned or zoomed
+    SyncView(sync::View),
+    /// Background work has results to show
+    Wake,
+}
+
+/// How long the watched folder must be quiet before its changes are applied
+const SETTLE_DELAY: Duration = Duration::from_millis(250);
+
+/// Decode `path` in the background, downloading it first if it is a URL. What is logged
+/// about it, here and on the threads its events reach, is in a new load span.
+fn spawn_load(proxy: &EventLoopProxy<AppEvent>, path: PathBuf) {
+    crash::set_file(&path);
+    let load = progress::load_span();
+    load.in_scope(|| tracing::debug!("Opening {:?}", path));
+    let proxy = proxy.clone();
+    std::thread::spawn(move || load.clone().in_scope(|| {
+        let _pending = stats::Pending::start(&stats::LOADS);
+        let path = match path.to_str().filter(|p| remote::is_url(p)) {
+            Some(url) => {
+                let progress = |received, total| {
+                    let _ = proxy.send_event(AppEvent::DownloadProgress(received, total));
+                };
+                match remote::download(url, progress) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        let _ = proxy.send_event(AppEvent::DownloadFailed(load.clone(), format!("{:#}", e)));
+                        return;
+                    }
+                }
+            }
+            None => path,
+        };
+        let _interactive = priority::interactive();
+        let sink = {
+            let proxy = proxy.clone();
+            move |stage, fraction| {
+                let _ = proxy.send_event(AppEvent::LoadProgress(stage, fraction));
+            }
+        };
+        match progress::watch(sink, || pyramid::load(&path)) {
+            Ok((img, tiles)) => {
+                let _ = proxy.send_event(AppEvent::ImageLoaded(load.clone(), Box::new(img), tiles));
+            }
+            Err(e) => {
+                let _ = proxy.send_event(AppEvent::LoadFailed(load.clone(), path, format!("{:#}", e)));
+            }
+        }
+    }));
+}
+
+/// Carry out a key-bound action. Returns false when the application should exit.
+fn perform_action(action: Action, state: &mut State, proxy: &EventLoopProxy<AppEvent>) -> bool {
+    match action {
+        Action::Quit => return false,
+        Action::OpenFile => {
+            let picked = rfd::FileDialog::new()
+                .set_title("Open Image")
+                .add_filter("Images", navigator::SUPPORTED_EXTENSIONS)
+                .add_filter("Playlists", playlist::EXTENSIONS)
+                .set_parent(&state.window)
+                .pick_file();
+            if let Some(path) = picked.and_then(|path| state.open_path(&path)) {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::OpenFolder => {
+            let picked = rfd::FileDialog::new()
+                .set_title("Open Folder")
+                .set_parent(&state.window)
+                .pick_folder();
+            if let Some(path) = picked.and_then(|dir| state.open_path(&dir)) {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::NextImage => {
+            if let Some(path) = state.get_next_image() {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::PrevImage => {
+            if let Some(path) = state.get_prev_image() {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::ZoomIn => state.zoom_by(0.9),
+        Action::ZoomOut => state.zoom_by(1.1),
+        Action::ToggleFilmstrip => state.toggle_filmstrip(),
+        Action::ToggleGrid => state.toggle_grid(),
+        Action::ToggleMap => state.toggle_map(),
+        Action::CachePanel => state.toggle_cache_panel(),
+        Action::FirstImage | Action::LastImage => {
+            if let Some(path) = state.get_end_image(action == Action::LastImage) {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::GoTo => state.start_goto(),
+        Action::Delete => {
+            if let Some(path) = state.delete_current() {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::Rename => state.start_rename(),
+        Action::CycleTheme => state.cycle_theme(),
+        Action::ToggleMatte => state.toggle_matte(),
+        Action::ClearRating => state.rate(0),
+        Action::Rate1 => state.rate(1),
+        Action::Rate2 => state.rate(2),
+        Action::Rate3 => state.rate(3),
+        Action::Rate4 => state.rate(4),
+        Action::Rate5 => state.rate(5),
+        Action::TogglePick => state.toggle_pick(),
+        Action::ToggleReject => state.toggle_reject(),
+        Action::CycleFraming => state.cycle_framing(),
+        Action::NudgeLeft => state.nudge(-1.0, 0.0, false),
+        Action::NudgeRight => state.nudge(1.0, 0.0, false),
+        Action::NudgeUp => state.nudge(0.0, -1.0, false),
+        Action::NudgeDown => state.nudge(0.0, 1.0, false),
+        Action::NudgePixelLeft => state.nudge(-1.0, 0.0, true),
+        Action::NudgePixelRight => state.nudge(1.0, 0.0, true),
+        Action::NudgePixelUp => state.nudge(0.0, -1.0, true),
+        Action::NudgePixelDown => state.nudge(0.0, 1.0, true),
+        Action::SnapshotView => state.snapshot_view(),
+        Action::Crop => state.toggle_crop(),
+        Action::ToggleBasket => state.toggle_basket(),
+        Action::OpenBasket => {
+            if let Some(path) = state.open_basket() {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::ClearBasket => state.clear_basket(),
+        Action::ToggleFavorite => state.toggle_favorite(),
+        Action::RecentFiles => state.show_recent(),
+        Action::QuickOpen => state.quick_open(),
+        Action::OpenFavorites => {
+            if let Some(path) = state.open_favorites() {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::SavePlaylist if state.can_save_playlist() => {
+            let picked = rfd::FileDialog::new()
+                .set_title("Save Playlist")
+                .add_filter("Playlists", playlist::EXTENSIONS)
+                .set_file_name("playlist.m3u")
+                .set_parent(&state.window)
+                .save_file();
+            if let Some(path) = picked {
+                state.save_playlist(&path);
+            }
+        }
+        Action::SavePlaylist => {}
+        Action::RotateClockwise | Action::RotateCounterclockwise => {
+            if let Some(path) = state.rotate(action == Action::RotateClockwise) {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::SaveRotation => state.request_save_rotation(),
+        Action::Compare => state.toggle_compare(),
+        Action::CycleExportColorSpace => state.cycle_export_space(),
+        Action::Paste => {
+            if let Some(path) = state.pasted_path() {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::SyncViews => {
+            let link = if state.sync_enabled() {
+                None
+            } else {
+                let proxy = proxy.clone();
+                match sync::Link::open(move |view| {
+                    let _ = proxy.send_event(AppEvent::SyncView(view));
+                }) {
+                    Ok(link) => Some(link),
+                    Err(e) => {
+                        log::warn!("Failed to sync views: {:?}", e);
+                        None
+                    }
+                }
+            };
+            state.set_sync(link);
+        }
+        Action::Undo | Action::Redo => {
+            if let Some(path) = state.undo(action == Action::Redo) {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::PlayPause => state.control_animation(Control::PlayPause),
+        Action::PrevFrame | Action::NextFrame => state.control_animation(Control::Step(action == Action::NextFrame)),
+        Action::SlowerPlayback | Action::FasterPlayback => {
+            state.control_animation(Control::Speed(action == Action::FasterPlayback))
+        }
+        Action::ToggleLoop => state.control_animation(Control::Loop),
+        Action::Adjust => state.toggle_adjust(),
+        Action::ResetAdjustments => state.reset_adjustments(),
+        Action::ClippingWarning => state.toggle_exposure(exposure::Overlay::Clipping),
+        Action::FalseColor => state.toggle_exposure(exposure::Overlay::FalseColor),
+        Action::LoadLut | Action::ToggleLut if action == Action::LoadLut || !state.has_lut() => {
+            let picked = rfd::FileDialog::new()
+                .set_title("Load LUT")
+                .add_filter("3D LUT", &["cube"])
+                .set_parent(&state.window)
+                .pick_file();
+            if let Some(path) = picked {
+                state.load_lut(&path);
+            }
+        }
+        Action::LoadLut | Action::ToggleLut => state.toggle_lut(),
+        Action::ShowOriginal => state.show_original(true),
+        Action::BeforeAfter => state.toggle_before_after(),
+        Action::CopyCoordinates => state.copy_coordinates(),
+        Action::OpenLocation => state.open_location(),
+        Action::EditMetadata => state.start_metadata_edit(),
+        Action::ExportClean => state.request_export_clean(),
+        Action::Convert => state.start_convert(),
+        Action::FindDuplicates => state.find_duplicates(),
+        Action::ExifPanel => state.toggle_panel("exif"),
+        Action::Properties => state.toggle_panel("properties"),
+        Action::Diagnostics => state.toggle_panel("diagnostics"),
+        Action::CyclePresentMode => state.cycle_present_mode(),
+        Action::ExtractFrames => {
+            if state.can_extract_frames() {
+                let picked = rfd::FileDialog::new()
+                    .set_title("Save Frames To")
+                    .set_parent(&state.window)
+                    .pick_folder();
+                if let Some(dir) = picked {
+                    state.extract_frames(dir);
+                }
+            }
+        }
+        Action::EditNote => state.start_note_edit(),
+        Action::Redact => state.toggle_redaction(),
+        Action::TogglePrivate | Action::TogglePrivateFolder => {
+            if let Some(path) = state.toggle_private(action == Action::TogglePrivateFolder) {
+                spawn_load(proxy, path);
+            }
+        }
+        Action::UnlockPrivate => state.toggle_private_unlock(),
+        Action::CycleSort => state.cycle_sort(),
+        Action::ToggleRecursive => state.toggle_recursive(),
+        Action::ToggleShuffle => state.toggle_shuffle(),
+        Action::Filter => state.start_filter_edit(),
+        Action::SearchLibrary => state.start_library_search(),
+        Action::Places => state.show_places(),
+        Action::Timeline => state.cycle_timeline(),
+    }
+    true
+}
+
+/// Show images without a GPU: fitted to the window and stepped through with the
+/// navigation keys. Everything else needs the GPU path.
+fn run_software(
+    event_loop: EventLoop<AppEvent>,
+    window: &Window,
+    path: Option<&std::path::Path>,
+    keymap: &Keymap,
+    proxy: &EventLoopProxy<AppEvent>,
+) {
+    let mut canvas = match software::Canvas::new(window) {
+        Ok(canvas) => canvas,
+        Err(e) => {
+            log::error!("Software rendering unavailable: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    window.set_title("Momemtum Image Viewer (software rendering)");
+    crash::set_renderer("CPU (software rendering)".to_string());
+    let mut navigator = navigator::Navigator::new();
+    // Private images stay hidden here as well; this fallback has no way to unlock them
+    match library::Library::open() {
+        Ok(lib) => navigator.private_paths = lib.private_paths().unwrap_or_default(),
+        Err(e) => log::warn!("Failed to open library database: {:?}", e),
+    }
+    let open = |navigator: &mut navigator::Navigator, path: &std::path::Path| match path.is_dir() {
+        true => navigator.open_directory(path),
+        false => Some(path.to_path_buf()),
+    };
+    if let Some(path) = path.and_then(|p| open(&mut navigator, p)) {
+        spawn_load(proxy, path);
+    }
+    let mut modifiers = ModifiersState::empty();
+
+    event_loop.run(move |event, elwt| match event {
+        Event::UserEvent(AppEvent::ImageLoaded(load, loaded_image, _)) => load.in_scope(|| {
+            let loaded_image = *loaded_image;
+            navigator.update_file_list(&loaded_image.path);
+            canvas.set_image(loaded_image.image);
+            window.request_redraw();
+        }),
+        Event::UserEvent(AppEvent::OpenPath(path)) => {
+            if let Some(path) = open(&mut navigator, &path) {
+                spawn_load(proxy, path);
+            }
+        }
+        Event::UserEvent(AppEvent::LoadFailed(load, path, error)) => {
+            load.in_scope(|| tracing::warn!("Failed to decode {}: {}", path.display(), error));
+        }
+        Event::UserEvent(AppEvent::DownloadFailed(load, error)) => {
+            load.in_scope(|| tracing::warn!("Download failed: {}", error));
+        }
+        Event::WindowEvent { event, window_id } if window_id == window.id() => match event {
+            WindowEvent::CloseRequested => elwt.exit(),
+            WindowEvent::ModifiersChanged(new_modifiers) => {
+                modifiers = new_modifiers.state();
+            }
+            WindowEvent::Resized(_) => window.request_redraw(),
+            WindowEvent::RedrawRequested => {
+                let size = window.inner_size();
+                if let Err(e) = canvas.draw(size.width, size.height) {
+                    log::error!("Failed to draw: {:#}", e);
+                }
+            }
+            WindowEvent::DroppedFile(path) => {
+                if let Some(path) = open(&mut navigator, &path) {
+                    spawn_load(proxy, path);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: winit::keyboard::PhysicalKey::Code(keycode),
+                        ..
+                    },
+                ..
+            } => {
+                let wrap = navigator.wrap;
+                let target = match keymap.lookup(keycode, modifiers) {
+                    Some(Action::Quit) => {
+                        elwt.exit();
+                        None
+                    }
+                    Some(Action::NextImage) => navigator.advance(true, wrap),
+                    Some(Action::PrevImage) => navigator.advance(false, wrap),
+                    _ => None,
+                };
+                if let Some(path) = target {
+                    spawn_load(proxy, path);
+                }
+            }
+            _ => {}
+        },
+        _ => {}
+    }).unwrap();
 }
 
 fn main() {
-    env_logger::init();
+    let args = cli::Args::parse();
+    // The sandboxed decoder reads no config and writes no log, leaving the viewer's alone
+    if let Some(command @ cli::Command::DecodeWorker { .. }) = &args.command {
+        if let Err(e) = cli::run(command, args.kiosk, &config::Config::default()) {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let (mut config, config_problem) = config::Config::load(!args.kiosk);
+    logging::init(&config.log, args.log.as_deref());
+    if let Some(problem) = &config_problem {
+        log::warn!("{}", problem);
+    }
+    loader::set_demosaic(config.decode.demosaic.algorithm());
+    if let Some(command) = &args.command {
+        if let Err(e) = cli::run(command, args.kiosk, &config) {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    crash::install();
+    crash::offer_report();
     let event_loop = EventLoopBuilder::<AppEvent>::with_user_event().build().unwrap();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    if let Some(background) = &args.background {
+        config.view.background = background.clone();
+        config.view.theme = config::ThemeMode::Fixed;
+    }
+    sandbox::set_enabled(args.sandbox || config.decode.sandbox);
+    let screen_side = event_loop.primary_monitor().map(|m| m.size().width.max(m.size().height));
+    proxy::configure(config.decode.proxy_megapixels, screen_side);
+    remote::configure(config.cache.directory.clone(), config.cache.download_cache_mb);
+    let slideshow_interval = config.view.slideshow_interval.filter(|s| s.is_finite() && *s > 0.0).map(Duration::from_secs_f32);
+    priority::init();
+    let keymap = Keymap::new(&config.keybindings);
+    let event_loop_proxy = event_loop.create_proxy();
+    {
+        let proxy = std::sync::Mutex::new(event_loop_proxy.clone());
+        redraw::set_waker(move || {
+            if let Ok(proxy) = proxy.lock() {
+                let _ = proxy.send_event(AppEvent::Wake);
+            }
+        });
+    }
+
+    if config.window.single_instance {
+        match instance::claim(args.path.as_deref()) {
+            Ok(instance::Instance::Forwarded) => return,
+            Ok(instance::Instance::Primary(listener)) => {
+                let proxy = event_loop_proxy.clone();
+                instance::serve(listener, move |path| {
+                    let _ = proxy.send_event(AppEvent::OpenPath(path));
+                });
+            }
+            Ok(instance::Instance::Secondary) => {}
+            Err(e) => log::warn!("Single-instance mode unavailable: {}", e),
+        }
+    }
+
+    // A file named on the command line decodes while the window and GPU are set up
+    // instead of after; folders are scanned once the navigator exists
+    let early_load = args.path.clone().filter(|p| p.is_file() && !playlist::is_playlist(p));
+    if let Some(path) = &early_load {
+        spawn_load(&event_loop_proxy, path.clone());
+    }
+
+    let session = config.window.restore_session.then(session::Session::load);
+    let mut builder = WindowBuilder::new()
+        .with_inner_size(winit::dpi::LogicalSize::new(config.window.width, config.window.height))
+        .with_fullscreen(args.fullscreen.then_some(Fullscreen::Borderless(None)));
+    if let Some(geometry) = session.as_ref().and_then(|s| s.window) {
+        builder = builder
+            .with_inner_size(winit::dpi::PhysicalSize::new(geometry.width, geometry.height))
+            .with_position(winit::dpi::PhysicalPosition::new(geometry.x, geometry.y))
+            .with_maximized(geometry.maximized);
+    }
+    let window = builder.build(&event_loop).unwrap();
     window.set_title("Momemtum Image Viewer");
     
     // Set window icon from assets/icon.ico
@@ -49,100 +489,322 @@ fn main() {
         }
     }
 
-    let event_loop_proxy = event_loop.create_proxy();
+    let save_session = config.window.restore_session && !args.kiosk;
+    let mut state = match pollster::block_on(State::new(&window, config)) {
+        Ok(state) => state,
+        Err(e) => {
+            log::warn!("{:#}; drawing on the CPU instead", e);
+            let path = args.path.as_deref().filter(|_| early_load.is_none());
+            run_software(event_loop, &window, path, &keymap, &event_loop_proxy);
+            return;
+        }
+    };
+    state.read_only = args.kiosk;
+    state.set_navigation(args.sort, args.recursive);
+    state.open_catalog();
+    if args.shuffle {
+        state.set_shuffle(true);
+    }
+    if let Some(problem) = config_problem {
+        state.show_error(problem);
+    }
+    if let Some(speed) = args.auto_pan {
+        state.set_auto_pan(speed);
+    }
+    if let Some(path) = &early_load {
+        state.remember_recent(path);
+        state.load_progress(progress::Stage::Reading, None);
+    } else if let Some(path) = args.path.as_deref().and_then(|p| state.open_path(p)) {
+        spawn_load(&event_loop_proxy, path);
+    } else if let Some(mut session) = session {
+        // An explicit --sort wins over the one saved
+        if args.sort != navigator::SortOrder::default() {
+            session.sort = args.sort;
+        }
+        if let Some(path) = state.restore_session(session) {
+            spawn_load(&event_loop_proxy, path);
+        }
+    }
+    // Put the background and the first image's progress up right away rather than
+    // leaving the window blank until the event loop draws
+    state.update();
+    if let Err(e) = state.render() {
+        log::error!("{:?}", e);
+    }
+    let mut watcher = {
+        let proxy = event_loop_proxy.clone();
+        watcher::FolderWatcher::new(move |paths| {
+            let _ = proxy.send_event(AppEvent::FilesChanged(paths));
+        })
+        .map_err(|e| log::warn!("File watching unavailable: {:?}", e))
+        .ok()
+    };
+    let mut dropped: Vec<PathBuf> = Vec::new();
+    // Changes are applied once the folder has been quiet for a moment, so a
+    // file being written in several steps is reloaded only once
+    let mut pending_changes = HashSet::new();
+    let mut last_change = Instant::now();
+    let slideshow = args.slideshow.map(Duration::from_secs_f32).or(slideshow_interval);
+    let mut last_advance = Instant::now();
+    let mut modifiers = ModifiersState::empty();
+    let mut schedule = redraw::Schedule::default();
 
-    let mut state = pollster::block_on(State::new(&window));
+    // Taken out and built again when the device is lost
+    let mut gpu_state = Some(state);
 
     event_loop.run(move |event, elwt| {
+        let Some(state) = gpu_state.as_mut() else {
+            return;
+        };
+        let mut out_of_memory = false;
+        match &event {
+            Event::UserEvent(_) => schedule.invalidate(),
+            Event::WindowEvent { event, .. } if !matches!(event, WindowEvent::RedrawRequested) => schedule.invalidate(),
+            _ => {}
+        }
         match event {
-            Event::UserEvent(AppEvent::ImageLoaded(loaded_image)) => {
-                state.set_image(loaded_image);
+            Event::UserEvent(AppEvent::ImageLoaded(load, loaded_image, tiles)) => {
+                load.in_scope(|| state.set_image(*loaded_image, tiles));
+                if let (Some(watcher), Some((dir, recursive))) = (watcher.as_mut(), state.watch_target()) {
+                    watcher.watch(&dir, recursive);
+                }
+                // The slideshow interval counts from when an image is shown, so slow decodes don't eat into it
+                last_advance = Instant::now();
+            }
+            Event::UserEvent(AppEvent::OpenPath(path)) => {
+                if let Some(path) = state.open_path(&path) {
+                    spawn_load(&event_loop_proxy, path);
+                }
+                state.window.focus_window();
+            }
+            Event::UserEvent(AppEvent::DownloadProgress(received, total)) => {
+                state.download_progress(received, total);
+            }
+            Event::UserEvent(AppEvent::DownloadFailed(load, error)) => {
+                load.in_scope(|| state.download_failed(&error));
+            }
+            Event::UserEvent(AppEvent::LoadProgress(stage, fraction)) => {
+                state.load_progress(stage, fraction);
+            }
+            Event::UserEvent(AppEvent::LoadFailed(load, path, error)) => {
+                load.in_scope(|| state.load_failed(&path, &error));
+            }
+            Event::UserEvent(AppEvent::SyncView(view)) => {
+                state.apply_synced_view(view);
+            }
+            Event::UserEvent(AppEvent::FilesChanged(paths)) => {
+                pending_changes.extend(paths);
+                last_change = Instant::now();
             }
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == state.window.id() => {
-                if !state.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested => elwt.exit(),
-                        WindowEvent::KeyboardInput {
-                            event:
-                                KeyEvent {
-                                    state: ElementState::Pressed,
-                                    physical_key: winit::keyboard::PhysicalKey::Code(keycode),
-                                    ..
-                                },
-                            ..
-                        } => {
-                            match keycode {
-                                winit::keyboard::KeyCode::Escape => elwt.exit(),
-                                winit::keyboard::KeyCode::ArrowLeft => {
-                                    if let Some(path) = state.get_prev_image() {
-                                        let proxy = event_loop_proxy.clone();
-                                        std::thread::spawn(move || {
-                                            match crate::loader::load_image(&path) {
-                                                Ok(img) => {
-                                                    let _ = proxy.send_event(AppEvent::ImageLoaded(img));
-                                                }
-                                                Err(e) => {
-                                                    eprintln!("Failed to load image: {:?}", e);
-                                                }
-                                            }
-                                        });
-                                    }
-                                }
-                                winit::keyboard::KeyCode::ArrowRight => {
-                                    if let Some(path) = state.get_next_image() {
-                                        let proxy = event_loop_proxy.clone();
-                                        std::thread::spawn(move || {
-                                            match crate::loader::load_image(&path) {
-                                                Ok(img) => {
-                                                    let _ = proxy.send_event(AppEvent::ImageLoaded(img));
-                                                }
-                                                Err(e) => {
-                                                    eprintln!("Failed to load image: {:?}", e);
-                                                }
-                                            }
-                                        });
-                                    }
-                                }
-                                _ => {}
+            } if window_id == state.window.id() && !state.input(event) => {
+                match event {
+                    WindowEvent::CloseRequested => elwt.exit(),
+                    WindowEvent::KeyboardInput {
+                        event:
+                            key_event @ KeyEvent {
+                                state: ElementState::Pressed,
+                                physical_key: winit::keyboard::PhysicalKey::Code(keycode),
+                                ..
+                            },
+                        ..
+                    } => {
+                        if state.prompt_active() {
+                            if let Some(path) = state.prompt_input(key_event) {
+                                spawn_load(&event_loop_proxy, path);
+                            }
+                        } else if state.confirmation_active() {
+                            if let Some(path) = state.confirmation_input(key_event) {
+                                spawn_load(&event_loop_proxy, path);
+                            }
+                        } else if state.cache_panel_active() {
+                            state.cache_panel_input(*keycode);
+                        } else if state.redaction_active() {
+                            state.redaction_input(*keycode, modifiers);
+                        } else if state.crop_active() {
+                            state.crop_input(*keycode, modifiers);
+                        } else if state.adjust_active() {
+                            state.adjust_input(*keycode, modifiers);
+                        } else if state.compare_active() {
+                            if let Some(path) = state.compare_input(*keycode, modifiers) {
+                                spawn_load(&event_loop_proxy, path);
+                            }
+                        } else if state.duplicates_key(*keycode) {
+                            if let Some(path) = state.duplicates_input(*keycode) {
+                                spawn_load(&event_loop_proxy, path);
+                            }
+                        } else if state.grid_visible() {
+                            if let Some(path) = state.grid_input(*keycode, modifiers) {
+                                spawn_load(&event_loop_proxy, path);
+                            }
+                        } else if state.map_visible() {
+                            state.map_input(*keycode);
+                        } else if let Some((folder, mode)) = state.culling_target(*keycode, modifiers) {
+                            if let Some(path) = state.send_current(&folder, mode) {
+                                spawn_load(&event_loop_proxy, path);
+                            }
+                        } else if let Some(action) = keymap.lookup(*keycode, modifiers) {
+                            if !perform_action(action, state, &event_loop_proxy) {
+                                elwt.exit();
+                            }
+                            // Toggling recursion changes what needs watching
+                            if let (Some(watcher), Some((dir, recursive))) = (watcher.as_mut(), state.watch_target()) {
+                                watcher.watch(&dir, recursive);
                             }
                         }
-                        WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
-                        }
-                        WindowEvent::DroppedFile(path) => {
-                            let proxy = event_loop_proxy.clone();
-                            let path = path.to_owned();
-                            std::thread::spawn(move || {
-                                match crate::loader::load_image(&path) {
-                                    Ok(img) => {
-                                        let _ = proxy.send_event(AppEvent::ImageLoaded(img));
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Failed to load image: {:?}", e);
-                                    }
-                                }
-                            });
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                state: ElementState::Released,
+                                physical_key: winit::keyboard::PhysicalKey::Code(keycode),
+                                ..
+                            },
+                        ..
+                    } if keymap.lookup(*keycode, modifiers) == Some(Action::ShowOriginal) => {
+                        state.show_original(false);
+                    }
+                    WindowEvent::Focused(false) => state.show_original(false),
+                    WindowEvent::ModifiersChanged(new_modifiers) => {
+                        modifiers = new_modifiers.state();
+                    }
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    }
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } => {
+                        let item = state
+                            .filmstrip_item_at_cursor()
+                            .or_else(|| state.grid_item_at_cursor())
+                            .or_else(|| state.map_item_at_cursor());
+                        if let Some(path) = item {
+                            spawn_load(&event_loop_proxy, path);
                         }
-                        WindowEvent::RedrawRequested => {
-                            state.update();
-                            match state.render() {
-                                Ok(_) => {}
-                                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                                Err(wgpu::SurfaceError::OutOfMemory) => elwt.exit(),
-                                Err(e) => eprintln!("{:?}", e),
-                            }
+                    }
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: button @ (MouseButton::Back | MouseButton::Forward),
+                        ..
+                    } => {
+                        let action = if *button == MouseButton::Forward { Action::NextImage } else { Action::PrevImage };
+                        perform_action(action, state, &event_loop_proxy);
+                    }
+                    WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                        state.set_scale_factor(*scale_factor);
+                    }
+                    WindowEvent::ThemeChanged(theme) => {
+                        state.system_theme_changed(*theme);
+                    }
+                    WindowEvent::DroppedFile(path) => {
+                        // Links dragged from a browser arrive as the URL or a shortcut file
+                        let path = remote::dropped_url(path).map_or_else(|| path.to_owned(), PathBuf::from);
+                        // A drop of several files arrives as one event each; they're opened together below
+                        dropped.push(path);
+                    }
+                    WindowEvent::RedrawRequested => {
+                        let started = Instant::now();
+                        state.update();
+                        schedule.drawn(Instant::now());
+                        match state.render() {
+                            Ok(_) => {}
+                            // Reconfigure, e.g. after the window moved to a screen on another GPU
+                            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => state.resize(state.size),
+                            Err(wgpu::SurfaceError::Timeout) => state.window.request_redraw(),
+                            Err(wgpu::SurfaceError::OutOfMemory) => out_of_memory = true,
                         }
-                        _ => {}
+                        state.frame_drawn(started.elapsed());
                     }
+                    _ => {}
                 }
             }
             Event::AboutToWait => {
-                state.window.request_redraw();
+                if let Some(path) = state.take_picked() {
+                    spawn_load(&event_loop_proxy, path);
+                }
+                if !dropped.is_empty() {
+                    if let Some(path) = state.open_dropped(std::mem::take(&mut dropped)) {
+                        spawn_load(&event_loop_proxy, path);
+                    }
+                }
+                if !pending_changes.is_empty() && last_change.elapsed() >= SETTLE_DELAY {
+                    if let Some(path) = state.apply_file_changes(&pending_changes) {
+                        spawn_load(&event_loop_proxy, path);
+                    }
+                    pending_changes.clear();
+                }
+                if let Some(path) = state.duplicates_ready() {
+                    spawn_load(&event_loop_proxy, path);
+                }
+                if let Some(forward) = state.take_step() {
+                    let action = if forward { Action::NextImage } else { Action::PrevImage };
+                    perform_action(action, state, &event_loop_proxy);
+                }
+                if let Some(interval) = slideshow {
+                    if last_advance.elapsed() >= interval {
+                        last_advance = Instant::now();
+                        if let Some(path) = state.get_next_image_wrapping() {
+                            spawn_load(&event_loop_proxy, path);
+                        }
+                    }
+                }
+                // Sleep until something needs drawing or one of the timers is due
+                let now = Instant::now();
+                let mut wake_at = match schedule.next(state.next_redraw(), now) {
+                    // Paced by presenting frames instead
+                    _ if state.auto_panning() => {
+                        state.window.request_redraw();
+                        None
+                    }
+                    Some(at) if at <= now => {
+                        state.window.request_redraw();
+                        None
+                    }
+                    at => at,
+                };
+                if !pending_changes.is_empty() {
+                    wake_at = redraw::earliest(wake_at, Some(last_change + SETTLE_DELAY));
+                }
+                if let Some(interval) = slideshow {
+                    wake_at = redraw::earliest(wake_at, Some(last_advance + interval));
+                }
+                elwt.set_control_flow(wake_at.map_or(ControlFlow::Wait, ControlFlow::WaitUntil));
+            }
+            Event::LoopExiting if save_session => {
+                if let Err(e) = state.session().save() {
+                    log::warn!("Failed to save the session: {:?}", e);
+                }
             }
             _ => {}
         }
+        // A driver reset, a switch between GPUs or running out of GPU memory
+        if out_of_memory || state.device_lost() {
+            let old = gpu_state.take().expect("state taken");
+            match rebuild_state(old, &event_loop_proxy) {
+                Ok(state) => gpu_state = Some(state),
+                Err(e) => {
+                    log::error!("Failed to set up rendering again: {:#}", e);
+                    elwt.exit();
+                }
+            }
+        }
     }).unwrap();
 }
+
+/// Start over on a new device, carrying on from `state` whose device was lost or ran out
+/// of memory, and reload the image that was shown.
+fn rebuild_state<'a>(state: State<'a>, proxy: &EventLoopProxy<AppEvent>) -> anyhow::Result<State<'a>> {
+    log::warn!("Setting up rendering again");
+    // The old surface has to go before the window can present to a new one
+    let handover = state.hand_over();
+    let mut state = pollster::block_on(State::new(handover.window, handover.settings.clone()))?;
+    if let Some(path) = state.take_over(handover) {
+        spawn_load(proxy, path);
+    }
+    state.window.request_redraw();
+    Ok(state)
+}
@@ -0,0 +1,390 @@
+//! Map of where the folder's photos were taken, drawn on slippy-map tiles that
+//! are downloaded in the background and kept in a disk cache.
+
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::quad::Rect;
+
+pub const DEFAULT_TILE_URL: &str = "https://tile.openstreetmap.org/{z}/{x}/{y}.png";
+/// Credit shown while the map is open, as the OpenStreetMap tile policy requires.
+pub const ATTRIBUTION: &str = "Map data © OpenStreetMap contributors";
+const USER_AGENT: &str = concat!("Momemtum/", env!("CARGO_PKG_VERSION"));
+
+const TILE_PIXELS: f64 = 256.0;
+const MAX_ZOOM: u32 = 19;
+/// Zoom used for a single location, about a city block across the window
+const CLOSE_ZOOM: f64 = 16.0;
+// Public tile servers ask clients to keep concurrent downloads low
+const MAX_DOWNLOADS: usize = 2;
+const MAX_TILE_BYTES: u64 = 4 * 1024 * 1024;
+const MAX_GPU_TILES: usize = 256;
+pub const MARKER_SIZE: f32 = 12.0;
+
+/// Web Mercator position of a latitude/longitude in [0, 1] x [0, 1], origin top left.
+pub fn project(lat: f64, lon: f64) -> (f64, f64) {
+    // The projection is cut off where it becomes square
+    let lat = lat.clamp(-85.051_128_78, 85.051_128_78).to_radians();
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0;
+    ((lon + 180.0) / 360.0, y)
+}
+
+/// Fill `{z}`, `{x}` and `{y}` into a tile URL template.
+pub fn tile_url(template: &str, z: u32, x: u32, y: u32) -> String {
+    template
+        .replace("{z}", &z.to_string())
+        .replace("{x}", &x.to_string())
+        .replace("{y}", &y.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TileKey {
+    z: u32,
+    x: u32,
+    y: u32,
+}
+
+struct GpuTile {
+    texture: crate::texture::Texture,
+    bind_group: wgpu::BindGroup,
+    last_used: u64,
+}
+
+/// Default location of the tile cache.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("momemtum").join("map-tiles"))
+}
+
+fn fetch_tile(template: &str, cache: Option<&Path>, key: TileKey) -> Result<DynamicImage> {
+    let cached = cache.map(|dir| dir.join(key.z.to_string()).join(key.x.to_string()).join(format!("{}.png", key.y)));
+    if let Some(path) = cached.as_deref().filter(|p| p.is_file()) {
+        return decode_tile(path);
+    }
+
+    let response = ureq::get(&tile_url(template, key.z, key.x, key.y))
+        .set("User-Agent", USER_AGENT)
+        .call()?;
+    let mut bytes = Vec::new();
+    response.into_reader().take(MAX_TILE_BYTES).read_to_end(&mut bytes)?;
+
+    let Some(path) = cached else {
+        return Ok(image::load_from_memory(&bytes)?);
+    };
+    let dir = path.parent().ok_or_else(|| anyhow!("Invalid tile path"))?;
+    std::fs::create_dir_all(dir)?;
+    // Write to a temporary file and rename, so a cancelled download never leaves half a tile
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&tmp, &bytes)?;
+    std::fs::rename(&tmp, &path)?;
+    decode_tile(&path)
+}
+
+/// Tiles come from the network, so they get the same decoder isolation as images.
+fn decode_tile(path: &Path) -> Result<DynamicImage> {
+    if crate::sandbox::enabled() {
+        Ok(crate::sandbox::load(path)?.image)
+    } else {
+        Ok(image::open(path)?)
+    }
+}
+
+/// Full-window map with a marker for every image in the folder that has a GPS position.
+pub struct MapView {
+    pub visible: bool,
+    /// Web Mercator position at the middle of the window
+    center: (f64, f64),
+    zoom: f64,
+    // Set once the user pans or zooms; until then the view follows the markers
+    moved: bool,
+    markers: Vec<(PathBuf, (f64, f64))>,
+    located: HashSet<PathBuf>,
+    position_sender: Sender<(PathBuf, Option<(f64, f64)>)>,
+    position_receiver: Receiver<(PathBuf, Option<(f64, f64)>)>,
+    tile_url: String,
+    cache: Option<PathBuf>,
+    resident: HashMap<TileKey, GpuTile>,
+    in_flight: HashSet<TileKey>,
+    failed: HashSet<TileKey>,
+    tile_sender: Sender<(TileKey, Result<DynamicImage>)>,
+    tile_receiver: Receiver<(TileKey, Result<DynamicImage>)>,
+    frame: u64,
+}
+
+impl MapView {
+    /// Tiles are cached in `cache_dir`, in a folder per tile server so switching
+    /// servers never mixes their tiles.
+    pub fn new(tile_url: &str, cache_dir: Option<PathBuf>) -> Self {
+        let (position_sender, position_receiver) = channel();
+        let (tile_sender, tile_receiver) = channel();
+        Self {
+            visible: false,
+            center: (0.5, 0.5),
+            zoom: 1.0,
+            moved: false,
+            markers: Vec::new(),
+            located: HashSet::new(),
+            position_sender,
+            position_receiver,
+            tile_url: tile_url.to_string(),
+            cache: cache_dir.map(|dir| dir.join(format!("{:x}", md5::compute(tile_url.as_bytes())))),
+            resident: HashMap::new(),
+            in_flight: HashSet::new(),
+            failed: HashSet::new(),
+            tile_sender,
+            tile_receiver,
+            frame: 0,
+        }
+    }
+
+    pub fn show(&mut self) {
+        self.visible = true;
+        self.moved = false;
+    }
+
+    pub fn marker_count(&self) -> usize {
+        self.markers.len()
+    }
+
+    /// Look up the GPS positions of `paths` not seen before, and drop markers
+    /// of images that are no longer in the list.
+    pub fn locate(&mut self, paths: &[PathBuf]) {
+        let wanted: HashSet<&PathBuf> = paths.iter().collect();
+        self.markers.retain(|(p, _)| wanted.contains(p));
+        self.located.retain(|p| wanted.contains(p));
+        for path in paths {
+            if self.located.insert(path.clone()) {
+                let sender = self.position_sender.clone();
+                let path = path.clone();
+                rayon::spawn(move || {
+                    let position = crate::gps::read_position(&path);
+                    let _ = sender.send((path, position));
+                    crate::redraw::wake();
+                });
+            }
+        }
+    }
+
+    /// Collect positions and tiles finished in the background, and request the
+    /// tiles covering a `width` x `height` window.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout, width: f32, height: f32) {
+        let mut new_markers = false;
+        for (path, position) in self.position_receiver.try_iter() {
+            if let (Some((lat, lon)), true) = (position, self.located.contains(&path)) {
+                self.markers.push((path, project(lat, lon)));
+                new_markers = true;
+            }
+        }
+        if new_markers && !self.moved {
+            self.fit(width, height);
+        }
+
+        self.frame += 1;
+        for (key, result) in self.tile_receiver.try_iter() {
+            self.in_flight.remove(&key);
+            match result.and_then(|img| crate::texture::Texture::from_image(device, queue, &img, Some("Map tile"))) {
+                Ok(texture) => {
+                    let bind_group = texture.create_bind_group(device, layout);
+                    self.resident.insert(key, GpuTile { texture, bind_group, last_used: self.frame });
+                }
+                Err(e) => {
+                    log::warn!("Failed to load map tile {:?}: {:?}", key, e);
+                    self.failed.insert(key);
+                }
+            }
+        }
+
+        for (key, _) in self.visible_tiles(width, height) {
+            if let Some(tile) = self.resident.get_mut(&key) {
+                tile.last_used = self.frame;
+            } else if !self.in_flight.contains(&key) && !self.failed.contains(&key) && self.in_flight.len() < MAX_DOWNLOADS {
+                self.in_flight.insert(key);
+                let sender = self.tile_sender.clone();
+                let (template, cache) = (self.tile_url.clone(), self.cache.clone());
+                std::thread::spawn(move || {
+                    let _ = sender.send((key, fetch_tile(&template, cache.as_deref(), key)));
+                    crate::redraw::wake();
+                });
+            }
+        }
+
+        // Evict least recently used tiles, never the ones on screen
+        if self.resident.len() > MAX_GPU_TILES {
+            let mut by_age: Vec<(u64, TileKey)> = self
+                .resident
+                .iter()
+                .filter(|(_, t)| t.last_used < self.frame)
+                .map(|(k, t)| (t.last_used, *k))
+                .collect();
+            by_age.sort_unstable_by_key(|(used, _)| *used);
+            let excess = self.resident.len() - MAX_GPU_TILES;
+            for (_, key) in by_age.into_iter().take(excess) {
+                self.resident.remove(&key);
+            }
+        }
+    }
+
+    /// GPU memory of the resident tiles.
+    pub fn gpu_bytes(&self) -> u64 {
+        self.resident.values().map(|t| t.texture.bytes()).sum()
+    }
+
+    /// Drop every resident tile not on screen; they come back from the disk cache.
+    pub fn trim(&mut self) {
+        let frame = self.frame;
+        self.resident.retain(|_, t| t.last_used >= frame);
+    }
+
+    /// Window pixels per Web Mercator unit.
+    fn scale(&self) -> f64 {
+        TILE_PIXELS * 2f64.powf(self.zoom)
+    }
+
+    fn to_screen(&self, world: (f64, f64), width: f32, height: f32) -> (f32, f32) {
+        let scale = self.scale();
+        (
+            ((world.0 - self.center.0) * scale) as f32 + width / 2.0,
+            ((world.1 - self.center.1) * scale) as f32 + height / 2.0,
+        )
+    }
+
+    /// Tiles covering the window at the zoom level closest to the view, with their screen rectangles.
+    fn visible_tiles(&self, width: f32, height: f32) -> Vec<(TileKey, Rect)> {
+        let z = (self.zoom.round().max(0.0) as u32).min(MAX_ZOOM);
+        let n = 1u32 << z;
+        let scale = self.scale();
+        let range = |center: f64, extent: f32| {
+            let lo = ((center - extent as f64 / 2.0 / scale) * n as f64).floor().max(0.0) as u32;
+            let hi = ((center + extent as f64 / 2.0 / scale) * n as f64).floor().max(0.0) as u32;
+            lo.min(n - 1)..=hi.min(n - 1)
+        };
+        let size = (scale / n as f64) as f32;
+        let mut tiles = Vec::new();
+        for y in range(self.center.1, height) {
+            for x in range(self.center.0, width) {
+                let (sx, sy) = self.to_screen((x as f64 / n as f64, y as f64 / n as f64), width, height);
+                tiles.push((TileKey { z, x, y }, Rect::new(sx, sy, size, size)));
+            }
+        }
+        tiles
+    }
+
+    /// Downloaded tiles on screen, ready to draw.
+    pub fn tiles(&self, width: f32, height: f32) -> Vec<(Rect, &wgpu::BindGroup)> {
+        self.visible_tiles(width, height)
+            .into_iter()
+            .filter_map(|(key, rect)| Some((rect, &self.resident.get(&key)?.bind_group)))
+            .collect()
+    }
+
+    /// Marker positions in window pixels.
+    pub fn markers(&self, width: f32, height: f32) -> Vec<(&Path, (f32, f32))> {
+        self.markers.iter().map(|(p, world)| (p.as_path(), self.to_screen(*world, width, height))).collect()
+    }
+
+    /// The image whose marker is under window position (`x`, `y`), the nearest if several overlap.
+    pub fn marker_at(&self, width: f32, height: f32, x: f32, y: f32) -> Option<PathBuf> {
+        self.markers(width, height)
+            .into_iter()
+            .map(|(p, (mx, my))| (p, (mx - x).hypot(my - y)))
+            .filter(|(_, d)| *d <= MARKER_SIZE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(p, _)| p.to_path_buf())
+    }
+
+    /// Show all markers.
+    pub fn fit(&mut self, width: f32, height: f32) {
+        let Some(first) = self.markers.first().map(|(_, w)| *w) else {
+            return;
+        };
+        let (mut lo, mut hi) = (first, first);
+        for (_, (x, y)) in &self.markers {
+            lo = (lo.0.min(*x), lo.1.min(*y));
+            hi = (hi.0.max(*x), hi.1.max(*y));
+        }
+        self.center = ((lo.0 + hi.0) / 2.0, (lo.1 + hi.1) / 2.0);
+        let margin = 4.0 * MARKER_SIZE as f64;
+        let fit = |extent: f32, span: f64| ((extent as f64 - 2.0 * margin).max(1.0) / (span * TILE_PIXELS)).log2();
+        self.zoom = fit(width, hi.0 - lo.0).min(fit(height, hi.1 - lo.1)).clamp(0.0, CLOSE_ZOOM);
+    }
+
+    pub fn pan_by(&mut self, dx: f32, dy: f32) {
+        let scale = self.scale();
+        self.center.0 = (self.center.0 - dx as f64 / scale).clamp(0.0, 1.0);
+        self.center.1 = (self.center.1 - dy as f64 / scale).clamp(0.0, 1.0);
+        self.moved = true;
+    }
+
+    /// Zoom by `steps` levels keeping the point under window position (`x`, `y`) in place.
+    pub fn zoom_at(&mut self, steps: f64, x: f32, y: f32, width: f32, height: f32) {
+        let (dx, dy) = (x - width / 2.0, y - height / 2.0);
+        self.pan_by(-dx, -dy);
+        self.zoom = (self.zoom + steps).clamp(0.0, MAX_ZOOM as f64);
+        self.pan_by(dx, dy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project() {
+        assert_eq!(project(0.0, 0.0), (0.5, 0.5));
+        let (x, y) = project(85.0511287798, -180.0);
+        assert_eq!(x, 0.0);
+        assert!(y.abs() < 1e-9);
+        // South of the equator is further down
+        assert!(project(-33.9, 151.2).1 > 0.5);
+        assert_eq!(
+            tile_url(DEFAULT_TILE_URL, 3, 4, 5),
+            "https://tile.openstreetmap.org/3/4/5.png"
+        );
+    }
+
+    #[test]
+    fn test_fit_and_markers() {
+        let (w, h) = (800.0, 600.0);
+        let mut map = MapView::new(DEFAULT_TILE_URL, None);
+        map.markers = vec![
+            (PathBuf::from("a.jpg"), project(48.85, 2.35)),
+            (PathBuf::from("b.jpg"), project(51.51, -0.13)),
+        ];
+        map.fit(w, h);
+        for (_, (x, y)) in map.markers(w, h) {
+            assert!(x >= 0.0 && x <= w && y >= 0.0 && y <= h, "{} {}", x, y);
+        }
+        let (_, (x, y)) = map.markers(w, h)[1];
+        assert_eq!(map.marker_at(w, h, x + 2.0, y - 2.0), Some(PathBuf::from("b.jpg")));
+        assert_eq!(map.marker_at(w, h, x + 50.0, y), None);
+
+        // Zooming keeps the point under the cursor in place
+        map.zoom_at(1.5, x, y, w, h);
+        let (_, (x2, y2)) = map.markers(w, h)[1];
+        assert!((x2 - x).abs() < 0.01 && (y2 - y).abs() < 0.01);
+
+        // A single spot is shown up close
+        map.markers.truncate(1);
+        map.fit(w, h);
+        assert_eq!(map.zoom, CLOSE_ZOOM);
+    }
+
+    #[test]
+    fn test_visible_tiles() {
+        let mut map = MapView::new(DEFAULT_TILE_URL, None);
+        // The whole world fits a 256 pixel window at zoom 0
+        map.zoom = 0.0;
+        let tiles = map.visible_tiles(256.0, 256.0);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].1, Rect::new(0.0, 0.0, 256.0, 256.0));
+
+        // Four tiles meet in the middle of the window
+        map.zoom = 3.0;
+        let keys: Vec<TileKey> = map.visible_tiles(100.0, 100.0).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys.len(), 4);
+        assert!(keys.iter().all(|k| k.z == 3 && (3..=4).contains(&k.x) && (3..=4).contains(&k.y)));
+    }
+}
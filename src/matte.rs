@@ -0,0 +1,37 @@
+//! Print-preview matte: a plain border framing the image.
+
+use crate::quad::Rect;
+
+/// The four strips of a border `width` wide (a fraction of the image's
+/// shorter side) around `image`, top and bottom spanning the corners.
+pub fn frame(image: Rect, width: f32) -> [Rect; 4] {
+    let b = width * image.w.abs().min(image.h.abs());
+    [
+        Rect::new(image.x - b, image.y - b, image.w + 2.0 * b, b),
+        Rect::new(image.x - b, image.y + image.h, image.w + 2.0 * b, b),
+        Rect::new(image.x - b, image.y, b, image.h),
+        Rect::new(image.x + image.w, image.y, b, image.h),
+    ]
+}
+
+/// Width and height of the framed image relative to the image alone.
+pub fn framed_scale(image_w: f32, image_h: f32, width: f32) -> (f32, f32) {
+    let b = width * image_w.min(image_h);
+    ((image_w + 2.0 * b) / image_w, (image_h + 2.0 * b) / image_h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        let [top, bottom, left, right] = frame(Rect::new(100.0, 50.0, 400.0, 200.0), 0.1);
+        assert_eq!((top.x, top.y, top.w, top.h), (80.0, 30.0, 440.0, 20.0));
+        assert_eq!((bottom.y, bottom.h), (250.0, 20.0));
+        assert_eq!((left.x, left.w, left.h), (80.0, 20.0, 200.0));
+        assert_eq!(right.x, 500.0);
+
+        assert_eq!(framed_scale(400.0, 200.0, 0.1), (1.1, 1.2));
+    }
+}
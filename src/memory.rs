@@ -0,0 +1,111 @@
+//! What the viewer holds in memory: decoded pixels kept on the CPU and textures on
+//! the GPU, by what they are for. `State` adds it up after every update, shows it in
+//! the diagnostics panel and, past the configured budget, drops what can be loaded
+//! again.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// The texture of the image shown, or the overview of a tiled one
+    Image,
+    /// Decoded frames of an animation
+    Animation,
+    /// Levels of a pyramid generated in memory for a large image
+    Pyramid,
+    /// Tiles of a large image on the GPU
+    Tiles,
+    Thumbnails,
+    /// Map tiles on the GPU
+    Map,
+    /// Images and labels of the compare view
+    Compare,
+}
+
+impl Category {
+    pub const ALL: [Category; 7] = [
+        Category::Image,
+        Category::Animation,
+        Category::Pyramid,
+        Category::Tiles,
+        Category::Thumbnails,
+        Category::Map,
+        Category::Compare,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::Image => "Image",
+            Category::Animation => "Animation",
+            Category::Pyramid => "Pyramid",
+            Category::Tiles => "Tiles",
+            Category::Thumbnails => "Thumbnails",
+            Category::Map => "Map",
+            Category::Compare => "Compare",
+        }
+    }
+}
+
+/// Bytes of an RGBA8 image or texture of `width` x `height` pixels.
+pub fn rgba_bytes(width: u32, height: u32) -> u64 {
+    width as u64 * height as u64 * 4
+}
+
+/// Bytes in use, on the CPU and on the GPU, per category.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+    bytes: [(u64, u64); Category::ALL.len()],
+}
+
+impl Usage {
+    pub fn add_cpu(&mut self, category: Category, bytes: u64) {
+        self.bytes[category as usize].0 += bytes;
+    }
+
+    pub fn add_gpu(&mut self, category: Category, bytes: u64) {
+        self.bytes[category as usize].1 += bytes;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bytes.iter().map(|(cpu, gpu)| cpu + gpu).sum()
+    }
+
+    /// Whether more is in use than `budget_mb` allows; a budget of 0 is no limit.
+    pub fn over(&self, budget_mb: u64) -> bool {
+        budget_mb > 0 && self.total() > budget_mb * 1024 * 1024
+    }
+
+    /// Each category holding anything, with its CPU and GPU bytes.
+    pub fn rows(&self) -> Vec<(Category, u64, u64)> {
+        Category::ALL
+            .iter()
+            .map(|&c| (c, self.bytes[c as usize].0, self.bytes[c as usize].1))
+            .filter(|(_, cpu, gpu)| cpu + gpu > 0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_and_budget() {
+        let mut usage = Usage::default();
+        assert!(usage.rows().is_empty());
+        usage.add_gpu(Category::Image, rgba_bytes(6000, 4000));
+        usage.add_gpu(Category::Thumbnails, rgba_bytes(256, 256) * 100);
+        usage.add_cpu(Category::Animation, 1024);
+        usage.add_gpu(Category::Thumbnails, 1);
+        assert_eq!(usage.total(), 96_000_000 + 26_214_400 + 1024 + 1);
+        assert_eq!(
+            usage.rows(),
+            vec![
+                (Category::Image, 0, 96_000_000),
+                (Category::Animation, 1024, 0),
+                (Category::Thumbnails, 0, 26_214_401),
+            ]
+        );
+        assert!(usage.over(100));
+        assert!(!usage.over(200));
+        assert!(!usage.over(0));
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::{anyhow, Result};
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+
+use crate::export::{ColorSpace, Metadata};
+
+/// Compare mode shows at most this many images side by side.
+pub const MAX_IMAGES: usize = 4;
+/// Height every panel is scaled to, capped so montages stay shareable.
+const MAX_PANEL_HEIGHT: u32 = 1600;
+const GAP: u32 = 16;
+pub const BACKGROUND: Rgb<u8> = Rgb([24, 24, 24]);
+const LABEL_COLOR: Rgb<u8> = Rgb([230, 230, 230]);
+
+/// Composite the images left to right at a common height, optionally with a
+/// caption under each panel.
+pub fn compose(images: &[RgbImage], labels: Option<&[String]>) -> RgbImage {
+    let height = images
+        .iter()
+        .map(|img| img.height())
+        .min()
+        .unwrap_or(1)
+        .clamp(1, MAX_PANEL_HEIGHT);
+    let panels: Vec<RgbImage> = images
+        .iter()
+        .map(|img| {
+            let width = ((img.width() as u64 * height as u64) / img.height().max(1) as u64).max(1) as u32;
+            if img.dimensions() == (width, height) {
+                img.clone()
+            } else {
+                imageops::resize(img, width, height, imageops::FilterType::Lanczos3)
+            }
+        })
+        .collect();
+
+    let scale = label_scale(height);
+    let label_height = match labels {
+        Some(_) => GLYPH_HEIGHT * scale + GAP,
+        None => 0,
+    };
+    let width = panels.iter().map(|p| p.width()).sum::<u32>() + GAP * (panels.len() as u32 + 1);
+    let mut canvas = RgbImage::from_pixel(width, height + label_height + GAP * 2, BACKGROUND);
+
+    let mut x = GAP;
+    for (i, panel) in panels.iter().enumerate() {
+        imageops::replace(&mut canvas, panel, x as i64, GAP as i64);
+        if let Some(text) = labels.and_then(|l| l.get(i)) {
+            let text_width = text_width(text, scale).min(panel.width());
+            let text_x = x + (panel.width() - text_width) / 2;
+            draw_text(&mut canvas, text, text_x, GAP + height + GAP / 2, scale, panel.width());
+        }
+        x += panel.width() + GAP;
+    }
+    canvas
+}
+
+/// Load 2 to 4 images and write their montage to `output`. Labels are the file names.
+pub fn save(paths: &[PathBuf], labels: bool, output: &Path, space: ColorSpace) -> Result<()> {
+    if paths.len() < 2 || paths.len() > MAX_IMAGES {
+        return Err(anyhow!("A montage needs between 2 and {} images", MAX_IMAGES));
+    }
+    let images = paths
+        .iter()
+        .map(|path| crate::loader::load_image(path).map(|loaded| loaded.image.to_rgb8()))
+        .collect::<Result<Vec<_>>>()?;
+    let names: Vec<String> = paths
+        .iter()
+        .map(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default())
+        .collect();
+    let montage = compose(&images, labels.then_some(names.as_slice()));
+
+    crate::export::save(&DynamicImage::ImageRgb8(montage), output, space, &Metadata::default())
+}
+
+const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+
+/// Caption size relative to the panel height; one font pixel per 200 image pixels.
+fn label_scale(height: u32) -> u32 {
+    (height / 200).clamp(1, 6)
+}
+
+/// `text` on a dark backing, to draw over the viewer.
+pub fn label(text: &str, scale: u32) -> RgbImage {
+    let pad = 2 * scale;
+    let width = text_width(text, scale);
+    let mut canvas = RgbImage::from_pixel(width + pad * 2, label_height(scale), BACKGROUND);
+    draw_text(&mut canvas, text, pad, pad, scale, width);
+    canvas
+}
+
+/// Height in pixels of the images `label` draws at `scale`.
+pub fn label_height(scale: u32) -> u32 {
+    GLYPH_HEIGHT * scale + 4 * scale
+}
+
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * (GLYPH_WIDTH + 1) * scale
+}
+
+/// Draw `text` with the built-in 5x7 font, clipped to `max_width`.
+pub fn draw_text(canvas: &mut RgbImage, text: &str, x: u32, y: u32, scale: u32, max_width: u32) {
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    for (i, c) in text.chars().enumerate() {
+        let cx = x + i as u32 * advance;
+        if cx + GLYPH_WIDTH * scale > x + max_width {
+            break;
+        }
+        let rows = glyph(c);
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = cx + col * scale + dx;
+                        let py = y + row as u32 * scale + dy;
+                        if px < canvas.width() && py < canvas.height() {
+                            canvas.put_pixel(px, py, LABEL_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a 5x7 glyph, most significant bit on the left. Lowercase letters
+/// are drawn as capitals and unknown characters as a box.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0; 7],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        _ => [0x1F, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1F],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_layout() {
+        let a = RgbImage::from_pixel(200, 100, Rgb([255, 0, 0]));
+        let b = RgbImage::from_pixel(100, 200, Rgb([0, 0, 255]));
+        let montage = compose(&[a, b], None);
+
+        // Both panels are scaled to the smaller height: 200x100 and 50x100
+        assert_eq!(montage.height(), 100 + GAP * 2);
+        assert_eq!(montage.width(), 200 + 50 + GAP * 3);
+        assert_eq!(*montage.get_pixel(GAP + 10, GAP + 10), Rgb([255, 0, 0]));
+        assert_eq!(*montage.get_pixel(GAP * 2 + 200 + 10, GAP + 10), Rgb([0, 0, 255]));
+        assert_eq!(*montage.get_pixel(GAP + 200 + 2, GAP + 10), BACKGROUND);
+    }
+
+    #[test]
+    fn test_compose_labels() {
+        let a = RgbImage::from_pixel(100, 100, Rgb([0, 0, 0]));
+        let labels = vec!["A".to_string(), "B".to_string()];
+        let montage = compose(&[a.clone(), a], Some(&labels));
+
+        assert_eq!(montage.height(), 100 + GLYPH_HEIGHT + GAP * 3);
+        let caption_row = GAP + 100 + GAP / 2;
+        let lit = (0..montage.width())
+            .flat_map(|x| (caption_row..caption_row + GLYPH_HEIGHT).map(move |y| (x, y)))
+            .filter(|&(x, y)| *montage.get_pixel(x, y) == LABEL_COLOR)
+            .count();
+        assert!(lit > 0);
+    }
+}
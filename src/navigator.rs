@@ -1,8 +1,110 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 
+/// Order of the images within a folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// File name, with runs of digits compared as numbers (IMG_9 before IMG_10)
+    #[default]
+    Name,
+    /// File modification time, oldest first
+    #[value(alias = "date")]
+    Modified,
+    /// File creation time, oldest first, where the file system records it
+    Created,
+    /// File size, smallest first
+    Size,
+    /// EXIF capture time, oldest first, once the folder is indexed; files without one go last
+    #[value(alias = "taken")]
+    Captured,
+    /// Shuffled, but stable for the session so rescans don't reorder the folder
+    Random,
+}
+
+impl SortOrder {
+    pub const ALL: &'static [SortOrder] =
+        &[SortOrder::Name, SortOrder::Modified, SortOrder::Created, SortOrder::Size, SortOrder::Captured, SortOrder::Random];
+
+    /// The next mode, for cycling through them with a single key.
+    pub fn next(self) -> Self {
+        let pos = Self::ALL.iter().position(|s| *s == self).unwrap_or(0);
+        Self::ALL[(pos + 1) % Self::ALL.len()]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortOrder::Name => "name",
+            SortOrder::Modified => "modified",
+            SortOrder::Created => "created",
+            SortOrder::Size => "size",
+            SortOrder::Captured => "captured",
+            SortOrder::Random => "random",
+        }
+    }
+}
+
+/// A hand-picked list of images browsed in place of a folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collection {
+    Basket,
+    Favorites,
+    Playlist,
+    /// Several files and folders dropped on the window at once
+    Dropped,
+    /// The results of a library search
+    Library,
+    /// The images taken at one place
+    Place,
+    /// A group of duplicates being reviewed
+    Duplicates,
+}
+
+impl Collection {
+    pub fn label(self) -> &'static str {
+        match self {
+            Collection::Basket => "Basket",
+            Collection::Favorites => "Favorites",
+            Collection::Playlist => "Playlist",
+            Collection::Dropped => "Dropped files",
+            Collection::Library => "Library",
+            Collection::Place => "Place",
+            Collection::Duplicates => "Duplicates",
+        }
+    }
+}
+
 pub struct Navigator {
     pub current_path: Option<PathBuf>,
     pub image_list: Vec<PathBuf>,
+    // Files/folders excluded from navigation unless unlocked
+    pub private_paths: Vec<PathBuf>,
+    pub unlocked: bool,
+    pub sort: SortOrder,
+    // Include images from subfolders of the scanned folder
+    pub recursive: bool,
+    root: Option<PathBuf>,
+    shuffle_seed: u64,
+    /// Next/previous go past the ends of the list to the other end
+    pub wrap: bool,
+    // Visiting order while shuffling; a new round is drawn once every image was shown
+    shuffle: Option<Vec<PathBuf>>,
+    shuffle_round: u64,
+    // Everything the last scan found; `image_list` is this narrowed by `filter`
+    scanned: Vec<PathBuf>,
+    pub filter: Option<crate::filter::Filter>,
+    /// Capture times from the metadata index, for sorting by them
+    pub capture_times: HashMap<PathBuf, String>,
+    // A hand-picked list browsed like a folder, e.g. the basket
+    collection: Option<(Collection, Vec<PathBuf>)>,
+}
+
+impl Default for Navigator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Navigator {
@@ -10,45 +112,366 @@ impl Navigator {
         Self {
             current_path: None,
             image_list: Vec::new(),
+            private_paths: Vec::new(),
+            unlocked: false,
+            sort: SortOrder::default(),
+            recursive: false,
+            root: None,
+            shuffle_seed: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64),
+            wrap: false,
+            shuffle: None,
+            shuffle_round: 0,
+            scanned: Vec::new(),
+            filter: None,
+            capture_times: HashMap::new(),
+            collection: None,
+        }
+    }
+
+    pub fn shuffling(&self) -> bool {
+        self.shuffle.is_some()
+    }
+
+    /// Visit the images in random order, each once per round, starting after the current one.
+    pub fn set_shuffle(&mut self, enabled: bool) {
+        self.shuffle = enabled.then(Vec::new);
+        self.sync_shuffle();
+    }
+
+    /// The image to show for next (`forward`) or previous, following the shuffle
+    /// order when shuffling and going round the ends when `wrap` is set.
+    pub fn advance(&mut self, forward: bool, wrap: bool) -> Option<PathBuf> {
+        let Some(order) = &self.shuffle else {
+            let neighbour = if forward { self.get_next_image() } else { self.get_prev_image() };
+            if neighbour.is_some() || !wrap {
+                return neighbour;
+            }
+            let other_end = if forward { self.image_list.first() } else { self.image_list.last() };
+            return other_end.filter(|p| Some(*p) != self.current_path.as_ref()).cloned();
+        };
+
+        let pos = self.current_path.as_ref().and_then(|c| order.iter().position(|p| p == c));
+        match (forward, pos) {
+            (true, Some(p)) if p + 1 < order.len() => Some(order[p + 1].clone()),
+            (true, None) => order.first().cloned(),
+            (false, Some(p)) if p > 0 => Some(order[p - 1].clone()),
+            (true, _) if wrap => {
+                // Every image was shown: draw a new round, not opening with the one on screen
+                self.shuffle_round += 1;
+                let mut order = self.shuffled(&self.image_list);
+                if order.len() > 1 && order.first() == self.current_path.as_ref() {
+                    order.rotate_left(1);
+                }
+                let first = order.first().cloned();
+                self.shuffle = Some(order);
+                first
+            }
+            (false, _) if wrap => order.last().filter(|p| Some(*p) != self.current_path.as_ref()).cloned(),
+            _ => None,
         }
     }
 
+    /// Keep the shuffle order in line with the list: drop vanished images and
+    /// queue new ones at random places after the ones already seen.
+    fn sync_shuffle(&mut self) {
+        let Some(mut order) = self.shuffle.take() else {
+            return;
+        };
+        order.retain(|p| self.image_list.contains(p));
+        if order.is_empty() {
+            if let Some(current) = self.current_path.as_ref().filter(|c| self.image_list.contains(c)) {
+                order.push(current.clone());
+            }
+        }
+        let new: Vec<PathBuf> = self.image_list.iter().filter(|p| !order.contains(p)).cloned().collect();
+        order.extend(self.shuffled(&new));
+        self.shuffle = Some(order);
+    }
+
+    fn shuffled(&self, list: &[PathBuf]) -> Vec<PathBuf> {
+        let mut list = list.to_vec();
+        list.sort_by_cached_key(|p| shuffle_key(self.shuffle_seed.wrapping_add(self.shuffle_round), p));
+        list
+    }
+
+    /// Change the order of the current list in place; the current image stays current.
+    pub fn set_sort(&mut self, sort: SortOrder) {
+        self.sort = sort;
+        let mut scanned = std::mem::take(&mut self.scanned);
+        let mut list = std::mem::take(&mut self.image_list);
+        self.sort_list(&mut scanned);
+        self.sort_list(&mut list);
+        self.scanned = scanned;
+        self.image_list = list;
+    }
+
+    /// Narrow the list to the images matching `filter`, or show them all again.
+    /// The folder is not rescanned.
+    pub fn set_filter(&mut self, filter: Option<crate::filter::Filter>) {
+        self.filter = filter;
+        self.image_list = self.scanned.clone();
+        self.apply_filter();
+        self.sync_shuffle();
+    }
+
+    fn sort_list(&self, list: &mut [PathBuf]) {
+        match self.sort {
+            SortOrder::Name => list.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy())),
+            SortOrder::Modified => list.sort_by_cached_key(|p| {
+                let modified = std::fs::metadata(p).and_then(|m| m.modified()).ok();
+                (modified, p.clone())
+            }),
+            SortOrder::Created => list.sort_by_cached_key(|p| {
+                let created = std::fs::metadata(p).and_then(|m| m.created()).ok();
+                (created, p.clone())
+            }),
+            SortOrder::Size => list.sort_by_cached_key(|p| {
+                let len = std::fs::metadata(p).map(|m| m.len()).ok();
+                (len, p.clone())
+            }),
+            SortOrder::Captured => list.sort_by_cached_key(|p| {
+                let taken = self.capture_times.get(p).cloned();
+                let modified = std::fs::metadata(p).and_then(|m| m.modified()).ok();
+                (taken.is_none(), taken, modified, p.clone())
+            }),
+            SortOrder::Random => list.sort_by_cached_key(|p| shuffle_key(self.shuffle_seed, p)),
+        }
+    }
+
+    /// Scan `dir` and make its first image current.
+    #[tracing::instrument(name = "scan", level = "debug", skip_all, fields(dir = %dir.display()))]
+    pub fn open_directory(&mut self, dir: &Path) -> Option<PathBuf> {
+        self.current_path = None;
+        self.scan_directory(dir);
+        tracing::debug!("{} images", self.image_list.len());
+        let order = self.shuffle.as_ref().unwrap_or(&self.image_list);
+        self.current_path = order.first().cloned();
+        self.current_path.clone()
+    }
+
+    /// Browse `paths` instead of a folder and make the first current.
+    pub fn open_collection(&mut self, kind: Collection, paths: Vec<PathBuf>) -> Option<PathBuf> {
+        self.collection = Some((kind, paths));
+        self.refresh();
+        let order = self.shuffle.as_ref().unwrap_or(&self.image_list);
+        self.current_path = order.first().cloned();
+        self.current_path.clone()
+    }
+
+    /// Browse a playlist of files and folders, e.g. several dropped at once: folders
+    /// contribute their images and other files that aren't images are skipped.
+    pub fn open_playlist(&mut self, kind: Collection, paths: &[PathBuf]) -> Option<PathBuf> {
+        let mut list = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                collect_images(path, self.recursive, &mut list);
+            } else if is_supported_image(path) {
+                list.push(path.clone());
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        list.retain(|p| seen.insert(p.clone()));
+        if list.is_empty() {
+            return None;
+        }
+        self.open_collection(kind, list)
+    }
+
+    /// Go back from a collection to the folder of the current image.
+    pub fn leave_collection(&mut self) {
+        if self.collection.is_none() {
+            return;
+        }
+        if let Some(dir) = self.current_path.as_deref().and_then(Path::parent).map(Path::to_path_buf) {
+            self.scan_directory(&dir);
+        }
+    }
+
+    /// Whether the list is a hand-picked collection rather than a folder.
+    pub fn in_collection(&self) -> bool {
+        self.collection.is_some()
+    }
+
+    /// The collection browsed, if the list is one.
+    pub fn collection(&self) -> Option<Collection> {
+        self.collection.as_ref().map(|(kind, _)| *kind)
+    }
+
     pub fn update_file_list(&mut self, path: &Path) {
         self.current_path = Some(path.to_path_buf());
+        if self.collection.is_some() && self.image_list.iter().any(|p| p == path) {
+            return;
+        }
         
         let parent = match path.parent() {
             Some(p) => p,
             None => return,
         };
         
-        let needs_update = if let Some(first) = self.image_list.first() {
+        let needs_update = if self.recursive {
+            !self.image_list.iter().any(|p| p == path)
+        } else if let Some(first) = self.image_list.first() {
             first.parent() != Some(parent)
         } else {
             true
         };
         
         if needs_update {
-            let mut list = Vec::new();
-            if let Ok(entries) = std::fs::read_dir(parent) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_file() {
-                        if let Some(ext) = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
-                            match ext.as_str() {
-                                "jpg" | "jpeg" | "png" | "nef" | "cr2" | "dng" | "arw" => {
-                                    list.push(path);
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                }
+            self.scan_directory(parent);
+        }
+    }
+
+    /// The folder the image list was scanned from.
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Switch between the current folder and its whole tree. Turning recursion
+    /// off narrows the list to the folder of the current image.
+    pub fn set_recursive(&mut self, recursive: bool) {
+        self.recursive = recursive;
+        let dir = if recursive {
+            self.root.clone()
+        } else {
+            self.current_path.as_deref().and_then(Path::parent).map(Path::to_path_buf)
+        };
+        if let Some(dir) = dir {
+            self.scan_directory(&dir);
+        }
+    }
+
+    /// How to show `path` to the user: relative to the scanned folder when
+    /// browsing recursively, so images in different subfolders can be told apart.
+    pub fn display_name(&self, path: &Path) -> Option<String> {
+        if self.recursive {
+            if let Some(relative) = self.root.as_deref().and_then(|root| path.strip_prefix(root).ok()) {
+                return Some(relative.display().to_string());
             }
-            list.sort();
-            self.image_list = list;
         }
+        path.file_name().map(|n| n.to_string_lossy().into_owned())
     }
-    
+
+    /// Re-read the current folder, e.g. after the private set or lock state changed.
+    pub fn refresh(&mut self) {
+        if let Some((_, mut list)) = self.collection.clone() {
+            list.retain(|p| p.exists());
+            self.load_list(list);
+        } else if let Some(root) = self.root.clone() {
+            self.scan_directory(&root);
+        }
+    }
+
+    fn scan_directory(&mut self, parent: &Path) {
+        let mut list = Vec::new();
+        collect_images(parent, self.recursive, &mut list);
+        self.collection = None;
+        self.root = Some(parent.to_path_buf());
+        self.load_list(list);
+    }
+
+    fn load_list(&mut self, mut list: Vec<PathBuf>) {
+        self.sort_list(&mut list);
+        self.image_list = list;
+        self.apply_private_filter();
+        self.scanned = self.image_list.clone();
+        self.apply_filter();
+        self.sync_shuffle();
+    }
+
+    fn apply_filter(&mut self) {
+        if let Some(filter) = &self.filter {
+            self.image_list.retain(|p| filter.matches(p));
+        }
+    }
+
+    fn apply_private_filter(&mut self) {
+        if !self.unlocked {
+            let private = &self.private_paths;
+            self.image_list.retain(|p| !crate::library::is_under_any(p, private));
+        }
+    }
+
+    /// Whether `path` is private, or in a private folder, while private images are locked.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        !self.unlocked && crate::library::is_under_any(path, &self.private_paths)
+    }
+
+    /// Drop `path` from the list after it was deleted. Returns the image to show
+    /// instead when it was the current one: the next, else the previous.
+    pub fn remove_path(&mut self, path: &Path) -> Option<PathBuf> {
+        let replacement = if self.current_path.as_deref() == Some(path) {
+            self.get_next_image().or_else(|| self.get_prev_image())
+        } else {
+            None
+        };
+        self.scanned.retain(|p| p != path);
+        self.image_list.retain(|p| p != path);
+        if let Some(order) = self.shuffle.as_mut() {
+            order.retain(|p| p != path);
+        }
+        replacement
+    }
+
+    /// Follow a rename of `from` to `to` without rescanning the folder.
+    pub fn rename_path(&mut self, from: &Path, to: &Path) {
+        let lists = [&mut self.scanned, &mut self.image_list]
+            .into_iter()
+            .chain(self.shuffle.as_mut())
+            .chain(self.collection.as_mut().map(|(_, list)| list));
+        for list in lists {
+            for path in list.iter_mut().filter(|p| p.as_path() == from) {
+                *path = to.to_path_buf();
+            }
+        }
+        if self.current_path.as_deref() == Some(from) {
+            self.current_path = Some(to.to_path_buf());
+        }
+        self.set_sort(self.sort);
+    }
+
+    /// 1-based position of the current image and the number of images.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        let current = self.current_path.as_ref()?;
+        let pos = self.image_list.iter().position(|p| p == current)?;
+        Some((pos + 1, self.image_list.len()))
+    }
+
+    /// An image by 1-based position, or by file name ignoring case: an exact
+    /// match first, then the first name starting with `query`, then one containing it.
+    pub fn find(&self, query: &str) -> Option<PathBuf> {
+        let query = query.trim();
+        if let Ok(n) = query.parse::<usize>() {
+            return n.checked_sub(1).and_then(|i| self.image_list.get(i)).cloned();
+        }
+        let query = query.to_lowercase();
+        if query.is_empty() {
+            return None;
+        }
+        let lower = |name: Option<&std::ffi::OsStr>| name.map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let list = &self.image_list;
+        list.iter()
+            .find(|p| lower(p.file_name()) == query || lower(p.file_stem()) == query)
+            .or_else(|| list.iter().find(|p| lower(p.file_name()).starts_with(&query)))
+            .or_else(|| list.iter().find(|p| lower(p.file_name()).contains(&query)))
+            .cloned()
+    }
+
+    /// What to show once the current image has dropped out of the list, e.g. hidden as
+    /// private: the first image after it in `previous`, the list it was in, that is still
+    /// listed, else the last one before it.
+    pub fn replacement(&self, previous: &[PathBuf]) -> Option<PathBuf> {
+        let current = self.current_path.as_ref()?;
+        let pos = previous.iter().position(|p| p == current)?;
+        let listed: HashSet<&PathBuf> = self.image_list.iter().collect();
+        previous[pos + 1..]
+            .iter()
+            .find(|p| listed.contains(p))
+            .or_else(|| previous[..pos].iter().rev().find(|p| listed.contains(p)))
+            .cloned()
+    }
+
     pub fn get_next_image(&self) -> Option<PathBuf> {
         if let Some(current) = &self.current_path {
             if let Some(pos) = self.image_list.iter().position(|p| p == current) {
@@ -72,9 +495,88 @@ impl Navigator {
     }
 }
 
+/// Append the images in `dir`, and in its subfolders when `recursive`, to `list`.
+pub fn collect_images(dir: &Path, recursive: bool, list: &mut Vec<PathBuf>) {
+    // Symlinked folders are not followed so a link loop can't recurse forever
+    let walker = walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| !is_dzi_tiles(e.path()));
+    for entry in walker.flatten() {
+        let path = entry.into_path();
+        if path.is_file() && is_supported_image(&path) {
+            list.push(path);
+        }
+    }
+}
+
+/// The `<name>_files` tile folder of a Deep Zoom image, shown through its `.dzi` instead.
+fn is_dzi_tiles(path: &Path) -> bool {
+    let Some(stem) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix("_files")) else {
+        return false;
+    };
+    path.is_dir() && path.with_file_name(format!("{}.dzi", stem)).is_file()
+}
+
+/// A random but reproducible position for `path`.
+fn shuffle_key(seed: u64, path: &Path) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    seed.hash(&mut hasher);
+    path.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Compare names the way people count: digit runs by value, the rest case-insensitively.
+/// Exact ties fall back to plain byte order so the result is still a total order.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut x, mut y) = (a, b);
+    loop {
+        let (Some(cx), Some(cy)) = (x.chars().next(), y.chars().next()) else {
+            return x.len().cmp(&y.len()).then_with(|| a.cmp(b));
+        };
+        let ordering = if cx.is_ascii_digit() && cy.is_ascii_digit() {
+            let end_x = x.find(|c: char| !c.is_ascii_digit()).unwrap_or(x.len());
+            let end_y = y.find(|c: char| !c.is_ascii_digit()).unwrap_or(y.len());
+            let (nx, ny) = (x[..end_x].trim_start_matches('0'), y[..end_y].trim_start_matches('0'));
+            let ordering = nx.len().cmp(&ny.len()).then_with(|| nx.cmp(ny));
+            x = &x[end_x..];
+            y = &y[end_y..];
+            ordering
+        } else {
+            x = &x[cx.len_utf8()..];
+            y = &y[cy.len_utf8()..];
+            cx.to_lowercase().cmp(cy.to_lowercase())
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Lowercase extensions of the formats the loader can decode.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "nef", "cr2", "dng", "arw", "dzi", "tif", "tiff", "svs", "btf"];
+
+/// Camera RAW formats among them.
+pub const RAW_EXTENSIONS: &[&str] = &["nef", "cr2", "dng", "arw"];
+
+pub fn is_supported_image(path: &Path) -> bool {
+    has_extension(path, SUPPORTED_EXTENSIONS)
+}
+
+/// Whether the lowercase extension of `path` is one of `extensions`.
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+        Some(ext) => extensions.contains(&ext.as_str()),
+        None => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scratch::scratch_dir;
 
     #[test]
     fn test_navigation() {
@@ -105,4 +607,262 @@ mod tests {
         nav.current_path = Some(p1.clone());
         assert_eq!(nav.get_prev_image(), None);
     }
+
+    #[test]
+    fn test_position_and_find() {
+        let mut nav = Navigator::new();
+        nav.image_list = ["/s/IMG_0001.jpg", "/s/IMG_0002.NEF", "/s/beach.jpg", "/s/IMG_0002.jpg"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(nav.position(), None);
+        nav.current_path = Some(PathBuf::from("/s/beach.jpg"));
+        assert_eq!(nav.position(), Some((3, 4)));
+
+        assert_eq!(nav.find("2"), Some(PathBuf::from("/s/IMG_0002.NEF")));
+        assert_eq!(nav.find("0"), None);
+        assert_eq!(nav.find("5"), None);
+        assert_eq!(nav.find("img_0002.jpg"), Some(PathBuf::from("/s/IMG_0002.jpg")));
+        assert_eq!(nav.find("beach"), Some(PathBuf::from("/s/beach.jpg")));
+        assert_eq!(nav.find("img_"), Some(PathBuf::from("/s/IMG_0001.jpg")));
+        assert_eq!(nav.find("each"), Some(PathBuf::from("/s/beach.jpg")));
+        assert_eq!(nav.find("sunset"), None);
+        assert_eq!(nav.find(" "), None);
+    }
+
+    #[test]
+    fn test_remove_and_rename() {
+        let mut nav = Navigator::new();
+        let list: Vec<PathBuf> = ["a.jpg", "b.jpg", "c.jpg"].iter().map(PathBuf::from).collect();
+        nav.image_list = list.clone();
+        nav.scanned = list.clone();
+        nav.current_path = Some(list[1].clone());
+        nav.set_shuffle(true);
+
+        assert_eq!(nav.remove_path(&list[1]), Some(list[2].clone()));
+        assert_eq!(nav.image_list, vec![list[0].clone(), list[2].clone()]);
+        assert!(!nav.shuffle.as_ref().unwrap().contains(&list[1]));
+        // The last image falls back to the previous one
+        nav.current_path = Some(list[2].clone());
+        assert_eq!(nav.remove_path(&list[2]), Some(list[0].clone()));
+        assert_eq!(nav.remove_path(Path::new("other.jpg")), None);
+
+        nav.current_path = Some(list[0].clone());
+        nav.image_list.push(PathBuf::from("m.jpg"));
+        nav.rename_path(&list[0], Path::new("z.jpg"));
+        assert_eq!(nav.current_path, Some(PathBuf::from("z.jpg")));
+        assert_eq!(nav.image_list, vec![PathBuf::from("m.jpg"), PathBuf::from("z.jpg")]);
+        assert_eq!(nav.scanned, vec![PathBuf::from("z.jpg")]);
+    }
+
+    #[test]
+    fn test_is_hidden() {
+        let mut nav = Navigator::new();
+        nav.private_paths = vec![PathBuf::from("/photos/private"), PathBuf::from("/photos/a.jpg")];
+        assert!(nav.is_hidden(Path::new("/photos/private/b.jpg")));
+        assert!(nav.is_hidden(Path::new("/photos/private")));
+        assert!(nav.is_hidden(Path::new("/photos/a.jpg")));
+        assert!(!nav.is_hidden(Path::new("/photos/c.jpg")));
+        nav.unlocked = true;
+        assert!(!nav.is_hidden(Path::new("/photos/a.jpg")));
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let mut nav = Navigator::new();
+        let list: Vec<PathBuf> = ["a.jpg", "b.jpg", "c.jpg"].iter().map(PathBuf::from).collect();
+        nav.image_list = list.clone();
+        nav.current_path = Some(list[2].clone());
+        assert_eq!(nav.advance(true, false), None);
+        assert_eq!(nav.advance(true, true), Some(list[0].clone()));
+        nav.current_path = Some(list[0].clone());
+        assert_eq!(nav.advance(false, true), Some(list[2].clone()));
+        assert_eq!(nav.advance(true, true), Some(list[1].clone()));
+    }
+
+    #[test]
+    fn test_shuffle_visits_all() {
+        let mut nav = Navigator::new();
+        nav.image_list = (0..20).map(|i| PathBuf::from(format!("{}.jpg", i))).collect();
+        nav.current_path = Some(PathBuf::from("7.jpg"));
+        nav.set_shuffle(true);
+
+        // One round shows every image exactly once, starting from the current one
+        let mut seen = vec![nav.current_path.clone().unwrap()];
+        while let Some(next) = nav.advance(true, false) {
+            nav.current_path = Some(next.clone());
+            seen.push(next);
+        }
+        assert_eq!(seen.len(), 20);
+        let mut sorted = seen.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 20);
+        assert_ne!(seen, nav.image_list);
+
+        // Previous retraces the round
+        assert_eq!(nav.advance(false, false), Some(seen[18].clone()));
+
+        // With wrap-around a new round starts, without repeating the last image
+        let next = nav.advance(true, true).unwrap();
+        assert_ne!(Some(next), nav.current_path);
+
+        // New files join the current round
+        nav.image_list.push(PathBuf::from("new.jpg"));
+        nav.sync_shuffle();
+        assert!(nav.shuffle.as_ref().unwrap().contains(&PathBuf::from("new.jpg")));
+    }
+
+    #[test]
+    fn test_private_filter() {
+        let mut nav = Navigator::new();
+        nav.image_list = vec![
+            PathBuf::from("/p/a.jpg"),
+            PathBuf::from("/p/secret/b.jpg"),
+            PathBuf::from("/p/c.jpg"),
+        ];
+        nav.private_paths = vec![PathBuf::from("/p/secret"), PathBuf::from("/p/c.jpg")];
+        nav.apply_private_filter();
+        assert_eq!(nav.image_list, vec![PathBuf::from("/p/a.jpg")]);
+    }
+
+    #[test]
+    fn test_replacement() {
+        let mut nav = Navigator::new();
+        let previous: Vec<PathBuf> = ["/p/a.jpg", "/p/secret/b.jpg", "/p/secret/c.jpg", "/p/d.jpg"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        nav.image_list = previous.clone();
+        nav.current_path = Some(previous[1].clone());
+        nav.private_paths = vec![PathBuf::from("/p/secret")];
+        nav.apply_private_filter();
+        // Not the next image of the folder just hidden
+        assert_eq!(nav.replacement(&previous), Some(previous[3].clone()));
+        nav.image_list.pop();
+        assert_eq!(nav.replacement(&previous), Some(previous[0].clone()));
+        nav.image_list.clear();
+        assert_eq!(nav.replacement(&previous), None);
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec!["IMG_10.jpg", "img_9.jpg", "IMG_009b.jpg", "IMG_2.jpg", "a.jpg", "IMG_10.JPG"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["a.jpg", "IMG_2.jpg", "img_9.jpg", "IMG_009b.jpg", "IMG_10.JPG", "IMG_10.jpg"]);
+        assert_eq!(natural_cmp("x1", "x01"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_sort_modes() {
+        let dir = scratch_dir("sort");
+        for (name, len) in [("IMG_10.jpg", 1), ("IMG_9.jpg", 30), ("IMG_100.jpg", 20)] {
+            std::fs::write(dir.join(name), vec![0u8; len]).unwrap();
+        }
+
+        let mut nav = Navigator::new();
+        nav.open_directory(&dir);
+        let by_name = vec![dir.join("IMG_9.jpg"), dir.join("IMG_10.jpg"), dir.join("IMG_100.jpg")];
+        assert_eq!(nav.image_list, by_name);
+
+        nav.current_path = Some(dir.join("IMG_100.jpg"));
+        nav.set_sort(SortOrder::Size);
+        assert_eq!(nav.image_list, vec![dir.join("IMG_10.jpg"), dir.join("IMG_100.jpg"), dir.join("IMG_9.jpg")]);
+        assert_eq!(nav.get_next_image(), Some(dir.join("IMG_9.jpg")));
+
+        // The shuffle is a permutation and survives a rescan
+        nav.set_sort(SortOrder::Random);
+        let shuffled = nav.image_list.clone();
+        nav.refresh();
+        assert_eq!(nav.image_list, shuffled);
+        nav.set_sort(SortOrder::Name);
+        assert_eq!(nav.image_list, by_name);
+
+        nav.capture_times.insert(dir.join("IMG_100.jpg"), "2023-01-01 09:00:00".to_string());
+        nav.capture_times.insert(dir.join("IMG_9.jpg"), "2024-01-01 09:00:00".to_string());
+        nav.set_sort(SortOrder::Captured);
+        assert_eq!(nav.image_list, vec![dir.join("IMG_100.jpg"), dir.join("IMG_9.jpg"), dir.join("IMG_10.jpg")]);
+        nav.set_sort(SortOrder::Name);
+
+        // A filter narrows the list without losing the rest, and survives sorting
+        nav.set_filter(crate::filter::Filter::parse("img_1??.jpg").unwrap());
+        assert_eq!(nav.image_list, vec![dir.join("IMG_100.jpg")]);
+        nav.set_sort(SortOrder::Size);
+        assert_eq!(nav.image_list, vec![dir.join("IMG_100.jpg")]);
+        nav.set_sort(SortOrder::Name);
+        nav.set_filter(None);
+        assert_eq!(nav.image_list, by_name);
+    }
+
+    #[test]
+    fn test_recursive_scan() {
+        let dir = scratch_dir("nav");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["b.jpg", "a.png", "notes.txt", "sub/c.jpg"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let mut nav = Navigator::new();
+        assert_eq!(nav.open_directory(&dir), Some(dir.join("a.png")));
+        assert_eq!(nav.image_list, vec![dir.join("a.png"), dir.join("b.jpg")]);
+
+        nav.recursive = true;
+        nav.open_directory(&dir);
+        assert_eq!(nav.image_list, vec![dir.join("a.png"), dir.join("b.jpg"), dir.join("sub/c.jpg")]);
+
+        // Opening a file in a subfolder keeps the recursive list
+        nav.update_file_list(&dir.join("sub/c.jpg"));
+        assert_eq!(nav.image_list.len(), 3);
+        assert_eq!(nav.display_name(&dir.join("sub/c.jpg")), Some(format!("sub{}c.jpg", std::path::MAIN_SEPARATOR)));
+
+        // Going flat keeps the folder of the current image
+        nav.set_recursive(false);
+        assert_eq!(nav.image_list, vec![dir.join("sub/c.jpg")]);
+        assert_eq!(nav.display_name(&dir.join("sub/c.jpg")), Some("c.jpg".to_string()));
+        nav.update_file_list(&dir.join("a.png"));
+        nav.set_recursive(true);
+        assert_eq!(nav.image_list.len(), 3);
+
+        // Deep Zoom tiles are not listed as images of their own
+        std::fs::create_dir_all(dir.join("scan_files/0")).unwrap();
+        std::fs::write(dir.join("scan_files/0/0_0.jpg"), b"").unwrap();
+        std::fs::write(dir.join("scan.dzi"), b"").unwrap();
+        nav.refresh();
+        assert_eq!(nav.image_list.len(), 4);
+
+        // A collection across folders is browsed like one, until a file outside it opens
+        nav.recursive = false;
+        assert_eq!(nav.open_collection(Collection::Basket, vec![dir.join("sub/c.jpg"), dir.join("b.jpg")]), Some(dir.join("b.jpg")));
+        assert_eq!(nav.collection(), Some(Collection::Basket));
+        nav.update_file_list(&dir.join("sub/c.jpg"));
+        assert_eq!(nav.image_list, vec![dir.join("b.jpg"), dir.join("sub/c.jpg")]);
+        std::fs::remove_file(dir.join("b.jpg")).unwrap();
+        nav.refresh();
+        assert_eq!(nav.image_list, vec![dir.join("sub/c.jpg")]);
+        nav.update_file_list(&dir.join("a.png"));
+        assert!(!nav.in_collection());
+        assert_eq!(nav.collection(), None);
+        assert_eq!(nav.image_list.len(), 2);
+    }
+
+    #[test]
+    fn test_open_playlist() {
+        let dir = scratch_dir("playlist");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["a.png", "notes.txt", "sub/b.jpg", "sub/c.jpg"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        // Folders are expanded, non-images and repeats dropped
+        let mut nav = Navigator::new();
+        let dropped = [dir.join("sub"), dir.join("notes.txt"), dir.join("a.png"), dir.join("sub/b.jpg")];
+        assert_eq!(nav.open_playlist(Collection::Dropped, &dropped), Some(dir.join("a.png")));
+        assert_eq!(nav.image_list, vec![dir.join("a.png"), dir.join("sub/b.jpg"), dir.join("sub/c.jpg")]);
+        assert!(nav.in_collection());
+
+        // Nothing to show leaves the list alone
+        assert_eq!(nav.open_playlist(Collection::Playlist, &[dir.join("notes.txt")]), None);
+        assert_eq!(nav.collection(), Some(Collection::Dropped));
+        assert_eq!(nav.image_list.len(), 3);
+    }
 }
@@ -0,0 +1,294 @@
+//! Places: the photos of the folder, or of the whole library in library mode, grouped by
+//! where they were taken. Each group is named by a reverse geocoding service, asked one
+//! place at a time on a thread of its own as public services require, and the names are
+//! kept in the library database so places looked up once are named offline afterwards.
+
+use anyhow::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use crate::library::Library;
+
+pub const DEFAULT_GEOCODE_URL: &str = "https://nominatim.openstreetmap.org/reverse?format=xml&zoom=10&lat={lat}&lon={lon}";
+const USER_AGENT: &str = concat!("Momemtum/", env!("CARGO_PKG_VERSION"));
+// Nominatim's usage policy allows one request a second
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RESPONSE_BYTES: u64 = 256 * 1024;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Images taken close to each other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    /// Mean position of the images, in degrees
+    pub center: (f64, f64),
+    pub paths: Vec<PathBuf>,
+    /// From the geocoder; none until looked up, or when it knew none
+    pub name: Option<String>,
+}
+
+impl Place {
+    /// The name, or the position while there is none.
+    pub fn title(&self) -> String {
+        self.name.clone().unwrap_or_else(|| crate::gps::format(self.center.0, self.center.1))
+    }
+
+    /// "Lyon, France — 12 images", for the list of places.
+    pub fn label(&self) -> String {
+        let count = self.paths.len();
+        format!("{} — {} image{}", self.title(), count, if count == 1 { "" } else { "s" })
+    }
+}
+
+/// What names are cached under: the position rounded to about a kilometre.
+pub fn key((lat, lon): (f64, f64)) -> String {
+    format!("{:.2},{:.2}", lat, lon)
+}
+
+/// Great-circle distance between two positions in degrees.
+pub fn distance_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let (dlat, dlon) = (lat2 - lat1, (b.1 - a.1).to_radians());
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().min(1.0).asin()
+}
+
+/// Group images by position: each joins the first place whose center is within
+/// `radius_km`, moving the center to the mean, or starts a place of its own. The
+/// places with the most images come first.
+pub fn cluster(located: &[(PathBuf, (f64, f64))], radius_km: f64) -> Vec<Place> {
+    let mut places: Vec<Place> = Vec::new();
+    for (path, position) in located {
+        match places.iter_mut().find(|p| distance_km(p.center, *position) <= radius_km) {
+            Some(place) => {
+                let n = place.paths.len() as f64;
+                place.center = ((place.center.0 * n + position.0) / (n + 1.0), (place.center.1 * n + position.1) / (n + 1.0));
+                place.paths.push(path.clone());
+            }
+            None => places.push(Place { center: *position, paths: vec![path.clone()], name: None }),
+        }
+    }
+    places.sort_by_key(|p| std::cmp::Reverse(p.paths.len()));
+    places
+}
+
+/// Fill `{lat}` and `{lon}` into a geocoding URL template.
+pub fn geocode_url(template: &str, lat: f64, lon: f64) -> String {
+    template.replace("{lat}", &format!("{:.5}", lat)).replace("{lon}", &format!("{:.5}", lon))
+}
+
+/// The name in a Nominatim reverse lookup answered in XML: the town or region and the
+/// country, "Lyon, France". None for places it has no address for, such as the sea.
+pub fn parse_name(xml: &str) -> Option<String> {
+    let address = &xml[xml.find("<addressparts>")?..];
+    let locality = ["city", "town", "village", "hamlet", "municipality", "county", "state"]
+        .iter()
+        .find_map(|tag| tag_text(address, tag));
+    let country = tag_text(address, "country");
+    match (locality, country) {
+        (Some(locality), Some(country)) => Some(format!("{}, {}", locality, country)),
+        (locality, country) => locality.or(country),
+    }
+}
+
+/// The text of the first `<tag>` element, which has no attributes in a Nominatim answer.
+fn tag_text(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    let text = crate::xmp::unescape(xml[start..end].trim());
+    (!text.is_empty()).then_some(text)
+}
+
+fn lookup(template: &str, (lat, lon): (f64, f64)) -> Result<Option<String>> {
+    let response = ureq::get(&geocode_url(template, lat, lon)).set("User-Agent", USER_AGENT).call()?;
+    let mut text = String::new();
+    response.into_reader().take(MAX_RESPONSE_BYTES).read_to_string(&mut text)?;
+    Ok(parse_name(&text))
+}
+
+/// Names places in the background, from the library's cache or else the geocoder.
+pub struct Geocoder {
+    requests: Sender<(f64, f64)>,
+    results: Receiver<((f64, f64), Option<String>)>,
+}
+
+impl Geocoder {
+    /// Look names up with the URL template `template`; none when it is empty.
+    pub fn start(template: &str) -> Option<Self> {
+        if template.trim().is_empty() {
+            return None;
+        }
+        let (requests, queue) = channel::<(f64, f64)>();
+        let (sender, results) = channel();
+        let template = template.to_string();
+        std::thread::spawn(move || {
+            // A connection of its own, like the indexer's
+            let library = Library::open().map_err(|e| log::warn!("Place names won't be kept: {:?}", e)).ok();
+            let mut last_lookup: Option<Instant> = None;
+            for center in queue {
+                let cached = library.as_ref().and_then(|lib| lib.place_name(&key(center)).ok().flatten());
+                let name = match cached {
+                    Some(name) => Some(name),
+                    None => {
+                        if let Some(last) = last_lookup {
+                            std::thread::sleep(LOOKUP_INTERVAL.saturating_sub(last.elapsed()));
+                        }
+                        last_lookup = Some(Instant::now());
+                        match lookup(&template, center) {
+                            // Places without a name are kept too, so they aren't asked for again
+                            Ok(name) => {
+                                let stored = name.as_deref().unwrap_or("");
+                                if let Some(Err(e)) = library.as_ref().map(|lib| lib.store_place_name(&key(center), stored)) {
+                                    log::warn!("Failed to keep the name of a place: {:?}", e);
+                                }
+                                name
+                            }
+                            Err(e) => {
+                                log::warn!("Failed to look up the place at {}: {:#}", key(center), e);
+                                None
+                            }
+                        }
+                    }
+                };
+                if sender.send((center, name.filter(|n| !n.is_empty()))).is_err() {
+                    return;
+                }
+                crate::redraw::wake();
+            }
+        });
+        Some(Self { requests, results })
+    }
+
+    /// Ask for the name of the place centered on `center`.
+    pub fn request(&self, center: (f64, f64)) {
+        let _ = self.requests.send(center);
+    }
+
+    /// Names found since last asked, by the center they were asked for.
+    pub fn poll(&self) -> Vec<((f64, f64), Option<String>)> {
+        self.results.try_iter().collect()
+    }
+}
+
+/// The places last listed, and the geocoder naming them.
+#[derive(Default)]
+pub struct Places {
+    places: Vec<Place>,
+    geocoder: Option<Geocoder>,
+}
+
+impl Places {
+    /// List the places of `located` anew. Names the library has kept are used as they are;
+    /// the others are asked of the geocoder at `geocode_url`, started the first time.
+    pub fn list(&mut self, located: &[(PathBuf, (f64, f64))], radius_km: f64, library: Option<&Library>, geocode_url: &str) {
+        self.places = cluster(located, radius_km);
+        let mut unnamed = Vec::new();
+        for place in &mut self.places {
+            match library.and_then(|lib| lib.place_name(&key(place.center)).ok().flatten()) {
+                Some(name) => place.name = Some(name).filter(|n| !n.is_empty()),
+                None => unnamed.push(place.center),
+            }
+        }
+        if !unnamed.is_empty() && self.geocoder.is_none() {
+            self.geocoder = Geocoder::start(geocode_url);
+        }
+        if let Some(geocoder) = &self.geocoder {
+            for center in unnamed {
+                geocoder.request(center);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.places.is_empty()
+    }
+
+    /// The entries of the list: each place's label, and its first image standing for it.
+    pub fn items(&self) -> Vec<(String, PathBuf)> {
+        self.places.iter().map(|p| (p.label(), p.paths[0].clone())).collect()
+    }
+
+    /// Take in the names found since last asked. Returns the places named, by their first
+    /// image, with their new label.
+    pub fn update(&mut self) -> Vec<(PathBuf, String)> {
+        let Some(geocoder) = &self.geocoder else {
+            return Vec::new();
+        };
+        let mut named = Vec::new();
+        for (center, name) in geocoder.poll() {
+            let Some(name) = name else {
+                continue;
+            };
+            let wanted = key(center);
+            for place in self.places.iter_mut().filter(|p| key(p.center) == wanted) {
+                place.name = Some(name.clone());
+                named.push((place.paths[0].clone(), place.label()));
+            }
+        }
+        named
+    }
+
+    /// The place listed with `first` as its first image.
+    pub fn find(&self, first: &Path) -> Option<&Place> {
+        self.places.iter().find(|p| p.paths.first().is_some_and(|path| path == first))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster() {
+        // Paris is about 390 km from Lyon, whose photos are a few kilometres apart
+        let located = vec![
+            (PathBuf::from("/trip/a.jpg"), (45.7640, 4.8357)),
+            (PathBuf::from("/trip/b.jpg"), (48.8566, 2.3522)),
+            (PathBuf::from("/trip/c.jpg"), (45.7500, 4.8500)),
+            (PathBuf::from("/trip/d.jpg"), (45.7800, 4.8200)),
+        ];
+        assert!((distance_km(located[0].1, located[1].1) - 392.0).abs() < 2.0);
+        let places = cluster(&located, 10.0);
+        assert_eq!(places.len(), 2);
+        assert_eq!(places[0].paths, [PathBuf::from("/trip/a.jpg"), PathBuf::from("/trip/c.jpg"), PathBuf::from("/trip/d.jpg")]);
+        assert!((places[0].center.0 - 45.7647).abs() < 1e-3);
+        assert_eq!(places[1].label(), "48.85660° N, 2.35220° E — 1 image");
+        assert_eq!(Place { name: Some("Lyon, France".to_string()), ..places[0].clone() }.label(), "Lyon, France — 3 images");
+        // Closer together than the radius, everything is one place
+        assert_eq!(cluster(&located, 500.0).len(), 1);
+        assert_eq!(key(places[1].center), "48.86,2.35");
+        assert_eq!(geocode_url("https://geo.example/?lat={lat}&lon={lon}", 1.5, -2.25), "https://geo.example/?lat=1.50000&lon=-2.25000");
+    }
+
+    #[test]
+    fn test_parse_name() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<reversegeocode timestamp="Sat, 17 Oct 26 10:00:00 +0000" querystring="format=xml&amp;lat=45.76&amp;lon=4.84">
+<result place_id="1" osm_type="relation" osm_id="120965" lat="45.75" lon="4.83">Lyon, Rhône, France</result>
+<addressparts><city>Lyon</city><county>Rhône</county><state>Auvergne-Rhône-Alpes</state><country>France</country><country_code>fr</country_code></addressparts>
+</reversegeocode>"#;
+        assert_eq!(parse_name(xml).as_deref(), Some("Lyon, France"));
+        let village = "<addressparts><village>Saint-Jean &amp; Paul</village><country_code>fr</country_code></addressparts>";
+        assert_eq!(parse_name(village).as_deref(), Some("Saint-Jean & Paul"));
+        let sea = r#"<reversegeocode><error>Unable to geocode</error></reversegeocode>"#;
+        assert_eq!(parse_name(sea), None);
+    }
+
+    #[test]
+    fn test_places() {
+        let located = vec![(PathBuf::from("/trip/a.jpg"), (45.7640, 4.8357)), (PathBuf::from("/trip/b.jpg"), (48.8566, 2.3522))];
+        let mut places = Places::default();
+        assert!(places.is_empty());
+        // Without a geocoder the places keep their positions for names
+        places.list(&located, 10.0, None, "");
+        assert_eq!(places.items(), [
+            ("45.76400° N, 4.83570° E — 1 image".to_string(), PathBuf::from("/trip/a.jpg")),
+            ("48.85660° N, 2.35220° E — 1 image".to_string(), PathBuf::from("/trip/b.jpg")),
+        ]);
+        assert!(places.update().is_empty());
+        assert_eq!(places.find(Path::new("/trip/b.jpg")).map(|p| p.center), Some((48.8566, 2.3522)));
+        assert!(places.find(Path::new("/trip/c.jpg")).is_none());
+    }
+}
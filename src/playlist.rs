@@ -0,0 +1,71 @@
+//! Playlist files in the M3U style: one image path per line, `#` lines are comments.
+//! Relative paths are taken from the playlist's folder, so a playlist kept next to the
+//! images it lists still works after the whole tree is moved.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+pub const EXTENSIONS: &[&str] = &["m3u", "m3u8"];
+
+pub fn is_playlist(path: &Path) -> bool {
+    crate::navigator::has_extension(path, EXTENSIONS)
+}
+
+/// The paths listed in the playlist at `path`, in order.
+pub fn read(path: &Path) -> Result<Vec<PathBuf>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    Ok(parse(&text, base))
+}
+
+fn parse(text: &str, base: &Path) -> Vec<PathBuf> {
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect()
+}
+
+/// Write `paths` to a playlist at `path`, relative to its folder where they are inside it.
+pub fn write(path: &Path, paths: &[PathBuf]) -> Result<()> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut text = String::from("#EXTM3U\n");
+    for item in paths {
+        let item = item.strip_prefix(base).unwrap_or(item);
+        text.push_str(&item.to_string_lossy());
+        text.push('\n');
+    }
+    std::fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_parse_skips_comments() {
+        let text = "\u{feff}#EXTM3U\n#EXTINF:-1,Beach\nholiday/a.jpg\r\n\n  /photos/b.png  \n";
+        let base = Path::new("/lists");
+        assert_eq!(parse(text, base), vec![PathBuf::from("/lists/holiday/a.jpg"), PathBuf::from("/photos/b.png")]);
+    }
+
+    #[test]
+    fn test_write_and_read_back() {
+        let dir = scratch_dir("m3u");
+        let list = dir.join("picks.m3u");
+        let elsewhere = std::env::temp_dir().join("elsewhere.jpg");
+        let paths = vec![dir.join("sub").join("b.jpg"), elsewhere.clone(), dir.join("a.png")];
+        write(&list, &paths).unwrap();
+
+        // Paths inside the playlist's folder are stored relative to it
+        let text = std::fs::read_to_string(&list).unwrap();
+        assert!(text.starts_with("#EXTM3U\n"));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], Path::new("sub").join("b.jpg").to_string_lossy());
+        assert_eq!(lines[2], elsewhere.to_string_lossy());
+        assert!(is_playlist(&list));
+        assert_eq!(read(&list).unwrap(), paths);
+    }
+}
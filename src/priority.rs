@@ -0,0 +1,178 @@
+//! Keeps the image the user asked for ahead of background work. Thumbnails,
+//! indexing and map tiles run on the global rayon pool at a lowered thread
+//! priority (lower still on battery power), and hold off while an interactive
+//! decode is in flight. Tiles of the image on screen get a pool of their own.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Interactive decodes currently running
+static INTERACTIVE: AtomicUsize = AtomicUsize::new(0);
+/// Longest a background job waits for interactive decodes before running anyway
+const MAX_YIELD: Duration = Duration::from_secs(2);
+const YIELD_STEP: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// The image being opened or viewed
+    Interactive,
+    /// Prefetching, thumbnails and indexing
+    Background,
+    /// Background work while running on battery
+    Idle,
+}
+
+/// Set up the global rayon pool for background work. Call once, before anything
+/// is spawned on it.
+pub fn init() {
+    let priority = if on_battery() { Priority::Idle } else { Priority::Background };
+    let cores = std::thread::available_parallelism().map_or(4, |n| n.get());
+    // On battery half the cores are plenty for work nobody is waiting on
+    let threads = if priority == Priority::Idle { (cores / 2).max(1) } else { cores };
+    let result = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("background-{}", i))
+        .start_handler(move |_| set_current(priority))
+        .build_global();
+    if let Err(e) = result {
+        log::warn!("Failed to set up the background pool: {}", e);
+    }
+}
+
+/// Run `job` on the pool for tiles of the image on screen, which background work
+/// can't hold up.
+pub fn spawn_interactive(job: impl FnOnce() + Send + 'static) {
+    static POOL: OnceLock<Option<rayon::ThreadPool>> = OnceLock::new();
+    let pool = POOL.get_or_init(|| {
+        rayon::ThreadPoolBuilder::new()
+            .thread_name(|i| format!("interactive-{}", i))
+            .start_handler(|_| set_current(Priority::Interactive))
+            .build()
+            .ok()
+    });
+    match pool {
+        Some(pool) => pool.spawn(job),
+        None => rayon::spawn(job),
+    }
+}
+
+/// Marks the current thread as decoding the image the user asked for, until dropped.
+pub struct InteractiveGuard(());
+
+pub fn interactive() -> InteractiveGuard {
+    set_current(Priority::Interactive);
+    INTERACTIVE.fetch_add(1, Ordering::SeqCst);
+    InteractiveGuard(())
+}
+
+impl Drop for InteractiveGuard {
+    fn drop(&mut self) {
+        INTERACTIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Called by background jobs before they start: waits, up to a limit, for
+/// interactive decodes to finish so they get the CPU and disk to themselves.
+pub fn yield_to_interactive() {
+    let start = Instant::now();
+    while INTERACTIVE.load(Ordering::SeqCst) > 0 && start.elapsed() < MAX_YIELD {
+        std::thread::sleep(YIELD_STEP);
+    }
+}
+
+/// Adjust the scheduling priority of the calling thread. Best effort: raising a
+/// priority usually needs privileges, so interactive threads keep the default
+/// where that fails.
+pub fn set_current(priority: Priority) {
+    #[cfg(target_os = "linux")]
+    {
+        let nice = match priority {
+            Priority::Interactive => 0,
+            Priority::Background => 10,
+            Priority::Idle => 19,
+        };
+        // Safety: on Linux the nice value is per thread, addressed by its id
+        unsafe {
+            libc::setpriority(libc::PRIO_PROCESS as _, libc::gettid() as libc::id_t, nice);
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let class = match priority {
+            Priority::Interactive => libc::qos_class_t::QOS_CLASS_USER_INITIATED,
+            Priority::Background => libc::qos_class_t::QOS_CLASS_UTILITY,
+            Priority::Idle => libc::qos_class_t::QOS_CLASS_BACKGROUND,
+        };
+        // Safety: only changes the calling thread's QoS class
+        unsafe {
+            libc::pthread_set_qos_class_self_np(class, 0);
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = priority;
+}
+
+/// Whether the machine is running on battery power.
+pub fn on_battery() -> bool {
+    if cfg!(target_os = "macos") {
+        return std::process::Command::new("pmset")
+            .args(["-g", "batt"])
+            .output()
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).contains("'Battery Power'"));
+    }
+    on_battery_in(Path::new("/sys/class/power_supply"))
+}
+
+/// Reads the power supplies listed under `dir`: on battery when no mains
+/// adapter is online and a battery is discharging.
+fn on_battery_in(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    let read = |path: &Path, name: &str| std::fs::read_to_string(path.join(name)).map(|s| s.trim().to_string()).unwrap_or_default();
+    let mut discharging = false;
+    for entry in entries.flatten() {
+        let supply = entry.path();
+        match read(&supply, "type").as_str() {
+            "Mains" | "USB" if read(&supply, "online") == "1" => return false,
+            "Battery" => discharging |= read(&supply, "status") == "Discharging",
+            _ => {}
+        }
+    }
+    discharging
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_on_battery() {
+        let dir = scratch_dir("power");
+        let supply = |name: &str, files: &[(&str, &str)]| {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            for (file, contents) in files {
+                std::fs::write(dir.join(name).join(file), format!("{}\n", contents)).unwrap();
+            }
+        };
+        // A desktop without supplies is never on battery
+        assert!(!on_battery_in(&dir));
+        supply("BAT0", &[("type", "Battery"), ("status", "Discharging")]);
+        supply("AC", &[("type", "Mains"), ("online", "0")]);
+        assert!(on_battery_in(&dir));
+        supply("AC", &[("type", "Mains"), ("online", "1")]);
+        assert!(!on_battery_in(&dir));
+        supply("AC", &[("online", "0")]);
+        supply("BAT0", &[("status", "Full")]);
+        assert!(!on_battery_in(&dir));
+
+        // Background jobs don't wait once the interactive decode is done
+        drop(interactive());
+        let start = Instant::now();
+        yield_to_interactive();
+        assert!(start.elapsed() < MAX_YIELD);
+    }
+}
@@ -0,0 +1,115 @@
+//! Progress of the decode the user is waiting for. The thread opening an image installs
+//! a sink with `watch`, and the loader reports each stage to it as the work goes on,
+//! so a slow RAW or HDR decode can show how far along it is.
+//!
+//! Each open is also numbered, for the `load` span its threads log in, so every message
+//! about one image carries the number however many threads it came from.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// The file being read into memory
+    Reading,
+    /// The sensor data of a RAW file being developed
+    Demosaicing,
+    /// A standard format being decoded, which the codecs don't report on
+    Decoding,
+    /// The decoded image being turned upright as its EXIF orientation says
+    Orienting,
+    /// The decoded image being cut into tiles and handed over to go onto the GPU
+    Uploading,
+}
+
+impl Stage {
+    /// Every stage, in the order a decode goes through them.
+    pub const ALL: [Stage; 5] = [Stage::Reading, Stage::Demosaicing, Stage::Decoding, Stage::Orienting, Stage::Uploading];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Stage::Reading => "Reading",
+            Stage::Demosaicing => "Demosaicing",
+            Stage::Decoding => "Decoding",
+            Stage::Orienting => "Rotating",
+            Stage::Uploading => "Uploading",
+        }
+    }
+}
+
+type Sink = Box<dyn FnMut(Stage, Option<f32>)>;
+type Last = Option<(Stage, Option<u32>)>;
+
+thread_local! {
+    /// The sink of this thread, and the last stage and percentage passed to it
+    static SINK: RefCell<Option<(Sink, Last)>> = RefCell::new(None);
+}
+
+/// Run `job`, passing what it reports to `sink`: the stage and how much of it is done,
+/// from 0 to 1, or `None` when that isn't known. Repeats of the same stage and whole
+/// percentage are dropped so a tight loop doesn't flood the sink.
+pub fn watch<R>(sink: impl FnMut(Stage, Option<f32>) + 'static, job: impl FnOnce() -> R) -> R {
+    let previous = SINK.with(|s| s.borrow_mut().replace((Box::new(sink), None)));
+    let result = job();
+    SINK.with(|s| *s.borrow_mut() = previous);
+    result
+}
+
+/// Tell the sink of this thread, if any, how far `stage` has got.
+pub fn report(stage: Stage, fraction: Option<f32>) {
+    SINK.with(|s| {
+        if let Some((sink, last)) = s.borrow_mut().as_mut() {
+            let fraction = fraction.map(|f| f.clamp(0.0, 1.0));
+            let key = (stage, fraction.map(|f| (f * 100.0) as u32));
+            if *last != Some(key) {
+                *last = Some(key);
+                sink(stage, fraction);
+            }
+        }
+    });
+}
+
+static NEXT_LOAD: AtomicU64 = AtomicU64::new(1);
+
+/// A number for an image about to be opened, different from every earlier one.
+fn next_load() -> u64 {
+    NEXT_LOAD.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The span of an image being opened: what is logged in it, on whichever thread it is
+/// entered, is tagged `load{n=N}`.
+pub fn load_span() -> tracing::Span {
+    tracing::info_span!("load", n = next_load())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_watch_dedupes_reports() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = {
+            let seen = seen.clone();
+            move |stage, fraction| seen.borrow_mut().push((stage, fraction))
+        };
+        let answer = watch(sink, || {
+            report(Stage::Reading, Some(0.0));
+            report(Stage::Reading, Some(0.001));
+            report(Stage::Reading, Some(0.5));
+            report(Stage::Decoding, None);
+            report(Stage::Decoding, None);
+            42
+        });
+        assert_eq!(answer, 42);
+        assert_eq!(
+            *seen.borrow(),
+            vec![(Stage::Reading, Some(0.0)), (Stage::Reading, Some(0.5)), (Stage::Decoding, None)]
+        );
+
+        // Nothing is watching once the job is done
+        report(Stage::Uploading, None);
+        assert_eq!(seen.borrow().len(), 3);
+    }
+}
@@ -0,0 +1,110 @@
+use winit::event::KeyEvent;
+use winit::keyboard::{Key, NamedKey};
+
+/// What a text prompt is collecting input for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    Note,
+    Filter,
+    GoTo,
+    Rename,
+    Title,
+    Copyright,
+    /// Comma-separated
+    Keywords,
+    /// Format and optional longest side for converting the folder
+    Convert,
+    /// Words, metadata terms and dates to find in the library
+    Search,
+    /// The PIN that unlocks private images
+    Pin,
+    /// A PIN to choose, the first time private images are unlocked
+    NewPin,
+}
+
+impl PromptKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PromptKind::Note => "Note",
+            PromptKind::Filter => "Filter",
+            PromptKind::GoTo => "Go to image number or name",
+            PromptKind::Rename => "Rename to",
+            PromptKind::Title => "Title",
+            PromptKind::Copyright => "Copyright",
+            PromptKind::Keywords => "Keywords (comma-separated)",
+            PromptKind::Convert => "Convert folder to (jpeg, png or webp, then the longest side)",
+            PromptKind::Search => "Search the library (words, camera:, lens:, iso:>3200, rating:>=3, date:2023..2024-06)",
+            PromptKind::Pin => "PIN for private images",
+            PromptKind::NewPin => "Choose a PIN for private images",
+        }
+    }
+
+    /// Typed text that is shown as dots.
+    pub fn secret(&self) -> bool {
+        matches!(self, PromptKind::Pin | PromptKind::NewPin)
+    }
+}
+
+pub enum PromptOutcome {
+    Submit(String),
+    Cancel,
+}
+
+/// Single-line text input shown in the window title while active.
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub text: String,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind, initial: &str) -> Self {
+        Self {
+            kind,
+            text: initial.to_string(),
+        }
+    }
+
+    pub fn handle_key(&mut self, event: &KeyEvent) -> Option<PromptOutcome> {
+        match &event.logical_key {
+            Key::Named(NamedKey::Enter) => return Some(PromptOutcome::Submit(self.text.clone())),
+            Key::Named(NamedKey::Escape) => return Some(PromptOutcome::Cancel),
+            Key::Named(NamedKey::Backspace) => self.backspace(),
+            _ => {
+                if let Some(text) = &event.text {
+                    self.push_str(text);
+                }
+            }
+        }
+        None
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.text.extend(text.chars().filter(|c| !c.is_control()));
+    }
+
+    fn backspace(&mut self) {
+        self.text.pop();
+    }
+
+    pub fn display(&self) -> String {
+        let text = if self.kind.secret() { "•".repeat(self.text.chars().count()) } else { self.text.clone() };
+        format!("{}: {}_", self.kind.label(), text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editing() {
+        let mut prompt = Prompt::new(PromptKind::Note, "ab");
+        prompt.push_str("c\r\u{8}é");
+        assert_eq!(prompt.text, "abcé");
+        prompt.backspace();
+        prompt.backspace();
+        assert_eq!(prompt.text, "ab");
+        assert_eq!(prompt.display(), "Note: ab_");
+        assert_eq!(Prompt::new(PromptKind::Pin, "1234").display(), "PIN for private images: ••••_");
+    }
+}
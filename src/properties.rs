@@ -0,0 +1,189 @@
+//! What the properties panel says about the current file: where it is, how big it is,
+//! when it changed, and what the decoder found in it.
+
+use image::ColorType;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Properties {
+    pub path: PathBuf,
+    pub file_size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+    pub dimensions: (u32, u32),
+    /// The pixel format the loader decoded to
+    pub color: ColorType,
+    /// Description of the embedded ICC profile, or the EXIF color space
+    pub color_space: Option<String>,
+    pub compression: Option<String>,
+}
+
+impl Properties {
+    /// Gather what is known about `path`, decoded at `dimensions` into `color`.
+    pub fn read(path: &Path, dimensions: (u32, u32), color: ColorType, exif: &HashMap<String, String>) -> Self {
+        let meta = std::fs::metadata(path).ok();
+        // Like geo tags, profiles are only parsed in-process when decoding isn't sandboxed
+        let icc = if crate::sandbox::enabled() { None } else { read_icc(path) };
+        let exif_field = |key: &str| exif.get(key).map(|v| v.trim().trim_matches('"').to_string()).filter(|v| !v.is_empty());
+        Self {
+            path: path.to_path_buf(),
+            file_size: meta.as_ref().map(|m| m.len()),
+            modified: meta.as_ref().and_then(|m| m.modified().ok()),
+            created: meta.as_ref().and_then(|m| m.created().ok()),
+            dimensions,
+            color,
+            color_space: icc.as_deref().and_then(icc_description).or_else(|| exif_field("ColorSpace")),
+            compression: format_compression(path).map(str::to_string).or_else(|| exif_field("Compression")),
+        }
+    }
+
+    /// Label and value of each row, in the order shown.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let (w, h) = self.dimensions;
+        let unknown = || "Unknown".to_string();
+        vec![
+            ("Path", self.path.display().to_string()),
+            ("File size", self.file_size.map_or_else(unknown, |b| format!("{} ({} bytes)", crate::cache::format_size(b), b))),
+            ("Modified", self.modified.map_or_else(unknown, format_time)),
+            ("Created", self.created.map_or_else(unknown, format_time)),
+            ("Dimensions", format!("{} x {} px", w, h)),
+            ("Megapixels", format!("{:.1}", w as f64 * h as f64 / 1_000_000.0)),
+            ("Decoded as", describe_color(self.color)),
+            ("Color space", self.color_space.clone().unwrap_or_else(unknown)),
+            ("Compression", self.compression.clone().unwrap_or_else(unknown)),
+        ]
+    }
+}
+
+/// "2024-06-21 10:00:00 UTC"
+fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (date, time) = crate::rename::format_unix_time(secs);
+    format!("{} {}:{}:{} UTC", date, &time[0..2], &time[2..4], &time[4..6])
+}
+
+/// "16-bit RGB with alpha", from the channels and depth of `color`.
+fn describe_color(color: ColorType) -> String {
+    let channels = match (color.has_color(), color.has_alpha()) {
+        (true, true) => "RGB with alpha",
+        (true, false) => "RGB",
+        (false, true) => "grayscale with alpha",
+        (false, false) => "grayscale",
+    };
+    let bits = color.bits_per_pixel() / color.channel_count().max(1) as u16;
+    let float = matches!(color, ColorType::Rgb32F | ColorType::Rgba32F);
+    format!("{}-bit{} {}", bits, if float { " float" } else { "" }, channels)
+}
+
+/// The compression every file of the format uses. Others, like TIFF, say in their EXIF.
+fn format_compression(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "jpg" | "jpeg" => "JPEG (lossy)",
+        "png" => "Deflate (lossless)",
+        "gif" => "LZW (lossless)",
+        "bmp" => "None",
+        "qoi" => "QOI (lossless)",
+        _ => return None,
+    })
+}
+
+/// The ICC profile embedded in a JPEG, PNG or WebP file.
+fn read_icc(path: &Path) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+    let reader = || std::fs::File::open(path).ok().map(std::io::BufReader::new);
+    match image::ImageFormat::from_path(path).ok()? {
+        image::ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(reader()?).ok()?.icc_profile(),
+        image::ImageFormat::Png => image::codecs::png::PngDecoder::new(reader()?).ok()?.icc_profile(),
+        image::ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(reader()?).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// The description tag of an ICC profile, e.g. "Display P3": the ASCII text of a
+/// version 2 `desc` tag or the first UTF-16 string of a version 4 `mluc` one.
+pub fn icc_description(profile: &[u8]) -> Option<String> {
+    let u32_at = |at: usize| profile.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let count = u32_at(128)?;
+    let entry = (0..count.min(256)).map(|i| 132 + i * 12).find(|&e| profile.get(e..e + 4) == Some(&b"desc"[..]))?;
+    let (offset, size) = (u32_at(entry + 4)?, u32_at(entry + 8)?);
+    let tag = profile.get(offset..offset.checked_add(size)?)?;
+    let text = match tag.get(0..4)? {
+        b"desc" => {
+            let len = u32_at(offset + 8)?;
+            let ascii = tag.get(12..12 + len)?;
+            String::from_utf8_lossy(ascii).trim_end_matches('\0').to_string()
+        }
+        b"mluc" => {
+            let (len, at) = (u32_at(offset + 20)?, u32_at(offset + 24)?);
+            let utf16: Vec<u16> = tag.get(at..at + len)?.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&utf16)
+        }
+        _ => return None,
+    };
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    /// A profile holding only a description tag of `kind` with `body`.
+    fn profile(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data.extend(1u32.to_be_bytes());
+        let offset = 128 + 4 + 12;
+        data.extend(b"desc");
+        data.extend((offset as u32).to_be_bytes());
+        data.extend(((8 + body.len()) as u32).to_be_bytes());
+        data.extend(kind);
+        data.extend([0; 4]);
+        data.extend(body);
+        data
+    }
+
+    #[test]
+    fn test_icc_description() {
+        let mut v2 = 17u32.to_be_bytes().to_vec();
+        v2.extend(b"sRGB IEC61966-2.1\0");
+        assert_eq!(icc_description(&profile(b"desc", &v2)).as_deref(), Some("sRGB IEC61966-2.1"));
+
+        let name: Vec<u8> = "Display P3".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut v4 = Vec::new();
+        v4.extend(1u32.to_be_bytes());
+        v4.extend(12u32.to_be_bytes());
+        v4.extend(b"enUS");
+        v4.extend((name.len() as u32).to_be_bytes());
+        v4.extend(28u32.to_be_bytes());
+        v4.extend(&name);
+        assert_eq!(icc_description(&profile(b"mluc", &v4)).as_deref(), Some("Display P3"));
+
+        // Truncated or foreign data gives nothing rather than garbage
+        assert_eq!(icc_description(&profile(b"desc", &v2)[..150]), None);
+        assert_eq!(icc_description(b"not a profile"), None);
+    }
+
+    #[test]
+    fn test_rows() {
+        let exif = HashMap::from([("ColorSpace".to_string(), "sRGB".to_string())]);
+        let dir = scratch_dir("properties");
+        let path = dir.join("properties.tif");
+        std::fs::write(&path, vec![0u8; 2048]).unwrap();
+        let properties = Properties::read(&path, (6000, 4000), ColorType::Rgba16, &exif);
+        let rows: HashMap<_, _> = properties.rows().into_iter().collect();
+        assert_eq!(rows["File size"], "2 KB (2048 bytes)");
+        assert_eq!(rows["Megapixels"], "24.0");
+        assert_eq!(rows["Decoded as"], "16-bit RGB with alpha");
+        assert_eq!(rows["Color space"], "sRGB");
+        assert_eq!(rows["Compression"], "Unknown");
+        assert!(rows["Modified"].ends_with(" UTC"));
+
+        assert_eq!(describe_color(ColorType::L8), "8-bit grayscale");
+        assert_eq!(describe_color(ColorType::Rgb32F), "32-bit float RGB");
+        assert_eq!(format_time(UNIX_EPOCH + std::time::Duration::from_secs(86_400 + 3_723)), "1970-01-02 01:02:03 UTC");
+    }
+}
@@ -0,0 +1,172 @@
+//! Screen-sized stand-ins for very large JPEGs. Decoding 100 megapixels takes seconds,
+//! while the decoder's DCT scaling gets an eighth of the resolution in a fraction of
+//! that. The proxy is shown at once, as the overview of a tiled image, and the full
+//! resolution is only decoded once the view is zoomed in past what the proxy holds.
+
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageDecoder};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use crate::loader::LoadedImage;
+use crate::pyramid::{GeneratedPyramid, TileSource, TILE_SIZE};
+
+/// Pixels from which an image is opened through a proxy; 0 never does
+static MIN_PIXELS: AtomicU64 = AtomicU64::new(100_000_000);
+/// Longest side of a proxy: that of the screen
+static SCREEN_SIDE: AtomicU32 = AtomicU32::new(2048);
+
+/// Open images of at least `megapixels` through a proxy as large as a screen whose
+/// longest side is `screen_side`. Call before anything is loaded.
+pub fn configure(megapixels: u64, screen_side: Option<u32>) {
+    MIN_PIXELS.store(megapixels * 1_000_000, Ordering::Relaxed);
+    if let Some(side) = screen_side.filter(|&s| s > 0) {
+        SCREEN_SIDE.store(side, Ordering::Relaxed);
+    }
+}
+
+/// The proxy and full-resolution source of `path`, if it is a JPEG large enough to
+/// need one. Anything the proxy can't be made from is left to the usual decode.
+pub fn load(path: &Path) -> Option<(LoadedImage, Arc<dyn TileSource>)> {
+    let min_pixels = MIN_PIXELS.load(Ordering::Relaxed);
+    // Like tiled TIFFs, the decoder runs in this process, so sandboxed decoding keeps
+    // to whole-image decodes
+    if min_pixels == 0 || crate::sandbox::enabled() || !crate::navigator::has_extension(path, &["jpg", "jpeg"]) {
+        return None;
+    }
+    open(path, min_pixels, SCREEN_SIDE.load(Ordering::Relaxed)).ok().flatten()
+}
+
+fn open(path: &Path, min_pixels: u64, screen_side: u32) -> Result<Option<(LoadedImage, Arc<dyn TileSource>)>> {
+    let start = Instant::now();
+    let buf = std::fs::read(path)?;
+    let mut decoder = image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&buf))?;
+    let (w, h) = decoder.dimensions();
+    if (w as u64 * h as u64) < min_pixels {
+        return Ok(None);
+    }
+    crate::progress::report(crate::progress::Stage::Decoding, None);
+    // The decoder picks the smallest of 1/8, 1/4, 1/2 or full size covering the request
+    let scale = screen_side as f64 / w.max(h) as f64;
+    let requested = |v: u32| (v as f64 * scale).ceil().clamp(1.0, u16::MAX as f64) as u16;
+    decoder.scale(requested(w), requested(h))?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    if image.width().max(image.height()) > screen_side {
+        image = image.resize(screen_side, screen_side, FilterType::Triangle);
+    }
+
+    let (exif, orientation) = crate::loader::parse_exif(&buf);
+    let mut size = (w, h);
+    if let Some(orientation) = orientation {
+        image = crate::raw::apply_orientation(image, orientation);
+        if (5..=8).contains(&orientation) {
+            size = (h, w);
+        }
+    }
+    let source = ProxySource {
+        path: path.to_path_buf(),
+        size,
+        proxy_scale: size.0 as f32 / image.width() as f32,
+        full: OnceLock::new(),
+    };
+    let loaded = LoadedImage {
+        image,
+        exif,
+        load_time: start.elapsed(),
+        path: path.to_path_buf(),
+        xmp: crate::xmp::read_sidecar(path),
+        frames: Vec::new(),
+    };
+    Ok(Some((loaded, Arc::new(source))))
+}
+
+/// The full resolution behind a proxy, decoded when the first tile is asked for.
+pub struct ProxySource {
+    path: PathBuf,
+    size: (u32, u32),
+    proxy_scale: f32,
+    full: OnceLock<std::result::Result<GeneratedPyramid, String>>,
+}
+
+impl std::fmt::Debug for ProxySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxySource")
+            .field("path", &self.path)
+            .field("size", &self.size)
+            .field("decoded", &self.full.get().is_some())
+            .finish()
+    }
+}
+
+impl ProxySource {
+    /// The full-resolution pyramid; tiles asked for meanwhile wait for the one decode.
+    fn full(&self) -> Result<&GeneratedPyramid> {
+        let full = self.full.get_or_init(|| {
+            let decoded = std::fs::read(&self.path).map_err(anyhow::Error::from).and_then(|buf| crate::loader::decode_standard(&buf));
+            match decoded {
+                Ok((image, _)) if image.dimensions() == self.size => Ok(GeneratedPyramid::new(&image)),
+                Ok((image, _)) => Err(format!("Decoded at {:?} instead of {:?}", image.dimensions(), self.size)),
+                Err(e) => Err(format!("{:#}", e)),
+            }
+        });
+        full.as_ref().map_err(|e| anyhow!("Failed to decode {} at full resolution: {}", self.path.display(), e))
+    }
+}
+
+impl TileSource for ProxySource {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn level_count(&self) -> u32 {
+        crate::pyramid::level_count(self.size, TILE_SIZE)
+    }
+
+    fn tile_size(&self, _level: u32) -> (u32, u32) {
+        (TILE_SIZE, TILE_SIZE)
+    }
+
+    fn tile(&self, level: u32, col: u32, row: u32) -> Result<DynamicImage> {
+        self.full()?.tile(level, col, row)
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        self.full.get().and_then(|f| f.as_ref().ok()).map_or(0, |f| f.memory_bytes())
+    }
+
+    fn proxy_scale(&self) -> Option<f32> {
+        Some(self.proxy_scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_proxy_then_full_resolution() {
+        let dir = scratch_dir("proxy");
+        let path = dir.join("proxy.jpg");
+        let full = image::RgbImage::from_fn(1600, 1200, |x, y| image::Rgb([(x / 8) as u8, (y / 8) as u8, 128]));
+        full.save(&path).unwrap();
+
+        // Too small to need a proxy
+        assert!(open(&path, 2_000_000, 200).unwrap().is_none());
+
+        // An eighth of the resolution fits a 200px screen without decoding the rest
+        let (loaded, source) = open(&path, 1, 200).unwrap().unwrap();
+        assert_eq!(loaded.image.dimensions(), (200, 150));
+        assert_eq!(source.size(), (1600, 1200));
+        assert_eq!(source.proxy_scale(), Some(8.0));
+        assert_eq!(source.memory_bytes(), 0);
+
+        // 1600 -> 800 -> 400, like a pyramid generated from the decoded image
+        assert_eq!(source.level_count(), 3);
+        let tile = source.tile(0, 3, 2).unwrap();
+        assert_eq!(tile.dimensions(), (1600 - 3 * 512, 1200 - 2 * 512));
+        assert!(source.memory_bytes() >= 1600 * 1200 * 4);
+    }
+}
@@ -0,0 +1,624 @@
+//! Multi-resolution tile pyramids for images too large for one texture.
+//! Level 0 is full resolution and every following level is smaller, usually
+//! half the size of the previous one. Only the tiles covering the visible part
+//! of the image, at the resolution it is displayed at, are streamed to the GPU.
+
+use anyhow::{anyhow, Result};
+use image::{imageops, DynamicImage, GenericImageView, RgbaImage};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::loader::LoadedImage;
+
+/// Tile edge length of generated pyramids.
+pub const TILE_SIZE: u32 = 512;
+/// Images larger than this on either side are displayed through a pyramid.
+pub const PYRAMID_THRESHOLD: u32 = 8192;
+/// Longest side of the overview texture drawn underneath the tiles.
+const OVERVIEW_SIZE: u32 = 2048;
+/// Tiles kept on the GPU; a 512px RGBA tile takes 1 MiB.
+const MAX_GPU_TILES: usize = 384;
+/// Tiles being decoded at once, so panning quickly doesn't queue up stale work.
+const MAX_IN_FLIGHT: usize = 16;
+
+/// Where tiles come from: an in-memory pyramid or tiles on disk.
+pub trait TileSource: Send + Sync + std::fmt::Debug {
+    /// Full-resolution size.
+    fn size(&self) -> (u32, u32);
+    fn level_count(&self) -> u32;
+    /// Tile width and height within a level.
+    fn tile_size(&self, level: u32) -> (u32, u32);
+    fn tile(&self, level: u32, col: u32, row: u32) -> Result<DynamicImage>;
+
+    /// Decoded pixels the source keeps in memory.
+    fn memory_bytes(&self) -> u64 {
+        0
+    }
+
+    /// Full-resolution pixels per pixel of an overview standing in for the image until
+    /// the view needs more detail than it has; tiles are only fetched past that.
+    fn proxy_scale(&self) -> Option<f32> {
+        None
+    }
+
+    /// Size of a level; by default each level halves the previous one.
+    fn level_size(&self, level: u32) -> (u32, u32) {
+        level_size(self.size(), level)
+    }
+
+    /// The whole image at no more than `max_side` pixels, for the overview texture.
+    /// Stitched from the first level that fits, or scaled down tile by tile
+    /// from the smallest level when none does.
+    fn overview(&self, max_side: u32) -> Result<DynamicImage> {
+        let level = (0..self.level_count())
+            .find(|&l| {
+                let (w, h) = self.level_size(l);
+                w.max(h) <= max_side
+            })
+            .unwrap_or(self.level_count() - 1);
+        let (w, h) = self.level_size(level);
+        let scale = (max_side as f64 / w.max(h) as f64).min(1.0);
+        let scaled = |v: u32| (v as f64 * scale).round() as u32;
+        let (tw, th) = self.tile_size(level);
+        let (cols, rows) = grid((w, h), (tw, th));
+        let mut canvas = RgbaImage::new(scaled(w).max(1), scaled(h).max(1));
+        for row in 0..rows {
+            for col in 0..cols {
+                let mut tile = self.tile(level, col, row)?.to_rgba8();
+                let (x, y) = (col * tw, row * th);
+                if scale < 1.0 {
+                    let (x1, y1) = (scaled(x + tile.width()), scaled(y + tile.height()));
+                    if x1 <= scaled(x) || y1 <= scaled(y) {
+                        continue;
+                    }
+                    tile = imageops::resize(&tile, x1 - scaled(x), y1 - scaled(y), imageops::FilterType::Triangle);
+                }
+                imageops::replace(&mut canvas, &tile, scaled(x) as i64, scaled(y) as i64);
+            }
+        }
+        Ok(DynamicImage::ImageRgba8(canvas))
+    }
+}
+
+/// Size of a level in a pyramid where every level halves the previous one.
+pub fn level_size(full: (u32, u32), level: u32) -> (u32, u32) {
+    let scale = 1u64 << level.min(63);
+    (
+        (full.0 as u64).div_ceil(scale).max(1) as u32,
+        (full.1 as u64).div_ceil(scale).max(1) as u32,
+    )
+}
+
+/// Levels needed until the whole image fits in one tile.
+pub fn level_count(full: (u32, u32), tile_size: u32) -> u32 {
+    let mut count = 1;
+    loop {
+        let (w, h) = level_size(full, count - 1);
+        if w.max(h) <= tile_size {
+            return count;
+        }
+        count += 1;
+    }
+}
+
+/// Columns and rows of tiles in a level.
+pub fn grid(level_size: (u32, u32), tile_size: (u32, u32)) -> (u32, u32) {
+    (level_size.0.div_ceil(tile_size.0), level_size.1.div_ceil(tile_size.1))
+}
+
+/// The level whose resolution best matches the display: the coarsest one that
+/// still has at least one image pixel per screen pixel.
+pub fn choose_level(source: &dyn TileSource, image_pixels_per_screen_pixel: f32) -> u32 {
+    let full_width = source.size().0 as f32;
+    // A little slack so rounded level sizes still count as an exact match
+    (1..source.level_count())
+        .take_while(|&l| full_width / source.level_size(l).0 as f32 <= image_pixels_per_screen_pixel * 1.01)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Texture coordinates `[u0, v0, u1, v1]` covered by a tile.
+pub fn tile_uv(level_size: (u32, u32), tile_size: (u32, u32), col: u32, row: u32) -> [f32; 4] {
+    let (w, h) = level_size;
+    let x0 = col * tile_size.0;
+    let y0 = row * tile_size.1;
+    [
+        x0 as f32 / w as f32,
+        y0 as f32 / h as f32,
+        (x0 + tile_size.0).min(w) as f32 / w as f32,
+        (y0 + tile_size.1).min(h) as f32 / h as f32,
+    ]
+}
+
+/// Tiles of a level intersecting the visible texture-coordinate rectangle.
+pub fn visible_tiles(level_size: (u32, u32), tile_size: (u32, u32), visible: [f32; 4]) -> Vec<(u32, u32)> {
+    let (w, h) = level_size;
+    let (cols, rows) = grid(level_size, tile_size);
+    let span = |lo: f32, hi: f32, extent: u32, tile: u32, count: u32| {
+        let first = ((lo.clamp(0.0, 1.0) * extent as f32) as u32 / tile).min(count - 1);
+        let last = (((hi.clamp(0.0, 1.0) * extent as f32).ceil() as u32).saturating_sub(1) / tile).min(count - 1);
+        first..=last
+    };
+    if visible[2] <= 0.0 || visible[3] <= 0.0 || visible[0] >= 1.0 || visible[1] >= 1.0 {
+        return Vec::new();
+    }
+    let mut tiles = Vec::new();
+    for row in span(visible[1], visible[3], h, tile_size.1, rows) {
+        for col in span(visible[0], visible[2], w, tile_size.0, cols) {
+            tiles.push((col, row));
+        }
+    }
+    tiles
+}
+
+/// Load an image for display. Tiled sources on disk, very large JPEGs and decoded
+/// images larger than [`PYRAMID_THRESHOLD`] come back as an overview plus the tiles
+/// to stream.
+pub fn load(path: &Path) -> Result<(LoadedImage, Option<Arc<dyn TileSource>>)> {
+    if let Some(source) = open_source(path)? {
+        let loaded = load_overview(path, source.as_ref())?;
+        return Ok((loaded, Some(source)));
+    }
+    if let Some((loaded, source)) = crate::proxy::load(path) {
+        return Ok((loaded, Some(source)));
+    }
+    let mut loaded = crate::sandbox::load(path)?;
+    let (w, h) = loaded.image.dimensions();
+    if w.max(h) <= PYRAMID_THRESHOLD {
+        return Ok((loaded, None));
+    }
+    crate::progress::report(crate::progress::Stage::Uploading, None);
+    let pyramid = GeneratedPyramid::new(&loaded.image);
+    loaded.image = pyramid.overview(OVERVIEW_SIZE)?;
+    Ok((loaded, Some(Arc::new(pyramid))))
+}
+
+/// Files read tile by tile instead of being decoded whole: Deep Zoom images, and
+/// large tiled or pyramidal TIFFs. None while decoding is sandboxed.
+pub fn open_source(path: &Path) -> Result<Option<Arc<dyn TileSource>>> {
+    // Descriptors, headers and tiles would all be parsed in this process, so sandboxed
+    // decoding keeps to whole-image decodes in the worker
+    if crate::sandbox::enabled() {
+        return Ok(None);
+    }
+    if is_dzi(path) {
+        return Ok(Some(Arc::new(DziSource::open(path)?)));
+    }
+    if crate::slide::is_tiff(path) {
+        if let Ok(source) = crate::slide::TiffSource::open(path) {
+            let (w, h) = source.size();
+            if w.max(h) > PYRAMID_THRESHOLD {
+                return Ok(Some(Arc::new(source)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn is_dzi(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("dzi"))
+}
+
+/// The overview of a tiled source, standing in for the decoded image.
+pub fn load_overview(path: &Path, source: &dyn TileSource) -> Result<LoadedImage> {
+    let start = Instant::now();
+    let image = source.overview(OVERVIEW_SIZE)?;
+    Ok(LoadedImage {
+        image,
+        exif: HashMap::new(),
+        load_time: start.elapsed(),
+        path: path.to_path_buf(),
+        xmp: crate::xmp::read_sidecar(path),
+        frames: Vec::new(),
+    })
+}
+
+/// A pyramid built in memory from a decoded image.
+pub struct GeneratedPyramid {
+    levels: Vec<RgbaImage>,
+}
+
+impl std::fmt::Debug for GeneratedPyramid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeneratedPyramid").field("levels", &self.levels.len()).finish()
+    }
+}
+
+impl GeneratedPyramid {
+    pub fn new(image: &DynamicImage) -> Self {
+        let mut levels = vec![image.to_rgba8()];
+        let count = level_count(image.dimensions(), TILE_SIZE);
+        for level in 1..count {
+            let (w, h) = level_size(image.dimensions(), level);
+            let next = imageops::resize(&levels[level as usize - 1], w, h, imageops::FilterType::Triangle);
+            levels.push(next);
+        }
+        Self { levels }
+    }
+}
+
+impl TileSource for GeneratedPyramid {
+    fn size(&self) -> (u32, u32) {
+        self.levels[0].dimensions()
+    }
+
+    fn tile_size(&self, _level: u32) -> (u32, u32) {
+        (TILE_SIZE, TILE_SIZE)
+    }
+
+    fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        self.levels.iter().map(|l| l.as_raw().len() as u64).sum()
+    }
+
+    fn tile(&self, level: u32, col: u32, row: u32) -> Result<DynamicImage> {
+        let image = self.levels.get(level as usize).ok_or_else(|| anyhow!("No level {}", level))?;
+        let (x, y) = (col * TILE_SIZE, row * TILE_SIZE);
+        if x >= image.width() || y >= image.height() {
+            return Err(anyhow!("Tile {},{} is outside level {}", col, row, level));
+        }
+        let w = TILE_SIZE.min(image.width() - x);
+        let h = TILE_SIZE.min(image.height() - y);
+        Ok(DynamicImage::ImageRgba8(imageops::crop_imm(image, x, y, w, h).to_image()))
+    }
+
+    fn overview(&self, max_side: u32) -> Result<DynamicImage> {
+        let level = self
+            .levels
+            .iter()
+            .find(|l| l.width().max(l.height()) <= max_side)
+            .unwrap_or(&self.levels[self.levels.len() - 1]);
+        Ok(DynamicImage::ImageRgba8(level.clone()))
+    }
+}
+
+/// A Deep Zoom Image: an XML descriptor next to a `<name>_files` folder holding
+/// one subfolder of `<col>_<row>.<format>` tiles per level, level 0 being 1x1.
+#[derive(Debug)]
+pub struct DziSource {
+    tiles_dir: PathBuf,
+    format: String,
+    tile_size: u32,
+    overlap: u32,
+    width: u32,
+    height: u32,
+    max_level: u32,
+}
+
+impl DziSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let number = |name: &str| -> Result<u32> {
+            attribute(&text, name)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| anyhow!("Missing or invalid {} in {:?}", name, path))
+        };
+        let (width, height) = (number("Width")?, number("Height")?);
+        let (tile_size, overlap) = (number("TileSize")?, number("Overlap")?);
+        if width == 0 || height == 0 || width > 1 << 30 || height > 1 << 30 || !(1..=4096).contains(&tile_size) || overlap >= tile_size {
+            return Err(anyhow!("Unsupported Deep Zoom geometry in {:?}", path));
+        }
+        let format = attribute(&text, "Format").ok_or_else(|| anyhow!("Missing Format in {:?}", path))?;
+        if !format.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow!("Invalid tile format {:?}", format));
+        }
+
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+        Ok(Self {
+            tiles_dir: path.with_file_name(format!("{}_files", stem)),
+            format: format.to_string(),
+            tile_size,
+            overlap,
+            width,
+            height,
+            max_level: 32 - (width.max(height) - 1).leading_zeros(),
+        })
+    }
+}
+
+impl TileSource for DziSource {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn tile_size(&self, _level: u32) -> (u32, u32) {
+        (self.tile_size, self.tile_size)
+    }
+
+    fn level_count(&self) -> u32 {
+        level_count(self.size(), self.tile_size).min(self.max_level + 1)
+    }
+
+    fn tile(&self, level: u32, col: u32, row: u32) -> Result<DynamicImage> {
+        let dzi_level = self.max_level.checked_sub(level).ok_or_else(|| anyhow!("No level {}", level))?;
+        let path = self.tiles_dir.join(dzi_level.to_string()).join(format!("{}_{}.{}", col, row, self.format));
+        let image = crate::sandbox::load(&path)?.image;
+
+        // Tiles carry `overlap` extra pixels on every side that has a neighbour
+        let (w, h) = level_size(self.size(), level);
+        let x = if col > 0 { self.overlap } else { 0 };
+        let y = if row > 0 { self.overlap } else { 0 };
+        let tile_w = self.tile_size.min(w.saturating_sub(col * self.tile_size));
+        let tile_h = self.tile_size.min(h.saturating_sub(row * self.tile_size));
+        if image.width() < x + tile_w || image.height() < y + tile_h {
+            return Err(anyhow!("Tile {:?} is smaller than expected", path));
+        }
+        Ok(image.crop_imm(x, y, tile_w, tile_h))
+    }
+}
+
+/// Value of an XML attribute, e.g. `attribute(r#"<Size Width="10"/>"#, "Width")`.
+fn attribute<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!("{}=\"", name);
+    let mut search = text;
+    while let Some(pos) = search.find(&needle) {
+        let preceded_by_space = search[..pos].ends_with(char::is_whitespace);
+        let rest = &search[pos + needle.len()..];
+        if preceded_by_space {
+            return rest.split('"').next();
+        }
+        search = rest;
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TileKey {
+    level: u32,
+    col: u32,
+    row: u32,
+}
+
+struct GpuTile {
+    texture: crate::texture::Texture,
+    bind_group: wgpu::BindGroup,
+    last_used: u64,
+}
+
+/// The GPU side of a tiled image: decodes visible tiles on the rayon pool and
+/// keeps the most recently used ones resident.
+pub struct TiledImage {
+    source: Arc<dyn TileSource>,
+    resident: HashMap<TileKey, GpuTile>,
+    in_flight: HashSet<TileKey>,
+    failed: HashSet<TileKey>,
+    wanted: Vec<TileKey>,
+    sender: Sender<(TileKey, Result<DynamicImage>)>,
+    receiver: Receiver<(TileKey, Result<DynamicImage>)>,
+    frame: u64,
+    /// The span of the load that opened the image, which tile failures are logged under
+    load: tracing::Span,
+}
+
+impl TiledImage {
+    pub fn new(source: Arc<dyn TileSource>) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            source,
+            resident: HashMap::new(),
+            in_flight: HashSet::new(),
+            failed: HashSet::new(),
+            wanted: Vec::new(),
+            sender,
+            receiver,
+            frame: 0,
+            load: tracing::Span::current(),
+        }
+    }
+
+    /// Upload finished tiles and request the ones covering `visible` (texture
+    /// coordinates) at the given magnification.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        visible: [f32; 4],
+        image_pixels_per_screen_pixel: f32,
+    ) {
+        self.frame += 1;
+        for (key, result) in self.receiver.try_iter() {
+            self.in_flight.remove(&key);
+            match result.and_then(|img| crate::texture::Texture::from_image(device, queue, &img, Some("Tile"))) {
+                Ok(texture) => {
+                    let bind_group = texture.create_bind_group(device, layout);
+                    self.resident.insert(key, GpuTile { texture, bind_group, last_used: self.frame });
+                }
+                Err(e) => {
+                    self.load.in_scope(|| tracing::warn!("Failed to load tile {:?}: {:?}", key, e));
+                    self.failed.insert(key);
+                }
+            }
+        }
+
+        let level = choose_level(self.source.as_ref(), image_pixels_per_screen_pixel);
+        let tiles = visible_tiles(self.source.level_size(level), self.source.tile_size(level), visible);
+        // Without a coarse enough level (e.g. a single-resolution TIFF seen whole), or
+        // while a proxy is as sharp as the view needs, the overview is all that is drawn
+        let proxy_suffices = self.source.proxy_scale().is_some_and(|scale| image_pixels_per_screen_pixel >= scale);
+        let before = std::mem::take(&mut self.wanted);
+        if tiles.len() <= MAX_GPU_TILES / 2 && !proxy_suffices {
+            self.wanted = tiles.into_iter().map(|(col, row)| TileKey { level, col, row }).collect();
+        }
+        // Each tile coming into view counts once, found on the GPU or not
+        for key in self.wanted.iter().filter(|key| !before.contains(key)) {
+            crate::stats::TILE_CACHE.record(self.resident.contains_key(key));
+        }
+
+        for key in &self.wanted {
+            if let Some(tile) = self.resident.get_mut(key) {
+                tile.last_used = self.frame;
+            } else if !self.in_flight.contains(key) && !self.failed.contains(key) && self.in_flight.len() < MAX_IN_FLIGHT {
+                self.in_flight.insert(*key);
+                let source = self.source.clone();
+                let sender = self.sender.clone();
+                let key = *key;
+                crate::priority::spawn_interactive(move || {
+                    let _ = sender.send((key, source.tile(key.level, key.col, key.row)));
+                    crate::redraw::wake();
+                });
+            }
+        }
+
+        // Evict least recently used tiles, never the ones on screen
+        if self.resident.len() > MAX_GPU_TILES {
+            let mut by_age: Vec<(u64, TileKey)> = self
+                .resident
+                .iter()
+                .filter(|(_, t)| t.last_used < self.frame)
+                .map(|(k, t)| (t.last_used, *k))
+                .collect();
+            by_age.sort_unstable_by_key(|(used, _)| *used);
+            let excess = self.resident.len() - MAX_GPU_TILES;
+            for (_, key) in by_age.into_iter().take(excess) {
+                self.resident.remove(&key);
+            }
+        }
+    }
+
+    /// Decoded pixels held by the source, and GPU memory of the resident tiles.
+    pub fn memory_bytes(&self) -> (u64, u64) {
+        (self.source.memory_bytes(), self.resident.values().map(|t| t.texture.bytes()).sum())
+    }
+
+    /// Tiles being read or decoded.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Drop every resident tile not on screen; they are decoded again when needed.
+    pub fn trim(&mut self) {
+        let frame = self.frame;
+        self.resident.retain(|_, t| t.last_used >= frame);
+    }
+
+    /// Resident tiles for the current view, as texture-coordinate rectangles.
+    pub fn visible(&self) -> Vec<([f32; 4], &wgpu::BindGroup)> {
+        self.wanted
+            .iter()
+            .filter_map(|key| {
+                let tile = self.resident.get(key)?;
+                let uv = tile_uv(self.source.level_size(key.level), self.source.tile_size(key.level), key.col, key.row);
+                Some((uv, &tile.bind_group))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_levels() {
+        let full = (20000, 10000);
+        assert_eq!(level_size(full, 0), full);
+        assert_eq!(level_size(full, 1), (10000, 5000));
+        assert_eq!(level_size((5, 3), 1), (3, 2));
+        // 20000 -> 10000 -> 5000 -> 2500 -> 1250 -> 625 -> 313
+        assert_eq!(level_count(full, 512), 7);
+        assert_eq!(level_count((512, 100), 512), 1);
+        assert_eq!(grid(full, (512, 512)), (40, 20));
+        assert_eq!(grid((1000, 600), (1000, 16)), (1, 38));
+    }
+
+    #[test]
+    fn test_choose_level() {
+        let image = DynamicImage::new_rgba8(2100, 1000);
+        let pyramid = GeneratedPyramid::new(&image);
+        // 2100 -> 1050 -> 525 -> 263
+        assert_eq!(pyramid.level_count(), 4);
+        assert_eq!(choose_level(&pyramid, 0.5), 0);
+        assert_eq!(choose_level(&pyramid, 1.5), 0);
+        assert_eq!(choose_level(&pyramid, 4.0), 2);
+        assert_eq!(choose_level(&pyramid, 1000.0), 3);
+        assert_eq!(choose_level(&pyramid, f32::NAN), 0);
+    }
+
+    #[test]
+    fn test_visible_tiles() {
+        assert_eq!(visible_tiles((2048, 1024), (512, 512), [0.0, 0.0, 1.0, 1.0]).len(), 8);
+        // The left quarter at full resolution is one column of tiles
+        assert_eq!(visible_tiles((2048, 1024), (512, 512), [0.0, 0.0, 0.25, 1.0]), vec![(0, 0), (0, 1)]);
+        // A view extending past the image is clamped to it
+        assert_eq!(visible_tiles((1024, 512), (512, 512), [-1.0, -1.0, 2.0, 2.0]), vec![(0, 0), (1, 0)]);
+        assert!(visible_tiles((2048, 1024), (512, 512), [1.2, 0.0, 1.5, 1.0]).is_empty());
+        // Non-square tiles, e.g. TIFF strips
+        assert_eq!(visible_tiles((1000, 100), (1000, 16), [0.5, 0.5, 0.6, 0.6]), vec![(0, 3)]);
+
+        let uv = tile_uv((1000, 600), (512, 512), 1, 1);
+        assert_eq!(uv, [0.512, 512.0 / 600.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_generated_pyramid_tiles() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(1300, 700, |x, y| image::Rgba([(x % 256) as u8, (y % 256) as u8, 0, 255])));
+        let pyramid = GeneratedPyramid::new(&image);
+        assert_eq!(pyramid.level_count(), 3);
+
+        let tile = pyramid.tile(0, 2, 1).unwrap();
+        assert_eq!(tile.dimensions(), (1300 - 1024, 700 - 512));
+        assert_eq!(tile.get_pixel(0, 0), image.get_pixel(1024, 512));
+        assert!(pyramid.tile(0, 3, 0).is_err());
+        assert_eq!(pyramid.overview(400).unwrap().dimensions(), (325, 175));
+    }
+
+    #[test]
+    fn test_dzi_source() {
+        let dir = scratch_dir("dzi");
+        // 300x200 with 128px tiles and 1px overlap: DZI level 9 is full resolution
+        let full = RgbaImage::from_fn(300, 200, |x, y| image::Rgba([(x / 2) as u8, (y / 2) as u8, 0, 255]));
+        for level in [9u32, 8, 7] {
+            let (w, h) = level_size((300, 200), 9 - level);
+            let scaled = imageops::resize(&full, w, h, imageops::FilterType::Nearest);
+            std::fs::create_dir_all(dir.join("scan_files").join(level.to_string())).unwrap();
+            for row in 0..h.div_ceil(128) {
+                for col in 0..w.div_ceil(128) {
+                    let x = (col * 128).saturating_sub(1);
+                    let y = (row * 128).saturating_sub(1);
+                    let x1 = ((col + 1) * 128 + 1).min(w);
+                    let y1 = ((row + 1) * 128 + 1).min(h);
+                    let tile = imageops::crop_imm(&scaled, x, y, x1 - x, y1 - y).to_image();
+                    tile.save(dir.join(format!("scan_files/{}/{}_{}.png", level, col, row))).unwrap();
+                }
+            }
+        }
+        std::fs::write(
+            dir.join("scan.dzi"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" Format="png" Overlap="1" TileSize="128">
+  <Size Width="300" Height="200"/>
+</Image>"#,
+        )
+        .unwrap();
+
+        let source = DziSource::open(&dir.join("scan.dzi")).unwrap();
+        assert_eq!(source.size(), (300, 200));
+        assert_eq!(source.level_count(), 3);
+
+        // Overlap is cropped off so the tile starts exactly at its grid position
+        let tile = source.tile(0, 1, 1).unwrap();
+        assert_eq!(tile.dimensions(), (128, 72));
+        assert_eq!(tile.get_pixel(0, 0), image::Rgba([64, 64, 0, 255]));
+
+        // Level 1 is stitched from two columns of tiles
+        let overview = source.overview(200).unwrap();
+        assert_eq!(overview.dimensions(), (150, 100));
+        assert!(overview.get_pixel(140, 10)[0].abs_diff(140) <= 1);
+    }
+
+    #[test]
+    fn test_attribute() {
+        let xml = r#"<Image TileSize="254" Overlap="1" Format="jpg"><Size Width="10" Height="20"/></Image>"#;
+        assert_eq!(attribute(xml, "TileSize"), Some("254"));
+        assert_eq!(attribute(xml, "Height"), Some("20"));
+        assert_eq!(attribute(xml, "Size"), None);
+    }
+}
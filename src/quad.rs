@@ -0,0 +1,266 @@
+use crate::texture;
+
+// Upper bound on quads drawn per frame; extra quads are dropped.
+const MAX_QUADS: usize = 4096;
+const FULL: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct QuadInstance {
+    rect: [f32; 4],
+    uv: [f32; 4],
+    color: [f32; 4],
+    /// For two-texture quads: the wipe split, or the difference gain and whether to colorize
+    params: [f32; 2],
+}
+
+impl QuadInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 4] =
+            wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4, 3 => Float32x2];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<QuadInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Rectangle in window pixels, origin at the top-left corner.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn contains(&self, px: f32, py: f32) -> bool {
+        px >= self.x && px < self.x + self.w && py >= self.y && py < self.y + self.h
+    }
+
+    /// Shrink (or grow, if negative) the rectangle by `d` pixels on every side.
+    pub fn inset(&self, d: f32) -> Self {
+        Self::new(self.x + d, self.y + d, self.w - 2.0 * d, self.h - 2.0 * d)
+    }
+
+    /// Multiply every coordinate by `s`, e.g. from logical to physical pixels.
+    pub fn scaled(&self, s: f32) -> Self {
+        Self::new(self.x * s, self.y * s, self.w * s, self.h * s)
+    }
+}
+
+struct QuadItem<'a> {
+    rect: Rect,
+    /// Texture coordinates: u0, v0, u1, v1
+    uv: [f32; 4],
+    color: [f32; 4],
+    texture: Option<&'a wgpu::BindGroup>,
+    /// The other texture of a two-texture quad, and how the two combine
+    second: Option<(&'a wgpu::BindGroup, Blend)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Blend {
+    /// The first texture left of this window x, the second right of it
+    Wipe(f32),
+    /// The absolute difference times a gain, as a heatmap if set
+    Difference(f32, bool),
+}
+
+impl Blend {
+    fn params(&self) -> [f32; 2] {
+        match *self {
+            Blend::Wipe(split) => [split, 0.0],
+            Blend::Difference(gain, heatmap) => [gain, if heatmap { 1.0 } else { 0.0 }],
+        }
+    }
+}
+
+/// A list of quads to draw this frame, in painter's order.
+#[derive(Default)]
+pub struct QuadBatch<'a> {
+    items: Vec<QuadItem<'a>>,
+}
+
+impl<'a> QuadBatch<'a> {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn solid(&mut self, rect: Rect, color: [f32; 4]) {
+        self.items.push(QuadItem { rect, uv: FULL, color, texture: None, second: None });
+    }
+
+    /// Rectangle border of the given thickness, drawn inside `rect`.
+    pub fn outline(&mut self, rect: Rect, thickness: f32, color: [f32; 4]) {
+        let t = thickness;
+        self.solid(Rect::new(rect.x, rect.y, rect.w, t), color);
+        self.solid(Rect::new(rect.x, rect.y + rect.h - t, rect.w, t), color);
+        self.solid(Rect::new(rect.x, rect.y + t, t, rect.h - 2.0 * t), color);
+        self.solid(Rect::new(rect.x + rect.w - t, rect.y + t, t, rect.h - 2.0 * t), color);
+    }
+
+    pub fn textured(&mut self, rect: Rect, texture: &'a wgpu::BindGroup) {
+        self.textured_region(rect, FULL, texture);
+    }
+
+    /// Part of a texture, `uv` being u0, v0, u1, v1.
+    pub fn textured_region(&mut self, rect: Rect, uv: [f32; 4], texture: &'a wgpu::BindGroup) {
+        self.items.push(QuadItem { rect, uv, color: [1.0; 4], texture: Some(texture), second: None });
+    }
+
+    /// Two textures over the same region of `rect`: `left` up to window x `split`, `right` after it.
+    pub fn wipe(&mut self, rect: Rect, uv: [f32; 4], left: &'a wgpu::BindGroup, right: &'a wgpu::BindGroup, split: f32) {
+        self.items.push(QuadItem { rect, uv, color: [1.0; 4], texture: Some(left), second: Some((right, Blend::Wipe(split))) });
+    }
+
+    /// The per-pixel absolute difference of two textures over the same region,
+    /// multiplied by `gain` and colorized from black through red to white if `heatmap`.
+    pub fn difference(&mut self, rect: Rect, uv: [f32; 4], a: &'a wgpu::BindGroup, b: &'a wgpu::BindGroup, gain: f32, heatmap: bool) {
+        let blend = Blend::Difference(gain, heatmap);
+        self.items.push(QuadItem { rect, uv, color: [1.0; 4], texture: Some(a), second: Some((b, blend)) });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// Draws screen-space rectangles, either solid or textured, on top of the image.
+pub struct QuadRenderer {
+    pipeline: wgpu::RenderPipeline,
+    /// Picks one of two textures per fragment, for wiping between images
+    wipe_pipeline: wgpu::RenderPipeline,
+    /// Shows how two textures differ
+    difference_pipeline: wgpu::RenderPipeline,
+    instance_buffer: wgpu::Buffer,
+    white_bind_group: wgpu::BindGroup,
+}
+
+impl QuadRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("quad.wgsl"));
+
+        let create_pipeline = |label: &str, bind_group_layouts: &[&wgpu::BindGroupLayout], fragment: &str| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[QuadInstance::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fragment,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let pipeline = create_pipeline("Quad Pipeline", &[texture_bind_group_layout], "fs_main");
+        let pair = [texture_bind_group_layout, texture_bind_group_layout];
+        let wipe_pipeline = create_pipeline("Wipe Pipeline", &pair, "fs_wipe");
+        let difference_pipeline = create_pipeline("Difference Pipeline", &pair, "fs_difference");
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Quad Instance Buffer"),
+            size: (MAX_QUADS * std::mem::size_of::<QuadInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255])));
+        let white_texture = texture::Texture::from_image(device, queue, &white, Some("white_texture")).unwrap();
+        let white_bind_group = white_texture.create_bind_group(device, texture_bind_group_layout);
+
+        Self {
+            pipeline,
+            wipe_pipeline,
+            difference_pipeline,
+            instance_buffer,
+            white_bind_group,
+        }
+    }
+
+    /// Upload the batch's instance data. Must be called before `draw` with the same batch.
+    pub fn prepare(&self, queue: &wgpu::Queue, screen: winit::dpi::PhysicalSize<u32>, batch: &QuadBatch) {
+        let sw = screen.width.max(1) as f32;
+        let sh = screen.height.max(1) as f32;
+        let instances: Vec<QuadInstance> = batch
+            .items
+            .iter()
+            .take(MAX_QUADS)
+            .map(|item| {
+                let r = item.rect;
+                QuadInstance {
+                    rect: [
+                        r.x / sw * 2.0 - 1.0,
+                        1.0 - r.y / sh * 2.0,
+                        (r.x + r.w) / sw * 2.0 - 1.0,
+                        1.0 - (r.y + r.h) / sh * 2.0,
+                    ],
+                    uv: item.uv,
+                    color: item.color,
+                    params: item.second.map_or([0.0; 2], |(_, blend)| blend.params()),
+                }
+            })
+            .collect();
+        if !instances.is_empty() {
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, batch: &QuadBatch<'a>) {
+        if batch.is_empty() {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        let mut current = None;
+        for (i, item) in batch.items.iter().take(MAX_QUADS).enumerate() {
+            let pipeline = match item.second {
+                None => &self.pipeline,
+                Some((_, Blend::Wipe(_))) => &self.wipe_pipeline,
+                Some((_, Blend::Difference(..))) => &self.difference_pipeline,
+            };
+            if current.is_none_or(|p| !std::ptr::eq(p, pipeline)) {
+                current = Some(pipeline);
+                render_pass.set_pipeline(pipeline);
+            }
+            let bind_group = item.texture.unwrap_or(&self.white_bind_group);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            if let Some((second, _)) = item.second {
+                render_pass.set_bind_group(1, second, &[]);
+            }
+            let i = i as u32;
+            render_pass.draw(0..6, i..i + 1);
+        }
+    }
+}
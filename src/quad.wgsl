@@ -0,0 +1,86 @@
+// Screen-space textured/tinted quads used for overlays (filmstrip, highlights, ...)
+
+struct InstanceInput {
+    // Rectangle in normalized device coordinates: x0, y0, x1, y1
+    @location(0) rect: vec4<f32>,
+    // Texture coordinates: u0, v0, u1, v1
+    @location(1) uv: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    // Two-texture quads: the window x in pixels where a wipe changes texture,
+    // or the difference gain and 1 for a heatmap
+    @location(3) params: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) params: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Two triangles: (0,0) (0,1) (1,1) / (0,0) (1,1) (1,0)
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+    );
+    let c = corners[vertex_index];
+
+    var out: VertexOutput;
+    let x = mix(instance.rect.x, instance.rect.z, c.x);
+    let y = mix(instance.rect.y, instance.rect.w, c.y);
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.tex_coords = vec2<f32>(mix(instance.uv.x, instance.uv.z, c.x), mix(instance.uv.y, instance.uv.w, c.y));
+    out.color = instance.color;
+    out.params = instance.params;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_quad: texture_2d<f32>;
+@group(0) @binding(1)
+var s_quad: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_quad, s_quad, in.tex_coords) * in.color;
+}
+
+@group(1) @binding(0)
+var t_second: texture_2d<f32>;
+@group(1) @binding(1)
+var s_second: sampler;
+
+// The first texture left of the split, the second right of it
+@fragment
+fn fs_wipe(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Sample both outside the branch: sampling needs uniform control flow
+    let first = textureSample(t_quad, s_quad, in.tex_coords);
+    let second = textureSample(t_second, s_second, in.tex_coords);
+    return select(second, first, in.clip_position.x < in.params.x) * in.color;
+}
+
+// Black through red and yellow to white as `t` goes from 0 to 1
+fn heat(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0) * 3.0;
+    return clamp(vec3<f32>(x, x - 1.0, x - 2.0), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// The absolute difference of the two textures, amplified, optionally as a heatmap
+@fragment
+fn fs_difference(in: VertexOutput) -> @location(0) vec4<f32> {
+    let first = textureSample(t_quad, s_quad, in.tex_coords);
+    let second = textureSample(t_second, s_second, in.tex_coords);
+    let difference = min(abs(first.rgb - second.rgb) * in.params.x, vec3<f32>(1.0));
+    let strongest = max(difference.r, max(difference.g, difference.b));
+    let color = select(difference, heat(strongest), in.params.y > 0.5);
+    return vec4<f32>(color, 1.0) * in.color;
+}
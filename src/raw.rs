@@ -0,0 +1,408 @@
+//! The RAW development pipeline: demosaicing, levels, color and orientation.
+//! Kept free of other crate modules so the benchmarks can build it on its own.
+
+use image::DynamicImage;
+use rayon::prelude::*;
+
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+pub fn demosaic_bilinear(
+    input: &[u16], 
+    width: usize, 
+    height: usize, 
+    pattern: &str, 
+    whitelevels: &[u16], 
+    blacklevels: &[u16], 
+    wb_coeffs: &[f32]
+) -> Option<Vec<u8>> {
+    demosaic_bilinear_with_progress(input, width, height, pattern, whitelevels, blacklevels, wb_coeffs, Layout::Rgb, &mut |_| {})
+}
+
+/// How the two colors each photosite didn't record are filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Demosaic {
+    /// Averaged from the neighbours that recorded them
+    Bilinear,
+    /// Taken from the nearest neighbour that recorded them: faster, with color fringes
+    /// along fine detail
+    Nearest,
+}
+
+/// How developed pixels are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Rgb,
+    /// With opaque alpha, the way textures take them, so nothing is converted for display
+    Rgba,
+}
+
+impl Layout {
+    pub fn channels(self) -> usize {
+        match self {
+            Layout::Rgb => 3,
+            Layout::Rgba => 4,
+        }
+    }
+}
+
+/// Rows demosaiced between progress reports; the rows of a band run in parallel.
+const BAND_ROWS: usize = 256;
+
+/// What a position of the 2x2 color filter tile samples.
+#[derive(Clone, Copy)]
+enum Site {
+    Red,
+    Blue,
+    /// Green with red neighbours left and right
+    GreenRedRow,
+    /// Green with blue neighbours left and right
+    GreenBlueRow,
+    /// Patterns other than RGGB and BGGR come out as luminance
+    Gray,
+}
+
+/// Bilinear red, green and blue at `x` of the row `here`, between the rows `above` and `below`.
+#[inline(always)]
+fn interpolate(site: Site, above: &[u16], here: &[u16], below: &[u16], x: usize) -> (f32, f32, f32) {
+    let at = |row: &[u16], x: usize| row[x] as f32;
+    let cross = || (at(here, x - 1) + at(here, x + 1) + at(above, x) + at(below, x)) / 4.0;
+    let diagonal = || (at(above, x - 1) + at(above, x + 1) + at(below, x - 1) + at(below, x + 1)) / 4.0;
+    let horizontal = || (at(here, x - 1) + at(here, x + 1)) / 2.0;
+    let vertical = || (at(above, x) + at(below, x)) / 2.0;
+    match site {
+        Site::Red => (at(here, x), cross(), diagonal()),
+        Site::Blue => (diagonal(), cross(), at(here, x)),
+        Site::GreenRedRow => (horizontal(), at(here, x), vertical()),
+        Site::GreenBlueRow => (vertical(), at(here, x), horizontal()),
+        Site::Gray => (at(here, x), at(here, x), at(here, x)),
+    }
+}
+
+/// The nearest recorded red, green and blue at `x`: the pixel's own sample, and the
+/// others from the right and below.
+#[inline(always)]
+fn nearest(site: Site, here: &[u16], below: &[u16], x: usize) -> (f32, f32, f32) {
+    let at = |row: &[u16], x: usize| row[x] as f32;
+    match site {
+        Site::Red => (at(here, x), at(here, x + 1), at(below, x + 1)),
+        Site::Blue => (at(below, x + 1), at(here, x + 1), at(here, x)),
+        Site::GreenRedRow => (at(here, x + 1), at(here, x), at(below, x)),
+        Site::GreenBlueRow => (at(below, x), at(here, x), at(here, x + 1)),
+        Site::Gray => (at(here, x), at(here, x), at(here, x)),
+    }
+}
+
+/// `demosaic_bilinear` into `layout`, calling `progress` with the fraction of rows done
+/// every few rows. Rows are spread over the rayon pool.
+#[allow(clippy::too_many_arguments)]
+pub fn demosaic_bilinear_with_progress(
+    input: &[u16],
+    width: usize,
+    height: usize,
+    pattern: &str,
+    whitelevels: &[u16],
+    blacklevels: &[u16],
+    wb_coeffs: &[f32],
+    layout: Layout,
+    progress: &mut dyn FnMut(f32),
+) -> Option<Vec<u8>> {
+    demosaic_with_progress(input, width, height, pattern, whitelevels, blacklevels, wb_coeffs, Demosaic::Bilinear, layout, progress)
+}
+
+/// `demosaic_bilinear_with_progress` with the colors filled in by `demosaic`.
+#[allow(clippy::too_many_arguments)]
+pub fn demosaic_with_progress(
+    input: &[u16],
+    width: usize,
+    height: usize,
+    pattern: &str,
+    whitelevels: &[u16],
+    blacklevels: &[u16],
+    wb_coeffs: &[f32],
+    demosaic: Demosaic,
+    layout: Layout,
+    progress: &mut dyn FnMut(f32),
+) -> Option<Vec<u8>> {
+    // The dimensions come from the file header; don't trust them to match the data
+    let pixels = width.checked_mul(height)?;
+    if input.len() < pixels || whitelevels.len() < 3 || blacklevels.len() < 3 || wb_coeffs.len() < 3 {
+        return None;
+    }
+    let channels = layout.channels();
+    let mut output = vec![0u8; pixels.checked_mul(channels)?];
+    if layout == Layout::Rgba {
+        output.par_chunks_mut(4).for_each(|pixel| pixel[3] = 255);
+    }
+    // The one-pixel border stays black, which leaves nothing to do below 3x3
+    if width < 3 || height < 3 {
+        return Some(output);
+    }
+
+    let gain = [wb_coeffs[0], wb_coeffs[1], wb_coeffs[2]];
+    let black = [blacklevels[0] as f32, blacklevels[1] as f32, blacklevels[2] as f32];
+    let white = [whitelevels[0] as f32, whitelevels[1] as f32, whitelevels[2] as f32];
+    let range = [white[0] - black[0], white[1] - black[1], white[2] - black[2]];
+
+    // Sites of the tile in row-major order, so row parity and column parity pick one
+    let sites = match pattern {
+        "RGGB" => [Site::Red, Site::GreenRedRow, Site::GreenBlueRow, Site::Blue],
+        "BGGR" => [Site::Blue, Site::GreenBlueRow, Site::GreenRedRow, Site::Red],
+        _ => [Site::Gray; 4],
+    };
+
+    let develop_row = |y: usize, out: &mut [u8]| {
+        let above = &input[(y - 1) * width..y * width];
+        let here = &input[y * width..(y + 1) * width];
+        let below = &input[(y + 1) * width..(y + 2) * width];
+        let (even, odd) = (sites[(y % 2) * 2], sites[(y % 2) * 2 + 1]);
+        for x in 1..width - 1 {
+            let site = if x % 2 == 0 { even } else { odd };
+            let (r, g, b) = match demosaic {
+                Demosaic::Bilinear => interpolate(site, above, here, below, x),
+                Demosaic::Nearest => nearest(site, here, below, x),
+            };
+
+            let r_norm = ((r - black[0]).max(0.0) / range[0]) * gain[0];
+            let g_norm = ((g - black[1]).max(0.0) / range[1]) * gain[1];
+            let b_norm = ((b - black[2]).max(0.0) / range[2]) * gain[2];
+
+            // Apply a simple color matrix for better color rendering
+            // This is a simplified sRGB-like matrix to improve color accuracy
+            let r_corrected = (1.6 * r_norm - 0.3 * g_norm - 0.3 * b_norm).clamp(0.0, 1.0);
+            let g_corrected = (-0.2 * r_norm + 1.4 * g_norm - 0.2 * b_norm).clamp(0.0, 1.0);
+            let b_corrected = (-0.1 * r_norm - 0.3 * g_norm + 1.4 * b_norm).clamp(0.0, 1.0);
+
+            // Apply gamma correction
+            let pixel = &mut out[x * channels..x * channels + 3];
+            pixel[0] = (r_corrected.powf(1.0 / 2.2) * 255.0).min(255.0) as u8;
+            pixel[1] = (g_corrected.powf(1.0 / 2.2) * 255.0).min(255.0) as u8;
+            pixel[2] = (b_corrected.powf(1.0 / 2.2) * 255.0).min(255.0) as u8;
+        }
+    };
+
+    let row_bytes = width * channels;
+    for (band, rows) in output.chunks_mut(row_bytes * BAND_ROWS).enumerate() {
+        let first = band * BAND_ROWS;
+        if first > 0 {
+            progress(first as f32 / height as f32);
+        }
+        rows.par_chunks_mut(row_bytes).enumerate().for_each(|(i, out)| {
+            let y = first + i;
+            if y > 0 && y < height - 1 {
+                develop_row(y, out);
+            }
+        });
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_apply_orientation() {
+        let img = DynamicImage::new_rgb8(10, 20);
+        
+        // Case 1: Normal (no change)
+        let res = apply_orientation(img.clone(), 1);
+        assert_eq!(res.dimensions(), (10, 20));
+
+        // Case 6: Rotate 90 CW
+        let res = apply_orientation(img.clone(), 6);
+        assert_eq!(res.dimensions(), (20, 10));
+        
+        // Case 8: Rotate 270 CW (90 CCW)
+        let res = apply_orientation(img.clone(), 8);
+        assert_eq!(res.dimensions(), (20, 10));
+        
+        // Case 3: Rotate 180
+        let res = apply_orientation(img.clone(), 3);
+        assert_eq!(res.dimensions(), (10, 20));
+    }
+
+    #[test]
+    fn test_color_rendering() {
+        // Simulate a 2x2 RGGB pattern with pure Blue
+        // R G
+        // G B
+        // Let's make it 4x4 to avoid boundary issues with the demosaic loop (it skips 1 pixel border)
+        let width = 4;
+        let height = 4;
+        let mut data = vec![0u16; width * height];
+        
+        // Fill with "Blue" signal
+        // In RGGB:
+        // Row 0: R G R G
+        // Row 1: G B G B
+        // Row 2: R G R G
+        // Row 3: G B G B
+        
+        // We want pure blue, so only B pixels have value.
+        // B pixels are at odd row, odd col.
+        for y in 0..height {
+            for x in 0..width {
+                if y % 2 == 1 && x % 2 == 1 {
+                    data[y * width + x] = 1000; // Blue signal
+                } else {
+                    data[y * width + x] = 0; // No signal
+                }
+            }
+        }
+        
+        let whitelevels = vec![1000, 1000, 1000, 1000];
+        let blacklevels = vec![0, 0, 0, 0];
+        let wb_coeffs = vec![1.0, 1.0, 1.0, 1.0]; // Neutral WB
+        
+        let rgb = demosaic_bilinear(
+            &data,
+            width,
+            height,
+            "RGGB",
+            &whitelevels,
+            &blacklevels,
+            &wb_coeffs
+        ).unwrap();
+        
+        // Check center pixel (1, 1) - should be Blue
+        // Index: (1 * 4 + 1) * 3 = 15
+        let idx = (4 + 1) * 3;
+        let r = rgb[idx];
+        let g = rgb[idx+1];
+        let b = rgb[idx+2];
+        
+        println!("RGB at (1,1): {}, {}, {}", r, g, b);
+        
+        // With current logic:
+        // B at (1,1) is 1000. Normalized: 1.0. Gamma: 1.0. Output: 255.
+        // G at (1,1) is avg of neighbors (0,1), (1,0), (1,2), (2,1). All 0. Output: 0.
+        // R at (1,1) is avg of (0,0), (0,2), (2,0), (2,2). All 0. Output: 0.
+        // So it should be pure blue (0, 0, 255).
+        
+        // However, real cameras have color crosstalk and need a matrix.
+        // If we had a matrix, this pure blue camera signal might map to something else in sRGB.
+        // But for this test, we just verify the pipeline works as expected.
+        
+        assert_eq!(b, 255);
+        assert_eq!(r, 0);
+        assert_eq!(g, 0);
+    }
+
+    #[test]
+    fn test_nearest_demosaic() {
+        // Pure blue, as above: the nearest neighbours agree with the averages
+        let (width, height) = (4, 4);
+        let data: Vec<u16> = (0..width * height).map(|i| if (i / width) % 2 == 1 && i % 2 == 1 { 1000 } else { 0 }).collect();
+        let levels = ([1000u16; 4], [0u16; 4]);
+        let develop = |demosaic| {
+            demosaic_with_progress(&data, width, height, "RGGB", &levels.0, &levels.1, &[1.0; 4], demosaic, Layout::Rgb, &mut |_| {})
+                .unwrap()
+        };
+        let nearest = develop(Demosaic::Nearest);
+        assert_eq!(&nearest[(4 + 1) * 3..(4 + 1) * 3 + 3], &[0, 0, 255]);
+        // A green site between two reds takes its blue from the row below
+        assert_eq!(nearest[(2 * 4 + 1) * 3 + 2], 255);
+        assert_eq!(develop(Demosaic::Bilinear)[(2 * 4 + 1) * 3 + 2], 255);
+        assert_eq!(nearest.len(), develop(Demosaic::Bilinear).len());
+    }
+
+    #[test]
+    fn test_demosaic_rejects_bad_input() {
+        let levels = [1000u16; 4];
+        let wb = [1.0f32; 4];
+        // Fewer samples than the header claims
+        assert!(demosaic_bilinear(&[0; 15], 4, 4, "RGGB", &levels, &[0; 4], &wb).is_none());
+        // Dimensions whose product overflows
+        assert!(demosaic_bilinear(&[0; 16], usize::MAX, 2, "RGGB", &levels, &[0; 4], &wb).is_none());
+        // Missing per-channel levels
+        assert!(demosaic_bilinear(&[0; 16], 4, 4, "RGGB", &levels[..2], &[0; 4], &wb).is_none());
+        // Degenerate sizes produce an (all black) image instead of underflowing
+        assert_eq!(demosaic_bilinear(&[], 0, 0, "RGGB", &levels, &[0; 4], &wb), Some(vec![]));
+        assert_eq!(demosaic_bilinear(&[7], 1, 1, "RGGB", &levels, &[0; 4], &wb), Some(vec![0; 3]));
+    }
+
+    #[test]
+    fn test_demosaic_bands() {
+        // Taller than a band, so rows are developed across several parallel passes
+        let (width, height) = (6, BAND_ROWS * 2 + 10);
+        let data = vec![600u16; width * height];
+        let mut reported = Vec::new();
+        let levels = ([1000; 4], [100; 4]);
+        let rgb = demosaic_bilinear_with_progress(&data, width, height, "RGGB", &levels.0, &levels.1, &[1.0; 4], Layout::Rgb, &mut |f| {
+            reported.push(f)
+        })
+        .unwrap();
+        assert_eq!(reported.len(), 2);
+        assert!(reported[0] < reported[1] && reported[1] < 1.0);
+
+        let pixel = |x: usize, y: usize| &rgb[(y * width + x) * 3..(y * width + x) * 3 + 3];
+        let inner = pixel(1, 1).to_vec();
+        assert!(inner.iter().all(|&v| v > 0));
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                assert_eq!(pixel(x, y), &inner[..], "pixel {},{}", x, y);
+            }
+        }
+        // The border is left black
+        assert_eq!(pixel(0, BAND_ROWS), &[0, 0, 0]);
+        assert_eq!(pixel(3, height - 1), &[0, 0, 0]);
+
+        // The same pixels, opaque, for upload as they are
+        let rgba = demosaic_bilinear_with_progress(&data, width, height, "RGGB", &levels.0, &levels.1, &[1.0; 4], Layout::Rgba, &mut |_| {})
+            .unwrap();
+        assert_eq!(rgba.len(), width * height * 4);
+        for (rgba, rgb) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3)) {
+            assert_eq!((&rgba[..3], rgba[3]), (rgb, 255));
+        }
+    }
+
+    /// Compare every fixture against its blessed output. After an intended
+    /// change to the pipeline, run with `MOMEMTUM_BLESS=1` to rewrite them.
+    #[test]
+    fn test_fixture_regressions() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/raw");
+        let bless = std::env::var_os("MOMEMTUM_BLESS").is_some();
+        let mut failures = Vec::new();
+
+        for fixture in crate::raw_fixtures::corpus() {
+            let output = fixture.develop();
+            let golden_path = dir.join(format!("{}.png", fixture.name));
+            if bless {
+                std::fs::create_dir_all(&dir).unwrap();
+                output.save(&golden_path).unwrap();
+                continue;
+            }
+            let golden = match image::open(&golden_path) {
+                Ok(img) => img.to_rgb8(),
+                Err(e) => {
+                    failures.push(format!("{}: {}", fixture.name, e));
+                    continue;
+                }
+            };
+            if golden.dimensions() != output.dimensions() {
+                failures.push(format!("{}: size {:?}, expected {:?}", fixture.name, output.dimensions(), golden.dimensions()));
+                continue;
+            }
+            let deltas: Vec<u8> = output.as_raw().iter().zip(golden.as_raw()).map(|(a, b)| a.abs_diff(*b)).collect();
+            let max = deltas.iter().copied().max().unwrap_or(0);
+            if max > 1 {
+                let mean = deltas.iter().map(|&d| d as f64).sum::<f64>() / deltas.len() as f64;
+                failures.push(format!("{}: max delta {}, mean delta {:.3}", fixture.name, max, mean));
+            }
+        }
+
+        assert!(failures.is_empty(), "RAW pipeline output changed:\n{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView, Rgba};
+use std::path::{Path, PathBuf};
+
+use crate::export::{ColorSpace, Metadata};
+
+pub const MAX_BOXES: usize = 16;
+
+/// Redaction mode: boxes (in image texture coordinates, 0..1) that get pixelated.
+pub struct Redactor {
+    pub active: bool,
+    pub boxes: Vec<[f32; 4]>,
+    drag_start: Option<(f32, f32)>,
+    drag_current: (f32, f32),
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            boxes: Vec::new(),
+            drag_start: None,
+            drag_current: (0.0, 0.0),
+        }
+    }
+
+    pub fn begin_drag(&mut self, uv: (f32, f32)) {
+        if self.boxes.len() < MAX_BOXES {
+            self.drag_start = Some(uv);
+            self.drag_current = uv;
+        }
+    }
+
+    pub fn update_drag(&mut self, uv: (f32, f32)) {
+        self.drag_current = uv;
+    }
+
+    pub fn end_drag(&mut self) {
+        if let Some(rect) = self.pending_box() {
+            if rect[2] - rect[0] > 0.002 && rect[3] - rect[1] > 0.002 {
+                self.boxes.push(rect);
+            }
+        }
+        self.drag_start = None;
+    }
+
+    pub fn dragging(&self) -> bool {
+        self.drag_start.is_some()
+    }
+
+    /// The box currently being dragged, normalized and clipped to the image.
+    pub fn pending_box(&self) -> Option<[f32; 4]> {
+        let (x0, y0) = self.drag_start?;
+        let (x1, y1) = self.drag_current;
+        Some([
+            x0.min(x1).clamp(0.0, 1.0),
+            y0.min(y1).clamp(0.0, 1.0),
+            x0.max(x1).clamp(0.0, 1.0),
+            y0.max(y1).clamp(0.0, 1.0),
+        ])
+    }
+
+    /// Committed boxes plus the one being dragged.
+    pub fn all_boxes(&self) -> Vec<[f32; 4]> {
+        let mut boxes = self.boxes.clone();
+        boxes.extend(self.pending_box());
+        boxes
+    }
+
+    pub fn clear(&mut self) {
+        self.boxes.clear();
+        self.drag_start = None;
+    }
+}
+
+/// Pixelation block size in pixels for an image of the given size.
+pub fn block_size(width: u32, height: u32) -> u32 {
+    (width.min(height) / 50).max(8)
+}
+
+/// Pixelate `boxes` (texture coordinates) in `img` by averaging square blocks.
+pub fn pixelate(img: &DynamicImage, boxes: &[[f32; 4]]) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    let block = block_size(width, height);
+    let mut out = img.to_rgba8();
+
+    for b in boxes {
+        let x0 = (b[0] * width as f32) as u32;
+        let y0 = (b[1] * height as f32) as u32;
+        let x1 = ((b[2] * width as f32).ceil() as u32).min(width);
+        let y1 = ((b[3] * height as f32).ceil() as u32).min(height);
+
+        // Align blocks to the image grid so the export matches the GPU preview
+        for by in (y0 / block * block..y1).step_by(block as usize) {
+            for bx in (x0 / block * block..x1).step_by(block as usize) {
+                let (sx0, sy0) = (bx.max(x0), by.max(y0));
+                let (sx1, sy1) = ((bx + block).min(x1), (by + block).min(y1));
+                let mut sum = [0u64; 4];
+                for y in sy0..sy1 {
+                    for x in sx0..sx1 {
+                        let p = out.get_pixel(x, y);
+                        for c in 0..4 {
+                            sum[c] += p[c] as u64;
+                        }
+                    }
+                }
+                let n = ((sx1 - sx0) * (sy1 - sy0)) as u64;
+                let avg = Rgba(sum.map(|s| s.checked_div(n).unwrap_or(0) as u8));
+                for y in sy0..sy1 {
+                    for x in sx0..sx1 {
+                        out.put_pixel(x, y, avg);
+                    }
+                }
+            }
+        }
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+/// `photo.jpg` -> `photo_redacted.jpg`; RAW sources are exported as JPEG.
+pub fn export_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let ext = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("png") => "png",
+        _ => "jpg",
+    };
+    path.with_file_name(format!("{}_redacted.{}", stem, ext))
+}
+
+/// Reload `source` at full resolution, pixelate the boxes and save a copy.
+/// The copy carries no EXIF or XMP metadata, only the color profile.
+pub fn export(source: &Path, boxes: &[[f32; 4]], space: ColorSpace) -> Result<PathBuf> {
+    let loaded = crate::sandbox::load(source)?;
+    let redacted = pixelate(&loaded.image, boxes);
+    let target = export_path(source);
+    crate::export::save(&redacted, &target, space, &Metadata::read(source).colors_only())?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixelate_only_inside_boxes() {
+        let mut img = image::RgbaImage::new(100, 100);
+        for (x, y, p) in img.enumerate_pixels_mut() {
+            *p = Rgba([(x * 2) as u8, (y * 2) as u8, 0, 255]);
+        }
+        let img = DynamicImage::ImageRgba8(img);
+        let out = pixelate(&img, &[[0.0, 0.0, 0.5, 0.5]]).to_rgba8();
+
+        // Inside: one 8x8 block is uniform
+        assert_eq!(out.get_pixel(0, 0), out.get_pixel(7, 7));
+        assert_ne!(out.get_pixel(0, 0), out.get_pixel(8, 8));
+        // Outside: untouched
+        assert_eq!(out.get_pixel(80, 80), &Rgba([160, 160, 0, 255]));
+    }
+
+    #[test]
+    fn test_drag_and_export_path() {
+        let mut r = Redactor::new();
+        r.begin_drag((0.6, 0.7));
+        r.update_drag((0.2, 0.1));
+        assert_eq!(r.pending_box(), Some([0.2, 0.1, 0.6, 0.7]));
+        r.end_drag();
+        assert_eq!(r.boxes.len(), 1);
+        assert!(!r.dragging());
+
+        assert_eq!(export_path(Path::new("/a/b.NEF")), PathBuf::from("/a/b_redacted.jpg"));
+        assert_eq!(export_path(Path::new("/a/b.png")), PathBuf::from("/a/b_redacted.png"));
+    }
+}
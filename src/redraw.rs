@@ -0,0 +1,97 @@
+//! When the window gets drawn. Rather than redrawing every frame, the event loop sleeps
+//! until input arrives, a deadline passes (the next animation frame, a toast expiring)
+//! or a background job delivering results calls `wake`. Continuous redraws, while
+//! something moves, are capped at `MAX_FPS`.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+pub const MAX_FPS: u32 = 60;
+/// How often progress shown in the title bar is looked at while a job runs
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static WAKER: OnceLock<Box<dyn Fn() + Send + Sync>> = OnceLock::new();
+
+/// Set what `wake` does, once, e.g. post an event to the event loop.
+pub fn set_waker(waker: impl Fn() + Send + Sync + 'static) {
+    let _ = WAKER.set(Box::new(waker));
+}
+
+/// Have the window drawn soon, from any thread: results are waiting to be picked up.
+pub fn wake() {
+    if let Some(waker) = WAKER.get() {
+        waker();
+    }
+}
+
+/// The earlier of two optional deadlines.
+pub fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Whether the window needs drawing, and when the last frame was.
+#[derive(Debug)]
+pub struct Schedule {
+    dirty: bool,
+    last_frame: Option<Instant>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self { dirty: true, last_frame: None }
+    }
+}
+
+impl Schedule {
+    /// Something changed that should show, like input or an event.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn drawn(&mut self, now: Instant) {
+        self.dirty = false;
+        self.last_frame = Some(now);
+    }
+
+    /// When to draw next given what is due at `due`: now if changed, never if nothing
+    /// is, and no sooner than a frame interval after the last frame.
+    pub fn next(&self, due: Option<Instant>, now: Instant) -> Option<Instant> {
+        let due = if self.dirty { Some(now) } else { due }?;
+        let interval = Duration::from_secs(1) / MAX_FPS;
+        Some(match self.last_frame {
+            Some(last) => due.max(last + interval),
+            None => due,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule_caps_frame_rate() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(1) / MAX_FPS;
+        let mut schedule = Schedule::default();
+        assert_eq!(schedule.next(None, start), Some(start));
+
+        // Idle after drawing: nothing until a deadline or a change
+        schedule.drawn(start);
+        assert_eq!(schedule.next(None, start), None);
+        let later = start + Duration::from_secs(2);
+        assert_eq!(schedule.next(Some(later), start), Some(later));
+
+        // A change right after a frame waits for the frame interval
+        schedule.invalidate();
+        assert_eq!(schedule.next(None, start + Duration::from_millis(1)), Some(start + interval));
+        assert_eq!(schedule.next(None, start + interval * 2), Some(start + interval * 2));
+
+        assert_eq!(earliest(Some(later), Some(start)), Some(start));
+        assert_eq!(earliest(None, Some(later)), Some(later));
+        assert_eq!(earliest(None, None), None);
+    }
+}
@@ -0,0 +1,335 @@
+//! Images opened from the web: a URL given on the command line, pasted, or
+//! dropped as a browser link is downloaded to a cache folder, which keeps the
+//! latest downloads up to a configured size, and then loaded like any local file.
+
+use anyhow::{anyhow, Result};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const USER_AGENT: &str = concat!("Momemtum/", env!("CARGO_PKG_VERSION"));
+/// Larger responses are refused rather than filling the disk
+const MAX_BYTES: u64 = 1024 * 1024 * 1024;
+/// Least time between two progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+pub const DEFAULT_KEEP_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// The configured cache folder, if any, and how many bytes of downloads to keep
+static CACHE_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+static KEEP_BYTES: AtomicU64 = AtomicU64::new(DEFAULT_KEEP_BYTES);
+
+/// Keep downloads in the cache folder under `root` when one is configured, and the
+/// oldest deleted once they take more than `keep_mb`.
+pub fn configure(root: Option<PathBuf>, keep_mb: u64) {
+    *CACHE_ROOT.lock().unwrap_or_else(|e| e.into_inner()) = root;
+    KEEP_BYTES.store(keep_mb * 1024 * 1024, Ordering::Relaxed);
+}
+
+/// Whether `text` is an address to download rather than a local path.
+pub fn is_url(text: &str) -> bool {
+    let lower = text.trim().to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// The address in a dropped path: the path itself on platforms that hand links over
+/// as text, or the target of an Internet shortcut (`.url`) or macOS `.webloc` file.
+pub fn dropped_url(path: &Path) -> Option<String> {
+    let text = path.to_string_lossy();
+    if is_url(&text) {
+        return Some(text.trim().to_string());
+    }
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    if extension != "url" && extension != "webloc" {
+        return None;
+    }
+    let contents = std::fs::read_to_string(path).ok()?;
+    let url = if extension == "url" {
+        contents.lines().find_map(|line| line.trim().strip_prefix("URL="))?
+    } else {
+        let start = contents.find("<string>")? + "<string>".len();
+        &contents[start..start + contents[start..].find("</string>")?]
+    };
+    is_url(url).then(|| url.trim().replace("&amp;", "&"))
+}
+
+/// A local name for the download: the last segment of the URL path, with an
+/// extension from the content type when the URL has no usable one. Images are
+/// decoded by their contents, so the extension only has to get them listed.
+pub fn file_name(url: &str, content_type: Option<&str>) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = path.split_once("://").map_or(path, |(_, rest)| rest);
+    let last = path.split_once('/').map_or("", |(_, p)| p).rsplit('/').next().unwrap_or_default();
+    let mut name: String = last
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    name = name.trim_start_matches('.').to_string();
+    if name.is_empty() {
+        name = "image".to_string();
+    }
+    let supported = Path::new(&name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| crate::navigator::SUPPORTED_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+    if !supported {
+        let extension = match content_type.map(|t| t.split(';').next().unwrap_or_default().trim().to_lowercase()).as_deref() {
+            Some("image/png") => "png",
+            Some("image/tiff") => "tiff",
+            _ => "jpg",
+        };
+        name = format!("{}.{}", name, extension);
+    }
+    name
+}
+
+/// Where downloaded images are kept by default, so they can be browsed like a folder: in
+/// the user's cache folder, or else a folder of the temporary directory.
+pub fn default_dir() -> PathBuf {
+    match dirs::cache_dir() {
+        Some(cache) => cache.join("momemtum").join("downloads"),
+        None => std::env::temp_dir().join("momemtum-downloads"),
+    }
+}
+
+/// The download folder, created. One in the temporary directory must be one only the
+/// user can get into, since other users could otherwise plant files or links there.
+fn download_dir() -> Result<PathBuf> {
+    let root = CACHE_ROOT.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let dir = crate::cache::CacheKind::Downloads.dir(root.as_deref()).unwrap_or_else(default_dir);
+    if dir.starts_with(std::env::temp_dir()) {
+        private_dir(&dir)?;
+    } else {
+        std::fs::create_dir_all(&dir)?;
+    }
+    Ok(dir)
+}
+
+/// Create an empty file for the download: `name` in `dir`, or `2_name`, `3_name` and so
+/// on when that is taken. Never replaces a file, even one that appears meanwhile.
+fn claim(dir: &Path, name: &str) -> Result<PathBuf> {
+    let mut n = 1;
+    loop {
+        let target = if n == 1 { dir.join(name) } else { dir.join(format!("{}_{}", n, name)) };
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&target) {
+            Ok(_) => return Ok(target),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Delete the oldest downloads in `dir` until they take at most `keep` bytes. `latest`
+/// and downloads still in progress stay.
+fn prune(dir: &Path, keep: u64, latest: &Path) {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path() != latest && e.path().extension().is_none_or(|x| x != "part"))
+        .filter_map(|e| {
+            let metadata = e.metadata().ok().filter(|m| m.is_file())?;
+            Some((metadata.modified().ok()?, metadata.len(), e.path()))
+        })
+        .collect();
+    let mut total = files.iter().map(|(_, len, _)| len).sum::<u64>() + std::fs::metadata(latest).map_or(0, |m| m.len());
+    files.sort();
+    for (_, len, path) in files {
+        if total <= keep {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => total -= len,
+            Err(e) => log::warn!("Failed to delete the old download {:?}: {}", path, e),
+        }
+    }
+}
+
+/// Create `dir` readable by the user alone, or check that an existing one is theirs
+/// and nobody else's.
+#[cfg(unix)]
+fn private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e.into()),
+    }
+    // Not following links, so a link planted in its place is refused
+    let metadata = std::fs::symlink_metadata(dir)?;
+    // Safety: geteuid has no preconditions and always succeeds
+    let uid = unsafe { libc::geteuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(anyhow!("{} isn't a private folder of this user", dir.display()));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn private_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+/// Fetch `url` into the download folder. `progress` gets the bytes received so far
+/// and the size, when the server tells it.
+pub fn download(url: &str, mut progress: impl FnMut(u64, Option<u64>)) -> Result<PathBuf> {
+    let response = ureq::get(url.trim()).set("User-Agent", USER_AGENT).call()?;
+    let total = response.header("Content-Length").and_then(|l| l.parse::<u64>().ok());
+    if total.is_some_and(|t| t > MAX_BYTES) {
+        return Err(anyhow!("The file is larger than {} MB", MAX_BYTES / 1024 / 1024));
+    }
+    let name = file_name(response.get_url(), response.header("Content-Type"));
+    let dir = download_dir()?;
+
+    // Write to a temporary file and rename, so a cancelled download never shows up as an image
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let temp = dir.join(&name).with_extension(format!("{}-{}.part", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let result = (|| -> Result<()> {
+        // A new file only, never one that is already there or what a link points to
+        let file = std::fs::OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        let mut file = std::io::BufWriter::new(file);
+        let mut reader = response.into_reader().take(MAX_BYTES + 1);
+        let mut buffer = vec![0; 64 * 1024];
+        let (mut received, mut reported) = (0u64, Instant::now());
+        progress(0, total);
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            received += read as u64;
+            if received > MAX_BYTES {
+                return Err(anyhow!("The file is larger than {} MB", MAX_BYTES / 1024 / 1024));
+            }
+            if reported.elapsed() >= PROGRESS_INTERVAL {
+                progress(received, total);
+                reported = Instant::now();
+            }
+        }
+        file.flush()?;
+        progress(received, total);
+        Ok(())
+    })();
+    let target = result.and_then(|_| claim(&dir, &name)).and_then(|target| match std::fs::rename(&temp, &target) {
+        Ok(()) => Ok(target),
+        Err(e) => {
+            let _ = std::fs::remove_file(&target);
+            Err(e.into())
+        }
+    });
+    if target.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    let target = target?;
+    prune(&dir, KEEP_BYTES.load(Ordering::Relaxed), &target);
+    Ok(target)
+}
+
+/// Text on the system clipboard, read with the platform's command-line tool.
+pub fn clipboard_text() -> Option<String> {
+    let candidates: &[&[&str]] = if cfg!(target_os = "windows") {
+        &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard"]]
+    } else if cfg!(target_os = "macos") {
+        &[&["pbpaste"]]
+    } else {
+        &[&["wl-paste", "--no-newline"], &["xclip", "-selection", "clipboard", "-o"], &["xsel", "--clipboard", "--output"]]
+    };
+    candidates.iter().find_map(|command| {
+        let output = std::process::Command::new(command[0]).args(&command[1..]).output().ok()?;
+        let text = String::from_utf8(output.stdout).ok()?;
+        (output.status.success() && !text.trim().is_empty()).then(|| text.trim().to_string())
+    })
+}
+
+/// Put `text` on the system clipboard with the platform's command-line tool.
+pub fn set_clipboard_text(text: &str) -> Result<()> {
+    let candidates: &[&[&str]] = if cfg!(target_os = "windows") {
+        &[&["clip"]]
+    } else if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else {
+        &[&["wl-copy"], &["xclip", "-selection", "clipboard"], &["xsel", "--clipboard", "--input"]]
+    };
+    for command in candidates {
+        let Ok(mut child) = std::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+    Err(anyhow!("No clipboard tool found"))
+}
+
+/// Open `url` in the default browser.
+pub fn open_in_browser(url: &str) -> Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        // Unlike `cmd /C start`, this doesn't split the URL at `&`
+        let mut command = std::process::Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command.arg(url).spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_names_and_links() {
+        assert!(is_url("HTTPS://example.com/a.jpg"));
+        assert!(!is_url("/home/me/http/a.jpg"));
+        assert_eq!(file_name("https://example.com/photos/sea%20view.JPG?w=800", None), "sea_20view.JPG");
+        assert_eq!(file_name("https://example.com/image?id=4", Some("image/png; charset=binary")), "image.png");
+        assert_eq!(file_name("https://example.com/", None), "image.jpg");
+        assert_eq!(file_name("http://example.com/../.hidden", Some("image/tiff")), "hidden.tiff");
+
+        let dir = scratch_dir("remote");
+        let shortcut = dir.join("link.url");
+        std::fs::write(&shortcut, "[InternetShortcut]\r\nURL=https://example.com/a.png\r\n").unwrap();
+        assert_eq!(dropped_url(&shortcut).as_deref(), Some("https://example.com/a.png"));
+        let webloc = dir.join("link.webloc");
+        std::fs::write(&webloc, "<plist><dict><key>URL</key><string>https://example.com/b.jpg?x=1&amp;y=2</string></dict></plist>").unwrap();
+        assert_eq!(dropped_url(&webloc).as_deref(), Some("https://example.com/b.jpg?x=1&y=2"));
+        assert_eq!(dropped_url(&dir.join("photo.jpg")), None);
+    }
+
+    #[test]
+    fn test_claim_and_prune() {
+        let dir = scratch_dir("downloads");
+        std::fs::write(dir.join("a.jpg"), [0; 100]).unwrap();
+        assert_eq!(claim(&dir, "a.jpg").unwrap(), dir.join("2_a.jpg"));
+        assert_eq!(claim(&dir, "a.jpg").unwrap(), dir.join("3_a.jpg"));
+        assert_eq!(std::fs::read(dir.join("a.jpg")).unwrap().len(), 100);
+
+        // The oldest go first; the latest and those in progress stay
+        std::fs::write(dir.join("2_a.jpg"), [0; 100]).unwrap();
+        std::fs::write(dir.join("b.1-0.part"), [0; 500]).unwrap();
+        let latest = dir.join("3_a.jpg");
+        std::fs::write(&latest, [0; 150]).unwrap();
+        let old = std::time::SystemTime::now() - Duration::from_secs(60);
+        std::fs::File::options().write(true).open(dir.join("a.jpg")).unwrap().set_modified(old).unwrap();
+        prune(&dir, 300, &latest);
+        assert!(!dir.join("a.jpg").exists());
+        assert!(dir.join("2_a.jpg").exists() && dir.join("b.1-0.part").exists() && latest.exists());
+        prune(&dir, 0, &latest);
+        assert!(!dir.join("2_a.jpg").exists() && latest.exists());
+    }
+}
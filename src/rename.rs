@@ -0,0 +1,272 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::journal::Operation;
+
+/// Expand a rename template such as `{date}_{camera}_{seq:3}` for one file.
+///
+/// Placeholders: `{date}` (YYYY-MM-DD), `{time}` (HHMMSS), `{year}`, `{month}`, `{day}`,
+/// `{camera}`, `{make}`, `{name}` (original file name without extension) and `{seq}` /
+/// `{seq:N}` (running number, zero-padded to N digits). The extension is kept as is.
+pub fn expand(template: &str, exif: &HashMap<String, String>, path: &Path, seq: usize) -> Result<String> {
+    let (date, time) = capture_time(exif, path);
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| anyhow!("Unclosed '{{' in template"))? + start;
+        let placeholder = &rest[start + 1..end];
+        let (name, arg) = match placeholder.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (placeholder, None),
+        };
+        let value = match name {
+            "date" => date.clone(),
+            "time" => time.clone(),
+            "year" => date[0..4].to_string(),
+            "month" => date[5..7].to_string(),
+            "day" => date[8..10].to_string(),
+            "camera" => exif.get("Model").map(|m| m.trim_matches('"').to_string()).unwrap_or_else(|| "unknown".to_string()),
+            "make" => exif.get("Make").map(|m| m.trim_matches('"').to_string()).unwrap_or_else(|| "unknown".to_string()),
+            "name" => path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string(),
+            "seq" => {
+                let width = match arg {
+                    Some(w) => w.parse::<usize>().map_err(|_| anyhow!("Invalid width in {{{}}}", placeholder))?,
+                    None => 1,
+                };
+                format!("{:0width$}", seq, width = width)
+            }
+            _ => return Err(anyhow!("Unknown placeholder {{{}}}", placeholder)),
+        };
+        out.push_str(&sanitize(&value));
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+
+    if out.is_empty() {
+        return Err(anyhow!("Template produces an empty file name"));
+    }
+    if out.contains(['/', '\\']) {
+        return Err(anyhow!("Template must not contain path separators"));
+    }
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        out.push('.');
+        out.push_str(ext);
+    }
+    Ok(out)
+}
+
+/// Keep substituted values usable as file names on every platform.
+fn sanitize(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_whitespace() || c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// EXIF capture date/time, falling back to the file's modification time.
+fn capture_time(exif: &HashMap<String, String>, path: &Path) -> (String, String) {
+    let exif_time = ["DateTimeOriginal", "DateTimeDigitized", "DateTime"]
+        .iter()
+        .find_map(|key| exif.get(*key))
+        .filter(|v| v.is_ascii() && v.len() >= 19 && v[0..4].bytes().all(|b| b.is_ascii_digit()));
+    if let Some(v) = exif_time {
+        // kamadak-exif displays DateTime values as "YYYY-MM-DD HH:MM:SS"
+        return (v[0..10].replace(':', "-"), v[11..19].replace(':', ""));
+    }
+    let secs = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_unix_time(secs)
+}
+
+/// UTC date (YYYY-MM-DD) and time (HHMMSS) for seconds since the Unix epoch.
+pub fn format_unix_time(secs: u64) -> (String, String) {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days, after Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!("{:02}{:02}{:02}", rem / 3600, rem % 3600 / 60, rem % 60),
+    )
+}
+
+/// Compute the new name of every file. Files keep their folder.
+pub fn plan(files: &[PathBuf], template: &str) -> Result<Vec<(PathBuf, PathBuf)>> {
+    plan_with(files, template, crate::loader::read_exif)
+}
+
+fn plan_with<F>(files: &[PathBuf], template: &str, read_exif: F) -> Result<Vec<(PathBuf, PathBuf)>>
+where
+    F: Fn(&Path) -> HashMap<String, String>,
+{
+    files
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let name = expand(template, &read_exif(path), path, i + 1)?;
+            Ok((path.clone(), path.with_file_name(name)))
+        })
+        .collect()
+}
+
+/// Human-readable problems that prevent the plan from running.
+pub fn conflicts(plan: &[(PathBuf, PathBuf)]) -> Vec<String> {
+    let sources: HashSet<&PathBuf> = plan.iter().map(|(from, _)| from).collect();
+    let mut seen = HashSet::new();
+    let mut problems = Vec::new();
+    for (_, to) in plan {
+        if !seen.insert(to) {
+            problems.push(format!("{} is produced more than once", to.display()));
+        } else if to.exists() && !sources.contains(to) {
+            problems.push(format!("{} already exists", to.display()));
+        }
+    }
+    problems
+}
+
+/// Preview table of the plan, one "old -> new" row per file.
+pub fn format_table(plan: &[(PathBuf, PathBuf)]) -> String {
+    let name = |p: &Path| p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let width = plan.iter().map(|(from, _)| name(from).chars().count()).max().unwrap_or(0).max(8);
+    let mut out = format!("{:<width$}   {}\n", "Current", "New", width = width);
+    out.push_str(&format!("{}   {}\n", "-".repeat(width), "-".repeat(8)));
+    for (from, to) in plan {
+        let marker = if from == to { " (unchanged)" } else { "" };
+        out.push_str(&format!("{:<width$} → {}{}\n", name(from), name(to), marker, width = width));
+    }
+    out
+}
+
+/// Perform the renames. Files are first moved to temporary names so that swaps and
+/// chains (a→b, b→c) work. Returns the operations for the journal.
+pub fn execute(plan: &[(PathBuf, PathBuf)]) -> Result<Vec<Operation>> {
+    let problems = conflicts(plan);
+    if !problems.is_empty() {
+        return Err(anyhow!(problems.join("\n")));
+    }
+    let changes: Vec<_> = plan.iter().filter(|(from, to)| from != to).collect();
+
+    let mut staged = Vec::new();
+    for (i, (from, to)) in changes.iter().enumerate() {
+        let tmp = from.with_file_name(format!(".momemtum-rename-{}-{}", std::process::id(), i));
+        if let Err(e) = crate::journal::move_file(from, &tmp) {
+            return Err(roll_back(&staged, 0, e));
+        }
+        staged.push((tmp, from.clone(), to.clone()));
+    }
+
+    for (i, (tmp, _, to)) in staged.iter().enumerate() {
+        if let Err(e) = crate::journal::move_file(tmp, to) {
+            return Err(roll_back(&staged, i, e));
+        }
+    }
+    Ok(staged.into_iter().map(|(_, from, to)| Operation::Rename { from, to }).collect())
+}
+
+/// Put every file of a failed rename back under its original name: the first
+/// `renamed` staged files already have their new name, the rest a temporary one.
+/// Returns `error`, with what couldn't be put back.
+fn roll_back(staged: &[(PathBuf, PathBuf, PathBuf)], renamed: usize, error: anyhow::Error) -> anyhow::Error {
+    let mut stuck = Vec::new();
+    // New names go back to temporary ones first, so that swaps free each other's names
+    for (tmp, _, to) in staged[..renamed].iter().rev() {
+        if let Err(e) = crate::journal::move_file(to, tmp) {
+            stuck.push(format!("{}: {:#}", to.display(), e));
+        }
+    }
+    for (tmp, from, _) in staged.iter().rev() {
+        if tmp.exists() {
+            if let Err(e) = crate::journal::move_file(tmp, from) {
+                stuck.push(format!("{} (was {}): {:#}", tmp.display(), from.display(), e));
+            }
+        }
+    }
+    if stuck.is_empty() {
+        error.context("Rename failed; every file has its original name again")
+    } else {
+        error.context(format!("Rename failed and these files couldn't be put back:\n{}", stuck.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    fn exif() -> HashMap<String, String> {
+        let mut exif = HashMap::new();
+        exif.insert("DateTimeOriginal".to_string(), "2024-06-01 18:42:07".to_string());
+        exif.insert("Model".to_string(), "\"X-T5\"".to_string());
+        exif.insert("Make".to_string(), "\"FUJIFILM\"".to_string());
+        exif
+    }
+
+    #[test]
+    fn test_expand() {
+        let path = Path::new("/shoot/DSCF0001.RAF");
+        assert_eq!(
+            expand("{date}_{camera}_{seq:3}", &exif(), path, 7).unwrap(),
+            "2024-06-01_X-T5_007.RAF"
+        );
+        assert_eq!(expand("{year}-{month} {time} {name}", &exif(), path, 1).unwrap(), "2024-06 184207 DSCF0001.RAF");
+        assert!(expand("{year}/{month}", &exif(), path, 1).is_err());
+        assert!(expand("{bogus}", &exif(), path, 1).is_err());
+        assert!(expand("{date", &exif(), path, 1).is_err());
+    }
+
+    #[test]
+    fn test_format_unix_time() {
+        assert_eq!(format_unix_time(0), ("1970-01-01".to_string(), "000000".to_string()));
+        assert_eq!(format_unix_time(1_709_251_199), ("2024-02-29".to_string(), "235959".to_string()));
+    }
+
+    #[test]
+    fn test_plan_conflicts_and_swap() {
+        let dir = scratch_dir("rename");
+        let a = dir.join("a.jpg");
+        let b = dir.join("b.jpg");
+        std::fs::write(&a, b"a").unwrap();
+        std::fs::write(&b, b"b").unwrap();
+
+        // Same name for every file
+        let plan = plan_with(&[a.clone(), b.clone()], "same", |_| HashMap::new()).unwrap();
+        assert_eq!(conflicts(&plan).len(), 1);
+
+        // Swapping names works thanks to the temporary names
+        let plan = vec![(a.clone(), b.clone()), (b.clone(), a.clone())];
+        assert!(conflicts(&plan).is_empty());
+        let ops = execute(&plan).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert_eq!(std::fs::read(&a).unwrap(), b"b");
+        assert_eq!(std::fs::read(&b).unwrap(), b"a");
+
+        // A failure halfway puts the files that were already moved back
+        let missing = dir.join("missing.jpg");
+        let plan = vec![(a.clone(), dir.join("c.jpg")), (missing, dir.join("d.jpg"))];
+        assert!(execute(&plan).is_err());
+        assert_eq!(std::fs::read(&a).unwrap(), b"b");
+        assert!(!dir.join("c.jpg").exists());
+        let mut names: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["a.jpg", "b.jpg"]);
+    }
+}
@@ -0,0 +1,475 @@
+//! The image as the viewer draws it: a quad sized to the image's aspect, seen through
+//! a panning and zooming camera, with redactions, view adjustments, exposure overlays
+//! and a preview LUT applied in `shader.wgsl`. The app's window and `Viewer` both draw
+//! with it.
+
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+}
+
+impl Vertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ],
+        }
+    }
+}
+
+const VERTICES: &[Vertex] = &[
+    Vertex { position: [-1.0, 1.0, 0.0], tex_coords: [0.0, 0.0] },
+    Vertex { position: [-1.0, -1.0, 0.0], tex_coords: [0.0, 1.0] },
+    Vertex { position: [1.0, -1.0, 0.0], tex_coords: [1.0, 1.0] },
+    Vertex { position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0] },
+];
+
+const INDICES: &[u16] = &[
+    0, 1, 2,
+    0, 2, 3,
+];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+    scale: [f32; 2],
+    padding: [f32; 2], // Padding to align to 16 bytes (mat4 is 64, vec2 is 8, need 8 more)
+}
+
+impl CameraUniform {
+    fn new() -> Self {
+        Self {
+            view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            scale: [1.0, 1.0],
+            padding: [0.0, 0.0],
+        }
+    }
+
+    fn update_view_proj(&mut self, camera: &Camera, image_aspect: f32) {
+        let view = Mat4::look_at_rh(
+            Vec3::new(camera.x, camera.y, 1.0),
+            Vec3::new(camera.x, camera.y, 0.0),
+            Vec3::Y,
+        );
+
+        let proj = Mat4::orthographic_rh(
+            -camera.aspect * camera.zoom,
+            camera.aspect * camera.zoom,
+            -camera.zoom,
+            camera.zoom,
+            0.1,
+            100.0
+        );
+
+        self.view_proj = (proj * view).to_cols_array_2d();
+
+        // If image_aspect > 1.0 (wider), we scale X.
+        // If image_aspect < 1.0 (taller), we scale Y?
+        // Actually, let's just make the quad size match the aspect ratio.
+        // Quad is 2x2 (-1 to 1).
+        // We want it to be (2*aspect) x 2.
+        self.scale = [image_aspect, 1.0];
+    }
+}
+
+/// What the image shader does to the pixels besides showing them.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Effects {
+    pub redact_rects: [[f32; 4]; crate::redact::MAX_BOXES],
+    pub redact_params: [f32; 4], // x: box count, yz: pixelation block size in texture coordinates
+    pub adjust: [f32; 4],        // brightness, contrast, gamma, saturation
+    pub exposure: [f32; 4],      // x: overlay mode, y: highlight threshold, z: shadow threshold
+    pub lut_min: [f32; 4],       // xyz: LUT domain minimum, w: intensity, 0 when off
+    pub lut_max: [f32; 4],       // xyz: LUT domain maximum, w: grid size
+    pub original: [f32; 4],      // x: window x left of which the unadjusted image shows
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Self {
+            redact_rects: [[0.0; 4]; crate::redact::MAX_BOXES],
+            redact_params: [0.0; 4],
+            adjust: crate::adjust::Adjustments::default().uniform(),
+            exposure: [0.0; 4],
+            lut_min: [0.0; 4],
+            lut_max: [1.0, 1.0, 1.0, 2.0],
+            original: [0.0; 4],
+        }
+    }
+}
+
+/// Where the view is centered and how far it is zoomed out. The image spans 2 world
+/// units vertically and `2 * image_aspect` across, centered on the origin; the view
+/// spans `2 * zoom` vertically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub x: f32,
+    pub y: f32,
+    pub zoom: f32,
+    /// Width over height of the target drawn into
+    pub aspect: f32,
+}
+
+impl Camera {
+    /// Map a window pixel position to image texture coordinates (0..1 inside the image).
+    pub fn screen_to_uv(&self, image_aspect: f32, width: f32, height: f32, sx: f32, sy: f32) -> (f32, f32) {
+        let ndc_x = sx / width * 2.0 - 1.0;
+        let ndc_y = 1.0 - sy / height * 2.0;
+        let world_x = self.x + ndc_x * self.aspect * self.zoom;
+        let world_y = self.y + ndc_y * self.zoom;
+        ((world_x / image_aspect + 1.0) / 2.0, (1.0 - world_y) / 2.0)
+    }
+
+    /// Multiply the zoom by `factor`, keeping what is under window pixel (sx, sy) there.
+    pub fn zoom_about(&mut self, factor: f32, width: f32, height: f32, sx: f32, sy: f32) {
+        let ndc_x = sx / width * 2.0 - 1.0;
+        let ndc_y = 1.0 - sy / height * 2.0;
+        self.x += ndc_x * self.aspect * self.zoom * (1.0 - factor);
+        self.y += ndc_y * self.zoom * (1.0 - factor);
+        self.zoom *= factor;
+    }
+
+    /// Move the view along with a drag of (dx, dy) window pixels.
+    pub fn drag(&mut self, dx: f32, dy: f32, width: f32, height: f32) {
+        self.x -= dx * 2.0 * self.aspect * self.zoom / width;
+        self.y += dy * 2.0 * self.zoom / height;
+    }
+
+    /// Inverse of `screen_to_uv`.
+    pub fn uv_to_screen(&self, image_aspect: f32, width: f32, height: f32, u: f32, v: f32) -> (f32, f32) {
+        let world_x = (u * 2.0 - 1.0) * image_aspect;
+        let world_y = 1.0 - v * 2.0;
+        let ndc_x = (world_x - self.x) / (self.aspect * self.zoom);
+        let ndc_y = (world_y - self.y) / self.zoom;
+        ((ndc_x + 1.0) / 2.0 * width, (1.0 - ndc_y) / 2.0 * height)
+    }
+}
+
+/// The image pipeline and its camera and effects uniforms.
+pub struct Renderer {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    /// Layout of the bind groups of the image textures drawn
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    pub effects: Effects,
+    effects_buffer: wgpu::Buffer,
+    effects_bind_group: wgpu::BindGroup,
+    effects_bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl Renderer {
+    /// A renderer drawing into targets of `format`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            });
+
+        let camera_uniform = CameraUniform::new();
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("camera_bind_group_layout"),
+        });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+
+        let effects = Effects::default();
+        let effects_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Effects Buffer"),
+            contents: bytemuck::cast_slice(&[effects]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let effects_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("effects_bind_group_layout"),
+        });
+
+        let effects_bind_group =
+            create_effects_bind_group(device, queue, &effects_bind_group_layout, &effects_buffer, &crate::lut::Lut::identity(2));
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout, &effects_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            num_indices: INDICES.len() as u32,
+            texture_bind_group_layout,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            effects,
+            effects_buffer,
+            effects_bind_group,
+            effects_bind_group_layout,
+        }
+    }
+
+    /// Look at an image of `image_aspect` through `camera` from the next draw on.
+    pub fn set_camera(&mut self, queue: &wgpu::Queue, camera: &Camera, image_aspect: f32) {
+        self.camera_uniform.update_view_proj(camera, image_aspect);
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+    }
+
+    /// Upload `effects` for the next draw.
+    pub fn write_effects(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.effects_buffer, 0, bytemuck::cast_slice(&[self.effects]));
+    }
+
+    /// Preview images through `lut`, at the intensity set in `effects`.
+    pub fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: &crate::lut::Lut) {
+        self.effects_bind_group =
+            create_effects_bind_group(device, queue, &self.effects_bind_group_layout, &self.effects_buffer, lut);
+    }
+
+    /// Draw the image whose texture `image` binds, made with `texture_bind_group_layout`.
+    pub fn draw<'b>(&'b self, render_pass: &mut wgpu::RenderPass<'b>, image: &'b wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, image, &[]);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.effects_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+}
+
+/// The effects uniform and a LUT uploaded as a 3D texture, for the image shader.
+fn create_effects_bind_group(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    lut: &crate::lut::Lut,
+) -> wgpu::BindGroup {
+    let n = lut.size as u32;
+    let size = wgpu::Extent3d { width: n, height: n, depth_or_array_layers: n };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("LUT"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(&lut.texels()),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(8 * n),
+            rows_per_image: Some(n),
+        },
+        size,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&view) },
+            wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&sampler) },
+        ],
+        label: Some("effects_bind_group"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_uv_roundtrip() {
+        let camera = Camera { x: 0.3, y: -0.2, zoom: 0.5, aspect: 1.6 };
+        let (w, h) = (1600.0, 1000.0);
+
+        let (u, v) = camera.screen_to_uv(1.5, w, h, 400.0, 700.0);
+        let (x, y) = camera.uv_to_screen(1.5, w, h, u, v);
+        assert!((x - 400.0).abs() < 1e-3);
+        assert!((y - 700.0).abs() < 1e-3);
+
+        // Unzoomed, centered: window center is the image center
+        let camera = Camera { x: 0.0, y: 0.0, zoom: 1.0, aspect: 1.6 };
+        let (u, v) = camera.screen_to_uv(1.5, w, h, 800.0, 500.0);
+        assert!((u - 0.5).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_zoom_about_and_drag() {
+        let mut camera = Camera { x: 0.3, y: -0.2, zoom: 0.5, aspect: 1.6 };
+        let (w, h) = (1600.0, 1000.0);
+        let before = camera.screen_to_uv(1.5, w, h, 400.0, 700.0);
+        camera.zoom_about(0.5, w, h, 400.0, 700.0);
+        let after = camera.screen_to_uv(1.5, w, h, 400.0, 700.0);
+        assert!((before.0 - after.0).abs() < 1e-5 && (before.1 - after.1).abs() < 1e-5);
+        assert_eq!(camera.zoom, 0.25);
+
+        // What was under the finger stays under it
+        let grabbed = camera.screen_to_uv(1.5, w, h, 800.0, 500.0);
+        camera.drag(120.0, -80.0, w, h);
+        let moved = camera.screen_to_uv(1.5, w, h, 920.0, 420.0);
+        assert!((grabbed.0 - moved.0).abs() < 1e-5 && (grabbed.1 - moved.1).abs() < 1e-5);
+    }
+}
+
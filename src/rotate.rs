@@ -0,0 +1,207 @@
+//! Lossless rotation of JPEGs on disk by rewriting the EXIF orientation tag,
+//! leaving the compressed image data untouched.
+
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+const SHORT: u16 = 3;
+
+/// Each EXIF orientation as (mirrored first, then turned clockwise this many quarter turns).
+const TRANSFORMS: [(bool, u32); 8] =
+    [(false, 0), (true, 0), (false, 2), (true, 2), (true, 3), (false, 1), (true, 1), (false, 3)];
+
+/// The orientation showing an image of `orientation` turned `quarter_turns` further clockwise.
+pub fn rotated(orientation: u32, quarter_turns: u32) -> u32 {
+    let (mirrored, turns) = TRANSFORMS[(orientation.clamp(1, 8) - 1) as usize];
+    let target = (mirrored, (turns + quarter_turns) % 4);
+    TRANSFORMS.iter().position(|t| *t == target).map_or(1, |i| i as u32 + 1)
+}
+
+/// Whether `path` can be rotated in place without re-encoding.
+pub fn supported(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref(),
+        Some("jpg") | Some("jpeg")
+    )
+}
+
+/// Turn the JPEG at `path` `quarter_turns` clockwise. Returns the new orientation.
+pub fn save(path: &Path, quarter_turns: u32) -> Result<u32> {
+    let mut data = std::fs::read(path)?;
+    let orientation = match find_orientation(&data)? {
+        Some(field) => {
+            let orientation = rotated(field.value(&data) as u32, quarter_turns);
+            field.write(&mut data, orientation as u16);
+            orientation
+        }
+        None => {
+            let orientation = rotated(1, quarter_turns);
+            insert_exif(&mut data, orientation as u16)?;
+            orientation
+        }
+    };
+    // Replace the file only once it is fully written
+    let temp = path.with_extension("rotate.tmp");
+    std::fs::write(&temp, &data)?;
+    if let Err(e) = std::fs::rename(&temp, path) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e.into());
+    }
+    Ok(orientation)
+}
+
+/// Where the orientation value sits in the file.
+struct Field {
+    offset: usize,
+    big_endian: bool,
+}
+
+impl Field {
+    fn value(&self, data: &[u8]) -> u16 {
+        let bytes = [data[self.offset], data[self.offset + 1]];
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn write(&self, data: &mut [u8], value: u16) {
+        let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        data[self.offset..self.offset + 2].copy_from_slice(&bytes);
+    }
+}
+
+/// The APP1 Exif segment before the image data: (start of the TIFF header, its length).
+fn exif_segment(data: &[u8]) -> Result<Option<(usize, usize)>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(anyhow!("Not a JPEG file"));
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return Err(anyhow!("Malformed JPEG marker at {}", pos));
+        }
+        let marker = data[pos + 1];
+        // Start of scan: no more metadata segments
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let body = pos + 4;
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            return Err(anyhow!("Truncated JPEG segment at {}", pos));
+        }
+        if marker == 0xE1 && data[body..end].starts_with(b"Exif\0\0") {
+            return Ok(Some((body + 6, end - body - 6)));
+        }
+        pos = end;
+    }
+    Ok(None)
+}
+
+/// The orientation entry of the first image directory, if the file has one.
+fn find_orientation(data: &[u8]) -> Result<Option<Field>> {
+    let Some((start, len)) = exif_segment(data)? else {
+        return Ok(None);
+    };
+    let tiff = &data[start..start + len];
+    let big_endian = match tiff.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return Err(anyhow!("Unknown EXIF byte order")),
+    };
+    let u16_at = |at: usize| {
+        tiff.get(at..at + 2)
+            .map(|b| if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+            .ok_or_else(|| anyhow!("Truncated EXIF data"))
+    };
+    let ifd = tiff
+        .get(4..8)
+        .map(|b| if big_endian { u32::from_be_bytes([b[0], b[1], b[2], b[3]]) } else { u32::from_le_bytes([b[0], b[1], b[2], b[3]]) })
+        .ok_or_else(|| anyhow!("Truncated EXIF data"))? as usize;
+    let count = u16_at(ifd)? as usize;
+    for i in 0..count {
+        let entry = ifd + 2 + i * 12;
+        if u16_at(entry)? == ORIENTATION_TAG {
+            if u16_at(entry + 2)? != SHORT {
+                return Err(anyhow!("Unexpected EXIF orientation type"));
+            }
+            // A single SHORT is stored in the first bytes of the value field
+            u16_at(entry + 8)?;
+            return Ok(Some(Field { offset: start + entry + 8, big_endian }));
+        }
+    }
+    if count > 0 {
+        // Adding an entry would move every offset after it; not worth the risk
+        return Err(anyhow!("The EXIF data has no orientation entry to change"));
+    }
+    Ok(None)
+}
+
+/// Add an EXIF block holding only the orientation, after the JFIF header if there is one.
+fn insert_exif(data: &mut Vec<u8>, orientation: u16) -> Result<()> {
+    if exif_segment(data)?.is_some() {
+        return Err(anyhow!("The EXIF data has no orientation entry to change"));
+    }
+    let mut segment = vec![0xFF, 0xE1, 0, 0];
+    segment.extend_from_slice(b"Exif\0\0");
+    // Big-endian TIFF header, one directory at offset 8 with a single entry
+    segment.extend_from_slice(b"MM\0\x2a\0\0\0\x08");
+    segment.extend_from_slice(&1u16.to_be_bytes());
+    segment.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    segment.extend_from_slice(&SHORT.to_be_bytes());
+    segment.extend_from_slice(&1u32.to_be_bytes());
+    segment.extend_from_slice(&orientation.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    // No further directories
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    let length = (segment.len() - 2) as u16;
+    segment[2..4].copy_from_slice(&length.to_be_bytes());
+
+    let mut at = 2;
+    if data.get(2..4) == Some(&[0xFF, 0xE0]) && data.len() >= 6 {
+        at = 4 + u16::from_be_bytes([data[4], data[5]]) as usize;
+    }
+    data.splice(at..at, segment);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_rotated() {
+        assert_eq!(rotated(1, 1), 6);
+        assert_eq!(rotated(6, 1), 3);
+        assert_eq!(rotated(8, 1), 1);
+        assert_eq!(rotated(1, 3), 8);
+        // Mirrored orientations stay mirrored
+        assert_eq!(rotated(2, 1), 7);
+        assert_eq!(rotated(5, 1), 2);
+        for orientation in 1..=8 {
+            assert_eq!(rotated(rotated(orientation, 1), 3), orientation);
+        }
+    }
+
+    #[test]
+    fn test_save_keeps_the_image_data() {
+        let dir = scratch_dir("rotate");
+        let path = dir.join("wide.jpg");
+        image::RgbImage::from_pixel(8, 4, image::Rgb([200, 40, 40])).save(&path).unwrap();
+        let before = std::fs::read(&path).unwrap();
+
+        // Without EXIF a block is added; after that the tag is changed in place
+        assert_eq!(save(&path, 1).unwrap(), 6);
+        assert_eq!(save(&path, 1).unwrap(), 3);
+        let after = std::fs::read(&path).unwrap();
+        assert!(after.ends_with(&before[before.len() - 100..]));
+        let loaded = crate::loader::load_image(&path).unwrap();
+        assert_eq!((loaded.image.width(), loaded.image.height()), (8, 4));
+        assert_eq!(save(&path, 1).unwrap(), 8);
+        let loaded = crate::loader::load_image(&path).unwrap();
+        assert_eq!((loaded.image.width(), loaded.image.height()), (4, 8));
+
+        assert!(save(&dir.join("missing.jpg"), 1).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Optional out-of-process decoding for untrusted files. The viewer re-runs its
+//! own executable as a restricted `decode-worker` child, which writes the decoded
+//! pixels, animation frames included, into a memory-mapped file that the viewer maps
+//! and validates, and reports its progress line by line on stdout. A decoder crash,
+//! hang or exploit then stays in the child. Tiled sources, which would be parsed in
+//! the viewer, are not opened while it is on.
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+use memmap2::{Mmap, MmapMut};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::time::{Duration, Instant};
+
+use crate::loader::{Frame, LoadedImage, MAX_DIMENSION};
+use crate::progress::Stage;
+use crate::raw::Demosaic;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Wall-clock budget for one decode before the worker is killed.
+const DECODE_TIMEOUT: Duration = Duration::from_secs(60);
+const MAGIC: &[u8; 4] = b"MMDW";
+// Magic, width, height, channels, EXIF length, frame count
+const HEADER_LEN: usize = 24;
+// Width, height and delay in milliseconds ahead of each frame's RGBA pixels
+const FRAME_HEADER_LEN: usize = 12;
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Decode `path`, in a worker process when sandboxed decoding is enabled.
+pub fn load(path: &Path) -> Result<LoadedImage> {
+    if enabled() {
+        if crate::pyramid::is_dzi(path) {
+            return Err(anyhow!("Deep Zoom images can't be opened while decoding is sandboxed"));
+        }
+        return load_isolated(path);
+    }
+    // Deep Zoom tiles come back through this function
+    if let Some(source) = crate::pyramid::open_source(path)? {
+        crate::pyramid::load_overview(path, source.as_ref())
+    } else {
+        crate::loader::load_image(path)
+    }
+}
+
+fn load_isolated(path: &Path) -> Result<LoadedImage> {
+    let start = Instant::now();
+    let output = scratch_path();
+    let mut command = Command::new(std::env::current_exe()?);
+    command.arg("decode-worker").arg(path).arg(&output);
+    if crate::loader::demosaic() == Demosaic::Nearest {
+        command.arg("--nearest-demosaic");
+    }
+    command.stdin(Stdio::null()).stdout(Stdio::piped());
+    #[cfg(unix)]
+    restrict(&mut command);
+
+    let mut child = command.spawn().context("Failed to start decoder process")?;
+    let reports = child.stdout.take().map(forward_progress);
+    let deadline = Instant::now() + DECODE_TIMEOUT;
+    let status = loop {
+        // The sink is this thread's, so the reader thread hands the reports over
+        for (stage, fraction) in reports.iter().flat_map(|r| r.try_iter()) {
+            crate::progress::report(stage, fraction);
+        }
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = std::fs::remove_file(&output);
+            return Err(anyhow!("Decoder process timed out on {:?}", path));
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+
+    let result = if status.success() {
+        read_result(&output)
+    } else {
+        Err(anyhow!("Decoder process failed on {:?} ({})", path, status))
+    };
+    let _ = std::fs::remove_file(&output);
+    let (image, exif, frames) = result?;
+
+    Ok(LoadedImage {
+        image,
+        exif,
+        load_time: start.elapsed(),
+        path: path.to_path_buf(),
+        xmp: crate::xmp::read_sidecar(path),
+        frames,
+    })
+}
+
+/// Entry point of the `decode-worker` child, which runs before the config is read or
+/// logging is set up, so it neither reads nor writes anything besides `input` and
+/// `output`.
+pub fn run_worker(input: &Path, output: &Path, nearest_demosaic: bool) -> Result<()> {
+    crate::loader::set_demosaic(if nearest_demosaic { Demosaic::Nearest } else { Demosaic::Bilinear });
+    let sink = |stage, fraction| println!("{}", format_progress(stage, fraction));
+    let loaded = crate::progress::watch(sink, || crate::loader::load_image(input))?;
+    write_result(output, &loaded.image, &loaded.exif, &loaded.frames)
+}
+
+/// A progress report as the worker prints it, e.g. `1 0.25` or `2 -`.
+fn format_progress(stage: Stage, fraction: Option<f32>) -> String {
+    let code = Stage::ALL.iter().position(|s| *s == stage).unwrap_or_default();
+    match fraction {
+        Some(fraction) => format!("{} {}", code, fraction),
+        None => format!("{} -", code),
+    }
+}
+
+/// A report the worker printed; anything else it prints is ignored.
+fn parse_progress(line: &[u8]) -> Option<(Stage, Option<f32>)> {
+    let (code, fraction) = std::str::from_utf8(line).ok()?.trim().split_once(' ')?;
+    let stage = *Stage::ALL.get(code.parse::<usize>().ok()?)?;
+    let fraction = match fraction {
+        "-" => None,
+        f => Some(f.parse::<f32>().ok().filter(|f| f.is_finite())?),
+    };
+    Some((stage, fraction))
+}
+
+/// Read the worker's reports on a thread of their own, so a silent worker can't hold up
+/// the timeout. Lines are short; a longer one is cut, and the pieces don't parse.
+fn forward_progress(stdout: ChildStdout) -> Receiver<(Stage, Option<f32>)> {
+    let (sender, receiver) = sync_channel(16);
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match (&mut reader).take(64).read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            if let Some(report) = parse_progress(&line) {
+                if sender.send(report).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+/// A fresh name in the user's private runtime directory, or the temp directory.
+/// The worker creates it exclusively, so a planted file makes the decode fail
+/// rather than be read back.
+fn scratch_path() -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let dir = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    dir.join(format!(
+        "momemtum-decode-{}-{}.bin",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+fn write_result(output: &Path, image: &DynamicImage, exif: &HashMap<String, String>, frames: &[Frame]) -> Result<()> {
+    let (pixels, channels) = if image.color().has_alpha() {
+        (image.to_rgba8().into_raw(), 4u32)
+    } else {
+        (image.to_rgb8().into_raw(), 3u32)
+    };
+    let exif = toml::to_string(exif)?.into_bytes();
+    let frames_len: usize = frames.iter().map(|f| FRAME_HEADER_LEN + f.image.as_raw().len()).sum();
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(output)?;
+    file.set_len((HEADER_LEN + exif.len() + pixels.len() + frames_len) as u64)?;
+    // Safety: the file was just created by this process and nothing else maps it yet
+    let mut map = unsafe { MmapMut::map_mut(&file)? };
+    map[0..4].copy_from_slice(MAGIC);
+    let header = [image.width(), image.height(), channels, exif.len() as u32, frames.len() as u32];
+    for (i, value) in header.into_iter().enumerate() {
+        map[4 + i * 4..8 + i * 4].copy_from_slice(&value.to_le_bytes());
+    }
+    let mut at = HEADER_LEN;
+    for bytes in [&exif[..], &pixels[..]] {
+        map[at..at + bytes.len()].copy_from_slice(bytes);
+        at += bytes.len();
+    }
+    for frame in frames {
+        let delay = frame.delay.as_millis().min(u32::MAX as u128) as u32;
+        for (i, value) in [frame.image.width(), frame.image.height(), delay].into_iter().enumerate() {
+            map[at + i * 4..at + 4 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        at += FRAME_HEADER_LEN;
+        let raw = frame.image.as_raw();
+        map[at..at + raw.len()].copy_from_slice(raw);
+        at += raw.len();
+    }
+    map.flush()?;
+    Ok(())
+}
+
+/// Read a worker's output. It comes from a process that may have been
+/// compromised, so every size is checked against the mapping.
+fn read_result(path: &Path) -> Result<(DynamicImage, HashMap<String, String>, Vec<Frame>)> {
+    let file = std::fs::File::open(path)?;
+    // Safety: the worker has exited, so the file no longer changes underneath the map
+    let map = unsafe { Mmap::map(&file)? };
+    if map.len() < HEADER_LEN || &map[0..4] != MAGIC {
+        return Err(anyhow!("Malformed decoder output"));
+    }
+    let field = |at: usize| u32::from_le_bytes(map[at..at + 4].try_into().unwrap());
+    let (width, height, channels, exif_len, frame_count) = (field(4), field(8), field(12), field(16) as usize, field(20));
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION || !(channels == 3 || channels == 4) {
+        return Err(anyhow!("Malformed decoder output: {}x{}x{}", width, height, channels));
+    }
+    let pixel_len = width as usize * height as usize * channels as usize;
+    let frames_at = HEADER_LEN
+        .checked_add(exif_len)
+        .and_then(|n| n.checked_add(pixel_len))
+        .filter(|n| *n <= map.len())
+        .ok_or_else(|| anyhow!("Malformed decoder output: size mismatch"))?;
+
+    let exif_text = std::str::from_utf8(&map[HEADER_LEN..HEADER_LEN + exif_len])?;
+    let exif: HashMap<String, String> = toml::from_str(exif_text)?;
+    let pixels = map[HEADER_LEN + exif_len..frames_at].to_vec();
+    let image = if channels == 4 {
+        DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, pixels).ok_or_else(|| anyhow!("Bad buffer"))?)
+    } else {
+        DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, pixels).ok_or_else(|| anyhow!("Bad buffer"))?)
+    };
+
+    let mut frames = Vec::new();
+    let mut at = frames_at;
+    for _ in 0..frame_count {
+        if map.len() - at < FRAME_HEADER_LEN {
+            return Err(anyhow!("Malformed decoder output: truncated frame"));
+        }
+        let (w, h, delay) = (field(at), field(at + 4), field(at + 8));
+        if w == 0 || h == 0 || w > MAX_DIMENSION || h > MAX_DIMENSION {
+            return Err(anyhow!("Malformed decoder output: {}x{} frame", w, h));
+        }
+        at += FRAME_HEADER_LEN;
+        let len = w as usize * h as usize * 4;
+        if map.len() - at < len {
+            return Err(anyhow!("Malformed decoder output: truncated frame"));
+        }
+        let image = RgbaImage::from_raw(w, h, map[at..at + len].to_vec()).ok_or_else(|| anyhow!("Bad buffer"))?;
+        frames.push(Frame { image, delay: Duration::from_millis(delay as u64) });
+        at += len;
+    }
+    if at != map.len() {
+        return Err(anyhow!("Malformed decoder output: size mismatch"));
+    }
+    Ok((image, exif, frames))
+}
+
+/// Drop what the worker doesn't need: privilege escalation through setuid
+/// binaries, the environment, core dumps, and unbounded CPU time and memory.
+#[cfg(unix)]
+fn restrict(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // Only lowers limits, so it never fails because of an already tighter hard limit
+    macro_rules! lower_limit {
+        ($resource:expr, $value:expr) => {{
+            let mut limit: libc::rlimit = std::mem::zeroed();
+            if libc::getrlimit($resource, &mut limit) == 0 {
+                let value = ($value as libc::rlim_t).min(limit.rlim_max);
+                limit.rlim_cur = value;
+                limit.rlim_max = value;
+                if libc::setrlimit($resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }};
+    }
+
+    command.env_clear();
+    // Safety: only async-signal-safe libc calls run between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            #[cfg(target_os = "linux")]
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            lower_limit!(libc::RLIMIT_CORE, 0);
+            lower_limit!(libc::RLIMIT_CPU, DECODE_TIMEOUT.as_secs());
+            lower_limit!(libc::RLIMIT_AS, 6u64 << 30);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_roundtrip() {
+        let path = scratch_path();
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_fn(5, 3, |x, y| image::Rgb([x as u8, y as u8, 7])));
+        let exif = HashMap::from([("Model".to_string(), "\"Test \\n Camera\"".to_string())]);
+
+        let frames = vec![
+            Frame { image: RgbaImage::from_pixel(5, 3, image::Rgba([1, 2, 3, 4])), delay: Duration::from_millis(40) },
+            Frame { image: RgbaImage::from_pixel(5, 3, image::Rgba([5, 6, 7, 8])), delay: Duration::from_millis(100) },
+        ];
+
+        write_result(&path, &image, &exif, &frames).unwrap();
+        // The worker never overwrites an existing file
+        assert!(write_result(&path, &image, &exif, &frames).is_err());
+        let (decoded, decoded_exif, decoded_frames) = read_result(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(decoded.to_rgb8(), image.to_rgb8());
+        assert_eq!(decoded_exif, exif);
+        assert_eq!(decoded_frames.len(), 2);
+        assert_eq!(decoded_frames[1].image, frames[1].image);
+        assert_eq!(decoded_frames[1].delay, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_progress_lines() {
+        for (stage, fraction) in [(Stage::Demosaicing, Some(0.25)), (Stage::Decoding, None), (Stage::Reading, Some(1.0))] {
+            let line = format_progress(stage, fraction) + "\n";
+            assert_eq!(parse_progress(line.as_bytes()), Some((stage, fraction)));
+        }
+        assert_eq!(parse_progress(b"9 0.5"), None);
+        assert_eq!(parse_progress(b"1 NaN"), None);
+        assert_eq!(parse_progress(b"hello"), None);
+        assert_eq!(parse_progress(&[0xff, b' ', b'-']), None);
+    }
+
+    #[test]
+    fn test_rejects_malformed_output() {
+        let path = scratch_path();
+        let image = DynamicImage::new_rgba8(4, 4);
+        let frame = Frame { image: RgbaImage::new(4, 4), delay: Duration::ZERO };
+        write_result(&path, &image, &HashMap::new(), &[frame]).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Truncated pixel data
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(read_result(&path).is_err());
+
+        // A frame claiming to be larger than what follows it
+        let mut lying = bytes.clone();
+        let frame_at = HEADER_LEN + 4 * 4 * 4;
+        lying[frame_at..frame_at + 4].copy_from_slice(&5u32.to_le_bytes());
+        std::fs::write(&path, &lying).unwrap();
+        assert!(read_result(&path).is_err());
+
+        // More frames than were written
+        let mut lying = bytes.clone();
+        lying[20..24].copy_from_slice(&2u32.to_le_bytes());
+        std::fs::write(&path, &lying).unwrap();
+        assert!(read_result(&path).is_err());
+
+        // A header claiming a huge image
+        bytes[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(read_result(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
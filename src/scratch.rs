@@ -0,0 +1,36 @@
+//! Scratch folders for tests. Each is emptied when made, in case an earlier run was cut
+//! short, and removed with whatever the test left in it when dropped, failed or not.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+pub struct ScratchDir(PathBuf);
+
+/// An empty folder in the temporary directory named after `name` and the process, so
+/// tests running at once don't share one.
+pub fn scratch_dir(name: &str) -> ScratchDir {
+    let dir = std::env::temp_dir().join(format!("momemtum-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    ScratchDir(dir)
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,82 @@
+//! Where the user left off: the open image and its view, the window, the sort
+//! order and the images being compared, saved on exit and restored on the next
+//! launch when `restore_session` is set.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::navigator::SortOrder;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Session {
+    pub path: Option<PathBuf>,
+    /// Camera position and zoom on `path`, in world units
+    pub camera: Option<[f32; 3]>,
+    pub window: Option<WindowGeometry>,
+    pub sort: SortOrder,
+    /// Images being compared, empty when compare wasn't open
+    pub compare: Vec<PathBuf>,
+    pub compare_focus: usize,
+}
+
+/// Outer position and inner size in physical pixels.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+}
+
+impl Session {
+    fn path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("momemtum").join("session.toml"))
+    }
+
+    /// The last saved session; empty when there is none or it can't be read.
+    pub fn load() -> Self {
+        let Some(text) = Self::path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+            return Self::default();
+        };
+        let mut session: Self = toml::from_str(&text).unwrap_or_else(|e| {
+            log::warn!("Ignoring invalid session file: {}", e);
+            Self::default()
+        });
+        // Files may have gone since
+        session.path = session.path.filter(|p| p.exists());
+        session.compare.retain(|p| p.exists());
+        session
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow!("No data directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_roundtrip() {
+        let session = Session {
+            path: Some(PathBuf::from("/photos/a.jpg")),
+            camera: Some([0.25, -0.5, 0.125]),
+            window: Some(WindowGeometry { x: -1920, y: 40, width: 1280, height: 800, maximized: false }),
+            sort: SortOrder::Captured,
+            compare: vec![PathBuf::from("/photos/a.jpg"), PathBuf::from("/photos/b.jpg")],
+            compare_focus: 1,
+        };
+        let text = toml::to_string_pretty(&session).unwrap();
+        assert_eq!(toml::from_str::<Session>(&text).unwrap(), session);
+        assert_eq!(toml::from_str::<Session>("sort = \"size\"").unwrap().sort, SortOrder::Size);
+    }
+}
@@ -37,7 +37,116 @@ var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
 
+struct EffectsUniform {
+    // Redaction boxes in texture coordinates: u0, v0, u1, v1
+    redact_rects: array<vec4<f32>, 16>,
+    // x: box count, yz: pixelation block size in texture coordinates
+    redact_params: vec4<f32>,
+    // View adjustments: brightness, contrast, gamma, saturation
+    adjust: vec4<f32>,
+    // x: exposure overlay (0 off, 1 clipping, 2 false color), y: highlight threshold, z: shadow threshold
+    exposure: vec4<f32>,
+    // Preview LUT: xyz domain minimum, w intensity (0 when off); xyz domain maximum, w grid size
+    lut_min: vec4<f32>,
+    lut_max: vec4<f32>,
+    // x: window x left of which the original shows, without LUT, adjustments or overlays
+    original: vec4<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> effects: EffectsUniform;
+@group(2) @binding(1)
+var t_lut: texture_3d<f32>;
+@group(2) @binding(2)
+var s_lut: sampler;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    var uv = in.tex_coords;
+    let count = u32(effects.redact_params.x);
+    for (var i = 0u; i < count; i = i + 1u) {
+        let r = effects.redact_rects[i];
+        if (uv.x >= r.x && uv.x <= r.z && uv.y >= r.y && uv.y <= r.w) {
+            let block = effects.redact_params.yz;
+            uv = (floor(uv / block) + 0.5) * block;
+            break;
+        }
+    }
+    let color = textureSampleLevel(t_diffuse, s_diffuse, uv, 0.0);
+    if (in.clip_position.x < effects.original.x) {
+        return color;
+    }
+    var rgb = color.rgb;
+    if (effects.lut_min.w > 0.0) {
+        rgb = apply_lut(rgb);
+    }
+    if (any(effects.adjust != vec4<f32>(0.0, 1.0, 1.0, 1.0))) {
+        rgb = adjust(rgb);
+    }
+    if (effects.exposure.x > 0.0) {
+        rgb = exposure(rgb);
+    }
+    return vec4<f32>(rgb, color.a);
+}
+
+fn srgb_encode(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+fn srgb_decode(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+// LUTs expect encoded values, as in the file
+fn apply_lut(linear: vec3<f32>) -> vec3<f32> {
+    let c = srgb_encode(linear);
+    let n = effects.lut_max.w;
+    let t = clamp((c - effects.lut_min.xyz) / (effects.lut_max.xyz - effects.lut_min.xyz), vec3<f32>(0.0), vec3<f32>(1.0));
+    // Grid points sit at texel centers
+    let graded = textureSampleLevel(t_lut, s_lut, (t * (n - 1.0) + 0.5) / n, 0.0).rgb;
+    return srgb_decode(clamp(mix(c, graded, effects.lut_min.w), vec3<f32>(0.0), vec3<f32>(1.0)));
+}
+
+// Adjustments work on display values, so their steps look even
+fn adjust(linear: vec3<f32>) -> vec3<f32> {
+    var c = srgb_encode(linear) + effects.adjust.x;
+    c = clamp((c - 0.5) * effects.adjust.y + 0.5, vec3<f32>(0.0), vec3<f32>(1.0));
+    c = pow(c, vec3<f32>(1.0 / effects.adjust.z));
+    let luma = dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+    c = clamp(mix(vec3<f32>(luma), c, effects.adjust.w), vec3<f32>(0.0), vec3<f32>(1.0));
+    return srgb_decode(c);
+}
+
+// Clipping warnings or false color, judged on the displayed values
+fn exposure(linear: vec3<f32>) -> vec3<f32> {
+    let c = srgb_encode(linear);
+    let highlight = effects.exposure.y;
+    let shadow = effects.exposure.z;
+    if (effects.exposure.x < 1.5) {
+        if (any(c >= vec3<f32>(highlight))) {
+            return vec3<f32>(1.0, 0.0, 0.0);
+        }
+        if (all(c <= vec3<f32>(shadow))) {
+            return vec3<f32>(0.0, 0.0, 1.0);
+        }
+        return linear;
+    }
+    let luma = dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+    var band = vec3<f32>(luma);
+    if (luma <= shadow) {
+        band = vec3<f32>(0.5, 0.0, 0.6);
+    } else if (luma < 0.1) {
+        band = vec3<f32>(0.0, 0.3, 1.0);
+    } else if (luma >= highlight) {
+        band = vec3<f32>(1.0, 0.0, 0.0);
+    } else if (luma >= 0.9) {
+        band = vec3<f32>(1.0, 1.0, 0.0);
+    } else if (luma >= 0.62 && luma < 0.7) {
+        // A stop over middle gray, where lit skin sits
+        band = vec3<f32>(1.0, 0.5, 0.7);
+    } else if (luma >= 0.42 && luma < 0.5) {
+        // Middle gray
+        band = vec3<f32>(0.0, 0.8, 0.2);
+    }
+    return srgb_decode(band);
 }
@@ -0,0 +1,422 @@
+//! Whole-slide and other very large TIFFs (classic or BigTIFF), read tile by tile.
+//! Scanners store reduced-resolution copies of the image as further IFDs; these
+//! become the levels of a [`TileSource`], so zooming out never decodes the
+//! full-resolution image. Files with few or no reduced copies, like scanned maps,
+//! get coarser levels made up from the smallest one they have.
+
+use anyhow::{anyhow, Result};
+use image::{imageops, DynamicImage, ImageBuffer, RgbaImage};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tiff::decoder::{ChunkType, Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use crate::pyramid::{TileSource, TILE_SIZE};
+
+/// Extensions of TIFF-based formats, including Aperio slides and BigTIFF.
+pub const TIFF_EXTENSIONS: &[&str] = &["tif", "tiff", "svs", "btf"];
+/// Largest tile accepted from a file, so a forged header can't ask for a huge texture.
+const MAX_TILE_SIZE: u32 = 4096;
+/// Halvings made up below the smallest stored level; a tile of the last one is read
+/// from 2^n x 2^n tiles of the stored level.
+const MAX_SHRINK: u32 = 3;
+
+type TiffDecoder = Decoder<BufReader<File>>;
+
+pub fn is_tiff(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+        Some(ext) => TIFF_EXTENSIONS.contains(&ext.as_str()),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    ifd: usize,
+    width: u32,
+    height: u32,
+    /// Tiled levels use the file's tiles. Striped levels are cut into
+    /// `TILE_SIZE` squares assembled from the strips they cover.
+    striped: Option<u32>,
+    tile: (u32, u32),
+    /// For levels the file doesn't store: the index of the stored level they are
+    /// scaled down from, and by how many halvings
+    scaled: Option<(usize, u32)>,
+}
+
+/// A TIFF whose resolution levels are the IFDs with the aspect ratio of the first one.
+pub struct TiffSource {
+    path: PathBuf,
+    levels: Vec<Level>,
+    /// Decoders already positioned on an IFD, reused across tiles
+    decoders: Mutex<HashMap<usize, Vec<TiffDecoder>>>,
+}
+
+impl std::fmt::Debug for TiffSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TiffSource").field("path", &self.path).field("levels", &self.levels).finish()
+    }
+}
+
+impl TiffSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut decoder = open_decoder(path)?;
+        let mut levels: Vec<Level> = Vec::new();
+        let mut ifd = 0;
+        loop {
+            if let Some(level) = describe_level(&mut decoder, ifd)? {
+                let base = levels.first().copied().unwrap_or(level);
+                // Label and macro photos of slides have their own aspect ratio
+                let same_aspect = (level.width as f64 / level.height as f64 - base.width as f64 / base.height as f64).abs()
+                    < 0.02 * base.width as f64 / base.height as f64;
+                if same_aspect && levels.iter().all(|l| l.width != level.width) {
+                    levels.push(level);
+                }
+            }
+            // A damaged IFD further down still leaves the levels found so far
+            if !decoder.more_images() || decoder.next_image().is_err() {
+                break;
+            }
+            ifd += 1;
+        }
+        if levels.is_empty() {
+            return Err(anyhow!("No supported image in {:?}", path));
+        }
+        levels.sort_by_key(|l| std::cmp::Reverse(l.width));
+        let smallest = levels.len() - 1;
+        let base = levels[smallest];
+        for shrink in 1..=MAX_SHRINK {
+            let last = levels[levels.len() - 1];
+            if last.width.max(last.height) <= TILE_SIZE {
+                break;
+            }
+            let (width, height) = crate::pyramid::level_size((base.width, base.height), shrink);
+            let tile = (TILE_SIZE, TILE_SIZE);
+            levels.push(Level { width, height, tile, scaled: Some((smallest, shrink)), ..base });
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            levels,
+            decoders: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn level(&self, level: u32) -> Result<Level> {
+        self.levels.get(level as usize).copied().ok_or_else(|| anyhow!("No level {}", level))
+    }
+
+    fn with_decoder<T>(&self, ifd: usize, read: impl FnOnce(&mut TiffDecoder) -> Result<T>) -> Result<T> {
+        let pooled = self.decoders.lock().unwrap().get_mut(&ifd).and_then(Vec::pop);
+        let mut decoder = match pooled {
+            Some(decoder) => decoder,
+            None => {
+                let mut decoder = open_decoder(&self.path)?;
+                decoder.seek_to_image(ifd)?;
+                decoder
+            }
+        };
+        let result = read(&mut decoder);
+        // A decoder that failed mid-read may be in any state, so only good ones go back
+        if result.is_ok() {
+            self.decoders.lock().unwrap().entry(ifd).or_default().push(decoder);
+        }
+        result
+    }
+}
+
+impl TileSource for TiffSource {
+    fn size(&self) -> (u32, u32) {
+        (self.levels[0].width, self.levels[0].height)
+    }
+
+    fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    fn level_size(&self, level: u32) -> (u32, u32) {
+        self.levels.get(level as usize).map_or((1, 1), |l| (l.width, l.height))
+    }
+
+    fn tile_size(&self, level: u32) -> (u32, u32) {
+        self.levels.get(level as usize).map_or((TILE_SIZE, TILE_SIZE), |l| l.tile)
+    }
+
+    fn tile(&self, level: u32, col: u32, row: u32) -> Result<DynamicImage> {
+        let level = self.level(level)?;
+        let (tw, th) = level.tile;
+        let (x, y) = (col * tw, row * th);
+        if x >= level.width || y >= level.height {
+            return Err(anyhow!("Tile {},{} is outside the image", col, row));
+        }
+        let (w, h) = (tw.min(level.width - x), th.min(level.height - y));
+        let Some((stored, shrink)) = level.scaled else {
+            return self.read_region(level, x, y, w, h).map(DynamicImage::ImageRgba8);
+        };
+        let stored = self.level(stored as u32)?;
+        let (sx, sy) = (x << shrink, y << shrink);
+        let (sw, sh) = ((w << shrink).min(stored.width - sx), (h << shrink).min(stored.height - sy));
+        let region = self.read_region(stored, sx, sy, sw, sh)?;
+        Ok(DynamicImage::ImageRgba8(imageops::resize(&region, w, h, imageops::FilterType::Triangle)))
+    }
+}
+
+impl TiffSource {
+    /// The `w` x `h` pixels at `x`, `y` of a stored level, decoding only the tiles or
+    /// strips they overlap.
+    fn read_region(&self, level: Level, x: u32, y: u32, w: u32, h: u32) -> Result<RgbaImage> {
+        // Strips are chunks as wide as the level
+        let (chunk_w, chunk_h) = match level.striped {
+            Some(rows_per_strip) => (level.width, rows_per_strip),
+            None => level.tile,
+        };
+        let per_row = level.width.div_ceil(chunk_w);
+        self.with_decoder(level.ifd, |decoder| {
+            let color = decoder.colortype()?;
+            let mut region = RgbaImage::new(w, h);
+            for row in y / chunk_h..=(y + h - 1) / chunk_h {
+                for col in x / chunk_w..=(x + w - 1) / chunk_w {
+                    let (cx, cy) = (col * chunk_w, row * chunk_h);
+                    let (cw, ch) = (chunk_w.min(level.width - cx), chunk_h.min(level.height - cy));
+                    let pixels = to_rgba(decoder.read_chunk(row * per_row + col)?, color, cw, ch)?;
+                    imageops::replace(&mut region, &pixels, cx as i64 - x as i64, cy as i64 - y as i64);
+                }
+            }
+            Ok(region)
+        })
+    }
+}
+
+fn open_decoder(path: &Path) -> Result<TiffDecoder> {
+    let mut limits = Limits::default();
+    // Tile offset tables of large slides exceed the default 1 MiB
+    limits.ifd_value_size = 64 * 1024 * 1024;
+    Ok(Decoder::new(BufReader::new(File::open(path)?))?.with_limits(limits))
+}
+
+/// The current IFD as a level, or `None` when its layout is one we can't read.
+fn describe_level(decoder: &mut TiffDecoder, ifd: usize) -> Result<Option<Level>> {
+    let (width, height) = decoder.dimensions()?;
+    let planar = decoder.find_tag_unsigned::<u16>(Tag::PlanarConfiguration)?.unwrap_or(1);
+    if width == 0 || height == 0 || planar != 1 || decoder.colortype().is_err() {
+        return Ok(None);
+    }
+    let (chunk_w, chunk_h) = decoder.chunk_dimensions();
+    let level = match decoder.get_chunk_type() {
+        ChunkType::Tile => {
+            if chunk_w == 0 || chunk_h == 0 || chunk_w > MAX_TILE_SIZE || chunk_h > MAX_TILE_SIZE {
+                return Ok(None);
+            }
+            Level { ifd, width, height, striped: None, tile: (chunk_w, chunk_h), scaled: None }
+        }
+        ChunkType::Strip => {
+            if chunk_h == 0 {
+                return Ok(None);
+            }
+            Level { ifd, width, height, striped: Some(chunk_h.min(height)), tile: (TILE_SIZE, TILE_SIZE), scaled: None }
+        }
+    };
+    Ok(Some(level))
+}
+
+/// Convert a decoded chunk of `width` x `height` pixels to RGBA.
+fn to_rgba(data: DecodingResult, color: ColorType, width: u32, height: u32) -> Result<RgbaImage> {
+    let samples: Vec<u8> = match data {
+        DecodingResult::U8(v) => v,
+        DecodingResult::U16(v) => v.into_iter().map(|s| (s >> 8) as u8).collect(),
+        _ => return Err(anyhow!("Unsupported TIFF sample format")),
+    };
+    let channels = match color {
+        ColorType::Gray(8 | 16) => 1,
+        ColorType::GrayA(8 | 16) => 2,
+        // JPEG-compressed YCbCr is converted to RGB by the decoder
+        ColorType::RGB(8 | 16) | ColorType::YCbCr(8) => 3,
+        ColorType::RGBA(8 | 16) => 4,
+        other => return Err(anyhow!("Unsupported TIFF color type {:?}", other)),
+    };
+    let pixel_count = width as usize * height as usize;
+    if samples.len() < pixel_count * channels {
+        return Err(anyhow!("TIFF chunk is smaller than {}x{}", width, height));
+    }
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+    for px in samples.chunks_exact(channels).take(pixel_count) {
+        match px {
+            [l] => rgba.extend_from_slice(&[*l, *l, *l, 255]),
+            [l, a] => rgba.extend_from_slice(&[*l, *l, *l, *a]),
+            [r, g, b] => rgba.extend_from_slice(&[*r, *g, *b, 255]),
+            _ => rgba.extend_from_slice(px),
+        }
+    }
+    ImageBuffer::from_raw(width, height, rgba).ok_or_else(|| anyhow!("Bad TIFF chunk"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use image::GenericImageView;
+
+    /// One IFD of the hand-written BigTIFF: tiled when `tile` is set, otherwise
+    /// a single strip per `rows_per_strip` rows.
+    struct TestIfd {
+        width: u32,
+        height: u32,
+        tile: Option<u32>,
+        rows_per_strip: u32,
+    }
+
+    fn pixel(x: u32, y: u32, width: u32) -> [u8; 3] {
+        // Coordinates as a fraction of the width, so every level looks the same
+        [(x * 255 / width) as u8, (y * 255 / width) as u8, 77]
+    }
+
+    /// Write an uncompressed RGB BigTIFF, which the `tiff` encoder can't do with tiles.
+    fn write_bigtiff(path: &Path, ifds: &[TestIfd]) {
+        let mut out: Vec<u8> = b"II".to_vec();
+        out.extend_from_slice(&43u16.to_le_bytes());
+        out.extend_from_slice(&8u16.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        let first_ifd_pos = out.len();
+        out.extend_from_slice(&0u64.to_le_bytes());
+
+        let mut link_pos = first_ifd_pos;
+        for ifd in ifds {
+            // Pixel data of each chunk
+            let (chunk_w, chunk_h) = match ifd.tile {
+                Some(t) => (t, t),
+                None => (ifd.width, ifd.rows_per_strip),
+            };
+            let (cols, rows) = (ifd.width.div_ceil(chunk_w), ifd.height.div_ceil(chunk_h));
+            let (mut offsets, mut counts) = (Vec::new(), Vec::new());
+            for row in 0..rows {
+                for col in 0..cols {
+                    offsets.push(out.len() as u64);
+                    let start = out.len();
+                    let rows_here = if ifd.tile.is_some() { chunk_h } else { chunk_h.min(ifd.height - row * chunk_h) };
+                    for cy in 0..rows_here {
+                        for cx in 0..chunk_w {
+                            let (x, y) = (col * chunk_w + cx, row * chunk_h + cy);
+                            let value = if x < ifd.width && y < ifd.height { pixel(x, y, ifd.width) } else { [0; 3] };
+                            out.extend_from_slice(&value);
+                        }
+                    }
+                    counts.push((out.len() - start) as u64);
+                }
+            }
+            // Out-of-line arrays
+            let array = |out: &mut Vec<u8>, values: &[u64]| {
+                let pos = out.len() as u64;
+                for v in values {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+                pos
+            };
+            let (offsets_pos, counts_pos) = (array(&mut out, &offsets), array(&mut out, &counts));
+
+            // (tag, type, count, value or offset); types: 3 SHORT, 4 LONG, 16 LONG8.
+            // Values up to 8 bytes are stored inline, like the three 8-bit samples here
+            let mut entries: Vec<(u16, u16, u64, u64)> = vec![
+                (256, 4, 1, ifd.width as u64),
+                (257, 4, 1, ifd.height as u64),
+                (258, 3, 3, 8 | 8 << 16 | 8 << 32),
+                (259, 3, 1, 1),
+                (262, 3, 1, 2),
+                (277, 3, 1, 3),
+                (284, 3, 1, 1),
+            ];
+            let (offsets_tag, counts_tag) = if let Some(t) = ifd.tile {
+                entries.push((322, 4, 1, t as u64));
+                entries.push((323, 4, 1, t as u64));
+                (324, 325)
+            } else {
+                entries.push((278, 4, 1, ifd.rows_per_strip as u64));
+                (273, 279)
+            };
+            let inline = offsets.len() == 1;
+            entries.push((offsets_tag, 16, offsets.len() as u64, if inline { offsets[0] } else { offsets_pos }));
+            entries.push((counts_tag, 16, counts.len() as u64, if inline { counts[0] } else { counts_pos }));
+            entries.sort();
+
+            let ifd_pos = out.len() as u64;
+            out[link_pos..link_pos + 8].copy_from_slice(&ifd_pos.to_le_bytes());
+            out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+            for (tag, kind, count, value) in entries {
+                out.extend_from_slice(&tag.to_le_bytes());
+                out.extend_from_slice(&kind.to_le_bytes());
+                out.extend_from_slice(&count.to_le_bytes());
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            link_pos = out.len();
+            out.extend_from_slice(&0u64.to_le_bytes());
+        }
+        std::fs::write(path, out).unwrap();
+    }
+
+    #[test]
+    fn test_pyramidal_bigtiff() {
+        let dir = scratch_dir("slide");
+        let path = dir.join("slide.svs");
+        write_bigtiff(
+            &path,
+            &[
+                TestIfd { width: 600, height: 400, tile: Some(256), rows_per_strip: 0 },
+                // A label image is not part of the pyramid
+                TestIfd { width: 100, height: 100, tile: None, rows_per_strip: 100 },
+                TestIfd { width: 150, height: 100, tile: None, rows_per_strip: 7 },
+            ],
+        );
+
+        let source = TiffSource::open(&path).unwrap();
+        assert_eq!(source.size(), (600, 400));
+        assert_eq!(source.level_count(), 2);
+        assert_eq!(source.level_size(1), (150, 100));
+        assert_eq!(source.tile_size(0), (256, 256));
+
+        // An edge tile of the tiled level, without the padding
+        let tile = source.tile(0, 2, 1).unwrap();
+        assert_eq!(tile.dimensions(), (600 - 512, 400 - 256));
+        assert_eq!(tile.get_pixel(3, 5).0[..3], pixel(515, 261, 600));
+        assert!(source.tile(0, 3, 0).is_err());
+
+        // Striped levels are cut into squares across strip boundaries
+        let tile = source.tile(1, 0, 0).unwrap();
+        assert_eq!(tile.dimensions(), (150, 100));
+        assert_eq!(tile.get_pixel(149, 50).0[..3], pixel(149, 50, 150));
+
+        let overview = source.overview(120).unwrap();
+        assert_eq!(overview.dimensions(), (120, 80));
+    }
+
+    #[test]
+    fn test_made_up_levels() {
+        let dir = scratch_dir("scan");
+
+        // A scan stored at full resolution only: 1200 -> 600 -> 300, which fits a tile
+        let path = dir.join("map.tif");
+        write_bigtiff(&path, &[TestIfd { width: 1200, height: 800, tile: Some(256), rows_per_strip: 0 }]);
+        let source = TiffSource::open(&path).unwrap();
+        assert_eq!(source.level_count(), 3);
+        assert_eq!(source.level_size(1), (600, 400));
+        assert_eq!(source.level_size(2), (300, 200));
+        let tile = source.tile(1, 1, 0).unwrap();
+        assert_eq!(tile.dimensions(), (600 - 512, 400));
+        let expected = pixel(1040, 200, 1200);
+        let got = tile.get_pixel(8, 100).0;
+        assert!(got.iter().zip(expected).all(|(a, b)| a.abs_diff(b) <= 2), "{:?} vs {:?}", got, expected);
+        assert!(source.tile(2, 1, 0).is_err());
+
+        // Strips are read once per made-up tile, not once per stored tile
+        let path = dir.join("strips.tif");
+        write_bigtiff(&path, &[TestIfd { width: 1100, height: 300, tile: None, rows_per_strip: 7 }]);
+        let source = TiffSource::open(&path).unwrap();
+        assert_eq!(source.level_count(), 3);
+        assert_eq!(source.level_size(2), (275, 75));
+        let tile = source.tile(1, 1, 0).unwrap();
+        assert_eq!(tile.dimensions(), (550 - 512, 150));
+        assert!(tile.get_pixel(37, 149).0[0] >= 250);
+    }
+}
@@ -0,0 +1,132 @@
+//! Saving the current view, as zoomed and panned, to a PNG.
+
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+/// An offscreen texture to render a frame into and read back.
+pub struct Target {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl Target {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Snapshot Target"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view, width, height, format }
+    }
+
+    /// Copy the rendered pixels back after `encoder`'s commands, and wait for them.
+    pub fn read(&self, device: &wgpu::Device, queue: &wgpu::Queue, mut encoder: wgpu::CommandEncoder) -> Result<image::RgbaImage> {
+        let bgra = match self.format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            other => return Err(anyhow!("Can't read back {:?} frames", other)),
+        };
+        // Rows of a buffer copy must be aligned to 256 bytes
+        let row = self.width * 4;
+        let padded_row = row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Snapshot Readback"),
+            size: padded_row as u64 * self.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d { width: self.width, height: self.height, depth_or_array_layers: 1 },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let data = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((row * self.height) as usize);
+        for line in data.chunks(padded_row as usize) {
+            pixels.extend_from_slice(&line[..row as usize]);
+        }
+        drop(data);
+        buffer.unmap();
+        if bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(self.width, self.height, pixels).ok_or_else(|| anyhow!("Snapshot size mismatch"))
+    }
+}
+
+/// Output size for a snapshot `width` pixels wide (the window's when `None`),
+/// keeping the window's aspect ratio and staying within `max` on both sides.
+pub fn size(window: (u32, u32), width: Option<u32>, max: u32) -> (u32, u32) {
+    let (ww, wh) = (window.0.max(1) as f64, window.1.max(1) as f64);
+    let mut w = width.unwrap_or(window.0).max(1) as f64;
+    let mut h = w * wh / ww;
+    let over = (w.max(h) / max as f64).max(1.0);
+    w /= over;
+    h /= over;
+    ((w.round() as u32).max(1), (h.round() as u32).max(1))
+}
+
+/// `IMG_0001_view.png` next to the image, numbered to not replace an earlier snapshot.
+pub fn output_path(path: &Path) -> PathBuf {
+    output_path_named(path, "view")
+}
+
+/// `IMG_0001_{suffix}.png` next to the image, numbered like `output_path`.
+pub fn output_path_named(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let mut candidate = path.with_file_name(format!("{}_{}.png", stem, suffix));
+    let mut n = 2;
+    while candidate.exists() {
+        candidate = path.with_file_name(format!("{}_{}_{}.png", stem, suffix, n));
+        n += 1;
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_size_and_path() {
+        assert_eq!(size((1600, 1000), None, 8192), (1600, 1000));
+        assert_eq!(size((1600, 1000), Some(4000), 8192), (4000, 2500));
+        assert_eq!(size((1600, 1000), Some(16000), 8192), (8192, 5120));
+
+        let dir = scratch_dir("snapshot");
+        let image = dir.join("IMG_1.nef");
+        assert_eq!(output_path(&image), dir.join("IMG_1_view.png"));
+        std::fs::write(dir.join("IMG_1_view.png"), b"").unwrap();
+        assert_eq!(output_path(&image), dir.join("IMG_1_view_2.png"));
+        assert_eq!(output_path_named(&image, "compare"), dir.join("IMG_1_compare.png"));
+    }
+}
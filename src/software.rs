@@ -0,0 +1,99 @@
+//! Drawing without a GPU, for VMs and old machines where no wgpu backend works: the
+//! image fitted to the window, scaled on the CPU and copied to it with softbuffer.
+//! Overlays, zooming and editing need the GPU and aren't available this way.
+
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage, RgbImage};
+use std::num::NonZeroU32;
+use winit::window::Window;
+
+/// Shown around the image, as on the GPU path before a theme is applied
+const BACKGROUND: [u8; 3] = [50, 50, 50];
+
+pub struct Canvas<'w> {
+    _context: softbuffer::Context<&'w Window>,
+    surface: softbuffer::Surface<&'w Window, &'w Window>,
+    image: Option<DynamicImage>,
+    /// `image` fitted to the last window size drawn, with that size
+    scaled: Option<((u32, u32), RgbImage)>,
+}
+
+impl<'w> Canvas<'w> {
+    pub fn new(window: &'w Window) -> Result<Self> {
+        let context = softbuffer::Context::new(window).map_err(|e| anyhow!("{}", e))?;
+        let surface = softbuffer::Surface::new(&context, window).map_err(|e| anyhow!("{}", e))?;
+        Ok(Self { _context: context, surface, image: None, scaled: None })
+    }
+
+    pub fn set_image(&mut self, image: DynamicImage) {
+        self.image = Some(image);
+        self.scaled = None;
+    }
+
+    /// Draw the image fitted to a window of `width` x `height` pixels.
+    pub fn draw(&mut self, width: u32, height: u32) -> Result<()> {
+        let (Some(w), Some(h)) = (NonZeroU32::new(width), NonZeroU32::new(height)) else {
+            return Ok(());
+        };
+        self.surface.resize(w, h).map_err(|e| anyhow!("{}", e))?;
+        if self.scaled.as_ref().map(|(size, _)| *size) != Some((width, height)) {
+            self.scaled = self.image.as_ref().map(|image| ((width, height), fit(image, width, height)));
+        }
+        let mut buffer = self.surface.buffer_mut().map_err(|e| anyhow!("{}", e))?;
+        compose(&mut buffer, width, height, self.scaled.as_ref().map(|(_, image)| image));
+        buffer.present().map_err(|e| anyhow!("{}", e))
+    }
+}
+
+/// `image` scaled to fit inside `width` x `height`, keeping its aspect ratio. Small
+/// images aren't enlarged.
+pub fn fit(image: &DynamicImage, width: u32, height: u32) -> RgbImage {
+    if image.width() <= width && image.height() <= height {
+        image.to_rgb8()
+    } else {
+        image.resize(width, height, FilterType::Triangle).to_rgb8()
+    }
+}
+
+/// Fill `buffer`, a `width` x `height` frame of 0RGB pixels, with `image` centered on
+/// the background.
+pub fn compose(buffer: &mut [u32], width: u32, height: u32, image: Option<&RgbImage>) {
+    let pack = |[r, g, b]: [u8; 3]| ((r as u32) << 16) | ((g as u32) << 8) | b as u32;
+    buffer.fill(pack(BACKGROUND));
+    let Some(image) = image else {
+        return;
+    };
+    let (w, h) = (image.width().min(width), image.height().min(height));
+    let (left, top) = ((width - w) / 2, (height - h) / 2);
+    for y in 0..h {
+        let row = ((top + y) * width + left) as usize;
+        for (x, pixel) in buffer[row..row + w as usize].iter_mut().enumerate() {
+            *pixel = pack(image.get_pixel(x as u32, y).0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_fit_and_compose() {
+        let wide = DynamicImage::ImageRgb8(RgbImage::from_pixel(400, 100, Rgb([255, 0, 0])));
+        let fitted = fit(&wide, 100, 100);
+        assert_eq!(fitted.dimensions(), (100, 25));
+        assert_eq!(fit(&wide, 800, 600).dimensions(), (400, 100));
+
+        let mut buffer = vec![0; 100 * 100];
+        compose(&mut buffer, 100, 100, Some(&fitted));
+        let background = 0x323232;
+        // Centered vertically with the background above and below
+        assert_eq!(buffer[50 * 100 + 50], 0xFF0000);
+        assert_eq!(buffer[10 * 100 + 50], background);
+        assert_eq!(buffer[90 * 100 + 50], background);
+
+        compose(&mut buffer, 100, 100, None);
+        assert!(buffer.iter().all(|&p| p == background));
+    }
+}
@@ -1,98 +1,42 @@
 use winit::window::Window;
-use wgpu::util::DeviceExt;
+use crate::navigator::Collection;
+use crate::renderer::Camera;
 use crate::texture;
-use glam::{Mat4, Vec3};
 use std::path::PathBuf;
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
-}
-
-impl Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-            ],
-        }
-    }
-}
-
-const VERTICES: &[Vertex] = &[
-    Vertex { position: [-1.0, 1.0, 0.0], tex_coords: [0.0, 0.0] },
-    Vertex { position: [-1.0, -1.0, 0.0], tex_coords: [0.0, 1.0] },
-    Vertex { position: [1.0, -1.0, 0.0], tex_coords: [1.0, 1.0] },
-    Vertex { position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0] },
-];
-
-const INDICES: &[u16] = &[
-    0, 1, 2,
-    0, 2, 3,
-];
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct CameraUniform {
-    view_proj: [[f32; 4]; 4],
-    scale: [f32; 2],
-    padding: [f32; 2], // Padding to align to 16 bytes (mat4 is 64, vec2 is 8, need 8 more)
-}
-
-impl CameraUniform {
-    fn new() -> Self {
-        Self {
-            view_proj: Mat4::IDENTITY.to_cols_array_2d(),
-            scale: [1.0, 1.0],
-            padding: [0.0, 0.0],
-        }
-    }
-
-    fn update_view_proj(&mut self, camera: &Camera, image_aspect: f32) {
-        let view = Mat4::look_at_rh(
-            Vec3::new(camera.x, camera.y, 1.0),
-            Vec3::new(camera.x, camera.y, 0.0),
-            Vec3::Y,
-        );
-        
-        let proj = Mat4::orthographic_rh(
-            -camera.aspect * camera.zoom, 
-            camera.aspect * camera.zoom, 
-            -camera.zoom, 
-            camera.zoom, 
-            0.1, 
-            100.0
-        );
-        
-        self.view_proj = (proj * view).to_cols_array_2d();
-        
-        // If image_aspect > 1.0 (wider), we scale X.
-        // If image_aspect < 1.0 (taller), we scale Y?
-        // Actually, let's just make the quad size match the aspect ratio.
-        // Quad is 2x2 (-1 to 1).
-        // We want it to be (2*aspect) x 2.
-        self.scale = [image_aspect, 1.0];
-    }
-}
+/// Longest scale bar drawn over geo-referenced images, in window pixels.
+const SCALE_BAR_MAX_WIDTH: f64 = 160.0;
+/// Window pixels the map moves per arrow key press.
+const MAP_PAN_STEP: f32 = 100.0;
+/// How long a status message stays in the title bar.
+const STATUS_DURATION: std::time::Duration = std::time::Duration::from_secs(4);
+/// Files and folders kept in the recent list
+const MAX_RECENT: usize = 20;
+/// Second click within this long of the first makes a double click
+const DOUBLE_CLICK: std::time::Duration = std::time::Duration::from_millis(400);
+/// How far, in logical pixels, the pointer may move during a click
+const CLICK_SLOP: f32 = 4.0;
+/// Share of the window width at each side that steps through images with `edge_click`
+const EDGE_ZONE: f32 = 0.2;
+/// Size of the crop corner handles in window pixels.
+const CROP_HANDLE: f32 = 8.0;
+/// How close to the wipe divider, in logical pixels, a press grabs it
+const WIPE_GRAB: f32 = 12.0;
+/// How often the automatic theme is re-evaluated, for the `sun` mode.
+const THEME_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
-struct Camera {
-    x: f32,
-    y: f32,
-    zoom: f32,
-    aspect: f32,
+/// An operation held back until the user confirms it.
+enum PendingAction {
+    Delete(PathBuf),
+    SaveNote(String),
+    SaveDetails { title: String, rights: String, keywords: String },
+    SaveRating { rating: i32, pick: bool },
+    ExportRedacted,
+    ExportCrop,
+    ExportClean,
+    SaveRotation,
+    /// Trash the rest of a group of duplicates
+    TrashDuplicates(Vec<PathBuf>),
 }
 
 pub struct State<'a> {
@@ -100,65 +44,199 @@ pub struct State<'a> {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
+    /// Present modes the surface offers
+    present_modes: Vec<wgpu::PresentMode>,
+    /// Set when the driver resets or the GPU the device was on goes away
+    device_lost: std::sync::Arc<std::sync::atomic::AtomicBool>,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub window: &'a Window,
-    pub render_pipeline: wgpu::RenderPipeline,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub num_indices: u32,
+    renderer: crate::renderer::Renderer,
     pub diffuse_bind_group: wgpu::BindGroup,
     pub diffuse_texture: texture::Texture,
-    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// The rows of a large image still to be copied into `diffuse_texture`
+    upload: Option<texture::Upload>,
+    /// Textures of earlier images, reused for the next one of the same size
+    texture_pool: texture::TexturePool,
     
     camera: Camera,
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
+    /// Preview LUT and its name, applied while `lut_enabled`
+    lut: Option<(String, crate::lut::Lut)>,
+    lut_enabled: bool,
+    /// Before/after split as a fraction of the window width, the original on the left
+    before_after: Option<f32>,
+    dragging_before_after: bool,
+    /// The whole image shows unadjusted while the key is held
+    showing_original: bool,
     
     mouse_pressed: bool,
     last_mouse_pos: Option<(f64, f64)>,
+    touches: crate::touch::Touches,
+    /// A flick, edge click or mouse button waiting to be turned into navigation, forward when true
+    step: Option<bool>,
+    middle_pressed: bool,
+    /// Where and when the left button last went down, to tell clicks and double clicks
+    last_press: Option<((f64, f64), std::time::Instant)>,
     image_aspect: f32,
     
     // UI Data
     load_time: std::time::Duration,
-    memory_usage: u64,
+    /// What is held in memory, as of the last update
+    memory: crate::memory::Usage,
+    frame_times: crate::stats::FrameTimes,
     exif_data: std::collections::HashMap<String, String>,
+    /// The pixel format the current image was decoded to
+    decoded_color: image::ColorType,
+    properties: Option<crate::properties::Properties>,
+    xmp_data: crate::xmp::XmpData,
+    /// Title and copyright entered so far while editing the metadata
+    details_draft: Vec<String>,
+    /// Batch conversion running in the background, and the folder it writes to
+    conversion: Option<(PathBuf, std::sync::Arc<crate::convert::Progress>)>,
+    /// Folder being hashed for duplicates, then the groups found being reviewed
+    duplicate_scan: Option<std::sync::Arc<crate::duplicates::Scan>>,
+    duplicates: Option<crate::duplicates::Duplicates>,
+    prompt: Option<crate::prompt::Prompt>,
+    /// Panels and dialogs drawn with egui over the image
+    ui: crate::ui::Ui,
+    cache_panel: Option<crate::cache::CachePanel>,
+    confirmation: Option<crate::confirm::Confirmation<PendingAction>>,
+    // Confirmation of the last file operation, shown in the title for a moment
+    status: Option<(String, std::time::Instant)>,
     
     // Navigation
     navigator: crate::navigator::Navigator,
+
+    // Kiosk mode: destructive actions and settings changes are disabled
+    pub read_only: bool,
+
+    library: Option<crate::library::Library>,
+
+    // Filmstrip
+    quad_renderer: crate::quad::QuadRenderer,
+    thumbnailer: crate::thumbnail::Thumbnailer,
+    thumbnails: std::collections::HashMap<PathBuf, crate::thumbnail::GpuThumbnail>,
+    pub filmstrip_visible: bool,
+    grid: crate::grid::Grid,
+    map: crate::map::MapView,
+    redactor: crate::redact::Redactor,
+    crop: crate::crop::CropTool,
+    basket: crate::basket::Basket,
+    /// Images marked with F, mirrored from the library
+    favorites: std::collections::HashSet<PathBuf>,
+    /// What the hand-picked list being browsed is, e.g. "Basket", while the navigator is in one
+    /// Clockwise quarter turns shown but not yet saved
+    rotation: u32,
+    compare: Option<crate::compare::Compare>,
+    auto_pan: Option<crate::autopan::AutoPan>,
+    /// Color space of crops, redacted copies and saved views
+    export_space: crate::export::ColorSpace,
+    /// Physical pixels per logical pixel of the window's monitor; the filmstrip,
+    /// grid and overlay text are sized in logical pixels
+    scale_factor: f32,
+    /// Pan and zoom shared with other windows, and the camera last sent or received
+    sync: Option<crate::sync::Link>,
+    synced_camera: (f32, f32, f32),
+    /// File changes made in this session, for undo and redo
+    history: crate::history::History,
+    /// Camera to restore once this image from the last session has loaded
+    restored_camera: Option<(PathBuf, [f32; 3])>,
+    compare_textures: std::collections::HashMap<usize, crate::thumbnail::GpuThumbnail>,
+    compare_labels: Vec<crate::thumbnail::GpuThumbnail>,
+    /// View adjustments, and the captions of their sliders with the text each was rendered from
+    adjust: crate::adjust::AdjustTool,
+    adjust_labels: Vec<(String, crate::thumbnail::GpuThumbnail)>,
+    exposure: crate::exposure::Overlay,
+    /// Playback of an animated image, its frames going into `diffuse_texture`
+    animation: Option<crate::animation::Animation>,
+    /// Frame counter shown with an animation, and the text it was rendered from
+    animation_label: Option<(String, crate::thumbnail::GpuThumbnail)>,
+    image_size: (u32, u32),
+    // Streamed tiles drawn over the overview texture for very large images
+    tiled: Option<crate::pyramid::TiledImage>,
+    geo: Option<crate::geotiff::GeoInfo>,
+
+    settings: crate::config::Config,
+    background: wgpu::Color,
+    // Theme in effect, `None` for the fixed background
+    theme: Option<crate::theme::Theme>,
+    theme_override: Option<crate::theme::Theme>,
+    system_theme: Option<crate::theme::Theme>,
+    theme_checked: std::time::Instant,
+    matte: bool,
+    // Index into `framing::PRESETS` of the guide shown
+    framing: Option<usize>,
+    indexer: crate::index::Indexer,
+    /// Read-ahead metadata of the files in the folder
+    file_index: std::collections::HashMap<PathBuf, crate::index::FileInfo>,
+    // Folder and list length last handed to the indexer
+    indexed_listing: (Option<PathBuf>, usize),
+    // Capture times arrived that the list isn't sorted by yet
+    capture_times_changed: bool,
+    // Measured from thumbnails, for the clipping badges and grid histograms
+    exposure_stats: std::collections::HashMap<PathBuf, crate::badges::ExposureStats>,
+    /// The library folders, in library mode
+    catalog: Option<crate::catalog::Catalog>,
+    // The last library search, offered again
+    library_query: String,
+    /// The places last listed, to browse one of
+    places: crate::places::Places,
+    /// The gallery's grouping by capture date
+    timeline: crate::timeline::Timeline,
+    // The gallery's header titles, rendered as they come into view
+    header_labels: std::collections::HashMap<String, crate::thumbnail::GpuThumbnail>,
+}
+
+/// What outlives a lost device: the folder, library, view settings, and the image
+/// and view shown. See `State::hand_over`.
+pub struct Handover<'a> {
+    session: crate::session::Session,
+    pub window: &'a Window,
+    pub settings: crate::config::Config,
+    present_mode: wgpu::PresentMode,
+    navigator: crate::navigator::Navigator,
+    library: Option<crate::library::Library>,
+    favorites: std::collections::HashSet<PathBuf>,
+    basket: crate::basket::Basket,
+    history: crate::history::History,
+    read_only: bool,
+    sync: Option<crate::sync::Link>,
+    synced_camera: (f32, f32, f32),
+    auto_pan: Option<crate::autopan::AutoPan>,
+    adjust: crate::adjust::AdjustTool,
+    lut: Option<((String, crate::lut::Lut), bool)>,
+    exposure: crate::exposure::Overlay,
+    export_space: crate::export::ColorSpace,
+    theme_override: Option<crate::theme::Theme>,
+    matte: bool,
+    framing: Option<usize>,
+    filmstrip_visible: bool,
+    rotation: u32,
+    file_index: std::collections::HashMap<PathBuf, crate::index::FileInfo>,
+    exposure_stats: std::collections::HashMap<PathBuf, crate::badges::ExposureStats>,
+    catalog: Option<crate::catalog::Catalog>,
+    library_query: String,
+    places: crate::places::Places,
+    timeline: crate::timeline::Timeline,
 }
 
 impl<'a> State<'a> {
-    pub async fn new(window: &'a Window) -> State<'a> {
+    /// Set up rendering to `window`. Fails when no GPU backend, software adapters
+    /// included, can drive it.
+    pub async fn new(window: &'a Window, settings: crate::config::Config) -> anyhow::Result<State<'a>> {
         let size = window.inner_size();
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        });
-
-        let surface = instance.create_surface(window).unwrap();
+        let crate::gpu::Gpu { surface, adapter, device, queue } = crate::gpu::init(window).await?;
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::empty(),
-                    required_limits: adapter.limits(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let device_lost = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the device on purpose is reported too
+            if matches!(reason, wgpu::DeviceLostReason::Unknown) {
+                tracing::error!("GPU device lost: {}", message);
+                lost.store(true, std::sync::atomic::Ordering::Relaxed);
+                crate::redraw::wake();
+            }
+        });
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -173,7 +251,7 @@ impl<'a> State<'a> {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: crate::gpu::present_mode(settings.window.present_mode, &surface_caps.present_modes),
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
@@ -185,31 +263,11 @@ impl<'a> State<'a> {
         let diffuse_image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([50, 50, 50, 255])));
         let diffuse_texture = texture::Texture::from_image(&device, &queue, &diffuse_image, Some("diffuse_texture")).unwrap();
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let renderer = crate::renderer::Renderer::new(&device, &queue, config.format);
+        let texture_bind_group_layout = &renderer.texture_bind_group_layout;
 
         let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
+            layout: texture_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -230,160 +288,214 @@ impl<'a> State<'a> {
             zoom: 1.0,
             aspect: config.width as f32 / config.height as f32,
         };
-        
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera, 1.0);
-
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("camera_bind_group_layout"),
-        });
-
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
-
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+        let quad_renderer = crate::quad::QuadRenderer::new(&device, &queue, config.format, texture_bind_group_layout);
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let background = background_color(&settings.view.background);
 
-        let num_indices = INDICES.len() as u32;
+        let library = match crate::library::Library::open() {
+            Ok(lib) => Some(lib),
+            Err(e) => {
+                tracing::warn!("Failed to open library database: {:?}", e);
+                None
+            }
+        };
+        let mut navigator = crate::navigator::Navigator::new();
+        let mut favorites = std::collections::HashSet::new();
+        if let Some(lib) = &library {
+            navigator.private_paths = lib.private_paths().unwrap_or_default();
+            favorites.extend(lib.favorites().unwrap_or_default());
+        }
+        navigator.wrap = settings.navigation.wrap_around;
+        navigator.set_shuffle(settings.navigation.shuffle);
 
-        Self {
+        let scale_factor = window.scale_factor() as f32;
+        let mut ui = crate::ui::Ui::new(window, &device, config.format);
+        ui.panels.add(Box::new(crate::ui::DiagnosticsPanel::new(crate::gpu::describe(&adapter.get_info()))));
+        let mut thumbnailer = crate::thumbnail::Thumbnailer::new(
+            crate::cache::CacheKind::Thumbnails.dir(settings.cache.directory.as_deref()),
+            settings.cache.thumbnail_cache_mb * 1024 * 1024,
+        );
+        thumbnailer.set_size(crate::thumbnail::Thumbnailer::size_for(crate::grid::CELL * scale_factor));
+        let mut state = Self {
             window,
             surface,
             device,
             queue,
             config,
+            present_modes: surface_caps.present_modes,
+            device_lost,
             size,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
+            renderer,
             diffuse_bind_group,
             diffuse_texture,
-            texture_bind_group_layout,
+            upload: None,
+            texture_pool: texture::TexturePool::default(),
             camera,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
+            lut: None,
+            lut_enabled: false,
+            before_after: None,
+            dragging_before_after: false,
+            showing_original: false,
             mouse_pressed: false,
             last_mouse_pos: None,
+            touches: crate::touch::Touches::default(),
+            step: None,
+            middle_pressed: false,
+            last_press: None,
             image_aspect: 1.0,
             load_time: std::time::Duration::from_secs(0),
-            memory_usage: 0,
+            memory: crate::memory::Usage::default(),
+            frame_times: crate::stats::FrameTimes::default(),
             exif_data: std::collections::HashMap::new(),
-            navigator: crate::navigator::Navigator::new(),
-        }
+            decoded_color: image::ColorType::Rgba8,
+            properties: None,
+            xmp_data: crate::xmp::XmpData::default(),
+            details_draft: Vec::new(),
+            conversion: None,
+            duplicate_scan: None,
+            duplicates: None,
+            prompt: None,
+            ui,
+            cache_panel: None,
+            confirmation: None,
+            status: None,
+            navigator,
+            read_only: false,
+            library,
+            quad_renderer,
+            thumbnailer,
+            thumbnails: std::collections::HashMap::new(),
+            filmstrip_visible: false,
+            grid: crate::grid::Grid::new(),
+            map: crate::map::MapView::new(
+                &settings.map.tile_url,
+                crate::cache::CacheKind::MapTiles.dir(settings.cache.directory.as_deref()),
+            ),
+            redactor: crate::redact::Redactor::new(),
+            crop: crate::crop::CropTool::new(),
+            basket: crate::basket::Basket::new(),
+            favorites,
+            rotation: 0,
+            compare: None,
+            auto_pan: None,
+            export_space: settings.export.color_space,
+            scale_factor,
+            sync: None,
+            synced_camera: (0.0, 0.0, 0.0),
+            history: crate::history::History::default(),
+            restored_camera: None,
+            compare_textures: std::collections::HashMap::new(),
+            compare_labels: Vec::new(),
+            adjust: crate::adjust::AdjustTool::default(),
+            adjust_labels: Vec::new(),
+            exposure: crate::exposure::Overlay::Off,
+            animation: None,
+            animation_label: None,
+            image_size: (1, 1),
+            tiled: None,
+            geo: None,
+            background,
+            settings,
+            theme: None,
+            theme_override: None,
+            system_theme: window.theme().map(crate::theme::Theme::from_window),
+            theme_checked: std::time::Instant::now(),
+            matte: false,
+            framing: None,
+            indexer: crate::index::Indexer::new(),
+            file_index: std::collections::HashMap::new(),
+            indexed_listing: (None, 0),
+            capture_times_changed: false,
+            exposure_stats: std::collections::HashMap::new(),
+            catalog: None,
+            library_query: String::new(),
+            places: crate::places::Places::default(),
+            timeline: crate::timeline::Timeline::default(),
+            header_labels: std::collections::HashMap::new(),
+        };
+        state.apply_theme();
+        Ok(state)
     }
 
-    pub fn set_image(&mut self, loaded_image: crate::loader::LoadedImage) {
-        let img = loaded_image.image;
-        let texture = crate::texture::Texture::from_image(&self.device, &self.queue, &img, Some("Image")).unwrap();
-        
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
+    #[tracing::instrument(name = "show", level = "debug", skip_all)]
+    pub fn set_image(
+        &mut self,
+        loaded_image: crate::loader::LoadedImage,
+        tiles: Option<std::sync::Arc<dyn crate::pyramid::TileSource>>,
+    ) {
+        self.ui.loading = None;
+        if self.navigator.current_path.as_ref() != Some(&loaded_image.path) {
+            self.rotation = 0;
+        }
+        let rotation = self.rotation;
+        let frames = loaded_image.frames.into_iter().map(|mut frame| {
+            frame.image = match rotation {
+                1 => image::imageops::rotate90(&frame.image),
+                2 => image::imageops::rotate180(&frame.image),
+                3 => image::imageops::rotate270(&frame.image),
+                _ => frame.image,
+            };
+            frame
         });
+        self.animation = Some(frames.collect::<Vec<_>>())
+            .filter(|frames| !frames.is_empty())
+            .map(|frames| crate::animation::Animation::new(frames, std::time::Instant::now()));
+        self.animation_label = None;
+        let img = match self.rotation {
+            1 => loaded_image.image.rotate90(),
+            2 => loaded_image.image.rotate180(),
+            3 => loaded_image.image.rotate270(),
+            _ => loaded_image.image,
+        };
+        // The texture may only be an overview; sizes refer to the full image
+        let full_size = tiles.as_ref().map_or((img.width(), img.height()), |t| t.size());
+        // A reload of the same file (e.g. it changed on disk) keeps the view
+        let reloaded = self.navigator.current_path.as_ref() == Some(&loaded_image.path)
+            && self.image_size == full_size;
+        let layout = &self.renderer.texture_bind_group_layout;
+        let (texture, bind_group) = self.texture_pool.take(&self.device, layout, (img.width(), img.height()), Some("Image"));
+        self.decoded_color = img.color();
+        // Images not decoded to RGBA are converted, the rest move into the upload as they
+        // are. Large ones go up a band per frame; animations replace whole frames at once
+        let mut upload = crate::texture::Upload::new(img.into_rgba8());
+        let budget = if self.animation.is_some() { usize::MAX } else { crate::texture::UPLOAD_BAND_BYTES };
+        self.upload = (!upload.step(&self.queue, &texture, budget)).then_some(upload);
 
-        self.diffuse_texture = texture;
-        self.diffuse_bind_group = bind_group;
+        let old_texture = std::mem::replace(&mut self.diffuse_texture, texture);
+        let old_bind_group = std::mem::replace(&mut self.diffuse_bind_group, bind_group);
+        self.texture_pool.give(old_texture, old_bind_group);
+        self.tiled = tiles.map(crate::pyramid::TiledImage::new);
+        // Like tiled TIFFs, geo tags are only parsed in-process when decoding isn't sandboxed
+        self.geo = if crate::sandbox::enabled() { None } else { crate::geotiff::GeoInfo::read(&loaded_image.path) };
         
         // Update aspect ratio
-        self.image_aspect = img.width() as f32 / img.height() as f32;
+        self.image_aspect = full_size.0 as f32 / full_size.1 as f32;
+        self.image_size = full_size;
+        self.redactor.clear();
+        self.crop.clear();
+        if let Some(pan) = self.auto_pan.as_mut() {
+            pan.restart();
+        }
         
         // Reset camera
-        self.camera.x = 0.0;
-        self.camera.y = 0.0;
-        self.camera.zoom = 1.0;
+        if !reloaded {
+            self.camera.x = 0.0;
+            self.camera.y = 0.0;
+            self.camera.zoom = self.default_zoom();
+        }
+        if let Some((path, [x, y, zoom])) = self.restored_camera.take() {
+            if path == loaded_image.path {
+                (self.camera.x, self.camera.y, self.camera.zoom) = (x, y, zoom);
+            }
+        }
         
+        tracing::debug!("Showing {:?} at {}x{}", loaded_image.path, full_size.0, full_size.1);
         // Update UI data
         self.load_time = loaded_image.load_time;
-        self.memory_usage = (img.width() as u64 * img.height() as u64 * 4) / 1024 / 1024;
         self.exif_data = loaded_image.exif;
-        
+        self.xmp_data = loaded_image.xmp;
+
         // Update window title with info
         self.update_window_title();
         
@@ -391,151 +503,3488 @@ impl<'a> State<'a> {
         
         // Update file list if needed
         self.navigator.update_file_list(&loaded_image.path);
+        self.refresh_properties();
     }
     
-    pub fn get_next_image(&self) -> Option<PathBuf> {
-        self.navigator.get_next_image()
+    /// Note how long the latest frame took to update and draw, for the diagnostics panel.
+    pub fn frame_drawn(&mut self, time: std::time::Duration) {
+        self.frame_times.push(time);
     }
-    
-    pub fn get_prev_image(&self) -> Option<PathBuf> {
-        self.navigator.get_prev_image()
+
+    /// Whether the device is gone and the state has to be built again on a new one.
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.load(std::sync::atomic::Ordering::Relaxed)
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            
-            self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+    /// Switch to the next present mode the surface supports, to compare tearing and lag.
+    pub fn cycle_present_mode(&mut self) {
+        self.config.present_mode = crate::gpu::next_present_mode(self.config.present_mode, &self.present_modes);
+        self.surface.configure(&self.device, &self.config);
+        self.notify(format!("Present mode: {}", crate::gpu::describe_present_mode(self.config.present_mode)));
+        self.window.request_redraw();
+    }
+
+    /// Pan across every image at `speed` window pixels per second, in step with
+    /// the display refresh.
+    pub fn set_auto_pan(&mut self, speed: f32) {
+        let refresh = self.window.current_monitor().and_then(|m| m.refresh_rate_millihertz()).map(|mhz| mhz as f64 / 1000.0);
+        self.auto_pan = Some(crate::autopan::AutoPan::new(speed, refresh));
+        // Presenting on every vertical blank is what keeps the motion even
+        self.config.present_mode = wgpu::PresentMode::Fifo;
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.camera.zoom *= factor;
+        self.window.request_redraw();
+    }
+
+    /// Move the view by one window pixel, or one image pixel with `image_pixel`,
+    /// in screen directions (`dy` down). Only above 100%, where a pixel step is visible.
+    pub fn nudge(&mut self, dx: f32, dy: f32, image_pixel: bool) {
+        if self.image_pixels_per_screen_pixel() >= 1.0 {
+            self.notify("Zoom past 100% to nudge the view".to_string());
+            return;
         }
+        // The image spans 2 world units vertically, as does the window at zoom 1
+        let step = if image_pixel {
+            2.0 / self.image_size.1 as f32
+        } else {
+            2.0 * self.camera.zoom / self.size.height.max(1) as f32
+        };
+        self.camera.x += dx * step;
+        self.camera.y -= dy * step;
+        self.window.request_redraw();
     }
 
-    pub fn input(&mut self, event: &winit::event::WindowEvent) -> bool {
-        use winit::event::*;
-        match event {
-            WindowEvent::MouseInput {
-                state,
-                button: MouseButton::Left,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
-                true
-            }
-            WindowEvent::CursorMoved { position, .. } => {
-                if self.mouse_pressed {
-                    if let Some((last_x, last_y)) = self.last_mouse_pos {
-                        let dx = position.x - last_x;
-                        let dy = position.y - last_y;
-                        
-                        // Convert screen delta to camera space
-                        // Screen width corresponds to 2.0 * aspect * zoom
-                        let scale_x = (2.0 * self.camera.aspect * self.camera.zoom) / self.config.width as f32;
-                        let scale_y = (2.0 * self.camera.zoom) / self.config.height as f32;
-                        
-                        self.camera.x -= dx as f32 * scale_x;
-                        self.camera.y += dy as f32 * scale_y; // Y is inverted in screen coords vs world
-                        
-                        self.window.request_redraw();
-                    }
-                }
-                self.last_mouse_pos = Some((position.x, position.y));
-                true
+    /// Camera zoom for a freshly opened image, per the configured zoom mode.
+    fn default_zoom(&self) -> f32 {
+        match self.settings.view.zoom_mode {
+            crate::config::ZoomMode::Fit => self.fit_zoom(),
+            crate::config::ZoomMode::Actual => self.size.height as f32 / self.image_size.1 as f32,
+        }
+    }
+
+    /// Camera zoom showing the whole image as large as the window allows.
+    fn fit_zoom(&self) -> f32 {
+        // Leave room for the matte when it is shown
+        let width = if self.matte { self.settings.matte.width } else { 0.0 };
+        let (sx, sy) = crate::matte::framed_scale(self.image_size.0 as f32, self.image_size.1 as f32, width);
+        (sx * self.image_aspect / self.camera.aspect).max(sy)
+    }
+
+    /// Zoom to 100% about window pixel `at`, or back to fitting the window when already there.
+    fn toggle_actual_size(&mut self, (x, y): (f64, f64)) {
+        let image_pixels = self.image_pixels_per_screen_pixel();
+        if (image_pixels - 1.0).abs() < 0.01 {
+            (self.camera.x, self.camera.y) = (0.0, 0.0);
+            self.camera.zoom = self.fit_zoom();
+        } else {
+            let (w, h) = (self.size.width as f32, self.size.height as f32);
+            self.camera.zoom_about(1.0 / image_pixels.max(1e-6), w, h, x as f32, y as f32);
+        }
+        self.window.request_redraw();
+    }
+
+    /// Double clicks switch between fit and 100%. With `edge_click`, a click on the left or
+    /// right fifth of an image at its default zoom steps back or forward.
+    fn click(&mut self, state: winit::event::ElementState) {
+        let Some(position) = self.last_mouse_pos else {
+            return;
+        };
+        let now = std::time::Instant::now();
+        let slop = (CLICK_SLOP * self.scale_factor) as f64;
+        let near = |(x, y): (f64, f64)| (x - position.0).abs() <= slop && (y - position.1).abs() <= slop;
+        if state == winit::event::ElementState::Pressed {
+            if self.last_press.is_some_and(|(p, at)| near(p) && now.duration_since(at) <= DOUBLE_CLICK) {
+                self.last_press = None;
+                self.toggle_actual_size(position);
+            } else {
+                self.last_press = Some((position, now));
             }
-            WindowEvent::MouseWheel { delta, .. } => {
-                let scroll = match delta {
-                    MouseScrollDelta::LineDelta(_, y) => *y,
-                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 100.0, // Arbitrary scaling
-                };
-                
-                if scroll > 0.0 {
-                    self.camera.zoom *= 0.9;
-                } else {
-                    self.camera.zoom *= 1.1;
-                }
-                self.window.request_redraw();
-                true
+            return;
+        }
+        let clicked = self.last_press.is_some_and(|(p, _)| near(p));
+        if clicked && self.settings.navigation.edge_click && self.at_default_zoom() {
+            let x = position.0 as f32 / self.size.width.max(1) as f32;
+            self.step = match x {
+                x if x < EDGE_ZONE => Some(false),
+                x if x > 1.0 - EDGE_ZONE => Some(true),
+                _ => None,
+            };
+            if self.step.is_some() {
+                self.last_press = None;
             }
-            _ => false,
         }
     }
 
-    pub fn update(&mut self) {
-        self.camera_uniform.update_view_proj(&self.camera, self.image_aspect);
-        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+    /// Frame the image with the configured matte, refitting it to the window.
+    pub fn toggle_matte(&mut self) {
+        self.matte = !self.matte;
+        self.camera.x = 0.0;
+        self.camera.y = 0.0;
+        self.camera.zoom = self.default_zoom();
+        self.window.request_redraw();
+    }
+
+    /// Next aspect-ratio guide, then none.
+    pub fn cycle_framing(&mut self) {
+        self.framing = match self.framing {
+            None => Some(0),
+            Some(i) if i + 1 < crate::framing::PRESETS.len() => Some(i + 1),
+            Some(_) => None,
+        };
+        self.window.request_redraw();
         self.update_window_title();
     }
-    
-    fn update_window_title(&self) {
-        let zoom_pct = (1.0 / self.camera.zoom * 100.0) as i32;
-        let mut title = format!("Momemtum - Zoom: {}%", zoom_pct);
-        
-        if let Some(path) = &self.navigator.current_path {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                title.push_str(&format!(" | {}", name));
-            }
+
+    /// Folder to watch for changes, and whether subfolders are included.
+    pub fn watch_target(&self) -> Option<(PathBuf, bool)> {
+        Some((self.navigator.root()?.to_path_buf(), self.navigator.recursive))
+    }
+
+    /// Bring the folder listing up to date after files changed on disk.
+    /// Returns the image to load: the current one if it was modified, or a
+    /// neighbour if it was removed.
+    pub fn apply_file_changes(&mut self, changed: &std::collections::HashSet<PathBuf>) -> Option<PathBuf> {
+        let images: Vec<&PathBuf> = changed.iter().filter(|p| crate::navigator::is_supported_image(p)).collect();
+        if images.is_empty() {
+            return None;
         }
-        
-        if self.load_time.as_millis() > 0 {
-            title.push_str(&format!(" | Load: {:.0}ms", self.load_time.as_secs_f64() * 1000.0));
+        for path in &images {
+            self.thumbnails.remove(*path);
         }
-        
-        if self.memory_usage > 0 {
-            title.push_str(&format!(" | Memory: ~{}MB", self.memory_usage));
+        let neighbour = self.navigator.get_next_image().or_else(|| self.navigator.get_prev_image());
+        self.navigator.refresh();
+        self.window.request_redraw();
+
+        let current = self.navigator.current_path.clone()?;
+        if !changed.contains(&current) {
+            None
+        } else if current.exists() {
+            Some(current)
+        } else {
+            neighbour.filter(|p| p.exists())
         }
-        
-        if let Some(model) = self.exif_data.get("Model") {
-            title.push_str(&format!(" | {}", model));
+    }
+
+    pub fn set_navigation(&mut self, sort: crate::navigator::SortOrder, recursive: bool) {
+        self.navigator.sort = sort;
+        self.navigator.recursive = recursive;
+    }
+
+    /// Switch to the next sort order; navigation follows the new order right away.
+    pub fn cycle_sort(&mut self) {
+        self.navigator.set_sort(self.navigator.sort.next());
+        self.update_window_title();
+    }
+
+    pub fn toggle_recursive(&mut self) {
+        self.navigator.set_recursive(!self.navigator.recursive);
+        self.update_window_title();
+    }
+
+    pub fn set_shuffle(&mut self, enabled: bool) {
+        self.navigator.set_shuffle(enabled);
+        self.update_window_title();
+    }
+
+    pub fn toggle_shuffle(&mut self) {
+        self.set_shuffle(!self.navigator.shuffling());
+    }
+
+    /// Pick the background for the manual or automatic theme.
+    fn apply_theme(&mut self) {
+        self.theme_checked = std::time::Instant::now();
+        let automatic = crate::theme::automatic(&self.settings.view, self.system_theme, std::time::SystemTime::now());
+        let theme = self.theme_override.or(automatic);
+        if theme == self.theme {
+            return;
         }
-        
-        self.window.set_title(&title);
+        self.theme = theme;
+        let color = match theme {
+            Some(theme) => theme.background(&self.settings.view),
+            None => &self.settings.view.background,
+        };
+        self.background = background_color(color);
+        self.window.set_theme(theme.map(crate::theme::Theme::to_window));
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    pub fn system_theme_changed(&mut self, theme: winit::window::Theme) {
+        self.system_theme = Some(crate::theme::Theme::from_window(theme));
+        self.apply_theme();
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+    /// Automatic, then dark, then light.
+    pub fn cycle_theme(&mut self) {
+        use crate::theme::Theme;
+        self.theme_override = match self.theme_override {
+            None => Some(Theme::Dark),
+            Some(Theme::Dark) => Some(Theme::Light),
+            Some(Theme::Light) => None,
+        };
+        self.apply_theme();
+        let text = match (self.theme_override, self.theme) {
+            (Some(theme), _) => format!("Theme: {}", theme.label()),
+            (None, Some(theme)) => format!("Theme: automatic ({})", theme.label()),
+            (None, None) => "Theme: fixed background".to_string(),
+        };
+        self.notify(text);
+    }
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.1,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+    /// sRGB, then Display P3, then Adobe RGB, for this session.
+    pub fn cycle_export_space(&mut self) {
+        self.export_space = self.export_space.next();
+        self.notify(format!("Export color space: {}", self.export_space.label()));
+    }
+
+    pub fn sync_enabled(&self) -> bool {
+        self.sync.is_some()
+    }
+
+    /// Join or leave the windows that pan and zoom together.
+    pub fn set_sync(&mut self, link: Option<crate::sync::Link>) {
+        let on = link.is_some();
+        self.sync = link;
+        // Bring the others to this view straight away
+        self.synced_camera = (f32::NAN, 0.0, 0.0);
+        self.notify(format!("Pan and zoom sync across windows: {}", if on { "on" } else { "off" }));
+    }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    /// Show the view another synchronized window moved to.
+    pub fn apply_synced_view(&mut self, view: crate::sync::View) {
+        if self.sync.is_none() {
+            return;
         }
+        let (u, v) = view.center;
+        self.camera.x = (u * 2.0 - 1.0) * self.image_aspect;
+        self.camera.y = 1.0 - v * 2.0;
+        // The image spans 2 world units and its height in pixels; the window 2 * zoom and its height
+        self.camera.zoom = view.scale * self.size.height.max(1) as f32 / self.image_size.1.max(1) as f32;
+        self.synced_camera = (self.camera.x, self.camera.y, self.camera.zoom);
+        self.window.request_redraw();
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    /// Send the view to the other synchronized windows if it moved here.
+    fn publish_view(&mut self) {
+        let camera = (self.camera.x, self.camera.y, self.camera.zoom);
+        if camera == self.synced_camera {
+            return;
+        }
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        let center = self.camera.screen_to_uv(self.image_aspect, w, h, w / 2.0, h / 2.0);
+        let scale = self.camera.zoom * self.image_size.1 as f32 / h.max(1.0);
+        if let Some(sync) = self.sync.as_mut() {
+            sync.publish(crate::sync::View { center, scale });
+        }
+        self.synced_camera = camera;
+    }
 
-        Ok(())
+    /// What to restore on the next launch.
+    pub fn session(&self) -> crate::session::Session {
+        let size = self.window.inner_size();
+        let window = self.window.outer_position().ok().map(|position| crate::session::WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            maximized: self.window.is_maximized(),
+        });
+        let path = self.navigator.current_path.clone();
+        crate::session::Session {
+            camera: path.as_ref().map(|_| [self.camera.x, self.camera.y, self.camera.zoom]),
+            path,
+            window,
+            sort: self.navigator.sort,
+            compare: self.compare.as_ref().map(|c| c.paths.clone()).unwrap_or_default(),
+            compare_focus: self.compare.as_ref().map_or(0, |c| c.focus),
+        }
+    }
+
+    /// Pick up where the last session left off. Returns the image to load.
+    pub fn restore_session(&mut self, session: crate::session::Session) -> Option<PathBuf> {
+        self.navigator.sort = session.sort;
+        if session.compare.len() >= 2 {
+            self.open_compare(session.compare);
+            if let Some(compare) = self.compare.as_mut() {
+                compare.set_focus(session.compare_focus);
+            }
+        }
+        let path = session.path?;
+        self.restored_camera = session.camera.map(|camera| (path.clone(), camera));
+        Some(path)
+    }
+
+    /// Give up this state's device, keeping what isn't on the GPU for the state that
+    /// replaces it. The surface goes with it, as a window presents to one at a time.
+    pub fn hand_over(self) -> Handover<'a> {
+        Handover {
+            session: self.session(),
+            window: self.window,
+            settings: self.settings,
+            present_mode: self.config.present_mode,
+            navigator: self.navigator,
+            library: self.library,
+            favorites: self.favorites,
+            basket: self.basket,
+            history: self.history,
+            read_only: self.read_only,
+            sync: self.sync,
+            synced_camera: self.synced_camera,
+            auto_pan: self.auto_pan,
+            adjust: self.adjust,
+            lut: self.lut.map(|lut| (lut, self.lut_enabled)),
+            exposure: self.exposure,
+            export_space: self.export_space,
+            theme_override: self.theme_override,
+            matte: self.matte,
+            framing: self.framing,
+            filmstrip_visible: self.filmstrip_visible,
+            rotation: self.rotation,
+            file_index: self.file_index,
+            exposure_stats: self.exposure_stats,
+            catalog: self.catalog,
+            library_query: self.library_query,
+            places: self.places,
+            timeline: self.timeline,
+        }
+    }
+
+    /// Carry on from a state whose device was lost. Returns the image to load again.
+    pub fn take_over(&mut self, old: Handover<'a>) -> Option<PathBuf> {
+        self.navigator = old.navigator;
+        self.library = old.library;
+        self.favorites = old.favorites;
+        self.basket = old.basket;
+        self.history = old.history;
+        self.read_only = old.read_only;
+        self.sync = old.sync;
+        self.synced_camera = old.synced_camera;
+        self.auto_pan = old.auto_pan;
+        self.adjust = old.adjust;
+        self.exposure = old.exposure;
+        self.export_space = old.export_space;
+        self.theme_override = old.theme_override;
+        self.matte = old.matte;
+        self.framing = old.framing;
+        self.filmstrip_visible = old.filmstrip_visible;
+        self.rotation = old.rotation;
+        self.file_index = old.file_index;
+        self.exposure_stats = old.exposure_stats;
+        self.catalog = old.catalog;
+        self.library_query = old.library_query;
+        self.places = old.places;
+        self.timeline = old.timeline;
+        // The new gallery has no headers yet
+        self.timeline.invalidate();
+        if let Some(((name, lut), enabled)) = old.lut {
+            self.renderer.set_lut(&self.device, &self.queue, &lut);
+            self.lut = Some((name, lut));
+            self.lut_enabled = enabled;
+        }
+        // Auto-pan keeps to vsync; otherwise the mode switched to, if the new surface has it too
+        let present_mode = if self.auto_pan.is_some() { wgpu::PresentMode::Fifo } else { old.present_mode };
+        if self.present_modes.contains(&present_mode) {
+            self.config.present_mode = present_mode;
+            self.surface.configure(&self.device, &self.config);
+        }
+        self.apply_theme();
+        self.restore_session(old.session)
+    }
+
+    /// Resolve a file or folder given on the command line to the image to load.
+    pub fn open_path(&mut self, path: &std::path::Path) -> Option<PathBuf> {
+        self.remember_recent(path);
+        if path.is_dir() {
+            self.navigator.open_directory(path)
+        } else if crate::playlist::is_playlist(path) {
+            self.open_playlist(path)
+        } else {
+            Some(path.to_path_buf())
+        }
+    }
+
+    /// Add a file or folder that was opened to the recent list.
+    pub fn remember_recent(&self, path: &std::path::Path) {
+        let Some(lib) = &self.library else {
+            return;
+        };
+        if !path.exists() {
+            return;
+        }
+        let path = std::env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf());
+        if self.navigator.is_hidden(&path) {
+            return;
+        }
+        if let Err(e) = lib.record_recent(&path, MAX_RECENT) {
+            tracing::warn!("Failed to update recent files: {:?}", e);
+        }
+    }
+
+    /// List the files and folders opened lately, to pick one to open again.
+    pub fn show_recent(&mut self) {
+        let recent = self.library.as_ref().and_then(|lib| lib.recent().ok()).unwrap_or_default();
+        let items: Vec<(String, PathBuf)> = recent
+            .into_iter()
+            .filter(|p| p.exists() && !self.navigator.is_hidden(p))
+            .map(|p| {
+                let mut label = p.display().to_string();
+                if p.is_dir() {
+                    label.push(std::path::MAIN_SEPARATOR);
+                }
+                (label, p)
+            })
+            .collect();
+        if items.is_empty() {
+            self.notify("Nothing opened recently".to_string());
+            return;
+        }
+        self.ui.palette = Some(crate::ui::Palette::new("Recent", items));
+        self.window.request_redraw();
+    }
+
+    /// Find an image of the folder, or of the collection being browsed, by typing some
+    /// letters of its name.
+    pub fn quick_open(&mut self) {
+        if self.navigator.image_list.is_empty() {
+            return;
+        }
+        let items = (self.navigator.image_list.iter())
+            .map(|p| (self.navigator.display_name(p).unwrap_or_else(|| p.display().to_string()), p.clone()))
+            .collect();
+        self.ui.palette = Some(crate::ui::Palette::new("Go to image", items));
+        self.window.request_redraw();
+    }
+
+    /// What was picked from a palette since last asked, resolved to the image to load.
+    pub fn take_picked(&mut self) -> Option<PathBuf> {
+        match self.ui.take_picked()? {
+            (crate::ui::PaletteKind::Files, path) => self.open_path(&path),
+            (crate::ui::PaletteKind::Places, first) => self.open_place(&first),
+        }
+    }
+
+    /// Browse the images listed in a playlist file in place of a folder.
+    fn open_playlist(&mut self, path: &std::path::Path) -> Option<PathBuf> {
+        let paths = match crate::playlist::read(path) {
+            Ok(paths) => paths,
+            Err(e) => {
+                self.notify(format!("{:#}", e));
+                return None;
+            }
+        };
+        self.duplicates = None;
+        let first = self.navigator.open_playlist(Collection::Playlist, &paths);
+        if first.is_none() {
+            self.notify(format!("No images in {}", path.display()));
+        }
+        first
+    }
+
+    /// Save the images being browsed, folder or collection, as a playlist file.
+    /// Whether the playlist may be saved, saying why not when it can't, before asking where.
+    pub fn can_save_playlist(&mut self) -> bool {
+        if self.read_only {
+            self.notify("Saving playlists is disabled in kiosk mode".to_string());
+            return false;
+        }
+        if self.navigator.image_list.is_empty() {
+            self.notify("No images to save".to_string());
+            return false;
+        }
+        true
+    }
+
+    pub fn save_playlist(&mut self, path: &std::path::Path) {
+        if !self.can_save_playlist() {
+            return;
+        }
+        match crate::playlist::write(path, &self.navigator.image_list) {
+            Ok(()) => self.notify(format!("Saved {} images to {}", self.navigator.image_list.len(), path.display())),
+            Err(e) => self.notify(format!("{:#}", e)),
+        }
+    }
+
+    /// Open what was dropped on the window: one file or folder like on the command line,
+    /// several as a playlist to browse in place of the folder.
+    pub fn open_dropped(&mut self, paths: Vec<PathBuf>) -> Option<PathBuf> {
+        if let [path] = &paths[..] {
+            return self.open_path(path);
+        }
+        self.duplicates = None;
+        let first = self.navigator.open_playlist(Collection::Dropped, &paths);
+        match first {
+            Some(_) => self.notify(format!("Browsing {} dropped images", self.navigator.image_list.len())),
+            None => self.notify("None of the dropped files is an image".to_string()),
+        }
+        first
+    }
+
+    /// Put the current image's GPS position on the clipboard as decimal degrees.
+    pub fn copy_coordinates(&mut self) {
+        let Some((lat, lon)) = crate::gps::position(&self.exif_data) else {
+            self.notify("No GPS position in this image".to_string());
+            return;
+        };
+        let text = crate::gps::decimal(lat, lon);
+        match crate::remote::set_clipboard_text(&text) {
+            Ok(()) => self.notify(format!("Copied {}", text)),
+            Err(e) => self.notify(format!("Failed to copy coordinates: {:#}", e)),
+        }
+    }
+
+    /// Show where the current image was taken on OpenStreetMap, in the browser.
+    pub fn open_location(&mut self) {
+        let Some((lat, lon)) = crate::gps::position(&self.exif_data) else {
+            self.notify("No GPS position in this image".to_string());
+            return;
+        };
+        if self.read_only {
+            self.notify("Opening the browser is disabled in kiosk mode".to_string());
+            return;
+        }
+        if let Err(e) = crate::remote::open_in_browser(&crate::gps::map_url(lat, lon)) {
+            self.notify(format!("Failed to open the browser: {:#}", e));
+        }
+    }
+
+    /// The image or folder whose path or URL is on the clipboard.
+    pub fn pasted_path(&mut self) -> Option<PathBuf> {
+        let text = crate::remote::clipboard_text().unwrap_or_default();
+        let text = text.lines().next().unwrap_or_default().trim().trim_matches('"');
+        if crate::remote::is_url(text) {
+            return Some(PathBuf::from(text));
+        }
+        let path = std::path::Path::new(text);
+        if text.is_empty() || !path.exists() {
+            self.notify("No image path or URL on the clipboard".to_string());
+            return None;
+        }
+        self.open_path(path)
+    }
+
+    pub fn download_progress(&mut self, received: u64, total: Option<u64>) {
+        const MB: f64 = 1024.0 * 1024.0;
+        let text = match total.filter(|t| *t > 0) {
+            Some(total) => format!("Downloading: {}% of {:.1} MB", received * 100 / total, total as f64 / MB),
+            None => format!("Downloading: {:.1} MB", received as f64 / MB),
+        };
+        self.notify(text);
+    }
+
+    /// Tell the user about a problem found before the window was up, already logged.
+    pub fn show_error(&mut self, text: String) {
+        self.ui.toasts.push(crate::ui::Level::Error, text);
+    }
+
+    pub fn download_failed(&mut self, error: &str) {
+        tracing::warn!("Download failed: {}", error);
+        self.ui.toasts.push(crate::ui::Level::Error, format!("Download failed: {}", error));
+    }
+
+    /// Show how far the decode in flight has got, over the dimmed image it replaces.
+    pub fn load_progress(&mut self, stage: crate::progress::Stage, fraction: Option<f32>) {
+        self.ui.loading = Some((stage, fraction));
+    }
+
+    /// Say why `path` couldn't be shown; the previous image stays on screen.
+    pub fn load_failed(&mut self, path: &std::path::Path, error: &str) {
+        self.ui.loading = None;
+        tracing::warn!("Failed to decode {}: {}", path.display(), error);
+        let name = self.navigator.display_name(path).unwrap_or_else(|| path.display().to_string());
+        self.ui.toasts.push(crate::ui::Level::Error, format!("Failed to decode {}: {}", name, error));
+    }
+
+    /// The image after the current one, starting over at the first image.
+    pub fn get_next_image_wrapping(&mut self) -> Option<PathBuf> {
+        self.navigator.advance(true, true)
+    }
+
+    pub fn get_next_image(&mut self) -> Option<PathBuf> {
+        self.navigator.advance(true, self.navigator.wrap)
+    }
+    
+    pub fn get_prev_image(&mut self) -> Option<PathBuf> {
+        self.navigator.advance(false, self.navigator.wrap)
+    }
+
+    /// The first image of the list, or the last with `last`.
+    pub fn get_end_image(&self, last: bool) -> Option<PathBuf> {
+        let list = &self.navigator.image_list;
+        let end = if last { list.last() } else { list.first() };
+        end.filter(|p| Some(*p) != self.navigator.current_path.as_ref()).cloned()
+    }
+
+    /// Toggle the private mark on the current file, or on its folder if `folder` is set.
+    /// Returns the image to show instead when the current one just became hidden.
+    pub fn toggle_private(&mut self, folder: bool) -> Option<PathBuf> {
+        if self.read_only {
+            return None;
+        }
+        let lib = self.library.as_ref()?;
+        let current = self.navigator.current_path.clone()?;
+        let target = crate::library::canonical(if folder { current.parent()? } else { current.as_path() });
+
+        let private = !self.navigator.private_paths.contains(&target);
+        if let Err(e) = lib.set_private(&target, private) {
+            tracing::warn!("Failed to update private paths: {:?}", e);
+            return None;
+        }
+
+        let previous = self.navigator.image_list.clone();
+        self.navigator.private_paths = lib.private_paths().unwrap_or_default();
+        self.navigator.refresh();
+        self.update_window_title();
+        // Picked from what is left once the image, or its whole folder, is hidden
+        if private && !self.navigator.unlocked {
+            self.navigator.replacement(&previous)
+        } else {
+            None
+        }
+    }
+
+    /// Show the image turned a quarter clockwise, or counterclockwise. Returns
+    /// the image to reload with the new rotation.
+    pub fn rotate(&mut self, clockwise: bool) -> Option<PathBuf> {
+        let current = self.navigator.current_path.clone()?;
+        if self.tiled.is_some() {
+            self.notify("Tiled images can't be rotated".to_string());
+            return None;
+        }
+        self.rotation = (self.rotation + if clockwise { 1 } else { 3 }) % 4;
+        Some(current)
+    }
+
+    /// Write the shown rotation to the file, losslessly.
+    pub fn request_save_rotation(&mut self) {
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        if self.read_only || self.rotation == 0 {
+            return;
+        }
+        if !crate::rotate::supported(&path) {
+            self.notify("Only JPEGs can be rotated on disk".to_string());
+            return;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let question = format!("Rotate {} on disk?", name);
+        self.confirm(crate::confirm::Guard::MetadataWrite, question, PendingAction::SaveRotation);
+    }
+
+    fn save_rotation(&mut self) {
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        match crate::rotate::save(&path, self.rotation) {
+            // What is shown is now what the file says
+            Ok(_) => {
+                self.history.push(crate::history::Change::Rotated { path, quarter_turns: self.rotation });
+                self.rotation = 0;
+                self.notify("Rotation saved".to_string());
+            }
+            Err(e) => {
+                tracing::warn!("Failed to rotate {:?}: {:?}", path, e);
+                self.notify(format!("Could not rotate: {}", e));
+            }
+        }
+    }
+
+    pub fn compare_active(&self) -> bool {
+        self.compare.is_some()
+    }
+
+    /// Compare the basket, or else the current image and the ones after it.
+    pub fn toggle_compare(&mut self) {
+        if self.compare.take().is_some() {
+            self.compare_textures.clear();
+            self.compare_labels.clear();
+            self.mouse_pressed = false;
+            self.update_window_title();
+            self.window.request_redraw();
+            return;
+        }
+        let paths = if self.basket.len() >= 2 {
+            self.basket.paths()
+        } else {
+            let list = &self.navigator.image_list;
+            let start = self.navigator.current_path.as_ref().and_then(|c| list.iter().position(|p| p == c)).unwrap_or(0);
+            list.iter().skip(start).take(crate::compare::MAX_PANES).cloned().collect()
+        };
+        if paths.len() < 2 {
+            self.notify("Nothing to compare: add images to the basket (B)".to_string());
+            return;
+        }
+        self.open_compare(paths);
+    }
+
+    fn open_compare(&mut self, paths: Vec<PathBuf>) {
+        self.grid.visible = false;
+        self.map.visible = false;
+        self.crop.active = false;
+        self.redactor.active = false;
+        self.adjust.active = false;
+        self.compare = Some(crate::compare::Compare::open(paths));
+        self.make_compare_labels();
+        self.update_window_title();
+        self.window.request_redraw();
+    }
+
+    /// Scale of the built-in font for overlay text, two font pixels per logical pixel.
+    fn text_scale(&self) -> u32 {
+        (2.0 * self.scale_factor).round().max(1.0) as u32
+    }
+
+    /// Render the pane captions at the current text scale.
+    fn make_compare_labels(&mut self) {
+        let Some(compare) = &self.compare else {
+            return;
+        };
+        let scale = self.text_scale();
+        self.compare_labels = compare
+            .paths
+            .iter()
+            .enumerate()
+            .filter_map(|(i, path)| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let label = image::DynamicImage::ImageRgb8(crate::montage::label(&format!("{} {}", i + 1, name), scale));
+                crate::thumbnail::GpuThumbnail::new(&self.device, &self.queue, &self.renderer.texture_bind_group_layout, &label).ok()
+            })
+            .collect();
+    }
+
+    /// The window moved to a monitor with another pixel density: redo what was
+    /// rendered for the old one.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor as f32;
+        let size = crate::thumbnail::Thumbnailer::size_for(crate::grid::CELL * self.scale_factor);
+        if self.thumbnailer.set_size(size) {
+            self.thumbnails.clear();
+        }
+        self.make_compare_labels();
+        // Labels rendered at the old text scale are made again when next drawn
+        self.animation_label = None;
+        self.adjust_labels.clear();
+        self.header_labels.clear();
+        self.window.request_redraw();
+    }
+
+    /// The window size in logical pixels, for laying out the filmstrip and grid.
+    fn logical_size(&self) -> (f32, f32) {
+        (self.size.width as f32 / self.scale_factor, self.size.height as f32 / self.scale_factor)
+    }
+
+    fn compare_panes(&self) -> Vec<crate::quad::Rect> {
+        let area = crate::quad::Rect::new(0.0, 0.0, self.size.width as f32, self.size.height as f32);
+        self.compare.as_ref().map_or_else(Vec::new, |c| c.panes(area))
+    }
+
+    /// Width over height of a compared image, once it is loaded.
+    fn compare_aspect(&self, pane: usize) -> f32 {
+        self.compare_textures.get(&pane).map_or(1.5, |t| t.aspect)
+    }
+
+    /// Keyboard handling while comparing. Returns the image to open, if any.
+    pub fn compare_input(&mut self, keycode: winit::keyboard::KeyCode, modifiers: winit::keyboard::ModifiersState) -> Option<PathBuf> {
+        use winit::keyboard::KeyCode;
+        let focus = self.compare.as_ref()?.focus;
+        let pane = *self.compare_panes().get(focus)?;
+        let aspect = self.compare_aspect(focus);
+        let center = (pane.x + pane.w / 2.0, pane.y + pane.h / 2.0);
+        let compare = self.compare.as_mut()?;
+        match keycode {
+            KeyCode::Tab => compare.focus_next(!modifiers.shift_key()),
+            KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 | KeyCode::Digit4 => {
+                let i = match keycode {
+                    KeyCode::Digit1 => 0,
+                    KeyCode::Digit2 => 1,
+                    KeyCode::Digit3 => 2,
+                    _ => 3,
+                };
+                compare.set_focus(i);
+            }
+            KeyCode::Space => compare.swap(),
+            KeyCode::KeyO => compare.toggle_mode(crate::compare::Mode::Blink),
+            KeyCode::KeyW => compare.toggle_mode(crate::compare::Mode::Wipe),
+            KeyCode::KeyD => compare.toggle_mode(crate::compare::Mode::Difference),
+            KeyCode::BracketRight => compare.amplify(true),
+            KeyCode::BracketLeft => compare.amplify(false),
+            KeyCode::KeyH => compare.heatmap = !compare.heatmap,
+            KeyCode::ArrowLeft => compare.pan(pane.w / 10.0, 0.0, pane, aspect),
+            KeyCode::ArrowRight => compare.pan(-pane.w / 10.0, 0.0, pane, aspect),
+            KeyCode::ArrowUp => compare.pan(0.0, pane.h / 10.0, pane, aspect),
+            KeyCode::ArrowDown => compare.pan(0.0, -pane.h / 10.0, pane, aspect),
+            KeyCode::Equal | KeyCode::NumpadAdd => compare.zoom_at(1.25, pane, aspect, center),
+            KeyCode::Minus | KeyCode::NumpadSubtract => compare.zoom_at(0.8, pane, aspect, center),
+            KeyCode::Digit0 => compare.view = crate::compare::View::default(),
+            KeyCode::KeyS => self.export_compare(),
+            KeyCode::Enter => {
+                let path = compare.paths.get(focus).cloned();
+                self.toggle_compare();
+                return path;
+            }
+            KeyCode::Escape | KeyCode::KeyK => self.toggle_compare(),
+            _ => {}
+        }
+        self.update_window_title();
+        self.window.request_redraw();
+        None
+    }
+
+    /// Mouse handling while comparing: a click focuses a pane, dragging pans (or
+    /// moves the wipe divider, when grabbed) and the wheel zooms all panes together.
+    fn compare_mouse(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::*;
+        use crate::compare::Mode;
+        let panes = self.compare_panes();
+        let aspects: Vec<f32> = (0..panes.len()).map(|i| self.compare_aspect(i)).collect();
+        let area = crate::quad::Rect::new(0.0, 0.0, self.size.width as f32, self.size.height as f32);
+        let grab = WIPE_GRAB * self.scale_factor;
+        let Some(compare) = self.compare.as_mut() else {
+            return false;
+        };
+        // While blinking or wiping every pane covers the window; the focused one is under the cursor
+        let under_cursor = if compare.mode == Mode::Side {
+            self.last_mouse_pos.and_then(|(x, y)| panes.iter().position(|p| p.contains(x as f32, y as f32)))
+        } else {
+            Some(compare.focus)
+        };
+        match event {
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                self.mouse_pressed = *state == ElementState::Pressed;
+                let divider = area.x + compare.split * area.w;
+                compare.dragging_split = self.mouse_pressed
+                    && compare.mode == Mode::Wipe
+                    && self.last_mouse_pos.is_some_and(|(x, _)| (x as f32 - divider).abs() <= grab);
+                if let (true, Some(i)) = (self.mouse_pressed, under_cursor) {
+                    compare.set_focus(i);
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } if compare.dragging_split && self.mouse_pressed => {
+                compare.set_split(position.x as f32, area);
+                self.last_mouse_pos = Some((position.x, position.y));
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some((last_x, last_y)), Some(pane)) = (self.mouse_pressed, self.last_mouse_pos, panes.get(compare.focus)) {
+                    compare.pan((position.x - last_x) as f32, (position.y - last_y) as f32, *pane, aspects[compare.focus]);
+                }
+                self.last_mouse_pos = Some((position.x, position.y));
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let scroll = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 100.0,
+                };
+                if let (Some(i), Some((x, y))) = (under_cursor, self.last_mouse_pos) {
+                    let factor = if scroll > 0.0 { 1.1 } else { 1.0 / 1.1 };
+                    compare.zoom_at(factor, panes[i], aspects[i], (x as f32, y as f32));
+                }
+            }
+            _ => return false,
+        }
+        self.update_window_title();
+        self.window.request_redraw();
+        true
+    }
+
+    fn update_compare(&mut self) {
+        let Some(compare) = &self.compare else {
+            return;
+        };
+        for (i, img) in compare.poll() {
+            match crate::thumbnail::GpuThumbnail::new(&self.device, &self.queue, &self.renderer.texture_bind_group_layout, &img) {
+                Ok(texture) => {
+                    self.compare_textures.insert(i, texture);
+                }
+                Err(e) => tracing::warn!("Failed to upload compared image: {:?}", e),
+            }
+            self.window.request_redraw();
+        }
+    }
+
+    /// The panes, or the pair, filling the window; `outline` marks the focused pane.
+    fn draw_compare<'b>(&'b self, batch: &mut crate::quad::QuadBatch<'b>, outline: bool) {
+        let Some(compare) = &self.compare else {
+            return;
+        };
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        let [r, g, b] = [self.background.r, self.background.g, self.background.b].map(|c| c as f32);
+        batch.solid(crate::quad::Rect::new(0.0, 0.0, w, h), [r, g, b, 1.0]);
+        if matches!(compare.mode, crate::compare::Mode::Wipe | crate::compare::Mode::Difference) {
+            self.draw_pair(batch, compare);
+            return;
+        }
+        for (i, pane) in self.compare_panes().into_iter().enumerate() {
+            if compare.mode == crate::compare::Mode::Blink && i != compare.focus {
+                continue;
+            }
+            if let Some(texture) = self.compare_textures.get(&i) {
+                if let Some((rect, uv)) = crate::compare::place(pane, texture.aspect, &compare.view) {
+                    batch.textured_region(rect, uv, &texture.bind_group);
+                }
+            }
+            if let Some(label) = self.compare_labels.get(i) {
+                let height = crate::montage::label_height(self.text_scale()) as f32;
+                let margin = 6.0 * self.scale_factor;
+                let rect = crate::quad::Rect::new(pane.x + margin, pane.y + margin, height * label.aspect, height);
+                batch.textured(rect, &label.bind_group);
+            }
+            if outline && i == compare.focus && compare.mode == crate::compare::Mode::Side {
+                batch.outline(pane, 2.0, [0.95, 0.65, 0.1, 1.0]);
+            }
+        }
+    }
+
+    /// The focused image and the one focused before in one frame, placed by the
+    /// first so they line up: either side of the divider when wiping, or their
+    /// difference.
+    fn draw_pair<'b>(&'b self, batch: &mut crate::quad::QuadBatch<'b>, compare: &crate::compare::Compare) {
+        let area = crate::quad::Rect::new(0.0, 0.0, self.size.width as f32, self.size.height as f32);
+        let wiping = compare.mode == crate::compare::Mode::Wipe;
+        let divider = area.x + compare.split * area.w;
+        let left = self.compare_textures.get(&compare.focus);
+        let right = self.compare_textures.get(&compare.previous);
+        if let Some(texture) = left.or(right) {
+            if let Some((rect, uv)) = crate::compare::place(area, texture.aspect, &compare.view) {
+                match (left, right) {
+                    (Some(left), Some(right)) if wiping => batch.wipe(rect, uv, &left.bind_group, &right.bind_group, divider),
+                    (Some(a), Some(b)) => batch.difference(rect, uv, &a.bind_group, &b.bind_group, compare.gain, compare.heatmap),
+                    _ => batch.textured_region(rect, uv, &texture.bind_group),
+                }
+            }
+        }
+        let s = self.scale_factor;
+        if wiping {
+            self.draw_divider(batch, divider, area);
+        }
+
+        let height = crate::montage::label_height(self.text_scale()) as f32;
+        let margin = 6.0 * s;
+        if let Some(label) = self.compare_labels.get(compare.focus) {
+            batch.textured(crate::quad::Rect::new(area.x + margin, area.y + margin, height * label.aspect, height), &label.bind_group);
+        }
+        if let Some(label) = self.compare_labels.get(compare.previous) {
+            let width = height * label.aspect;
+            batch.textured(crate::quad::Rect::new(area.x + area.w - margin - width, area.y + margin, width, height), &label.bind_group);
+        }
+    }
+
+    /// The line between two images wiped over each other, with a handle to drag it by.
+    fn draw_divider(&self, batch: &mut crate::quad::QuadBatch<'_>, x: f32, area: crate::quad::Rect) {
+        let s = self.scale_factor;
+        batch.solid(crate::quad::Rect::new(x - s, area.y, 2.0 * s, area.h), [1.0, 1.0, 1.0, 0.9]);
+        let handle = crate::quad::Rect::new(x - 5.0 * s, area.y + area.h / 2.0 - 20.0 * s, 10.0 * s, 40.0 * s);
+        batch.solid(handle, [1.0, 1.0, 1.0, 1.0]);
+        batch.outline(handle, s, [0.0, 0.0, 0.0, 0.6]);
+    }
+
+    /// Put the current image in the basket, or take it out.
+    pub fn toggle_basket(&mut self) {
+        let Some(current) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let added = self.basket.toggle(&current);
+        let text = format!("{} basket ({})", if added { "Added to" } else { "Removed from" }, self.basket.len());
+        self.notify(text);
+    }
+
+    /// Mark the current image as a favorite, or unmark it. Favorites are kept in the
+    /// library, across sessions.
+    pub fn toggle_favorite(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let (Some(lib), Some(current)) = (self.library.as_ref(), self.navigator.current_path.clone()) else {
+            return;
+        };
+        let favorite = !self.favorites.contains(&current);
+        if let Err(e) = lib.set_favorite(&current, favorite) {
+            tracing::warn!("Failed to update favorites: {:?}", e);
+            return;
+        }
+        if favorite {
+            self.favorites.insert(current);
+            self.notify(format!("Added to favorites ({})", self.favorites.len()));
+        } else {
+            self.favorites.remove(&current);
+            self.notify(format!("Removed from favorites ({})", self.favorites.len()));
+        }
+    }
+
+    /// Browse only the favorites, or go back to the folder of the current image.
+    pub fn open_favorites(&mut self) -> Option<PathBuf> {
+        if self.navigator.collection() == Some(Collection::Favorites) {
+            self.navigator.leave_collection();
+            self.update_window_title();
+            return None;
+        }
+        let paths: Vec<PathBuf> = self.favorites.iter().filter(|p| p.exists()).cloned().collect();
+        if paths.is_empty() {
+            self.notify("No favorites yet (F marks the current image)".to_string());
+            return None;
+        }
+        self.duplicates = None;
+        self.navigator.open_collection(Collection::Favorites, paths)
+    }
+
+    /// Browse the basket like a folder, or go back to the folder of the current image.
+    pub fn open_basket(&mut self) -> Option<PathBuf> {
+        if self.navigator.in_collection() {
+            self.duplicates = None;
+            self.navigator.leave_collection();
+            self.update_window_title();
+            return None;
+        }
+        if self.basket.is_empty() {
+            self.notify("The basket is empty (B adds the current image)".to_string());
+            return None;
+        }
+        self.navigator.open_collection(Collection::Basket, self.basket.paths())
+    }
+
+    /// Start cataloging the library folders of the config, if it lists any.
+    pub fn open_catalog(&mut self) {
+        self.catalog = crate::catalog::Catalog::start(&self.settings.library.folders);
+    }
+
+    pub fn start_library_search(&mut self) {
+        if self.catalog.is_none() {
+            self.notify("No library folders (list them under [library] in the config)".to_string());
+            return;
+        }
+        let text = self.library_query.clone();
+        self.prompt = Some(crate::prompt::Prompt::new(crate::prompt::PromptKind::Search, &text));
+        self.update_window_title();
+    }
+
+    /// Browse the images of the library matching `text` in the gallery; an empty search goes
+    /// back to the folder of the current image. Returns the first match.
+    fn search_library(&mut self, text: &str) -> Option<PathBuf> {
+        let query = match crate::catalog::Query::parse(text) {
+            Ok(query) => query,
+            Err(e) => {
+                self.notify(format!("{}", e));
+                return None;
+            }
+        };
+        self.library_query = text.trim().to_string();
+        let Some(query) = query else {
+            if self.navigator.collection() == Some(Collection::Library) {
+                self.navigator.leave_collection();
+            }
+            return None;
+        };
+        let found = match self.library.as_ref().map(|lib| lib.search(&query)) {
+            Some(Ok(found)) => found,
+            Some(Err(e)) => {
+                tracing::warn!("Library search failed: {:?}", e);
+                return None;
+            }
+            None => return None,
+        };
+        let indexing = match self.catalog.as_ref().and_then(|c| c.scan_progress()) {
+            Some(scanned) => format!(", still indexing ({} so far)", scanned),
+            None => String::new(),
+        };
+        if found.is_empty() {
+            self.notify(format!("Nothing in the library matches \"{}\"{}", self.library_query, indexing));
+            return None;
+        }
+        self.notify(format!("{} image(s) found{}", found.len(), indexing));
+        self.duplicates = None;
+        let first = self.navigator.open_collection(Collection::Library, found);
+        if !self.grid.visible {
+            self.toggle_grid();
+        }
+        first
+    }
+
+    /// List the places the images of the library were taken at, or in folder mode those of
+    /// the images browsed, to browse one. Names not known yet are looked up in the background.
+    pub fn show_places(&mut self) {
+        // While browsing a place, the places listed are those it was picked from
+        if self.navigator.collection() != Some(Collection::Place) {
+            let mut located = match (&self.catalog, &self.library) {
+                (Some(_), Some(lib)) => lib.located().unwrap_or_else(|e| {
+                    tracing::warn!("Failed to read the library's positions: {:?}", e);
+                    Vec::new()
+                }),
+                _ => (self.navigator.image_list.iter())
+                    .filter_map(|p| Some((p.clone(), self.file_index.get(p)?.position?)))
+                    .collect(),
+            };
+            // Private images are neither listed nor have their positions sent to the geocoder
+            located.retain(|(path, _)| !self.navigator.is_hidden(path));
+            let places = &self.settings.places;
+            self.places.list(&located, places.radius_km, self.library.as_ref(), &places.geocode_url);
+        }
+        if self.places.is_empty() {
+            self.notify("No images with a GPS position".to_string());
+            return;
+        }
+        let palette = crate::ui::Palette::new("Places", self.places.items()).with_kind(crate::ui::PaletteKind::Places);
+        self.ui.palette = Some(palette);
+        self.window.request_redraw();
+    }
+
+    /// Take in place names found in the background, in the list if it is open.
+    fn update_places(&mut self) {
+        for (first, label) in self.places.update() {
+            if let Some(palette) = self.ui.palette.as_mut().filter(|p| p.kind() == crate::ui::PaletteKind::Places) {
+                palette.relabel(&first, label);
+                self.window.request_redraw();
+            }
+        }
+    }
+
+    /// Browse the images of the place listed with `first` as its first image in the
+    /// gallery. Returns the image to show.
+    fn open_place(&mut self, first: &std::path::Path) -> Option<PathBuf> {
+        let place = self.places.find(first)?.clone();
+        self.notify(format!("{}: {} image(s)", place.title(), place.paths.len()));
+        self.duplicates = None;
+        let shown = self.navigator.open_collection(Collection::Place, place.paths);
+        if !self.grid.visible {
+            self.toggle_grid();
+        }
+        shown
+    }
+
+    pub fn clear_basket(&mut self) {
+        self.basket.clear();
+        if self.navigator.collection() == Some(Collection::Basket) {
+            self.navigator.leave_collection();
+        }
+        self.notify("Basket emptied".to_string());
+    }
+
+    /// Lock private images again, or ask for the PIN that unlocks them; the first time,
+    /// for a PIN to choose.
+    pub fn toggle_private_unlock(&mut self) {
+        if self.read_only {
+            return;
+        }
+        if self.navigator.unlocked {
+            self.set_private_unlocked(false);
+            return;
+        }
+        let Some(library) = &self.library else {
+            self.notify("Private images can't be unlocked without the library database".to_string());
+            return;
+        };
+        match library.has_private_pin() {
+            Ok(has_pin) => {
+                let kind = if has_pin { crate::prompt::PromptKind::Pin } else { crate::prompt::PromptKind::NewPin };
+                self.prompt = Some(crate::prompt::Prompt::new(kind, ""));
+                self.update_window_title();
+            }
+            Err(e) => tracing::warn!("Failed to read the private PIN: {:?}", e),
+        }
+    }
+
+    fn set_private_unlocked(&mut self, unlocked: bool) {
+        self.navigator.unlocked = unlocked;
+        self.navigator.refresh();
+        self.update_window_title();
+    }
+
+    fn unlock_private(&mut self, pin: &str) {
+        let Some(library) = &self.library else {
+            return;
+        };
+        match library.check_private_pin(pin) {
+            Ok(true) => self.set_private_unlocked(true),
+            Ok(false) => self.notify("Wrong PIN".to_string()),
+            Err(e) => tracing::warn!("Failed to check the private PIN: {:?}", e),
+        }
+    }
+
+    fn choose_private_pin(&mut self, pin: &str) {
+        let Some(library) = &self.library else {
+            return;
+        };
+        if pin.is_empty() {
+            self.notify("The PIN can't be empty".to_string());
+            return;
+        }
+        match library.set_private_pin(pin) {
+            Ok(()) => self.set_private_unlocked(true),
+            Err(e) => tracing::warn!("Failed to store the private PIN: {:?}", e),
+        }
+    }
+
+    pub fn prompt_active(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// Feed a key press to the active prompt. Returns an image to open, if the prompt asked for one.
+    pub fn prompt_input(&mut self, event: &winit::event::KeyEvent) -> Option<PathBuf> {
+        use crate::prompt::{PromptKind, PromptOutcome};
+        let prompt = self.prompt.as_mut()?;
+        let outcome = prompt.handle_key(event);
+        let kind = prompt.kind;
+        let mut open = None;
+        match outcome {
+            Some(PromptOutcome::Submit(text)) => {
+                self.prompt = None;
+                match kind {
+                    PromptKind::Note => {
+                        let question = "Write the note to the XMP sidecar?".to_string();
+                        self.confirm(crate::confirm::Guard::MetadataWrite, question, PendingAction::SaveNote(text));
+                    }
+                    PromptKind::Filter => open = self.apply_filter(&text),
+                    PromptKind::GoTo => open = self.navigator.find(&text),
+                    PromptKind::Rename => self.rename_current(&text),
+                    PromptKind::Title => {
+                        self.details_draft = vec![text];
+                        let rights = self.xmp_data.rights.clone().unwrap_or_default();
+                        self.prompt = Some(crate::prompt::Prompt::new(PromptKind::Copyright, &rights));
+                    }
+                    PromptKind::Copyright => {
+                        self.details_draft.push(text);
+                        let keywords = self.xmp_data.keywords.join(", ");
+                        self.prompt = Some(crate::prompt::Prompt::new(PromptKind::Keywords, &keywords));
+                    }
+                    PromptKind::Convert => self.convert_folder(&text),
+                    PromptKind::Search => open = self.search_library(&text),
+                    PromptKind::Pin => self.unlock_private(&text),
+                    PromptKind::NewPin => self.choose_private_pin(&text),
+                    PromptKind::Keywords => {
+                        let mut draft = std::mem::take(&mut self.details_draft).into_iter();
+                        let (title, rights) = (draft.next().unwrap_or_default(), draft.next().unwrap_or_default());
+                        let question = "Write the title, copyright and keywords to the XMP sidecar?".to_string();
+                        let action = PendingAction::SaveDetails { title, rights, keywords: text };
+                        self.confirm(crate::confirm::Guard::MetadataWrite, question, action);
+                    }
+                }
+            }
+            Some(PromptOutcome::Cancel) => {
+                self.prompt = None;
+                self.details_draft.clear();
+            }
+            None => {}
+        }
+        self.update_window_title();
+        open
+    }
+
+    pub fn start_note_edit(&mut self) {
+        if self.read_only || self.navigator.current_path.is_none() {
+            return;
+        }
+        let note = self.xmp_data.description.clone().unwrap_or_default();
+        self.prompt = Some(crate::prompt::Prompt::new(crate::prompt::PromptKind::Note, &note));
+        self.update_window_title();
+    }
+
+    /// Edit the title, copyright and keywords, one prompt after the other.
+    pub fn start_metadata_edit(&mut self) {
+        if self.read_only || self.navigator.current_path.is_none() {
+            return;
+        }
+        self.details_draft.clear();
+        let title = self.xmp_data.title.clone().unwrap_or_default();
+        self.prompt = Some(crate::prompt::Prompt::new(crate::prompt::PromptKind::Title, &title));
+        self.update_window_title();
+    }
+
+    /// Ask for the format and size to convert the images of the folder (as filtered) to.
+    pub fn start_convert(&mut self) {
+        if self.read_only || self.navigator.image_list.is_empty() {
+            return;
+        }
+        if self.conversion.is_some() {
+            self.notify("A conversion is already running".to_string());
+            return;
+        }
+        self.prompt = Some(crate::prompt::Prompt::new(crate::prompt::PromptKind::Convert, "jpeg 2048"));
+        self.update_window_title();
+    }
+
+    fn convert_folder(&mut self, spec: &str) {
+        let Some((format, max_size)) = crate::convert::parse_spec(spec) else {
+            self.notify(format!("Expected a format and an optional longest side, like \"webp 2048\", not \"{}\"", spec.trim()));
+            return;
+        };
+        let files = self.navigator.image_list.clone();
+        let Some(dir) = files.first().and_then(|f| f.parent()).map(|d| d.join(crate::convert::OUTPUT_DIR)) else {
+            return;
+        };
+        let options = crate::convert::Options {
+            format,
+            max_size,
+            color_space: self.export_space,
+            strip_metadata: self.settings.export.strip_metadata,
+            overwrite: !self.settings.safety.confirm_overwrite,
+        };
+        let targets = crate::convert::target_paths(&files, Some(&dir), format);
+        if self.settings.safety.dry_run {
+            for (file, target) in files.iter().zip(&targets) {
+                tracing::info!("Dry run: would convert {:?} to {:?}", file, target);
+            }
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            self.notify(format!("Dry run: {} image(s) would be converted into {}", files.len(), name));
+            return;
+        }
+        self.conversion = Some((dir, crate::convert::start(files, targets, options)));
+    }
+
+    /// Report a batch conversion once it is done.
+    fn update_conversion(&mut self) {
+        let Some((dir, progress)) = self.conversion.take_if(|(_, p)| p.finished()) else {
+            return;
+        };
+        let errors = progress.errors();
+        for (file, error) in &errors {
+            tracing::warn!("Failed to convert {:?}: {}", file, error);
+        }
+        let converted = progress.total - errors.len();
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        match errors.len() {
+            0 => self.ui.toasts.push(crate::ui::Level::Info, format!("Converted {} image(s) into {}", converted, name)),
+            failed => self.ui.toasts.push(
+                crate::ui::Level::Error,
+                format!("Converted {} image(s) into {}, {} failed", converted, name, failed),
+            ),
+        }
+    }
+
+    /// Open or close the egui panel `id`.
+    pub fn toggle_panel(&mut self, id: &str) {
+        self.ui.panels.toggle(id);
+        self.refresh_properties();
+    }
+
+    /// Gather the current file's properties while their panel is open.
+    fn refresh_properties(&mut self) {
+        self.properties = match &self.navigator.current_path {
+            Some(path) if self.ui.panels.is_open("properties") => {
+                Some(crate::properties::Properties::read(path, self.image_size, self.decoded_color, &self.exif_data))
+            }
+            _ => None,
+        };
+    }
+
+    pub fn cache_panel_active(&self) -> bool {
+        self.cache_panel.is_some()
+    }
+
+    pub fn toggle_cache_panel(&mut self) {
+        self.cache_panel = match self.cache_panel {
+            Some(_) => None,
+            None => Some(crate::cache::CachePanel::open(self.settings.cache.directory.clone(), crate::library::Library::path())),
+        };
+        self.update_window_title();
+    }
+
+    /// Keyboard handling while the cache panel is open: digits clear a cache.
+    pub fn cache_panel_input(&mut self, keycode: winit::keyboard::KeyCode) {
+        use winit::keyboard::KeyCode;
+        let Some(panel) = self.cache_panel.as_mut() else {
+            return;
+        };
+        let number = match keycode {
+            KeyCode::Digit1 | KeyCode::Numpad1 => 1,
+            KeyCode::Digit2 | KeyCode::Numpad2 => 2,
+            KeyCode::Escape | KeyCode::F9 => {
+                self.cache_panel = None;
+                self.update_window_title();
+                return;
+            }
+            _ => return,
+        };
+        if !self.read_only {
+            if let Err(e) = panel.clear(number) {
+                tracing::warn!("Failed to clear cache: {:?}", e);
+            }
+        }
+        self.update_window_title();
+    }
+
+    fn notify(&mut self, text: String) {
+        self.status = Some((text, std::time::Instant::now()));
+        self.update_window_title();
+    }
+
+    /// Run `action` now, or ask first if the safety settings say so.
+    /// Returns the image to show next, if the action changed it.
+    fn confirm(&mut self, guard: crate::confirm::Guard, question: String, action: PendingAction) -> Option<PathBuf> {
+        if guard.required(&self.settings.safety) {
+            self.confirmation = Some(crate::confirm::Confirmation::new(guard, question, action));
+            self.update_window_title();
+            None
+        } else {
+            self.run_action(action)
+        }
+    }
+
+    fn run_action(&mut self, action: PendingAction) -> Option<PathBuf> {
+        match action {
+            PendingAction::Delete(path) => return self.trash(&path),
+            PendingAction::TrashDuplicates(paths) if self.settings.safety.dry_run => {
+                for path in &paths {
+                    tracing::info!("Dry run: would move {:?} to the trash", path);
+                }
+                self.notify(format!("Dry run: {} duplicate(s) would be moved to the trash", paths.len()));
+            }
+            PendingAction::TrashDuplicates(paths) => {
+                let mut open = None;
+                for path in &paths {
+                    open = self.trash(path).or(open);
+                }
+                return open;
+            }
+            PendingAction::SaveNote(text) => self.save_note(&text),
+            PendingAction::SaveDetails { title, rights, keywords } => self.save_details(&title, &rights, &keywords),
+            PendingAction::SaveRating { rating, pick } => self.save_rating(rating, pick),
+            PendingAction::ExportRedacted => self.export_redacted(),
+            PendingAction::ExportCrop => self.export_crop(),
+            PendingAction::ExportClean => self.export_clean(),
+            PendingAction::SaveRotation => self.save_rotation(),
+        }
+        None
+    }
+
+    pub fn confirmation_active(&self) -> bool {
+        self.confirmation.is_some()
+    }
+
+    /// Feed a key press to the open question. Returns an image to open, if the confirmed action changed it.
+    pub fn confirmation_input(&mut self, event: &winit::event::KeyEvent) -> Option<PathBuf> {
+        use crate::confirm::Answer;
+        let answer = crate::confirm::answer(event)?;
+        let confirmation = self.confirmation.take()?;
+        let open = match answer {
+            Answer::No => None,
+            Answer::Yes => self.run_action(confirmation.action),
+            Answer::Always => {
+                confirmation.guard.stop_asking(&mut self.settings.safety);
+                if let Err(e) = self.settings.save() {
+                    tracing::warn!("Failed to save settings: {:?}", e);
+                }
+                self.run_action(confirmation.action)
+            }
+        };
+        self.update_window_title();
+        open
+    }
+
+    /// Move the current file to the trash, after confirmation if required. Returns the image to show next.
+    pub fn delete_current(&mut self) -> Option<PathBuf> {
+        if self.read_only {
+            return None;
+        }
+        let current = self.navigator.current_path.clone()?;
+        let name = self.navigator.display_name(&current).unwrap_or_default();
+        let question = format!("Move {} to the trash?", name);
+        self.confirm(crate::confirm::Guard::Delete, question, PendingAction::Delete(current))
+    }
+
+    fn trash(&mut self, path: &std::path::Path) -> Option<PathBuf> {
+        let name = self.navigator.display_name(path).unwrap_or_default();
+        if let Err(e) = trash::delete(path) {
+            tracing::warn!("Failed to move {:?} to the trash: {:?}", path, e);
+            self.notify(format!("Could not delete {}", name));
+            return None;
+        }
+        self.thumbnails.remove(path);
+        self.history.push(crate::history::Change::Trashed(path.to_path_buf()));
+        self.notify(format!("Moved {} to the trash", name));
+        let next = self.navigator.remove_path(path);
+        self.resolve_duplicates(&[path.to_path_buf()]).or(next)
+    }
+
+    /// Hash the images of the folder (as filtered) to find duplicates, or stop reviewing them.
+    pub fn find_duplicates(&mut self) {
+        if self.duplicates.take().is_some() {
+            self.navigator.leave_collection();
+            self.update_window_title();
+            return;
+        }
+        if self.duplicate_scan.is_some() {
+            self.notify("Already looking for duplicates".to_string());
+            return;
+        }
+        let files = self.navigator.image_list.clone();
+        if files.len() < 2 {
+            return;
+        }
+        let cache_dir = crate::cache::CacheKind::Thumbnails.dir(self.settings.cache.directory.as_deref());
+        self.duplicate_scan = Some(crate::duplicates::start(files, cache_dir, crate::duplicates::DEFAULT_THRESHOLD));
+        self.update_window_title();
+    }
+
+    /// Once the folder is hashed, browse the first group of duplicates. Returns its first image.
+    pub fn duplicates_ready(&mut self) -> Option<PathBuf> {
+        let groups = self.duplicate_scan.as_ref()?.take_groups()?;
+        self.duplicate_scan = None;
+        let count = groups.len();
+        let Some(duplicates) = crate::duplicates::Duplicates::new(groups) else {
+            self.notify("No duplicates found".to_string());
+            return None;
+        };
+        let first = duplicates.group().to_vec();
+        self.duplicates = Some(duplicates);
+        self.notify(format!("Found {} group(s) of duplicates", count));
+        self.navigator.open_collection(Collection::Duplicates, first)
+    }
+
+    /// Whether reviewing duplicates gives `keycode` a meaning of its own.
+    pub fn duplicates_key(&self, keycode: winit::keyboard::KeyCode) -> bool {
+        use winit::keyboard::KeyCode;
+        self.duplicates.is_some()
+            && matches!(keycode, KeyCode::PageUp | KeyCode::PageDown | KeyCode::Enter | KeyCode::Escape)
+    }
+
+    /// Page Up/Down switch groups, Enter keeps the current image and trashes the rest of
+    /// its group, Escape stops reviewing. Returns the image to show next.
+    pub fn duplicates_input(&mut self, keycode: winit::keyboard::KeyCode) -> Option<PathBuf> {
+        use winit::keyboard::KeyCode;
+        let duplicates = self.duplicates.as_mut()?;
+        match keycode {
+            KeyCode::PageUp | KeyCode::PageDown => {
+                let group = duplicates.step(keycode == KeyCode::PageDown).to_vec();
+                self.navigator.open_collection(Collection::Duplicates, group)
+            }
+            KeyCode::Enter if !self.read_only => {
+                let keep = self.navigator.current_path.clone()?;
+                let others = duplicates.others(&keep);
+                let name = self.navigator.display_name(&keep).unwrap_or_default();
+                let question = format!("Keep {} and move {} other(s) to the trash?", name, others.len());
+                self.confirm(crate::confirm::Guard::Delete, question, PendingAction::TrashDuplicates(others))
+            }
+            KeyCode::Escape => {
+                self.find_duplicates();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Follow files deleted while reviewing duplicates. When that leaves the current group
+    /// with a single image, move on to the next group and return its first image.
+    fn resolve_duplicates(&mut self, gone: &[PathBuf]) -> Option<PathBuf> {
+        let duplicates = self.duplicates.as_mut()?;
+        let group = duplicates.group().to_vec();
+        if !duplicates.forget(gone) {
+            self.duplicates = None;
+            self.navigator.leave_collection();
+            self.notify("No duplicates left".to_string());
+            return None;
+        }
+        if duplicates.group().iter().any(|p| group.contains(p)) {
+            return None;
+        }
+        let next = duplicates.group().to_vec();
+        self.navigator.open_collection(Collection::Duplicates, next)
+    }
+
+    pub fn start_rename(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let Some(name) = self.navigator.current_path.as_deref().and_then(|p| p.file_name()) else {
+            return;
+        };
+        let name = name.to_string_lossy().into_owned();
+        self.prompt = Some(crate::prompt::Prompt::new(crate::prompt::PromptKind::Rename, &name));
+        self.update_window_title();
+    }
+
+    /// Rename the current file within its folder, recorded in the journal so `momemtum undo` reverts it.
+    /// Target folder and transfer for a number key, when a folder is configured for it.
+    pub fn culling_target(
+        &self,
+        keycode: winit::keyboard::KeyCode,
+        modifiers: winit::keyboard::ModifiersState,
+    ) -> Option<(PathBuf, crate::config::TransferMode)> {
+        use crate::config::TransferMode;
+        use winit::keyboard::KeyCode;
+        const DIGITS: [KeyCode; 9] = [
+            KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4, KeyCode::Digit5,
+            KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+        ];
+        if modifiers.control_key() || modifiers.alt_key() {
+            return None;
+        }
+        let index = DIGITS.iter().position(|k| *k == keycode)?;
+        let folder = self.settings.culling.targets.get(index)?.clone();
+        let mode = match (self.settings.culling.mode, modifiers.shift_key()) {
+            (mode, false) => mode,
+            (TransferMode::Move, true) => TransferMode::Copy,
+            (TransferMode::Copy, true) => TransferMode::Move,
+        };
+        Some((folder, mode))
+    }
+
+    /// Copy or move the current image into `folder` and go on to the next one.
+    pub fn send_current(&mut self, folder: &std::path::Path, mode: crate::config::TransferMode) -> Option<PathBuf> {
+        use crate::config::TransferMode;
+        if self.read_only {
+            return None;
+        }
+        let current = self.navigator.current_path.clone()?;
+        let name = self.navigator.display_name(&current).unwrap_or_default();
+        let folder_name = folder.file_name().unwrap_or(folder.as_os_str()).to_string_lossy().into_owned();
+        let sent = match crate::cull::send(&current, folder, mode) {
+            Ok(sent) => sent,
+            Err(e) => {
+                tracing::warn!("Failed to send {:?} to {:?}: {:?}", current, folder, e);
+                self.notify(format!("Could not send {} to {}", name, folder_name));
+                return None;
+            }
+        };
+        match mode {
+            TransferMode::Copy => {
+                self.history.push(sent.change());
+                self.notify(format!("Copied {} to {}", name, folder_name));
+                self.get_next_image()
+            }
+            TransferMode::Move => {
+                self.record_moves(&sent.moved);
+                self.history.push(sent.change());
+                self.thumbnails.remove(&current);
+                self.notify(format!("Moved {} to {}", name, folder_name));
+                self.navigator.remove_path(&current)
+            }
+        }
+    }
+
+    fn rename_current(&mut self, name: &str) {
+        let Some(from) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let name = name.trim();
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            self.notify(format!("Invalid file name {:?}", name));
+            return;
+        }
+        let to = from.with_file_name(name);
+        if to == from {
+            return;
+        }
+        if let Err(e) = crate::journal::move_file(&from, &to) {
+            tracing::warn!("Failed to rename {:?}: {:?}", from, e);
+            self.notify(format!("Could not rename to {}", name));
+            return;
+        }
+        let moves = vec![(from.clone(), to.clone())];
+        self.record_moves(&moves);
+        self.history.push(crate::history::Change::Moved(moves));
+        if let Some(thumb) = self.thumbnails.remove(&from) {
+            self.thumbnails.insert(to.clone(), thumb);
+        }
+        self.navigator.rename_path(&from, &to);
+        self.basket.rename(&from, &to);
+        if self.favorites.remove(&from) {
+            self.favorites.insert(to.clone());
+            if let Some(lib) = &self.library {
+                let moved = lib.set_favorite(&from, false).and_then(|_| lib.set_favorite(&to, true));
+                if let Err(e) = moved {
+                    tracing::warn!("Failed to update favorites: {:?}", e);
+                }
+            }
+        }
+        self.notify(format!("Renamed to {}", name));
+    }
+
+    /// Add moves to the journal, so `momemtum undo` can revert them after the session.
+    fn record_moves(&mut self, moves: &[(PathBuf, PathBuf)]) {
+        let Some(lib) = self.library.as_mut().filter(|_| !moves.is_empty()) else {
+            return;
+        };
+        let operations: Vec<_> =
+            moves.iter().map(|(from, to)| crate::journal::Operation::Rename { from: from.clone(), to: to.clone() }).collect();
+        if let Err(e) = crate::journal::record(lib, &operations) {
+            tracing::warn!("Failed to record move: {:?}", e);
+        }
+    }
+
+    /// Undo the latest file change of this session, or redo the latest undone one.
+    /// Returns the image to show.
+    pub fn undo(&mut self, redo: bool) -> Option<PathBuf> {
+        use crate::history::Change;
+        if self.read_only {
+            return None;
+        }
+        let verb = if redo { "Redid" } else { "Undid" };
+        let (reverted, now) = match self.history.step(redo) {
+            Ok(Some(step)) => step,
+            Ok(None) => {
+                self.notify(format!("Nothing to {}", if redo { "redo" } else { "undo" }));
+                return None;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to {}: {:?}", if redo { "redo" } else { "undo" }, e);
+                self.notify(format!("Could not {}: {}", if redo { "redo" } else { "undo" }, e));
+                return None;
+            }
+        };
+        self.record_moves(&now.moves());
+        if let Change::Rated { path, to: (rating, pick), .. } = &now {
+            if let Some(info) = self.file_index.get_mut(path) {
+                (info.rating, info.pick) = ((*rating != 0).then_some(*rating), *pick);
+            }
+        }
+        self.notify(format!("{} {}", verb, if redo { now.describe() } else { reverted.describe() }));
+
+        // Files came and went: leave an image that is gone, then list the folder again
+        let shown = now.shown().filter(|p| p.exists() && self.navigator.root().is_some_and(|root| p.starts_with(root)));
+        if let Some(path) = shown {
+            self.thumbnails.remove(path);
+        }
+        let current = self.navigator.current_path.clone();
+        let replacement = current.filter(|c| !c.exists()).and_then(|c| self.navigator.remove_path(&c));
+        self.navigator.refresh();
+        self.window.request_redraw();
+        shown.map(std::path::Path::to_path_buf).or(replacement)
+    }
+
+    pub fn start_goto(&mut self) {
+        self.prompt = Some(crate::prompt::Prompt::new(crate::prompt::PromptKind::GoTo, ""));
+        self.update_window_title();
+    }
+
+    pub fn start_filter_edit(&mut self) {
+        let text = self.navigator.filter.as_ref().map(|f| f.text().to_string()).unwrap_or_default();
+        self.prompt = Some(crate::prompt::Prompt::new(crate::prompt::PromptKind::Filter, &text));
+        self.update_window_title();
+    }
+
+    /// Narrow navigation to the images matching `text`; an empty filter shows all again.
+    /// Returns the first match when the current image no longer passes.
+    fn apply_filter(&mut self, text: &str) -> Option<PathBuf> {
+        let filter = match crate::filter::Filter::parse(text) {
+            Ok(filter) => filter,
+            Err(e) => {
+                tracing::warn!("Invalid filter: {}", e);
+                return None;
+            }
+        };
+        self.navigator.set_filter(filter);
+        let list = &self.navigator.image_list;
+        let current = self.navigator.current_path.as_ref().and_then(|c| list.iter().position(|p| p == c));
+        self.grid.selected = current.unwrap_or(0);
+        match current {
+            Some(_) => None,
+            None => list.first().cloned(),
+        }
+    }
+
+    fn save_note(&mut self, text: &str) {
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let note = Some(text.trim()).filter(|t| !t.is_empty());
+        match crate::xmp::write_description(&path, note) {
+            Ok(()) => self.xmp_data.description = note.map(str::to_string),
+            Err(e) => tracing::warn!("Failed to write sidecar for {:?}: {:?}", path, e),
+        }
+    }
+
+    fn save_details(&mut self, title: &str, rights: &str, keywords: &str) {
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let title = Some(title.trim()).filter(|t| !t.is_empty());
+        let rights = Some(rights.trim()).filter(|r| !r.is_empty());
+        let keywords = crate::xmp::parse_keywords(keywords);
+        match crate::xmp::write_details(&path, title, rights, &keywords) {
+            Ok(()) => {
+                self.xmp_data.title = title.map(str::to_string);
+                self.xmp_data.rights = rights.map(str::to_string);
+                self.xmp_data.keywords = keywords;
+                self.notify("Saved title, copyright and keywords".to_string());
+            }
+            Err(e) => tracing::warn!("Failed to write sidecar for {:?}: {:?}", path, e),
+        }
+    }
+
+    /// Give the current image 1 to 5 stars, or clear the rating with 0.
+    pub fn rate(&mut self, stars: i32) {
+        self.request_rating(stars, self.xmp_data.pick);
+    }
+
+    pub fn toggle_pick(&mut self) {
+        let pick = !self.xmp_data.pick;
+        // A pick can't stay rejected
+        let rating = match self.xmp_data.rating.unwrap_or(0) {
+            -1 if pick => 0,
+            rating => rating,
+        };
+        self.request_rating(rating, pick);
+    }
+
+    pub fn toggle_reject(&mut self) {
+        let rating = if self.xmp_data.rating == Some(-1) { 0 } else { -1 };
+        self.request_rating(rating, false);
+    }
+
+    fn request_rating(&mut self, rating: i32, pick: bool) {
+        if self.read_only || self.navigator.current_path.is_none() {
+            return;
+        }
+        let question = "Write the rating to the XMP sidecar?".to_string();
+        self.confirm(crate::confirm::Guard::MetadataWrite, question, PendingAction::SaveRating { rating, pick });
+    }
+
+    fn save_rating(&mut self, rating: i32, pick: bool) {
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let before = (self.xmp_data.rating.unwrap_or(0), self.xmp_data.pick);
+        match crate::xmp::write_rating(&path, rating, pick) {
+            Ok(()) => {
+                self.history.push(crate::history::Change::Rated { path: path.clone(), from: before, to: (rating, pick) });
+                self.xmp_data.rating = (rating != 0).then_some(rating);
+                self.xmp_data.pick = pick;
+                if let Some(info) = self.file_index.get_mut(&path) {
+                    (info.rating, info.pick) = (self.xmp_data.rating, pick);
+                }
+                self.update_window_title();
+            }
+            Err(e) => tracing::warn!("Failed to write sidecar for {:?}: {:?}", path, e),
+        }
+    }
+
+    pub fn redaction_active(&self) -> bool {
+        self.redactor.active
+    }
+
+    pub fn toggle_redaction(&mut self) {
+        if self.navigator.current_path.is_none() {
+            return;
+        }
+        self.crop.active = false;
+        self.adjust.active = false;
+        self.redactor.active = !self.redactor.active;
+        if !self.redactor.active {
+            self.redactor.clear();
+        }
+        self.mouse_pressed = false;
+        self.window.request_redraw();
+    }
+
+    /// Keyboard handling in redaction mode: Backspace removes the last box, Ctrl+S exports
+    /// a redacted copy, X or Escape leaves the mode.
+    pub fn redaction_input(&mut self, keycode: winit::keyboard::KeyCode, modifiers: winit::keyboard::ModifiersState) {
+        use winit::keyboard::KeyCode;
+        match keycode {
+            KeyCode::Backspace => {
+                self.redactor.boxes.pop();
+            }
+            KeyCode::KeyS if modifiers.control_key() => self.request_export_redacted(),
+            KeyCode::KeyX | KeyCode::Escape => self.toggle_redaction(),
+            _ => {}
+        }
+        self.window.request_redraw();
+    }
+
+    fn request_export_redacted(&mut self) {
+        if self.read_only || self.redactor.boxes.is_empty() {
+            return;
+        }
+        let Some(target) = self.navigator.current_path.as_deref().map(crate::redact::export_path) else {
+            return;
+        };
+        if target.exists() {
+            let name = target.file_name().unwrap_or_default().to_string_lossy();
+            let question = format!("Replace {}?", name);
+            self.confirm(crate::confirm::Guard::Overwrite, question, PendingAction::ExportRedacted);
+        } else {
+            self.export_redacted();
+        }
+    }
+
+    fn export_redacted(&self) {
+        if self.read_only || self.redactor.boxes.is_empty() {
+            return;
+        }
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let boxes = self.redactor.boxes.clone();
+        let space = self.export_space;
+        std::thread::spawn(move || match crate::redact::export(&path, &boxes, space) {
+            Ok(target) => tracing::info!("Saved redacted copy to {:?}", target),
+            Err(e) => tracing::warn!("Failed to export redacted copy: {:?}", e),
+        });
+    }
+
+    pub fn crop_active(&self) -> bool {
+        self.crop.active
+    }
+
+    pub fn toggle_crop(&mut self) {
+        if self.navigator.current_path.is_none() {
+            return;
+        }
+        self.redactor.active = false;
+        self.adjust.active = false;
+        self.crop.active = !self.crop.active;
+        self.crop.clear();
+        self.mouse_pressed = false;
+        self.window.request_redraw();
+    }
+
+    pub fn crop_input(&mut self, keycode: winit::keyboard::KeyCode, modifiers: winit::keyboard::ModifiersState) {
+        use winit::keyboard::KeyCode;
+        match keycode {
+            KeyCode::KeyA => self.crop.cycle_preset(self.image_aspect),
+            KeyCode::Backspace => self.crop.clear(),
+            KeyCode::Enter => self.request_export_crop(),
+            KeyCode::KeyS if modifiers.control_key() => self.request_export_crop(),
+            KeyCode::KeyC | KeyCode::Escape => self.toggle_crop(),
+            _ => {}
+        }
+        self.window.request_redraw();
+    }
+
+    pub fn adjust_active(&self) -> bool {
+        self.adjust.active
+    }
+
+    /// Show or hide the adjustment sliders; the adjustments stay either way.
+    pub fn toggle_adjust(&mut self) {
+        self.redactor.active = false;
+        self.crop.active = false;
+        self.adjust.active = !self.adjust.active;
+        self.adjust.dragging = None;
+        self.window.request_redraw();
+    }
+
+    /// Show the clipping warning or false color, or turn it off again.
+    pub fn toggle_exposure(&mut self, overlay: crate::exposure::Overlay) {
+        self.exposure = self.exposure.toggled(overlay);
+        self.window.request_redraw();
+    }
+
+    pub fn has_lut(&self) -> bool {
+        self.lut.is_some()
+    }
+
+    /// Preview images through the `.cube` LUT at `path`.
+    pub fn load_lut(&mut self, path: &std::path::Path) {
+        match crate::lut::Lut::load(path) {
+            Ok(lut) => {
+                self.renderer.set_lut(&self.device, &self.queue, &lut);
+                let name = lut.title.clone().unwrap_or_else(|| path.file_stem().unwrap_or_default().to_string_lossy().into_owned());
+                self.notify(format!("LUT {} ({} points)", name, lut.size));
+                self.lut = Some((name, lut));
+                self.lut_enabled = true;
+            }
+            Err(e) => self.notify(format!("Failed to load LUT: {:#}", e)),
+        }
+        self.window.request_redraw();
+    }
+
+    pub fn toggle_lut(&mut self) {
+        self.lut_enabled = !self.lut_enabled;
+        self.window.request_redraw();
+    }
+
+    /// Whether a LUT, adjustments or an exposure overlay change how the image shows.
+    fn view_adjusted(&self) -> bool {
+        !self.adjust.values.is_neutral() || self.exposure != crate::exposure::Overlay::Off || (self.lut.is_some() && self.lut_enabled)
+    }
+
+    /// Show the image as decoded, while the key is held.
+    pub fn show_original(&mut self, held: bool) {
+        if self.showing_original != held {
+            self.showing_original = held;
+            self.window.request_redraw();
+        }
+    }
+
+    /// Split the view into the original left of a divider and the adjusted image right of it.
+    pub fn toggle_before_after(&mut self) {
+        self.before_after = match self.before_after {
+            Some(_) => None,
+            None => {
+                if !self.view_adjusted() {
+                    self.notify("Nothing adjusted yet: A for adjustments, U for a LUT, J for exposure".to_string());
+                }
+                Some(0.5)
+            }
+        };
+        self.dragging_before_after = false;
+        self.window.request_redraw();
+    }
+
+    pub fn reset_adjustments(&mut self) {
+        self.adjust.values = crate::adjust::Adjustments::default();
+        self.notify("View adjustments reset".to_string());
+        self.window.request_redraw();
+    }
+
+    pub fn adjust_input(&mut self, keycode: winit::keyboard::KeyCode, modifiers: winit::keyboard::ModifiersState) {
+        use winit::keyboard::KeyCode;
+        let param = self.adjust.selected_param();
+        match keycode {
+            KeyCode::ArrowUp => self.adjust.select(false),
+            KeyCode::ArrowDown => self.adjust.select(true),
+            KeyCode::ArrowLeft | KeyCode::ArrowRight => {
+                // Shift moves five steps at a time
+                for _ in 0..if modifiers.shift_key() { 5 } else { 1 } {
+                    self.adjust.values.step(param, keycode == KeyCode::ArrowRight);
+                }
+            }
+            KeyCode::Backspace => self.adjust.values.reset(param),
+            KeyCode::Digit0 => self.adjust.values = crate::adjust::Adjustments::default(),
+            KeyCode::KeyA | KeyCode::Escape | KeyCode::Enter => self.toggle_adjust(),
+            _ => {}
+        }
+        self.window.request_redraw();
+    }
+
+    /// Top left corner of the slider panel and the height of one of its rows.
+    fn adjust_panel(&self) -> (f32, f32, f32) {
+        let margin = 16.0 * self.scale_factor;
+        (margin, margin, crate::montage::label_height(self.text_scale()) as f32)
+    }
+
+    /// Slider under the cursor, grabbed a few pixels above and below its track.
+    fn adjust_slider_at_cursor(&self) -> Option<usize> {
+        let (x, y) = self.last_mouse_pos?;
+        let (px, py, row) = self.adjust_panel();
+        crate::adjust::slider_rects(px, py, row, self.scale_factor)
+            .iter()
+            .position(|track| crate::quad::Rect::new(track.x, track.y - row / 2.0, track.w, track.h + row).contains(x as f32, y as f32))
+    }
+
+    /// Move the dragged slider to the cursor.
+    fn drag_adjust_slider(&mut self) {
+        let (Some(i), Some((x, _))) = (self.adjust.dragging, self.last_mouse_pos) else {
+            return;
+        };
+        let (px, py, row) = self.adjust_panel();
+        let track = crate::adjust::slider_rects(px, py, row, self.scale_factor)[i];
+        self.adjust.values.set_fraction(crate::adjust::Param::ALL[i], (x as f32 - track.x) / track.w);
+        self.window.request_redraw();
+    }
+
+    /// Render the slider captions whose values changed.
+    fn update_adjust_labels(&mut self) {
+        if !self.adjust.active {
+            return;
+        }
+        let scale = self.text_scale();
+        for (i, param) in crate::adjust::Param::ALL.into_iter().enumerate() {
+            let text = self.adjust.values.describe(param);
+            if self.adjust_labels.get(i).is_some_and(|(shown, _)| *shown == text) {
+                continue;
+            }
+            let label = image::DynamicImage::ImageRgb8(crate::montage::label(&text, scale));
+            let Ok(label) = crate::thumbnail::GpuThumbnail::new(&self.device, &self.queue, &self.renderer.texture_bind_group_layout, &label) else {
+                return;
+            };
+            if i < self.adjust_labels.len() {
+                self.adjust_labels[i] = (text, label);
+            } else {
+                self.adjust_labels.push((text, label));
+            }
+        }
+    }
+
+    fn draw_adjust_panel<'b>(&'b self, batch: &mut crate::quad::QuadBatch<'b>) {
+        let (x, y, row) = self.adjust_panel();
+        let s = self.scale_factor;
+        let tracks = crate::adjust::slider_rects(x, y, row, s);
+        let panel = crate::quad::Rect::new(x, y, crate::adjust::SLIDER_WIDTH * s, row * 2.0 * tracks.len() as f32);
+        batch.solid(panel.inset(-8.0 * s), [0.0, 0.0, 0.0, 0.6]);
+        for (i, (track, param)) in tracks.iter().zip(crate::adjust::Param::ALL).enumerate() {
+            if let Some((_, label)) = self.adjust_labels.get(i) {
+                let top = y + i as f32 * row * 2.0;
+                batch.textured(crate::quad::Rect::new(x, top, row * label.aspect, row), &label.bind_group);
+            }
+            let color = if i == self.adjust.selected { [0.95, 0.65, 0.1, 1.0] } else { [1.0, 1.0, 1.0, 1.0] };
+            batch.solid(*track, [0.4, 0.4, 0.4, 1.0]);
+            let knob_x = track.x + self.adjust.values.fraction(param) * track.w;
+            batch.solid(crate::quad::Rect::new(knob_x - 3.0 * s, track.y - 5.0 * s, 6.0 * s, track.h + 10.0 * s), color);
+        }
+    }
+
+    fn request_export_crop(&mut self) {
+        if self.read_only || self.crop.rect.is_none() {
+            return;
+        }
+        let Some(target) = self.navigator.current_path.as_deref().map(crate::crop::export_path) else {
+            return;
+        };
+        if target.exists() {
+            let name = target.file_name().unwrap_or_default().to_string_lossy();
+            let question = format!("Replace {}?", name);
+            self.confirm(crate::confirm::Guard::Overwrite, question, PendingAction::ExportCrop);
+        } else {
+            self.export_crop();
+        }
+    }
+
+    fn export_crop(&mut self) {
+        let (Some(path), Some(rect)) = (self.navigator.current_path.clone(), self.crop.rect) else {
+            return;
+        };
+        let name = crate::crop::export_path(&path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let (space, strip) = (self.export_space, self.settings.export.strip_metadata);
+        std::thread::spawn(move || match crate::crop::export(&path, rect, space, strip) {
+            Ok(target) => tracing::info!("Saved crop to {:?}", target),
+            Err(e) => tracing::warn!("Failed to export crop: {:?}", e),
+        });
+        self.notify(format!("Saving crop to {}", name));
+    }
+
+    /// Save a copy of the current image without EXIF, GPS and other metadata, for sharing.
+    pub fn request_export_clean(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let Some(target) = self.navigator.current_path.as_deref().map(crate::export::clean_path) else {
+            return;
+        };
+        if target.exists() {
+            let name = target.file_name().unwrap_or_default().to_string_lossy();
+            let question = format!("Replace {}?", name);
+            self.confirm(crate::confirm::Guard::Overwrite, question, PendingAction::ExportClean);
+        } else {
+            self.export_clean();
+        }
+    }
+
+    fn export_clean(&mut self) {
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let target = crate::export::clean_path(&path);
+        let name = target.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        std::thread::spawn(move || match crate::export::save_clean(&path, &target) {
+            Ok(()) => tracing::info!("Saved copy without metadata to {:?}", target),
+            Err(e) => tracing::warn!("Failed to save copy without metadata: {:?}", e),
+        });
+        self.notify(format!("Saving {} without metadata", name));
+    }
+
+    fn cursor_uv(&self) -> Option<(f32, f32)> {
+        let (x, y) = self.last_mouse_pos?;
+        Some(self.camera.screen_to_uv(
+            self.image_aspect,
+            self.size.width as f32,
+            self.size.height as f32,
+            x as f32,
+            y as f32,
+        ))
+    }
+
+    /// Image texture-coordinate rectangle to window pixels.
+    fn uv_rect_to_screen(&self, r: [f32; 4]) -> crate::quad::Rect {
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        let (x0, y0) = self.camera.uv_to_screen(self.image_aspect, w, h, r[0], r[1]);
+        let (x1, y1) = self.camera.uv_to_screen(self.image_aspect, w, h, r[2], r[3]);
+        crate::quad::Rect::new(x0, y0, x1 - x0, y1 - y0)
+    }
+
+    pub fn toggle_filmstrip(&mut self) {
+        self.filmstrip_visible = !self.filmstrip_visible;
+        self.window.request_redraw();
+    }
+
+    fn filmstrip_cells(&self) -> Vec<(usize, crate::quad::Rect)> {
+        let current = self
+            .navigator
+            .current_path
+            .as_ref()
+            .and_then(|c| self.navigator.image_list.iter().position(|p| p == c));
+        let (w, h) = self.logical_size();
+        crate::filmstrip::layout(w, h, self.navigator.image_list.len(), current)
+            .into_iter()
+            .map(|(i, cell)| (i, cell.scaled(self.scale_factor)))
+            .collect()
+    }
+
+    fn strip_rect(&self) -> crate::quad::Rect {
+        let (w, h) = self.logical_size();
+        crate::filmstrip::strip_rect(w, h).scaled(self.scale_factor)
+    }
+
+    /// Visible grid cells in physical pixels.
+    fn grid_cells(&self) -> Vec<(usize, crate::quad::Rect)> {
+        let (w, h) = self.logical_size();
+        self.grid
+            .layout(w, h, self.navigator.image_list.len())
+            .into_iter()
+            .map(|(i, cell)| (i, cell.scaled(self.scale_factor)))
+            .collect()
+    }
+
+    fn grid_headers(&self) -> Vec<(&crate::grid::Header, crate::quad::Rect)> {
+        let (w, h) = self.logical_size();
+        self.grid
+            .header_rects(w, h, self.navigator.image_list.len())
+            .into_iter()
+            .map(|(header, rect)| (header, rect.scaled(self.scale_factor)))
+            .collect()
+    }
+
+    /// The filmstrip image under the mouse cursor, if the filmstrip is shown.
+    pub fn filmstrip_item_at_cursor(&self) -> Option<PathBuf> {
+        if !self.filmstrip_visible || self.grid.visible || self.map.visible {
+            return None;
+        }
+        let (x, y) = self.last_mouse_pos?;
+        self.filmstrip_cells()
+            .into_iter()
+            .find(|(_, rect)| rect.contains(x as f32, y as f32))
+            .map(|(i, _)| self.navigator.image_list[i].clone())
+    }
+
+    fn cursor_in_filmstrip(&self) -> bool {
+        match self.last_mouse_pos {
+            Some((x, y)) if self.filmstrip_visible => self.strip_rect().contains(x as f32, y as f32),
+            _ => false,
+        }
+    }
+
+    /// Group the gallery by capture day, then by month, then go back to the order before.
+    pub fn cycle_timeline(&mut self) {
+        let selected = self.navigator.image_list.get(self.grid.selected).cloned();
+        let grouping = self.timeline.cycle(&mut self.navigator);
+        match grouping {
+            Some(grouping) => self.notify(format!("Timeline by {}", grouping.label())),
+            None => self.notify("Timeline off".to_string()),
+        }
+        self.update_timeline();
+        if self.grid.visible {
+            // The selection follows its image to where it is now
+            if let Some(selected) = selected {
+                self.grid.selected = self.navigator.image_list.iter().position(|p| *p == selected).unwrap_or(0);
+            }
+            let (w, h) = self.logical_size();
+            self.grid.ensure_visible(w, h, self.navigator.image_list.len());
+            self.window.request_redraw();
+        } else if grouping.is_some() {
+            self.toggle_grid();
+        }
+        self.update_window_title();
+    }
+
+    /// Keep the gallery's headers in step with the list and the capture times indexed so
+    /// far, and render the titles coming into view. Choosing another order ends the timeline.
+    fn update_timeline(&mut self) {
+        if let Some(headers) = self.timeline.update(&self.navigator) {
+            let titles: std::collections::HashSet<&String> = headers.iter().map(|h| &h.title).collect();
+            self.header_labels.retain(|title, _| titles.contains(title));
+            self.grid.headers = headers;
+        }
+        if self.grid.headers.is_empty() || !self.grid.visible {
+            return;
+        }
+        let scale = self.text_scale();
+        let titles: Vec<String> = (self.grid_headers().into_iter())
+            .map(|(header, _)| header.title.clone())
+            .filter(|title| !self.header_labels.contains_key(title))
+            .collect();
+        for title in titles {
+            let label = image::DynamicImage::ImageRgb8(crate::montage::label(&title, scale));
+            match crate::thumbnail::GpuThumbnail::new(&self.device, &self.queue, &self.renderer.texture_bind_group_layout, &label) {
+                Ok(label) => {
+                    self.header_labels.insert(title, label);
+                }
+                Err(e) => tracing::warn!("Failed to upload a timeline header: {:?}", e),
+            }
+        }
+    }
+
+    pub fn grid_visible(&self) -> bool {
+        self.grid.visible
+    }
+
+    pub fn toggle_grid(&mut self) {
+        self.grid.visible = !self.grid.visible;
+        if self.grid.visible {
+            self.map.visible = false;
+            if let Some(current) = &self.navigator.current_path {
+                self.grid.selected = self.navigator.image_list.iter().position(|p| p == current).unwrap_or(0);
+            }
+            let (w, h) = self.logical_size();
+            self.grid.ensure_visible(w, h, self.navigator.image_list.len());
+        }
+        self.window.request_redraw();
+    }
+
+    /// Keyboard handling while the grid is shown. Returns the image to open, if any.
+    pub fn grid_input(&mut self, keycode: winit::keyboard::KeyCode, modifiers: winit::keyboard::ModifiersState) -> Option<PathBuf> {
+        use winit::keyboard::KeyCode;
+        let (w, h) = self.logical_size();
+        let count = self.navigator.image_list.len();
+        let page = crate::grid::Grid::rows_per_page(h);
+        match keycode {
+            KeyCode::ArrowLeft => self.grid.move_selection(-1, 0, w, count),
+            KeyCode::ArrowRight => self.grid.move_selection(1, 0, w, count),
+            KeyCode::ArrowUp => self.grid.move_selection(0, -1, w, count),
+            KeyCode::ArrowDown => self.grid.move_selection(0, 1, w, count),
+            KeyCode::PageUp => self.grid.move_selection(0, -page, w, count),
+            KeyCode::PageDown => self.grid.move_selection(0, page, w, count),
+            KeyCode::Home => self.grid.selected = 0,
+            KeyCode::End => self.grid.selected = count.saturating_sub(1),
+            KeyCode::Enter | KeyCode::NumpadEnter => {
+                let path = self.navigator.image_list.get(self.grid.selected).cloned();
+                if path.is_some() {
+                    self.toggle_grid();
+                }
+                return path;
+            }
+            KeyCode::KeyG if modifiers.shift_key() => self.cycle_timeline(),
+            KeyCode::KeyG | KeyCode::Escape => self.toggle_grid(),
+            _ => {}
+        }
+        self.grid.ensure_visible(w, h, self.navigator.image_list.len());
+        self.window.request_redraw();
+        None
+    }
+
+    /// The grid cell under the mouse cursor; opening it closes the grid.
+    pub fn grid_item_at_cursor(&mut self) -> Option<PathBuf> {
+        if !self.grid.visible {
+            return None;
+        }
+        let (x, y) = self.last_mouse_pos?;
+        let index = self
+            .grid_cells()
+            .into_iter()
+            .find(|(_, rect)| rect.contains(x as f32, y as f32))
+            .map(|(i, _)| i)?;
+        self.grid.selected = index;
+        self.toggle_grid();
+        self.navigator.image_list.get(index).cloned()
+    }
+
+    pub fn map_visible(&self) -> bool {
+        self.map.visible
+    }
+
+    pub fn toggle_map(&mut self) {
+        if self.map.visible {
+            self.map.visible = false;
+        } else {
+            self.grid.visible = false;
+            self.map.show();
+            self.map.fit(self.size.width as f32, self.size.height as f32);
+        }
+        self.window.request_redraw();
+    }
+
+    /// Keyboard handling while the map is shown.
+    pub fn map_input(&mut self, keycode: winit::keyboard::KeyCode) {
+        use winit::keyboard::KeyCode;
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        match keycode {
+            KeyCode::ArrowLeft => self.map.pan_by(MAP_PAN_STEP, 0.0),
+            KeyCode::ArrowRight => self.map.pan_by(-MAP_PAN_STEP, 0.0),
+            KeyCode::ArrowUp => self.map.pan_by(0.0, MAP_PAN_STEP),
+            KeyCode::ArrowDown => self.map.pan_by(0.0, -MAP_PAN_STEP),
+            KeyCode::Equal | KeyCode::NumpadAdd => self.map.zoom_at(1.0, w / 2.0, h / 2.0, w, h),
+            KeyCode::Minus | KeyCode::NumpadSubtract => self.map.zoom_at(-1.0, w / 2.0, h / 2.0, w, h),
+            KeyCode::Home => self.map.fit(w, h),
+            KeyCode::KeyM | KeyCode::Escape => self.toggle_map(),
+            _ => {}
+        }
+        self.window.request_redraw();
+    }
+
+    /// The image whose map marker is under the mouse cursor; opening it closes the map.
+    pub fn map_item_at_cursor(&mut self) -> Option<PathBuf> {
+        if !self.map.visible {
+            return None;
+        }
+        let (x, y) = self.last_mouse_pos?;
+        let path = self.map.marker_at(self.size.width as f32, self.size.height as f32, x as f32, y as f32)?;
+        self.mouse_pressed = false;
+        self.toggle_map();
+        Some(path)
+    }
+
+    fn update_map(&mut self) {
+        if !self.map.visible {
+            return;
+        }
+        self.map.locate(&self.navigator.image_list);
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        self.map.update(&self.device, &self.queue, &self.renderer.texture_bind_group_layout, w, h);
+    }
+
+    /// Hand new folder listings to the indexer and take in what it found.
+    fn update_index(&mut self) {
+        let listing = (self.navigator.root().map(PathBuf::from), self.navigator.image_list.len());
+        if listing != self.indexed_listing {
+            self.indexer.request(&self.navigator.image_list);
+            self.indexed_listing = listing;
+        }
+        let found = self.indexer.poll();
+        if found.is_empty() {
+            // Re-sort once the indexer has caught up rather than on every result
+            if std::mem::take(&mut self.capture_times_changed) && self.navigator.sort == crate::navigator::SortOrder::Captured {
+                self.navigator.set_sort(self.navigator.sort);
+                self.timeline.invalidate();
+            }
+            return;
+        }
+        for (path, info) in found {
+            if let Some(taken) = &info.taken {
+                self.navigator.capture_times.insert(path.clone(), taken.clone());
+                self.capture_times_changed = true;
+            }
+            self.file_index.insert(path, info);
+        }
+    }
+
+    fn update_thumbnails(&mut self) {
+        for (path, img) in self.thumbnailer.poll() {
+            self.exposure_stats.insert(path.clone(), crate::badges::ExposureStats::measure(&img));
+            match crate::thumbnail::GpuThumbnail::new(&self.device, &self.queue, &self.renderer.texture_bind_group_layout, &img) {
+                Ok(thumb) => {
+                    self.thumbnails.insert(path, thumb);
+                }
+                Err(e) => tracing::warn!("Failed to upload thumbnail: {:?}", e),
+            }
+        }
+
+        for i in self.thumbnails_on_screen() {
+            let path = self.navigator.image_list[i].clone();
+            self.thumbnailer.request(&path);
+        }
+    }
+
+    /// Positions in the list of the images whose thumbnails the grid or filmstrip shows.
+    fn thumbnails_on_screen(&self) -> Vec<usize> {
+        if self.grid.visible {
+            self.grid_cells().into_iter().map(|(i, _)| i).collect()
+        } else if self.filmstrip_visible {
+            self.filmstrip_cells().into_iter().map(|(i, _)| i).collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Add up what is held in memory and, past `[cache] memory_budget_mb`, drop what can
+    /// be loaded again: textures kept for reuse and thumbnails off screen first, then
+    /// tiles off screen.
+    fn account_memory(&mut self) {
+        let budget = self.settings.cache.memory_budget_mb;
+        self.memory = self.measure_memory();
+        if !self.memory.over(budget) {
+            return;
+        }
+        self.texture_pool.clear();
+        let on_screen: std::collections::HashSet<&PathBuf> =
+            self.thumbnails_on_screen().into_iter().map(|i| &self.navigator.image_list[i]).collect();
+        let dropped: Vec<PathBuf> = self.thumbnails.keys().filter(|p| !on_screen.contains(p)).cloned().collect();
+        for path in dropped {
+            self.thumbnails.remove(&path);
+            self.thumbnailer.forget(&path);
+        }
+        self.memory = self.measure_memory();
+        if self.memory.over(budget) {
+            if let Some(tiled) = self.tiled.as_mut() {
+                tiled.trim();
+            }
+            self.map.trim();
+            self.memory = self.measure_memory();
+        }
+    }
+
+    fn measure_memory(&self) -> crate::memory::Usage {
+        use crate::memory::Category;
+        let mut usage = crate::memory::Usage::default();
+        usage.add_gpu(Category::Image, self.diffuse_texture.bytes());
+        usage.add_gpu(Category::Image, self.texture_pool.bytes());
+        if let Some(upload) = &self.upload {
+            usage.add_cpu(Category::Image, upload.bytes());
+        }
+        if let Some(animation) = &self.animation {
+            usage.add_cpu(Category::Animation, animation.bytes());
+        }
+        if let Some(tiled) = &self.tiled {
+            let (cpu, gpu) = tiled.memory_bytes();
+            usage.add_cpu(Category::Pyramid, cpu);
+            usage.add_gpu(Category::Tiles, gpu);
+        }
+        usage.add_gpu(Category::Thumbnails, self.thumbnails.values().map(|t| t.bytes()).sum());
+        usage.add_gpu(Category::Map, self.map.gpu_bytes());
+        let compare = self.compare_textures.values().chain(&self.compare_labels);
+        usage.add_gpu(Category::Compare, compare.map(|t| t.bytes()).sum());
+        usage
+    }
+
+    fn draw_badges(&self, batch: &mut crate::quad::QuadBatch, path: &std::path::Path, cell: crate::quad::Rect, histogram: bool) {
+        use crate::navigator::{has_extension, RAW_EXTENSIONS};
+        let info = self.file_index.get(path);
+        let marks = crate::badges::Marks {
+            raw: has_extension(path, RAW_EXTENSIONS),
+            jpeg: has_extension(path, &["jpg", "jpeg"]),
+            rating: info.and_then(|i| i.rating),
+            pick: info.is_some_and(|i| i.pick),
+            located: info.is_some_and(|i| i.position.is_some()),
+            exposure: self.exposure_stats.get(path),
+        };
+        // Badges are sized for logical pixels
+        let s = self.scale_factor;
+        for (rect, color) in crate::badges::layout(cell.scaled(1.0 / s), &marks, histogram) {
+            batch.solid(rect.scaled(s), color);
+        }
+    }
+
+    /// Render the current view, without the interface, to a PNG next to the image:
+    /// at the window's resolution or `[snapshot] width` wide.
+    pub fn snapshot_view(&mut self) {
+        if self.read_only || self.grid.visible || self.map.visible {
+            return;
+        }
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let max = self.device.limits().max_texture_dimension_2d;
+        let (w, h) = crate::snapshot::size((self.size.width, self.size.height), self.settings.snapshot.width, max);
+        let target = crate::snapshot::Target::new(&self.device, w, h, self.config.format);
+        let mut layers = crate::quad::QuadBatch::new();
+        self.draw_image_layers(&mut layers);
+        // Quads are placed relative to the window, so they scale with the target
+        self.quad_renderer.prepare(&self.queue, self.size, &layers);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Snapshot Encoder") });
+        self.encode_frame(&mut encoder, &target.view, &layers);
+        let pixels = target.read(&self.device, &self.queue, encoder);
+        drop(layers);
+
+        let img = match pixels {
+            Ok(img) => img,
+            Err(e) => {
+                tracing::warn!("Failed to render snapshot: {:?}", e);
+                self.notify("Could not save the view".to_string());
+                return;
+            }
+        };
+        let output = crate::snapshot::output_path(&path);
+        let name = output.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let (space, strip) = (self.export_space, self.settings.export.strip_metadata);
+        std::thread::spawn(move || {
+            let mut metadata = crate::export::Metadata::read(&path);
+            if strip {
+                metadata = metadata.colors_only();
+            }
+            if let Err(e) = crate::export::save(&image::DynamicImage::ImageRgba8(img), &output, space, &metadata) {
+                tracing::warn!("Failed to save {:?}: {:?}", output, e);
+            }
+        });
+        self.notify(format!("Saving view to {} ({}x{})", name, w, h));
+    }
+
+    /// Render what is being compared, panes, labels and the shared view as they are, to a
+    /// PNG next to the focused image, the way `snapshot_view` saves a single view.
+    fn export_compare(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let Some(path) = self.compare.as_ref().and_then(|c| c.paths.get(c.focus).cloned()) else {
+            return;
+        };
+        let max = self.device.limits().max_texture_dimension_2d;
+        let (w, h) = crate::snapshot::size((self.size.width, self.size.height), self.settings.snapshot.width, max);
+        let target = crate::snapshot::Target::new(&self.device, w, h, self.config.format);
+        let mut layers = crate::quad::QuadBatch::new();
+        self.draw_compare(&mut layers, false);
+        self.quad_renderer.prepare(&self.queue, self.size, &layers);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Compare Export Encoder") });
+        self.encode_frame(&mut encoder, &target.view, &layers);
+        let pixels = target.read(&self.device, &self.queue, encoder);
+        drop(layers);
+
+        let img = match pixels {
+            Ok(img) => img,
+            Err(e) => {
+                tracing::warn!("Failed to render comparison: {:?}", e);
+                self.notify("Could not save the comparison".to_string());
+                return;
+            }
+        };
+        let output = crate::snapshot::output_path_named(&path, "compare");
+        let name = output.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let space = self.export_space;
+        std::thread::spawn(move || {
+            // Only the color profile applies to a picture of several images
+            let metadata = crate::export::Metadata::read(&path).colors_only();
+            if let Err(e) = crate::export::save(&image::DynamicImage::ImageRgba8(img), &output, space, &metadata) {
+                tracing::warn!("Failed to save {:?}: {:?}", output, e);
+            }
+        });
+        self.notify(format!("Saving comparison to {} ({}x{})", name, w, h));
+    }
+
+    /// What belongs to the picture rather than the interface: the matte and full-resolution tiles.
+    fn draw_image_layers<'b>(&'b self, batch: &mut crate::quad::QuadBatch<'b>) {
+        if self.matte && !self.grid.visible && !self.map.visible {
+            let [r, g, b] = crate::config::parse_color(&self.settings.matte.color).unwrap_or([1.0, 1.0, 1.0]);
+            let image = self.uv_rect_to_screen([0.0, 0.0, 1.0, 1.0]);
+            for strip in crate::matte::frame(image, self.settings.matte.width) {
+                batch.solid(strip, [r as f32, g as f32, b as f32, 1.0]);
+            }
+        }
+        // Until a large image is all on the GPU, the rows still missing show the background
+        if let (Some(upload), false, false) = (&self.upload, self.grid.visible, self.map.visible) {
+            let c = self.background;
+            let missing = self.uv_rect_to_screen([0.0, upload.progress(), 1.0, 1.0]);
+            batch.solid(missing, [c.r as f32, c.g as f32, c.b as f32, 1.0]);
+        }
+        // Tiles would cover the redaction preview, adjustments and exposure overlays, which are rendered into the overview
+        let overview_only = self.redactor.active || self.view_adjusted();
+        if let (Some(tiled), false, false) = (&self.tiled, overview_only, self.grid.visible) {
+            for (uv, bind_group) in tiled.visible() {
+                batch.textured(self.uv_rect_to_screen(uv), bind_group);
+            }
+        }
+    }
+
+    fn build_overlay(&self) -> crate::quad::QuadBatch<'_> {
+        let mut batch = crate::quad::QuadBatch::new();
+        self.draw_image_layers(&mut batch);
+        if let (Some(i), false, false) = (self.framing, self.grid.visible, self.map.visible) {
+            let image = self.uv_rect_to_screen([0.0, 0.0, 1.0, 1.0]);
+            let crop = crate::framing::crop_rect(image, crate::framing::PRESETS[i].ratio);
+            for strip in crate::framing::outside(image, crop) {
+                batch.solid(strip, [0.0, 0.0, 0.0, 0.55]);
+            }
+            batch.outline(crop, 1.0, [1.0, 1.0, 1.0, 0.8]);
+        }
+        if let (true, Some(rect)) = (self.crop.active, self.crop.rect) {
+            let image = self.uv_rect_to_screen([0.0, 0.0, 1.0, 1.0]);
+            let crop = self.uv_rect_to_screen(rect);
+            for strip in crate::framing::outside(image, crop) {
+                batch.solid(strip, [0.0, 0.0, 0.0, 0.6]);
+            }
+            // Rule of thirds
+            for i in 1..3 {
+                let t = i as f32 / 3.0;
+                batch.solid(crate::quad::Rect::new(crop.x + crop.w * t, crop.y, 1.0, crop.h), [1.0, 1.0, 1.0, 0.4]);
+                batch.solid(crate::quad::Rect::new(crop.x, crop.y + crop.h * t, crop.w, 1.0), [1.0, 1.0, 1.0, 0.4]);
+            }
+            batch.outline(crop, 1.0, [1.0, 1.0, 1.0, 0.9]);
+            for (x, y) in [(crop.x, crop.y), (crop.x + crop.w, crop.y), (crop.x, crop.y + crop.h), (crop.x + crop.w, crop.y + crop.h)] {
+                let handle = crate::quad::Rect::new(x - CROP_HANDLE / 2.0, y - CROP_HANDLE / 2.0, CROP_HANDLE, CROP_HANDLE);
+                batch.solid(handle, [1.0, 1.0, 1.0, 1.0]);
+            }
+        }
+        if let (Some(split), false, false, false) = (self.before_after, self.showing_original, self.grid.visible, self.map.visible) {
+            let area = crate::quad::Rect::new(0.0, 0.0, self.size.width as f32, self.size.height as f32);
+            self.draw_divider(&mut batch, split * area.w, area);
+        }
+        if self.adjust.active && !self.grid.visible && !self.map.visible {
+            self.draw_adjust_panel(&mut batch);
+        }
+        if self.redactor.active {
+            for b in self.redactor.all_boxes() {
+                batch.outline(self.uv_rect_to_screen(b), 2.0, [0.9, 0.2, 0.2, 1.0]);
+            }
+        }
+        if let (Some((length, _)), false) = (self.scale_bar(), self.grid.visible) {
+            // Bottom left, above the filmstrip when it is shown
+            let h = self.size.height as f32;
+            let bottom = if self.filmstrip_visible { self.strip_rect().y } else { h } - 16.0;
+            let bar = crate::quad::Rect::new(16.0, bottom - 4.0, length, 4.0);
+            batch.solid(bar.inset(-1.0), [0.0, 0.0, 0.0, 0.7]);
+            batch.solid(bar, [1.0, 1.0, 1.0, 1.0]);
+            for x in [bar.x, bar.x + bar.w - 2.0] {
+                batch.solid(crate::quad::Rect::new(x, bar.y - 6.0, 2.0, 6.0), [1.0, 1.0, 1.0, 1.0]);
+            }
+        }
+        if self.grid.visible {
+            let (w, h) = (self.size.width as f32, self.size.height as f32);
+            batch.solid(crate::quad::Rect::new(0.0, 0.0, w, h), [0.08, 0.08, 0.08, 1.0]);
+            let label_height = crate::montage::label_height(self.text_scale()) as f32;
+            for (header, rect) in self.grid_headers() {
+                if let Some(label) = self.header_labels.get(&header.title) {
+                    let top = rect.y + (rect.h - label_height) / 2.0;
+                    batch.textured(crate::quad::Rect::new(rect.x, top, label_height * label.aspect, label_height), &label.bind_group);
+                }
+            }
+            for (i, cell) in self.grid_cells() {
+                if i == self.grid.selected {
+                    batch.solid(cell.inset(-4.0 * self.scale_factor), [0.95, 0.65, 0.1, 1.0]);
+                }
+                batch.solid(cell, [0.18, 0.18, 0.18, 1.0]);
+                if let Some(thumb) = self.thumbnails.get(&self.navigator.image_list[i]) {
+                    batch.textured(crate::filmstrip::fit(cell, thumb.aspect), &thumb.bind_group);
+                }
+                self.draw_badges(&mut batch, &self.navigator.image_list[i], cell, true);
+            }
+        } else if self.filmstrip_visible {
+            batch.solid(self.strip_rect(), [0.0, 0.0, 0.0, 0.6]);
+            for (i, cell) in self.filmstrip_cells() {
+                let path = &self.navigator.image_list[i];
+                if self.navigator.current_path.as_ref() == Some(path) {
+                    batch.solid(cell.inset(-3.0 * self.scale_factor), [0.95, 0.65, 0.1, 1.0]);
+                }
+                batch.solid(cell, [0.2, 0.2, 0.2, 1.0]);
+                if let Some(thumb) = self.thumbnails.get(path) {
+                    batch.textured(crate::filmstrip::fit(cell, thumb.aspect), &thumb.bind_group);
+                }
+                self.draw_badges(&mut batch, path, cell, false);
+            }
+        }
+        if self.map.visible {
+            let (w, h) = (self.size.width as f32, self.size.height as f32);
+            batch.solid(crate::quad::Rect::new(0.0, 0.0, w, h), [0.67, 0.78, 0.85, 1.0]);
+            for (rect, bind_group) in self.map.tiles(w, h) {
+                batch.textured(rect, bind_group);
+            }
+            let size = crate::map::MARKER_SIZE;
+            for (path, (x, y)) in self.map.markers(w, h) {
+                let marker = crate::quad::Rect::new(x - size / 2.0, y - size / 2.0, size, size);
+                let color = if self.navigator.current_path.as_deref() == Some(path) {
+                    [0.95, 0.65, 0.1, 1.0]
+                } else {
+                    [0.85, 0.15, 0.15, 1.0]
+                };
+                batch.solid(marker, color);
+                batch.outline(marker, 2.0, [1.0, 1.0, 1.0, 1.0]);
+            }
+        }
+        if let (Some((_, label)), false, false) = (&self.animation_label, self.grid.visible, self.map.visible) {
+            // Bottom left like the scale bar, which animations don't have
+            let height = crate::montage::label_height(self.text_scale()) as f32;
+            let margin = 16.0 * self.scale_factor;
+            let bottom = if self.filmstrip_visible { self.strip_rect().y } else { self.size.height as f32 } - margin;
+            let rect = crate::quad::Rect::new(margin, bottom - height, height * label.aspect, height);
+            batch.solid(rect.inset(-4.0 * self.scale_factor), [0.0, 0.0, 0.0, 0.6]);
+            batch.textured(rect, &label.bind_group);
+        }
+        self.draw_compare(&mut batch, true);
+        batch
+    }
+
+    /// Follow a new window size, in physical pixels. A fitted image is fitted again; a
+    /// zoomed one keeps its image pixels per window pixel, so moving between monitors of
+    /// different density doesn't change what "100%" shows.
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            let fitted = self.at_default_zoom();
+            let old_height = self.size.height.max(1) as f32;
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.device, &self.config);
+            
+            self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+            if fitted {
+                self.camera.zoom = self.default_zoom();
+            } else {
+                // The window spans 2 * zoom world units over its height in pixels
+                self.camera.zoom *= new_size.height as f32 / old_height;
+            }
+        }
+    }
+
+    /// Whether the view is as a freshly opened image shows it, centered at the default zoom.
+    fn at_default_zoom(&self) -> bool {
+        let zoom = self.default_zoom();
+        self.camera.x == 0.0 && self.camera.y == 0.0 && (self.camera.zoom - zoom).abs() <= zoom * 1e-4
+    }
+
+    pub fn input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::*;
+        if self.ui.on_window_event(self.window, event) {
+            return true;
+        }
+        if self.compare.is_some() {
+            return self.compare_mouse(event);
+        }
+        match event {
+            // The middle button drags the view in any mode
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Middle,
+                ..
+            } => {
+                self.middle_pressed = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                if self.map.visible {
+                    // Dragging pans the map; a press is also passed on to check for a marker
+                    self.mouse_pressed = *state == ElementState::Pressed;
+                    return !self.mouse_pressed;
+                }
+                if *state == ElementState::Pressed && (self.grid.visible || self.cursor_in_filmstrip()) {
+                    return false;
+                }
+                if self.redactor.active {
+                    if *state == ElementState::Pressed {
+                        if let Some(uv) = self.cursor_uv() {
+                            self.redactor.begin_drag(uv);
+                        }
+                    } else {
+                        self.redactor.end_drag();
+                    }
+                    self.window.request_redraw();
+                    return true;
+                }
+                self.dragging_before_after = false;
+                if let (Some(split), ElementState::Pressed, Some((x, _))) = (self.before_after, state, self.last_mouse_pos) {
+                    if (x as f32 - split * self.size.width as f32).abs() <= WIPE_GRAB * self.scale_factor {
+                        self.dragging_before_after = true;
+                        return true;
+                    }
+                }
+                if self.adjust.active {
+                    self.adjust.dragging = None;
+                    if *state == ElementState::Pressed {
+                        if let Some(i) = self.adjust_slider_at_cursor() {
+                            self.adjust.selected = i;
+                            self.adjust.dragging = Some(i);
+                            self.drag_adjust_slider();
+                            return true;
+                        }
+                    }
+                }
+                if self.crop.active {
+                    if *state == ElementState::Pressed {
+                        if let Some(uv) = self.cursor_uv() {
+                            // Corner handles are grabbed within a few window pixels
+                            let image = self.uv_rect_to_screen([0.0, 0.0, 1.0, 1.0]);
+                            let grab = (CROP_HANDLE / image.w.abs().max(1.0), CROP_HANDLE / image.h.abs().max(1.0));
+                            self.crop.begin_drag(uv, grab);
+                        }
+                    } else {
+                        self.crop.end_drag();
+                    }
+                    self.window.request_redraw();
+                    return true;
+                }
+                self.mouse_pressed = *state == ElementState::Pressed;
+                self.click(*state);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if self.middle_pressed {
+                    if let Some((last_x, last_y)) = self.last_mouse_pos {
+                        self.apply_gesture(crate::touch::Gesture::Pan((position.x - last_x) as f32, (position.y - last_y) as f32));
+                    }
+                    self.last_mouse_pos = Some((position.x, position.y));
+                    return true;
+                }
+                if self.redactor.dragging() {
+                    self.last_mouse_pos = Some((position.x, position.y));
+                    if let Some(uv) = self.cursor_uv() {
+                        self.redactor.update_drag(uv);
+                    }
+                    self.window.request_redraw();
+                    return true;
+                }
+                if self.dragging_before_after {
+                    self.last_mouse_pos = Some((position.x, position.y));
+                    self.before_after = Some((position.x as f32 / self.size.width.max(1) as f32).clamp(0.0, 1.0));
+                    self.window.request_redraw();
+                    return true;
+                }
+                if self.adjust.dragging.is_some() {
+                    self.last_mouse_pos = Some((position.x, position.y));
+                    self.drag_adjust_slider();
+                    return true;
+                }
+                if self.crop.dragging() {
+                    self.last_mouse_pos = Some((position.x, position.y));
+                    if let Some(uv) = self.cursor_uv() {
+                        self.crop.update_drag(uv, self.image_aspect);
+                    }
+                    self.window.request_redraw();
+                    return true;
+                }
+                if let (true, true, Some((last_x, last_y))) = (self.map.visible, self.mouse_pressed, self.last_mouse_pos) {
+                    self.map.pan_by((position.x - last_x) as f32, (position.y - last_y) as f32);
+                    self.last_mouse_pos = Some((position.x, position.y));
+                    return true;
+                }
+                if self.mouse_pressed {
+                    if let Some((last_x, last_y)) = self.last_mouse_pos {
+                        let dx = position.x - last_x;
+                        let dy = position.y - last_y;
+                        
+                        // Convert screen delta to camera space
+                        // Screen width corresponds to 2.0 * aspect * zoom
+                        let scale_x = (2.0 * self.camera.aspect * self.camera.zoom) / self.config.width as f32;
+                        let scale_y = (2.0 * self.camera.zoom) / self.config.height as f32;
+                        
+                        self.camera.x -= dx as f32 * scale_x;
+                        self.camera.y += dy as f32 * scale_y; // Y is inverted in screen coords vs world
+                        
+                        self.window.request_redraw();
+                    }
+                }
+                self.last_mouse_pos = Some((position.x, position.y));
+                true
+            }
+            WindowEvent::Touch(touch) => {
+                let position = (touch.location.x, touch.location.y);
+                let now = std::time::Instant::now();
+                for gesture in self.touches.handle(touch.id, touch.phase, position, now, self.scale_factor as f64) {
+                    self.apply_gesture(gesture);
+                }
+                true
+            }
+            WindowEvent::TouchpadMagnify { delta, .. } => {
+                let (w, h) = (self.size.width as f32, self.size.height as f32);
+                let (x, y) = self.last_mouse_pos.map_or((w / 2.0, h / 2.0), |(x, y)| (x as f32, y as f32));
+                self.apply_gesture(crate::touch::Gesture::Zoom(1.0 / (1.0 + *delta as f32).max(0.1), (x, y)));
+                true
+            }
+            // Two fingers on a touchpad pan the image; the grid and map scroll and zoom as with a wheel
+            WindowEvent::MouseWheel { delta: MouseScrollDelta::PixelDelta(pos), .. } if !self.grid.visible && !self.map.visible => {
+                self.apply_gesture(crate::touch::Gesture::Pan(pos.x as f32, pos.y as f32));
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let scroll = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 100.0, // Arbitrary scaling
+                };
+
+                if self.map.visible {
+                    let (w, h) = (self.size.width as f32, self.size.height as f32);
+                    let (x, y) = self.last_mouse_pos.unwrap_or((w as f64 / 2.0, h as f64 / 2.0));
+                    self.map.zoom_at(scroll as f64 / 2.0, x as f32, y as f32, w, h);
+                    return true;
+                }
+
+                if self.grid.visible {
+                    let (w, h) = self.logical_size();
+                    self.grid.scroll_by(-scroll * 60.0, w, h, self.navigator.image_list.len());
+                    self.window.request_redraw();
+                    return true;
+                }
+                
+                if scroll > 0.0 {
+                    self.camera.zoom *= 0.9;
+                } else {
+                    self.camera.zoom *= 1.1;
+                }
+                self.window.request_redraw();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Carry out a touch or touchpad gesture on whatever is shown.
+    fn apply_gesture(&mut self, gesture: crate::touch::Gesture) {
+        use crate::touch::Gesture;
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        if self.map.visible {
+            match gesture {
+                Gesture::Pan(dx, dy) => self.map.pan_by(dx, dy),
+                // Halving the camera zoom is one map zoom level
+                Gesture::Zoom(factor, (x, y)) => self.map.zoom_at(-factor.log2() as f64, x, y, w, h),
+                Gesture::Swipe(_) => {}
+            }
+        } else if self.grid.visible {
+            if let Gesture::Pan(_, dy) = gesture {
+                let (lw, lh) = self.logical_size();
+                self.grid.scroll_by(-dy / self.scale_factor, lw, lh, self.navigator.image_list.len());
+            }
+        } else {
+            match gesture {
+                Gesture::Pan(dx, dy) => self.camera.drag(dx, dy, w, h),
+                Gesture::Zoom(factor, (x, y)) => self.camera.zoom_about(factor, w, h, x, y),
+                Gesture::Swipe(forward) => self.step = Some(forward),
+            }
+        }
+        self.window.request_redraw();
+    }
+
+    /// A flick or click to the next (true) or previous image, once.
+    pub fn take_step(&mut self) -> Option<bool> {
+        self.step.take()
+    }
+
+    pub fn update(&mut self) {
+        if let Some(pan) = self.auto_pan.as_mut() {
+            let frame = pan.advance(std::time::Instant::now(), self.image_aspect, self.camera.aspect, self.size.height as f32);
+            (self.camera.zoom, self.camera.x, self.camera.y) = (frame.zoom, frame.x, frame.y);
+        }
+        if self.sync.is_some() {
+            self.publish_view();
+        }
+        self.renderer.set_camera(&self.queue, &self.camera, self.image_aspect);
+        self.update_upload();
+        self.update_effects();
+        self.update_thumbnails();
+        self.update_compare();
+        self.update_animation();
+        self.update_conversion();
+        self.update_adjust_labels();
+        self.update_index();
+        self.update_places();
+        self.update_timeline();
+        self.update_tiles();
+        self.update_map();
+        self.account_memory();
+        if self.theme_checked.elapsed() >= THEME_CHECK_INTERVAL {
+            self.apply_theme();
+        }
+        if let Some(panel) = self.cache_panel.as_mut() {
+            panel.poll();
+        }
+        self.update_window_title();
+    }
+
+    /// Copy the next band of a large image into its texture.
+    fn update_upload(&mut self) {
+        let Some(upload) = self.upload.as_mut() else {
+            return;
+        };
+        if upload.step(&self.queue, &self.diffuse_texture, crate::texture::UPLOAD_BAND_BYTES) {
+            self.upload = None;
+        }
+        self.window.request_redraw();
+    }
+
+    /// Auto-pan moves the view on every display refresh, so it draws as fast as frames
+    /// are presented rather than on a schedule.
+    pub fn auto_panning(&self) -> bool {
+        self.auto_pan.is_some()
+    }
+
+    /// When the window next needs drawing without input: right away while a large image
+    /// is uploading, at the next timed change
+    /// (animation frame, spinner, status text or toast going away), every
+    /// `POLL_INTERVAL` while a job reports progress in the title bar, or never.
+    /// Background results wake the event loop themselves.
+    pub fn next_redraw(&self) -> Option<std::time::Instant> {
+        use crate::redraw::earliest;
+        let now = std::time::Instant::now();
+        // The diagnostics panel keeps its frame times and queues current
+        let polling = self.conversion.is_some()
+            || self.duplicate_scan.is_some()
+            || self.cache_panel.is_some()
+            || self.ui.panels.is_open("diagnostics");
+        let mut due = polling.then_some(now + crate::redraw::POLL_INTERVAL);
+        due = earliest(due, self.upload.as_ref().map(|_| now));
+        due = earliest(due, self.animation.as_ref().and_then(|a| a.next_change()));
+        due = earliest(due, self.ui.next_repaint());
+        due = earliest(due, self.status.as_ref().map(|(_, shown)| *shown + STATUS_DURATION).filter(|at| *at > now));
+        earliest(due, Some(self.theme_checked + THEME_CHECK_INTERVAL))
+    }
+
+    /// Show the animation frame now due, and keep the frame counter current.
+    fn update_animation(&mut self) {
+        let Some(animation) = self.animation.as_mut() else {
+            return;
+        };
+        if animation.advance(std::time::Instant::now()) {
+            self.diffuse_texture.write(&self.queue, animation.frame());
+        }
+        let status = animation.status();
+        if self.animation_label.as_ref().is_none_or(|(text, _)| *text != status) {
+            let label = image::DynamicImage::ImageRgb8(crate::montage::label(&status, self.text_scale()));
+            self.animation_label = crate::thumbnail::GpuThumbnail::new(&self.device, &self.queue, &self.renderer.texture_bind_group_layout, &label)
+                .ok()
+                .map(|label| (status, label));
+        }
+    }
+
+    /// Play or pause the animation, step through it frame by frame, change its
+    /// speed or whether it loops.
+    pub fn control_animation(&mut self, control: crate::animation::Control) {
+        let Some(animation) = self.animation.as_mut() else {
+            self.notify("Not an animated image".to_string());
+            return;
+        };
+        animation.control(control, std::time::Instant::now());
+        self.diffuse_texture.write(&self.queue, animation.frame());
+        self.window.request_redraw();
+    }
+
+    /// Whether the current image has frames to save, saying why not otherwise.
+    pub fn can_extract_frames(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        if self.animation.is_none() {
+            self.notify("Not an animated image".to_string());
+            return false;
+        }
+        true
+    }
+
+    /// Save every frame of the current animation into `dir` as numbered PNGs.
+    pub fn extract_frames(&mut self, dir: PathBuf) {
+        let Some(path) = self.navigator.current_path.clone() else {
+            return;
+        };
+        let overwrite = !self.settings.safety.confirm_overwrite;
+        std::thread::spawn(move || match crate::frames::extract(&path, &dir, overwrite) {
+            Ok(paths) => tracing::info!("Saved {} frame(s) to {:?}", paths.len(), dir),
+            Err(e) => tracing::warn!("Failed to save frames: {:?}", e),
+        });
+        self.notify("Saving frames".to_string());
+    }
+
+    /// Full-resolution image pixels across one window pixel at the current zoom.
+    fn image_pixels_per_screen_pixel(&self) -> f32 {
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        let (u0, _) = self.camera.screen_to_uv(self.image_aspect, w, h, 0.0, 0.0);
+        let (u1, _) = self.camera.screen_to_uv(self.image_aspect, w, h, w, h);
+        (u1 - u0) * self.image_size.0 as f32 / w.max(1.0)
+    }
+
+    /// Scale bar for geo-referenced images: its length in window pixels and label.
+    fn scale_bar(&self) -> Option<(f32, String)> {
+        let geo = self.geo.as_ref()?;
+        let (u, v) = self.camera.screen_to_uv(
+            self.image_aspect,
+            self.size.width as f32,
+            self.size.height as f32,
+            self.size.width as f32 / 2.0,
+            self.size.height as f32 / 2.0,
+        );
+        let (x, y) = (u as f64 * self.image_size.0 as f64, v as f64 * self.image_size.1 as f64);
+        let metres = geo.metres_per_pixel(x, y)? * self.image_pixels_per_screen_pixel() as f64;
+        let (length, label) = crate::geotiff::scale_bar(metres, SCALE_BAR_MAX_WIDTH)?;
+        Some((length as f32, label))
+    }
+
+    fn update_tiles(&mut self) {
+        let (w, h) = (self.size.width as f32, self.size.height as f32);
+        let (u0, v0) = self.camera.screen_to_uv(self.image_aspect, w, h, 0.0, 0.0);
+        let (u1, v1) = self.camera.screen_to_uv(self.image_aspect, w, h, w, h);
+        let image_pixels_per_screen_pixel = self.image_pixels_per_screen_pixel();
+        let Some(tiled) = self.tiled.as_mut() else {
+            return;
+        };
+        tiled.update(
+            &self.device,
+            &self.queue,
+            &self.renderer.texture_bind_group_layout,
+            [u0, v0, u1, v1],
+            image_pixels_per_screen_pixel,
+        );
+    }
+    
+    fn update_effects(&mut self) {
+        let boxes = if self.redactor.active { self.redactor.all_boxes() } else { Vec::new() };
+        self.renderer.effects.redact_rects = [[0.0; 4]; crate::redact::MAX_BOXES];
+        for (slot, b) in self.renderer.effects.redact_rects.iter_mut().zip(&boxes) {
+            *slot = *b;
+        }
+        let (w, h) = self.image_size;
+        let block = crate::redact::block_size(w, h) as f32;
+        self.renderer.effects.redact_params = [
+            boxes.len().min(crate::redact::MAX_BOXES) as f32,
+            block / w as f32,
+            block / h as f32,
+            0.0,
+        ];
+        self.renderer.effects.adjust = self.adjust.values.uniform();
+        self.renderer.effects.exposure = self.exposure.uniform(&self.settings.exposure);
+        match &self.lut {
+            Some((_, lut)) => {
+                let ([r0, g0, b0], [r1, g1, b1]) = lut.domain;
+                let intensity = if self.lut_enabled { self.adjust.values.lut_intensity() } else { 0.0 };
+                self.renderer.effects.lut_min = [r0, g0, b0, intensity];
+                self.renderer.effects.lut_max = [r1, g1, b1, lut.size as f32];
+            }
+            None => self.renderer.effects.lut_min[3] = 0.0,
+        }
+        let width = self.size.width as f32;
+        self.renderer.effects.original[0] = match self.before_after {
+            _ if self.showing_original => width,
+            Some(split) => split * width,
+            None => 0.0,
+        };
+        self.renderer.write_effects(&self.queue);
+    }
+
+    fn update_window_title(&self) {
+        if let Some(prompt) = &self.prompt {
+            self.window.set_title(&prompt.display());
+            return;
+        }
+        if let Some(confirmation) = &self.confirmation {
+            self.window.set_title(&confirmation.display());
+            return;
+        }
+        if let Some(panel) = &self.cache_panel {
+            self.window.set_title(&panel.display(self.read_only));
+            return;
+        }
+        if let Some((text, _)) = self.status.as_ref().filter(|(_, shown)| shown.elapsed() < STATUS_DURATION) {
+            self.window.set_title(&format!("Momemtum - {}", text));
+            return;
+        }
+
+        if let Some(compare) = &self.compare {
+            let name = compare.paths.get(compare.focus).and_then(|p| p.file_name()).unwrap_or_default().to_string_lossy();
+            let hint = match compare.mode {
+                crate::compare::Mode::Blink => format!("Blink: Space to swap with {}, O for side by side", compare.previous + 1),
+                crate::compare::Mode::Wipe => {
+                    format!("Wipe with {}: drag the divider, Space to swap sides, W for side by side", compare.previous + 1)
+                }
+                crate::compare::Mode::Difference => format!(
+                    "Difference with {} x{}: [ and ] to amplify, H for {}, D for side by side",
+                    compare.previous + 1,
+                    compare.gain,
+                    if compare.heatmap { "channels" } else { "heatmap" }
+                ),
+                crate::compare::Mode::Side => "Tab or 1-4 to focus, O to blink, W to wipe, D for difference, S to save, Enter to open".to_string(),
+            };
+            self.window.set_title(&format!(
+                "Momemtum - Compare {}/{}: {} | Zoom: {:.1}x | {}",
+                compare.focus + 1,
+                compare.paths.len(),
+                name,
+                compare.view.zoom,
+                hint
+            ));
+            return;
+        }
+
+        // 100% is one image pixel per physical pixel, whatever the monitor's scale factor
+        let zoom_pct = (100.0 / self.image_pixels_per_screen_pixel().max(1e-6)).round() as i32;
+        let mut title = format!("Momemtum - Zoom: {}%", zoom_pct);
+        
+        if let Some(path) = &self.navigator.current_path {
+            if let Some(name) = self.navigator.display_name(path) {
+                title.push_str(&format!(" | {}", name));
+            }
+            if let Some((index, count)) = self.navigator.position() {
+                title.push_str(&format!(" ({}/{})", index, count));
+            }
+        }
+        
+        // View center in image pixels while pixel peeping
+        if self.image_pixels_per_screen_pixel() < 1.0 {
+            let (u, v) = ((self.camera.x / self.image_aspect + 1.0) / 2.0, (1.0 - self.camera.y) / 2.0);
+            let (x, y) = (u * self.image_size.0 as f32, v * self.image_size.1 as f32);
+            title.push_str(&format!(" | Center: {:.1}, {:.1} px", x, y));
+        }
+
+        if self.load_time.as_millis() > 0 {
+            title.push_str(&format!(" | Load: {:.0}ms", self.load_time.as_secs_f64() * 1000.0));
+        }
+        
+        if self.memory.total() > 0 {
+            title.push_str(&format!(" | Memory: ~{}MB", self.memory.total() / 1024 / 1024));
+        }
+        
+        if let Some(grouping) = self.timeline.grouping() {
+            title.push_str(&format!(" | Timeline by {}", grouping.label()));
+        } else if self.navigator.sort != crate::navigator::SortOrder::Name {
+            title.push_str(&format!(" | Sort: {}", self.navigator.sort.label()));
+        }
+
+        if let Some(i) = self.framing {
+            title.push_str(&format!(" | Frame: {}", crate::framing::PRESETS[i].label));
+        }
+
+        if self.navigator.shuffling() {
+            title.push_str(" | Shuffle");
+        }
+
+        if self.map.visible {
+            title.push_str(&format!(" | Map: {} located | {}", self.map.marker_count(), crate::map::ATTRIBUTION));
+        }
+
+        if let Some(filter) = &self.navigator.filter {
+            title.push_str(&format!(" | Filter: {} ({})", filter.text(), self.navigator.image_list.len()));
+        }
+
+        if let Some((_, progress)) = &self.conversion {
+            title.push_str(&format!(" | Converting {} of {}", progress.done(), progress.total));
+        }
+
+        if let Some(model) = self.exif_data.get("Model") {
+            title.push_str(&format!(" | {}", model));
+        }
+
+        if let Some((lat, lon)) = crate::gps::position(&self.exif_data) {
+            title.push_str(&format!(" | GPS {}", crate::gps::format(lat, lon)));
+        }
+
+        match self.xmp_data.rating {
+            Some(-1) => title.push_str(" | Rejected"),
+            Some(stars @ 1..=5) => {
+                title.push_str(&format!(" | {}{}", "★".repeat(stars as usize), "☆".repeat(5 - stars as usize)));
+            }
+            _ => {}
+        }
+        if self.xmp_data.pick {
+            title.push_str(" | Pick");
+        }
+        if self.navigator.current_path.as_ref().is_some_and(|p| self.favorites.contains(p)) {
+            title.push_str(" | Favorite");
+        }
+
+        if self.rotation != 0 {
+            title.push_str(&format!(" | Rotated {}°, Ctrl+S to save", self.rotation * 90));
+        }
+
+        if let Some(scan) = &self.duplicate_scan {
+            title.push_str(&format!(" | Finding duplicates {} of {}", scan.done(), scan.total));
+        }
+
+        if let Some(duplicates) = &self.duplicates {
+            title.push_str(&format!(
+                " | {}: Page Up/Down for other groups, Enter keeps this one and deletes the rest",
+                duplicates.label()
+            ));
+        } else if let Some(collection) = self.navigator.collection() {
+            title.push_str(&format!(" | {}", collection.label()));
+        } else if self.navigator.current_path.as_deref().is_some_and(|p| self.basket.contains(p)) {
+            title.push_str(&format!(" | In basket ({})", self.basket.len()));
+        }
+
+        if let Some(text) = &self.xmp_data.title {
+            title.push_str(&format!(" | Title: {}", text));
+        }
+        if let Some(note) = &self.xmp_data.description {
+            title.push_str(&format!(" | Note: {}", note));
+        }
+        if !self.xmp_data.keywords.is_empty() {
+            title.push_str(&format!(" | Keywords: {}", self.xmp_data.keywords.join(", ")));
+        }
+        if let Some(rights) = &self.xmp_data.rights {
+            title.push_str(&format!(" | {}", rights));
+        }
+
+        if let (Some(geo), Some((u, v))) = (&self.geo, self.cursor_uv()) {
+            if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+                let (x, y) = (u as f64 * self.image_size.0 as f64, v as f64 * self.image_size.1 as f64);
+                title.push_str(&format!(" | {}", geo.format(x, y)));
+            }
+        }
+        if let Some((_, label)) = self.scale_bar() {
+            title.push_str(&format!(" | Scale bar: {}", label));
+        }
+
+        if self.redactor.active {
+            title.push_str(&format!(" | Redact: {} box(es), Ctrl+S to export", self.redactor.boxes.len()));
+        }
+
+        if self.crop.active {
+            let ratio = self.crop.preset.map_or("free", |i| crate::framing::PRESETS[i].label);
+            match self.crop.rect {
+                Some(rect) => {
+                    let (_, _, w, h) = crate::crop::pixel_rect(rect, self.image_size);
+                    title.push_str(&format!(" | Crop: {}x{} px ({}), A for ratio, Enter to export", w, h, ratio));
+                }
+                None => title.push_str(&format!(" | Crop: drag a selection ({}), A for ratio", ratio)),
+            }
+        }
+
+        if self.showing_original {
+            title.push_str(" | Original");
+        } else if self.before_after.is_some() {
+            title.push_str(" | Before/after: original left of the divider");
+        }
+
+        if let (Some((name, _)), true) = (&self.lut, self.lut_enabled) {
+            title.push_str(&format!(" | LUT: {}", name));
+        }
+
+        if let Some(label) = self.exposure.label() {
+            title.push_str(&format!(" | {}", label));
+        }
+
+        if self.adjust.active {
+            title.push_str(" | Adjust: Up/Down to pick, Left/Right to change, Backspace to reset, 0 for all");
+        } else if !self.adjust.values.is_neutral() {
+            title.push_str(" | Adjusted, Shift+A to reset");
+        }
+
+        if self.read_only {
+            title.push_str(" | Read-only");
+        }
+
+        if self.navigator.unlocked {
+            title.push_str(" | Private unlocked");
+        }
+        if let Some(path) = &self.navigator.current_path {
+            if crate::library::is_under_any(path, &self.navigator.private_paths) {
+                title.push_str(" | Private");
+            }
+        }
+        
+        self.window.set_title(&title);
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let overlay = self.build_overlay();
+        self.quad_renderer.prepare(&self.queue, self.size, &overlay);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+        self.encode_frame(&mut encoder, &view, &overlay);
+
+        let info = crate::ui::Info {
+            path: self.navigator.current_path.as_deref(),
+            image_size: self.image_size,
+            exif: &self.exif_data,
+            properties: self.properties.as_ref(),
+            memory: &self.memory,
+            memory_budget_mb: self.settings.cache.memory_budget_mb,
+            frame_times: &self.frame_times,
+            surface_format: self.config.format,
+            present_mode: self.config.present_mode,
+            texture_size: (self.diffuse_texture.texture.width(), self.diffuse_texture.texture.height()),
+            texture_format: self.diffuse_texture.texture.format(),
+            tiles_in_flight: self.tiled.as_ref().map_or(0, |t| t.in_flight()),
+        };
+        let ui_commands = self.ui.render(self.window, &self.device, &self.queue, &mut encoder, &view, &info);
+
+        self.queue.submit(ui_commands.into_iter().chain(std::iter::once(encoder.finish())));
+        output.present();
+
+        Ok(())
+    }
+
+    /// Record the image and overlay into `target`. Kept apart from surface
+    /// acquisition and presentation so a frame can be drawn into any texture
+    /// view of the surface format, such as one owned by a host application.
+    fn encode_frame<'b>(&'b self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, overlay: &crate::quad::QuadBatch<'b>) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.background),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        self.renderer.draw(&mut render_pass, &self.diffuse_bind_group);
+
+        self.quad_renderer.draw(&mut render_pass, overlay);
+    }
+}
+
+fn background_color(text: &str) -> wgpu::Color {
+    match crate::config::parse_color(text) {
+        Some([r, g, b]) => wgpu::Color { r, g, b, a: 1.0 },
+        None => {
+            tracing::warn!("Invalid background color {:?}", text);
+            wgpu::Color { r: 0.1, g: 0.1, b: 0.1, a: 1.0 }
+        }
     }
 }
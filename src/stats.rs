@@ -0,0 +1,122 @@
+//! Counters for the diagnostics panel: how long frames take to draw, how often the
+//! caches have what is asked of them, and how much work waits on the threads that
+//! decode images, thumbnails and tiles.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Frames the frame time is averaged over
+const FRAMES: usize = 120;
+
+/// Thumbnails found in the disk cache, out of those asked for
+pub static THUMBNAIL_CACHE: HitRate = HitRate::new();
+/// Tiles of a large image wanted on screen that were already on the GPU
+pub static TILE_CACHE: HitRate = HitRate::new();
+/// Images being read and decoded to be shown
+pub static LOADS: AtomicUsize = AtomicUsize::new(0);
+/// Thumbnails requested and not generated yet
+pub static THUMBNAILS: AtomicUsize = AtomicUsize::new(0);
+
+/// Lookups in a cache that found what they were after, and those that didn't.
+#[derive(Debug)]
+pub struct HitRate {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for HitRate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HitRate {
+    pub const fn new() -> Self {
+        Self { hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+
+    pub fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Hits as a fraction of lookups, and the number of lookups; none before the first.
+    pub fn ratio(&self) -> Option<(f64, u64)> {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let lookups = hits + self.misses.load(Ordering::Relaxed);
+        (lookups > 0).then(|| (hits as f64 / lookups as f64, lookups))
+    }
+}
+
+/// One job counted in `counter` until the guard is dropped.
+pub struct Pending(&'static AtomicUsize);
+
+impl Pending {
+    pub fn start(counter: &'static AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// How long the latest frames took to update and draw.
+#[derive(Debug, Default)]
+pub struct FrameTimes {
+    recent: VecDeque<Duration>,
+}
+
+impl FrameTimes {
+    pub fn push(&mut self, time: Duration) {
+        if self.recent.len() == FRAMES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(time);
+    }
+
+    /// Mean and longest of the latest frames.
+    pub fn summary(&self) -> Option<(Duration, Duration)> {
+        let longest = *self.recent.iter().max()?;
+        Some((self.recent.iter().sum::<Duration>() / self.recent.len() as u32, longest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_times_and_hit_rate() {
+        let mut times = FrameTimes::default();
+        assert_eq!(times.summary(), None);
+        times.push(Duration::from_millis(30));
+        for _ in 0..FRAMES {
+            times.push(Duration::from_millis(10));
+        }
+        // The slow frame has dropped out
+        assert_eq!(times.summary(), Some((Duration::from_millis(10), Duration::from_millis(10))));
+        times.push(Duration::from_millis(130));
+        assert_eq!(times.summary().unwrap().1, Duration::from_millis(130));
+
+        let rate = HitRate::new();
+        assert_eq!(rate.ratio(), None);
+        rate.record(true);
+        rate.record(true);
+        rate.record(true);
+        rate.record(false);
+        assert_eq!(rate.ratio(), Some((0.75, 4)));
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let first = Pending::start(&COUNTER);
+        let second = Pending::start(&COUNTER);
+        drop(first);
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 1);
+        drop(second);
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,140 @@
+//! Synchronized pan and zoom between viewer windows. Every window with sync on
+//! maps one small shared file: a window the user pans or zooms writes its view
+//! there, and the others pick it up and show the same image point at the same
+//! magnification, to compare lens copies or edits in separate windows.
+
+use anyhow::Result;
+use memmap2::MmapMut;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often other windows' views are looked for
+const POLL_INTERVAL: Duration = Duration::from_millis(15);
+// Sequence number, writer process id, the view, checksum
+const RECORD_LEN: usize = 8 + 4 + 12 + 8;
+
+/// A view independent of window and image size: the image point at the window
+/// center (texture coordinates) and full-resolution image pixels per window pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub center: (f32, f32),
+    pub scale: f32,
+}
+
+/// Membership in the group of synchronized windows; leaves it when dropped.
+pub struct Link {
+    map: MmapMut,
+    sequence: u64,
+    stop: Arc<AtomicBool>,
+}
+
+impl Link {
+    /// Join the group. `on_view` is called, from a background thread, with every
+    /// view another window publishes.
+    pub fn open(on_view: impl Fn(View) + Send + 'static) -> Result<Self> {
+        let path = shared_path()?;
+        let map = open_shared(&path)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let sequence = decode(&map).map_or(0, |(sequence, _, _)| sequence);
+        let watching = stop.clone();
+        let watched = open_shared(&path)?;
+        std::thread::spawn(move || {
+            let mut seen = sequence;
+            while !watching.load(Ordering::Relaxed) {
+                std::thread::sleep(POLL_INTERVAL);
+                // A torn record fails its checksum and is read again on the next poll
+                if let Some((sequence, pid, view)) = decode(&watched) {
+                    if sequence != seen {
+                        seen = sequence;
+                        if pid != std::process::id() {
+                            on_view(view);
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Self { map, sequence, stop })
+    }
+
+    /// Send this window's view to the others.
+    pub fn publish(&mut self, view: View) {
+        // Continue from the latest writer so every record gets a new number
+        self.sequence = decode(&self.map).map_or(self.sequence, |(sequence, _, _)| sequence.max(self.sequence)) + 1;
+        self.map[..RECORD_LEN].copy_from_slice(&encode(self.sequence, std::process::id(), view));
+        let _ = self.map.flush_async();
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The shared file, in the private runtime directory where there is one.
+fn shared_path() -> Result<PathBuf> {
+    let dir = dirs::runtime_dir().or_else(dirs::cache_dir).unwrap_or_else(std::env::temp_dir).join("momemtum");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.join("view-sync"))
+}
+
+fn open_shared(path: &std::path::Path) -> Result<MmapMut> {
+    let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    if file.metadata()?.len() < RECORD_LEN as u64 {
+        file.set_len(RECORD_LEN as u64)?;
+    }
+    // Safety: the file only ever holds a checksummed record, so concurrent writes
+    // by other windows can at worst make a read fail its check
+    Ok(unsafe { MmapMut::map_mut(&file)? })
+}
+
+fn encode(sequence: u64, pid: u32, view: View) -> [u8; RECORD_LEN] {
+    let mut record = [0; RECORD_LEN];
+    record[..8].copy_from_slice(&sequence.to_le_bytes());
+    record[8..12].copy_from_slice(&pid.to_le_bytes());
+    record[12..16].copy_from_slice(&view.center.0.to_le_bytes());
+    record[16..20].copy_from_slice(&view.center.1.to_le_bytes());
+    record[20..24].copy_from_slice(&view.scale.to_le_bytes());
+    let checksum = fnv1a(&record[..24]);
+    record[24..].copy_from_slice(&checksum.to_le_bytes());
+    record
+}
+
+fn decode(bytes: &[u8]) -> Option<(u64, u32, View)> {
+    let record = bytes.get(..RECORD_LEN)?;
+    let field = |range: std::ops::Range<usize>| -> [u8; 4] { record[range].try_into().unwrap() };
+    let checksum = u64::from_le_bytes(record[24..].try_into().ok()?);
+    let sequence = u64::from_le_bytes(record[..8].try_into().ok()?);
+    if sequence == 0 || checksum != fnv1a(&record[..24]) {
+        return None;
+    }
+    let view = View {
+        center: (f32::from_le_bytes(field(12..16)), f32::from_le_bytes(field(16..20))),
+        scale: f32::from_le_bytes(field(20..24)),
+    };
+    let valid = view.center.0.is_finite() && view.center.1.is_finite() && view.scale.is_finite() && view.scale > 0.0;
+    valid.then_some((sequence, u32::from_le_bytes(field(8..12)), view))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_roundtrip() {
+        let view = View { center: (0.25, 0.75), scale: 0.5 };
+        let mut record = encode(7, 42, view);
+        assert_eq!(decode(&record), Some((7, 42, view)));
+        // A half-written record is ignored
+        record[13] ^= 0xff;
+        assert_eq!(decode(&record), None);
+        assert_eq!(decode(&[0; RECORD_LEN]), None);
+        assert_eq!(decode(&encode(1, 1, View { center: (0.5, 0.5), scale: 0.0 })), None);
+    }
+}
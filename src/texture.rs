@@ -1,8 +1,13 @@
 use image::GenericImageView;
 use anyhow::*;
 
+/// Bytes copied to a texture per frame while a large image uploads, a few
+/// milliseconds' work for the queue.
+pub const UPLOAD_BAND_BYTES: usize = 16 * 1024 * 1024;
+/// Textures of images no longer shown kept for reuse.
+const POOL_SIZE: usize = 2;
+
 pub struct Texture {
-    #[allow(dead_code)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
@@ -26,9 +31,21 @@ impl Texture {
         img: &image::DynamicImage,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let converted;
+        let rgba = match img.as_rgba8() {
+            Some(rgba) => rgba,
+            None => {
+                converted = img.to_rgba8();
+                &converted
+            }
+        };
+        let texture = Self::new(device, img.dimensions(), label);
+        texture.write_rows(queue, rgba, 0..rgba.height());
+        Ok(texture)
+    }
 
+    /// A texture of `dimensions` whose contents are still to be written.
+    pub fn new(device: &wgpu::Device, dimensions: (u32, u32), label: Option<&str>) -> Self {
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -45,22 +62,6 @@ impl Texture {
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -72,10 +73,169 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
+        }
+    }
+
+    /// Replace the contents with an image of the same size, e.g. the next frame of an animation.
+    pub fn write(&self, queue: &wgpu::Queue, rgba: &image::RgbaImage) {
+        let size = self.texture.size();
+        if rgba.dimensions() != (size.width, size.height) {
+            return;
+        }
+        self.write_rows(queue, rgba, 0..size.height);
+    }
+
+    /// Copy `rows` of `rgba`, an image of the texture's size, into the texture.
+    pub fn write_rows(&self, queue: &wgpu::Queue, rgba: &image::RgbaImage, rows: std::ops::Range<u32>) {
+        if rows.is_empty() {
+            return;
+        }
+        let width = self.texture.width();
+        let row_bytes = 4 * width as usize;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: rows.start, z: 0 },
+            },
+            &rgba.as_raw()[rows.start as usize * row_bytes..rows.end as usize * row_bytes],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(rows.end - rows.start),
+            },
+            wgpu::Extent3d {
+                width,
+                height: rows.end - rows.start,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// GPU memory the texture takes.
+    pub fn bytes(&self) -> u64 {
+        let size = self.texture.size();
+        crate::memory::rgba_bytes(size.width, size.height)
+    }
+
+    pub fn create_bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("diffuse_bind_group"),
         })
     }
 }
+
+/// Textures of images no longer shown, with their bind groups, kept to be written over
+/// by the next image of the same size instead of allocating anew. Photos from one
+/// camera mostly share theirs. All image textures have the same format.
+#[derive(Default)]
+pub struct TexturePool {
+    free: Vec<(Texture, wgpu::BindGroup)>,
+}
+
+impl TexturePool {
+    /// A texture of `dimensions` and its bind group, reused when one is free.
+    pub fn take(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        dimensions: (u32, u32),
+        label: Option<&str>,
+    ) -> (Texture, wgpu::BindGroup) {
+        let fits = |(t, _): &(Texture, wgpu::BindGroup)| (t.texture.width(), t.texture.height()) == dimensions;
+        if let Some(i) = self.free.iter().position(fits) {
+            return self.free.remove(i);
+        }
+        let texture = Texture::new(device, dimensions, label);
+        let bind_group = texture.create_bind_group(device, layout);
+        (texture, bind_group)
+    }
+
+    /// Keep a texture no longer shown, letting go of the oldest past a few.
+    pub fn give(&mut self, texture: Texture, bind_group: wgpu::BindGroup) {
+        self.free.push((texture, bind_group));
+        if self.free.len() > POOL_SIZE {
+            self.free.remove(0);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.free.clear();
+    }
+
+    /// GPU memory of the textures kept.
+    pub fn bytes(&self) -> u64 {
+        self.free.iter().map(|(t, _)| t.bytes()).sum()
+    }
+}
+
+/// An image being copied into its texture a band of rows at a time, so that a
+/// large one doesn't hold up drawing for the whole copy.
+pub struct Upload {
+    rgba: image::RgbaImage,
+    done: u32,
+}
+
+impl Upload {
+    pub fn new(rgba: image::RgbaImage) -> Self {
+        Self { rgba, done: 0 }
+    }
+
+    /// Copy the next rows, `max_bytes` worth but at least one, into `texture`.
+    /// True once every row is in.
+    pub fn step(&mut self, queue: &wgpu::Queue, texture: &Texture, max_bytes: usize) -> bool {
+        let rows = band_rows(self.rgba.width(), max_bytes);
+        let end = self.done.saturating_add(rows).min(self.rgba.height());
+        texture.write_rows(queue, &self.rgba, self.done..end);
+        self.done = end;
+        self.done >= self.rgba.height()
+    }
+
+    /// Fraction of the rows copied so far.
+    pub fn progress(&self) -> f32 {
+        self.done as f32 / self.rgba.height().max(1) as f32
+    }
+
+    /// Memory held until the copy is done.
+    pub fn bytes(&self) -> u64 {
+        self.rgba.as_raw().len() as u64
+    }
+}
+
+/// Rows of an RGBA image `width` pixels wide that fit in `max_bytes`, at least one.
+fn band_rows(width: u32, max_bytes: usize) -> u32 {
+    let row_bytes = (4 * width as usize).max(1);
+    (max_bytes / row_bytes).clamp(1, u32::MAX as usize) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_rows() {
+        // A 9216 px wide image goes up in bands of 455 rows, 16 of them for 6912 rows
+        let rows = band_rows(9216, UPLOAD_BAND_BYTES);
+        assert_eq!(rows, 455);
+        assert_eq!(6912u32.div_ceil(rows), 16);
+        // Rows wider than the budget still go one at a time
+        assert_eq!(band_rows(65_535, 1024), 1);
+        assert_eq!(band_rows(100, usize::MAX), u32::MAX);
+    }
+}
@@ -0,0 +1,125 @@
+//! Light and dark viewing surrounds, chosen from the OS setting or the sun.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{ThemeMode, ViewConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub fn label(self) -> &'static str {
+        match self {
+            Theme::Light => "Light",
+            Theme::Dark => "Dark",
+        }
+    }
+
+    pub fn from_window(theme: winit::window::Theme) -> Self {
+        match theme {
+            winit::window::Theme::Light => Theme::Light,
+            winit::window::Theme::Dark => Theme::Dark,
+        }
+    }
+
+    pub fn to_window(self) -> winit::window::Theme {
+        match self {
+            Theme::Light => winit::window::Theme::Light,
+            Theme::Dark => winit::window::Theme::Dark,
+        }
+    }
+
+    /// Background color setting for this theme.
+    pub fn background(self, view: &ViewConfig) -> &str {
+        match self {
+            Theme::Light => &view.light_background,
+            Theme::Dark => &view.dark_background,
+        }
+    }
+}
+
+/// The theme `view.theme` asks for, or `None` to keep the fixed background.
+/// `system` is what the OS reports, if anything.
+pub fn automatic(view: &ViewConfig, system: Option<Theme>, now: SystemTime) -> Option<Theme> {
+    let by_sun = || {
+        let [lat, lon] = view.location?;
+        let secs = now.duration_since(UNIX_EPOCH).ok()?.as_secs_f64();
+        Some(if is_daytime(lat, lon, secs) { Theme::Light } else { Theme::Dark })
+    };
+    match view.theme {
+        ThemeMode::Fixed => None,
+        ThemeMode::System => system.or_else(by_sun),
+        ThemeMode::Sun => by_sun().or(system),
+    }
+}
+
+/// Whether the sun is up at a place (degrees, east and north positive) at a
+/// Unix time, after the NOAA sunrise equation. Good to a few minutes.
+pub fn is_daytime(lat: f64, lon: f64, unix_secs: f64) -> bool {
+    const J2000: f64 = 2_451_545.0;
+    let julian = unix_secs / 86_400.0 + 2_440_587.5;
+    // Solar noon nearest to `julian`, so sunrise and sunset bracket it
+    let day = (julian - J2000 + lon / 360.0).round();
+    let mean_noon = day - lon / 360.0;
+
+    let anomaly = (357.5291 + 0.985_600_28 * mean_noon).rem_euclid(360.0).to_radians();
+    let center = 1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic = (anomaly.to_degrees() + center + 180.0 + 102.9372).rem_euclid(360.0).to_radians();
+    let transit = J2000 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic).sin();
+
+    let declination = (ecliptic.sin() * 23.4397f64.to_radians().sin()).asin();
+    let lat = lat.to_radians();
+    let cos_hour_angle =
+        ((-0.833f64).to_radians().sin() - lat.sin() * declination.sin()) / (lat.cos() * declination.cos());
+    if cos_hour_angle <= -1.0 {
+        return true; // Midnight sun
+    }
+    if cos_hour_angle >= 1.0 {
+        return false; // Polar night
+    }
+    let half_day = cos_hour_angle.acos().to_degrees() / 360.0;
+    (transit - half_day..transit + half_day).contains(&julian)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-06-21 00:00 UTC and 2024-12-21 00:00 UTC
+    const JUNE: f64 = 1_718_928_000.0;
+    const DECEMBER: f64 = 1_734_739_200.0;
+    const HOUR: f64 = 3600.0;
+
+    #[test]
+    fn test_daytime() {
+        // Paris: sunrise about 03:47 UTC and sunset about 19:58 UTC in June
+        assert!(is_daytime(48.85, 2.35, JUNE + 12.0 * HOUR));
+        assert!(is_daytime(48.85, 2.35, JUNE + 19.5 * HOUR));
+        assert!(!is_daytime(48.85, 2.35, JUNE + 21.0 * HOUR));
+        assert!(!is_daytime(48.85, 2.35, DECEMBER + 17.0 * HOUR));
+        // New York, late evening local time is after midnight UTC
+        assert!(is_daytime(40.71, -74.0, JUNE + 23.0 * HOUR));
+        assert!(!is_daytime(40.71, -74.0, DECEMBER + 23.0 * HOUR));
+        // Tromsø
+        assert!(is_daytime(69.65, 18.96, JUNE));
+        assert!(!is_daytime(69.65, 18.96, DECEMBER + 11.0 * HOUR));
+    }
+
+    #[test]
+    fn test_automatic() {
+        let noon = UNIX_EPOCH + std::time::Duration::from_secs_f64(JUNE + 12.0 * HOUR);
+        let mut view = ViewConfig::default();
+        assert_eq!(automatic(&view, Some(Theme::Dark), noon), None);
+
+        view.theme = ThemeMode::System;
+        assert_eq!(automatic(&view, Some(Theme::Dark), noon), Some(Theme::Dark));
+        assert_eq!(automatic(&view, None, noon), None);
+
+        view.theme = ThemeMode::Sun;
+        view.location = Some([48.85, 2.35]);
+        assert_eq!(automatic(&view, Some(Theme::Dark), noon), Some(Theme::Light));
+    }
+}
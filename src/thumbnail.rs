@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+/// Thumbnail size, matching the freedesktop.org "large" flavor.
+pub const THUMBNAIL_SIZE: u32 = 256;
+/// The "x-large" flavor, for cells wider than `THUMBNAIL_SIZE` physical pixels on dense displays.
+pub const X_LARGE_THUMBNAIL_SIZE: u32 = 512;
+/// Cache subfolders for each size
+const FLAVORS: [(u32, &str); 2] = [(THUMBNAIL_SIZE, "large"), (X_LARGE_THUMBNAIL_SIZE, "x-large")];
+
+/// Default upper bound for the thumbnails Momemtum keeps on disk.
+pub const DEFAULT_CACHE_BYTES: u64 = 512 * 1024 * 1024;
+
+const SOFTWARE: &str = "Momemtum";
+
+/// Generates thumbnails on the rayon pool and caches them on disk.
+pub struct Thumbnailer {
+    sender: Sender<(PathBuf, DynamicImage)>,
+    receiver: Receiver<(PathBuf, DynamicImage)>,
+    requested: HashSet<PathBuf>,
+    cache: Option<Arc<DiskCache>>,
+    /// Longest side of the thumbnails generated
+    size: u32,
+}
+
+impl Thumbnailer {
+    /// Thumbnails are cached on disk in `cache_dir`, if there is one.
+    pub fn new(cache_dir: Option<PathBuf>, max_cache_bytes: u64) -> Self {
+        let (sender, receiver) = channel();
+        let cache = cache_dir.map(|root| Arc::new(DiskCache::new(root, max_cache_bytes)));
+        if let Some(cache) = &cache {
+            let cache = cache.clone();
+            rayon::spawn(move || {
+                if let Err(e) = cache.prune() {
+                    log::warn!("Failed to prune thumbnail cache: {:?}", e);
+                }
+            });
+        }
+        Self {
+            sender,
+            receiver,
+            requested: HashSet::new(),
+            cache,
+            size: THUMBNAIL_SIZE,
+        }
+    }
+
+    /// The thumbnail size that stays sharp in cells `pixels` physical pixels wide.
+    pub fn size_for(pixels: f32) -> u32 {
+        if pixels > THUMBNAIL_SIZE as f32 { X_LARGE_THUMBNAIL_SIZE } else { THUMBNAIL_SIZE }
+    }
+
+    /// Generate thumbnails `size` pixels across from now on. Returns whether that is a
+    /// change, in which case every path is generated again when requested.
+    pub fn set_size(&mut self, size: u32) -> bool {
+        if size == self.size {
+            return false;
+        }
+        self.size = size;
+        self.requested.clear();
+        // Drop results of the old size still on their way
+        self.receiver.try_iter().for_each(drop);
+        true
+    }
+
+    /// Let `path` be generated again when next requested, e.g. after its texture was
+    /// dropped to save memory.
+    pub fn forget(&mut self, path: &Path) {
+        self.requested.remove(path);
+    }
+
+    /// Queue a thumbnail for `path`. Each path is only generated once per session.
+    pub fn request(&mut self, path: &Path) {
+        if !self.requested.insert(path.to_path_buf()) {
+            return;
+        }
+        let sender = self.sender.clone();
+        let cache = self.cache.clone();
+        let path = path.to_path_buf();
+        let size = self.size;
+        let pending = crate::stats::Pending::start(&crate::stats::THUMBNAILS);
+        rayon::spawn(move || {
+            let _pending = pending;
+            crate::priority::yield_to_interactive();
+            match load_thumbnail(&path, cache.as_deref(), size) {
+                Ok(thumb) => {
+                    let _ = sender.send((path, thumb));
+                    crate::redraw::wake();
+                }
+                Err(e) => {
+                    log::warn!("Failed to create thumbnail for {:?}: {:?}", path, e);
+                }
+            }
+        });
+    }
+
+    /// Thumbnails finished since the last call.
+    pub fn poll(&self) -> Vec<(PathBuf, DynamicImage)> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+/// The thumbnail of `path`, from `cache` when it is there and up to date, else decoded and cached.
+pub fn load_thumbnail(path: &Path, cache: Option<&DiskCache>, size: u32) -> Result<DynamicImage> {
+    if let Some(cache) = cache {
+        let cached = cache.get(path, size);
+        crate::stats::THUMBNAIL_CACHE.record(cached.is_some());
+        if let Some(thumb) = cached {
+            return Ok(thumb);
+        }
+    }
+
+    let loaded = crate::sandbox::load(path)?;
+    let thumb = loaded.image.thumbnail(size, size);
+
+    if let Some(cache) = cache {
+        if let Err(e) = cache.put(path, &thumb, size) {
+            log::warn!("Failed to cache thumbnail for {:?}: {:?}", path, e);
+        }
+    }
+    Ok(thumb)
+}
+
+/// Decode `source` the way the viewer does (RAW files developed, EXIF orientation applied)
+/// and save it scaled down to `size` pixels on its longest side, as PNG for a `.png` target
+/// and JPEG otherwise. Needs no window or GPU, so scripts and file managers can use it.
+pub fn save(source: &Path, target: &Path, size: u32) -> Result<()> {
+    let loaded = crate::sandbox::load(source)?;
+    // `thumbnail` scales small images up to the size; they are kept as they are
+    let thumb = if loaded.image.width().max(loaded.image.height()) > size {
+        loaded.image.thumbnail(size, size)
+    } else {
+        loaded.image
+    };
+    let metadata = crate::export::Metadata::read(source).colors_only();
+    crate::export::save(&thumb, target, crate::export::ColorSpace::Srgb, &metadata)
+}
+
+/// On-disk thumbnail store following the freedesktop.org thumbnail specification:
+/// `<root>/large/<md5 of file URI>.png` (or `x-large` for 512 pixels), tagged with `Thumb::URI` and `Thumb::MTime`
+/// so entries are invalidated when the source file changes.
+pub struct DiskCache {
+    root: PathBuf,
+    max_bytes: u64,
+}
+
+impl DiskCache {
+    /// `$XDG_CACHE_HOME/thumbnails` on Linux (shared with file managers),
+    /// an app-private directory elsewhere.
+    pub fn default_location() -> Option<PathBuf> {
+        let cache = dirs::cache_dir()?;
+        if cfg!(target_os = "linux") {
+            Some(cache.join("thumbnails"))
+        } else {
+            Some(cache.join("momemtum").join("thumbnails"))
+        }
+    }
+
+    pub fn new(root: PathBuf, max_bytes: u64) -> Self {
+        Self { root, max_bytes }
+    }
+
+    fn entry_path(&self, uri: &str, size: u32) -> PathBuf {
+        let digest = md5::compute(uri.as_bytes());
+        let flavor = FLAVORS.iter().find(|(s, _)| *s >= size).unwrap_or(&FLAVORS[FLAVORS.len() - 1]).1;
+        self.root.join(flavor).join(format!("{:x}.png", digest))
+    }
+
+    /// The cached thumbnail of `size` for `path`, if present and still up to date.
+    pub fn get(&self, path: &Path, size: u32) -> Option<DynamicImage> {
+        let uri = file_uri(path)?;
+        let mtime = mtime_secs(path)?;
+        let bytes = std::fs::read(self.entry_path(&uri, size)).ok()?;
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(&bytes));
+        let reader = decoder.read_info().ok()?;
+        let text = &reader.info().uncompressed_latin1_text;
+        let field = |key: &str| text.iter().find(|t| t.keyword == key).map(|t| t.text.as_str());
+        if field("Thumb::URI") != Some(uri.as_str()) || field("Thumb::MTime") != Some(mtime.to_string().as_str()) {
+            return None;
+        }
+        image::load_from_memory_with_format(&bytes, image::ImageFormat::Png).ok()
+    }
+
+    pub fn put(&self, path: &Path, thumb: &DynamicImage, size: u32) -> Result<()> {
+        let uri = file_uri(path).ok_or_else(|| anyhow!("Cannot build URI for {:?}", path))?;
+        let mtime = mtime_secs(path).ok_or_else(|| anyhow!("No modification time for {:?}", path))?;
+        let target = self.entry_path(&uri, size);
+        let dir = target.parent().ok_or_else(|| anyhow!("Invalid cache path"))?;
+        std::fs::create_dir_all(dir)?;
+
+        let rgba = thumb.to_rgba8();
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, rgba.width(), rgba.height());
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.add_text_chunk("Thumb::URI".to_string(), uri)?;
+            encoder.add_text_chunk("Thumb::MTime".to_string(), mtime.to_string())?;
+            encoder.add_text_chunk("Software".to_string(), SOFTWARE.to_string())?;
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&rgba)?;
+        }
+
+        // Write to a temporary file and rename, so readers never see a partial thumbnail
+        let tmp = target.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&tmp, &data)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+        }
+        std::fs::rename(&tmp, &target)?;
+        Ok(())
+    }
+
+    /// Thumbnails written by Momemtum, as (modification time, size, path).
+    /// Thumbnails created by other applications in a shared directory are skipped.
+    fn entries(&self) -> Vec<(std::time::SystemTime, u64, PathBuf)> {
+        let mut ours = Vec::new();
+        let dirs = FLAVORS.iter().filter_map(|(_, flavor)| std::fs::read_dir(self.root.join(flavor)).ok());
+        for entry in dirs.flat_map(|entries| entries.flatten()) {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("png") || !written_by_us(&path) {
+                continue;
+            }
+            if let Ok(meta) = entry.metadata() {
+                ours.push((meta.modified().unwrap_or(UNIX_EPOCH), meta.len(), path));
+            }
+        }
+        ours
+    }
+
+    /// Bytes taken by Momemtum's thumbnails.
+    pub fn usage(&self) -> u64 {
+        self.entries().iter().map(|(_, len, _)| len).sum()
+    }
+
+    /// Delete the oldest thumbnails written by Momemtum until the cache fits in `max_bytes`.
+    /// Thumbnails created by other applications in a shared directory are left alone.
+    pub fn prune(&self) -> Result<u64> {
+        let mut ours = self.entries();
+        let mut total: u64 = ours.iter().map(|(_, len, _)| len).sum();
+        ours.sort();
+        let mut removed = 0;
+        for (_, len, path) in ours {
+            if total <= self.max_bytes {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                total -= len;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn written_by_us(path: &Path) -> bool {
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+    match png::Decoder::new(std::io::BufReader::new(file)).read_info() {
+        Ok(reader) => reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .any(|t| t.keyword == "Software" && t.text == SOFTWARE),
+        Err(_) => false,
+    }
+}
+
+fn mtime_secs(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Absolute `file://` URI with RFC 3986 percent-encoding, as required for the cache key.
+fn file_uri(path: &Path) -> Option<String> {
+    let absolute = std::fs::canonicalize(path).ok()?;
+    let mut text = absolute.to_str()?.replace('\\', "/");
+    if let Some(stripped) = text.strip_prefix("//?/") {
+        text = stripped.to_string();
+    }
+    if !text.starts_with('/') {
+        text.insert(0, '/');
+    }
+    let mut uri = String::from("file://");
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => uri.push(b as char),
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    Some(uri)
+}
+
+/// A thumbnail uploaded to the GPU, ready to be drawn by the quad renderer.
+pub struct GpuThumbnail {
+    texture: crate::texture::Texture,
+    pub bind_group: wgpu::BindGroup,
+    pub aspect: f32,
+}
+
+impl GpuThumbnail {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout, img: &DynamicImage) -> Result<Self> {
+        let texture = crate::texture::Texture::from_image(device, queue, img, Some("Thumbnail"))?;
+        let bind_group = texture.create_bind_group(device, layout);
+        Ok(Self {
+            texture,
+            bind_group,
+            aspect: img.width() as f32 / img.height().max(1) as f32,
+        })
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.texture.bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_cache_roundtrip_and_invalidation() {
+        let dir = scratch_dir("thumbcache");
+        let source = dir.join("photo one.jpg");
+        std::fs::write(&source, b"not really a jpeg").unwrap();
+
+        let cache = DiskCache::new(dir.join("cache"), DEFAULT_CACHE_BYTES);
+        assert!(cache.get(&source, THUMBNAIL_SIZE).is_none());
+
+        let thumb = DynamicImage::new_rgb8(32, 16);
+        cache.put(&source, &thumb, THUMBNAIL_SIZE).unwrap();
+        let cached = cache.get(&source, THUMBNAIL_SIZE).unwrap();
+        assert_eq!((cached.width(), cached.height()), (32, 16));
+        // Each size has its own entry
+        assert!(cache.get(&source, X_LARGE_THUMBNAIL_SIZE).is_none());
+        cache.put(&source, &DynamicImage::new_rgb8(64, 32), X_LARGE_THUMBNAIL_SIZE).unwrap();
+        assert_eq!(cache.get(&source, X_LARGE_THUMBNAIL_SIZE).unwrap().width(), 64);
+
+        // Touching the source invalidates the entry
+        let file = std::fs::File::options().write(true).open(&source).unwrap();
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_000_000)).unwrap();
+        assert!(cache.get(&source, THUMBNAIL_SIZE).is_none());
+    }
+
+    #[test]
+    fn test_prune_respects_limit() {
+        let dir = scratch_dir("thumbprune");
+        let cache = DiskCache::new(dir.join("cache"), 0);
+        for i in 0..3 {
+            let source = dir.join(format!("{}.jpg", i));
+            std::fs::write(&source, b"x").unwrap();
+            cache.put(&source, &DynamicImage::new_rgb8(8, 8), FLAVORS[i % 2].0).unwrap();
+        }
+        // A foreign thumbnail without our Software tag survives pruning
+        std::fs::write(dir.join("cache").join("large").join("foreign.png"), b"x").unwrap();
+
+        assert!(cache.usage() > 0);
+        assert_eq!(cache.prune().unwrap(), 3);
+        assert_eq!(cache.usage(), 0);
+        let left: Vec<_> = std::fs::read_dir(dir.join("cache").join("large")).unwrap().flatten().collect();
+        assert_eq!(left.len(), 1);
+    }
+
+    #[test]
+    fn test_save_headless() {
+        let dir = scratch_dir("thumbsave");
+        let source = dir.join("wide.png");
+        image::RgbImage::from_pixel(1000, 400, image::Rgb([200, 40, 40])).save(&source).unwrap();
+
+        let target = dir.join("wide_thumb.jpg");
+        save(&source, &target, 100).unwrap();
+        let thumb = image::open(&target).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (100, 40));
+        assert_eq!(image::guess_format(&std::fs::read(&target).unwrap()).unwrap(), image::ImageFormat::Jpeg);
+
+        // Small images are not scaled up
+        let target = dir.join("wide_thumb.png");
+        save(&source, &target, 2048).unwrap();
+        assert_eq!(image::open(&target).unwrap().width(), 1000);
+
+        assert!(save(&dir.join("missing.jpg"), &dir.join("out.jpg"), 100).is_err());    }
+
+    #[test]
+    fn test_file_uri_escaping() {
+        let dir = scratch_dir("thumburi");
+        let source = dir.join("a b#c.jpg");
+        std::fs::write(&source, b"x").unwrap();
+        let uri = file_uri(&source).unwrap();
+        assert!(uri.starts_with("file:///"));
+        assert!(uri.ends_with("/a%20b%23c.jpg"));    }
+}
@@ -0,0 +1,196 @@
+//! Timeline: the gallery in order of capture, under a title per day or month, so shoots
+//! merged from several cameras read in the order they were taken whatever the files
+//! are called.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::grid::Header;
+use crate::navigator::{Navigator, SortOrder};
+
+const MONTHS: [&str; 12] =
+    ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+
+/// What the images are grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Day,
+    Month,
+}
+
+impl Grouping {
+    pub fn label(self) -> &'static str {
+        match self {
+            Grouping::Day => "day",
+            Grouping::Month => "month",
+        }
+    }
+
+    /// By day, then by month, then off.
+    pub fn next(current: Option<Self>) -> Option<Self> {
+        match current {
+            None => Some(Grouping::Day),
+            Some(Grouping::Day) => Some(Grouping::Month),
+            Some(Grouping::Month) => None,
+        }
+    }
+
+    /// "21 June 2024" or "June 2024" for a capture time as `YYYY-MM-DD HH:MM:SS`;
+    /// none when it isn't one.
+    fn title(self, taken: &str) -> Option<String> {
+        let year = taken.get(0..4).filter(|y| y.bytes().all(|b| b.is_ascii_digit()))?;
+        let month = MONTHS.get(taken.get(5..7)?.parse::<usize>().ok()?.checked_sub(1)?)?;
+        match self {
+            Grouping::Month => Some(format!("{} {}", month, year)),
+            Grouping::Day => {
+                let day = taken.get(8..10)?.parse::<u32>().ok().filter(|d| (1..=31).contains(d))?;
+                Some(format!("{} {} {}", day, month, year))
+            }
+        }
+    }
+}
+
+/// A header over each run of images in `list` taken on the same day or in the same
+/// month, with the number of images; those without a capture time go under one of
+/// their own. `list` is expected in order of capture, so each day comes up once.
+pub fn headers(list: &[PathBuf], capture_times: &HashMap<PathBuf, String>, grouping: Grouping) -> Vec<Header> {
+    let mut runs: Vec<(usize, Option<String>)> = Vec::new();
+    for (i, path) in list.iter().enumerate() {
+        let title = capture_times.get(path).and_then(|taken| grouping.title(taken));
+        if runs.last().is_none_or(|(_, last)| *last != title) {
+            runs.push((i, title));
+        }
+    }
+    let ends: Vec<usize> = runs.iter().skip(1).map(|(first, _)| *first).chain([list.len()]).collect();
+    runs.into_iter()
+        .zip(ends)
+        .map(|((first, title), end)| Header {
+            first,
+            title: format!("{} ({})", title.as_deref().unwrap_or("No capture date"), end - first),
+        })
+        .collect()
+}
+
+/// The gallery's grouping, with the order to go back to without it, and the listing its
+/// headers were made for.
+#[derive(Default)]
+pub struct Timeline {
+    on: Option<(Grouping, SortOrder)>,
+    listing: Option<(Option<PathBuf>, usize)>,
+    // Whether there are headers up, to take down when the timeline ends
+    shown: bool,
+}
+
+impl Timeline {
+    pub fn grouping(&self) -> Option<Grouping> {
+        self.on.map(|(grouping, _)| grouping)
+    }
+
+    /// Group by day, then by month, in order of capture, then go back to the order
+    /// before. Returns the grouping now.
+    pub fn cycle(&mut self, navigator: &mut Navigator) -> Option<Grouping> {
+        let before = self.on.map_or(navigator.sort, |(_, sort)| sort);
+        match Grouping::next(self.grouping()) {
+            Some(grouping) => {
+                if navigator.shuffling() {
+                    navigator.set_shuffle(false);
+                }
+                if navigator.sort != SortOrder::Captured {
+                    navigator.set_sort(SortOrder::Captured);
+                }
+                self.on = Some((grouping, before));
+            }
+            None => {
+                self.on = None;
+                navigator.set_sort(before);
+            }
+        }
+        self.listing = None;
+        self.grouping()
+    }
+
+    /// The headers for `navigator`'s list, when they changed since last asked; none left
+    /// once the timeline ended. Choosing another order or shuffling ends it.
+    pub fn update(&mut self, navigator: &Navigator) -> Option<Vec<Header>> {
+        let on_timeline = navigator.sort == SortOrder::Captured && !navigator.shuffling();
+        let Some((grouping, _)) = self.on.filter(|_| on_timeline) else {
+            self.on = None;
+            self.listing = None;
+            return std::mem::take(&mut self.shown).then(Vec::new);
+        };
+        let listing = (navigator.root().map(PathBuf::from), navigator.image_list.len());
+        if self.listing.as_ref() == Some(&listing) {
+            return None;
+        }
+        self.listing = Some(listing);
+        self.shown = true;
+        Some(headers(&navigator.image_list, &navigator.capture_times, grouping))
+    }
+
+    /// Make the headers again at the next update, the capture times having changed.
+    pub fn invalidate(&mut self) {
+        self.listing = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let list: Vec<PathBuf> = ["b/DSC_0002.jpg", "a/IMG_0101.jpg", "b/DSC_0003.jpg", "a/IMG_0102.jpg", "scan.png"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let times = HashMap::from([
+            (list[0].clone(), "2024-06-21 09:00:00".to_string()),
+            (list[1].clone(), "2024-06-21 09:05:00".to_string()),
+            (list[2].clone(), "2024-06-22 08:00:00".to_string()),
+            (list[3].clone(), "2024-07-01 10:00:00".to_string()),
+        ]);
+        let titles = |grouping| -> Vec<(usize, String)> {
+            headers(&list, &times, grouping).into_iter().map(|h| (h.first, h.title)).collect()
+        };
+        assert_eq!(
+            titles(Grouping::Day),
+            [
+                (0, "21 June 2024 (2)".to_string()),
+                (2, "22 June 2024 (1)".to_string()),
+                (3, "1 July 2024 (1)".to_string()),
+                (4, "No capture date (1)".to_string()),
+            ]
+        );
+        assert_eq!(titles(Grouping::Month)[..2], [(0, "June 2024 (3)".to_string()), (3, "July 2024 (1)".to_string())]);
+        assert_eq!(Grouping::Day.title("0000:00:00"), None);
+        assert!(headers(&[], &times, Grouping::Day).is_empty());
+        assert_eq!(Grouping::next(Some(Grouping::Month)), None);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mut nav = Navigator::new();
+        nav.image_list = vec![PathBuf::from("a.jpg"), PathBuf::from("b.jpg")];
+        nav.capture_times.insert(nav.image_list[1].clone(), "2024-06-21 09:00:00".to_string());
+        let mut timeline = Timeline::default();
+        assert_eq!(timeline.update(&nav), None);
+        assert_eq!(timeline.cycle(&mut nav), Some(Grouping::Day));
+        assert_eq!(nav.sort, SortOrder::Captured);
+        assert_eq!(timeline.update(&nav).map(|h| h.len()), Some(2));
+        // Made once for each listing, or again when asked to
+        assert_eq!(timeline.update(&nav), None);
+        timeline.invalidate();
+        assert!(timeline.update(&nav).is_some());
+        assert_eq!(timeline.cycle(&mut nav), Some(Grouping::Month));
+        assert_eq!(timeline.cycle(&mut nav), None);
+        assert_eq!(nav.sort, SortOrder::default());
+        assert_eq!(timeline.update(&nav), Some(Vec::new()));
+        assert_eq!(timeline.update(&nav), None);
+        // Another order ends it
+        timeline.cycle(&mut nav);
+        timeline.update(&nav);
+        nav.set_sort(SortOrder::Name);
+        assert_eq!(timeline.update(&nav), Some(Vec::new()));
+        assert_eq!(timeline.grouping(), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! Touchscreen gestures: one finger drags the image, two fingers pinch to zoom about
+//! their midpoint and pan together, and a quick horizontal flick of one finger moves
+//! to the next or previous image.
+
+use std::time::{Duration, Instant};
+use winit::event::TouchPhase;
+
+/// A flick is at least this far, in window pixels per unit of scale factor...
+const SWIPE_DISTANCE: f64 = 80.0;
+/// ...and no slower than this.
+const SWIPE_TIME: Duration = Duration::from_millis(400);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    /// Drag the view by this many window pixels
+    Pan(f32, f32),
+    /// Multiply the camera zoom by the factor, keeping the window point in place
+    Zoom(f32, (f32, f32)),
+    /// Next image when true, previous otherwise
+    Swipe(bool),
+}
+
+/// The fingers on the screen and how the current gesture began.
+#[derive(Debug, Default)]
+pub struct Touches {
+    points: Vec<(u64, (f64, f64))>,
+    /// Where and when the first finger went down, while it is the only one so far
+    start: Option<(Instant, (f64, f64))>,
+}
+
+impl Touches {
+    /// Follow finger `id` through `phase` at window position `position`, returning what it
+    /// amounts to. `scale` is the window's scale factor.
+    pub fn handle(&mut self, id: u64, phase: TouchPhase, position: (f64, f64), now: Instant, scale: f64) -> Vec<Gesture> {
+        match phase {
+            TouchPhase::Started => {
+                self.points.retain(|(p, _)| *p != id);
+                self.points.push((id, position));
+                // A second finger makes it a pinch, never a flick
+                self.start = (self.points.len() == 1).then_some((now, position));
+                Vec::new()
+            }
+            TouchPhase::Moved => {
+                let before = self.pair();
+                let Some(point) = self.points.iter_mut().find(|(p, _)| *p == id) else {
+                    return Vec::new();
+                };
+                let (old, new) = (point.1, position);
+                point.1 = new;
+                match (before, self.pair()) {
+                    (Some((c0, d0)), Some((c1, d1))) => {
+                        let mut gestures = vec![Gesture::Pan((c1.0 - c0.0) as f32, (c1.1 - c0.1) as f32)];
+                        if d0 > 0.0 && d1 > 0.0 {
+                            gestures.push(Gesture::Zoom((d0 / d1) as f32, (c1.0 as f32, c1.1 as f32)));
+                        }
+                        gestures
+                    }
+                    _ => vec![Gesture::Pan((new.0 - old.0) as f32, (new.1 - old.1) as f32)],
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.points.retain(|(p, _)| *p != id);
+                let Some((started, (x0, y0))) = self.start.take() else {
+                    return Vec::new();
+                };
+                let (dx, dy) = (position.0 - x0, position.1 - y0);
+                let flick = phase == TouchPhase::Ended
+                    && now.duration_since(started) <= SWIPE_TIME
+                    && dx.abs() >= SWIPE_DISTANCE * scale
+                    && dx.abs() > 2.0 * dy.abs();
+                match flick {
+                    true => vec![Gesture::Swipe(dx < 0.0)],
+                    false => Vec::new(),
+                }
+            }
+        }
+    }
+
+    /// Midpoint and distance of the first two fingers.
+    fn pair(&self) -> Option<((f64, f64), f64)> {
+        let [(_, a), (_, b), ..] = self.points[..] else {
+            return None;
+        };
+        let center = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+        Some((center, ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drag_and_flick() {
+        let mut touches = Touches::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(touches.handle(1, TouchPhase::Started, (500.0, 300.0), at(0), 1.0).is_empty());
+        assert_eq!(touches.handle(1, TouchPhase::Moved, (450.0, 305.0), at(50), 1.0), vec![Gesture::Pan(-50.0, 5.0)]);
+        // Quick and mostly sideways, to the left: the next image
+        assert_eq!(touches.handle(1, TouchPhase::Ended, (380.0, 310.0), at(150), 1.0), vec![Gesture::Swipe(true)]);
+
+        // Too slow, or too short on a HiDPI screen, is only a drag
+        touches.handle(1, TouchPhase::Started, (500.0, 300.0), at(1000), 1.0);
+        assert!(touches.handle(1, TouchPhase::Ended, (700.0, 300.0), at(1600), 1.0).is_empty());
+        touches.handle(1, TouchPhase::Started, (500.0, 300.0), at(2000), 2.0);
+        assert!(touches.handle(1, TouchPhase::Ended, (600.0, 300.0), at(2100), 2.0).is_empty());
+    }
+
+    #[test]
+    fn test_pinch_zooms_about_the_midpoint() {
+        let mut touches = Touches::default();
+        let now = Instant::now();
+        touches.handle(1, TouchPhase::Started, (400.0, 300.0), now, 1.0);
+        touches.handle(2, TouchPhase::Started, (600.0, 300.0), now, 1.0);
+        // Spreading the fingers to twice the distance halves the camera zoom, enlarging the image
+        let gestures = touches.handle(2, TouchPhase::Moved, (800.0, 300.0), now, 1.0);
+        assert_eq!(gestures, vec![Gesture::Pan(100.0, 0.0), Gesture::Zoom(0.5, (600.0, 300.0))]);
+
+        // Lifting the fingers after a pinch is no flick
+        assert!(touches.handle(2, TouchPhase::Ended, (800.0, 300.0), now, 1.0).is_empty());
+        assert!(touches.handle(1, TouchPhase::Ended, (100.0, 300.0), now, 1.0).is_empty());
+    }
+}
@@ -0,0 +1,721 @@
+//! The egui layer: panels, menus and dialogs drawn over the image in a render pass
+//! of their own after it. Features plug in by implementing `Panel` and registering
+//! with `Panels::add`; a panel is laid out every frame while it is open.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use winit::window::Window;
+
+use crate::progress::Stage;
+
+/// How long a toast stays up.
+const TOAST_DURATION: Duration = Duration::from_secs(6);
+/// Toasts shown at once; older ones make way for new ones.
+const MAX_TOASTS: usize = 4;
+/// How much the image on screen is darkened while the next one loads.
+const STALE_DIM: u8 = 140;
+/// Matches listed in a palette at once.
+const PALETTE_ROWS: usize = 12;
+
+/// What panels can show about the viewer, gathered by `State` for each frame.
+pub struct Info<'a> {
+    pub path: Option<&'a Path>,
+    pub image_size: (u32, u32),
+    pub exif: &'a HashMap<String, String>,
+    /// Gathered only while the properties panel is open
+    pub properties: Option<&'a crate::properties::Properties>,
+    pub memory: &'a crate::memory::Usage,
+    pub memory_budget_mb: u64,
+    pub frame_times: &'a crate::stats::FrameTimes,
+    pub surface_format: wgpu::TextureFormat,
+    pub present_mode: wgpu::PresentMode,
+    /// Size and format of the image texture, which may only be an overview
+    pub texture_size: (u32, u32),
+    pub texture_format: wgpu::TextureFormat,
+    /// Tiles of a large image being read or decoded
+    pub tiles_in_flight: usize,
+}
+
+pub trait Panel {
+    /// Stable name to open and close the panel by.
+    fn id(&self) -> &'static str;
+    fn title(&self) -> String;
+    /// Lay out the panel's contents inside its window.
+    fn show(&mut self, ui: &mut egui::Ui, info: &Info);
+}
+
+/// The registered panels, each with whether it is open.
+#[derive(Default)]
+pub struct Panels {
+    entries: Vec<(Box<dyn Panel>, bool)>,
+}
+
+impl Panels {
+    /// Register `panel`, closed. A panel with the same id is replaced.
+    pub fn add(&mut self, panel: Box<dyn Panel>) {
+        self.entries.retain(|(p, _)| p.id() != panel.id());
+        self.entries.push((panel, false));
+    }
+
+    /// Open or close the panel `id`. Returns whether it is open now.
+    pub fn toggle(&mut self, id: &str) -> bool {
+        match self.entries.iter_mut().find(|(p, _)| p.id() == id) {
+            Some((_, open)) => {
+                *open = !*open;
+                *open
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_open(&self, id: &str) -> bool {
+        self.entries.iter().any(|(p, open)| *open && p.id() == id)
+    }
+
+    pub fn any_open(&self) -> bool {
+        self.entries.iter().any(|(_, open)| *open)
+    }
+
+    /// Each open panel in a window of its own; closing the window closes the panel.
+    fn show(&mut self, ctx: &egui::Context, info: &Info) {
+        for (panel, open) in self.entries.iter_mut() {
+            if *open {
+                egui::Window::new(panel.title())
+                    .id(egui::Id::new(panel.id()))
+                    .open(open)
+                    .show(ctx, |ui| panel.show(ui, info));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+/// Short messages stacked in the bottom right corner that go away on their own,
+/// e.g. to say why an image couldn't be opened.
+#[derive(Debug, Default)]
+pub struct Toasts {
+    items: VecDeque<(Level, String, Instant)>,
+}
+
+impl Toasts {
+    /// Show `text`. The same text shown again only restarts its timer.
+    pub fn push(&mut self, level: Level, text: String) {
+        self.items.retain(|(_, t, _)| *t != text);
+        self.items.push_back((level, text, Instant::now()));
+        while self.items.len() > MAX_TOASTS {
+            self.items.pop_front();
+        }
+    }
+
+    /// Drop the toasts shown for long enough by `now`.
+    pub fn expire(&mut self, now: Instant) {
+        self.items.retain(|(_, _, shown)| now.duration_since(*shown) < TOAST_DURATION);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// When the oldest toast goes away.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.items.iter().map(|(_, _, shown)| *shown + TOAST_DURATION).min()
+    }
+
+    /// The newest toast at the bottom, older ones above it.
+    fn show(&self, ctx: &egui::Context) {
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-12.0, -12.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                for (level, text, _) in &self.items {
+                    let frame = egui::Frame::popup(ui.style());
+                    frame.show(ui, |ui| {
+                        let color = match level {
+                            Level::Info => ui.visuals().text_color(),
+                            Level::Error => ui.visuals().error_fg_color,
+                        };
+                        ui.set_max_width(420.0);
+                        ui.label(egui::RichText::new(text).color(color));
+                    });
+                }
+            });
+    }
+}
+
+/// How a palette was closed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choice {
+    Picked(PathBuf),
+    Dismissed,
+}
+
+/// What the entries of a palette stand for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteKind {
+    /// Files or folders to open
+    Files,
+    /// Places to browse, each entry being the first image of one
+    Places,
+}
+
+/// A list to pick a file or folder from by typing some letters of its name, e.g. the
+/// recent files. Up and Down move the selection, Enter or a click picks, Escape closes.
+pub struct Palette {
+    title: String,
+    kind: PaletteKind,
+    query: String,
+    /// What is shown for each entry, and the entry
+    items: Vec<(String, PathBuf)>,
+    selected: usize,
+}
+
+impl Palette {
+    pub fn new(title: impl Into<String>, items: Vec<(String, PathBuf)>) -> Self {
+        Self { title: title.into(), kind: PaletteKind::Files, query: String::new(), items, selected: 0 }
+    }
+
+    pub fn with_kind(mut self, kind: PaletteKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> PaletteKind {
+        self.kind
+    }
+
+    /// Show `label` for the entry `item` from now on, e.g. once a name for it is known.
+    pub fn relabel(&mut self, item: &Path, label: String) {
+        if let Some(entry) = self.items.iter_mut().find(|(_, p)| p == item) {
+            entry.0 = label;
+        }
+    }
+
+    /// The items matching the query, best first; all of them in order while it is empty.
+    fn matches(&self) -> Vec<usize> {
+        let mut scored: Vec<(i32, usize)> = (self.items.iter().enumerate())
+            .filter_map(|(i, (label, _))| Some((fuzzy_score(&self.query, label)?, i)))
+            .collect();
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        scored.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// A window near the top of the screen with the query and the first matches.
+    /// Returns how it was closed, if it was.
+    fn show(&mut self, ctx: &egui::Context) -> Option<Choice> {
+        let (up, down, enter, escape) = ctx.input(|i| {
+            use egui::Key;
+            (i.key_pressed(Key::ArrowUp), i.key_pressed(Key::ArrowDown), i.key_pressed(Key::Enter), i.key_pressed(Key::Escape))
+        });
+        if escape {
+            return Some(Choice::Dismissed);
+        }
+        let matches = self.matches();
+        let shown = matches.len().min(PALETTE_ROWS);
+        if down {
+            self.selected = (self.selected + 1).min(shown.saturating_sub(1));
+        }
+        if up {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if enter {
+            return matches.get(self.selected).map(|&i| Choice::Picked(self.items[i].1.clone()));
+        }
+
+        let mut choice = None;
+        egui::Window::new(&self.title)
+            .id(egui::Id::new("palette"))
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 48.0))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let edit = ui.add(egui::TextEdit::singleline(&mut self.query).hint_text("Type to filter").desired_width(480.0));
+                edit.request_focus();
+                if edit.changed() {
+                    self.selected = 0;
+                }
+                ui.separator();
+                let matches = self.matches();
+                if matches.is_empty() {
+                    ui.weak("No matches");
+                }
+                for (row, &i) in matches.iter().take(PALETTE_ROWS).enumerate() {
+                    if ui.selectable_label(row == self.selected, &self.items[i].0).clicked() {
+                        choice = Some(Choice::Picked(self.items[i].1.clone()));
+                    }
+                }
+                if matches.len() > PALETTE_ROWS {
+                    ui.weak(format!("{} more", matches.len() - PALETTE_ROWS));
+                }
+            });
+        choice
+    }
+}
+
+/// How well `query` matches `text` as letters appearing in order, ignoring case and
+/// spaces: higher is better, `None` when it doesn't match. Runs of adjacent letters and
+/// letters starting a word count extra, skipped stretches count against.
+fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut score, mut from) = (0, 0);
+    for (n, q) in query.to_lowercase().chars().filter(|c| !c.is_whitespace()).enumerate() {
+        let i = from + text[from..].iter().position(|&c| c == q)?;
+        score += 1;
+        if n > 0 && i == from {
+            score += 5;
+        }
+        if i == 0 || !text[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= (i - from).min(3) as i32;
+        from = i + 1;
+    }
+    Some(score)
+}
+
+/// The stage of the decode in flight and how far it has got, as a bar in the middle of
+/// the window over the dimmed previous image. Stages that can't tell show a spinner.
+fn show_loading(ctx: &egui::Context, stage: Stage, fraction: Option<f32>) {
+    let screen = ctx.screen_rect();
+    ctx.layer_painter(egui::LayerId::background())
+        .rect_filled(screen, 0.0, egui::Color32::from_black_alpha(STALE_DIM));
+    egui::Area::new(egui::Id::new("loading"))
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .interactable(false)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| match fraction {
+                Some(fraction) => {
+                    let bar = egui::ProgressBar::new(fraction)
+                        .desired_width(240.0)
+                        .text(format!("{} {}%", stage.label(), (fraction * 100.0) as u32));
+                    ui.add(bar);
+                }
+                None => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(stage.label());
+                    });
+                }
+            });
+        });
+}
+
+/// egui's context, its winit input translation and its wgpu renderer.
+pub struct Ui {
+    context: egui::Context,
+    input: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    pub panels: Panels,
+    pub toasts: Toasts,
+    /// The decode in flight, if any: its stage and the fraction of it done
+    pub loading: Option<(Stage, Option<f32>)>,
+    /// The open palette, if any, and what was last picked from one
+    pub palette: Option<Palette>,
+    picked: Option<(PaletteKind, PathBuf)>,
+}
+
+impl Ui {
+    pub fn new(window: &Window, device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let context = egui::Context::default();
+        let input = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let mut panels = Panels::default();
+        panels.add(Box::new(ExifPanel));
+        panels.add(Box::new(PropertiesPanel));
+        let renderer = egui_wgpu::Renderer::new(device, format, None, 1);
+        Self { context, input, renderer, panels, toasts: Toasts::default(), loading: None, palette: None, picked: None }
+    }
+
+    /// Whether anything is drawn: an open panel, a toast, load progress or a palette.
+    fn visible(&self) -> bool {
+        self.panels.any_open() || !self.toasts.is_empty() || self.loading.is_some() || self.palette.is_some()
+    }
+
+    /// What was picked from a palette since last asked, and what it stands for, once.
+    pub fn take_picked(&mut self) -> Option<(PaletteKind, PathBuf)> {
+        self.picked.take()
+    }
+
+    /// When the layer next changes by itself: right away while a spinner turns, when a
+    /// toast expires, or never.
+    pub fn next_repaint(&self) -> Option<Instant> {
+        match self.loading {
+            Some(_) => Some(Instant::now()),
+            None => self.toasts.next_expiry(),
+        }
+    }
+
+    /// Pass a window event to egui. Returns whether it was meant for the UI, such as a
+    /// click on a panel or typing into one of its fields, and should go no further.
+    pub fn on_window_event(&mut self, window: &Window, event: &winit::event::WindowEvent) -> bool {
+        // Keep up with the monitor's scale even while nothing is shown
+        if let winit::event::WindowEvent::ScaleFactorChanged { .. } = event {
+            let _ = self.input.on_window_event(window, event);
+            return false;
+        }
+        self.visible() && self.input.on_window_event(window, event).consumed
+    }
+
+    /// Lay out the open panels and record them into `target` over what is already drawn.
+    /// Returns command buffers with their vertex data, to submit before `encoder`.
+    pub fn render(
+        &mut self,
+        window: &Window,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        info: &Info,
+    ) -> Vec<wgpu::CommandBuffer> {
+        self.toasts.expire(Instant::now());
+        if !self.visible() {
+            return Vec::new();
+        }
+        let raw_input = self.input.take_egui_input(window);
+        let (panels, toasts, loading, palette) = (&mut self.panels, &self.toasts, self.loading, &mut self.palette);
+        let mut choice = None;
+        let kind = palette.as_ref().map_or(PaletteKind::Files, Palette::kind);
+        let output = self.context.run(raw_input, |ctx| {
+            if let Some((stage, fraction)) = loading {
+                show_loading(ctx, stage, fraction);
+            }
+            panels.show(ctx, info);
+            if let Some(palette) = palette.as_mut() {
+                choice = palette.show(ctx);
+            }
+            toasts.show(ctx);
+        });
+        if let Some(choice) = choice {
+            self.palette = None;
+            if let Choice::Picked(path) = choice {
+                self.picked = Some((kind, path));
+                // Picked up by the event loop, which may be asleep otherwise
+                crate::redraw::wake();
+            }
+        }
+        self.input.handle_platform_output(window, output.platform_output);
+
+        let jobs = self.context.tessellate(output.shapes, output.pixels_per_point);
+        let size = window.inner_size();
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let commands = self.renderer.update_buffers(device, queue, encoder, &jobs, &screen);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("UI Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.renderer.render(&mut render_pass, &jobs, &screen);
+        }
+        for id in &output.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+        commands
+    }
+}
+
+/// Every EXIF field of the current image, sorted by name.
+pub struct ExifPanel;
+
+impl Panel for ExifPanel {
+    fn id(&self) -> &'static str {
+        "exif"
+    }
+
+    fn title(&self) -> String {
+        "EXIF".to_string()
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui, info: &Info) {
+        if let Some(name) = info.path.and_then(Path::file_name) {
+            ui.strong(name.to_string_lossy());
+        }
+        ui.label(format!("{} x {} px", info.image_size.0, info.image_size.1));
+        ui.separator();
+        if info.exif.is_empty() {
+            ui.label("No EXIF data");
+            return;
+        }
+        let mut fields: Vec<(&String, &String)> = info.exif.iter().collect();
+        fields.sort();
+        egui::ScrollArea::vertical().max_height(480.0).show(ui, |ui| {
+            egui::Grid::new("exif_fields").striped(true).num_columns(2).show(ui, |ui| {
+                for (key, value) in fields {
+                    ui.label(key);
+                    ui.label(value);
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+/// The file behind the current image and what the decoder made of it.
+pub struct PropertiesPanel;
+
+impl Panel for PropertiesPanel {
+    fn id(&self) -> &'static str {
+        "properties"
+    }
+
+    fn title(&self) -> String {
+        "Properties".to_string()
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui, info: &Info) {
+        let Some(properties) = info.properties else {
+            ui.label("No image");
+            return;
+        };
+        egui::Grid::new("properties").striped(true).num_columns(2).show(ui, |ui| {
+            for (label, value) in properties.rows() {
+                ui.label(label);
+                ui.label(value);
+                ui.end_row();
+            }
+        });
+    }
+}
+
+/// What the viewer is running on, for bug reports.
+pub struct DiagnosticsPanel {
+    renderer: String,
+}
+
+impl DiagnosticsPanel {
+    /// `renderer` names the active backend and adapter, see `gpu::describe`.
+    pub fn new(renderer: String) -> Self {
+        Self { renderer }
+    }
+}
+
+impl Panel for DiagnosticsPanel {
+    fn id(&self) -> &'static str {
+        "diagnostics"
+    }
+
+    fn title(&self) -> String {
+        "Diagnostics".to_string()
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui, info: &Info) {
+        egui::Grid::new("diagnostics").num_columns(2).show(ui, |ui| {
+            ui.label("Renderer");
+            ui.label(&self.renderer);
+            ui.end_row();
+            ui.label("Version");
+            ui.label(env!("CARGO_PKG_VERSION"));
+            ui.end_row();
+            ui.label("Surface");
+            ui.label(format!("{:?}, {}", info.surface_format, crate::gpu::describe_present_mode(info.present_mode)));
+            ui.end_row();
+            ui.label("Texture");
+            let (w, h) = info.texture_size;
+            ui.label(format!("{} x {} {:?}", w, h, info.texture_format));
+            ui.end_row();
+            ui.label("Frame time");
+            ui.label(match info.frame_times.summary() {
+                Some((mean, longest)) => format!("{:.1} ms, longest {:.1} ms", mean.as_secs_f64() * 1000.0, longest.as_secs_f64() * 1000.0),
+                None => "None drawn yet".to_string(),
+            });
+            ui.end_row();
+        });
+        ui.separator();
+        egui::Grid::new("queues").num_columns(2).show(ui, |ui| {
+            use std::sync::atomic::Ordering;
+            let hit_rate = |rate: &crate::stats::HitRate| match rate.ratio() {
+                Some((ratio, lookups)) => format!("{:.0}% of {}", ratio * 100.0, lookups),
+                None => "No lookups yet".to_string(),
+            };
+            ui.label("Thumbnail cache hits");
+            ui.label(hit_rate(&crate::stats::THUMBNAIL_CACHE));
+            ui.end_row();
+            ui.label("Tile cache hits");
+            ui.label(hit_rate(&crate::stats::TILE_CACHE));
+            ui.end_row();
+            ui.label("Loading");
+            ui.label(format!(
+                "{} images, {} thumbnails, {} tiles",
+                crate::stats::LOADS.load(Ordering::Relaxed),
+                crate::stats::THUMBNAILS.load(Ordering::Relaxed),
+                info.tiles_in_flight
+            ));
+            ui.end_row();
+        });
+        ui.separator();
+        let mb = |bytes: u64| format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0));
+        egui::Grid::new("memory").striped(true).num_columns(3).show(ui, |ui| {
+            ui.strong("Memory");
+            ui.strong("CPU");
+            ui.strong("GPU");
+            ui.end_row();
+            for (category, cpu, gpu) in info.memory.rows() {
+                ui.label(category.label());
+                ui.label(mb(cpu));
+                ui.label(mb(gpu));
+                ui.end_row();
+            }
+        });
+        let budget = match info.memory_budget_mb {
+            0 => "no limit".to_string(),
+            limit => format!("{} MB", limit),
+        };
+        ui.label(format!("Total {} of {}", mb(info.memory.total()), budget));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named(&'static str);
+
+    impl Panel for Named {
+        fn id(&self) -> &'static str {
+            self.0
+        }
+
+        fn title(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn show(&mut self, _: &mut egui::Ui, _: &Info) {}
+    }
+
+    #[test]
+    fn test_panels_toggle() {
+        let mut panels = Panels::default();
+        panels.add(Box::new(Named("exif")));
+        panels.add(Box::new(Named("histogram")));
+        assert!(!panels.any_open());
+
+        assert!(panels.toggle("histogram"));
+        assert!(panels.any_open());
+        assert!(!panels.toggle("histogram"));
+        assert!(!panels.toggle("missing"));
+
+        // Registering an id again replaces the panel, closed
+        panels.toggle("exif");
+        panels.add(Box::new(Named("exif")));
+        assert!(!panels.any_open());
+        assert_eq!(panels.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_toasts_queue() {
+        let mut toasts = Toasts::default();
+        for i in 0..MAX_TOASTS + 2 {
+            toasts.push(Level::Error, format!("Failed to decode {}.cr2", i));
+        }
+        assert_eq!(toasts.items.len(), MAX_TOASTS);
+        assert_eq!(toasts.items.front().unwrap().1, "Failed to decode 2.cr2");
+
+        // Repeating a message moves it to the bottom instead of stacking it twice
+        toasts.push(Level::Info, "Failed to decode 3.cr2".to_string());
+        assert_eq!(toasts.items.len(), MAX_TOASTS);
+        assert_eq!(toasts.items.back().unwrap().1, "Failed to decode 3.cr2");
+
+        toasts.expire(Instant::now());
+        assert!(!toasts.is_empty());
+        assert!(toasts.next_expiry().unwrap() <= Instant::now() + TOAST_DURATION);
+        toasts.expire(Instant::now() + TOAST_DURATION);
+        assert!(toasts.is_empty());
+    }
+
+    #[test]
+    fn test_panels_lay_out() {
+        let exif = HashMap::from([("Model".to_string(), "D850".to_string())]);
+        let memory = crate::memory::Usage::default();
+        let mut frame_times = crate::stats::FrameTimes::default();
+        frame_times.push(std::time::Duration::from_millis(4));
+        let info = Info {
+            path: Some(Path::new("a.nef")),
+            image_size: (8256, 5504),
+            exif: &exif,
+            properties: None,
+            memory: &memory,
+            memory_budget_mb: 0,
+            frame_times: &frame_times,
+            surface_format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            present_mode: wgpu::PresentMode::Fifo,
+            texture_size: (8192, 5462),
+            texture_format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            tiles_in_flight: 0,
+        };
+        let mut panels = Panels::default();
+        panels.add(Box::new(ExifPanel));
+        panels.add(Box::new(DiagnosticsPanel::new("Vulkan: Test".to_string())));
+        panels.toggle("exif");
+        panels.toggle("diagnostics");
+        assert!(panels.is_open("exif"));
+        let ctx = egui::Context::default();
+        let output = ctx.run(egui::RawInput::default(), |ctx| panels.show(ctx, &info));
+        assert!(!output.shapes.is_empty());
+    }
+
+    #[test]
+    fn test_palette_filters() {
+        let items = ["/photos/Beach.jpg", "/photos/shoot", "/archive/beach-2.png"]
+            .iter()
+            .map(|p| (p.to_string(), PathBuf::from(p)))
+            .collect();
+        let mut palette = Palette::new("Recent", items);
+        assert_eq!(palette.matches(), vec![0, 1, 2]);
+        palette.query = "BEACH".to_string();
+        assert_eq!(palette.matches(), vec![0, 2]);
+        palette.relabel(Path::new("/photos/shoot"), "Shoot at the beach".to_string());
+        assert_eq!(palette.matches().len(), 3);
+
+        let ctx = egui::Context::default();
+        let mut choice = None;
+        let output = ctx.run(egui::RawInput::default(), |ctx| choice = palette.show(ctx));
+        assert!(!output.shapes.is_empty());
+        assert_eq!(choice, None);
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("", "IMG_1234.jpg"), Some(0));
+        assert_eq!(fuzzy_score("xyz", "IMG_1234.jpg"), None);
+        assert_eq!(fuzzy_score("gmi", "IMG_1234.jpg"), None);
+        // Adjacent letters beat scattered ones, a word start beats the middle of a word
+        assert!(fuzzy_score("img", "IMG_1234.jpg") > fuzzy_score("img", "I_M_G.jpg"));
+        assert!(fuzzy_score("1234", "IMG_1234.jpg") > fuzzy_score("1234", "IMG_91234.jpg"));
+        assert!(fuzzy_score("dsc 12", "sub/DSC_0012.NEF").is_some());
+    }
+
+    #[test]
+    fn test_loading_dims_and_shows_progress() {
+        let ctx = egui::Context::default();
+        let output = ctx.run(egui::RawInput::default(), |ctx| show_loading(ctx, Stage::Demosaicing, Some(0.4)));
+        // The dimming rectangle, then the bar's frame and contents
+        assert!(output.shapes.len() > 1);
+        let output = ctx.run(egui::RawInput::default(), |ctx| show_loading(ctx, Stage::Decoding, None));
+        assert!(output.shapes.len() > 1);
+    }
+}
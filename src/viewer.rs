@@ -0,0 +1,178 @@
+//! An image view for applications that own the device and the texture it is drawn to.
+//! Images open the way the app opens them, through `sandbox::load` and so in a worker
+//! process when `sandbox::set_enabled` is on, which needs the host's executable to run
+//! `sandbox::run_worker` when started with `decode-worker <input> <output>`. They are
+//! drawn with the app's `renderer`, fitted whole and centered at first, then panned
+//! and zoomed through the view's `Camera`.
+
+use anyhow::Result;
+use image::GenericImageView;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::renderer::{Camera, Effects, Renderer};
+use crate::texture::Texture;
+
+/// Camera zoom that shows an image of `image_aspect` whole, as large as a target of
+/// `target_aspect` allows.
+pub fn fit_zoom(image_aspect: f32, target_aspect: f32) -> f32 {
+    if !(image_aspect > 0.0 && target_aspect > 0.0) {
+        return 1.0;
+    }
+    (image_aspect / target_aspect).max(1.0)
+}
+
+/// Draws the open image into a texture view the host hands it each frame.
+pub struct Viewer {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    renderer: Renderer,
+    camera: Camera,
+    /// The open image, its bind group and size
+    image: Option<(Texture, wgpu::BindGroup, (u32, u32))>,
+    target_size: (u32, u32),
+    background: wgpu::Color,
+}
+
+impl Viewer {
+    /// A viewer drawing into textures of `format`, `target_size` pixels large.
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, format: wgpu::TextureFormat, target_size: (u32, u32)) -> Self {
+        let renderer = Renderer::new(&device, &queue, format);
+        let camera = Camera { x: 0.0, y: 0.0, zoom: 1.0, aspect: aspect(target_size) };
+        Self {
+            device,
+            queue,
+            renderer,
+            camera,
+            image: None,
+            target_size,
+            background: wgpu::Color { r: 0.05, g: 0.05, b: 0.05, a: 1.0 },
+        }
+    }
+
+    /// Decode and show the image at `path`, replacing the one open.
+    pub fn open(&mut self, path: &Path) -> Result<()> {
+        let loaded = crate::sandbox::load(path)?;
+        self.show(&loaded.image)
+    }
+
+    /// Show an image the host decoded itself, fitted to the target.
+    pub fn show(&mut self, image: &image::DynamicImage) -> Result<()> {
+        let texture = Texture::from_image(&self.device, &self.queue, image, Some("viewer_texture"))?;
+        let bind_group = texture.create_bind_group(&self.device, &self.renderer.texture_bind_group_layout);
+        self.image = Some((texture, bind_group, image.dimensions()));
+        self.fit();
+        Ok(())
+    }
+
+    /// The size of the textures drawn into from now on.
+    pub fn resize(&mut self, target_size: (u32, u32)) {
+        self.target_size = target_size;
+        self.camera.aspect = aspect(target_size);
+    }
+
+    pub fn set_background(&mut self, background: wgpu::Color) {
+        self.background = background;
+    }
+
+    /// Where the view is and how far it is zoomed.
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = Camera { aspect: aspect(self.target_size), ..camera };
+    }
+
+    /// Show the whole image, centered.
+    pub fn fit(&mut self) {
+        self.camera.x = 0.0;
+        self.camera.y = 0.0;
+        self.camera.zoom = fit_zoom(self.image_aspect(), self.camera.aspect);
+    }
+
+    /// Zoom in by `factor` (out below 1), keeping what is under target pixel (x, y) there.
+    pub fn zoom_about(&mut self, factor: f32, x: f32, y: f32) {
+        let (width, height) = self.target_extent();
+        self.camera.zoom_about(1.0 / factor, width, height, x, y);
+    }
+
+    /// Move the image along with a drag of (dx, dy) target pixels.
+    pub fn drag(&mut self, dx: f32, dy: f32) {
+        let (width, height) = self.target_extent();
+        self.camera.drag(dx, dy, width, height);
+    }
+
+    /// The image texture coordinates (0..1 inside the image) under target pixel (x, y).
+    pub fn image_position(&self, x: f32, y: f32) -> (f32, f32) {
+        let (width, height) = self.target_extent();
+        self.camera.screen_to_uv(self.image_aspect(), width, height, x, y)
+    }
+
+    /// The adjustments, overlays and redactions the image is drawn with.
+    pub fn effects_mut(&mut self) -> &mut Effects {
+        &mut self.renderer.effects
+    }
+
+    /// Clear `target` to the background and draw the open image over it.
+    pub fn render_into(&mut self, target: &wgpu::TextureView) {
+        self.renderer.set_camera(&self.queue, &self.camera, self.image_aspect());
+        self.renderer.write_effects(&self.queue);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Viewer Encoder") });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Viewer Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.background),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            if let Some((_, bind_group, _)) = &self.image {
+                self.renderer.draw(&mut pass, bind_group);
+            }
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn image_aspect(&self) -> f32 {
+        match &self.image {
+            Some((_, _, (w, h))) => aspect((*w, *h)),
+            None => 1.0,
+        }
+    }
+
+    fn target_extent(&self) -> (f32, f32) {
+        (self.target_size.0.max(1) as f32, self.target_size.1.max(1) as f32)
+    }
+}
+
+fn aspect((width, height): (u32, u32)) -> f32 {
+    width.max(1) as f32 / height.max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_zoom() {
+        // Wider than the target: full width, the view spanning 4 image heights
+        assert_eq!(fit_zoom(4.0, 1.0), 4.0);
+        // Narrower: full height
+        assert_eq!(fit_zoom(0.5, 2.0), 1.0);
+        assert_eq!(fit_zoom(1.5, 1.5), 1.0);
+        assert_eq!(fit_zoom(0.0, 1.5), 1.0);
+
+        // Fitted, the image's corners land on the target's edges
+        let camera = Camera { x: 0.0, y: 0.0, zoom: fit_zoom(4.0, 1.0), aspect: 1.0 };
+        let (u, v) = camera.screen_to_uv(4.0, 200.0, 200.0, 0.0, 75.0);
+        assert!(u.abs() < 1e-6 && v.abs() < 1e-6, "{} {}", u, v);
+    }
+}
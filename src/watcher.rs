@@ -0,0 +1,73 @@
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+
+/// Watches the folder being browsed and reports created, modified and removed paths.
+pub struct FolderWatcher {
+    watcher: RecommendedWatcher,
+    watched: Option<(PathBuf, bool)>,
+}
+
+impl FolderWatcher {
+    /// `on_change` runs on the watcher's thread with the paths touched by each event.
+    pub fn new(on_change: impl Fn(Vec<PathBuf>) + Send + 'static) -> notify::Result<Self> {
+        let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+            // Reads don't change anything we show
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) && !event.paths.is_empty() => {
+                on_change(event.paths)
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("File watcher error: {:?}", e),
+        })?;
+        Ok(Self { watcher, watched: None })
+    }
+
+    /// Switch to watching `dir`; a no-op when it is already being watched.
+    pub fn watch(&mut self, dir: &Path, recursive: bool) {
+        if self.watched.as_ref().is_some_and(|(d, r)| d == dir && *r == recursive) {
+            return;
+        }
+        if let Some((old, _)) = self.watched.take() {
+            let _ = self.watcher.unwatch(&old);
+        }
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        match self.watcher.watch(dir, mode) {
+            Ok(()) => self.watched = Some((dir.to_path_buf(), recursive)),
+            Err(e) => log::warn!("Failed to watch {:?}: {:?}", dir, e),
+        }
+    }
+
+    /// Also watch `dir` and everything below it, for good; `watch` leaves it alone.
+    pub fn add(&mut self, dir: &Path) {
+        if let Err(e) = self.watcher.watch(dir, RecursiveMode::Recursive) {
+            log::warn!("Failed to watch {:?}: {:?}", dir, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reports_new_files() {
+        let dir = scratch_dir("watch");
+        let dir = std::fs::canonicalize(&dir).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = FolderWatcher::new(move |paths| {
+            let _ = tx.send(paths);
+        })
+        .unwrap();
+        watcher.watch(&dir, false);
+
+        let file = dir.join("render.png");
+        std::fs::write(&file, b"frame").unwrap();
+        let seen = std::iter::from_fn(|| rx.recv_timeout(Duration::from_secs(5)).ok())
+            .flatten()
+            .any(|p| p == file);
+        assert!(seen);
+    }
+}
@@ -0,0 +1,445 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Metadata Momemtum reads from and writes to XMP sidecar files.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct XmpData {
+    pub description: Option<String>,
+    /// `dc:title`
+    pub title: Option<String>,
+    /// `dc:rights`: the copyright notice
+    pub rights: Option<String>,
+    /// `dc:subject`
+    pub keywords: Vec<String>,
+    /// `xmp:Rating`: 1 to 5 stars, 0 for unrated and -1 for rejected
+    pub rating: Option<i32>,
+    /// `xmpDM:pick="1"`: flagged as a pick
+    pub pick: bool,
+}
+
+/// Sidecar location: `IMG_0001.xmp` next to `IMG_0001.NEF`, as used by Lightroom.
+/// An existing darktable-style `IMG_0001.NEF.xmp` is used instead if present.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut darktable = path.as_os_str().to_owned();
+    darktable.push(".xmp");
+    let darktable = PathBuf::from(darktable);
+    if darktable.exists() {
+        darktable
+    } else {
+        path.with_extension("xmp")
+    }
+}
+
+/// The images a sidecar belongs to: `a.nef` for darktable's `a.nef.xmp`, any `a.*` for `a.xmp`.
+pub fn sidecar_images(sidecar: &Path) -> Vec<PathBuf> {
+    let image = sidecar.with_extension("");
+    if crate::navigator::is_supported_image(&image) {
+        return vec![image];
+    }
+    let Some(dir) = sidecar.parent().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    dir.flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.file_stem() == image.file_name() && crate::navigator::is_supported_image(p))
+        .collect()
+}
+
+pub fn read_sidecar(path: &Path) -> XmpData {
+    match std::fs::read_to_string(sidecar_path(path)) {
+        Ok(xml) => parse(&xml),
+        Err(_) => XmpData::default(),
+    }
+}
+
+pub fn write_description(path: &Path, description: Option<&str>) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    let existing = read_existing(&sidecar)?;
+    std::fs::write(&sidecar, set_description(&existing, description))?;
+    Ok(())
+}
+
+/// Store a star rating (-1 for rejected, 0 to clear) and the pick flag.
+pub fn write_rating(path: &Path, rating: i32, pick: bool) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    let existing = read_existing(&sidecar)?;
+    std::fs::write(&sidecar, set_rating(&existing, rating, pick))?;
+    Ok(())
+}
+
+/// Store the title, copyright notice and keywords; empty ones are removed.
+pub fn write_details(path: &Path, title: Option<&str>, rights: Option<&str>, keywords: &[String]) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    let existing = read_existing(&sidecar)?;
+    let xml = set_alt(&existing, "dc:title", title);
+    let xml = set_alt(&xml, "dc:rights", rights);
+    std::fs::write(&sidecar, set_keywords(&xml, keywords))?;
+    Ok(())
+}
+
+/// The sidecar's current contents, empty when there is none yet. Any other failure is an
+/// error, so that a sidecar that can't be read isn't replaced by a new one.
+fn read_existing(sidecar: &Path) -> Result<String> {
+    match std::fs::read_to_string(sidecar) {
+        Ok(xml) => Ok(xml),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", sidecar.display())),
+    }
+}
+
+/// Keywords typed as "beach, Sunset,beach": trimmed, without blanks or repeats.
+pub fn parse_keywords(text: &str) -> Vec<String> {
+    let mut keywords: Vec<String> = Vec::new();
+    for keyword in text.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+        if !keywords.iter().any(|k| k.eq_ignore_ascii_case(keyword)) {
+            keywords.push(keyword.to_string());
+        }
+    }
+    keywords
+}
+
+pub fn parse(xml: &str) -> XmpData {
+    let alt = |name: &str| {
+        element(xml, name)
+            .and_then(|block| element(block, "rdf:li"))
+            .map(unescape)
+            .filter(|d| !d.is_empty())
+    };
+    let keywords = element(xml, "dc:subject").map(|bag| items(bag).map(unescape).collect()).unwrap_or_default();
+    let rating = attribute(xml, "xmp:Rating")
+        .or_else(|| element(xml, "xmp:Rating"))
+        .and_then(|r| r.trim().parse().ok());
+    let pick = attribute(xml, "xmpDM:pick").or_else(|| element(xml, "xmpDM:pick")).map(str::trim) == Some("1");
+    XmpData { description: alt("dc:description"), title: alt("dc:title"), rights: alt("dc:rights"), keywords, rating, pick }
+}
+
+const EMPTY_PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/">
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#;
+
+/// Replace (or insert, or remove) the dc:description of an XMP packet, leaving everything
+/// else written by other applications untouched.
+pub fn set_description(xml: &str, description: Option<&str>) -> String {
+    set_alt(xml, "dc:description", description)
+}
+
+/// Replace (or insert, or remove) a Dublin Core property holding one default-language text.
+fn set_alt(xml: &str, name: &str, value: Option<&str>) -> String {
+    let value = value.map(str::trim).filter(|v| !v.is_empty()).map(|v| {
+        format!("    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    </rdf:Alt>\n", escape(v))
+    });
+    set_dc(xml, name, value.as_deref())
+}
+
+/// Replace the dc:subject keywords of an XMP packet; none removes the property.
+pub fn set_keywords(xml: &str, keywords: &[String]) -> String {
+    let items: String = keywords.iter().map(|k| format!("     <rdf:li>{}</rdf:li>\n", escape(k))).collect();
+    let value = (!keywords.is_empty()).then(|| format!("    <rdf:Bag>\n{}    </rdf:Bag>\n", items));
+    set_dc(xml, "dc:subject", value.as_deref())
+}
+
+/// Put `<name>` with the given RDF container as the last property of `rdf:Description`.
+fn set_dc(xml: &str, name: &str, container: Option<&str>) -> String {
+    let mut xml = editable(xml);
+
+    remove_element(&mut xml, name);
+
+    let Some(container) = container else {
+        return xml;
+    };
+
+    declare_namespace(&mut xml, "dc", "http://purl.org/dc/elements/1.1/");
+
+    let block = format!("   <{}>\n{}   </{}>\n", name, container, name);
+    if let Some(end) = xml.find("</rdf:Description>") {
+        let line_start = xml[..end].rfind('\n').map(|i| i + 1).unwrap_or(end);
+        xml.insert_str(line_start, &block);
+    }
+    xml
+}
+
+/// The packet with an `rdf:Description` that properties can go into. A self-closing one,
+/// as darktable and Lightroom write when every property is an attribute, is opened up;
+/// a packet without one gets an empty one, and no packet at all becomes `EMPTY_PACKET`.
+fn editable(xml: &str) -> String {
+    if xml.contains("</rdf:Description>") {
+        return xml.to_string();
+    }
+    let mut xml = xml.to_string();
+    if let Some(start) = xml.find("<rdf:Description") {
+        if let Some(len) = xml[start..].find('>') {
+            let end = start + len;
+            if xml[..end].ends_with('/') {
+                let indent = indent(&xml, start);
+                xml.replace_range(end - 1..end + 1, &format!(">\n{}</rdf:Description>", indent));
+                return xml;
+            }
+        }
+    } else if let Some(start) = xml.find("<rdf:RDF") {
+        if let Some(len) = xml[start..].find('>') {
+            let indent = format!("{} ", indent(&xml, start));
+            let description = format!("\n{0}<rdf:Description rdf:about=\"\">\n{0}</rdf:Description>", indent);
+            xml.insert_str(start + len + 1, &description);
+            return xml;
+        }
+    }
+    EMPTY_PACKET.to_string()
+}
+
+/// The whitespace before the tag at `at` on its line.
+fn indent(xml: &str, at: usize) -> String {
+    let line_start = xml[..at].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    xml[line_start..at].chars().take_while(|c| c.is_whitespace()).collect()
+}
+
+/// Set `xmp:Rating` and `xmpDM:pick` in an XMP packet, leaving everything else untouched.
+/// A rating of 0 and no pick remove the properties, as Lightroom does.
+pub fn set_rating(xml: &str, rating: i32, pick: bool) -> String {
+    let mut xml = editable(xml);
+    let rating = (rating != 0).then(|| rating.to_string());
+    set_property(&mut xml, ("xmp", "http://ns.adobe.com/xap/1.0/"), "Rating", rating.as_deref());
+    let pick = pick.then_some("1");
+    set_property(&mut xml, ("xmpDM", "http://ns.adobe.com/xmp/1.0/DynamicMedia/"), "pick", pick);
+    xml
+}
+
+/// Replace a simple property, written in either form, by an attribute of `rdf:Description`.
+fn set_property(xml: &mut String, (prefix, uri): (&str, &str), name: &str, value: Option<&str>) {
+    let name = format!("{}:{}", prefix, name);
+    remove_element(xml, &name);
+    let open = format!("{}=\"", name);
+    if let Some(start) = xml.find(&open) {
+        if let Some(len) = xml[start + open.len()..].find('"') {
+            let end = start + open.len() + len + 1;
+            // Along with the whitespace before it
+            let start = xml[..start].trim_end().len();
+            xml.replace_range(start..end, "");
+        }
+    }
+    let Some(value) = value else {
+        return;
+    };
+    declare_namespace(xml, prefix, uri);
+    if let Some(tag) = xml.find("<rdf:Description") {
+        let insert_at = tag + "<rdf:Description".len();
+        xml.insert_str(insert_at, &format!("\n    {}=\"{}\"", name, escape(value)));
+    }
+}
+
+fn declare_namespace(xml: &mut String, prefix: &str, uri: &str) {
+    if xml.contains(&format!("xmlns:{}=", prefix)) {
+        return;
+    }
+    if let Some(tag) = xml.find("<rdf:Description") {
+        let insert_at = tag + "<rdf:Description".len();
+        xml.insert_str(insert_at, &format!("\n    xmlns:{}=\"{}\"", prefix, uri));
+    }
+}
+
+/// Drop the first `<name>...</name>` element, with its line when it has one to itself.
+fn remove_element(xml: &mut String, name: &str) {
+    let (open, close) = (format!("<{}>", name), format!("</{}>", name));
+    let Some(start) = xml.find(&open) else {
+        return;
+    };
+    let Some(len) = xml[start..].find(&close) else {
+        return;
+    };
+    let mut end = start + len + close.len();
+    if xml[end..].starts_with('\n') {
+        end += 1;
+    }
+    let line_start = xml[..start].rfind('\n').map(|i| i + 1).unwrap_or(start);
+    let start = if xml[line_start..start].trim().is_empty() { line_start } else { start };
+    xml.replace_range(start..end, "");
+}
+
+/// Mark the packet's image as upright, for a copy whose pixels were already rotated.
+pub fn set_orientation_upright(xml: &str) -> String {
+    let mut xml = xml.to_string();
+    if let Some(value) = attribute(&xml, "tiff:Orientation") {
+        let from = format!("tiff:Orientation=\"{}\"", value);
+        xml = xml.replacen(&from, "tiff:Orientation=\"1\"", 1);
+    }
+    if let Some(value) = element(&xml, "tiff:Orientation") {
+        let from = format!(">{}</tiff:Orientation>", value);
+        xml = xml.replacen(&from, ">1</tiff:Orientation>", 1);
+    }
+    xml
+}
+
+/// Text content of the first `<name ...>...</name>` element.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}", name);
+    let start = xml.find(&open)?;
+    let content_start = start + xml[start..].find('>')? + 1;
+    let close = format!("</{}>", name);
+    let content_len = xml[content_start..].find(&close)?;
+    Some(&xml[content_start..content_start + content_len])
+}
+
+/// Text of every `<rdf:li>` item in an RDF container.
+fn items(xml: &str) -> impl Iterator<Item = &str> {
+    xml.split("<rdf:li").skip(1).filter_map(|item| {
+        let content = &item[item.find('>')? + 1..];
+        Some(&content[..content.find("</rdf:li>")?])
+    })
+}
+
+/// Value of the first `name="..."` attribute, the compact form XMP writers use for simple properties.
+fn attribute<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("{}=\"", name);
+    let start = xml.find(&open)? + open.len();
+    let len = xml[start..].find('"')?;
+    Some(&xml[start..start + len])
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Undo the escaping of XML text and attribute values.
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_description_roundtrip() {
+        let xml = set_description("", Some("Sunset at <the> pier & co"));
+        assert_eq!(parse(&xml).description.as_deref(), Some("Sunset at <the> pier & co"));
+
+        let xml = set_description(&xml, Some("Replaced"));
+        assert_eq!(parse(&xml).description.as_deref(), Some("Replaced"));
+        assert_eq!(xml.matches("<dc:description>").count(), 1);
+
+        let xml = set_description(&xml, None);
+        assert_eq!(parse(&xml).description, None);
+        assert!(xml.contains("</rdf:Description>"));
+    }
+
+    #[test]
+    fn test_preserves_foreign_metadata() {
+        let lightroom = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmp:Rating="4">
+   <crs:Exposure2012>+0.50</crs:Exposure2012>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+        let xml = set_description(lightroom, Some("Note"));
+        assert!(xml.contains("xmp:Rating=\"4\""));
+        assert!(xml.contains("<crs:Exposure2012>+0.50</crs:Exposure2012>"));
+        assert!(xml.contains("xmlns:dc="));
+        assert_eq!(parse(&xml).description.as_deref(), Some("Note"));
+        assert_eq!(parse(&xml).rating, Some(4));
+        assert_eq!(parse("<xmp:Rating>-1</xmp:Rating>").rating, Some(-1));
+    }
+
+    #[test]
+    fn test_rating_roundtrip() {
+        let xml = set_rating("", 3, true);
+        assert_eq!(parse(&xml), XmpData { rating: Some(3), pick: true, ..Default::default() });
+        assert!(xml.contains("xmlns:xmp=") && xml.contains("xmlns:xmpDM="));
+
+        let xml = set_description(&xml, Some("Keeper"));
+        let xml = set_rating(&xml, -1, false);
+        assert_eq!(parse(&xml), XmpData { description: Some("Keeper".to_string()), rating: Some(-1), ..Default::default() });
+        assert_eq!(xml.matches("xmp:Rating=").count(), 1);
+
+        // Element form, as some tools write it, is replaced
+        let element_form = set_description("", None).replace(
+            "  </rdf:Description>",
+            "   <xmp:Rating>2</xmp:Rating>\n  </rdf:Description>",
+        );
+        let xml = set_rating(&element_form, 5, false);
+        assert!(!xml.contains("<xmp:Rating>"));
+        assert_eq!(parse(&xml).rating, Some(5));
+
+        let xml = set_rating(&xml, 0, false);
+        assert_eq!(parse(&xml).rating, None);
+        assert!(!xml.contains("xmp:Rating=") && xml.contains("rdf:about=\"\""));
+    }
+
+    #[test]
+    fn test_self_closing_description() {
+        let darktable = r#"<?xml version="1.0" encoding="UTF-8"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="XMP Core 4.4.0-Exiv2">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:darktable="http://darktable.sf.net/"
+    xmp:Rating="1"
+    darktable:xmp_version="5"
+    darktable:raw_params="0"/>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+        let xml = set_rating(darktable, 4, true);
+        assert!(xml.contains("darktable:xmp_version=\"5\"") && xml.contains("darktable:raw_params=\"0\">"));
+        assert!(xml.contains("XMP Core 4.4.0-Exiv2"));
+        assert_eq!(xml.matches("xmp:Rating=").count(), 1);
+        assert_eq!(parse(&xml), XmpData { rating: Some(4), pick: true, ..Default::default() });
+        assert!(xml.contains("  </rdf:Description>\n </rdf:RDF>"));
+
+        let xml = set_keywords(&set_description(darktable, Some("Harbour")), &["boats".to_string()]);
+        let data = parse(&xml);
+        assert_eq!((data.description.as_deref(), data.keywords.len(), data.rating), (Some("Harbour"), 1, Some(1)));
+        assert!(xml.contains("darktable:raw_params=\"0\">"));
+        let bare = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n </rdf:RDF>\n</x:xmpmeta>\n";
+        assert_eq!(parse(&set_alt(bare, "dc:title", Some("Pier"))).title.as_deref(), Some("Pier"));
+
+        // A sidecar that can't be read is left alone
+        let dir = scratch_dir("xmp");
+        std::fs::create_dir_all(dir.join("locked.xmp")).unwrap();
+        assert!(write_rating(&dir.join("locked.jpg"), 3, false).is_err());
+        assert!(write_description(&dir.join("locked.jpg"), Some("Note")).is_err());
+        assert!(write_details(&dir.join("locked.jpg"), Some("Pier"), None, &[]).is_err());
+        assert!(dir.join("locked.xmp").is_dir());
+    }
+
+    #[test]
+    fn test_details_roundtrip() {
+        let keywords = parse_keywords(" beach, Sunset,,beach , R&D ");
+        assert_eq!(keywords, ["beach", "Sunset", "R&D"]);
+
+        let xml = set_description("", Some("Note"));
+        let xml = set_alt(&xml, "dc:title", Some("Pier"));
+        let xml = set_alt(&xml, "dc:rights", Some("© 2024 A. Photographer"));
+        let xml = set_keywords(&xml, &keywords);
+        let data = parse(&xml);
+        assert_eq!(data.description.as_deref(), Some("Note"));
+        assert_eq!(data.title.as_deref(), Some("Pier"));
+        assert_eq!(data.rights.as_deref(), Some("© 2024 A. Photographer"));
+        assert_eq!(data.keywords, keywords);
+        assert!(xml.contains("<rdf:li>R&amp;D</rdf:li>"));
+
+        let xml = set_keywords(&set_alt(&xml, "dc:title", None), &[]);
+        let data = parse(&xml);
+        assert_eq!((data.title, data.keywords.len()), (None, 0));
+        assert_eq!(data.rights.as_deref(), Some("© 2024 A. Photographer"));
+        assert!(!xml.contains("dc:subject") && !xml.contains("dc:title"));
+    }
+}